@@ -0,0 +1,762 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Allocators for bounded heaps which can be inspected at runtime.
+//!
+//! This is the Rust counterpart of the C++ `pw_allocator` module.  A
+//! [`BlockAllocator`] divides a fixed region of memory into blocks, each
+//! with a small header, and allocates the first free block which fits.
+//! Freed blocks are merged with free neighbors.  Allocators track
+//! [`Metrics`] and their blocks can be listed with
+//! [`BlockAllocator::blocks()`]:
+//!
+//! ```
+//! use core::alloc::Layout;
+//! use pw_allocator::BlockAllocator;
+//!
+//! let mut buffer = [0u8; 256];
+//! let mut allocator = BlockAllocator::new(&mut buffer);
+//!
+//! let layout = Layout::new::<[u32; 4]>();
+//! let ptr = allocator.allocate(layout)?;
+//! assert_eq!(allocator.metrics().num_allocations, 1);
+//!
+//! // SAFETY: `ptr` was allocated with `layout` by this allocator.
+//! unsafe { allocator.deallocate(ptr, layout) };
+//! assert_eq!(allocator.metrics().allocated_bytes, 0);
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! Crates which use `alloc` can run with a bounded heap by registering a
+//! [`GlobalBlockAllocator`] as the `#[global_allocator]`.
+#![no_std]
+#![deny(missing_docs)]
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::{RefCell, UnsafeCell};
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+use core::ptr::{self, NonNull};
+
+use critical_section::Mutex;
+use pw_status::{Error, Result};
+
+// Each block starts with a header of two words: the size of the previous
+// block, or zero for the first block, and the size of the block, including
+// its header, with `USED` set if the block is allocated.  Blocks are aligned
+// to the size of their header.
+const HEADER_SIZE: usize = 2 * size_of::<usize>();
+const BLOCK_ALIGN: usize = HEADER_SIZE;
+const USED: usize = 1;
+
+/// The smallest block, including its header.
+pub const MIN_BLOCK_SIZE: usize = HEADER_SIZE + BLOCK_ALIGN;
+
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+/// Statistics of an allocator's use, matching the C++ `pw::allocator`
+/// metrics.
+///
+/// Byte counts are of the usable space of blocks, which may be larger than
+/// the sizes requested.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    /// Bytes currently allocated.
+    pub allocated_bytes: usize,
+    /// The most bytes which have been allocated at once.
+    pub peak_allocated_bytes: usize,
+    /// Bytes allocated over the allocator's lifetime.
+    pub cumulative_allocated_bytes: usize,
+    /// Number of successful allocations.
+    pub num_allocations: usize,
+    /// Number of deallocations.
+    pub num_deallocations: usize,
+    /// Number of successful reallocations.
+    pub num_reallocations: usize,
+    /// Number of allocations and reallocations which failed.
+    pub num_failures: usize,
+}
+
+impl Metrics {
+    fn add_allocated(&mut self, bytes: usize) {
+        self.allocated_bytes += bytes;
+        self.cumulative_allocated_bytes += bytes;
+        self.peak_allocated_bytes = self.peak_allocated_bytes.max(self.allocated_bytes);
+    }
+}
+
+/// A block of a [`BlockAllocator`]'s memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// Offset of the block's usable space from the start of the allocator's
+    /// memory.
+    pub offset: usize,
+    /// Usable size of the block.
+    pub size: usize,
+    /// Whether the block is allocated.
+    pub used: bool,
+}
+
+/// A first fit allocator of blocks of a fixed region of memory.
+///
+/// Each allocation uses a header of two words in addition to its size,
+/// rounded up to a multiple of the header size.  Allocations with larger
+/// alignments may leave free blocks before them.
+///
+/// A `BlockAllocator` is not synchronized.  Wrap it in a lock to share it,
+/// or use a [`GlobalBlockAllocator`].
+pub struct BlockAllocator<'a> {
+    base: NonNull<u8>,
+    len: usize,
+    metrics: Metrics,
+    _memory: PhantomData<&'a mut [u8]>,
+}
+
+// SAFETY: The allocator has exclusive access to its memory.
+unsafe impl Send for BlockAllocator<'_> {}
+
+impl<'a> BlockAllocator<'a> {
+    /// Creates an allocator of the memory in `buffer`.
+    ///
+    /// The start of `buffer` is skipped if it is not aligned for a block
+    /// header.
+    ///
+    /// # Panics
+    /// Panics if `buffer` is too small for a block.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        // SAFETY: `buffer` is valid and exclusively borrowed for `'a`.
+        unsafe { Self::from_raw(buffer.as_mut_ptr(), buffer.len()) }
+    }
+
+    /// Creates an allocator of `len` bytes of memory starting at `ptr`.
+    ///
+    /// # Panics
+    /// Panics if the memory is too small for a block.
+    ///
+    /// # Safety
+    /// The memory must be valid for reads and writes, and not accessed other
+    /// than through this allocator and its allocations, for `'a`.
+    pub unsafe fn from_raw(ptr: *mut u8, len: usize) -> Self {
+        let skip = align_up(ptr as usize, BLOCK_ALIGN) - ptr as usize;
+        let len = len.saturating_sub(skip) & !(BLOCK_ALIGN - 1);
+        assert!(len >= MIN_BLOCK_SIZE, "Buffer is too small for a block");
+        let mut allocator = Self {
+            base: NonNull::new_unchecked(ptr.add(skip)),
+            len,
+            metrics: Metrics::default(),
+            _memory: PhantomData,
+        };
+        allocator.set_prev_size(0, 0);
+        allocator.set_info(0, len);
+        allocator
+    }
+
+    fn header(&self, offset: usize) -> *mut usize {
+        // SAFETY: Block offsets are within the allocator's memory.
+        unsafe { self.base.as_ptr().add(offset).cast() }
+    }
+
+    fn prev_size(&self, offset: usize) -> usize {
+        // SAFETY: Headers are aligned and within the allocator's memory.
+        unsafe { self.header(offset).read() }
+    }
+
+    fn info(&self, offset: usize) -> usize {
+        // SAFETY: Headers are aligned and within the allocator's memory.
+        unsafe { self.header(offset).add(1).read() }
+    }
+
+    fn size(&self, offset: usize) -> usize {
+        self.info(offset) & !USED
+    }
+
+    fn is_used(&self, offset: usize) -> bool {
+        self.info(offset) & USED != 0
+    }
+
+    fn set_prev_size(&mut self, offset: usize, prev_size: usize) {
+        // SAFETY: Headers are aligned and within the allocator's memory.
+        unsafe { self.header(offset).write(prev_size) }
+    }
+
+    fn set_info(&mut self, offset: usize, info: usize) {
+        // SAFETY: Headers are aligned and within the allocator's memory.
+        unsafe { self.header(offset).add(1).write(info) }
+    }
+
+    // Sets the size of the block at `offset`, and the previous size of the
+    // block after it.
+    fn resize_block(&mut self, offset: usize, size: usize, used: bool) {
+        self.set_info(offset, size | if used { USED } else { 0 });
+        if offset + size < self.len {
+            self.set_prev_size(offset + size, size);
+        }
+    }
+
+    // Splits the block at `offset` so that it is `size` bytes, creating a
+    // free block from the rest if it is large enough, and returns the size
+    // of the block.
+    fn split(&mut self, offset: usize, size: usize, used: bool) -> usize {
+        let block_size = self.size(offset);
+        if block_size - size < MIN_BLOCK_SIZE {
+            self.resize_block(offset, block_size, used);
+            return block_size;
+        }
+        self.resize_block(offset, size, used);
+        let rest = offset + size;
+        self.set_prev_size(rest, size);
+        self.resize_block(rest, block_size - size, false);
+        self.merge_next(rest);
+        size
+    }
+
+    // Merges the free block at `offset` with the next block if it is free.
+    fn merge_next(&mut self, offset: usize) {
+        let size = self.size(offset);
+        let next = offset + size;
+        if next < self.len && !self.is_used(next) {
+            self.resize_block(offset, size + self.size(next), false);
+        }
+    }
+
+    fn offset_of(&self, ptr: NonNull<u8>) -> usize {
+        let offset = (ptr.as_ptr() as usize)
+            .wrapping_sub(self.base.as_ptr() as usize)
+            .wrapping_sub(HEADER_SIZE);
+        assert!(
+            offset < self.len && offset & (BLOCK_ALIGN - 1) == 0 && self.is_used(offset),
+            "Pointer was not allocated by this allocator"
+        );
+        offset
+    }
+
+    fn data(&self, offset: usize) -> NonNull<u8> {
+        // SAFETY: Block offsets are within the allocator's memory.
+        unsafe { NonNull::new_unchecked(self.base.as_ptr().add(offset + HEADER_SIZE)) }
+    }
+
+    /// Allocates memory for `layout`.
+    ///
+    /// Zero sized layouts are allocated as if they were one byte.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - No free block is large enough.
+    pub fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>> {
+        let result = self.allocate_block(layout);
+        match result {
+            Ok(offset) => {
+                self.metrics.num_allocations += 1;
+                self.metrics.add_allocated(self.size(offset) - HEADER_SIZE);
+                Ok(self.data(offset))
+            }
+            Err(e) => {
+                self.metrics.num_failures += 1;
+                Err(e)
+            }
+        }
+    }
+
+    fn allocate_block(&mut self, layout: Layout) -> Result<usize> {
+        let size = HEADER_SIZE + align_up(layout.size().max(1), BLOCK_ALIGN);
+        let align = layout.align().max(BLOCK_ALIGN);
+        let base = self.base.as_ptr() as usize;
+        let mut offset = 0;
+        while offset < self.len {
+            let block_size = self.size(offset);
+            if !self.is_used(offset) {
+                // A block which is not aligned is preceded by a free block,
+                // which must be large enough for its header.
+                let data = base + offset + HEADER_SIZE;
+                let mut aligned = align_up(data, align);
+                if aligned != data {
+                    aligned = align_up(data + MIN_BLOCK_SIZE, align);
+                }
+                let gap = aligned - data;
+                if gap + size <= block_size {
+                    let offset = if gap > 0 {
+                        self.split(offset, gap, false);
+                        offset + gap
+                    } else {
+                        offset
+                    };
+                    self.split(offset, size, true);
+                    return Ok(offset);
+                }
+            }
+            offset += block_size;
+        }
+        Err(Error::ResourceExhausted)
+    }
+
+    /// Frees memory returned by [`BlockAllocator::allocate()`].
+    ///
+    /// # Panics
+    /// Panics if `ptr` is not an allocated block of this allocator.  Not
+    /// every invalid pointer is detected.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this allocator with `layout`, and not
+    /// be used after it is freed.
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, _layout: Layout) {
+        let mut offset = self.offset_of(ptr);
+        let size = self.size(offset);
+        self.metrics.num_deallocations += 1;
+        self.metrics.allocated_bytes -= size - HEADER_SIZE;
+        self.resize_block(offset, size, false);
+        self.merge_next(offset);
+        let prev_size = self.prev_size(offset);
+        if prev_size != 0 && !self.is_used(offset - prev_size) {
+            offset -= prev_size;
+            self.merge_next(offset);
+        }
+    }
+
+    /// Changes the size of memory returned by [`BlockAllocator::allocate()`]
+    /// to `new_size`, returning its new location.
+    ///
+    /// The block is resized in place if possible.  Otherwise, a new block is
+    /// allocated, the contents are copied to it, and the old block is freed.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - No free block is large enough.  The
+    ///   original block is not freed.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated by this allocator with `layout`.  It may
+    /// not be used after a successful reallocation.
+    pub unsafe fn reallocate(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<NonNull<u8>> {
+        let offset = self.offset_of(ptr);
+        let old_size = self.size(offset);
+        let size = HEADER_SIZE + align_up(new_size.max(1), BLOCK_ALIGN);
+
+        // Include the next block if it is free.
+        let next = offset + old_size;
+        let available = if next < self.len && !self.is_used(next) {
+            old_size + self.size(next)
+        } else {
+            old_size
+        };
+        if size <= available {
+            self.resize_block(offset, available, true);
+            let new_size = self.split(offset, size, true);
+            self.metrics.num_reallocations += 1;
+            self.metrics.allocated_bytes -= old_size - HEADER_SIZE;
+            self.metrics.add_allocated(new_size - HEADER_SIZE);
+            return Ok(ptr);
+        }
+
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            self.metrics.num_failures += 1;
+            return Err(Error::ResourceExhausted);
+        };
+        let new_ptr = match self.allocate_block(new_layout) {
+            Ok(new_offset) => self.data(new_offset),
+            Err(e) => {
+                self.metrics.num_failures += 1;
+                return Err(e);
+            }
+        };
+        ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), layout.size().min(new_size));
+        self.deallocate(ptr, layout);
+        self.metrics.num_deallocations -= 1;
+        self.metrics.num_reallocations += 1;
+        let new_offset = self.offset_of(new_ptr);
+        self.metrics
+            .add_allocated(self.size(new_offset) - HEADER_SIZE);
+        Ok(new_ptr)
+    }
+
+    /// Returns the allocator's metrics.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics
+    }
+
+    /// Returns the number of bytes of memory managed by the allocator,
+    /// including block headers.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Returns an iterator over the allocator's blocks, in address order.
+    pub fn blocks(&self) -> impl Iterator<Item = BlockInfo> + '_ {
+        let mut offset = 0;
+        core::iter::from_fn(move || {
+            if offset >= self.len {
+                return None;
+            }
+            let block = BlockInfo {
+                offset: offset + HEADER_SIZE,
+                size: self.size(offset) - HEADER_SIZE,
+                used: self.is_used(offset),
+            };
+            offset += self.size(offset);
+            Some(block)
+        })
+    }
+
+    /// Checks the integrity of the allocator's block headers, which may be
+    /// corrupted by writes outside of allocated memory.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - A block header is corrupt.
+    pub fn check_integrity(&self) -> Result<()> {
+        let mut offset = 0;
+        let mut prev_size = 0;
+        let mut prev_used = true;
+        while offset < self.len {
+            let size = self.size(offset);
+            let used = self.is_used(offset);
+            if self.prev_size(offset) != prev_size
+                || size < MIN_BLOCK_SIZE
+                || size & (BLOCK_ALIGN - 1) != 0
+                || size > self.len - offset
+                || (!used && !prev_used)
+            {
+                return Err(Error::DataLoss);
+            }
+            prev_size = size;
+            prev_used = used;
+            offset += size;
+        }
+        Ok(())
+    }
+}
+
+/// A [`BlockAllocator`] of `N` bytes which can be used as the
+/// `#[global_allocator]`.
+///
+/// Allocations are made within a critical section.  Failed allocations
+/// return null, so the global allocation error handler is called.
+///
+/// ```
+/// use pw_allocator::GlobalBlockAllocator;
+///
+/// #[global_allocator]
+/// static HEAP: GlobalBlockAllocator<4096> = GlobalBlockAllocator::new();
+///
+/// let mut values = Vec::new();
+/// values.push(1);
+/// assert!(HEAP.metrics().num_allocations >= 1);
+/// ```
+pub struct GlobalBlockAllocator<const N: usize> {
+    memory: UnsafeCell<[MaybeUninit<u8>; N]>,
+    allocator: Mutex<RefCell<Option<BlockAllocator<'static>>>>,
+}
+
+// SAFETY: The memory is only accessed through the allocator, which is
+// accessed within a critical section.
+unsafe impl<const N: usize> Sync for GlobalBlockAllocator<N> {}
+
+impl<const N: usize> GlobalBlockAllocator<N> {
+    /// Creates an allocator.  Its blocks are initialized on first use.
+    pub const fn new() -> Self {
+        Self {
+            memory: UnsafeCell::new([MaybeUninit::uninit(); N]),
+            allocator: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut BlockAllocator<'static>) -> R) -> R {
+        critical_section::with(|cs| {
+            let mut allocator = self.allocator.borrow_ref_mut(cs);
+            let allocator = allocator.get_or_insert_with(|| {
+                // SAFETY: The memory is only accessed through this allocator,
+                // which is dropped with it.
+                unsafe { BlockAllocator::from_raw(self.memory.get().cast(), N) }
+            });
+            f(allocator)
+        })
+    }
+
+    /// Returns the allocator's metrics.
+    pub fn metrics(&self) -> Metrics {
+        self.with(|allocator| allocator.metrics())
+    }
+
+    /// Calls `f` with each of the allocator's blocks, in address order.
+    ///
+    /// `f` is called within a critical section, so it must not allocate.
+    pub fn for_each_block(&self, mut f: impl FnMut(BlockInfo)) {
+        self.with(|allocator| allocator.blocks().for_each(&mut f))
+    }
+
+    /// Checks the integrity of the allocator's block headers.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - A block header is corrupt.
+    pub fn check_integrity(&self) -> Result<()> {
+        self.with(|allocator| allocator.check_integrity())
+    }
+}
+
+impl<const N: usize> Default for GlobalBlockAllocator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// SAFETY: Blocks are allocated with at least the layout's size and alignment,
+// and are not reused until they are freed.
+unsafe impl<const N: usize> GlobalAlloc for GlobalBlockAllocator<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with(|allocator| {
+            allocator
+                .allocate(layout)
+                .map_or(ptr::null_mut(), NonNull::as_ptr)
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            self.with(|allocator| allocator.deallocate(ptr, layout))
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Some(ptr) = NonNull::new(ptr) else {
+            return ptr::null_mut();
+        };
+        self.with(|allocator| {
+            allocator
+                .reallocate(ptr, layout, new_size)
+                .map_or(ptr::null_mut(), NonNull::as_ptr)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[repr(align(64))]
+    struct Buffer([u8; 512]);
+
+    fn free_bytes(allocator: &BlockAllocator) -> usize {
+        allocator
+            .blocks()
+            .filter(|block| !block.used)
+            .map(|block| block.size)
+            .sum()
+    }
+
+    #[test]
+    fn allocations_do_not_overlap() {
+        let mut buffer = Buffer([0; 512]);
+        let mut allocator = BlockAllocator::new(&mut buffer.0);
+        let layout = Layout::from_size_align(20, 4).unwrap();
+        let first = allocator.allocate(layout).unwrap();
+        let second = allocator.allocate(layout).unwrap();
+        // SAFETY: The allocations are 20 bytes.
+        unsafe {
+            ptr::write_bytes(first.as_ptr(), 0xaa, 20);
+            ptr::write_bytes(second.as_ptr(), 0xbb, 20);
+            assert_eq!(*first.as_ptr().add(19), 0xaa);
+        }
+        assert!(second.as_ptr() as usize >= first.as_ptr() as usize + 20);
+        assert_eq!(allocator.check_integrity(), Ok(()));
+        let used: Vec<_> = allocator.blocks().filter(|block| block.used).collect();
+        assert_eq!(used.len(), 2);
+        assert!(used.iter().all(|block| block.size >= 20));
+    }
+
+    #[test]
+    fn freed_blocks_are_merged() {
+        let mut buffer = Buffer([0; 512]);
+        let mut allocator = BlockAllocator::new(&mut buffer.0);
+        let free = free_bytes(&allocator);
+        let layout = Layout::new::<[u64; 4]>();
+        let ptrs = [
+            allocator.allocate(layout).unwrap(),
+            allocator.allocate(layout).unwrap(),
+            allocator.allocate(layout).unwrap(),
+        ];
+        // SAFETY: The pointers were allocated with `layout`.
+        unsafe {
+            allocator.deallocate(ptrs[0], layout);
+            allocator.deallocate(ptrs[2], layout);
+            allocator.deallocate(ptrs[1], layout);
+        }
+        assert_eq!(allocator.blocks().count(), 1);
+        assert_eq!(free_bytes(&allocator), free);
+        assert_eq!(allocator.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn freed_blocks_are_reused() {
+        let mut buffer = Buffer([0; 512]);
+        let mut allocator = BlockAllocator::new(&mut buffer.0);
+        let layout = Layout::new::<[u8; 100]>();
+        let first = allocator.allocate(layout).unwrap();
+        let _second = allocator.allocate(layout).unwrap();
+        // SAFETY: `first` was allocated with `layout`.
+        unsafe { allocator.deallocate(first, layout) };
+        assert_eq!(allocator.allocate(layout), Ok(first));
+    }
+
+    #[test]
+    fn allocations_are_aligned() {
+        let mut buffer = Buffer([0; 512]);
+        let mut allocator = BlockAllocator::new(&mut buffer.0[1..]);
+        for align in [1, 2, 8, 32, 64] {
+            let ptr = allocator
+                .allocate(Layout::from_size_align(3, align).unwrap())
+                .unwrap();
+            assert_eq!(ptr.as_ptr() as usize % align, 0);
+        }
+        assert_eq!(allocator.check_integrity(), Ok(()));
+    }
+
+    #[test]
+    fn exhausted_allocator_fails() {
+        let mut buffer = Buffer([0; 512]);
+        let mut allocator = BlockAllocator::new(&mut buffer.0);
+        assert_eq!(
+            allocator.allocate(Layout::new::<[u8; 512]>()),
+            Err(Error::ResourceExhausted)
+        );
+        assert_eq!(allocator.metrics().num_failures, 1);
+        assert_eq!(allocator.metrics().num_allocations, 0);
+    }
+
+    #[test]
+    fn metrics_track_allocations() {
+        let mut buffer = Buffer([0; 512]);
+        let mut allocator = BlockAllocator::new(&mut buffer.0);
+        let layout = Layout::new::<[u8; 32]>();
+        let first = allocator.allocate(layout).unwrap();
+        let second = allocator.allocate(layout).unwrap();
+        // SAFETY: The pointers were allocated with `layout`.
+        unsafe {
+            allocator.deallocate(first, layout);
+            allocator.deallocate(second, layout);
+        }
+        let _third = allocator.allocate(layout).unwrap();
+        assert_eq!(
+            allocator.metrics(),
+            Metrics {
+                allocated_bytes: 32,
+                peak_allocated_bytes: 64,
+                cumulative_allocated_bytes: 96,
+                num_allocations: 3,
+                num_deallocations: 2,
+                num_reallocations: 0,
+                num_failures: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn reallocate_grows_in_place_or_moves() {
+        let mut buffer = Buffer([0; 512]);
+        let mut allocator = BlockAllocator::new(&mut buffer.0);
+        let layout = Layout::new::<[u8; 16]>();
+        let ptr = allocator.allocate(layout).unwrap();
+        // SAFETY: The allocation is 16 bytes.
+        unsafe { ptr::copy_nonoverlapping(b"0123456789abcdef".as_ptr(), ptr.as_ptr(), 16) };
+
+        // The next block is free, so the block grows in place.
+        // SAFETY: `ptr` was allocated with `layout`.
+        let grown = unsafe { allocator.reallocate(ptr, layout, 48) }.unwrap();
+        assert_eq!(grown, ptr);
+
+        // A following allocation forces the block to move.
+        let blocker = allocator.allocate(layout).unwrap();
+        let layout = Layout::new::<[u8; 48]>();
+        // SAFETY: `grown` was allocated with `layout`.
+        let moved = unsafe { allocator.reallocate(grown, layout, 100) }.unwrap();
+        assert_ne!(moved, grown);
+        // SAFETY: The allocation is at least 16 bytes.
+        assert_eq!(
+            unsafe { core::slice::from_raw_parts(moved.as_ptr(), 16) },
+            b"0123456789abcdef"
+        );
+        assert_eq!(allocator.metrics().num_reallocations, 2);
+        assert_eq!(allocator.metrics().num_deallocations, 0);
+        assert_eq!(allocator.blocks().filter(|block| block.used).count(), 2);
+
+        // Shrinking frees the end of the block.
+        let layout = Layout::new::<[u8; 100]>();
+        // SAFETY: `moved` was allocated with `layout`.
+        let shrunk = unsafe { allocator.reallocate(moved, layout, 8) }.unwrap();
+        assert_eq!(shrunk, moved);
+        // SAFETY: The pointers were allocated by this allocator.
+        unsafe {
+            allocator.deallocate(shrunk, Layout::new::<[u8; 8]>());
+            allocator.deallocate(blocker, Layout::new::<[u8; 16]>());
+        }
+        assert_eq!(allocator.blocks().count(), 1);
+        assert_eq!(allocator.metrics().allocated_bytes, 0);
+    }
+
+    #[test]
+    fn corrupted_header_is_detected() {
+        let mut buffer = Buffer([0; 512]);
+        let mut allocator = BlockAllocator::new(&mut buffer.0);
+        let ptr = allocator.allocate(Layout::new::<[u8; 16]>()).unwrap();
+        // SAFETY: Overruns the allocation into the next block's header.
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0xff, 16 + HEADER_SIZE) };
+        assert_eq!(allocator.check_integrity(), Err(Error::DataLoss));
+    }
+
+    #[test]
+    #[should_panic(expected = "Pointer was not allocated by this allocator")]
+    fn freeing_unallocated_pointer_panics() {
+        let mut buffer = Buffer([0; 512]);
+        let mut allocator = BlockAllocator::new(&mut buffer.0);
+        let ptr = allocator.allocate(Layout::new::<u32>()).unwrap();
+        // SAFETY: Expected to panic before freeing anything.
+        unsafe {
+            allocator.deallocate(
+                NonNull::new_unchecked(ptr.as_ptr().add(1)),
+                Layout::new::<u32>(),
+            )
+        };
+    }
+
+    #[test]
+    fn global_allocator_allocates() {
+        static HEAP: GlobalBlockAllocator<256> = GlobalBlockAllocator::new();
+        let layout = Layout::new::<[u32; 4]>();
+        // SAFETY: `layout` is not zero sized.
+        let ptr = unsafe { HEAP.alloc(layout) };
+        assert!(!ptr.is_null());
+        // SAFETY: `ptr` was allocated with `layout`.
+        let ptr = unsafe { HEAP.realloc(ptr, layout, 32) };
+        assert!(!ptr.is_null());
+        let layout = Layout::new::<[u32; 8]>();
+        // SAFETY: The allocation fails, since the heap is too small.
+        assert!(unsafe { HEAP.alloc(Layout::new::<[u8; 512]>()) }.is_null());
+        // SAFETY: `ptr` was reallocated with `layout`'s size.
+        unsafe { HEAP.dealloc(ptr, layout) };
+
+        let mut blocks = 0;
+        HEAP.for_each_block(|block| {
+            assert!(!block.used);
+            blocks += 1;
+        });
+        assert_eq!(blocks, 1);
+        assert_eq!(HEAP.check_integrity(), Ok(()));
+        assert_eq!(HEAP.metrics().num_failures, 1);
+    }
+}
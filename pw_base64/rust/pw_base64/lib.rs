@@ -14,7 +14,8 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 #![deny(missing_docs)]
 
-//! `pw_base64` provides simple encoding of data into base64.
+//! `pw_base64` provides simple encoding and decoding of data to and from
+//! base64.
 //!
 //! ```
 //! const INPUT: &'static [u8] = "I 💖 Pigweed".as_bytes();
@@ -30,10 +31,33 @@
 //! let output_str = pw_base64::encode_str(INPUT, &mut output).unwrap();
 //! assert_eq!(output_str, "SSDwn5KWIFBpZ3dlZWQ=");
 //! ```
+//!
+//! Decoding accepts both the standard and the URL-safe alphabets and can be
+//! done in place, since decoded data is always smaller than its encoding.
+//!
+//! ```
+//! let mut buffer = *b"SSDwn5KWIFBpZ3dlZWQ=";
+//!
+//! // [`max_decoded_size`] can be used to calculate the size of the output
+//! // buffer.
+//! let mut output = [0u8; pw_base64::max_decoded_size(20)];
+//! let output_size = pw_base64::decode(&buffer, &mut output).unwrap();
+//! assert_eq!(&output[0..output_size], "I 💖 Pigweed".as_bytes());
+//!
+//! let output_size = pw_base64::decode_in_place(&mut buffer).unwrap();
+//! assert_eq!(&buffer[0..output_size], "I 💖 Pigweed".as_bytes());
+//! ```
+//!
+//! [`Base64Writer`] encodes data on the fly into any [`pw_stream::Write`]
+//! without a staging buffer.
 
 use pw_status::{Error, Result};
 use pw_stream::{Cursor, ReadInteger, Seek, Write};
 
+mod writer;
+
+pub use writer::{Base64Writer, TOKEN_PREFIX};
+
 // Helper macro to make declaring the base 64 encode table more concise.
 macro_rules! b {
     ($char:tt) => {
@@ -87,6 +111,24 @@ const fn char_3(b: &[u8; 3]) -> u8 {
     BASE64_ENCODE_TABLE[(b[2] & 0b00111111) as usize]
 }
 
+// Encodes the first `len` bytes of `bytes` as a padded group of 4 characters.
+const fn encode_group(bytes: &[u8; 3], len: usize) -> [u8; 4] {
+    [
+        char_0(bytes),
+        char_1(bytes),
+        if len > 1 {
+            char_2(bytes)
+        } else {
+            BASE64_PADDING
+        },
+        if len > 2 {
+            char_3(bytes)
+        } else {
+            BASE64_PADDING
+        },
+    ]
+}
+
 /// Encode `input` as base64 into the `output_buffer`.
 ///
 /// Returns the number of bytes written to `output_buffer` on success or
@@ -106,20 +148,7 @@ pub fn encode(input: &[u8], output: &mut [u8]) -> Result<usize> {
             input.read_u8_le().unwrap_or(0),
         ];
 
-        output.write(&[
-            char_0(&bytes),
-            char_1(&bytes),
-            if remaining_bytes > 1 {
-                char_2(&bytes)
-            } else {
-                BASE64_PADDING
-            },
-            if remaining_bytes > 2 {
-                char_3(&bytes)
-            } else {
-                BASE64_PADDING
-            },
-        ])?;
+        output.write(&encode_group(&bytes, remaining_bytes))?;
         remaining_bytes = remaining_bytes.saturating_add_signed(-3);
     }
 
@@ -145,5 +174,116 @@ pub fn encode_str<'a>(input: &[u8], output_buffer: &'a mut [u8]) -> Result<&'a s
     }
 }
 
+// Marks bytes which are not part of either base64 alphabet in
+// `BASE64_DECODE_TABLE`.
+const INVALID: u8 = 0xff;
+
+// Maps each character of the standard and URL-safe alphabets to the 6 bits of
+// data it represents.
+const BASE64_DECODE_TABLE: [u8; 256] = {
+    let mut table = [INVALID; 256];
+    let mut i = 0;
+    while i < BASE64_ENCODE_TABLE.len() {
+        table[BASE64_ENCODE_TABLE[i] as usize] = i as u8;
+        i += 1;
+    }
+    // The URL-safe alphabet replaces `+` and `/` with `-` and `_`.
+    table[b'-' as usize] = 62;
+    table[b'_' as usize] = 63;
+    table
+};
+
+/// Returns the maximum size of the output buffer needed to decode an input
+/// buffer of size `input_size`.
+///
+/// The decoded data may be up to 2 bytes shorter, depending on padding.
+pub const fn max_decoded_size(input_size: usize) -> usize {
+    input_size / 4 * 3
+}
+
+/// Returns `true` if `c` is a character of the standard or URL-safe base64
+/// alphabets.  The padding character `=` is not included.
+pub const fn is_valid_char(c: u8) -> bool {
+    BASE64_DECODE_TABLE[c as usize] != INVALID
+}
+
+/// Returns `true` if `input` is valid, padded base64.
+pub fn is_valid(input: &[u8]) -> bool {
+    decoded_size(input).is_ok()
+}
+
+// Validates `input` and returns the exact size of its decoded data.
+fn decoded_size(input: &[u8]) -> Result<usize> {
+    // Input must be made up of whole groups of 4 characters.
+    if input.len() & 3 != 0 {
+        return Err(Error::DataLoss);
+    }
+    let padding = match input {
+        [.., BASE64_PADDING, BASE64_PADDING] => 2,
+        [.., BASE64_PADDING] => 1,
+        _ => 0,
+    };
+    if !input[..input.len() - padding]
+        .iter()
+        .all(|&c| is_valid_char(c))
+    {
+        return Err(Error::DataLoss);
+    }
+    Ok(max_decoded_size(input.len()) - padding)
+}
+
+// Decodes a group of 4 valid characters into 3 bytes.  Padding decodes as
+// zero bits.
+const fn decode_group(group: [u8; 4]) -> [u8; 3] {
+    let mut bits = 0u32;
+    let mut i = 0;
+    while i < group.len() {
+        let value = BASE64_DECODE_TABLE[group[i] as usize];
+        bits = (bits << 6) | if value == INVALID { 0 } else { value as u32 };
+        i += 1;
+    }
+    [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8]
+}
+
+/// Decode base64 `input` into `output`.
+///
+/// Returns the number of bytes written to `output` on success,
+/// `Error::DataLoss` if `input` is not valid, padded base64, or
+/// `Error::OutOfRange` if `output` is not large enough.
+pub fn decode(input: &[u8], output: &mut [u8]) -> Result<usize> {
+    let len = decoded_size(input)?;
+    let output = output.get_mut(..len).ok_or(Error::OutOfRange)?;
+    for (group, bytes) in input.chunks_exact(4).zip(output.chunks_mut(3)) {
+        let decoded = decode_group([group[0], group[1], group[2], group[3]]);
+        bytes.copy_from_slice(&decoded[..bytes.len()]);
+    }
+    Ok(len)
+}
+
+/// Decode base64 data in `buffer`, writing the decoded data to the start of
+/// `buffer`.
+///
+/// Returns the number of decoded bytes on success or `Error::DataLoss` if
+/// `buffer` is not valid, padded base64, in which case `buffer` is
+/// unmodified.
+pub fn decode_in_place(buffer: &mut [u8]) -> Result<usize> {
+    let len = decoded_size(buffer)?;
+    // Each group is read before it is overwritten, since groups of 3 decoded
+    // bytes are written no further into the buffer than their 4 characters.
+    for i in 0..buffer.len() / 4 {
+        let group = i * 4;
+        let decoded = decode_group([
+            buffer[group],
+            buffer[group + 1],
+            buffer[group + 2],
+            buffer[group + 3],
+        ]);
+        let start = i * 3;
+        let end = len.min(start + 3);
+        buffer[start..end].copy_from_slice(&decoded[..end - start]);
+    }
+    Ok(len)
+}
+
 #[cfg(test)]
 mod tests;
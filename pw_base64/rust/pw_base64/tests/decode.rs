@@ -0,0 +1,119 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use super::*;
+
+#[test]
+fn max_decoded_size_fits_encoded_test_cases() {
+    for (input, expected_output) in random_data::test_cases() {
+        assert!(max_decoded_size(expected_output.len()) >= input.len());
+        assert!(max_decoded_size(expected_output.len()) < input.len() + 3);
+    }
+}
+
+#[test]
+fn single_characters_decode_correctly() {
+    for (expected_output, input) in single_char::test_cases() {
+        let mut output_buffer = vec![0u8; max_decoded_size(input.len())];
+        let decode_len = decode(input.as_bytes(), &mut output_buffer).unwrap();
+        assert_eq!(&output_buffer[..decode_len], expected_output);
+    }
+}
+
+#[test]
+fn random_data_decodes_correctly() {
+    for (expected_output, input) in random_data::test_cases() {
+        let mut output_buffer = vec![0u8; max_decoded_size(input.len())];
+        let decode_len = decode(input.as_bytes(), &mut output_buffer).unwrap();
+        assert_eq!(&output_buffer[..decode_len], expected_output);
+    }
+}
+
+#[test]
+fn random_data_decodes_in_place() {
+    for (expected_output, input) in random_data::test_cases() {
+        let mut buffer = input.as_bytes().to_vec();
+        let decode_len = decode_in_place(&mut buffer).unwrap();
+        assert_eq!(&buffer[..decode_len], expected_output);
+    }
+}
+
+#[test]
+fn examples_from_rfc4648_section_2_decode_correctly() {
+    let mut output_buffer = [0u8; 6];
+    for (input, expected_output) in [
+        ("", ""),
+        ("Zg==", "f"),
+        ("Zm8=", "fo"),
+        ("Zm9v", "foo"),
+        ("Zm9vYg==", "foob"),
+        ("Zm9vYmE=", "fooba"),
+        ("Zm9vYmFy", "foobar"),
+    ] {
+        let decode_len = decode(input.as_bytes(), &mut output_buffer).unwrap();
+        assert_eq!(&output_buffer[..decode_len], expected_output.as_bytes());
+    }
+}
+
+#[test]
+fn url_safe_alphabet_decodes_correctly() {
+    let mut output_buffer = [0u8; 3];
+    assert_eq!(decode(b"-_-_", &mut output_buffer), Ok(3));
+    assert_eq!(output_buffer, [0xfb, 0xff, 0xbf]);
+    assert_eq!(decode(b"+/+/", &mut output_buffer), Ok(3));
+    assert_eq!(output_buffer, [0xfb, 0xff, 0xbf]);
+}
+
+#[test]
+fn invalid_input_returns_error() {
+    let mut output_buffer = [0u8; 6];
+    for input in [
+        &b"Zg"[..],
+        b"Zg=",
+        b"Z===",
+        b"Zg=a",
+        b"=Zg=",
+        b"Zm9v\n",
+        b"Zm9*",
+        b"Zg==Zg==",
+    ] {
+        assert!(!is_valid(input));
+        assert_eq!(decode(input, &mut output_buffer), Err(Error::DataLoss));
+        let mut buffer = input.to_vec();
+        assert_eq!(decode_in_place(&mut buffer), Err(Error::DataLoss));
+        assert_eq!(buffer, input);
+    }
+}
+
+#[test]
+fn valid_characters_are_detected() {
+    let valid: Vec<u8> = (0..=255).filter(|&c| is_valid_char(c)).collect();
+    assert_eq!(
+        valid,
+        b"+-/0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz"
+    );
+    assert!(is_valid(b"SSDwn5KWIFBpZ3dlZWQ="));
+}
+
+#[test]
+fn too_small_output_buffer_returns_error() {
+    let mut output_buffer = [0u8; 6];
+    assert_eq!(
+        decode(b"Zm9vYmE=", &mut output_buffer[..4]),
+        Err(Error::OutOfRange)
+    );
+    // Padding means fewer bytes than `max_decoded_size()` are needed.
+    assert_eq!(decode(b"Zm9vYmE=", &mut output_buffer[..5]), Ok(5));
+    assert_eq!(&output_buffer[..5], b"fooba");
+}
@@ -83,3 +83,6 @@ fn examples_from_rfc4648_section_2_encode_correctly() {
     assert_eq!(encode_str(&input[0..5], &mut output_buffer), Ok("Zm9vYmE="));
     assert_eq!(encode_str(&input[0..6], &mut output_buffer), Ok("Zm9vYmFy"));
 }
+
+mod decode;
+mod writer;
@@ -0,0 +1,91 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_stream::{Cursor, Write};
+
+use super::*;
+
+// Encodes `input` with `Base64Writer`, calling `write()` with at most
+// `chunk_size` bytes at a time.
+fn encode_with_writer(input: &[u8], chunk_size: usize, prefix: Option<u8>) -> String {
+    let output = Cursor::new(vec![0u8; encoded_size(input.len()) + 1]);
+    let mut writer = match prefix {
+        Some(prefix) => Base64Writer::with_prefix(output, prefix),
+        None => Base64Writer::new(output),
+    };
+    for chunk in input.chunks(chunk_size) {
+        writer.write_all(chunk).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let output = writer.into_inner();
+    let len = output.position();
+    String::from_utf8(output.into_inner()[..len].to_vec()).unwrap()
+}
+
+#[test]
+fn writer_encodes_single_characters() {
+    for (input, expected_output) in single_char::test_cases() {
+        assert_eq!(encode_with_writer(input, 1, None), expected_output);
+    }
+}
+
+#[test]
+fn writer_encodes_random_data_with_any_write_size() {
+    for (input, expected_output) in random_data::test_cases() {
+        for chunk_size in [1, 2, 3, 4, 7, 48, 100] {
+            assert_eq!(encode_with_writer(input, chunk_size, None), expected_output);
+        }
+    }
+}
+
+#[test]
+fn writer_prepends_prefix() {
+    assert_eq!(encode_with_writer(b"hi", 1, Some(TOKEN_PREFIX)), "$aGk=");
+}
+
+#[test]
+fn writer_writes_nothing_for_empty_message() {
+    assert_eq!(encode_with_writer(b"", 1, Some(TOKEN_PREFIX)), "");
+}
+
+#[test]
+fn writer_starts_new_message_after_finish() {
+    let mut writer = Base64Writer::with_prefix(Cursor::new([0u8; 16]), TOKEN_PREFIX);
+    writer.write_all(b"hi").unwrap();
+    writer.finish().unwrap();
+    writer.finish().unwrap();
+    writer.write_all(b"foo").unwrap();
+    writer.finish().unwrap();
+
+    let output = writer.into_inner();
+    let len = output.position();
+    assert_eq!(&output.into_inner()[..len], b"$aGk=$Zm9v");
+}
+
+#[test]
+fn writer_holds_partial_groups_until_finish() {
+    let mut writer = Base64Writer::new(Cursor::new([0u8; 16]));
+    writer.write_all(b"foob").unwrap();
+    writer.flush().unwrap();
+    assert_eq!(writer.get_ref().position(), 4);
+    writer.finish().unwrap();
+    assert_eq!(writer.get_ref().position(), 8);
+}
+
+#[test]
+fn writer_propagates_inner_errors() {
+    let mut writer = Base64Writer::new(Cursor::new([0u8; 3]));
+    assert_eq!(writer.write_all(b"foo"), Err(Error::OutOfRange));
+}
@@ -0,0 +1,179 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_status::Result;
+use pw_stream::Write;
+
+use crate::encode_group;
+
+/// The prefix used by `pw_tokenizer` to mark Base64 encoded tokenized
+/// messages in plaintext output.
+pub const TOKEN_PREFIX: u8 = b'$';
+
+// Number of input bytes encoded per write to the inner writer.
+const INPUT_BATCH_SIZE: usize = 48;
+
+/// A writer adapter which Base64 encodes all data written through it into an
+/// inner writer.
+///
+/// Data is encoded in groups of 3 bytes as it is written.  Any remaining
+/// bytes are encoded with padding when [`Base64Writer::finish()`] is called,
+/// which ends the current message.  Writing after `finish()` starts a new
+/// message.
+///
+/// Optionally, each message may be preceded by a prefix character.  Using
+/// [`TOKEN_PREFIX`] allows tokenized messages to be interleaved with plaintext
+/// output such as a console.
+///
+/// # Example
+///
+/// ```
+/// use pw_base64::{Base64Writer, TOKEN_PREFIX};
+/// use pw_stream::{Cursor, Write};
+///
+/// let mut writer = Base64Writer::with_prefix(Cursor::new([0u8; 16]), TOKEN_PREFIX);
+/// writer.write_all(&[0x31, 0x3d]).unwrap();
+/// writer.write_all(&[0x4b, 0xa1]).unwrap();
+/// writer.finish().unwrap();
+///
+/// let cursor = writer.into_inner();
+/// let len = cursor.position();
+/// assert_eq!(&cursor.into_inner()[..len], b"$MT1LoQ==");
+/// ```
+pub struct Base64Writer<W: Write> {
+    inner: W,
+    prefix: Option<u8>,
+    pending: [u8; 3],
+    pending_len: usize,
+    in_message: bool,
+}
+
+impl<W: Write> Base64Writer<W> {
+    /// Create a new `Base64Writer` which writes encoded data to `inner`.
+    pub const fn new(inner: W) -> Self {
+        Self {
+            inner,
+            prefix: None,
+            pending: [0; 3],
+            pending_len: 0,
+            in_message: false,
+        }
+    }
+
+    /// Create a new `Base64Writer` which writes `prefix` before each message.
+    pub const fn with_prefix(inner: W, prefix: u8) -> Self {
+        Self {
+            inner,
+            prefix: Some(prefix),
+            pending: [0; 3],
+            pending_len: 0,
+            in_message: false,
+        }
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consumes the `Base64Writer` and returns the underlying writer.
+    ///
+    /// Any data not yet encoded by [`Base64Writer::finish()`] is discarded.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Ends the current message by encoding any remaining bytes with padding.
+    ///
+    /// If nothing has been written since the last message ended, nothing is
+    /// written.
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.in_message {
+            return Ok(());
+        }
+        if self.pending_len > 0 {
+            self.pending[self.pending_len..].fill(0);
+            self.inner
+                .write_all(&encode_group(&self.pending, self.pending_len))?;
+            self.pending_len = 0;
+        }
+        self.in_message = false;
+        Ok(())
+    }
+
+    fn start_message(&mut self) -> Result<()> {
+        if !self.in_message {
+            if let Some(prefix) = self.prefix {
+                self.inner.write_all(&[prefix])?;
+            }
+            self.in_message = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Base64Writer<W> {
+    fn write(&mut self, mut buf: &[u8]) -> Result<usize> {
+        let len = buf.len();
+        if len == 0 {
+            return Ok(0);
+        }
+        self.start_message()?;
+
+        // Complete any partial group from a previous write.
+        if self.pending_len > 0 {
+            let count = (3 - self.pending_len).min(buf.len());
+            self.pending[self.pending_len..self.pending_len + count].copy_from_slice(&buf[..count]);
+            self.pending_len += count;
+            buf = &buf[count..];
+            if self.pending_len < 3 {
+                return Ok(len);
+            }
+            self.inner.write_all(&encode_group(&self.pending, 3))?;
+            self.pending_len = 0;
+        }
+
+        // Encode full groups in batches to limit the number of writes to the
+        // inner writer.
+        let mut output = [0u8; INPUT_BATCH_SIZE / 3 * 4];
+        while buf.len() >= 3 {
+            let batch_len = buf.len().min(INPUT_BATCH_SIZE) / 3 * 3;
+            let (batch, rest) = buf.split_at(batch_len);
+            for (input, output) in batch.chunks_exact(3).zip(output.chunks_exact_mut(4)) {
+                let group = [input[0], input[1], input[2]];
+                output.copy_from_slice(&encode_group(&group, 3));
+            }
+            self.inner.write_all(&output[..batch_len / 3 * 4])?;
+            buf = rest;
+        }
+
+        // Hold on to any remaining bytes until the group is complete.
+        self.pending[..buf.len()].copy_from_slice(buf);
+        self.pending_len = buf.len();
+        Ok(len)
+    }
+
+    /// Flushes the underlying writer.
+    ///
+    /// Bytes which do not complete a 3 byte group are not encoded until more
+    /// data is written or [`Base64Writer::finish()`] is called.
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
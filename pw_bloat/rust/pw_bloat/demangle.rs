@@ -0,0 +1,551 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! Demangling of Rust symbol names, for attributing symbols to crates.
+//!
+//! Both the legacy (`_ZN...17h<hash>E`) and v0 (`_R...`) mangling schemes are
+//! supported.  Demangling is only as thorough as size reports need: legacy
+//! names are demangled without their hash, and v0 names are demangled unless
+//! they use constructs such as `dyn` types or function pointers, in which case
+//! they are left mangled.
+//!
+//! ```
+//! use pw_bloat::demangle::{crate_name, demangle};
+//!
+//! let symbol = "_ZN4core3fmt5write17h0123456789abcdefE";
+//! assert_eq!(demangle(symbol), "core::fmt::write");
+//! assert_eq!(crate_name(symbol).as_deref(), Some("core"));
+//!
+//! assert_eq!(demangle("memcpy"), "memcpy");
+//! assert_eq!(crate_name("memcpy"), None);
+//! ```
+
+/// Returns the demangled name of `symbol`, or `symbol` itself if it is not a
+/// Rust symbol or cannot be demangled.
+pub fn demangle(symbol: &str) -> String {
+    legacy_path(symbol)
+        .or_else(|| V0Parser::new(symbol)?.path())
+        .unwrap_or_else(|| symbol.to_string())
+}
+
+/// Returns the name of the crate `symbol` was defined in, or `None` if it is
+/// not a Rust symbol.
+///
+/// Trait implementations, such as `<T as core::fmt::Debug>::fmt`, are
+/// attributed to the implementing type's crate if the type is a path, or to
+/// the trait's crate otherwise.
+pub fn crate_name(symbol: &str) -> Option<String> {
+    if let Some(path) = legacy_path(symbol) {
+        return path_crate(&path).map(str::to_string);
+    }
+    V0Parser::new(symbol)?.crate_name()
+}
+
+// Removes the suffix LLVM adds to local symbols it renames, such as
+// `.llvm.1234`.
+fn strip_llvm_suffix(symbol: &str) -> &str {
+    symbol
+        .find(".llvm.")
+        .map_or(symbol, |index| &symbol[..index])
+}
+
+// Demangles a legacy symbol, which is an Itanium C++ style nested name whose
+// last component is a hash.
+fn legacy_path(symbol: &str) -> Option<String> {
+    let symbol = strip_llvm_suffix(symbol);
+    let mut rest = symbol
+        .strip_prefix("_ZN")
+        .or_else(|| symbol.strip_prefix("__ZN"))?
+        .strip_suffix('E')?;
+
+    let mut components = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let len: usize = rest[..digits].parse().ok()?;
+        let component = rest.get(digits..digits + len)?;
+        components.push(component);
+        rest = &rest[digits + len..];
+    }
+
+    // C++ symbols use the same scheme, but only Rust symbols end in a hash.
+    let hash = components.pop()?;
+    let is_hash = hash.len() == 17
+        && hash.starts_with('h')
+        && hash[1..].bytes().all(|b| b.is_ascii_hexdigit());
+    if !is_hash || components.is_empty() {
+        return None;
+    }
+
+    let components: Option<Vec<String>> = components.into_iter().map(decode_legacy).collect();
+    Some(components?.join("::"))
+}
+
+// Decodes the `$`-escaped punctuation and `..` path separators in a legacy
+// path component.
+fn decode_legacy(component: &str) -> Option<String> {
+    // Components that would start with an escape are prefixed with `_`.
+    let mut rest = match component.strip_prefix("_$") {
+        Some(_) => &component[1..],
+        None => component,
+    };
+    let mut decoded = String::with_capacity(rest.len());
+    while let Some(c) = rest.chars().next() {
+        if c == '$' {
+            let end = rest[1..].find('$')? + 1;
+            decoded.push(match &rest[1..end] {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                escape => {
+                    let code = u32::from_str_radix(escape.strip_prefix('u')?, 16).ok()?;
+                    char::from_u32(code)?
+                }
+            });
+            rest = &rest[end + 1..];
+        } else if let Some(after) = rest.strip_prefix("..") {
+            decoded.push_str("::");
+            rest = after;
+        } else {
+            decoded.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    Some(decoded)
+}
+
+// Returns the crate of a demangled path, such as `core` for
+// `core::fmt::write`.
+fn path_crate(path: &str) -> Option<&str> {
+    if let Some(inner) = path.strip_prefix('<') {
+        let (ty, trait_path) = match inner.split_once(" as ") {
+            Some((ty, trait_path)) => (ty, Some(trait_path)),
+            None => (inner, None),
+        };
+        return path_crate(ty).or_else(|| trait_path.and_then(path_crate));
+    }
+
+    let path = path.trim_start_matches(['&', '*', '[', '(']);
+    let path = path.strip_prefix("mut ").unwrap_or(path);
+    let path = path.strip_prefix("const ").unwrap_or(path);
+    let (name, _) = path.split_once("::")?;
+    let is_identifier =
+        !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+    is_identifier.then_some(name)
+}
+
+// The deepest backreferences are followed, which bounds the recursion of
+// malicious symbols.
+const MAX_DEPTH: u32 = 64;
+
+// Parses v0 mangled symbols.  Returns `None` for constructs size reports do
+// not need, such as `dyn` types, which leaves those symbols mangled.
+struct V0Parser<'a> {
+    // The symbol after `_R`, which backreferences are offsets into.
+    data: &'a [u8],
+    pos: usize,
+    depth: u32,
+}
+
+impl<'a> V0Parser<'a> {
+    fn new(symbol: &'a str) -> Option<Self> {
+        let symbol = strip_llvm_suffix(symbol);
+        let symbol = symbol
+            .strip_prefix("_R")
+            .or_else(|| symbol.strip_prefix("__R"))?;
+        // Skip the optional encoding version.
+        let version = symbol.bytes().take_while(u8::is_ascii_digit).count();
+        Some(Self {
+            data: symbol.as_bytes(),
+            pos: version,
+            depth: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let matches = self.peek() == Some(byte);
+        self.pos += usize::from(matches);
+        matches
+    }
+
+    // Parses a base 62 number terminated by `_`, in which `_` alone is 0.
+    fn base62(&mut self) -> Option<u64> {
+        if self.eat(b'_') {
+            return Some(0);
+        }
+        let mut value: u64 = 0;
+        loop {
+            let digit = match self.next()? {
+                b'_' => return value.checked_add(1),
+                b @ b'0'..=b'9' => b - b'0',
+                b @ b'a'..=b'z' => b - b'a' + 10,
+                b @ b'A'..=b'Z' => b - b'A' + 36,
+                _ => return None,
+            };
+            value = value.checked_mul(62)?.checked_add(u64::from(digit))?;
+        }
+    }
+
+    fn disambiguator(&mut self) -> Option<u64> {
+        if self.eat(b's') {
+            self.base62().map(|value| value + 1)
+        } else {
+            Some(0)
+        }
+    }
+
+    fn identifier(&mut self) -> Option<(u64, &'a str)> {
+        let disambiguator = self.disambiguator()?;
+        // Punycode identifiers are not supported.
+        if self.peek() == Some(b'u') {
+            return None;
+        }
+        let digits = self.data[self.pos..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        let len: usize = core::str::from_utf8(&self.data[self.pos..self.pos + digits])
+            .ok()?
+            .parse()
+            .ok()?;
+        self.pos += digits;
+        self.eat(b'_');
+        let identifier = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some((disambiguator, core::str::from_utf8(identifier).ok()?))
+    }
+
+    // Parses what a backreference points to with `parse`, then continues after
+    // the backreference.
+    fn backref<T>(&mut self, parse: impl FnOnce(&mut Self) -> Option<T>) -> Option<T> {
+        let start = self.pos - 1;
+        let target = usize::try_from(self.base62()?).ok()?;
+        if target >= start || self.depth >= MAX_DEPTH {
+            return None;
+        }
+        let pos = core::mem::replace(&mut self.pos, target);
+        self.depth += 1;
+        let parsed = parse(self);
+        self.depth -= 1;
+        self.pos = pos;
+        parsed
+    }
+
+    fn path(&mut self) -> Option<String> {
+        match self.next()? {
+            b'C' => self.identifier().map(|(_, name)| name.to_string()),
+            b'N' => {
+                let namespace = self.next()?;
+                let parent = self.path()?;
+                let (disambiguator, name) = self.identifier()?;
+                Some(match namespace {
+                    b'C' => format!("{parent}::{{closure#{disambiguator}}}"),
+                    b'S' => format!("{parent}::{{shim#{disambiguator}}}"),
+                    _ => format!("{parent}::{name}"),
+                })
+            }
+            b'M' => {
+                self.disambiguator()?;
+                self.path()?;
+                Some(format!("<{}>", self.ty()?))
+            }
+            b'X' => {
+                self.disambiguator()?;
+                self.path()?;
+                let ty = self.ty()?;
+                Some(format!("<{ty} as {}>", self.path()?))
+            }
+            b'Y' => {
+                let ty = self.ty()?;
+                Some(format!("<{ty} as {}>", self.path()?))
+            }
+            b'I' => {
+                let path = self.path()?;
+                let mut args = Vec::new();
+                while !self.eat(b'E') {
+                    args.push(self.generic_arg()?);
+                }
+                Some(format!("{path}::<{}>", args.join(", ")))
+            }
+            b'B' => self.backref(Self::path),
+            _ => None,
+        }
+    }
+
+    fn generic_arg(&mut self) -> Option<String> {
+        if self.eat(b'L') {
+            self.base62()?;
+            Some("'_".to_string())
+        } else if self.eat(b'K') {
+            self.constant()
+        } else {
+            self.ty()
+        }
+    }
+
+    fn ty(&mut self) -> Option<String> {
+        let basic = match self.peek()? {
+            b'a' => "i8",
+            b'b' => "bool",
+            b'c' => "char",
+            b'd' => "f64",
+            b'e' => "str",
+            b'f' => "f32",
+            b'h' => "u8",
+            b'i' => "isize",
+            b'j' => "usize",
+            b'l' => "i32",
+            b'm' => "u32",
+            b'n' => "i128",
+            b'o' => "u128",
+            b'p' => "_",
+            b's' => "i16",
+            b't' => "u16",
+            b'u' => "()",
+            b'v' => "...",
+            b'x' => "i64",
+            b'y' => "u64",
+            b'z' => "!",
+            _ => "",
+        };
+        if !basic.is_empty() {
+            self.pos += 1;
+            return Some(basic.to_string());
+        }
+
+        match self.peek()? {
+            b'R' | b'Q' => {
+                let mutable = self.next()? == b'Q';
+                if self.eat(b'L') {
+                    self.base62()?;
+                }
+                let ty = self.ty()?;
+                Some(if mutable {
+                    format!("&mut {ty}")
+                } else {
+                    format!("&{ty}")
+                })
+            }
+            b'P' | b'O' => {
+                let mutable = self.next()? == b'O';
+                let ty = self.ty()?;
+                Some(if mutable {
+                    format!("*mut {ty}")
+                } else {
+                    format!("*const {ty}")
+                })
+            }
+            b'A' => {
+                self.pos += 1;
+                let ty = self.ty()?;
+                Some(format!("[{ty}; {}]", self.constant()?))
+            }
+            b'S' => {
+                self.pos += 1;
+                Some(format!("[{}]", self.ty()?))
+            }
+            b'T' => {
+                self.pos += 1;
+                let mut types = Vec::new();
+                while !self.eat(b'E') {
+                    types.push(self.ty()?);
+                }
+                Some(match types.as_slice() {
+                    [ty] => format!("({ty},)"),
+                    _ => format!("({})", types.join(", ")),
+                })
+            }
+            b'B' => {
+                self.pos += 1;
+                self.backref(Self::ty)
+            }
+            b'C' | b'N' | b'M' | b'X' | b'Y' | b'I' => self.path(),
+            _ => None,
+        }
+    }
+
+    fn constant(&mut self) -> Option<String> {
+        let ty = match self.next()? {
+            b'p' => return Some("_".to_string()),
+            b'B' => return self.backref(Self::constant),
+            ty => ty,
+        };
+        let negative = self.eat(b'n');
+        let digits = self.data[self.pos..].iter().position(|&b| b == b'_')?;
+        let hex = core::str::from_utf8(&self.data[self.pos..self.pos + digits]).ok()?;
+        let value = if hex.is_empty() {
+            0
+        } else {
+            u128::from_str_radix(hex, 16).ok()?
+        };
+        self.pos += digits + 1;
+        match ty {
+            b'b' => Some((value != 0).to_string()),
+            b'c' => Some(format!("{:?}", char::from_u32(u32::try_from(value).ok()?)?)),
+            b'a' | b'h' | b'i' | b'j' | b'l' | b'm' | b'n' | b'o' | b's' | b't' | b'x' | b'y' => {
+                Some(if negative {
+                    format!("-{value}")
+                } else {
+                    value.to_string()
+                })
+            }
+            _ => None,
+        }
+    }
+
+    // Skips to the first crate root in the path, which is the crate the path
+    // (or the impl block it is in) was defined in.
+    fn crate_name(mut self) -> Option<String> {
+        loop {
+            match self.next()? {
+                b'C' => return self.identifier().map(|(_, name)| name.to_string()),
+                b'N' => {
+                    self.next()?;
+                }
+                b'M' | b'X' => {
+                    self.disambiguator()?;
+                }
+                b'B' => {
+                    let start = self.pos - 1;
+                    self.pos = usize::try_from(self.base62()?).ok()?;
+                    if self.pos >= start {
+                        return None;
+                    }
+                }
+                b'I' | b'Y' => {}
+                _ => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demangles_legacy_symbols() {
+        assert_eq!(
+            demangle("_ZN4core3fmt9Formatter3pad17h1234567890abcdefE"),
+            "core::fmt::Formatter::pad"
+        );
+        assert_eq!(
+            demangle(
+                "_ZN50_$LT$T$u20$as$u20$core..convert..Into$LT$U$GT$$GT$4into17h1234567890abcdefE"
+            ),
+            "<T as core::convert::Into<U>>::into"
+        );
+        assert_eq!(
+            demangle("_ZN5alloc7raw_vec11finish_grow17h1234567890abcdefE.llvm.123"),
+            "alloc::raw_vec::finish_grow"
+        );
+    }
+
+    #[test]
+    fn demangles_v0_symbols() {
+        assert_eq!(demangle("_RNvCs1234_7mycrate4main"), "mycrate::main");
+        assert_eq!(
+            demangle("_RNCNvNtCs1234_7mycrate6module3run0B5_"),
+            "mycrate::module::run::{closure#0}"
+        );
+        assert_eq!(
+            demangle("_RNvMsa_NtCsgEmfK2I1SDS_4core3fmtNtB5_9Formatter19pad_formatted_parts"),
+            "<core::fmt::Formatter>::pad_formatted_parts"
+        );
+        assert_eq!(
+            demangle("_RINvNtNtCsgEmfK2I1SDS_4core3fmt5float29float_to_decimal_common_exactdEB6_"),
+            "core::fmt::float::float_to_decimal_common_exact::<f64>"
+        );
+        assert_eq!(
+            demangle("_RINvCs1234_7mycrate4sizeNtB2_3FooE"),
+            "mycrate::size::<mycrate::Foo>"
+        );
+        assert_eq!(
+            demangle("_RNvXs_Cs1234_7mycrateRShNtNtCs5678_4core3fmt5Debug3fmt"),
+            "<&[u8] as core::fmt::Debug>::fmt"
+        );
+        assert_eq!(
+            demangle("_RINvCs1234_7mycrate3bufAhj10_TmlEE"),
+            "mycrate::buf::<[u8; 16], (u32, i32)>"
+        );
+    }
+
+    #[test]
+    fn leaves_other_symbols_mangled() {
+        assert_eq!(demangle("main"), "main");
+        assert_eq!(
+            demangle("_ZN2pw6metric5Group4DumpEv"),
+            "_ZN2pw6metric5Group4DumpEv"
+        );
+        assert_eq!(demangle("_ZN3foo3barE"), "_ZN3foo3barE");
+        assert_eq!(
+            demangle("_RINvCs1234_7mycrate3runDNtCs5678_4core3AnyEL_EB2_"),
+            "_RINvCs1234_7mycrate3runDNtCs5678_4core3AnyEL_EB2_"
+        );
+        assert_eq!(demangle("_RNvB0_3foo"), "_RNvB0_3foo");
+    }
+
+    #[test]
+    fn finds_crate_names() {
+        assert_eq!(
+            crate_name("_ZN4core3fmt9Formatter3pad17h1234567890abcdefE").as_deref(),
+            Some("core")
+        );
+        assert_eq!(
+            crate_name(
+                "_ZN50_$LT$T$u20$as$u20$core..convert..Into$LT$U$GT$$GT$4into17h1234567890abcdefE"
+            )
+            .as_deref(),
+            Some("core")
+        );
+        assert_eq!(
+            crate_name(
+                "_ZN51_$LT$pw_log..Record$u20$as$u20$core..fmt..Debug$GT$3fmt17h1234567890abcdefE"
+            )
+            .as_deref(),
+            Some("pw_log")
+        );
+        assert_eq!(
+            crate_name("_ZN50_$LT$$RF$mut$u20$W$u20$as$u20$core..fmt..Write$GT$9write_str17h1234567890abcdefE").as_deref(),
+            Some("core")
+        );
+        assert_eq!(
+            crate_name("_RNvCs1234_7mycrate4main").as_deref(),
+            Some("mycrate")
+        );
+        assert_eq!(
+            crate_name("_RINvCs1234_7mycrate4sizeNtB2_3FooE").as_deref(),
+            Some("mycrate")
+        );
+        assert_eq!(
+            crate_name("_RNvMs_NtCs1234_7mycrate6moduleNtB4_3Foo3new").as_deref(),
+            Some("mycrate")
+        );
+        assert_eq!(crate_name("_ZN2pw6metric5Group4DumpEv"), None);
+        assert_eq!(crate_name("memcpy"), None);
+    }
+}
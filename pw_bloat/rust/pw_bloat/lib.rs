@@ -0,0 +1,915 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! `pw_bloat` generates size reports for Rust firmware, like the `pw bloat`
+//! command does for C and C++ binaries.
+//!
+//! A [`SizeReport`] breaks an ELF file's allocated sections down by the
+//! [`DataSource`]s it is generated with: sections, crates, and symbols.
+//! Crates are found by demangling symbol names (see [`demangle`]), and the
+//! space in a section which no symbol accounts for is reported as
+//! `[section .name]`.  Diffing two reports attributes the change in size
+//! between two binaries, such as the cost of enabling a feature, to the crates
+//! and symbols responsible for it.
+//!
+//! Reports are printed as ASCII tables in the same format as `pw bloat`'s:
+//!
+//! ```no_run
+//! use pw_bloat::{AsciiTable, DataSource, SizeReport};
+//! use pw_elf_reader::Elf;
+//!
+//! let sources = [DataSource::Sections, DataSource::Crates];
+//! let target = std::fs::read("with_float_formatting.elf").unwrap();
+//! let base = std::fs::read("base.elf").unwrap();
+//! let target = SizeReport::from_elf(&Elf::parse(&target)?, &sources)?;
+//! let base = SizeReport::from_elf(&Elf::parse(&base)?, &sources)?;
+//!
+//! print!("{}", AsciiTable::new(&target.diff(&base)?));
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! The `pw_bloat` binary generates reports from the command line.
+//!
+//! *Note*: This module requires `std`.
+#![deny(missing_docs)]
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use pw_elf_reader::{Elf, STT_FILE, STT_SECTION};
+use pw_status::{Error, Result};
+
+pub mod demangle;
+
+/// The crate label of symbols which are not Rust symbols, such as those from
+/// C libraries.
+pub const UNKNOWN_CRATE: &str = "[unknown]";
+
+/// The default maximum width of a column of an [`AsciiTable`].  Longer labels
+/// are split across multiple rows.
+pub const DEFAULT_MAX_WIDTH: usize = 80;
+
+/// A level of a size report's hierarchy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataSource {
+    /// The ELF section, such as `.text`.
+    Sections,
+    /// The crate a symbol was defined in.
+    Crates,
+    /// The demangled symbol.
+    Symbols,
+}
+
+impl DataSource {
+    /// Returns the name of the data source, as used in table headers and on
+    /// the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            DataSource::Sections => "sections",
+            DataSource::Crates => "crates",
+            DataSource::Symbols => "symbols",
+        }
+    }
+
+    /// Parses a comma separated list of data source names, such as
+    /// `sections,crates`.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - The list is empty or has an unknown or
+    ///   repeated name.
+    pub fn parse_list(list: &str) -> Result<Vec<Self>> {
+        let mut sources = Vec::new();
+        for name in list.split(',') {
+            let source = name.trim().parse()?;
+            if sources.contains(&source) {
+                return Err(Error::InvalidArgument);
+            }
+            sources.push(source);
+        }
+        Ok(sources)
+    }
+}
+
+impl FromStr for DataSource {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "sections" => Ok(DataSource::Sections),
+            "crates" => Ok(DataSource::Crates),
+            "symbols" => Ok(DataSource::Symbols),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+}
+
+/// A contiguous piece of a binary, which size reports are built from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Item {
+    /// The name of the section the item is in.
+    pub section: String,
+    /// The name of the crate the item was defined in.
+    pub crate_name: String,
+    /// The item's demangled symbol name.
+    pub symbol: String,
+    /// The item's size in bytes.
+    pub size: u64,
+}
+
+impl Item {
+    fn label(&self, source: DataSource) -> &str {
+        match source {
+            DataSource::Sections => &self.section,
+            DataSource::Crates => &self.crate_name,
+            DataSource::Symbols => &self.symbol,
+        }
+    }
+
+    /// Returns the items in the allocated sections of `elf`: one per sized
+    /// symbol, and one for the space in each section that its symbols do not
+    /// account for.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The symbol table is malformed.
+    pub fn from_elf(elf: &Elf) -> Result<Vec<Item>> {
+        let mut symbols = elf.symbols()?;
+        symbols.retain(|symbol| {
+            symbol.size != 0 && symbol.kind != STT_SECTION && symbol.kind != STT_FILE
+        });
+        // Aliases share an address and size; only count them once.
+        symbols.sort_by(|a, b| (a.address, a.size, a.name).cmp(&(b.address, b.size, b.name)));
+        symbols.dedup_by_key(|symbol| (symbol.section_index, symbol.address, symbol.size));
+
+        let mut items = Vec::new();
+        for (index, section) in elf.sections().iter().enumerate() {
+            if !section.is_allocated() || section.size == 0 {
+                continue;
+            }
+            let mut attributed = 0;
+            for symbol in symbols
+                .iter()
+                .filter(|symbol| usize::from(symbol.section_index) == index)
+            {
+                attributed += symbol.size;
+                items.push(Item {
+                    section: section.name.to_string(),
+                    crate_name: demangle::crate_name(symbol.name)
+                        .unwrap_or_else(|| UNKNOWN_CRATE.to_string()),
+                    symbol: demangle::demangle(symbol.name),
+                    size: symbol.size,
+                });
+            }
+            if section.size > attributed {
+                let label = format!("[section {}]", section.name);
+                items.push(Item {
+                    section: section.name.to_string(),
+                    crate_name: label.clone(),
+                    symbol: label,
+                    size: section.size - attributed,
+                });
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// Whether a label was added or removed by a diff.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The label is in both binaries, or the report is not a diff.
+    Unchanged,
+    /// The label is only in the target binary.
+    New,
+    /// The label is only in the base binary.
+    Deleted,
+}
+
+/// A node of a size report: a section, crate, or symbol and its size.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    /// The label's name.
+    pub name: String,
+    /// The label's size in bytes, or its change in size in a diff.
+    pub size: i64,
+    /// Whether the label was added or removed by a diff.
+    pub status: Status,
+    /// The labels one level down the report's hierarchy, largest first.
+    pub children: Vec<Label>,
+}
+
+// Sorts labels by decreasing magnitude, then by name.
+fn sort_labels(labels: &mut [Label]) {
+    labels.sort_by(|a, b| {
+        b.size
+            .unsigned_abs()
+            .cmp(&a.size.unsigned_abs())
+            .then_with(|| a.name.cmp(&b.name))
+    });
+}
+
+// Accumulates items into a tree before it is converted to sorted labels.
+#[derive(Default)]
+struct Node {
+    size: u64,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn into_labels(self) -> Vec<Label> {
+        let mut labels: Vec<Label> = self
+            .children
+            .into_iter()
+            .map(|(name, node)| Label {
+                name,
+                size: i64::try_from(node.size).unwrap_or(i64::MAX),
+                status: Status::Unchanged,
+                children: node.into_labels(),
+            })
+            .collect();
+        sort_labels(&mut labels);
+        labels
+    }
+}
+
+// Returns the labels whose sizes differ between `target` and `base`.
+fn diff_labels(target: &[Label], base: &[Label]) -> Vec<Label> {
+    let mut names: Vec<&str> = target
+        .iter()
+        .chain(base)
+        .map(|label| label.name.as_str())
+        .collect();
+    names.sort_unstable();
+    names.dedup();
+
+    let mut labels = Vec::new();
+    for name in names {
+        let label = match (
+            target.iter().find(|label| label.name == name),
+            base.iter().find(|label| label.name == name),
+        ) {
+            (Some(target), Some(base)) => Label {
+                name: name.to_string(),
+                size: target.size - base.size,
+                status: Status::Unchanged,
+                children: diff_labels(&target.children, &base.children),
+            },
+            (Some(target), None) => Label {
+                name: name.to_string(),
+                size: target.size,
+                status: Status::New,
+                children: diff_labels(&target.children, &[]),
+            },
+            (None, Some(base)) => Label {
+                name: name.to_string(),
+                size: -base.size,
+                status: Status::Deleted,
+                children: diff_labels(&[], &base.children),
+            },
+            (None, None) => unreachable!(),
+        };
+        if label.size != 0 || !label.children.is_empty() {
+            labels.push(label);
+        }
+    }
+    sort_labels(&mut labels);
+    labels
+}
+
+/// The sizes of a binary, or the differences in size between two binaries,
+/// broken down by one or more [`DataSource`]s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeReport {
+    data_sources: Vec<DataSource>,
+    labels: Vec<Label>,
+    is_diff: bool,
+}
+
+impl SizeReport {
+    /// Creates a report of `items`, with one level of labels per data source.
+    ///
+    /// # Panics
+    /// Panics if `data_sources` is empty.
+    pub fn new(data_sources: &[DataSource], items: impl IntoIterator<Item = Item>) -> Self {
+        assert!(!data_sources.is_empty(), "size reports need a data source");
+        let mut root = Node::default();
+        for item in items {
+            let mut node = &mut root;
+            for &source in data_sources {
+                node = node
+                    .children
+                    .entry(item.label(source).to_string())
+                    .or_default();
+                node.size += item.size;
+            }
+        }
+        Self {
+            data_sources: data_sources.to_vec(),
+            labels: root.into_labels(),
+            is_diff: false,
+        }
+    }
+
+    /// Creates a report of the allocated sections of `elf`.  See
+    /// [`Item::from_elf()`].
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `data_sources` is empty.
+    /// - [`Error::DataLoss`] - The symbol table is malformed.
+    pub fn from_elf(elf: &Elf, data_sources: &[DataSource]) -> Result<Self> {
+        if data_sources.is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(Self::new(data_sources, Item::from_elf(elf)?))
+    }
+
+    /// Returns a report of the changes in size from `base` to this report.
+    /// Labels whose sizes did not change are omitted.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - The reports have different data sources,
+    ///   or either is already a diff.
+    pub fn diff(&self, base: &SizeReport) -> Result<SizeReport> {
+        if self.data_sources != base.data_sources || self.is_diff || base.is_diff {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(Self {
+            data_sources: self.data_sources.clone(),
+            labels: diff_labels(&self.labels, &base.labels),
+            is_diff: true,
+        })
+    }
+
+    /// Returns the data sources of the report's levels, from the top level
+    /// down.
+    pub fn data_sources(&self) -> &[DataSource] {
+        &self.data_sources
+    }
+
+    /// Returns the top level labels of the report.
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    /// Returns whether the report is a diff.
+    pub fn is_diff(&self) -> bool {
+        self.is_diff
+    }
+
+    /// Returns the total size of the report.
+    pub fn total(&self) -> i64 {
+        self.labels.iter().map(|label| label.size).sum()
+    }
+}
+
+// Formats a size with thousands separators, and a sign for growth in diffs.
+fn format_size(size: i64, is_diff: bool) -> String {
+    let digits = size.unsigned_abs().to_string();
+    let mut groups: Vec<&str> = digits
+        .as_bytes()
+        .rchunks(3)
+        .map(|group| core::str::from_utf8(group).unwrap())
+        .collect();
+    groups.reverse();
+    let sign = if size < 0 {
+        "-"
+    } else if size > 0 && is_diff {
+        "+"
+    } else {
+        ""
+    };
+    format!("{sign}{}", groups.join(","))
+}
+
+#[derive(Clone, Copy)]
+enum Align {
+    Center,
+    Left,
+    Right,
+}
+
+/// Formats a [`SizeReport`] as an ASCII table, in the format of the tables
+/// `pw bloat` prints.
+///
+/// ```
+/// use pw_bloat::{AsciiTable, DataSource, Item, SizeReport};
+///
+/// let item = |section: &str, symbol: &str, size| Item {
+///     section: section.to_string(),
+///     crate_name: "app".to_string(),
+///     symbol: symbol.to_string(),
+///     size,
+/// };
+/// let report = SizeReport::new(
+///     &[DataSource::Sections, DataSource::Symbols],
+///     [item(".text", "app::main", 1200), item(".bss", "app::BUFFER", 64)],
+/// );
+/// assert_eq!(
+///     AsciiTable::new(&report).to_string(),
+///     "\
+/// +--------+-----------+-----+
+/// |sections|  symbols  |sizes|
+/// +========+===========+=====+
+/// |.text   |           |1,200|
+/// |        |app::main  |1,200|
+/// +--------+-----------+-----+
+/// |.bss    |           |   64|
+/// |        |app::BUFFER|   64|
+/// +========+===========+=====+
+/// |Total   |           |1,264|
+/// +--------+-----------+-----+
+/// "
+/// );
+/// ```
+pub struct AsciiTable<'a> {
+    report: &'a SizeReport,
+    max_width: usize,
+    diff_label: Option<&'a str>,
+}
+
+impl<'a> AsciiTable<'a> {
+    /// Creates a table of `report`.
+    pub fn new(report: &'a SizeReport) -> Self {
+        Self {
+            report,
+            max_width: DEFAULT_MAX_WIDTH,
+            diff_label: None,
+        }
+    }
+
+    /// Sets the maximum width of the label columns, which defaults to
+    /// [`DEFAULT_MAX_WIDTH`].
+    #[must_use]
+    pub fn with_max_width(mut self, max_width: usize) -> Self {
+        // Split labels are continued after a `...`, so they need room for
+        // more than that.
+        self.max_width = max_width.max(4);
+        self
+    }
+
+    /// Sets the title printed above the columns of a diff's table.
+    #[must_use]
+    pub fn with_diff_label(mut self, label: &'a str) -> Self {
+        self.diff_label = Some(label);
+        self
+    }
+}
+
+// Lays out the rows of an `AsciiTable`.
+struct Layout {
+    widths: Vec<usize>,
+    // The index of the first label column, which is 1 if there is a diff
+    // status column.
+    first: usize,
+    lines: Vec<String>,
+}
+
+impl Layout {
+    fn cell(&self, line: &mut String, column: usize, content: &str, align: Align) {
+        let pad = self.widths[column].saturating_sub(content.chars().count());
+        line.push('|');
+        match align {
+            Align::Center => {
+                line.push_str(&" ".repeat(pad - pad / 2));
+                line.push_str(content);
+                line.push_str(&" ".repeat(pad / 2));
+            }
+            Align::Left => {
+                line.push_str(content);
+                line.push_str(&" ".repeat(pad));
+            }
+            Align::Right => {
+                line.push_str(&" ".repeat(pad));
+                line.push_str(content);
+            }
+        }
+        if column == self.widths.len() - 1 {
+            line.push('|');
+        }
+    }
+
+    fn row(&mut self, cells: &[(usize, &str, Align)]) {
+        let mut line = String::new();
+        for column in 0..self.widths.len() {
+            match cells.iter().find(|(index, _, _)| *index == column) {
+                Some(&(_, content, align)) => self.cell(&mut line, column, content, align),
+                None => self.cell(&mut line, column, "", Align::Right),
+            }
+        }
+        self.lines.push(line);
+    }
+
+    fn divider(&mut self, fill: char) {
+        let mut line = String::new();
+        for &width in &self.widths {
+            line.push('+');
+            line.extend(std::iter::repeat_n(fill, width));
+        }
+        line.push('+');
+        self.lines.push(line);
+    }
+
+    fn label(&mut self, depth: usize, label: &Label, max_width: usize, is_diff: bool) {
+        let status = match label.status {
+            Status::New => "NEW",
+            Status::Deleted => "DEL",
+            Status::Unchanged => "",
+        };
+        let column = self.first + depth;
+        let size = format_size(label.size, is_diff);
+        let sizes = self.widths.len() - 1;
+
+        // Labels which are too wide are split across rows, with each row after
+        // the first continuing after a `...`.
+        let chars: Vec<char> = label.name.chars().collect();
+        let mut pieces = vec![chars.iter().take(max_width).collect::<String>()];
+        for chunk in chars
+            .get(max_width..)
+            .unwrap_or_default()
+            .chunks(max_width - 3)
+        {
+            pieces.push(format!("...{}", chunk.iter().collect::<String>()));
+        }
+        for (index, piece) in pieces.iter().enumerate() {
+            let mut cells = vec![(column, piece.as_str(), Align::Left)];
+            if index == 0 && is_diff {
+                cells.push((0, status, Align::Right));
+            }
+            if index == pieces.len() - 1 {
+                cells.push((sizes, size.as_str(), Align::Right));
+            }
+            self.row(&cells);
+        }
+
+        for child in &label.children {
+            self.label(depth + 1, child, max_width, is_diff);
+        }
+    }
+}
+
+// Calls `visit` with each label of `labels` and their descendants, and their
+// depth.
+fn visit_labels(labels: &[Label], depth: usize, visit: &mut impl FnMut(usize, &Label)) {
+    for label in labels {
+        visit(depth, label);
+        visit_labels(&label.children, depth + 1, visit);
+    }
+}
+
+impl fmt::Display for AsciiTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let report = self.report;
+        let is_diff = report.is_diff;
+        let total = format_size(report.total(), is_diff);
+
+        let mut titles: Vec<&str> = report.data_sources.iter().map(|s| s.name()).collect();
+        titles.push("sizes");
+        let mut widths: Vec<usize> = titles.iter().map(|title| title.len()).collect();
+        let sizes = widths.len() - 1;
+        widths[sizes] = widths[sizes].max(total.len());
+        visit_labels(&report.labels, 0, &mut |depth, label| {
+            let len = label.name.chars().count().min(self.max_width);
+            widths[depth] = widths[depth].max(len);
+            widths[sizes] = widths[sizes].max(format_size(label.size, is_diff).len());
+        });
+        if is_diff {
+            titles.insert(0, "diff");
+            widths.insert(0, "Total".len());
+            widths[sizes + 1] = widths[sizes + 1].max("(SAME)".len());
+        }
+        if let Some(label) = self.diff_label.filter(|_| is_diff) {
+            let sum: usize = widths.iter().sum();
+            let extra = label.chars().count().saturating_sub(sum) / widths.len();
+            widths.iter_mut().for_each(|width| *width += extra);
+        }
+
+        let mut layout = Layout {
+            widths,
+            first: usize::from(is_diff),
+            lines: Vec::new(),
+        };
+        let inner = layout.widths.iter().sum::<usize>() + layout.widths.len() - 1;
+        if is_diff {
+            let border = format!("+{}+", "-".repeat(inner));
+            let label = self.diff_label.unwrap_or_default();
+            let pad = inner.saturating_sub(label.chars().count());
+            layout.lines.push(border.clone());
+            layout.lines.push(format!(
+                "|{}{label}{}|",
+                " ".repeat(pad - pad / 2),
+                " ".repeat(pad / 2)
+            ));
+            layout.lines.push(border);
+        } else {
+            layout.divider('-');
+        }
+        let title_cells: Vec<_> = titles
+            .iter()
+            .enumerate()
+            .map(|(index, &title)| (index, title, Align::Center))
+            .collect();
+        layout.row(&title_cells);
+        layout.divider('=');
+
+        for (index, label) in report.labels.iter().enumerate() {
+            if index != 0 {
+                layout.divider('-');
+            }
+            layout.label(0, label, self.max_width, is_diff);
+        }
+        let last = layout.widths.len() - 1;
+        if is_diff && report.labels.is_empty() {
+            let mut cells = vec![(0, "N/A", Align::Center), (last, "0", Align::Right)];
+            cells.extend((1..last).map(|column| (column, "(same)", Align::Center)));
+            layout.row(&cells);
+        }
+        layout.divider('=');
+        layout.row(&[(0, "Total", Align::Left), (last, &total, Align::Right)]);
+        layout.divider('-');
+
+        for line in &layout.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_ELF: &[u8] = include_bytes!("../../../pw_tokenizer/py/elf_reader_test_binary.elf");
+
+    fn item(section: &str, crate_name: &str, symbol: &str, size: u64) -> Item {
+        Item {
+            section: section.to_string(),
+            crate_name: crate_name.to_string(),
+            symbol: symbol.to_string(),
+            size,
+        }
+    }
+
+    fn label(name: &str, size: i64, status: Status, children: Vec<Label>) -> Label {
+        Label {
+            name: name.to_string(),
+            size,
+            status,
+            children,
+        }
+    }
+
+    const SOURCES: &[DataSource] = &[DataSource::Sections, DataSource::Crates];
+
+    fn base_items() -> Vec<Item> {
+        vec![
+            item(".text", "app", "app::main", 400),
+            item(".text", "core", "core::fmt::write", 1000),
+            item(".text", "pw_log", "pw_log::log", 100),
+            item(".bss", "app", "app::BUFFER", 64),
+        ]
+    }
+
+    fn base() -> SizeReport {
+        SizeReport::new(SOURCES, base_items())
+    }
+
+    fn target() -> SizeReport {
+        SizeReport::new(
+            SOURCES,
+            [
+                item(".text", "app", "app::main", 420),
+                item(".text", "core", "core::fmt::write", 1000),
+                item(".text", "core", "core::fmt::float::float_to_decimal", 2500),
+                item(".rodata", "core", "core::fmt::float::TABLE", 300),
+                item(".bss", "app", "app::BUFFER", 64),
+            ],
+        )
+    }
+
+    #[test]
+    fn parses_data_sources() {
+        assert_eq!(
+            DataSource::parse_list("sections, crates,symbols"),
+            Ok(vec![
+                DataSource::Sections,
+                DataSource::Crates,
+                DataSource::Symbols
+            ])
+        );
+        assert_eq!(
+            DataSource::parse_list("symbols"),
+            Ok(vec![DataSource::Symbols])
+        );
+        assert_eq!(
+            DataSource::parse_list("segments"),
+            Err(Error::InvalidArgument)
+        );
+        assert_eq!(DataSource::parse_list(""), Err(Error::InvalidArgument));
+        assert_eq!(
+            DataSource::parse_list("crates,crates"),
+            Err(Error::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn aggregates_items() {
+        let report = base();
+        assert!(!report.is_diff());
+        assert_eq!(report.total(), 1564);
+        assert_eq!(
+            report.labels(),
+            &[
+                label(
+                    ".text",
+                    1500,
+                    Status::Unchanged,
+                    vec![
+                        label("core", 1000, Status::Unchanged, vec![]),
+                        label("app", 400, Status::Unchanged, vec![]),
+                        label("pw_log", 100, Status::Unchanged, vec![]),
+                    ]
+                ),
+                label(
+                    ".bss",
+                    64,
+                    Status::Unchanged,
+                    vec![label("app", 64, Status::Unchanged, vec![])]
+                ),
+            ]
+        );
+
+        let by_crate = SizeReport::new(&[DataSource::Crates], base_items());
+        let names: Vec<_> = by_crate
+            .labels()
+            .iter()
+            .map(|l| (l.name.as_str(), l.size))
+            .collect();
+        assert_eq!(names, [("core", 1000), ("app", 464), ("pw_log", 100)]);
+    }
+
+    #[test]
+    fn diffs_reports() {
+        let diff = target().diff(&base()).unwrap();
+        assert!(diff.is_diff());
+        assert_eq!(diff.total(), 2720);
+        assert_eq!(
+            diff.labels(),
+            &[
+                label(
+                    ".text",
+                    2420,
+                    Status::Unchanged,
+                    vec![
+                        label("core", 2500, Status::Unchanged, vec![]),
+                        label("pw_log", -100, Status::Deleted, vec![]),
+                        label("app", 20, Status::Unchanged, vec![]),
+                    ]
+                ),
+                label(
+                    ".rodata",
+                    300,
+                    Status::New,
+                    vec![label("core", 300, Status::New, vec![])]
+                ),
+            ]
+        );
+
+        let same = base().diff(&base()).unwrap();
+        assert!(same.labels().is_empty());
+        assert_eq!(same.total(), 0);
+
+        let symbols = SizeReport::new(&[DataSource::Symbols], base_items());
+        assert_eq!(target().diff(&symbols), Err(Error::InvalidArgument));
+        assert_eq!(diff.diff(&same), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn reports_elf_files() {
+        let elf = Elf::parse(TEST_ELF).unwrap();
+        let report =
+            SizeReport::from_elf(&elf, &[DataSource::Sections, DataSource::Symbols]).unwrap();
+        let allocated: u64 = elf
+            .sections()
+            .iter()
+            .filter(|section| section.is_allocated())
+            .map(|section| section.size)
+            .sum();
+        assert_eq!(report.total(), i64::try_from(allocated).unwrap());
+
+        let text = report.labels().iter().find(|l| l.name == ".text").unwrap();
+        assert_eq!(text.size, 0x151);
+        let symbols: Vec<_> = text
+            .children
+            .iter()
+            .map(|l| (l.name.as_str(), l.size))
+            .collect();
+        assert_eq!(
+            symbols,
+            [
+                ("[section .text]", 0x151 - 43 - 93 - 1),
+                ("__libc_csu_init", 93),
+                ("_start", 43),
+                ("__libc_csu_fini", 1),
+            ]
+        );
+
+        let test_section = report
+            .labels()
+            .iter()
+            .find(|l| l.name == ".test_section_1")
+            .unwrap();
+        assert_eq!(
+            test_section.children,
+            [label("message", 16, Status::Unchanged, vec![])]
+        );
+
+        let crates = SizeReport::from_elf(&elf, &[DataSource::Crates]).unwrap();
+        assert!(crates.labels().iter().any(|l| l.name == UNKNOWN_CRATE));
+        assert_eq!(SizeReport::from_elf(&elf, &[]), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(0, false), "0");
+        assert_eq!(format_size(0, true), "0");
+        assert_eq!(format_size(999, true), "+999");
+        assert_eq!(format_size(1000, false), "1,000");
+        assert_eq!(format_size(-1_234_567, true), "-1,234,567");
+    }
+
+    #[test]
+    fn formats_diff_tables() {
+        let diff = target().diff(&base()).unwrap();
+        assert_eq!(
+            AsciiTable::new(&diff).to_string(),
+            "\
++----------------------------+
+|                            |
++----------------------------+
+| diff|sections|crates| sizes|
++=====+========+======+======+
+|     |.text   |      |+2,420|
+|     |        |core  |+2,500|
+|  DEL|        |pw_log|  -100|
+|     |        |app   |   +20|
++-----+--------+------+------+
+|  NEW|.rodata |      |  +300|
+|  NEW|        |core  |  +300|
++=====+========+======+======+
+|Total|        |      |+2,720|
++-----+--------+------+------+
+"
+        );
+
+        let same = base().diff(&base()).unwrap();
+        assert_eq!(
+            AsciiTable::new(&same)
+                .with_diff_label("float formatting")
+                .to_string(),
+            "\
++----------------------------+
+|      float formatting      |
++----------------------------+
+| diff|sections|crates| sizes|
++=====+========+======+======+
+| N/A | (same) |(same)|     0|
++=====+========+======+======+
+|Total|        |      |     0|
++-----+--------+------+------+
+"
+        );
+    }
+
+    #[test]
+    fn splits_long_labels() {
+        let report = SizeReport::new(
+            &[DataSource::Symbols],
+            [item(".text", "app", "app::a_long_function_name", 10)],
+        );
+        assert_eq!(
+            AsciiTable::new(&report).with_max_width(10).to_string(),
+            "\
++----------+-----+
+|  symbols |sizes|
++==========+=====+
+|app::a_lon|     |
+|...g_funct|     |
+|...ion_nam|     |
+|...e      |   10|
++==========+=====+
+|Total     |   10|
++----------+-----+
+"
+        );
+    }
+}
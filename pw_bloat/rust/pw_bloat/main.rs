@@ -0,0 +1,122 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! Prints size reports of Rust firmware.
+//!
+//! ```text
+//! pw_bloat BINARY [--diff BINARY] [-d DATA_SOURCES] [--max-width WIDTH]
+//!          [--diff-label LABEL]
+//! ```
+
+use std::process::ExitCode;
+
+use pw_bloat::{AsciiTable, DataSource, SizeReport, DEFAULT_MAX_WIDTH};
+use pw_elf_reader::Elf;
+use pw_status::{Error, Result};
+
+const USAGE: &str = "\
+Usage: pw_bloat BINARY [--diff BINARY] [-d DATA_SOURCES] [--max-width WIDTH]
+                [--diff-label LABEL]
+
+Options:
+  --diff BINARY                   Run a size diff against a base binary
+  -d, --data-sources DATA_SOURCES Comma-separated list of data sources to
+                                  report: sections, crates, and symbols
+                                  [default: sections,crates]
+  --max-width WIDTH               Maximum width of label columns [default: 80]
+  --diff-label LABEL              Title to print above a diff's table";
+
+struct Args {
+    binary: String,
+    base: Option<String>,
+    data_sources: Vec<DataSource>,
+    max_width: usize,
+    diff_label: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> std::result::Result<Args, String> {
+    let mut binary = None;
+    let mut base = None;
+    let mut data_sources = vec![DataSource::Sections, DataSource::Crates];
+    let mut max_width = DEFAULT_MAX_WIDTH;
+    let mut diff_label = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} requires a value"));
+        match arg.as_str() {
+            "--diff" => base = Some(value()?),
+            "-d" | "--data-sources" => {
+                let list = value()?;
+                data_sources = DataSource::parse_list(&list)
+                    .map_err(|_| format!("invalid data sources: {list}"))?;
+            }
+            "--max-width" => {
+                let width = value()?;
+                max_width = width
+                    .parse()
+                    .map_err(|_| format!("invalid value for {arg}: {width}"))?;
+            }
+            "--diff-label" => diff_label = Some(value()?),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') => return Err(format!("unknown argument: {arg}")),
+            _ if binary.is_none() => binary = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}")),
+        }
+    }
+
+    Ok(Args {
+        binary: binary.ok_or("a binary is required")?,
+        base,
+        data_sources,
+        max_width,
+        diff_label,
+    })
+}
+
+fn report(path: &str, data_sources: &[DataSource]) -> Result<SizeReport> {
+    let data = std::fs::read(path).map_err(|_| Error::NotFound)?;
+    SizeReport::from_elf(&Elf::parse(&data)?, data_sources)
+}
+
+fn run(args: Args) -> Result<()> {
+    let mut report = report(&args.binary, &args.data_sources)?;
+    if let Some(base) = &args.base {
+        report = report.diff(&self::report(base, &args.data_sources)?)?;
+    }
+    let mut table = AsciiTable::new(&report).with_max_width(args.max_width);
+    if let Some(label) = &args.diff_label {
+        table = table.with_diff_label(label);
+    }
+    print!("{table}");
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("error: {message}\n");
+            }
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Boot support for ARM Cortex-M cores.
+//!
+//! `pw_boot_cortex_m` is the Rust counterpart of the C `pw_boot_cortex_m`
+//! module and works with the same linker script conventions, such as those of
+//! `basic_cortex_m.ld`.  It provides:
+//!
+//! - `pw_boot_Entry`, the reset handler, defined with [`entry!`].
+//! - Initialization of `.data` and `.bss` from the `_pw_static_init_*` and
+//!   `_pw_zero_init_*` linker symbols.
+//! - A [`VectorTable`] for the `.vector_table` section, placed with
+//!   [`vector_table!`].
+//! - Entry [`Hooks`] matching the user implemented functions of the C module.
+//! - The bounds of the main stack and heap from the `pw_boot_*_addr` symbols.
+//!
+//! The boot sequence is:
+//!
+//! ```text
+//! pw_boot_Entry() {
+//!     // Interrupts disabled.
+//!     // On ARMv8-M, set VTOR and MSPLIM.
+//!     hooks.pre_static_memory_init();
+//!     // Static memory initialization.
+//!     // Interrupts enabled.
+//!     hooks.pre_static_constructor_init();
+//!     // C++ static constructors linked into the image are invoked.
+//!     hooks.pre_main_init();
+//!     main();
+//!     hooks.post_main();
+//! }
+//! ```
+//!
+//! The main stack pointer is not changed; it is loaded from the first entry
+//! of the vector table by the core on reset.  ARMv8-M targets must enable the
+//! `armv8m` feature.
+//!
+//! # Example
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! use pw_boot_cortex_m::{default_handler, entry, vector_table, Exceptions, Handler, Hooks};
+//!
+//! unsafe extern "C" fn uart0_handler() {
+//!     // Handle the interrupt.
+//! }
+//!
+//! fn init_clocks() {
+//!     // Configure the system clocks.
+//! }
+//!
+//! fn main() {
+//!     // Run the application.
+//! }
+//!
+//! entry!(main, Hooks {
+//!     pre_main_init: init_clocks,
+//!     ..Hooks::DEFAULT
+//! });
+//!
+//! vector_table!(Exceptions::DEFAULT, {
+//!     let mut interrupts = [default_handler as Handler; 32];
+//!     interrupts[5] = uart0_handler;
+//!     interrupts
+//! });
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+use core::ops::Range;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{compiler_fence, Ordering};
+
+extern "C" {
+    // Defined with `entry!`.
+    fn pw_boot_Entry() -> !;
+
+    // Exported by the linker script.  These symbols have no value; only their
+    // addresses are meaningful.
+    static pw_boot_stack_low_addr: u8;
+    static pw_boot_stack_high_addr: u8;
+    static pw_boot_heap_low_addr: u8;
+    static pw_boot_heap_high_addr: u8;
+    static pw_boot_vector_table_addr: u8;
+
+    static _pw_static_init_flash_start: u32;
+    static mut _pw_static_init_ram_start: u32;
+    static mut _pw_static_init_ram_end: u32;
+    static mut _pw_zero_init_ram_start: u32;
+    static mut _pw_zero_init_ram_end: u32;
+
+    static __preinit_array_start: unsafe extern "C" fn();
+    static __preinit_array_end: unsafe extern "C" fn();
+    static __init_array_start: unsafe extern "C" fn();
+    static __init_array_end: unsafe extern "C" fn();
+}
+
+/// Returns the address range of the main stack.
+pub fn stack() -> Range<usize> {
+    addr_of!(pw_boot_stack_low_addr) as usize..addr_of!(pw_boot_stack_high_addr) as usize
+}
+
+/// Returns the address range of the heap, which may be empty.
+pub fn heap() -> Range<usize> {
+    addr_of!(pw_boot_heap_low_addr) as usize..addr_of!(pw_boot_heap_high_addr) as usize
+}
+
+/// Returns the address of the link-time vector table.
+pub fn vector_table_addr() -> usize {
+    addr_of!(pw_boot_vector_table_addr) as usize
+}
+
+/// An exception or interrupt handler.
+pub type Handler = unsafe extern "C" fn();
+
+/// Handles unexpected exceptions and interrupts by waiting forever.
+pub extern "C" fn default_handler() {
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+/// The handlers of the system exceptions in a [`VectorTable`].
+#[derive(Clone, Copy)]
+pub struct Exceptions {
+    /// Non-maskable interrupt.
+    pub nmi: Handler,
+    /// Hard fault.
+    pub hard_fault: Handler,
+    /// Memory management fault.  Not used on ARMv6-M.
+    pub mem_manage: Handler,
+    /// Bus fault.  Not used on ARMv6-M.
+    pub bus_fault: Handler,
+    /// Usage fault.  Not used on ARMv6-M.
+    pub usage_fault: Handler,
+    /// Secure fault.  Only used on ARMv8-M with the Security Extension.
+    pub secure_fault: Handler,
+    /// Supervisor call.
+    pub sv_call: Handler,
+    /// Debug monitor.  Not used on ARMv6-M.
+    pub debug_monitor: Handler,
+    /// Pending supervisor call.
+    pub pend_sv: Handler,
+    /// System tick timer.
+    pub sys_tick: Handler,
+}
+
+impl Exceptions {
+    /// Handles every exception with [`default_handler`].
+    pub const DEFAULT: Self = Self {
+        nmi: default_handler,
+        hard_fault: default_handler,
+        mem_manage: default_handler,
+        bus_fault: default_handler,
+        usage_fault: default_handler,
+        secure_fault: default_handler,
+        sv_call: default_handler,
+        debug_monitor: default_handler,
+        pend_sv: default_handler,
+        sys_tick: default_handler,
+    };
+
+    // Returns exception vectors 2 through 15, with `None` for the reserved
+    // vectors.
+    const fn vectors(&self) -> [Option<Handler>; 14] {
+        [
+            Some(self.nmi),
+            Some(self.hard_fault),
+            Some(self.mem_manage),
+            Some(self.bus_fault),
+            Some(self.usage_fault),
+            Some(self.secure_fault),
+            None,
+            None,
+            None,
+            Some(self.sv_call),
+            Some(self.debug_monitor),
+            None,
+            Some(self.pend_sv),
+            Some(self.sys_tick),
+        ]
+    }
+}
+
+impl Default for Exceptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A Cortex-M vector table with `N` device specific interrupts.
+///
+/// The initial stack pointer is `pw_boot_stack_high_addr` and the reset
+/// handler is `pw_boot_Entry`.  Place a vector table in the `.vector_table`
+/// section with [`vector_table!`].
+#[repr(C)]
+pub struct VectorTable<const N: usize> {
+    initial_stack_pointer: *const u8,
+    reset: unsafe extern "C" fn() -> !,
+    exceptions: [Option<Handler>; 14],
+    interrupts: [Handler; N],
+}
+
+// SAFETY: The vector table is immutable and only read by the core.
+unsafe impl<const N: usize> Sync for VectorTable<N> {}
+
+impl<const N: usize> VectorTable<N> {
+    /// Creates a vector table with the handlers of the system `exceptions`
+    /// and the device specific `interrupts`.
+    pub const fn new(exceptions: Exceptions, interrupts: [Handler; N]) -> Self {
+        Self {
+            initial_stack_pointer: addr_of!(pw_boot_stack_high_addr),
+            reset: pw_boot_Entry,
+            exceptions: exceptions.vectors(),
+            interrupts,
+        }
+    }
+}
+
+/// Places a [`VectorTable`] in the `.vector_table` section.
+///
+/// Takes the [`Exceptions`] handlers and an array of interrupt handlers.
+/// Without arguments, a table with default exception handlers and no
+/// interrupts is placed.
+#[macro_export]
+macro_rules! vector_table {
+    () => {
+        $crate::vector_table!($crate::Exceptions::DEFAULT, []);
+    };
+    ($exceptions:expr, $interrupts:expr $(,)?) => {
+        #[link_section = ".vector_table"]
+        #[used]
+        static __PW_BOOT_VECTOR_TABLE: $crate::VectorTable<{ $interrupts.len() }> =
+            $crate::VectorTable::new($exceptions, $interrupts);
+    };
+}
+
+unsafe fn nop() {}
+
+fn wait_forever() -> ! {
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+/// Functions which are called during boot, like the user implemented
+/// functions of the C `pw_boot_cortex_m` module.
+#[derive(Clone, Copy)]
+pub struct Hooks {
+    /// Called with interrupts disabled before `.data` and `.bss` are
+    /// initialized, to enable the FPU, configure clocks and memories, etc.
+    ///
+    /// # Safety
+    /// Statics have not been initialized and must not be accessed.
+    pub pre_static_memory_init: unsafe fn(),
+    /// Called after static memory is initialized and before C++ static
+    /// constructors are run, to set up the allocator, MPU, etc.
+    pub pre_static_constructor_init: fn(),
+    /// Called just before `main`, for device initialization which isn't
+    /// application specific.
+    pub pre_main_init: fn(),
+    /// Called if `main` returns.  Waits forever by default.
+    pub post_main: fn() -> !,
+}
+
+impl Hooks {
+    /// Hooks which do nothing before `main` and wait forever after it.
+    pub const DEFAULT: Self = Self {
+        pre_static_memory_init: nop,
+        pre_static_constructor_init: || {},
+        pre_main_init: || {},
+        post_main: wait_forever,
+    };
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Defines `pw_boot_Entry`, the reset handler, which boots the core and calls
+/// `main`.
+///
+/// `main` must be a `fn()`.  Optionally takes the [`Hooks`] to call during
+/// boot.
+#[macro_export]
+macro_rules! entry {
+    ($main:path $(,)?) => {
+        $crate::entry!($main, $crate::Hooks::DEFAULT);
+    };
+    ($main:path, $hooks:expr $(,)?) => {
+        #[export_name = "pw_boot_Entry"]
+        unsafe extern "C" fn __pw_boot_entry() -> ! {
+            const HOOKS: $crate::Hooks = $hooks;
+            // SAFETY: Only called by the core on reset.
+            unsafe { $crate::__private::boot(&HOOKS, $main) }
+        }
+    };
+}
+
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+
+    /// Boots the core and calls `main`.
+    ///
+    /// # Safety
+    /// Must only be called once, from the reset handler.
+    pub unsafe fn boot(hooks: &Hooks, main: fn()) -> ! {
+        cortex_m::interrupt::disable();
+
+        #[cfg(feature = "armv8m")]
+        {
+            // SAFETY: The vector table and stack are defined by the linker
+            // script and interrupts are disabled.
+            unsafe {
+                (*cortex_m::peripheral::SCB::PTR)
+                    .vtor
+                    .write(vector_table_addr() as u32);
+                cortex_m::register::msplim::write(stack().start as u32);
+            }
+        }
+
+        // SAFETY: Static memory is not yet initialized, as the hook expects.
+        unsafe {
+            (hooks.pre_static_memory_init)();
+            static_memory_init();
+            cortex_m::interrupt::enable();
+        }
+
+        (hooks.pre_static_constructor_init)();
+        // SAFETY: Static memory is initialized.
+        unsafe { static_constructor_init() };
+
+        (hooks.pre_main_init)();
+        main();
+        (hooks.post_main)()
+    }
+
+    // Copies `.data` from flash and zeroes `.bss`.  This runs before statics
+    // are initialized, so it only accesses memory through raw pointers.
+    unsafe fn static_memory_init() {
+        // SAFETY: The linker script aligns these regions to words and places
+        // no Rust values in them before they are initialized.
+        unsafe {
+            let mut src = addr_of!(_pw_static_init_flash_start);
+            let mut dst = addr_of_mut!(_pw_static_init_ram_start);
+            let end = addr_of_mut!(_pw_static_init_ram_end);
+            while dst < end {
+                dst.write_volatile(src.read_volatile());
+                src = src.add(1);
+                dst = dst.add(1);
+            }
+
+            let mut dst = addr_of_mut!(_pw_zero_init_ram_start);
+            let end = addr_of_mut!(_pw_zero_init_ram_end);
+            while dst < end {
+                dst.write_volatile(0);
+                dst = dst.add(1);
+            }
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    // Runs the static constructors of C and C++ code linked into the image.
+    unsafe fn static_constructor_init() {
+        // SAFETY: The linker script fills these arrays with valid functions.
+        unsafe {
+            let arrays = [
+                (
+                    addr_of!(__preinit_array_start),
+                    addr_of!(__preinit_array_end),
+                ),
+                (addr_of!(__init_array_start), addr_of!(__init_array_end)),
+            ];
+            for (mut function, end) in arrays {
+                while function < end {
+                    (*function)();
+                    function = function.add(1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn sys_tick() {}
+
+    #[test]
+    fn exceptions_are_placed_at_their_vectors() {
+        let exceptions = Exceptions {
+            sys_tick,
+            ..Exceptions::DEFAULT
+        };
+        let vectors = exceptions.vectors();
+        let reserved: [usize; 4] = [6, 7, 8, 11];
+        for (i, vector) in vectors.iter().enumerate() {
+            assert_eq!(vector.is_none(), reserved.contains(&i), "vector {}", i + 2);
+        }
+        assert_eq!(
+            vectors[13].map(|f| f as usize),
+            Some(sys_tick as Handler as usize)
+        );
+        assert_eq!(
+            vectors[0].map(|f| f as usize),
+            Some(default_handler as Handler as usize)
+        );
+    }
+
+    #[test]
+    fn vector_table_has_one_word_per_vector() {
+        assert_eq!(
+            core::mem::size_of::<VectorTable<32>>(),
+            (16 + 32) * core::mem::size_of::<usize>()
+        );
+    }
+}
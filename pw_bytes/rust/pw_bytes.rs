@@ -17,6 +17,11 @@
 //! # Features
 //! pw_bytes contains the follow features:
 //! * macros for concatenating `const [u8]`s and `&'static str`s.
+//! * a macro for building `const` byte arrays from integers with explicit
+//!   endianness.
+//! * [`ByteBuilder`] for building byte strings in a fixed buffer at runtime.
+//! * functions and a [`packed_struct!`] macro for reading and writing integers
+//!   at offsets in byte buffers without `unsafe` code.
 //!
 //! # Examples
 //! ```
@@ -39,9 +44,20 @@
 //! assert_eq!(STR_AB, "abcdef");
 //!
 //! ```
+//!
+//! ```
+//! use pw_bytes::byte_array;
+//!
+//! // Build a protocol header from typed fields.
+//! const MAGIC: u32 = 0xfeedface;
+//! const HEADER: [u8; 8] = byte_array!(u8: 1, u16 le: 0x0203, u32 be: MAGIC, [u8]: b"!");
+//! assert_eq!(HEADER, [1, 0x03, 0x02, 0xfe, 0xed, 0xfa, 0xce, b'!']);
+//! ```
 #![no_std]
 #![deny(missing_docs)]
 
+use pw_status::{Error, Result};
+
 /// Concatenates multiple `const [u8]`s into one.
 ///
 /// Returns a `const [u8]`
@@ -88,6 +104,507 @@ macro_rules! concat_static_strs {
   }}
 }
 
+/// Builds a `const` byte array from typed fields.
+///
+/// Each field is a type, an endianness of `le` or `be` for multi-byte
+/// integers, and a `const` value, such as `u32 be: MAGIC`.  `u8` and `i8`
+/// fields have no endianness, and `[u8]` fields copy a `const [u8]` or byte
+/// string.  This replaces hand indexed `buf[3] = ...` code for protocol
+/// headers with a declaration of their fields, as with C++ `pw::bytes::Concat`.
+///
+/// Returns a `[u8; N]` where `N` is the total size of the fields.
+///
+/// ```
+/// use pw_bytes::byte_array;
+///
+/// const ID: i16 = -2;
+/// const PACKET: [u8; 5] = byte_array!(i16 be: ID, [u8]: [0xaa, 0xbb], i8: -1);
+/// assert_eq!(PACKET, [0xff, 0xfe, 0xaa, 0xbb, 0xff]);
+/// ```
+///
+/// Multi-byte integers without an endianness do not compile:
+///
+/// ```compile_fail
+/// const PACKET: [u8; 2] = pw_bytes::byte_array!(u16: 1);
+/// ```
+#[macro_export]
+macro_rules! byte_array {
+  ($($ty:tt $($endian:ident)? : $value:expr),+ $(,)?) => {{
+      const TOTAL_LEN: usize = 0 $(+ $crate::__byte_array_field_len!($ty, $value))+;
+      const ARRAY: [u8; TOTAL_LEN] = {
+          let mut array = [0u8; TOTAL_LEN];
+          let mut array_index = 0;
+
+          // For each field, copy its bytes into `array`.
+          $({
+              let bytes = $crate::__byte_array_field!($ty $($endian)?, $value);
+              // Using while loop as for loops are not allowed in `const` expressions
+              let mut bytes_index = 0;
+              while bytes_index < bytes.len() {
+                  array[array_index] = bytes[bytes_index];
+                  array_index += 1;
+                  bytes_index += 1;
+              }
+          })+;
+
+          array
+      };
+      ARRAY
+  }}
+}
+
+// Returns the size of a `byte_array!` field.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __byte_array_field_len {
+    ([u8], $value:expr) => {
+        $value.len()
+    };
+    ($ty:ident, $value:expr) => {
+        core::mem::size_of::<$ty>()
+    };
+}
+
+// Returns the bytes of a `byte_array!` field.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __byte_array_field {
+    ([u8], $value:expr) => {
+        $value
+    };
+    (u8, $value:expr) => {
+        u8::to_le_bytes($value)
+    };
+    (i8, $value:expr) => {
+        i8::to_le_bytes($value)
+    };
+    ($ty:ident le, $value:expr) => {
+        $ty::to_le_bytes($value)
+    };
+    ($ty:ident be, $value:expr) => {
+        $ty::to_be_bytes($value)
+    };
+    ($ty:ident, $value:expr) => {
+        compile_error!(concat!(
+            "`",
+            stringify!($ty),
+            "` fields require an endianness of `le` or `be`"
+        ))
+    };
+}
+
+/// The byte order of a multi-byte integer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Endian {
+    /// Least significant byte first.
+    Little,
+    /// Most significant byte first.
+    Big,
+}
+
+macro_rules! put_integer {
+    ($name:ident, $ty:ty) => {
+        #[doc = concat!("Appends a `", stringify!($ty), "` with the byte order `endian`.")]
+        pub fn $name(&mut self, value: $ty, endian: Endian) -> &mut Self {
+            match endian {
+                Endian::Little => self.append(&value.to_le_bytes()),
+                Endian::Big => self.append(&value.to_be_bytes()),
+            }
+        }
+    };
+}
+
+/// Builds a byte string in a fixed buffer, such as a packet whose size is not
+/// known until all of it is written.
+///
+/// As with the C++ `pw::ByteBuilder`, appends do not return errors.  Instead
+/// the first error is recorded and returned by [`ByteBuilder::status()`],
+/// and appends after an error are ignored.  [`ByteBuilder::required_size()`]
+/// reports how large the buffer would have needed to be for every append to
+/// succeed.
+///
+/// # Example
+///
+/// ```
+/// use pw_bytes::{ByteBuilder, Endian};
+/// use pw_status::Error;
+///
+/// let mut buffer = [0u8; 4];
+/// let mut builder = ByteBuilder::new(&mut buffer);
+/// builder.put_u16(0x0102, Endian::Big).append(b"ab");
+/// assert_eq!(builder.as_slice(), &[0x01, 0x02, b'a', b'b']);
+///
+/// builder.put_u32(7, Endian::Little);
+/// assert_eq!(builder.status(), Err(Error::ResourceExhausted));
+/// assert_eq!(builder.len(), 4);
+/// assert_eq!(builder.required_size(), 8);
+/// ```
+pub struct ByteBuilder<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+    required_size: usize,
+    status: Result<()>,
+}
+
+impl<'a> ByteBuilder<'a> {
+    /// Creates an empty `ByteBuilder` which builds into `buffer`.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            buffer,
+            len: 0,
+            required_size: 0,
+            status: Ok(()),
+        }
+    }
+
+    /// Appends `bytes`.
+    ///
+    /// If `bytes` does not fit, nothing is appended and the status is set to
+    /// [`Error::ResourceExhausted`].
+    pub fn append(&mut self, bytes: &[u8]) -> &mut Self {
+        self.required_size = self.required_size.saturating_add(bytes.len());
+        if self.status.is_err() {
+            return self;
+        }
+        match self.buffer.get_mut(self.len..self.len + bytes.len()) {
+            Some(dest) => {
+                dest.copy_from_slice(bytes);
+                self.len += bytes.len();
+            }
+            None => self.status = Err(Error::ResourceExhausted),
+        }
+        self
+    }
+
+    /// Appends a `u8`.
+    pub fn put_u8(&mut self, value: u8) -> &mut Self {
+        self.append(&[value])
+    }
+
+    /// Appends an `i8`.
+    pub fn put_i8(&mut self, value: i8) -> &mut Self {
+        self.append(&value.to_le_bytes())
+    }
+
+    put_integer!(put_u16, u16);
+    put_integer!(put_u32, u32);
+    put_integer!(put_u64, u64);
+    put_integer!(put_i16, i16);
+    put_integer!(put_i32, i32);
+    put_integer!(put_i64, i64);
+
+    /// Returns the first error encountered, if any.
+    pub fn status(&self) -> Result<()> {
+        self.status
+    }
+
+    /// Returns `true` if every append succeeded.
+    pub fn ok(&self) -> bool {
+        self.status.is_ok()
+    }
+
+    /// Returns the number of bytes appended.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no bytes were appended.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the size of the buffer.
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns the number of bytes every append would have used, including
+    /// those which failed.
+    pub fn required_size(&self) -> usize {
+        self.required_size
+    }
+
+    /// Returns the bytes appended.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    /// Removes all bytes and clears the status.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.required_size = 0;
+        self.status = Ok(());
+    }
+
+    /// Returns the bytes appended, or the first error encountered.
+    pub fn finish(self) -> Result<&'a [u8]> {
+        self.status?;
+        Ok(&self.buffer[..self.len])
+    }
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// An integer which can be read from and written to byte buffers.
+///
+/// Implemented for all fixed size integer types.
+pub trait Integer: Copy + private::Sealed {
+    /// The integer's bytes, a `[u8; N]`.
+    type Bytes: AsRef<[u8]> + AsMut<[u8]> + Default;
+
+    /// Returns the integer with the byte order `endian` from `bytes`.
+    fn from_bytes(bytes: Self::Bytes, endian: Endian) -> Self;
+
+    /// Returns the bytes of the integer with the byte order `endian`.
+    fn to_bytes(self, endian: Endian) -> Self::Bytes;
+}
+
+macro_rules! impl_integer {
+    ($($ty:ty),*) => {$(
+        impl private::Sealed for $ty {}
+
+        impl Integer for $ty {
+            type Bytes = [u8; core::mem::size_of::<$ty>()];
+
+            fn from_bytes(bytes: Self::Bytes, endian: Endian) -> Self {
+                match endian {
+                    Endian::Little => <$ty>::from_le_bytes(bytes),
+                    Endian::Big => <$ty>::from_be_bytes(bytes),
+                }
+            }
+
+            fn to_bytes(self, endian: Endian) -> Self::Bytes {
+                match endian {
+                    Endian::Little => self.to_le_bytes(),
+                    Endian::Big => self.to_be_bytes(),
+                }
+            }
+        }
+    )*};
+}
+
+impl_integer!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+/// Reads a `T` with the byte order `endian` from `buffer` at `offset`.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `buffer` ends before the integer does.
+pub fn read<T: Integer>(buffer: &[u8], offset: usize, endian: Endian) -> Result<T> {
+    let mut bytes = T::Bytes::default();
+    let len = bytes.as_ref().len();
+    let src = offset
+        .checked_add(len)
+        .and_then(|end| buffer.get(offset..end))
+        .ok_or(Error::OutOfRange)?;
+    bytes.as_mut().copy_from_slice(src);
+    Ok(T::from_bytes(bytes, endian))
+}
+
+/// Reads a little endian `T` from `buffer` at `offset`.
+///
+/// ```
+/// let buffer = [0xaa, 0x01, 0x02, 0x03, 0x04];
+/// assert_eq!(pw_bytes::read_le::<u32>(&buffer, 1), Ok(0x04030201));
+/// ```
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `buffer` ends before the integer does.
+pub fn read_le<T: Integer>(buffer: &[u8], offset: usize) -> Result<T> {
+    read(buffer, offset, Endian::Little)
+}
+
+/// Reads a big endian `T` from `buffer` at `offset`.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `buffer` ends before the integer does.
+pub fn read_be<T: Integer>(buffer: &[u8], offset: usize) -> Result<T> {
+    read(buffer, offset, Endian::Big)
+}
+
+/// Writes `value` with the byte order `endian` to `buffer` at `offset`.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `buffer` ends before the integer does.  Nothing
+///   is written.
+pub fn write<T: Integer>(buffer: &mut [u8], offset: usize, value: T, endian: Endian) -> Result<()> {
+    let bytes = value.to_bytes(endian);
+    let len = bytes.as_ref().len();
+    offset
+        .checked_add(len)
+        .and_then(|end| buffer.get_mut(offset..end))
+        .ok_or(Error::OutOfRange)?
+        .copy_from_slice(bytes.as_ref());
+    Ok(())
+}
+
+/// Writes `value` as little endian to `buffer` at `offset`.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `buffer` ends before the integer does.  Nothing
+///   is written.
+pub fn write_le<T: Integer>(buffer: &mut [u8], offset: usize, value: T) -> Result<()> {
+    write(buffer, offset, value, Endian::Little)
+}
+
+/// Writes `value` as big endian to `buffer` at `offset`.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `buffer` ends before the integer does.  Nothing
+///   is written.
+pub fn write_be<T: Integer>(buffer: &mut [u8], offset: usize, value: T) -> Result<()> {
+    write(buffer, offset, value, Endian::Big)
+}
+
+/// Declares a struct of integer fields with a packed byte layout.
+///
+/// Each field is an integer type followed by an endianness of `le` or `be`,
+/// which may be omitted for `u8` and `i8`.  Fields are laid out in order with
+/// no padding.  The struct gets:
+///
+/// - `SIZE`, the size of its layout in bytes.
+/// - `from_array()` and `to_array()`, which convert to and from a
+///   `[u8; SIZE]`, so buffers of the wrong size are rejected at compile time.
+/// - `read()` and `write()`, which decode and encode the struct at an offset
+///   in a buffer and return [`Error::OutOfRange`] if it does not fit.
+///
+/// The layout can be checked at compile time with a `const` assertion on
+/// `SIZE`.
+///
+/// ```
+/// use pw_bytes::packed_struct;
+///
+/// packed_struct! {
+///     /// A packet header.
+///     #[derive(Debug, PartialEq)]
+///     pub struct Header {
+///         pub magic: u32 be,
+///         pub length: u16 le,
+///         pub flags: u8,
+///     }
+/// }
+/// const _: () = assert!(Header::SIZE == 7);
+///
+/// let buffer = [0xfe, 0xed, 0xfa, 0xce, 0x10, 0x00, 0x01];
+/// let header = Header::read(&buffer, 0).unwrap();
+/// assert_eq!(header, Header { magic: 0xfeedface, length: 16, flags: 1 });
+/// assert_eq!(header.to_array(), buffer);
+/// ```
+#[macro_export]
+macro_rules! packed_struct {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_attr:meta])* $field_vis:vis $field:ident : $ty:ident $($endian:ident)?),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $($(#[$field_attr])* $field_vis $field: $ty,)*
+        }
+
+        impl $name {
+            /// The size of the struct's packed layout in bytes.
+            pub const SIZE: usize = 0 $(+ core::mem::size_of::<$ty>())*;
+
+            /// Decodes the struct from its packed layout.
+            pub fn from_array(bytes: &[u8; Self::SIZE]) -> Self {
+                let mut offset = 0;
+                $(
+                    let mut field_bytes = [0u8; core::mem::size_of::<$ty>()];
+                    let end = offset + field_bytes.len();
+                    field_bytes.copy_from_slice(&bytes[offset..end]);
+                    offset = end;
+                    let $field = <$ty as $crate::Integer>::from_bytes(
+                        field_bytes,
+                        $crate::__packed_struct_endian!($ty $($endian)?),
+                    );
+                )*
+                let _ = offset;
+                Self { $($field,)* }
+            }
+
+            /// Encodes the struct in its packed layout.
+            // Structs are not required to be `Copy`.
+            #[allow(clippy::wrong_self_convention)]
+            pub fn to_array(&self) -> [u8; Self::SIZE] {
+                let mut bytes = [0u8; Self::SIZE];
+                let mut offset = 0;
+                $(
+                    let field_bytes = $crate::Integer::to_bytes(
+                        self.$field,
+                        $crate::__packed_struct_endian!($ty $($endian)?),
+                    );
+                    bytes[offset..offset + field_bytes.len()].copy_from_slice(&field_bytes);
+                    offset += field_bytes.len();
+                )*
+                let _ = offset;
+                bytes
+            }
+
+            /// Decodes the struct from `buffer` at `offset`.
+            ///
+            /// # Errors
+            /// - `Error::OutOfRange` - `buffer` ends before the struct does.
+            pub fn read(buffer: &[u8], offset: usize) -> $crate::__private::Result<Self> {
+                offset
+                    .checked_add(Self::SIZE)
+                    .and_then(|end| buffer.get(offset..end))
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(Self::from_array)
+                    .ok_or($crate::__private::Error::OutOfRange)
+            }
+
+            /// Encodes the struct to `buffer` at `offset`.
+            ///
+            /// # Errors
+            /// - `Error::OutOfRange` - `buffer` ends before the struct does.
+            ///   Nothing is written.
+            pub fn write(&self, buffer: &mut [u8], offset: usize) -> $crate::__private::Result<()> {
+                offset
+                    .checked_add(Self::SIZE)
+                    .and_then(|end| buffer.get_mut(offset..end))
+                    .ok_or($crate::__private::Error::OutOfRange)?
+                    .copy_from_slice(&self.to_array());
+                Ok(())
+            }
+        }
+    };
+}
+
+// Returns the `Endian` of a `packed_struct!` field.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __packed_struct_endian {
+    (u8) => {
+        $crate::Endian::Little
+    };
+    (i8) => {
+        $crate::Endian::Little
+    };
+    ($ty:ident le) => {
+        $crate::Endian::Little
+    };
+    ($ty:ident be) => {
+        $crate::Endian::Big
+    };
+    ($ty:ident) => {
+        compile_error!(concat!(
+            "`",
+            stringify!($ty),
+            "` fields require an endianness of `le` or `be`"
+        ))
+    };
+}
+
+// Re-export dependences of `pw_bytes` macros to be accessed via
+// `$crate::__private`.
+#[doc(hidden)]
+pub mod __private {
+    pub use pw_status::{Error, Result};
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -150,4 +667,123 @@ mod tests {
         const STR_AB: &'static str = concat_static_strs!(STR_A, STR_B);
         assert_eq!(STR_AB, "abcdef");
     }
+
+    #[test]
+    fn byte_array_encodes_fields_with_endianness() {
+        const LEN: u16 = 0x0102;
+        const ARRAY: [u8; 14] = byte_array!(
+            u8: 0xff,
+            u16 le: LEN,
+            u16 be: LEN,
+            i32 le: -2,
+            u32 be: 0x0a0b0c0d,
+            [u8]: b"",
+            i8: -128,
+        );
+        assert_eq!(
+            ARRAY,
+            [0xff, 0x02, 0x01, 0x01, 0x02, 0xfe, 0xff, 0xff, 0xff, 0x0a, 0x0b, 0x0c, 0x0d, 0x80]
+        );
+    }
+
+    #[test]
+    fn byte_array_copies_byte_slices() {
+        const SLICE: &[u8] = b"abc";
+        const ARRAY: [u8; 5] = byte_array!([u8]: SLICE, u16 be: 0x6465);
+        assert_eq!(&ARRAY, b"abcde");
+    }
+
+    #[test]
+    fn byte_builder_appends_integers() {
+        use crate::{ByteBuilder, Endian};
+
+        let mut buffer = [0u8; 16];
+        let mut builder = ByteBuilder::new(&mut buffer);
+        assert!(builder.is_empty());
+        builder
+            .put_u8(0xff)
+            .put_i8(-2)
+            .put_u16(0x0102, Endian::Little)
+            .put_i32(-2, Endian::Big)
+            .put_u64(0x0a0b, Endian::Big);
+        assert_eq!(
+            builder.finish(),
+            Ok(&[0xff, 0xfe, 0x02, 0x01, 0xff, 0xff, 0xff, 0xfe, 0, 0, 0, 0, 0, 0, 0x0a, 0x0b][..])
+        );
+    }
+
+    #[test]
+    fn byte_builder_records_first_error_and_required_size() {
+        use crate::{ByteBuilder, Endian};
+        use pw_status::Error;
+
+        let mut buffer = [0u8; 5];
+        let mut builder = ByteBuilder::new(&mut buffer);
+        builder.append(b"abcd").put_u16(1, Endian::Little);
+        assert_eq!(builder.status(), Err(Error::ResourceExhausted));
+        // Appends after an error are ignored even if they would fit.
+        builder.put_u8(1);
+        assert!(!builder.ok());
+        assert_eq!(builder.as_slice(), b"abcd");
+        assert_eq!(builder.required_size(), 7);
+        assert_eq!(builder.capacity(), 5);
+
+        builder.clear();
+        builder.put_u8(1);
+        assert!(builder.ok());
+        assert_eq!(builder.required_size(), 1);
+        assert_eq!(builder.finish(), Ok(&[1u8][..]));
+
+        let mut buffer = [0u8; 1];
+        let mut builder = ByteBuilder::new(&mut buffer);
+        builder.put_u32(1, Endian::Big);
+        assert_eq!(builder.finish(), Err(Error::ResourceExhausted));
+    }
+
+    #[test]
+    fn integers_are_read_and_written_at_offsets() {
+        use crate::{read, read_be, read_le, write_be, write_le, Endian};
+        use pw_status::Error;
+
+        let mut buffer = [0u8; 8];
+        write_le(&mut buffer, 1, 0x0102u16).unwrap();
+        write_be(&mut buffer, 3, -2i32).unwrap();
+        assert_eq!(buffer, [0, 0x02, 0x01, 0xff, 0xff, 0xff, 0xfe, 0]);
+        assert_eq!(read_le::<u16>(&buffer, 1), Ok(0x0102));
+        assert_eq!(read_be::<i32>(&buffer, 3), Ok(-2));
+        assert_eq!(read::<u8>(&buffer, 7, Endian::Big), Ok(0));
+
+        assert_eq!(read_le::<u16>(&buffer, 7), Err(Error::OutOfRange));
+        assert_eq!(read_le::<u8>(&buffer, usize::MAX), Err(Error::OutOfRange));
+        assert_eq!(write_le(&mut buffer, 5, 0u32), Err(Error::OutOfRange));
+        assert_eq!(buffer[5..], [0xff, 0xfe, 0]);
+    }
+
+    packed_struct! {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        struct Record {
+            id: i8,
+            value: u64 le,
+            checksum: u16 be,
+        }
+    }
+
+    #[test]
+    fn packed_struct_round_trips() {
+        use pw_status::Error;
+
+        const _: () = assert!(Record::SIZE == 11);
+        let record = Record {
+            id: -1,
+            value: 0x0102,
+            checksum: 0xabcd,
+        };
+        let mut buffer = [0u8; 12];
+        record.write(&mut buffer, 1).unwrap();
+        assert_eq!(buffer, [0, 0xff, 0x02, 0x01, 0, 0, 0, 0, 0, 0, 0xab, 0xcd]);
+        assert_eq!(Record::read(&buffer, 1), Ok(record));
+
+        assert_eq!(Record::read(&buffer, 2), Err(Error::OutOfRange));
+        assert_eq!(record.write(&mut buffer, 2), Err(Error::OutOfRange));
+    }
 }
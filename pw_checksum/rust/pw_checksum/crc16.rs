@@ -0,0 +1,201 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use super::Checksum;
+
+// Shifts `bits` bits of input through a non-reflected 16 bit CRC register.
+const fn shift(mut crc: u16, poly: u16, bits: u32) -> u16 {
+    let mut bit = 0;
+    while bit < bits {
+        crc = if crc & 0x8000 != 0 {
+            (crc << 1) ^ poly
+        } else {
+            crc << 1
+        };
+        bit += 1;
+    }
+    crc
+}
+
+/// A bitwise, non-reflected 16 bit CRC with polynomial `POLY` and initial
+/// value `INIT`.
+///
+/// See [`Crc16Ccitt`] for the variant used by Pigweed's C++
+/// `pw::checksum::CcittCrc16()`.  This implementation favors code size over
+/// speed.  [`Crc16EightBit`] computes the same CRC with a lookup table.
+#[derive(Clone, Copy)]
+pub struct Crc16<const POLY: u16, const INIT: u16> {
+    crc: u16,
+}
+
+/// The CRC-16-CCITT checksum.  See [`Crc16`].
+pub type Crc16Ccitt = Crc16<0x1021, 0xffff>;
+
+impl<const POLY: u16, const INIT: u16> Crc16<POLY, INIT> {
+    /// Create a new CRC with no data.
+    pub const fn new() -> Self {
+        Self::from_value(INIT)
+    }
+
+    /// Create a CRC which continues from `value`, the CRC of earlier data.
+    pub const fn from_value(value: u16) -> Self {
+        Self { crc: value }
+    }
+
+    /// Computes the CRC of `data` in a single call.
+    pub const fn calculate(data: &[u8]) -> u16 {
+        Self::new().update_const(data).crc
+    }
+
+    /// Returns a CRC updated with `data`.  Usable in `const` contexts.
+    pub const fn update_const(mut self, data: &[u8]) -> Self {
+        let mut i = 0;
+        while i < data.len() {
+            self.crc = shift(self.crc ^ ((data[i] as u16) << 8), POLY, 8);
+            i += 1;
+        }
+        self
+    }
+}
+
+impl<const POLY: u16, const INIT: u16> Default for Crc16<POLY, INIT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const POLY: u16, const INIT: u16> Checksum for Crc16<POLY, INIT> {
+    type Output = u16;
+
+    fn update(&mut self, data: &[u8]) {
+        *self = self.update_const(data);
+    }
+
+    fn value(&self) -> u16 {
+        self.crc
+    }
+}
+
+/// A non-reflected 16 bit CRC with polynomial `POLY` and initial value
+/// `INIT` which processes a byte at a time with a 256 entry lookup table.
+///
+/// This computes the same CRC as [`Crc16`], favoring speed over code size.
+#[derive(Clone, Copy)]
+pub struct Crc16EightBit<const POLY: u16, const INIT: u16> {
+    crc: u16,
+}
+
+/// The CRC-16-CCITT checksum.  See [`Crc16EightBit`].
+pub type Crc16CcittEightBit = Crc16EightBit<0x1021, 0xffff>;
+
+impl<const POLY: u16, const INIT: u16> Crc16EightBit<POLY, INIT> {
+    const TABLE: [u16; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < table.len() {
+            table[i] = shift((i as u16) << 8, POLY, 8);
+            i += 1;
+        }
+        table
+    };
+
+    /// Create a new CRC with no data.
+    pub const fn new() -> Self {
+        Self::from_value(INIT)
+    }
+
+    /// Create a CRC which continues from `value`, the CRC of earlier data.
+    pub const fn from_value(value: u16) -> Self {
+        Self { crc: value }
+    }
+
+    /// Computes the CRC of `data` in a single call.
+    pub const fn calculate(data: &[u8]) -> u16 {
+        Self::new().update_const(data).crc
+    }
+
+    /// Returns a CRC updated with `data`.  Usable in `const` contexts.
+    pub const fn update_const(mut self, data: &[u8]) -> Self {
+        let mut i = 0;
+        while i < data.len() {
+            let index = ((self.crc >> 8) as u8 ^ data[i]) as usize;
+            self.crc = (self.crc << 8) ^ Self::TABLE[index];
+            i += 1;
+        }
+        self
+    }
+}
+
+impl<const POLY: u16, const INIT: u16> Default for Crc16EightBit<POLY, INIT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const POLY: u16, const INIT: u16> Checksum for Crc16EightBit<POLY, INIT> {
+    type Output = u16;
+
+    fn update(&mut self, data: &[u8]) {
+        *self = self.update_const(data);
+    }
+
+    fn value(&self) -> u16 {
+        self.crc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CHECK_DATA;
+
+    #[test]
+    fn crc16_ccitt_matches_check_value() {
+        assert_eq!(Crc16Ccitt::calculate(CHECK_DATA), 0x29b1);
+        assert_eq!(Crc16Ccitt::calculate(&[]), 0xffff);
+        assert_eq!(Crc16CcittEightBit::calculate(CHECK_DATA), 0x29b1);
+        assert_eq!(Crc16CcittEightBit::calculate(&[]), 0xffff);
+    }
+
+    #[test]
+    fn crc16_supports_other_polynomials() {
+        // CRC-16/XMODEM
+        assert_eq!(Crc16::<0x1021, 0>::calculate(CHECK_DATA), 0x31c3);
+        // CRC-16/BUYPASS
+        assert_eq!(Crc16EightBit::<0x8005, 0>::calculate(CHECK_DATA), 0xfee8);
+    }
+
+    #[test]
+    fn implementations_match_for_all_bytes() {
+        let data: [u8; 256] = core::array::from_fn(|i| i as u8);
+        assert_eq!(
+            Crc16CcittEightBit::calculate(&data),
+            Crc16Ccitt::calculate(&data)
+        );
+    }
+
+    #[test]
+    fn resumed_crc_matches_single_calculation() {
+        let (first, second) = CHECK_DATA.split_at(4);
+        let mut crc = Crc16CcittEightBit::from_value(Crc16Ccitt::calculate(first));
+        crc.update(second);
+        assert_eq!(crc.value(), Crc16Ccitt::calculate(CHECK_DATA));
+    }
+
+    #[test]
+    fn crc_is_computed_at_compile_time() {
+        const CRC: u16 = Crc16CcittEightBit::calculate(b"123456789");
+        assert_eq!(CRC, 0x29b1);
+    }
+}
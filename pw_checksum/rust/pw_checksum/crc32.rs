@@ -0,0 +1,224 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use super::Checksum;
+
+// Shifts `bits` bits of input through a reflected 32 bit CRC register.
+const fn shift(mut crc: u32, poly: u32, bits: u32) -> u32 {
+    let mut bit = 0;
+    while bit < bits {
+        crc = if crc & 1 != 0 {
+            (crc >> 1) ^ poly
+        } else {
+            crc >> 1
+        };
+        bit += 1;
+    }
+    crc
+}
+
+// Builds the lookup table for processing `N` bits of input at a time, where
+// `N` is `log2(SIZE)`.
+const fn table<const SIZE: usize>(poly: u32) -> [u32; SIZE] {
+    let mut table = [0; SIZE];
+    let mut i = 0;
+    while i < SIZE {
+        table[i] = shift(i as u32, poly, SIZE.trailing_zeros());
+        i += 1;
+    }
+    table
+}
+
+// Implements the parts of the 32 bit CRCs' APIs which do not depend on how
+// data is processed.
+macro_rules! crc32_common {
+    ($name:ident) => {
+        impl<const POLY: u32> $name<POLY> {
+            /// Create a new CRC with no data.
+            pub const fn new() -> Self {
+                Self { crc: !0 }
+            }
+
+            /// Create a CRC which continues from `value`, the CRC of earlier
+            /// data.
+            pub const fn from_value(value: u32) -> Self {
+                Self { crc: !value }
+            }
+
+            /// Computes the CRC of `data` in a single call.
+            pub const fn calculate(data: &[u8]) -> u32 {
+                !Self::new().update_const(data).crc
+            }
+        }
+
+        impl<const POLY: u32> Default for $name<POLY> {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl<const POLY: u32> Checksum for $name<POLY> {
+            type Output = u32;
+
+            fn update(&mut self, data: &[u8]) {
+                *self = self.update_const(data);
+            }
+
+            fn value(&self) -> u32 {
+                !self.crc
+            }
+        }
+    };
+}
+
+/// A bitwise, reflected 32 bit CRC with reversed polynomial `POLY`.
+///
+/// The register is initialized to all ones and the final value is inverted.
+/// See [`Crc32Ieee`] for the standard CRC-32 used by Pigweed's C++
+/// `pw::checksum::Crc32`.  This implementation favors code size over speed.
+/// [`Crc32FourBit`] and [`Crc32EightBit`] compute the same CRC with lookup
+/// tables.
+#[derive(Clone, Copy)]
+pub struct Crc32<const POLY: u32> {
+    crc: u32,
+}
+
+/// The standard CRC-32 (IEEE 802.3) checksum.  See [`Crc32`].
+pub type Crc32Ieee = Crc32<0xedb8_8320>;
+
+impl<const POLY: u32> Crc32<POLY> {
+    /// Returns a CRC updated with `data`.  Usable in `const` contexts.
+    pub const fn update_const(mut self, data: &[u8]) -> Self {
+        let mut i = 0;
+        while i < data.len() {
+            self.crc = shift(self.crc ^ data[i] as u32, POLY, 8);
+            i += 1;
+        }
+        self
+    }
+}
+
+crc32_common!(Crc32);
+
+/// A reflected 32 bit CRC with reversed polynomial `POLY` which processes 4
+/// bits at a time with a 16 entry lookup table.
+///
+/// This computes the same CRC as [`Crc32`], balancing code size and speed.
+#[derive(Clone, Copy)]
+pub struct Crc32FourBit<const POLY: u32> {
+    crc: u32,
+}
+
+/// The standard CRC-32 (IEEE 802.3) checksum.  See [`Crc32FourBit`].
+pub type Crc32IeeeFourBit = Crc32FourBit<0xedb8_8320>;
+
+impl<const POLY: u32> Crc32FourBit<POLY> {
+    const TABLE: [u32; 16] = table(POLY);
+
+    /// Returns a CRC updated with `data`.  Usable in `const` contexts.
+    pub const fn update_const(mut self, data: &[u8]) -> Self {
+        let mut i = 0;
+        while i < data.len() {
+            let byte = data[i] as u32;
+            self.crc = (self.crc >> 4) ^ Self::TABLE[((self.crc ^ byte) & 0xf) as usize];
+            self.crc = (self.crc >> 4) ^ Self::TABLE[((self.crc ^ (byte >> 4)) & 0xf) as usize];
+            i += 1;
+        }
+        self
+    }
+}
+
+crc32_common!(Crc32FourBit);
+
+/// A reflected 32 bit CRC with reversed polynomial `POLY` which processes a
+/// byte at a time with a 256 entry lookup table.
+///
+/// This computes the same CRC as [`Crc32`], favoring speed over code size.
+#[derive(Clone, Copy)]
+pub struct Crc32EightBit<const POLY: u32> {
+    crc: u32,
+}
+
+/// The standard CRC-32 (IEEE 802.3) checksum.  See [`Crc32EightBit`].
+pub type Crc32IeeeEightBit = Crc32EightBit<0xedb8_8320>;
+
+impl<const POLY: u32> Crc32EightBit<POLY> {
+    const TABLE: [u32; 256] = table(POLY);
+
+    /// Returns a CRC updated with `data`.  Usable in `const` contexts.
+    pub const fn update_const(mut self, data: &[u8]) -> Self {
+        let mut i = 0;
+        while i < data.len() {
+            let index = (self.crc as u8 ^ data[i]) as usize;
+            self.crc = (self.crc >> 8) ^ Self::TABLE[index];
+            i += 1;
+        }
+        self
+    }
+}
+
+crc32_common!(Crc32EightBit);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CHECK_DATA;
+
+    #[test]
+    fn crc32_matches_check_value() {
+        assert_eq!(Crc32Ieee::calculate(CHECK_DATA), 0xcbf4_3926);
+        assert_eq!(Crc32Ieee::calculate(&[]), 0);
+        assert_eq!(Crc32IeeeFourBit::calculate(CHECK_DATA), 0xcbf4_3926);
+        assert_eq!(Crc32IeeeEightBit::calculate(CHECK_DATA), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn crc32_supports_other_polynomials() {
+        // CRC-32C (Castagnoli)
+        assert_eq!(Crc32::<0x82f6_3b78>::calculate(CHECK_DATA), 0xe306_9283);
+        assert_eq!(
+            Crc32FourBit::<0x82f6_3b78>::calculate(CHECK_DATA),
+            0xe306_9283
+        );
+        assert_eq!(
+            Crc32EightBit::<0x82f6_3b78>::calculate(CHECK_DATA),
+            0xe306_9283
+        );
+    }
+
+    #[test]
+    fn implementations_match_for_all_bytes() {
+        let data: [u8; 256] = core::array::from_fn(|i| i as u8);
+        let crc = Crc32Ieee::calculate(&data);
+        assert_eq!(Crc32IeeeFourBit::calculate(&data), crc);
+        assert_eq!(Crc32IeeeEightBit::calculate(&data), crc);
+    }
+
+    #[test]
+    fn incremental_update_matches_single_calculation() {
+        let mut crc = Crc32IeeeEightBit::new();
+        for chunk in CHECK_DATA.chunks(2) {
+            crc.update(chunk);
+        }
+        assert_eq!(crc.value(), Crc32Ieee::calculate(CHECK_DATA));
+    }
+
+    #[test]
+    fn resumed_crc_matches_single_calculation() {
+        let (first, second) = CHECK_DATA.split_at(4);
+        let mut crc = Crc32IeeeFourBit::from_value(Crc32Ieee::calculate(first));
+        crc.update(second);
+        assert_eq!(crc.value(), Crc32Ieee::calculate(CHECK_DATA));
+    }
+}
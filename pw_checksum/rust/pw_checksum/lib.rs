@@ -0,0 +1,125 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_checksum` provides the checksum algorithms used by Pigweed protocols,
+//! such as the CRC-32 frame check sequence of HDLC frames and the CRC-16 of
+//! key-value store entries.
+//!
+//! Checksums can be calculated in a single call, including in `const`
+//! contexts, or incrementally with [`Checksum::update()`]:
+//!
+//! ```
+//! use pw_checksum::{Checksum, Crc32Ieee};
+//!
+//! let mut crc = Crc32Ieee::new();
+//! crc.update(b"12345");
+//! crc.update(b"6789");
+//! assert_eq!(crc.value(), Crc32Ieee::calculate(b"123456789"));
+//! ```
+//!
+//! A checksum of earlier data, such as one stored alongside it, can be
+//! extended with more data by resuming from its value:
+//!
+//! ```
+//! use pw_checksum::{Checksum, Crc16Ccitt};
+//!
+//! let stored = Crc16Ccitt::calculate(b"1234");
+//! let mut crc = Crc16Ccitt::from_value(stored);
+//! crc.update(b"56789");
+//! assert_eq!(crc.value(), Crc16Ccitt::calculate(b"123456789"));
+//! ```
+//!
+//! # Implementations
+//!
+//! Like the C++ `pw_checksum` module, each CRC is available in
+//! implementations with different size and speed tradeoffs.  All of them
+//! compute the same values.
+//!
+//! | Processes       | CRC-16                 | CRC-32                | Lookup table |
+//! |-----------------|------------------------|-----------------------|--------------|
+//! | 1 bit per step  | [`Crc16`]              | [`Crc32`]             | none         |
+//! | 4 bits per step |                        | [`Crc32FourBit`]      | 16 entries   |
+//! | 8 bits per step | [`Crc16EightBit`]      | [`Crc32EightBit`]     | 256 entries  |
+//!
+//! Lookup tables are computed at compile time and are only included in
+//! binaries which use them.
+//!
+//! # Hardware CRC peripherals
+//!
+//! Code which computes checksums, such as `pw_stream`'s `CrcWriter`,
+//! should accept any [`Checksum`] so that drivers for CRC peripherals can
+//! be used in place of the software implementations:
+//!
+//! ```
+//! use pw_checksum::{Checksum, Crc32Ieee};
+//!
+//! // A driver for a CRC peripheral which is configured for the standard
+//! // CRC-32.
+//! struct HardwareCrc32 {
+//!     // Stands in for the peripheral's data and result registers.
+//!     software: Crc32Ieee,
+//! }
+//!
+//! impl Checksum for HardwareCrc32 {
+//!     type Output = u32;
+//!
+//!     fn update(&mut self, data: &[u8]) {
+//!         // Feed `data` to the peripheral's data register.
+//! #       self.software.update(data);
+//!     }
+//!
+//!     fn value(&self) -> u32 {
+//!         // Read the peripheral's result register.
+//! #       self.software.value()
+//!     }
+//! }
+//!
+//! fn frame_check_sequence(frame: &[u8], checksum: &mut impl Checksum<Output = u32>) -> u32 {
+//!     checksum.update(frame);
+//!     checksum.value()
+//! }
+//!
+//! let mut crc = HardwareCrc32 { software: Crc32Ieee::new() };
+//! assert_eq!(frame_check_sequence(b"123456789", &mut crc), 0xcbf4_3926);
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+mod crc16;
+mod crc32;
+
+pub use crc16::{Crc16, Crc16Ccitt, Crc16CcittEightBit, Crc16EightBit};
+pub use crc32::{
+    Crc32, Crc32EightBit, Crc32FourBit, Crc32Ieee, Crc32IeeeEightBit, Crc32IeeeFourBit,
+};
+
+/// A trait for checksum algorithms which can be computed incrementally.
+///
+/// Implement this trait to use a different checksum algorithm or a CRC
+/// peripheral in place of the software implementations in this crate.
+pub trait Checksum {
+    /// The type of the computed checksum value.
+    type Output;
+
+    /// Update the checksum with `data`.
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the checksum of all data passed to [`Checksum::update()`].
+    fn value(&self) -> Self::Output;
+}
+
+// Test vector for the "check" values which CRC catalogs list for each
+// algorithm.
+#[cfg(test)]
+const CHECK_DATA: &[u8] = b"123456789";
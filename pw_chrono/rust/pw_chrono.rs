@@ -0,0 +1,378 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_chrono` provides the system clock, a monotonic source of time shared
+//! by logging, tracing, and timeouts.
+//!
+//! A [`SystemClock`] counts ticks at a fixed rate from an epoch, such as
+//! boot.  Times are represented as an [`Instant`], the [`Duration`] since the
+//! clock's epoch, so code measuring intervals or computing deadlines does not
+//! depend on the clock's tick rate:
+//!
+//! ```
+//! use pw_chrono::{Duration, Instant, SystemClock};
+//!
+//! struct MillisecondClock;
+//!
+//! impl SystemClock for MillisecondClock {
+//!     fn ticks_per_second(&self) -> u64 {
+//!         1000
+//!     }
+//!
+//!     fn now_ticks(&self) -> u64 {
+//!         1500
+//!     }
+//! }
+//!
+//! static CLOCK: MillisecondClock = MillisecondClock;
+//! pw_chrono::set_system_clock(&CLOCK);
+//!
+//! let deadline = pw_chrono::now() + Duration::from_millis(100);
+//! assert_eq!(deadline.duration_since_epoch(), Duration::from_millis(1600));
+//! assert!(!deadline.has_passed());
+//! ```
+//!
+//! Backends are provided for common platforms:
+//!
+//! - [`StdClock`] measures time with [`std::time::Instant`].  It is enabled
+//!   with the `std` feature.
+//! - [`SysTickClock`] counts the Cortex-M core clock with the SysTick timer.
+//!   It is enabled with the `cortex-m` feature.
+#![cfg_attr(not(feature = "std"), no_std)]
+#![deny(missing_docs)]
+
+use core::cell::Cell;
+use core::ops::{Add, AddAssign, Sub};
+
+use critical_section::Mutex;
+
+pub use core::time::Duration;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// A point in time, measured as the [`Duration`] since a [`SystemClock`]'s
+/// epoch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    /// The clock's epoch.
+    pub const EPOCH: Instant = Instant(Duration::ZERO);
+
+    /// Creates an instant `duration` after the epoch.
+    pub const fn from_duration_since_epoch(duration: Duration) -> Self {
+        Self(duration)
+    }
+
+    /// Creates an instant `ticks` after the epoch of a clock which counts
+    /// `ticks_per_second`.
+    pub const fn from_ticks(ticks: u64, ticks_per_second: u64) -> Self {
+        let secs = ticks / ticks_per_second;
+        let remainder = (ticks % ticks_per_second) as u128;
+        let nanos = remainder * NANOS_PER_SEC as u128 / ticks_per_second as u128;
+        Self(Duration::new(secs, nanos as u32))
+    }
+
+    /// Returns the time since the epoch.
+    pub const fn duration_since_epoch(&self) -> Duration {
+        self.0
+    }
+
+    /// Returns the time elapsed from `earlier` to this instant, or `None` if
+    /// `earlier` is later.
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    /// Returns the time elapsed from `earlier` to this instant, or zero if
+    /// `earlier` is later.
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    /// Returns the instant `duration` after this one, or `None` on overflow.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration).map(Instant)
+    }
+
+    /// Returns the instant `duration` before this one, or `None` if it would
+    /// be before the epoch.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration).map(Instant)
+    }
+
+    /// Returns the time elapsed since this instant, measured with the system
+    /// clock.
+    pub fn elapsed(&self) -> Duration {
+        now().saturating_duration_since(*self)
+    }
+
+    /// Returns `true` if the system clock has reached this instant, treating
+    /// it as a deadline.
+    pub fn has_passed(&self) -> bool {
+        now() >= *self
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    /// Saturates at the latest representable instant, so deadlines computed
+    /// from very long timeouts never pass.
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0.saturating_add(duration))
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    /// Saturates at zero, as [`Instant::saturating_duration_since()`].
+    fn sub(self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+}
+
+/// A monotonic clock which counts ticks at a fixed rate from an epoch.
+pub trait SystemClock: Sync {
+    /// Returns the number of ticks per second.
+    fn ticks_per_second(&self) -> u64;
+
+    /// Returns the number of ticks since the epoch.  The count must not
+    /// decrease.
+    fn now_ticks(&self) -> u64;
+
+    /// Returns the current time.
+    fn now(&self) -> Instant {
+        Instant::from_ticks(self.now_ticks(), self.ticks_per_second())
+    }
+}
+
+static SYSTEM_CLOCK: Mutex<Cell<Option<&'static dyn SystemClock>>> = Mutex::new(Cell::new(None));
+
+/// Sets the clock returned by [`system_clock()`] and used by [`now()`].
+pub fn set_system_clock(clock: &'static dyn SystemClock) {
+    critical_section::with(|cs| SYSTEM_CLOCK.borrow(cs).set(Some(clock)))
+}
+
+/// Returns the clock set by [`set_system_clock()`], if any.
+pub fn system_clock() -> Option<&'static dyn SystemClock> {
+    critical_section::with(|cs| SYSTEM_CLOCK.borrow(cs).get())
+}
+
+/// Returns the current time from the system clock, or [`Instant::EPOCH`] if
+/// no clock is set.
+pub fn now() -> Instant {
+    system_clock().map_or(Instant::EPOCH, |clock| clock.now())
+}
+
+/// A [`SystemClock`] backed by [`std::time::Instant`], which counts
+/// nanoseconds from the first time it is read.
+#[cfg(feature = "std")]
+pub struct StdClock;
+
+#[cfg(feature = "std")]
+impl StdClock {
+    fn epoch() -> std::time::Instant {
+        static EPOCH: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        *EPOCH.get_or_init(std::time::Instant::now)
+    }
+}
+
+#[cfg(feature = "std")]
+impl SystemClock for StdClock {
+    fn ticks_per_second(&self) -> u64 {
+        NANOS_PER_SEC
+    }
+
+    fn now_ticks(&self) -> u64 {
+        // Saturates after about 584 years.
+        u64::try_from(Self::epoch().elapsed().as_nanos()).unwrap_or(u64::MAX)
+    }
+
+    fn now(&self) -> Instant {
+        Instant(Self::epoch().elapsed())
+    }
+}
+
+/// A [`SystemClock`] which counts Cortex-M core clock cycles with the SysTick
+/// timer.
+///
+/// The 24 bit SysTick counter is extended to 64 bits by counting its wraps in
+/// the SysTick exception, which must call [`SysTickClock::on_interrupt()`]:
+///
+/// ```ignore
+/// use cortex_m_rt::exception;
+/// use pw_chrono::SysTickClock;
+///
+/// static CLOCK: SysTickClock = SysTickClock::new(64_000_000);
+///
+/// #[exception]
+/// fn SysTick() {
+///     CLOCK.on_interrupt();
+/// }
+///
+/// let peripherals = cortex_m::Peripherals::take().unwrap();
+/// CLOCK.start(peripherals.SYST);
+/// pw_chrono::set_system_clock(&CLOCK);
+/// ```
+#[cfg(feature = "cortex-m")]
+pub struct SysTickClock {
+    core_clock_hz: u64,
+    wraps: Mutex<Cell<u64>>,
+}
+
+#[cfg(feature = "cortex-m")]
+impl SysTickClock {
+    // The SysTick counter is 24 bits.
+    const RELOAD: u32 = 0x00ff_ffff;
+
+    /// Creates a clock for a core running at `core_clock_hz`.
+    pub const fn new(core_clock_hz: u64) -> Self {
+        Self {
+            core_clock_hz,
+            wraps: Mutex::new(Cell::new(0)),
+        }
+    }
+
+    /// Configures and starts SysTick to count core clock cycles over its full
+    /// range, and enables its exception.
+    pub fn start(&self, mut syst: cortex_m::peripheral::SYST) {
+        syst.set_clock_source(cortex_m::peripheral::syst::SystClkSource::Core);
+        syst.set_reload(Self::RELOAD);
+        syst.clear_current();
+        syst.enable_interrupt();
+        syst.enable_counter();
+    }
+
+    /// Counts a wrap of the SysTick counter.  Must be called from the SysTick
+    /// exception handler.
+    pub fn on_interrupt(&self) {
+        critical_section::with(|cs| {
+            let wraps = self.wraps.borrow(cs);
+            wraps.set(wraps.get() + 1);
+        })
+    }
+}
+
+#[cfg(feature = "cortex-m")]
+impl SystemClock for SysTickClock {
+    fn ticks_per_second(&self) -> u64 {
+        self.core_clock_hz
+    }
+
+    fn now_ticks(&self) -> u64 {
+        use cortex_m::peripheral::{SCB, SYST};
+
+        critical_section::with(|cs| {
+            let mut wraps = self.wraps.borrow(cs).get();
+            let mut current = SYST::get_current();
+            // The counter may have wrapped since the exception was last
+            // handled, including while reading it.
+            if SCB::is_pendst_pending() {
+                current = SYST::get_current();
+                wraps += 1;
+            }
+            let period = u64::from(Self::RELOAD) + 1;
+            wraps * period + u64::from(Self::RELOAD - current)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeClock;
+
+    static TICKS: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+    impl SystemClock for FakeClock {
+        fn ticks_per_second(&self) -> u64 {
+            32_768
+        }
+
+        fn now_ticks(&self) -> u64 {
+            critical_section::with(|cs| TICKS.borrow(cs).get())
+        }
+    }
+
+    static FAKE_CLOCK: FakeClock = FakeClock;
+
+    #[test]
+    fn ticks_convert_to_duration() {
+        assert_eq!(
+            Instant::from_ticks(1_500, 1_000).duration_since_epoch(),
+            Duration::from_millis(1_500)
+        );
+        assert_eq!(
+            Instant::from_ticks(16_384, 32_768).duration_since_epoch(),
+            Duration::from_millis(500)
+        );
+        // Ticks faster than nanoseconds round down.
+        assert_eq!(
+            Instant::from_ticks(u64::MAX, 10 * NANOS_PER_SEC).duration_since_epoch(),
+            Duration::new(1_844_674_407, 370_955_161)
+        );
+    }
+
+    #[test]
+    fn instant_arithmetic() {
+        let start = Instant::from_duration_since_epoch(Duration::from_secs(10));
+        let later = start + Duration::from_millis(250);
+        assert_eq!(later - start, Duration::from_millis(250));
+        assert_eq!(start - later, Duration::ZERO);
+        assert_eq!(start.checked_duration_since(later), None);
+        assert_eq!(
+            start.checked_sub(Duration::from_secs(1)),
+            Some(Instant::from_duration_since_epoch(Duration::from_secs(9)))
+        );
+        assert_eq!(start.checked_sub(Duration::from_secs(11)), None);
+        assert_eq!(start.checked_add(Duration::MAX), None);
+        assert_eq!(
+            (start + Duration::MAX).duration_since_epoch(),
+            Duration::MAX
+        );
+    }
+
+    #[test]
+    fn system_clock_measures_deadlines() {
+        set_system_clock(&FAKE_CLOCK);
+        critical_section::with(|cs| TICKS.borrow(cs).set(32_768));
+        let start = now();
+        assert_eq!(start.duration_since_epoch(), Duration::from_secs(1));
+
+        let deadline = start + Duration::from_millis(500);
+        assert!(!deadline.has_passed());
+        critical_section::with(|cs| TICKS.borrow(cs).set(32_768 + 16_384));
+        assert!(deadline.has_passed());
+        assert_eq!(start.elapsed(), Duration::from_millis(500));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn std_clock_is_monotonic() {
+        let first = StdClock.now();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        let second = StdClock.now();
+        assert!(second - first >= Duration::from_millis(1));
+        assert_eq!(StdClock.ticks_per_second(), NANOS_PER_SEC);
+    }
+}
@@ -0,0 +1,481 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use core::cell::Cell;
+use core::fmt;
+use core::ptr;
+
+/// The links embedded in each item of an [`IntrusiveList`].
+///
+/// An item may be in at most one list at a time through each of its links.
+pub struct Link<'a, T> {
+    prev: Cell<Option<&'a T>>,
+    next: Cell<Option<&'a T>>,
+    linked: Cell<bool>,
+}
+
+impl<T> Link<'_, T> {
+    /// Creates a link which is not in a list.
+    pub const fn new() -> Self {
+        Self {
+            prev: Cell::new(None),
+            next: Cell::new(None),
+            linked: Cell::new(false),
+        }
+    }
+
+    /// Returns whether the item is in a list.
+    pub fn is_linked(&self) -> bool {
+        self.linked.get()
+    }
+}
+
+impl<T> Default for Link<'_, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> fmt::Debug for Link<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Link")
+            .field("linked", &self.is_linked())
+            .finish()
+    }
+}
+
+/// A type whose values can be in an [`IntrusiveList`].
+///
+/// ```
+/// use core::cell::Cell;
+/// use pw_containers::{Link, Linked};
+///
+/// struct Timer<'a> {
+///     deadline: Cell<u64>,
+///     link: Link<'a, Timer<'a>>,
+/// }
+///
+/// impl<'a> Linked<'a> for Timer<'a> {
+///     fn link(&self) -> &Link<'a, Self> {
+///         &self.link
+///     }
+/// }
+/// ```
+pub trait Linked<'a>: Sized {
+    /// Returns the item's link.
+    fn link(&self) -> &Link<'a, Self>;
+}
+
+/// A doubly linked list of items which are not owned by the list.
+///
+/// Rather than allocating nodes, each item embeds a [`Link`], so an item may
+/// be queued and removed any number of times without allocating or moving.
+/// Items are borrowed by the list for its lifetime `'a`, so they can not be
+/// moved or dropped while they may be in the list.  Since items are shared,
+/// any of their fields which change while they are in the list use interior
+/// mutability.
+///
+/// ```
+/// use pw_containers::{IntrusiveList, Link, Linked};
+///
+/// struct Call<'a> {
+///     id: u32,
+///     link: Link<'a, Call<'a>>,
+/// }
+///
+/// impl<'a> Linked<'a> for Call<'a> {
+///     fn link(&self) -> &Link<'a, Self> {
+///         &self.link
+///     }
+/// }
+///
+/// let first = Call { id: 1, link: Link::new() };
+/// let second = Call { id: 2, link: Link::new() };
+///
+/// let mut calls = IntrusiveList::new();
+/// calls.push_back(&first);
+/// calls.push_back(&second);
+/// assert_eq!(calls.iter().map(|call| call.id).collect::<Vec<_>>(), [1, 2]);
+///
+/// calls.remove(&first);
+/// assert!(!first.link.is_linked());
+/// assert_eq!(calls.front().unwrap().id, 2);
+/// ```
+///
+/// Removing an item from a list it is not in is a logic error.  It may panic
+/// or leave either list with the wrong items, but it can not cause undefined
+/// behavior.
+pub struct IntrusiveList<'a, T: Linked<'a>> {
+    head: Option<&'a T>,
+    tail: Option<&'a T>,
+    len: usize,
+}
+
+impl<'a, T: Linked<'a>> IntrusiveList<'a, T> {
+    /// Creates an empty list.
+    pub const fn new() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of items in the list.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the list is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the first item in the list.
+    pub fn front(&self) -> Option<&'a T> {
+        self.head
+    }
+
+    /// Returns the last item in the list.
+    pub fn back(&self) -> Option<&'a T> {
+        self.tail
+    }
+
+    /// Returns an iterator over the items in the list, front to back.
+    pub fn iter(&self) -> Iter<'_, 'a, T> {
+        Iter {
+            front: self.head,
+            back: self.tail,
+            len: self.len,
+            _list: self,
+        }
+    }
+
+    // Links `item` between `prev` and `next`, which must be adjacent.
+    fn link_between(&mut self, item: &'a T, prev: Option<&'a T>, next: Option<&'a T>) {
+        let link = item.link();
+        assert!(!link.is_linked(), "Item is already in a list");
+        link.linked.set(true);
+        link.prev.set(prev);
+        link.next.set(next);
+        match prev {
+            Some(prev) => prev.link().next.set(Some(item)),
+            None => self.head = Some(item),
+        }
+        match next {
+            Some(next) => next.link().prev.set(Some(item)),
+            None => self.tail = Some(item),
+        }
+        self.len += 1;
+    }
+
+    /// Adds `item` to the front of the list.
+    ///
+    /// # Panics
+    /// Panics if `item` is already in a list.
+    pub fn push_front(&mut self, item: &'a T) {
+        self.link_between(item, None, self.head);
+    }
+
+    /// Adds `item` to the back of the list.
+    ///
+    /// # Panics
+    /// Panics if `item` is already in a list.
+    pub fn push_back(&mut self, item: &'a T) {
+        self.link_between(item, self.tail, None);
+    }
+
+    /// Inserts `item` after `position`, which must be in the list.
+    ///
+    /// # Panics
+    /// Panics if `item` is already in a list or `position` is not.
+    pub fn insert_after(&mut self, position: &'a T, item: &'a T) {
+        assert!(position.link().is_linked(), "Position is not in a list");
+        self.link_between(item, Some(position), position.link().next.get());
+    }
+
+    /// Inserts `item` before `position`, which must be in the list.
+    ///
+    /// # Panics
+    /// Panics if `item` is already in a list or `position` is not.
+    pub fn insert_before(&mut self, position: &'a T, item: &'a T) {
+        assert!(position.link().is_linked(), "Position is not in a list");
+        self.link_between(item, position.link().prev.get(), Some(position));
+    }
+
+    /// Inserts `item` before the first item for which `is_before` returns
+    /// `true`, or at the back of the list if there is none.
+    ///
+    /// Inserting every item this way keeps the list sorted, as for a queue of
+    /// timers ordered by deadline.
+    ///
+    /// # Panics
+    /// Panics if `item` is already in a list.
+    pub fn insert_sorted(&mut self, item: &'a T, mut is_before: impl FnMut(&T) -> bool) {
+        match self.iter().find(|other| is_before(other)) {
+            Some(position) => self.insert_before(position, item),
+            None => self.push_back(item),
+        }
+    }
+
+    /// Removes `item`, which must be in the list.
+    ///
+    /// # Panics
+    /// Panics if `item` is not in a list.
+    pub fn remove(&mut self, item: &'a T) {
+        let link = item.link();
+        assert!(link.is_linked(), "Item is not in a list");
+        let prev = link.prev.take();
+        let next = link.next.take();
+        link.linked.set(false);
+        match prev {
+            Some(prev) => prev.link().next.set(next),
+            None => {
+                assert!(
+                    matches!(self.head, Some(head) if ptr::eq(head, item)),
+                    "Item is not in this list"
+                );
+                self.head = next;
+            }
+        }
+        match next {
+            Some(next) => next.link().prev.set(prev),
+            None => {
+                assert!(
+                    matches!(self.tail, Some(tail) if ptr::eq(tail, item)),
+                    "Item is not in this list"
+                );
+                self.tail = prev;
+            }
+        }
+        self.len -= 1;
+    }
+
+    /// Removes and returns the first item in the list.
+    pub fn pop_front(&mut self) -> Option<&'a T> {
+        let item = self.head?;
+        self.remove(item);
+        Some(item)
+    }
+
+    /// Removes and returns the last item in the list.
+    pub fn pop_back(&mut self) -> Option<&'a T> {
+        let item = self.tail?;
+        self.remove(item);
+        Some(item)
+    }
+
+    /// Removes the items for which `keep` returns `false`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        let mut current = self.head;
+        while let Some(item) = current {
+            current = item.link().next.get();
+            if !keep(item) {
+                self.remove(item);
+            }
+        }
+    }
+
+    /// Removes all items from the list.
+    pub fn clear(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+}
+
+impl<'a, T: Linked<'a>> Default for IntrusiveList<'a, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T: Linked<'a> + fmt::Debug> fmt::Debug for IntrusiveList<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'l, 'a, T: Linked<'a>> IntoIterator for &'l IntrusiveList<'a, T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'l, 'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// An iterator over the items in an [`IntrusiveList`].
+///
+/// The list is borrowed while the iterator exists, so it can not be modified.
+pub struct Iter<'l, 'a, T: Linked<'a>> {
+    front: Option<&'a T>,
+    back: Option<&'a T>,
+    len: usize,
+    _list: &'l IntrusiveList<'a, T>,
+}
+
+impl<'a, T: Linked<'a>> Iterator for Iter<'_, 'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.front?;
+        self.front = item.link().next.get();
+        self.len -= 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<'a, T: Linked<'a>> DoubleEndedIterator for Iter<'_, 'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.len == 0 {
+            return None;
+        }
+        let item = self.back?;
+        self.back = item.link().prev.get();
+        self.len -= 1;
+        Some(item)
+    }
+}
+
+impl<'a, T: Linked<'a>> ExactSizeIterator for Iter<'_, 'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    struct Item<'a> {
+        value: u32,
+        link: Link<'a, Item<'a>>,
+    }
+
+    impl Item<'_> {
+        fn new(value: u32) -> Self {
+            Self {
+                value,
+                link: Link::new(),
+            }
+        }
+    }
+
+    impl<'a> Linked<'a> for Item<'a> {
+        fn link(&self) -> &Link<'a, Self> {
+            &self.link
+        }
+    }
+
+    fn values<'a>(list: &IntrusiveList<'a, Item<'a>>) -> Vec<u32> {
+        list.iter().map(|item| item.value).collect()
+    }
+
+    #[test]
+    fn push_and_pop_at_both_ends() {
+        let items = [Item::new(1), Item::new(2), Item::new(3)];
+        let mut list = IntrusiveList::new();
+        assert!(list.is_empty());
+        list.push_back(&items[1]);
+        list.push_front(&items[0]);
+        list.push_back(&items[2]);
+        assert_eq!(values(&list), [1, 2, 3]);
+        assert_eq!(list.len(), 3);
+        assert_eq!(
+            list.iter().rev().map(|item| item.value).collect::<Vec<_>>(),
+            [3, 2, 1]
+        );
+
+        assert_eq!(list.pop_front().unwrap().value, 1);
+        assert_eq!(list.pop_back().unwrap().value, 3);
+        assert!(!items[0].link.is_linked());
+        assert_eq!(list.front().unwrap().value, 2);
+        assert_eq!(list.back().unwrap().value, 2);
+        assert_eq!(list.pop_back().unwrap().value, 2);
+        assert!(list.pop_front().is_none());
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn remove_from_middle() {
+        let items = [Item::new(1), Item::new(2), Item::new(3)];
+        let mut list = IntrusiveList::new();
+        for item in &items {
+            list.push_back(item);
+        }
+        list.remove(&items[1]);
+        assert_eq!(values(&list), [1, 3]);
+
+        // Removed items can be added again.
+        list.insert_after(&items[0], &items[1]);
+        assert_eq!(values(&list), [1, 2, 3]);
+        list.remove(&items[2]);
+        list.insert_before(&items[0], &items[2]);
+        assert_eq!(values(&list), [3, 1, 2]);
+    }
+
+    #[test]
+    fn insert_sorted_keeps_order() {
+        let items = [Item::new(30), Item::new(10), Item::new(20), Item::new(10)];
+        let mut list = IntrusiveList::new();
+        for item in &items {
+            list.insert_sorted(item, |other| other.value > item.value);
+        }
+        assert_eq!(values(&list), [10, 10, 20, 30]);
+        // Equal items stay in insertion order.
+        assert!(ptr::eq(list.front().unwrap(), &items[1]));
+    }
+
+    #[test]
+    fn retain_and_clear_unlink_items() {
+        let items = [Item::new(1), Item::new(2), Item::new(3), Item::new(4)];
+        let mut list = IntrusiveList::new();
+        for item in &items {
+            list.push_back(item);
+        }
+        list.retain(|item| item.value % 2 == 0);
+        assert_eq!(values(&list), [2, 4]);
+        assert!(!items[0].link.is_linked());
+
+        list.clear();
+        assert!(list.is_empty());
+        assert!(items.iter().all(|item| !item.link.is_linked()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Item is already in a list")]
+    fn item_can_only_be_in_one_list() {
+        let item = Item::new(1);
+        let mut first = IntrusiveList::new();
+        let mut second = IntrusiveList::new();
+        first.push_back(&item);
+        second.push_back(&item);
+    }
+
+    #[test]
+    #[should_panic(expected = "Item is not in this list")]
+    fn removing_item_from_other_list_panics() {
+        let items = [Item::new(1), Item::new(2)];
+        let mut first = IntrusiveList::new();
+        let mut second = IntrusiveList::new();
+        first.push_back(&items[0]);
+        second.push_back(&items[1]);
+        second.remove(&items[0]);
+    }
+}
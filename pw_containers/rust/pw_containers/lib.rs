@@ -0,0 +1,26 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_containers` provides `no_std` containers which do not allocate.
+//!
+//! This is the Rust counterpart of the C++ `pw_containers` module:
+//! * An [`IntrusiveList`] is a doubly linked list of items which each embed
+//!   their own [`Link`], like `pw::IntrusiveList`.  It queues items, such as
+//!   timers, RPC calls, or log entries, without allocating or copying them.
+#![no_std]
+#![deny(missing_docs)]
+
+mod intrusive_list;
+
+pub use intrusive_list::{IntrusiveList, Iter, Link, Linked};
@@ -0,0 +1,55 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! ECDSA signature verification.
+use pw_status::Result;
+
+/// The size of an uncompressed P-256 public key, `04 || x || y`, in bytes.
+pub const P256_PUBLIC_KEY_SIZE: usize = 65;
+
+/// The size of a P-256 signature, `r || s`, in bytes.
+pub const P256_SIGNATURE_SIZE: usize = 64;
+
+/// Verification of ECDSA signatures over the NIST P-256 curve.
+pub trait P256Verify {
+    /// Verifies that `signature` is a signature of `digest` by
+    /// `public_key`.
+    ///
+    /// `public_key` is an uncompressed SEC 1 point and `signature` is the
+    /// concatenation of `r` and `s`, all big endian.  `digest` is usually a
+    /// SHA-256 digest.  Digests longer than 32 bytes are truncated.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`](pw_status::Error::InvalidArgument) -
+    ///   `public_key` is not a point on the curve, `signature` is not
+    ///   [`P256_SIGNATURE_SIZE`] bytes, or `digest` is shorter than 32 bytes.
+    /// - [`Error::Unauthenticated`](pw_status::Error::Unauthenticated) - The
+    ///   signature is not valid.
+    fn verify_p256_signature(
+        &self,
+        public_key: &[u8],
+        digest: &[u8],
+        signature: &[u8],
+    ) -> Result<()>;
+}
+
+impl<T: P256Verify + ?Sized> P256Verify for &T {
+    fn verify_p256_signature(
+        &self,
+        public_key: &[u8],
+        digest: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        (**self).verify_p256_signature(public_key, digest, signature)
+    }
+}
@@ -0,0 +1,47 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! `pw_crypto` provides the cryptographic primitives needed to verify
+//! software updates and sign device data: SHA-256 digests and ECDSA
+//! signature verification over the NIST P-256 curve.
+//!
+//! Primitives are traits, [`sha256::Sha256`] and [`ecdsa::P256Verify`], so
+//! that code which uses them can be given a hardware accelerator where one
+//! is available.  [`software::Software`] is a portable reference
+//! implementation of both, written in Rust without dependencies.
+//!
+//! ```
+//! use pw_crypto::ecdsa::P256Verify;
+//! use pw_crypto::sha256::Sha256;
+//! use pw_crypto::software::Software;
+//! # use pw_status::Error;
+//! # let public_key = [0u8; 65];
+//! # let signature = [0u8; 64];
+//!
+//! let digest = Software.hash(b"message")?;
+//! let verified = Software.verify_p256_signature(&public_key, &digest, &signature);
+//! # assert_eq!(verified, Err(Error::InvalidArgument));
+//! # Ok::<(), Error>(())
+//! ```
+//!
+//! As in the C++ API, digests and signatures are big endian, signatures are
+//! the concatenation of `r` and `s`, and public keys are uncompressed SEC 1
+//! points.
+#![no_std]
+#![deny(missing_docs)]
+
+pub mod ecdsa;
+pub mod sha256;
+pub mod software;
+
+mod p256;
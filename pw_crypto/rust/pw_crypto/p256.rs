@@ -0,0 +1,401 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! Arithmetic on the NIST P-256 curve, `y² = x³ - 3x + b` over the prime
+//! field of order `p`, for ECDSA verification.
+//!
+//! Integers are four 64 bit limbs, least significant first.  Field elements
+//! and scalars are multiplied in Montgomery form, and points are in Jacobian
+//! coordinates to avoid an inversion per addition.
+
+type U256 = [u64; 4];
+
+const ZERO: U256 = [0; 4];
+const ONE: U256 = [1, 0, 0, 0];
+
+const fn from_be_bytes(bytes: &[u8; 32]) -> U256 {
+    let mut limbs = ZERO;
+    let mut i = 0;
+    while i < 4 {
+        let mut limb = 0;
+        let mut j = 0;
+        while j < 8 {
+            limb = (limb << 8) | bytes[(3 - i) * 8 + j] as u64;
+            j += 1;
+        }
+        limbs[i] = limb;
+        i += 1;
+    }
+    limbs
+}
+
+const fn is_zero(a: &U256) -> bool {
+    a[0] == 0 && a[1] == 0 && a[2] == 0 && a[3] == 0
+}
+
+// Returns whether `a` < `b`.
+const fn less_than(a: &U256, b: &U256) -> bool {
+    let mut i = 4;
+    while i > 0 {
+        i -= 1;
+        if a[i] != b[i] {
+            return a[i] < b[i];
+        }
+    }
+    false
+}
+
+// Returns `a + b` and the carry.
+const fn add(a: &U256, b: &U256) -> (U256, bool) {
+    let mut sum = ZERO;
+    let mut carry = 0;
+    let mut i = 0;
+    while i < 4 {
+        let limb = a[i] as u128 + b[i] as u128 + carry;
+        sum[i] = limb as u64;
+        carry = limb >> 64;
+        i += 1;
+    }
+    (sum, carry != 0)
+}
+
+// Returns `a - b` and the borrow.
+const fn sub(a: &U256, b: &U256) -> (U256, bool) {
+    let mut difference = ZERO;
+    let mut borrow = 0;
+    let mut i = 0;
+    while i < 4 {
+        let limb = (a[i] as u128).wrapping_sub(b[i] as u128 + borrow);
+        difference[i] = limb as u64;
+        borrow = limb >> 127;
+        i += 1;
+    }
+    (difference, borrow != 0)
+}
+
+// A prime modulus of 256 bits, with the constants for Montgomery
+// multiplication.
+struct Modulus {
+    m: U256,
+    // -m⁻¹ mod 2⁶⁴
+    m_inv: u64,
+    // R² mod m, where R = 2²⁵⁶
+    r2: U256,
+}
+
+impl Modulus {
+    const fn new(m: U256) -> Self {
+        // Newton's iteration doubles the correct low bits of m⁻¹ each step.
+        let mut inv: u64 = 1;
+        let mut i = 0;
+        while i < 6 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(m[0].wrapping_mul(inv)));
+            i += 1;
+        }
+        let mut modulus = Self {
+            m,
+            m_inv: inv.wrapping_neg(),
+            r2: ONE,
+        };
+        let mut i = 0;
+        while i < 512 {
+            modulus.r2 = modulus.add(&modulus.r2, &modulus.r2);
+            i += 1;
+        }
+        modulus
+    }
+
+    // Reduces `a` < 2²⁵⁶, which is less than 2m for the P-256 moduli.
+    const fn reduce(&self, a: &U256) -> U256 {
+        if less_than(a, &self.m) {
+            *a
+        } else {
+            sub(a, &self.m).0
+        }
+    }
+
+    const fn add(&self, a: &U256, b: &U256) -> U256 {
+        let (sum, carry) = add(a, b);
+        if carry || !less_than(&sum, &self.m) {
+            sub(&sum, &self.m).0
+        } else {
+            sum
+        }
+    }
+
+    fn sub(&self, a: &U256, b: &U256) -> U256 {
+        let (difference, borrow) = sub(a, b);
+        if borrow {
+            add(&difference, &self.m).0
+        } else {
+            difference
+        }
+    }
+
+    // Returns a·b·R⁻¹ mod m.
+    fn mul(&self, a: &U256, b: &U256) -> U256 {
+        let mut t = [0u64; 6];
+        for &b_limb in b {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let limb = t[j] as u128 + a[j] as u128 * b_limb as u128 + carry;
+                t[j] = limb as u64;
+                carry = limb >> 64;
+            }
+            let limb = t[4] as u128 + carry;
+            t[4] = limb as u64;
+            t[5] = (limb >> 64) as u64;
+
+            let q = t[0].wrapping_mul(self.m_inv);
+            let mut carry = (t[0] as u128 + q as u128 * self.m[0] as u128) >> 64;
+            for j in 1..4 {
+                let limb = t[j] as u128 + q as u128 * self.m[j] as u128 + carry;
+                t[j - 1] = limb as u64;
+                carry = limb >> 64;
+            }
+            let limb = t[4] as u128 + carry;
+            t[3] = limb as u64;
+            t[4] = t[5] + (limb >> 64) as u64;
+        }
+        let product = [t[0], t[1], t[2], t[3]];
+        if t[4] != 0 || !less_than(&product, &self.m) {
+            sub(&product, &self.m).0
+        } else {
+            product
+        }
+    }
+
+    fn to_montgomery(&self, a: &U256) -> U256 {
+        self.mul(a, &self.r2)
+    }
+
+    fn out_of_montgomery(&self, a: &U256) -> U256 {
+        self.mul(a, &ONE)
+    }
+
+    // Returns a⁻¹ in Montgomery form, by Fermat's little theorem.
+    fn invert(&self, a: &U256) -> U256 {
+        let exponent = sub(&self.m, &[2, 0, 0, 0]).0;
+        let mut result = self.to_montgomery(&ONE);
+        for i in (0..256).rev() {
+            result = self.mul(&result, &result);
+            if (exponent[i / 64] >> (i % 64)) & 1 == 1 {
+                result = self.mul(&result, a);
+            }
+        }
+        result
+    }
+}
+
+const P: Modulus = Modulus::new([
+    0xffffffffffffffff,
+    0x00000000ffffffff,
+    0x0000000000000000,
+    0xffffffff00000001,
+]);
+
+const N: Modulus = Modulus::new([
+    0xf3b9cac2fc632551,
+    0xbce6faada7179e84,
+    0xffffffffffffffff,
+    0xffffffff00000000,
+]);
+
+const B: U256 = [
+    0x3bce3c3e27d2604b,
+    0x651d06b0cc53b0f6,
+    0xb3ebbd55769886bc,
+    0x5ac635d8aa3a93e7,
+];
+
+const GX: U256 = [
+    0xf4a13945d898c296,
+    0x77037d812deb33a0,
+    0xf8bce6e563a440f2,
+    0x6b17d1f2e12c4247,
+];
+
+const GY: U256 = [
+    0xcbb6406837bf51f5,
+    0x2bce33576b315ece,
+    0x8ee7eb4a7c0f9e16,
+    0x4fe342e2fe1a7f9b,
+];
+
+/// A point on the curve other than the point at infinity, with coordinates
+/// in Montgomery form.
+#[derive(Clone, Copy)]
+pub struct AffinePoint {
+    x: U256,
+    y: U256,
+}
+
+impl AffinePoint {
+    /// Returns the point with big endian coordinates `x` and `y`, or `None`
+    /// if it is not on the curve.
+    pub fn from_coordinates(x: &[u8; 32], y: &[u8; 32]) -> Option<Self> {
+        let (x, y) = (from_be_bytes(x), from_be_bytes(y));
+        if !less_than(&x, &P.m) || !less_than(&y, &P.m) {
+            return None;
+        }
+        let (x, y) = (P.to_montgomery(&x), P.to_montgomery(&y));
+        let x3 = P.mul(&P.mul(&x, &x), &x);
+        let three_x = P.add(&P.add(&x, &x), &x);
+        let rhs = P.add(&P.sub(&x3, &three_x), &P.to_montgomery(&B));
+        if P.mul(&y, &y) != rhs {
+            return None;
+        }
+        Some(Self { x, y })
+    }
+
+    fn generator() -> Self {
+        Self {
+            x: P.to_montgomery(&GX),
+            y: P.to_montgomery(&GY),
+        }
+    }
+}
+
+// A point in Jacobian coordinates, (X / Z², Y / Z³), which is the point at
+// infinity if Z is zero.
+#[derive(Clone, Copy)]
+struct JacobianPoint {
+    x: U256,
+    y: U256,
+    z: U256,
+}
+
+impl JacobianPoint {
+    const INFINITY: Self = Self {
+        x: ZERO,
+        y: ZERO,
+        z: ZERO,
+    };
+
+    fn from_affine(point: &AffinePoint) -> Self {
+        Self {
+            x: point.x,
+            y: point.y,
+            z: P.to_montgomery(&ONE),
+        }
+    }
+
+    fn is_infinity(&self) -> bool {
+        is_zero(&self.z)
+    }
+
+    // "dbl-2001-b" from the Explicit-Formulas Database, for a = -3.
+    fn double(&self) -> Self {
+        let delta = P.mul(&self.z, &self.z);
+        let gamma = P.mul(&self.y, &self.y);
+        let beta = P.mul(&self.x, &gamma);
+        let alpha = P.mul(&P.sub(&self.x, &delta), &P.add(&self.x, &delta));
+        let alpha = P.add(&P.add(&alpha, &alpha), &alpha);
+        let four_beta = P.add(&P.add(&beta, &beta), &P.add(&beta, &beta));
+        let x = P.sub(&P.mul(&alpha, &alpha), &P.add(&four_beta, &four_beta));
+        let y_plus_z = P.add(&self.y, &self.z);
+        let z = P.sub(&P.sub(&P.mul(&y_plus_z, &y_plus_z), &gamma), &delta);
+        let gamma2 = P.mul(&gamma, &gamma);
+        let eight_gamma2 = P.add(&gamma2, &gamma2);
+        let eight_gamma2 = P.add(&eight_gamma2, &eight_gamma2);
+        let eight_gamma2 = P.add(&eight_gamma2, &eight_gamma2);
+        let y = P.sub(&P.mul(&alpha, &P.sub(&four_beta, &x)), &eight_gamma2);
+        Self { x, y, z }
+    }
+
+    // "add-2007-bl" from the Explicit-Formulas Database.
+    fn add(&self, other: &Self) -> Self {
+        if self.is_infinity() {
+            return *other;
+        }
+        if other.is_infinity() {
+            return *self;
+        }
+        let z1z1 = P.mul(&self.z, &self.z);
+        let z2z2 = P.mul(&other.z, &other.z);
+        let u1 = P.mul(&self.x, &z2z2);
+        let u2 = P.mul(&other.x, &z1z1);
+        let s1 = P.mul(&P.mul(&self.y, &other.z), &z2z2);
+        let s2 = P.mul(&P.mul(&other.y, &self.z), &z1z1);
+        let h = P.sub(&u2, &u1);
+        let r = P.sub(&s2, &s1);
+        if is_zero(&h) {
+            return if is_zero(&r) {
+                self.double()
+            } else {
+                Self::INFINITY
+            };
+        }
+        let r = P.add(&r, &r);
+        let two_h = P.add(&h, &h);
+        let i = P.mul(&two_h, &two_h);
+        let j = P.mul(&h, &i);
+        let v = P.mul(&u1, &i);
+        let x = P.sub(&P.sub(&P.mul(&r, &r), &j), &P.add(&v, &v));
+        let s1j = P.mul(&s1, &j);
+        let y = P.sub(&P.mul(&r, &P.sub(&v, &x)), &P.add(&s1j, &s1j));
+        let z1_plus_z2 = P.add(&self.z, &other.z);
+        let z = P.mul(
+            &P.sub(&P.sub(&P.mul(&z1_plus_z2, &z1_plus_z2), &z1z1), &z2z2),
+            &h,
+        );
+        Self { x, y, z }
+    }
+
+    // Returns the affine x coordinate, not in Montgomery form, or `None` for
+    // the point at infinity.
+    fn affine_x(&self) -> Option<U256> {
+        if self.is_infinity() {
+            return None;
+        }
+        let z_inv = P.invert(&self.z);
+        Some(P.out_of_montgomery(&P.mul(&self.x, &P.mul(&z_inv, &z_inv))))
+    }
+}
+
+// Returns a·G + b·Q, with Shamir's trick.
+fn double_scalar_mul(a: &U256, b: &U256, q: &AffinePoint) -> JacobianPoint {
+    let g = JacobianPoint::from_affine(&AffinePoint::generator());
+    let q = JacobianPoint::from_affine(q);
+    let g_plus_q = g.add(&q);
+    let mut result = JacobianPoint::INFINITY;
+    for i in (0..256).rev() {
+        result = result.double();
+        let bit = |scalar: &U256| (scalar[i / 64] >> (i % 64)) & 1 == 1;
+        match (bit(a), bit(b)) {
+            (true, true) => result = result.add(&g_plus_q),
+            (true, false) => result = result.add(&g),
+            (false, true) => result = result.add(&q),
+            (false, false) => (),
+        }
+    }
+    result
+}
+
+/// Returns whether `(r, s)` is a valid signature of `digest` by
+/// `public_key`, all big endian.
+pub fn verify(public_key: &AffinePoint, digest: &[u8; 32], r: &[u8; 32], s: &[u8; 32]) -> bool {
+    let (r, s) = (from_be_bytes(r), from_be_bytes(s));
+    if is_zero(&r) || is_zero(&s) || !less_than(&r, &N.m) || !less_than(&s, &N.m) {
+        return false;
+    }
+    let e = N.reduce(&from_be_bytes(digest));
+
+    let w = N.invert(&N.to_montgomery(&s));
+    let u1 = N.out_of_montgomery(&N.mul(&N.to_montgomery(&e), &w));
+    let u2 = N.out_of_montgomery(&N.mul(&N.to_montgomery(&r), &w));
+    match double_scalar_mul(&u1, &u2, public_key).affine_x() {
+        Some(x) => N.reduce(&x) == r,
+        None => false,
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! SHA-256 digests.
+use pw_status::Result;
+use pw_stream::Read;
+
+/// The size of a SHA-256 digest in bytes.
+pub const DIGEST_SIZE_BYTES: usize = 32;
+
+/// A SHA-256 digest.
+pub type Digest = [u8; DIGEST_SIZE_BYTES];
+
+/// A SHA-256 hash in progress.
+pub trait Hasher {
+    /// Feeds `data` to the hash.  The order of updates matters.
+    fn update(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Finishes the hash and returns the digest.
+    fn finalize(self) -> Result<Digest>;
+}
+
+/// A SHA-256 implementation, such as a hardware accelerator or
+/// [`Software`](crate::software::Software).
+pub trait Sha256 {
+    /// The hash in progress, which may borrow the implementation to use its
+    /// hardware.
+    type Hasher<'a>: Hasher
+    where
+        Self: 'a;
+
+    /// Starts a hash.
+    ///
+    /// # Errors
+    /// - [`Error::Unavailable`](pw_status::Error::Unavailable) - The
+    ///   implementation can not start another hash at this time.
+    fn hasher(&self) -> Result<Self::Hasher<'_>>;
+
+    /// Returns the digest of `message`.
+    fn hash(&self, message: &[u8]) -> Result<Digest> {
+        let mut hasher = self.hasher()?;
+        hasher.update(message)?;
+        hasher.finalize()
+    }
+
+    /// Returns the digest of the rest of the data in `reader`.
+    ///
+    /// Errors reading from `reader` are returned.
+    fn hash_reader(&self, reader: &mut impl Read) -> Result<Digest> {
+        let mut hasher = self.hasher()?;
+        let mut buffer = [0u8; 64];
+        loop {
+            let len = reader.read(&mut buffer)?;
+            if len == 0 {
+                break;
+            }
+            hasher.update(&buffer[..len])?;
+        }
+        hasher.finalize()
+    }
+}
+
+impl<T: Sha256 + ?Sized> Sha256 for &T {
+    type Hasher<'a>
+        = T::Hasher<'a>
+    where
+        Self: 'a;
+
+    fn hasher(&self) -> Result<Self::Hasher<'_>> {
+        (**self).hasher()
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! A portable software implementation of the `pw_crypto` primitives.
+//!
+//! The implementation favors size and simplicity over speed.  Signature
+//! verification only handles public data, so it is not constant time.
+use pw_status::{Error, Result};
+
+use crate::ecdsa::{P256Verify, P256_PUBLIC_KEY_SIZE, P256_SIGNATURE_SIZE};
+use crate::p256;
+use crate::sha256::{self, Digest, DIGEST_SIZE_BYTES};
+
+/// The software implementation of SHA-256 and P-256 signature verification.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Software;
+
+impl sha256::Sha256 for Software {
+    type Hasher<'a> = Sha256Hasher;
+
+    fn hasher(&self) -> Result<Sha256Hasher> {
+        Ok(Sha256Hasher::new())
+    }
+}
+
+impl P256Verify for Software {
+    fn verify_p256_signature(
+        &self,
+        public_key: &[u8],
+        digest: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        if signature.len() != P256_SIGNATURE_SIZE
+            || public_key.len() != P256_PUBLIC_KEY_SIZE
+            || public_key[0] != 0x04
+            || digest.len() < DIGEST_SIZE_BYTES
+        {
+            return Err(Error::InvalidArgument);
+        }
+        let public_key = p256::AffinePoint::from_coordinates(
+            array(&public_key[1..33]),
+            array(&public_key[33..]),
+        )
+        .ok_or(Error::InvalidArgument)?;
+        if p256::verify(
+            &public_key,
+            array(&digest[..DIGEST_SIZE_BYTES]),
+            array(&signature[..32]),
+            array(&signature[32..]),
+        ) {
+            Ok(())
+        } else {
+            Err(Error::Unauthenticated)
+        }
+    }
+}
+
+fn array(bytes: &[u8]) -> &[u8; 32] {
+    bytes.try_into().unwrap()
+}
+
+const BLOCK_SIZE: usize = 64;
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// A SHA-256 hash in progress in software.
+#[derive(Clone)]
+pub struct Sha256Hasher {
+    state: [u32; 8],
+    block: [u8; BLOCK_SIZE],
+    block_len: usize,
+    // The length of the message in bytes.
+    len: u64,
+}
+
+impl Sha256Hasher {
+    /// Starts a hash.
+    pub const fn new() -> Self {
+        Self {
+            state: INITIAL_STATE,
+            block: [0; BLOCK_SIZE],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, word) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(ROUND_CONSTANTS[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    fn push(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let len = data.len().min(BLOCK_SIZE - self.block_len);
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&data[..len]);
+            self.block_len += len;
+            data = &data[len..];
+            if self.block_len == BLOCK_SIZE {
+                self.compress();
+                self.block_len = 0;
+            }
+        }
+    }
+}
+
+impl Default for Sha256Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl sha256::Hasher for Sha256Hasher {
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        self.len = self.len.wrapping_add(data.len() as u64);
+        self.push(data);
+        Ok(())
+    }
+
+    fn finalize(mut self) -> Result<Digest> {
+        let bit_len = self.len.wrapping_mul(8);
+        self.push(&[0x80]);
+        while self.block_len != BLOCK_SIZE - 8 {
+            self.push(&[0]);
+        }
+        self.push(&bit_len.to_be_bytes());
+
+        let mut digest = [0u8; DIGEST_SIZE_BYTES];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        Ok(digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use pw_stream::Cursor;
+
+    use super::*;
+    use crate::sha256::{Hasher, Sha256};
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn sha256_matches_test_vectors() {
+        for (message, digest) in [
+            (
+                &b""[..],
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+        ] {
+            assert_eq!(Software.hash(message).unwrap()[..], hex(digest));
+        }
+    }
+
+    #[test]
+    fn sha256_of_chunks_matches_oneshot() {
+        let message: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let mut hasher = Software.hasher().unwrap();
+        for chunk in message.chunks(37) {
+            hasher.update(chunk).unwrap();
+        }
+        assert_eq!(hasher.finalize(), Software.hash(&message));
+
+        let mut cursor = Cursor::new(&message[..]);
+        assert_eq!(Software.hash_reader(&mut cursor), Software.hash(&message));
+    }
+
+    // The P-256 test vectors for SHA-256 from RFC 6979, appendix A.2.5.
+    const PUBLIC_KEY: &str = "04\
+        60fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6\
+        7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299";
+    const SAMPLE_SIGNATURE: &str = "\
+        efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716\
+        f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8";
+    const TEST_SIGNATURE: &str = "\
+        f1abb023518351cd71d881567b1ea663ed3efcf6c5132b354f28d3b0b7d38367\
+        019f4113742a2b14bd25926b49c649155f267e60d3814b4c0cc84250e46f0083";
+
+    #[test]
+    fn verifies_valid_signatures() {
+        let public_key = hex(PUBLIC_KEY);
+        for (message, signature) in [
+            (&b"sample"[..], SAMPLE_SIGNATURE),
+            (b"test", TEST_SIGNATURE),
+        ] {
+            let digest = Software.hash(message).unwrap();
+            assert_eq!(
+                Software.verify_p256_signature(&public_key, &digest, &hex(signature)),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_signatures() {
+        let public_key = hex(PUBLIC_KEY);
+        let digest = Software.hash(b"sample").unwrap();
+        let signature = hex(SAMPLE_SIGNATURE);
+
+        let other_digest = Software.hash(b"test").unwrap();
+        assert_eq!(
+            Software.verify_p256_signature(&public_key, &other_digest, &signature),
+            Err(Error::Unauthenticated)
+        );
+
+        let mut tampered = signature.clone();
+        tampered[40] ^= 1;
+        assert_eq!(
+            Software.verify_p256_signature(&public_key, &digest, &tampered),
+            Err(Error::Unauthenticated)
+        );
+
+        let mut zero_r = signature.clone();
+        zero_r[..32].fill(0);
+        assert_eq!(
+            Software.verify_p256_signature(&public_key, &digest, &zero_r),
+            Err(Error::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_arguments() {
+        let public_key = hex(PUBLIC_KEY);
+        let digest = Software.hash(b"sample").unwrap();
+        let signature = hex(SAMPLE_SIGNATURE);
+
+        let mut off_curve = public_key.clone();
+        off_curve[64] ^= 1;
+        let mut compressed = public_key.clone();
+        compressed[0] = 0x02;
+        for public_key in [&off_curve[..], &compressed, &public_key[..64]] {
+            assert_eq!(
+                Software.verify_p256_signature(public_key, &digest, &signature),
+                Err(Error::InvalidArgument)
+            );
+        }
+        assert_eq!(
+            Software.verify_p256_signature(&public_key, &digest[..31], &signature),
+            Err(Error::InvalidArgument)
+        );
+        assert_eq!(
+            Software.verify_p256_signature(&public_key, &digest, &signature[..63]),
+            Err(Error::InvalidArgument)
+        );
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_digital_io` provides traits for using General Purpose Input and Output
+//! (GPIO) lines for simple digital I/O.
+//!
+//! A line's state is logical: [`State::Active`] may be a high or low voltage
+//! depending on the line's [`Polarity`], which the backend applies.  Drivers
+//! take the traits for the functionality they need:
+//! - [`DigitalIn`] reads the state of a line.
+//! - [`DigitalOut`] sets the state of a line.
+//! - [`DigitalInterrupt`] calls a handler when the state of a line changes.
+//!
+//! All lines implement [`DigitalIo`], which enables and disables them.  Lines
+//! must be enabled before they are used, and operations on disabled lines
+//! fail with [`pw_status::Error::FailedPrecondition`].
+//!
+//! ```
+//! use pw_digital_io::{DigitalIn, DigitalOut};
+//! use pw_status::Result;
+//!
+//! fn update_led_from_switch(
+//!     switch: &mut impl DigitalIn,
+//!     led: &mut impl DigitalOut,
+//! ) -> Result<()> {
+//!     led.set_state(switch.get_state()?)
+//! }
+//! ```
+//!
+//! Host tests of driver logic can use the lines of the
+//! `pw_digital_io_simulated` crate.
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_status::Result;
+
+/// The logical state of a digital line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// The line is inactive.
+    Inactive,
+    /// The line is active.
+    Active,
+}
+
+impl State {
+    /// Returns whether the state is [`State::Active`].
+    pub const fn is_active(self) -> bool {
+        matches!(self, State::Active)
+    }
+}
+
+impl From<bool> for State {
+    fn from(active: bool) -> Self {
+        if active {
+            State::Active
+        } else {
+            State::Inactive
+        }
+    }
+}
+
+/// The mapping of a logical state to the physical level of a line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Polarity {
+    /// Active is high and inactive is low.
+    #[default]
+    ActiveHigh,
+    /// Active is low and inactive is high.
+    ActiveLow,
+}
+
+impl Polarity {
+    /// Returns the state of a line at the physical level `high`.
+    pub const fn state(self, high: bool) -> State {
+        match (self, high) {
+            (Polarity::ActiveHigh, true) | (Polarity::ActiveLow, false) => State::Active,
+            _ => State::Inactive,
+        }
+    }
+
+    /// Returns whether a line in `state` is at a high physical level.
+    pub const fn is_high(self, state: State) -> bool {
+        state.is_active() == matches!(self, Polarity::ActiveHigh)
+    }
+}
+
+/// The state changes which call an interrupt handler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterruptTrigger {
+    /// A change from inactive to active.
+    ActivatingEdge,
+    /// A change from active to inactive.
+    DeactivatingEdge,
+    /// Any change of state.
+    BothEdges,
+}
+
+impl InterruptTrigger {
+    /// Returns whether a change from `from` to `to` triggers the handler.
+    pub const fn is_triggered_by(self, from: State, to: State) -> bool {
+        matches!(
+            (self, from, to),
+            (
+                InterruptTrigger::ActivatingEdge | InterruptTrigger::BothEdges,
+                State::Inactive,
+                State::Active
+            ) | (
+                InterruptTrigger::DeactivatingEdge | InterruptTrigger::BothEdges,
+                State::Active,
+                State::Inactive
+            )
+        )
+    }
+}
+
+/// A function called with the latest known state of a line when it changes.
+///
+/// Handlers are called in a backend-specific context, such as an interrupt
+/// handler or a shared notification thread, so they must not block or do
+/// expensive work.  They must not get the state of any line.
+pub type InterruptHandler = &'static (dyn Fn(State) + Sync);
+
+/// A digital line, which is disabled until [`DigitalIo::enable()`] is
+/// called.
+pub trait DigitalIo {
+    /// Enables the line, initializing it to a backend-specific default state
+    /// such as enabling a pull-up resistor.  Callers must wait for the level
+    /// to settle.
+    fn enable(&mut self) -> Result<()>;
+
+    /// Disables the line, disconnecting any pull-up or pull-down resistors
+    /// and drivers to save power.  Interrupt handlers are disabled.
+    fn disable(&mut self) -> Result<()>;
+}
+
+/// A line whose state can be read.
+pub trait DigitalIn: DigitalIo {
+    /// Returns the state of the line.
+    ///
+    /// # Errors
+    /// - [`pw_status::Error::FailedPrecondition`] - The line is not enabled.
+    fn get_state(&mut self) -> Result<State>;
+
+    /// Returns whether the line is active.
+    ///
+    /// # Errors
+    /// - [`pw_status::Error::FailedPrecondition`] - The line is not enabled.
+    fn is_state_active(&mut self) -> Result<bool> {
+        Ok(self.get_state()?.is_active())
+    }
+}
+
+/// A line whose state can be set.
+pub trait DigitalOut: DigitalIo {
+    /// Sets the state of the line.
+    ///
+    /// # Errors
+    /// - [`pw_status::Error::FailedPrecondition`] - The line is not enabled.
+    fn set_state(&mut self, state: State) -> Result<()>;
+
+    /// Sets the line active.
+    ///
+    /// # Errors
+    /// - [`pw_status::Error::FailedPrecondition`] - The line is not enabled.
+    fn set_state_active(&mut self) -> Result<()> {
+        self.set_state(State::Active)
+    }
+
+    /// Sets the line inactive.
+    ///
+    /// # Errors
+    /// - [`pw_status::Error::FailedPrecondition`] - The line is not enabled.
+    fn set_state_inactive(&mut self) -> Result<()> {
+        self.set_state(State::Inactive)
+    }
+}
+
+/// A line which calls a handler when its state changes.
+///
+/// A handler is set with [`DigitalInterrupt::set_interrupt_handler()`], and
+/// is only called while it is enabled with
+/// [`DigitalInterrupt::enable_interrupt_handler()`].
+pub trait DigitalInterrupt: DigitalIo {
+    /// Sets the handler which is called when the state changes as described
+    /// by `trigger`.  The handler is not enabled.
+    ///
+    /// # Errors
+    /// - [`pw_status::Error::AlreadyExists`] - A handler is already set.
+    fn set_interrupt_handler(
+        &mut self,
+        trigger: InterruptTrigger,
+        handler: InterruptHandler,
+    ) -> Result<()>;
+
+    /// Disables and clears the interrupt handler.
+    fn clear_interrupt_handler(&mut self) -> Result<()>;
+
+    /// Enables calling the interrupt handler.
+    ///
+    /// # Errors
+    /// - [`pw_status::Error::FailedPrecondition`] - The line is not enabled,
+    ///   or no handler is set.
+    fn enable_interrupt_handler(&mut self) -> Result<()>;
+
+    /// Disables calling the interrupt handler.  Changes which were already
+    /// detected may still call the handler.
+    fn disable_interrupt_handler(&mut self) -> Result<()>;
+}
+
+impl<L: DigitalIo + ?Sized> DigitalIo for &mut L {
+    fn enable(&mut self) -> Result<()> {
+        (**self).enable()
+    }
+
+    fn disable(&mut self) -> Result<()> {
+        (**self).disable()
+    }
+}
+
+impl<L: DigitalIn + ?Sized> DigitalIn for &mut L {
+    fn get_state(&mut self) -> Result<State> {
+        (**self).get_state()
+    }
+}
+
+impl<L: DigitalOut + ?Sized> DigitalOut for &mut L {
+    fn set_state(&mut self, state: State) -> Result<()> {
+        (**self).set_state(state)
+    }
+}
+
+impl<L: DigitalInterrupt + ?Sized> DigitalInterrupt for &mut L {
+    fn set_interrupt_handler(
+        &mut self,
+        trigger: InterruptTrigger,
+        handler: InterruptHandler,
+    ) -> Result<()> {
+        (**self).set_interrupt_handler(trigger, handler)
+    }
+
+    fn clear_interrupt_handler(&mut self) -> Result<()> {
+        (**self).clear_interrupt_handler()
+    }
+
+    fn enable_interrupt_handler(&mut self) -> Result<()> {
+        (**self).enable_interrupt_handler()
+    }
+
+    fn disable_interrupt_handler(&mut self) -> Result<()> {
+        (**self).disable_interrupt_handler()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polarity_maps_levels_to_states() {
+        assert_eq!(Polarity::ActiveHigh.state(true), State::Active);
+        assert_eq!(Polarity::ActiveHigh.state(false), State::Inactive);
+        assert_eq!(Polarity::ActiveLow.state(true), State::Inactive);
+        assert_eq!(Polarity::ActiveLow.state(false), State::Active);
+        assert!(Polarity::ActiveHigh.is_high(State::Active));
+        assert!(!Polarity::ActiveLow.is_high(State::Active));
+        assert!(Polarity::ActiveLow.is_high(State::Inactive));
+    }
+
+    #[test]
+    fn triggers_match_edges() {
+        use InterruptTrigger::*;
+        use State::*;
+        assert!(ActivatingEdge.is_triggered_by(Inactive, Active));
+        assert!(!ActivatingEdge.is_triggered_by(Active, Inactive));
+        assert!(DeactivatingEdge.is_triggered_by(Active, Inactive));
+        assert!(!DeactivatingEdge.is_triggered_by(Inactive, Active));
+        assert!(BothEdges.is_triggered_by(Active, Inactive));
+        assert!(BothEdges.is_triggered_by(Inactive, Active));
+        assert!(!BothEdges.is_triggered_by(Active, Active));
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Simulated `pw_digital_io` lines for host tests of driver logic.
+//!
+//! A [`SimulatedLine`] implements [`DigitalIn`], [`DigitalOut`], and
+//! [`DigitalInterrupt`].  Clones of a line share its state, so a test can give
+//! one clone to the driver under test and use another to drive the line's
+//! physical level, which calls the interrupt handler as hardware would, and
+//! to check the level the driver set:
+//!
+//! ```
+//! use pw_digital_io::{DigitalIo, DigitalOut, Polarity};
+//! use pw_digital_io_simulated::SimulatedLine;
+//!
+//! let reset = SimulatedLine::new(Polarity::ActiveLow);
+//! let mut driver_reset = reset.clone();
+//! driver_reset.enable()?;
+//! driver_reset.set_state_active()?;
+//! assert!(!reset.level());
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! *Note*: This module requires `std`.
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use pw_digital_io::{
+    DigitalIn, DigitalInterrupt, DigitalIo, DigitalOut, InterruptHandler, InterruptTrigger,
+    Polarity, State,
+};
+use pw_status::{Error, Result};
+
+struct LineState {
+    polarity: Polarity,
+    enabled: bool,
+    high: bool,
+    handler: Option<(InterruptTrigger, InterruptHandler)>,
+    interrupt_enabled: bool,
+}
+
+/// A simulated digital line, which starts disabled and at a low level.
+#[derive(Clone)]
+pub struct SimulatedLine {
+    state: Arc<Mutex<LineState>>,
+}
+
+impl SimulatedLine {
+    /// Creates a line with `polarity`.
+    pub fn new(polarity: Polarity) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(LineState {
+                polarity,
+                enabled: false,
+                high: false,
+                handler: None,
+                interrupt_enabled: false,
+            })),
+        }
+    }
+
+    /// Returns the line's physical level.
+    pub fn level(&self) -> bool {
+        self.lock().high
+    }
+
+    /// Sets the line's physical level, as an external device driving it
+    /// would.  If the line is enabled and the change triggers the enabled
+    /// interrupt handler, the handler is called before returning.
+    pub fn set_level(&self, high: bool) {
+        let mut state = self.lock();
+        let from = state.polarity.state(state.high);
+        let to = state.polarity.state(high);
+        state.high = high;
+        let handler = match state.handler {
+            Some((trigger, handler))
+                if state.enabled
+                    && state.interrupt_enabled
+                    && trigger.is_triggered_by(from, to) =>
+            {
+                handler
+            }
+            _ => return,
+        };
+        // The handler is called without the lock held so that it may set the
+        // level of this line.
+        drop(state);
+        handler(to);
+    }
+
+    /// Returns whether the line is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.lock().enabled
+    }
+
+    /// Returns whether the interrupt handler is enabled.
+    pub fn is_interrupt_enabled(&self) -> bool {
+        self.lock().interrupt_enabled
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LineState> {
+        // A handler which panicked does not leave the state inconsistent.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_enabled(&self) -> Result<MutexGuard<'_, LineState>> {
+        let state = self.lock();
+        if !state.enabled {
+            return Err(Error::FailedPrecondition);
+        }
+        Ok(state)
+    }
+}
+
+impl DigitalIo for SimulatedLine {
+    fn enable(&mut self) -> Result<()> {
+        self.lock().enabled = true;
+        Ok(())
+    }
+
+    fn disable(&mut self) -> Result<()> {
+        let mut state = self.lock();
+        state.enabled = false;
+        state.interrupt_enabled = false;
+        Ok(())
+    }
+}
+
+impl DigitalIn for SimulatedLine {
+    fn get_state(&mut self) -> Result<State> {
+        let state = self.lock_enabled()?;
+        Ok(state.polarity.state(state.high))
+    }
+}
+
+impl DigitalOut for SimulatedLine {
+    fn set_state(&mut self, state: State) -> Result<()> {
+        let high = self.lock_enabled()?.polarity.is_high(state);
+        self.set_level(high);
+        Ok(())
+    }
+}
+
+impl DigitalInterrupt for SimulatedLine {
+    fn set_interrupt_handler(
+        &mut self,
+        trigger: InterruptTrigger,
+        handler: InterruptHandler,
+    ) -> Result<()> {
+        let mut state = self.lock();
+        if state.handler.is_some() {
+            return Err(Error::AlreadyExists);
+        }
+        state.handler = Some((trigger, handler));
+        Ok(())
+    }
+
+    fn clear_interrupt_handler(&mut self) -> Result<()> {
+        let mut state = self.lock();
+        state.interrupt_enabled = false;
+        state.handler = None;
+        Ok(())
+    }
+
+    fn enable_interrupt_handler(&mut self) -> Result<()> {
+        let mut state = self.lock_enabled()?;
+        if state.handler.is_none() {
+            return Err(Error::FailedPrecondition);
+        }
+        state.interrupt_enabled = true;
+        Ok(())
+    }
+
+    fn disable_interrupt_handler(&mut self) -> Result<()> {
+        self.lock().interrupt_enabled = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn disabled_line_fails() {
+        let mut line = SimulatedLine::new(Polarity::ActiveHigh);
+        assert_eq!(line.get_state(), Err(Error::FailedPrecondition));
+        assert_eq!(line.set_state_active(), Err(Error::FailedPrecondition));
+        line.enable().unwrap();
+        assert!(line.is_enabled());
+        assert_eq!(line.get_state(), Ok(State::Inactive));
+    }
+
+    #[test]
+    fn output_level_follows_polarity() {
+        let line = SimulatedLine::new(Polarity::ActiveLow);
+        let mut output = line.clone();
+        output.enable().unwrap();
+        output.set_state_inactive().unwrap();
+        assert!(line.level());
+        output.set_state_active().unwrap();
+        assert!(!line.level());
+
+        line.set_level(true);
+        assert_eq!(output.is_state_active(), Ok(false));
+    }
+
+    #[test]
+    fn handler_is_called_on_triggering_edges() {
+        static ACTIVATIONS: AtomicU32 = AtomicU32::new(0);
+        static HANDLER: fn(State) = |state| {
+            assert_eq!(state, State::Active);
+            ACTIVATIONS.fetch_add(1, Ordering::Relaxed);
+        };
+
+        let line = SimulatedLine::new(Polarity::ActiveHigh);
+        let mut input = line.clone();
+        input
+            .set_interrupt_handler(InterruptTrigger::ActivatingEdge, &HANDLER)
+            .unwrap();
+        assert_eq!(
+            input.set_interrupt_handler(InterruptTrigger::BothEdges, &HANDLER),
+            Err(Error::AlreadyExists)
+        );
+        assert_eq!(
+            input.enable_interrupt_handler(),
+            Err(Error::FailedPrecondition)
+        );
+        input.enable().unwrap();
+        input.enable_interrupt_handler().unwrap();
+
+        line.set_level(true);
+        line.set_level(false);
+        line.set_level(true);
+        assert_eq!(ACTIVATIONS.load(Ordering::Relaxed), 2);
+
+        input.disable_interrupt_handler().unwrap();
+        line.set_level(false);
+        line.set_level(true);
+        assert_eq!(ACTIVATIONS.load(Ordering::Relaxed), 2);
+
+        input.clear_interrupt_handler().unwrap();
+        assert_eq!(
+            input.enable_interrupt_handler(),
+            Err(Error::FailedPrecondition)
+        );
+    }
+}
@@ -33,7 +33,7 @@
 //!
 //!
 
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 
 use proc_macro2::Ident;
 use quote::{format_ident, quote, ToTokens};
@@ -230,6 +230,13 @@ impl ToTokens for Arg {
 /// To support uses where format strings need to be built by macros at compile
 /// time, the format string can be specified as a set of string literals
 /// separated by the custom `PW_FMT_CONCAT` keyword.
+///
+/// Arguments may be followed by key-value fields written as `key = value`.
+/// Each field is appended to the format string as `■key♦` followed by an
+/// untyped (`%v`) conversion of its value, matching the field syntax of
+/// `pw_log_tokenized`.  As with `%v`, values must be given as type casts
+/// (i.e. `temp = t as i32`) unless the `nightly_tait` feature is enabled.
+/// Positional arguments may not follow a field.
 #[derive(Debug)]
 pub struct FormatAndArgs {
     format_string: LitStr,
@@ -250,24 +257,56 @@ impl Parse for FormatAndArgs {
             span,
         );
 
-        let args = if input.is_empty() {
+        let punctuated = if input.is_empty() {
             // If there are no more tokens, no arguments were specified.
-            VecDeque::new()
+            Punctuated::new()
         } else {
             // Eat the `,` following the format string.
             input.parse::<Token![,]>()?;
 
-            let punctuated = Punctuated::<Arg, Token![,]>::parse_terminated(input)?;
-            punctuated.into_iter().collect()
+            Punctuated::<Arg, Token![,]>::parse_terminated(input)?
         };
 
-        let parsed = FormatString::parse(&format_string.value()).map_err(|e| {
+        let mut parsed = FormatString::parse(&format_string.value()).map_err(|e| {
             syn::Error::new_spanned(
                 format_string.to_token_stream(),
                 format!("Error parsing format string {e}"),
             )
         })?;
 
+        let mut args = VecDeque::new();
+        let mut fields = Vec::new();
+        for arg in punctuated {
+            match field(&arg) {
+                Some(field) => fields.push(field),
+                None if fields.is_empty() => args.push_back(arg),
+                None => {
+                    return Err(syn::Error::new_spanned(
+                        arg,
+                        "Positional arguments must precede key-value fields",
+                    ))
+                }
+            }
+        }
+
+        for (key, value) in fields {
+            let key = format!("■{key}♦");
+            match parsed.fragments.last_mut() {
+                Some(FormatFragment::Literal(literal)) => literal.push_str(&key),
+                _ => parsed.fragments.push(FormatFragment::Literal(key)),
+            }
+            parsed
+                .fragments
+                .push(FormatFragment::Conversion(ConversionSpec {
+                    flags: HashSet::new(),
+                    min_field_width: MinFieldWidth::None,
+                    precision: Precision::None,
+                    length: None,
+                    specifier: Specifier::Untyped,
+                }));
+            args.push_back(value);
+        }
+
         Ok(FormatAndArgs {
             format_string,
             parsed,
@@ -276,6 +315,35 @@ impl Parse for FormatAndArgs {
     }
 }
 
+// Split a `key = value` field argument into its key and value.  Returns `None`
+// if the argument is not a field.
+fn field(arg: &Arg) -> Option<(Ident, Arg)> {
+    let Arg::Expr(expr) = arg else {
+        return None;
+    };
+    let Expr::Assign(assign) = ungroup(expr) else {
+        return None;
+    };
+    let key = match assign.left.as_ref() {
+        Expr::Path(path) if assign.attrs.is_empty() => path.path.get_ident().cloned()?,
+        _ => return None,
+    };
+    let value = match ungroup(&assign.right) {
+        Expr::Cast(cast) => Arg::ExprCast(cast.clone()),
+        expr => Arg::Expr(expr.clone()),
+    };
+    Some((key, value))
+}
+
+// Arguments forwarded through `macro_rules!` as `expr` fragments are wrapped
+// in invisible groups.
+fn ungroup(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Group(group) => ungroup(&group.expr),
+        expr => expr,
+    }
+}
+
 // Grab the next argument returning a descriptive error if no more args are left.
 fn next_arg(spec: &ConversionSpec, args: &mut VecDeque<Arg>) -> Result<Arg> {
     args.pop_front()
@@ -111,6 +111,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn generate_appends_key_value_fields() {
+        assert_eq!(
+            generator_test_macro!("test %d", 5, id = 1, name = "test"),
+            vec![
+                TestGeneratorOps::StringFragment("test ".to_string()),
+                TestGeneratorOps::IntegerConversion {
+                    display_type: IntegerDisplayType::Signed,
+                    type_width: 32,
+                },
+                TestGeneratorOps::StringFragment("■id♦".to_string()),
+                TestGeneratorOps::UntypedConversion,
+                TestGeneratorOps::StringFragment("■name♦".to_string()),
+                TestGeneratorOps::UntypedConversion,
+                TestGeneratorOps::Finalize
+            ]
+        );
+    }
+
+    #[test]
+    fn generate_printf_appends_key_value_fields() {
+        assert_eq!(
+            printf_generator_test_macro!("test", id = 1 as u32, name = "test" as &str),
+            (
+                "test■id♦%u■name♦%s",
+                vec![
+                    PrintfTestGeneratorOps::StringFragment("test■id♦".to_string()),
+                    PrintfTestGeneratorOps::UntypedConversion,
+                    PrintfTestGeneratorOps::StringFragment("■name♦".to_string()),
+                    PrintfTestGeneratorOps::UntypedConversion,
+                    PrintfTestGeneratorOps::Finalize
+                ]
+            )
+        );
+    }
+
     // Test that a generator returning an overridden integer conversion specifier
     // changes that and only that conversion specifier in the format string.
     #[test]
@@ -0,0 +1,605 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use core::ops::Range;
+
+use pw_checksum::{Checksum, Crc32Ieee};
+use pw_status::{Error, Result};
+
+use crate::{CONTROL_SIZE, ESCAPE, ESCAPE_XOR, FCS_SIZE, FLAG, MAX_ADDRESS_SIZE};
+
+/// The smallest number of unescaped bytes between the flags of a valid
+/// frame: a one byte address, the control field, and the FCS.
+pub const MIN_FRAME_CONTENT_SIZE: usize = 1 + CONTROL_SIZE + FCS_SIZE;
+
+/// Returns the size of the buffer a [`Decoder`] needs to decode frames of up
+/// to `max_frame_size` encoded bytes, matching the C++
+/// `pw::hdlc::Decoder::RequiredBufferSizeForFrameSize()`.
+///
+/// Flags and escape bytes are not stored, so the buffer may be smaller than
+/// the largest encoded frame.
+pub const fn required_buffer_size(max_frame_size: usize) -> usize {
+    if max_frame_size < MIN_FRAME_CONTENT_SIZE + 2 {
+        MIN_FRAME_CONTENT_SIZE
+    } else {
+        max_frame_size - 2
+    }
+}
+
+/// Decodes a one terminated varint address from the start of `data` and
+/// returns it along with the number of bytes it used.
+///
+/// # Errors
+/// - [`Error::DataLoss`] - `data` does not start with a valid address of at
+///   most [`MAX_ADDRESS_SIZE`] bytes.
+pub fn decode_address(data: &[u8]) -> Result<(u64, usize)> {
+    let mut address = 0u64;
+    for (i, &byte) in data.iter().take(MAX_ADDRESS_SIZE).enumerate() {
+        let bits = u64::from(byte >> 1);
+        let shift = 7 * i as u32;
+        // The tenth byte may only hold the top bit of a `u64`.
+        if shift > 0 && bits >> (u64::BITS - shift) != 0 {
+            return Err(Error::DataLoss);
+        }
+        address |= bits << shift;
+        if byte & 1 != 0 {
+            return Ok((address, i + 1));
+        }
+    }
+    Err(Error::DataLoss)
+}
+
+/// The contents of a valid HDLC frame: the unescaped data between two flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame<'a> {
+    address: u64,
+    control: u8,
+    payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Parses the unescaped contents of a frame, including its FCS, matching
+    /// the C++ `pw::hdlc::Frame::Parse()`.
+    ///
+    /// The FCS is not verified.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The address is invalid or the frame is too
+    ///   short.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let (address, address_len) = decode_address(data)?;
+        let payload_end = data
+            .len()
+            .checked_sub(FCS_SIZE)
+            .filter(|&end| end > address_len)
+            .ok_or(Error::DataLoss)?;
+        Ok(Self {
+            address,
+            control: data[address_len],
+            payload: &data[address_len + CONTROL_SIZE..payload_end],
+        })
+    }
+
+    /// Returns the frame's address.
+    pub const fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// Returns the frame's control field.
+    pub const fn control(&self) -> u8 {
+        self.control
+    }
+
+    /// Returns the frame's payload.
+    pub const fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+}
+
+/// Counts of the frames a [`Decoder`] has completed, by outcome.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecoderStats {
+    /// Valid frames decoded.
+    pub frames: u32,
+    /// Frames whose frame check sequence did not match their contents.
+    pub fcs_errors: u32,
+    /// Frames shorter than [`MIN_FRAME_CONTENT_SIZE`].
+    pub short_frames: u32,
+    /// Valid frames which did not fit in the decoder's buffer.
+    pub oversized_frames: u32,
+    /// Frames abandoned because of an escaped flag or escape byte.
+    pub escape_errors: u32,
+    /// Frames with an invalid address.
+    pub address_errors: u32,
+    /// Bytes received outside of a frame, including the rest of a frame
+    /// abandoned because of a double escape.
+    pub discarded_bytes: u32,
+}
+
+impl DecoderStats {
+    /// Returns the number of frames which were not decoded because they were
+    /// malformed or too large.
+    pub const fn malformed_frames(&self) -> u32 {
+        self.fcs_errors
+            .saturating_add(self.short_frames)
+            .saturating_add(self.oversized_frames)
+            .saturating_add(self.escape_errors)
+            .saturating_add(self.address_errors)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    InterFrame,
+    Frame,
+    FrameEscape,
+}
+
+/// A decoder which reassembles HDLC frames from a stream of bytes, matching
+/// the C++ `pw::hdlc::Decoder`.
+///
+/// Bytes can be passed to the decoder in chunks of any size, such as the
+/// bytes received by each UART interrupt.  The unescaped contents of the
+/// current frame are stored in the decoder's buffer `B` and its frame check
+/// sequence is computed as the frame is received, so the buffer only needs
+/// to hold one frame.  See [`required_buffer_size()`].
+///
+/// Completed frames are returned as `Ok(frame)` and malformed frames as
+/// errors:
+/// - [`Error::DataLoss`] - The frame was invalid.  Its FCS did not match, it
+///   was too short, or it contained an invalid escape sequence or address.
+/// - [`Error::ResourceExhausted`] - The frame was valid but too large for the
+///   buffer.
+///
+/// Each outcome is counted in the decoder's [`DecoderStats`].
+///
+/// ```
+/// use pw_hdlc::{encode_ui_frame, Decoder};
+///
+/// let mut encoded = [0u8; 32];
+/// let len = encode_ui_frame(&mut encoded, 1, b"hello").unwrap();
+///
+/// let mut decoder = Decoder::new([0u8; 16]);
+/// let mut data = &encoded[..len];
+/// // Frames may be split across any number of calls.
+/// assert!(decoder.next_frame(&mut &data[..4]).is_none());
+/// data = &data[4..];
+/// let frame = decoder.next_frame(&mut data).unwrap().unwrap();
+/// assert_eq!(frame.address(), 1);
+/// assert_eq!(frame.payload(), b"hello");
+/// assert_eq!(decoder.stats().frames, 1);
+/// ```
+pub struct Decoder<B: AsRef<[u8]> + AsMut<[u8]>> {
+    buffer: B,
+    state: State,
+    len: usize,
+    // The last four bytes of the frame, which are its FCS once the frame
+    // ends.  Bytes are added to the running FCS as they leave this ring.
+    last_bytes: [u8; FCS_SIZE],
+    last_bytes_index: usize,
+    fcs: Crc32Ieee,
+    stats: DecoderStats,
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Decoder<B> {
+    /// Creates a decoder which stores frames in `buffer`.
+    pub const fn new(buffer: B) -> Self {
+        Self {
+            buffer,
+            state: State::InterFrame,
+            len: 0,
+            last_bytes: [0; FCS_SIZE],
+            last_bytes_index: 0,
+            fcs: Crc32Ieee::new(),
+            stats: DecoderStats {
+                frames: 0,
+                fcs_errors: 0,
+                short_frames: 0,
+                oversized_frames: 0,
+                escape_errors: 0,
+                address_errors: 0,
+                discarded_bytes: 0,
+            },
+        }
+    }
+
+    /// Returns the size of the largest frame contents the decoder can hold.
+    pub fn max_size(&self) -> usize {
+        self.buffer.as_ref().len()
+    }
+
+    /// Returns the decoder's statistics.
+    pub fn stats(&self) -> DecoderStats {
+        self.stats
+    }
+
+    /// Resets the decoder's statistics.
+    pub fn reset_stats(&mut self) {
+        self.stats = DecoderStats::default();
+    }
+
+    /// Discards any partially decoded frame.  Bytes up to the next flag are
+    /// ignored.
+    pub fn clear(&mut self) {
+        self.state = State::InterFrame;
+        self.reset();
+    }
+
+    /// Consumes the decoder and returns its buffer.
+    pub fn into_inner(self) -> B {
+        self.buffer
+    }
+
+    /// Decodes a single byte and returns the frame or error it completes, if
+    /// any.
+    pub fn process_byte(&mut self, byte: u8) -> Option<Result<Frame<'_>>> {
+        let result = self.step(byte)?;
+        Some(result.map(|(address, control, payload)| Frame {
+            address,
+            control,
+            payload: &self.buffer.as_ref()[payload],
+        }))
+    }
+
+    /// Decodes bytes from the start of `data` until a frame or error is
+    /// completed, and advances `data` past the decoded bytes.
+    ///
+    /// Returns `None` once all of `data` is decoded without completing a
+    /// frame.  Call this in a loop to handle each frame in `data`:
+    ///
+    /// ```
+    /// # use pw_hdlc::Decoder;
+    /// # fn handle(_frame: pw_hdlc::Frame) {}
+    /// # let mut decoder = Decoder::new([0u8; 16]);
+    /// # let mut data: &[u8] = &[];
+    /// while let Some(result) = decoder.next_frame(&mut data) {
+    ///     if let Ok(frame) = result {
+    ///         handle(frame);
+    ///     }
+    /// }
+    /// ```
+    pub fn next_frame(&mut self, data: &mut &[u8]) -> Option<Result<Frame<'_>>> {
+        while let Some((&byte, rest)) = data.split_first() {
+            *data = rest;
+            if let Some(result) = self.step(byte) {
+                return Some(result.map(|(address, control, payload)| Frame {
+                    address,
+                    control,
+                    payload: &self.buffer.as_ref()[payload],
+                }));
+            }
+        }
+        None
+    }
+
+    /// Decodes all of `data` and calls `callback` with each frame or error it
+    /// completes.
+    pub fn process(&mut self, mut data: &[u8], mut callback: impl FnMut(Result<Frame<'_>>)) {
+        while let Some(result) = self.next_frame(&mut data) {
+            callback(result);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.len = 0;
+        self.last_bytes_index = 0;
+        self.fcs = Crc32Ieee::new();
+    }
+
+    // Decodes a byte, returning the address, control field, and payload range
+    // of a completed frame.
+    fn step(&mut self, byte: u8) -> Option<Result<(u64, u8, Range<usize>)>> {
+        match self.state {
+            State::InterFrame => {
+                if byte == FLAG {
+                    self.state = State::Frame;
+                    // Bytes between frames are reported when the next frame
+                    // starts.
+                    if self.len != 0 {
+                        self.stats.discarded_bytes =
+                            self.stats.discarded_bytes.saturating_add(self.len as u32);
+                        self.reset();
+                        return Some(Err(Error::DataLoss));
+                    }
+                } else {
+                    self.len += 1;
+                }
+                None
+            }
+            State::Frame => {
+                if byte == FLAG {
+                    let len = self.len;
+                    let result = self.check_frame();
+                    self.reset();
+                    return result.map(|result| result.and_then(|()| self.parse(len)));
+                }
+                if byte == ESCAPE {
+                    self.state = State::FrameEscape;
+                } else {
+                    self.append(byte);
+                }
+                None
+            }
+            State::FrameEscape => {
+                if byte == FLAG {
+                    // The flag can not be escaped.
+                    self.state = State::Frame;
+                    self.reset();
+                    self.stats.escape_errors = self.stats.escape_errors.saturating_add(1);
+                    return Some(Err(Error::DataLoss));
+                }
+                if byte == ESCAPE {
+                    // Two escapes in a row abandon the frame, which is
+                    // reported when the next flag arrives.
+                    self.state = State::InterFrame;
+                    self.stats.escape_errors = self.stats.escape_errors.saturating_add(1);
+                    self.len += 1;
+                } else {
+                    self.state = State::Frame;
+                    self.append(byte ^ ESCAPE_XOR);
+                }
+                None
+            }
+        }
+    }
+
+    fn append(&mut self, byte: u8) {
+        if let Some(slot) = self.buffer.as_mut().get_mut(self.len) {
+            *slot = byte;
+        }
+        if self.len >= FCS_SIZE {
+            self.fcs.update(&[self.last_bytes[self.last_bytes_index]]);
+        }
+        self.last_bytes[self.last_bytes_index] = byte;
+        self.last_bytes_index = (self.last_bytes_index + 1) % FCS_SIZE;
+        // The length keeps growing past the buffer so oversized frames are
+        // detected.
+        self.len += 1;
+    }
+
+    // Checks the frame which just ended.  Returns `None` for an empty frame,
+    // since repeated flags are allowed.
+    fn check_frame(&mut self) -> Option<Result<()>> {
+        if self.len == 0 {
+            return None;
+        }
+        if self.len < MIN_FRAME_CONTENT_SIZE {
+            self.stats.short_frames = self.stats.short_frames.saturating_add(1);
+            return Some(Err(Error::DataLoss));
+        }
+
+        let mut fcs = [0u8; FCS_SIZE];
+        for (i, byte) in fcs.iter_mut().enumerate() {
+            *byte = self.last_bytes[(self.last_bytes_index + i) % FCS_SIZE];
+        }
+        if u32::from_le_bytes(fcs) != self.fcs.value() {
+            self.stats.fcs_errors = self.stats.fcs_errors.saturating_add(1);
+            return Some(Err(Error::DataLoss));
+        }
+
+        if self.len > self.max_size() {
+            self.stats.oversized_frames = self.stats.oversized_frames.saturating_add(1);
+            return Some(Err(Error::ResourceExhausted));
+        }
+        Some(Ok(()))
+    }
+
+    fn parse(&mut self, len: usize) -> Result<(u64, u8, Range<usize>)> {
+        let frame = match Frame::parse(&self.buffer.as_ref()[..len]) {
+            Ok(frame) => frame,
+            Err(error) => {
+                self.stats.address_errors = self.stats.address_errors.saturating_add(1);
+                return Err(error);
+            }
+        };
+        let (address, control) = (frame.address, frame.control);
+        let start = len - FCS_SIZE - frame.payload.len();
+        self.stats.frames = self.stats.frames.saturating_add(1);
+        Ok((address, control, start..len - FCS_SIZE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_ui_frame;
+
+    // Decodes `data` one byte at a time and returns the last result.
+    fn decode_bytes<B: AsRef<[u8]> + AsMut<[u8]>>(
+        decoder: &mut Decoder<B>,
+        data: &[u8],
+    ) -> Option<Result<(u64, usize)>> {
+        let mut last = None;
+        for &byte in data {
+            assert!(last.is_none(), "result before the end of the data");
+            last = decoder
+                .process_byte(byte)
+                .map(|result| result.map(|frame| (frame.address(), frame.payload().len())));
+        }
+        last
+    }
+
+    #[test]
+    fn frame_fields_are_parsed() {
+        let frame = Frame::parse(b"\x05\xab\x42\x24\xf9\x54\xfb\x3d").unwrap();
+        assert_eq!(frame.address(), 2);
+        assert_eq!(frame.control(), 0xab);
+        assert_eq!(frame.payload(), &[0x42, 0x24]);
+    }
+
+    #[test]
+    fn multibyte_address_is_parsed() {
+        let frame = Frame::parse(b"\x2c\xd9\x33\x01\x02\xaf\xc8\x77\x48").unwrap();
+        assert_eq!(frame.address(), 0b11011000010110);
+        assert_eq!(frame.control(), 0x33);
+        assert_eq!(frame.payload(), &[0x01, 0x02]);
+    }
+
+    #[test]
+    fn too_long_address_is_data_loss() {
+        assert_eq!(
+            Frame::parse(
+                b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x33\x01\x02\xaf\xc8\x77\x48"
+            ),
+            Err(Error::DataLoss)
+        );
+        // The tenth byte may only hold one bit.
+        assert_eq!(
+            decode_address(b"\xfe\xfe\xfe\xfe\xfe\xfe\xfe\xfe\xfe\x03"),
+            Ok((u64::MAX, 10))
+        );
+        assert_eq!(
+            decode_address(b"\xfe\xfe\xfe\xfe\xfe\xfe\xfe\xfe\xfe\x05"),
+            Err(Error::DataLoss)
+        );
+        assert_eq!(Frame::parse(b"\x03\x03\x00\x00\x00"), Err(Error::DataLoss));
+    }
+
+    #[test]
+    fn clear_discards_partial_frame() {
+        let mut decoder = Decoder::new([0u8; 8]);
+        decoder.process(b"~1234abcd", |_| panic!("unexpected frame"));
+        decoder.clear();
+
+        let mut frames = 0;
+        decoder.process(b"~1234\xa3\xe0\xe3\x9b~", |result| {
+            assert_eq!(result.unwrap().payload(), b"34");
+            frames += 1;
+        });
+        assert_eq!(frames, 1);
+    }
+
+    #[test]
+    fn frame_which_exactly_fits_is_decoded() {
+        let mut decoder = Decoder::new([0u8; 8]);
+        assert_eq!(
+            decode_bytes(&mut decoder, b"~1234\xa3\xe0\xe3\x9b~"),
+            Some(Ok((0x18, 2)))
+        );
+    }
+
+    #[test]
+    fn minimum_sized_buffer_decodes_empty_payload() {
+        let mut decoder = Decoder::new([0u8; MIN_FRAME_CONTENT_SIZE]);
+        assert_eq!(
+            decode_bytes(&mut decoder, b"~12\xcd\x44\x53\x4f~"),
+            Some(Ok((0x18, 0)))
+        );
+    }
+
+    #[test]
+    fn frame_too_large_for_buffer_is_resource_exhausted() {
+        let mut buffer = [b'?'; 16];
+        let mut decoder = Decoder::new(&mut buffer[..8]);
+        assert_eq!(
+            decode_bytes(&mut decoder, b"~12345\x1c\x3a\xf5\xcb~"),
+            Some(Err(Error::ResourceExhausted))
+        );
+        assert_eq!(
+            decode_bytes(&mut decoder, b"12345678901234567890\xf2\x19\x63\x90~"),
+            Some(Err(Error::ResourceExhausted))
+        );
+        // The next frame starts after the last frame's flag.
+        assert_eq!(
+            decode_bytes(&mut decoder, b"1234\xa3\xe0\xe3\x9b~"),
+            Some(Ok((0x18, 2)))
+        );
+        assert_eq!(decoder.stats().oversized_frames, 2);
+        assert!(buffer[8..].iter().all(|&b| b == b'?'));
+    }
+
+    #[test]
+    fn malformed_frames_are_counted() {
+        let mut decoder = Decoder::new([0u8; 16]);
+        let mut errors = 0;
+        decoder.process(
+            // Bytes before the first flag, a short frame, a bad FCS, an
+            // escaped flag, and a double escape.
+            b"xy~12~1234\xa3\xe0\xe3\x9c~12\x7d~12\x7d\x7d34~",
+            |result| {
+                assert!(matches!(result, Err(Error::DataLoss)));
+                errors += 1;
+            },
+        );
+        assert_eq!(errors, 5);
+        assert_eq!(
+            decoder.stats(),
+            DecoderStats {
+                frames: 0,
+                fcs_errors: 1,
+                short_frames: 1,
+                oversized_frames: 0,
+                escape_errors: 2,
+                address_errors: 0,
+                discarded_bytes: 7,
+            }
+        );
+        assert_eq!(decoder.stats().malformed_frames(), 4);
+        decoder.reset_stats();
+        assert_eq!(decoder.stats(), DecoderStats::default());
+    }
+
+    #[test]
+    fn repeated_flags_are_not_errors() {
+        let mut decoder = Decoder::new([0u8; 8]);
+        decoder.process(b"~~~~", |_| panic!("unexpected result"));
+        assert_eq!(decoder.stats(), DecoderStats::default());
+    }
+
+    #[test]
+    fn malformed_frames_saturates() {
+        let stats = DecoderStats {
+            fcs_errors: u32::MAX,
+            escape_errors: 1,
+            ..DecoderStats::default()
+        };
+        assert_eq!(stats.malformed_frames(), u32::MAX);
+    }
+
+    #[test]
+    fn encoded_frames_are_decoded_across_chunks() {
+        let mut encoded = [0u8; 64];
+        let mut len = 0;
+        for (address, payload) in [(1, &b"\x7e\x7dab"[..]), (u64::MAX, b""), (300, b"xyz")] {
+            len += encode_ui_frame(&mut encoded[len..], address, payload).unwrap();
+        }
+
+        for chunk_size in 1..len {
+            let mut decoder = Decoder::new([0u8; 32]);
+            let mut decoded = 0;
+            for mut chunk in encoded[..len].chunks(chunk_size) {
+                while let Some(result) = decoder.next_frame(&mut chunk) {
+                    let frame = result.unwrap();
+                    match decoded {
+                        0 => {
+                            assert_eq!((frame.address(), frame.payload()), (1, &b"\x7e\x7dab"[..]))
+                        }
+                        1 => assert_eq!((frame.address(), frame.payload()), (u64::MAX, &b""[..])),
+                        _ => assert_eq!((frame.address(), frame.payload()), (300, &b"xyz"[..])),
+                    }
+                    decoded += 1;
+                }
+            }
+            assert_eq!(decoded, 3);
+            assert_eq!(decoder.stats().frames, 3);
+        }
+    }
+
+    #[test]
+    fn required_buffer_size_excludes_flags() {
+        assert_eq!(required_buffer_size(0), MIN_FRAME_CONTENT_SIZE);
+        assert_eq!(required_buffer_size(8), MIN_FRAME_CONTENT_SIZE);
+        assert_eq!(required_buffer_size(64), 62);
+    }
+}
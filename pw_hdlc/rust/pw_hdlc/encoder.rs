@@ -0,0 +1,374 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_checksum::{Checksum, Crc32Ieee};
+use pw_status::{Error, Result};
+use pw_stream::{Cursor, HdlcWriter, Write};
+
+use crate::{
+    encode_address, needs_escape, CONTROL_SIZE, FCS_SIZE, MAX_ADDRESS_SIZE, UI_FRAME_CONTROL,
+};
+
+// The largest escaped address.  The last byte of a `u64` address is at most
+// 0x03, so it is never escaped.
+const MAX_ESCAPED_ADDRESS_SIZE: usize = 2 * MAX_ADDRESS_SIZE - 1;
+
+// The size of every part of a frame other than the payload when each byte is
+// escaped, plus the two flags.
+const MAX_ESCAPED_OVERHEAD: usize = 2 + MAX_ESCAPED_ADDRESS_SIZE + 2 * CONTROL_SIZE + 2 * FCS_SIZE;
+
+/// Returns the size of `data` once its flag and escape bytes are escaped.
+pub const fn escaped_size(data: &[u8]) -> usize {
+    let mut size = data.len();
+    let mut i = 0;
+    while i < data.len() {
+        if needs_escape(data[i]) {
+            size += 1;
+        }
+        i += 1;
+    }
+    size
+}
+
+/// Returns the size of a buffer which can hold any encoded frame with a
+/// payload of up to `max_payload_size` bytes, matching the C++
+/// `pw::hdlc::MaxEncodedFrameSize()`.
+///
+/// This assumes every byte of the frame is escaped, so the result can be
+/// used to size buffers at compile time.
+pub const fn max_encoded_frame_size(max_payload_size: usize) -> usize {
+    MAX_ESCAPED_OVERHEAD + 2 * max_payload_size
+}
+
+/// Returns the largest payload which can always be encoded in a frame of
+/// `max_frame_size` bytes, or zero if no payload fits.
+///
+/// This is not an exact inverse of [`max_encoded_frame_size()`], since
+/// payload bytes may be escaped.
+pub const fn max_safe_payload_size(max_frame_size: usize) -> usize {
+    max_frame_size.saturating_sub(MAX_ESCAPED_OVERHEAD) / 2
+}
+
+/// Writes `payload` as a complete HDLC frame addressed to `address` with the
+/// one byte `control` field to `writer`.
+///
+/// # Errors
+/// Returns any error from `writer`, which may be left with a partial frame.
+pub fn write_frame<W: Write>(
+    writer: &mut W,
+    address: u64,
+    control: u8,
+    payload: &[u8],
+) -> Result<()> {
+    HdlcWriter::new(writer).write_frame(address, control, payload)
+}
+
+/// Writes `payload` as an unnumbered information (UI) frame addressed to
+/// `address` to `writer`, matching the C++ `pw::hdlc::WriteUIFrame()`.
+///
+/// # Errors
+/// Returns any error from `writer`, which may be left with a partial frame.
+pub fn write_ui_frame<W: Write>(writer: &mut W, address: u64, payload: &[u8]) -> Result<()> {
+    write_frame(writer, address, UI_FRAME_CONTROL, payload)
+}
+
+/// Encodes `payload` as a complete HDLC frame addressed to `address` with the
+/// one byte `control` field into `buffer` and returns the size of the frame.
+///
+/// # Errors
+/// - [`Error::ResourceExhausted`] - The frame does not fit in `buffer`.  A
+///   buffer of [`max_encoded_frame_size()`] bytes always fits the frame.
+pub fn encode_frame(buffer: &mut [u8], address: u64, control: u8, payload: &[u8]) -> Result<usize> {
+    let mut cursor = Cursor::new(buffer);
+    write_frame(&mut cursor, address, control, payload).map_err(|_| Error::ResourceExhausted)?;
+    Ok(cursor.position())
+}
+
+/// Encodes `payload` as an unnumbered information (UI) frame addressed to
+/// `address` into `buffer` and returns the size of the frame.
+///
+/// # Errors
+/// - [`Error::ResourceExhausted`] - The frame does not fit in `buffer`.  A
+///   buffer of [`max_encoded_frame_size()`] bytes always fits the frame.
+pub fn encode_ui_frame(buffer: &mut [u8], address: u64, payload: &[u8]) -> Result<usize> {
+    encode_frame(buffer, address, UI_FRAME_CONTROL, payload)
+}
+
+/// A writer which streams its data into a single HDLC frame as the payload.
+///
+/// The frame's flag, address, and control field are written when the
+/// `FrameWriter` is created.  Payload data is escaped and written as it
+/// arrives, so it does not need to be buffered, and [`FrameWriter::finish()`]
+/// writes the frame check sequence and closing flag.
+///
+/// ```
+/// use pw_hdlc::{encode_ui_frame, FrameWriter, UI_FRAME_CONTROL};
+/// use pw_stream::{Cursor, Write};
+///
+/// let mut frame = FrameWriter::new(Cursor::new([0u8; 16]), 1, UI_FRAME_CONTROL).unwrap();
+/// frame.write_all(b"he").unwrap();
+/// frame.write_all(b"llo").unwrap();
+/// let cursor = frame.finish().unwrap();
+/// let len = cursor.position();
+///
+/// let mut expected = [0u8; 16];
+/// let expected_len = encode_ui_frame(&mut expected, 1, b"hello").unwrap();
+/// assert_eq!(&cursor.into_inner()[..len], &expected[..expected_len]);
+/// ```
+pub struct FrameWriter<W: Write> {
+    hdlc: HdlcWriter<W>,
+    fcs: Crc32Ieee,
+}
+
+impl<W: Write> FrameWriter<W> {
+    /// Starts a frame addressed to `address` with the one byte `control`
+    /// field on `inner`.
+    ///
+    /// # Errors
+    /// Returns any error from `inner`.
+    pub fn new(inner: W, address: u64, control: u8) -> Result<Self> {
+        let mut header = [0u8; MAX_ADDRESS_SIZE + CONTROL_SIZE];
+        let mut address_bytes = [0u8; MAX_ADDRESS_SIZE];
+        let len = encode_address(address, &mut address_bytes);
+        header[..len].copy_from_slice(&address_bytes[..len]);
+        header[len] = control;
+
+        let mut writer = Self {
+            hdlc: HdlcWriter::new(inner),
+            fcs: Crc32Ieee::new(),
+        };
+        writer.write_all(&header[..len + CONTROL_SIZE])?;
+        Ok(writer)
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.hdlc.get_ref()
+    }
+
+    /// Writes the frame check sequence and closing flag and returns the
+    /// underlying writer.
+    ///
+    /// # Errors
+    /// Returns any error from the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        let fcs = self.fcs.value().to_le_bytes();
+        self.hdlc.write_all(&fcs)?;
+        self.hdlc.finish()?;
+        Ok(self.hdlc.into_inner())
+    }
+}
+
+impl<W: Write> Write for FrameWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let len = self.hdlc.write(buf)?;
+        self.fcs.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.hdlc.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_stream::VecWriter;
+
+    use super::*;
+    use crate::{ESCAPE, FLAG};
+
+    // The address used by the C++ `pw_hdlc` encoder tests.
+    const ADDRESS: u64 = 0x7b;
+    const ENCODED_ADDRESS: u8 = (0x7b << 1) | 1;
+
+    // Encodes a UI frame and checks it against the concatenation of
+    // `expected`.
+    fn assert_encodes(address: u64, payload: &[u8], expected: &[&[u8]]) {
+        let mut buffer = [0u8; max_encoded_frame_size(32)];
+        let len = encode_ui_frame(&mut buffer, address, payload).unwrap();
+
+        let mut concatenated = VecWriter::<{ max_encoded_frame_size(32) }>::new();
+        for part in expected {
+            concatenated.write_all(part).unwrap();
+        }
+        assert_eq!(&buffer[..len], concatenated.as_slice());
+    }
+
+    #[test]
+    fn empty_payload() {
+        assert_encodes(
+            ADDRESS,
+            &[],
+            &[
+                &[FLAG, ENCODED_ADDRESS, UI_FRAME_CONTROL],
+                &0x832d343fu32.to_le_bytes(),
+                &[FLAG],
+            ],
+        );
+    }
+
+    #[test]
+    fn one_byte_payload() {
+        assert_encodes(
+            ADDRESS,
+            b"A",
+            &[
+                &[FLAG, ENCODED_ADDRESS, UI_FRAME_CONTROL, b'A'],
+                &0x653c9e82u32.to_le_bytes(),
+                &[FLAG],
+            ],
+        );
+    }
+
+    #[test]
+    fn payload_flag_and_escape_bytes_are_escaped() {
+        assert_encodes(
+            ADDRESS,
+            &[0x7d],
+            &[
+                &[FLAG, ENCODED_ADDRESS, UI_FRAME_CONTROL, ESCAPE, 0x5d],
+                &0x4a53e205u32.to_le_bytes(),
+                &[FLAG],
+            ],
+        );
+        assert_encodes(
+            ADDRESS,
+            &[0x7e],
+            &[
+                &[FLAG, ENCODED_ADDRESS, UI_FRAME_CONTROL, ESCAPE, 0x5e],
+                &0xd35ab3bfu32.to_le_bytes(),
+                &[FLAG],
+            ],
+        );
+    }
+
+    #[test]
+    fn address_is_escaped() {
+        assert_encodes(
+            0x7d >> 1,
+            b"A",
+            &[
+                &[FLAG, ESCAPE, 0x5d, UI_FRAME_CONTROL, b'A'],
+                &0x899e00d4u32.to_le_bytes(),
+                &[FLAG],
+            ],
+        );
+    }
+
+    #[test]
+    fn fcs_is_escaped() {
+        // The FCS is 0x7ee04473.
+        assert_encodes(
+            ADDRESS,
+            b"aa",
+            &[
+                &[FLAG, ENCODED_ADDRESS, UI_FRAME_CONTROL, b'a', b'a'],
+                &[0x73, 0x44, 0xe0, ESCAPE, 0x5e, FLAG],
+            ],
+        );
+    }
+
+    #[test]
+    fn multibyte_address() {
+        assert_encodes(
+            0x3fff,
+            b"abc",
+            &[
+                &[FLAG, 0xfe, 0xff, UI_FRAME_CONTROL],
+                b"abc",
+                &0x8cee2978u32.to_le_bytes(),
+                &[FLAG],
+            ],
+        );
+    }
+
+    #[test]
+    fn payload_with_multiple_escapes() {
+        assert_encodes(
+            ADDRESS,
+            &[0x7e, 0x7b, 0x61, 0x62, 0x63, 0x7d, 0x7e],
+            &[
+                &[FLAG, ENCODED_ADDRESS, UI_FRAME_CONTROL],
+                &[0x7d, 0x5e, 0x7b, 0x61, 0x62, 0x63, 0x7d, 0x5d, 0x7d, 0x5e],
+                &0x1563a4e6u32.to_le_bytes(),
+                &[FLAG],
+            ],
+        );
+    }
+
+    #[test]
+    fn consecutive_frames_each_have_flags() {
+        let mut writer = VecWriter::<32>::new();
+        write_ui_frame(&mut writer, ADDRESS, b"ABC").unwrap();
+        write_ui_frame(&mut writer, ADDRESS, b"DEF").unwrap();
+
+        let mut expected = VecWriter::<32>::new();
+        for (payload, fcs) in [(b"ABC", 0x72410ee4u32), (b"DEF", 0x4ba1ae47)] {
+            expected
+                .write_all(&[FLAG, ENCODED_ADDRESS, UI_FRAME_CONTROL])
+                .unwrap();
+            expected.write_all(payload).unwrap();
+            expected.write_all(&fcs.to_le_bytes()).unwrap();
+            expected.write_all(&[FLAG]).unwrap();
+        }
+        assert_eq!(writer.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn frame_which_does_not_fit_is_resource_exhausted() {
+        let payload = [FLAG; 7];
+        let mut buffer = [0u8; max_encoded_frame_size(7)];
+        assert!(encode_ui_frame(&mut buffer, ADDRESS, &payload).is_ok());
+        assert_eq!(
+            encode_ui_frame(&mut buffer[..20], ADDRESS, &payload),
+            Err(Error::ResourceExhausted)
+        );
+    }
+
+    #[test]
+    fn frame_sizes_match_cpp() {
+        assert_eq!(max_encoded_frame_size(0), 31);
+        assert_eq!(max_encoded_frame_size(7), 45);
+        assert_eq!(max_safe_payload_size(45), 7);
+        assert_eq!(max_safe_payload_size(46), 7);
+        assert_eq!(max_safe_payload_size(10), 0);
+        assert_eq!(escaped_size(&[0x7e, 0x00, 0x7d]), 5);
+    }
+
+    #[test]
+    fn worst_case_address_fits_max_size() {
+        let mut address = [0u8; MAX_ADDRESS_SIZE];
+        assert_eq!(encode_address(u64::MAX, &mut address), MAX_ADDRESS_SIZE);
+        assert_eq!(address[MAX_ADDRESS_SIZE - 1], 0x03);
+        assert_eq!(encode_address(0, &mut address), 1);
+        assert_eq!(address[0], 0x01);
+
+        let payload = [FLAG; 16];
+        let mut buffer = [0u8; max_encoded_frame_size(16)];
+        assert!(encode_frame(&mut buffer, u64::MAX, FLAG, &payload).is_ok());
+    }
+
+    #[test]
+    fn frame_writer_matches_encode_frame() {
+        let mut frame = FrameWriter::new(VecWriter::<32>::new(), 300, 0x7e).unwrap();
+        frame.write_all(&[0x01, 0x7d]).unwrap();
+        frame.write_all(&[0x7e]).unwrap();
+        let writer = frame.finish().unwrap();
+
+        let mut expected = [0u8; 32];
+        let len = encode_frame(&mut expected, 300, 0x7e, &[0x01, 0x7d, 0x7e]).unwrap();
+        assert_eq!(writer.as_slice(), &expected[..len]);
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_hdlc` encodes and decodes the HDLC-Lite frames Pigweed uses to carry
+//! RPC packets and logs over byte oriented transports such as UARTs.
+//!
+//! This is the Rust counterpart of the C++ and Python `pw_hdlc` modules and
+//! produces frames which they can decode.  Each frame is:
+//!
+//! ```text
+//! FLAG | address | control | payload | FCS | FLAG
+//! ```
+//!
+//! - The address is a one terminated, least significant byte first varint
+//!   of up to [`MAX_ADDRESS_SIZE`] bytes.
+//! - The control field is a single byte, [`UI_FRAME_CONTROL`] for the
+//!   unnumbered information frames Pigweed sends.
+//! - The frame check sequence (FCS) is the little endian CRC-32 of the
+//!   address, control, and payload.
+//! - [`FLAG`] and [`ESCAPE`] bytes between the flags are escaped as
+//!   [`ESCAPE`] followed by the byte XORed with [`ESCAPE_XOR`].
+//!
+//! Frames can be encoded into a buffer with [`encode_ui_frame()`] or written
+//! to a [`pw_stream::Write`] with [`write_ui_frame()`]:
+//!
+//! ```
+//! use pw_hdlc::{encode_ui_frame, max_encoded_frame_size, DEFAULT_RPC_ADDRESS};
+//!
+//! let mut buffer = [0u8; max_encoded_frame_size(8)];
+//! let len = encode_ui_frame(&mut buffer, DEFAULT_RPC_ADDRESS, b"hi").unwrap();
+//! assert_eq!(
+//!     &buffer[..len],
+//!     &[0x7e, 0xa5, 0x03, b'h', b'i', 0x21, 0xb0, 0xfd, 0xc0, 0x7e]
+//! );
+//! ```
+//!
+//! Payloads which are produced incrementally, such as tokenized log
+//! messages, can be streamed into a frame with a [`FrameWriter`].
+//!
+//! Received bytes are reassembled into frames by a [`Decoder`], which
+//! verifies each frame's FCS and only buffers the frame it is decoding.
+//! Several protocols, such as logs and RPC, can share one transport by
+//! sending frames to different addresses: a [`Router`] dispatches received
+//! frames to a handler for each address and an [`Endpoint`] tags the frames
+//! it sends with its address.
+//!
+//! [`RpcChannelOutput`] sends `pw_rpc` packets in frames, compatible with
+//! `pw_console` and C++ devices.
+#![no_std]
+#![deny(missing_docs)]
+
+mod decoder;
+mod encoder;
+mod router;
+mod rpc;
+
+pub use decoder::{
+    decode_address, required_buffer_size, Decoder, DecoderStats, Frame, MIN_FRAME_CONTENT_SIZE,
+};
+
+pub use encoder::{
+    encode_frame, encode_ui_frame, escaped_size, max_encoded_frame_size, max_safe_payload_size,
+    write_frame, write_ui_frame, FrameWriter,
+};
+pub use router::{Endpoint, FrameHandler, Router};
+pub use rpc::RpcChannelOutput;
+
+// The framing constants are shared with `pw_stream::HdlcWriter`.
+pub use pw_stream::hdlc::{
+    encode_address, needs_escape, DEFAULT_LOG_ADDRESS, DEFAULT_RPC_ADDRESS, ESCAPE, ESCAPE_XOR,
+    FLAG, MAX_ADDRESS_SIZE, UI_FRAME_CONTROL,
+};
+
+/// The size of the control field.
+pub const CONTROL_SIZE: usize = 1;
+
+/// The size of the CRC-32 frame check sequence.
+pub const FCS_SIZE: usize = 4;
@@ -0,0 +1,333 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_status::{Error, Result};
+use pw_stream::{SharedWriter, Write};
+
+use crate::{write_ui_frame, Decoder, Frame};
+
+/// A handler for frames received on an HDLC address.
+///
+/// Implemented for closures which take a [`Frame`].
+pub trait FrameHandler {
+    /// Handles a valid frame sent to the handler's address.
+    fn handle_frame(&mut self, frame: Frame<'_>);
+}
+
+impl<F: FnMut(Frame<'_>)> FrameHandler for F {
+    fn handle_frame(&mut self, frame: Frame<'_>) {
+        self(frame)
+    }
+}
+
+struct Route<'a> {
+    address: u64,
+    handler: &'a mut dyn FrameHandler,
+}
+
+/// Dispatches received frames to a handler registered for each address, as
+/// `pw_system` does to share one UART between logs and RPC.
+///
+/// Up to `N` handlers can be registered.  Frames sent to an address without
+/// a handler are dropped and counted.
+///
+/// ```
+/// use pw_hdlc::{encode_ui_frame, Decoder, Frame, Router, DEFAULT_LOG_ADDRESS, DEFAULT_RPC_ADDRESS};
+///
+/// let mut logs = 0;
+/// let mut log_handler = |_frame: Frame| logs += 1;
+/// let mut rpc_handler = |frame: Frame| assert_eq!(frame.payload(), b"packet");
+///
+/// let mut router = Router::<2>::new();
+/// router.register(DEFAULT_LOG_ADDRESS, &mut log_handler).unwrap();
+/// router.register(DEFAULT_RPC_ADDRESS, &mut rpc_handler).unwrap();
+///
+/// let mut data = [0u8; 64];
+/// let mut len = encode_ui_frame(&mut data, DEFAULT_RPC_ADDRESS, b"packet").unwrap();
+/// len += encode_ui_frame(&mut data[len..], DEFAULT_LOG_ADDRESS, b"log").unwrap();
+///
+/// let mut decoder = Decoder::new([0u8; 32]);
+/// router.process(&mut decoder, &data[..len]);
+/// assert_eq!(logs, 1);
+/// ```
+pub struct Router<'a, const N: usize> {
+    routes: [Option<Route<'a>>; N],
+    unrouted_frames: u32,
+}
+
+impl<'a, const N: usize> Router<'a, N> {
+    /// Creates a router with no handlers.
+    pub const fn new() -> Self {
+        Self {
+            routes: [const { None }; N],
+            unrouted_frames: 0,
+        }
+    }
+
+    /// Registers `handler` for frames sent to `address`.
+    ///
+    /// # Errors
+    /// - [`Error::AlreadyExists`] - A handler is already registered for
+    ///   `address`.
+    /// - [`Error::ResourceExhausted`] - `N` handlers are already registered.
+    pub fn register(&mut self, address: u64, handler: &'a mut dyn FrameHandler) -> Result<()> {
+        if self.find(address).is_some() {
+            return Err(Error::AlreadyExists);
+        }
+        let slot = self
+            .routes
+            .iter_mut()
+            .find(|route| route.is_none())
+            .ok_or(Error::ResourceExhausted)?;
+        *slot = Some(Route { address, handler });
+        Ok(())
+    }
+
+    /// Removes the handler for `address`.
+    ///
+    /// # Errors
+    /// - [`Error::NotFound`] - No handler is registered for `address`.
+    pub fn unregister(&mut self, address: u64) -> Result<()> {
+        let index = self.find(address).ok_or(Error::NotFound)?;
+        self.routes[index] = None;
+        Ok(())
+    }
+
+    /// Passes `frame` to the handler for its address.
+    ///
+    /// # Errors
+    /// - [`Error::NotFound`] - No handler is registered for the frame's
+    ///   address.  The frame is counted as unrouted.
+    pub fn route(&mut self, frame: Frame<'_>) -> Result<()> {
+        let Some(index) = self.find(frame.address()) else {
+            self.unrouted_frames = self.unrouted_frames.saturating_add(1);
+            return Err(Error::NotFound);
+        };
+        if let Some(route) = &mut self.routes[index] {
+            route.handler.handle_frame(frame);
+        }
+        Ok(())
+    }
+
+    /// Decodes `data` with `decoder` and routes each valid frame.
+    ///
+    /// Malformed frames are counted in the decoder's
+    /// [`crate::DecoderStats`] and frames without a handler in
+    /// [`Router::unrouted_frames()`].
+    pub fn process<B: AsRef<[u8]> + AsMut<[u8]>>(&mut self, decoder: &mut Decoder<B>, data: &[u8]) {
+        decoder.process(data, |result| {
+            if let Ok(frame) = result {
+                // Unrouted frames are counted by `route()`.
+                let _ = self.route(frame);
+            }
+        });
+    }
+
+    /// Returns the number of frames dropped because no handler was
+    /// registered for their address.
+    pub fn unrouted_frames(&self) -> u32 {
+        self.unrouted_frames
+    }
+
+    fn find(&self, address: u64) -> Option<usize> {
+        self.routes
+            .iter()
+            .position(|route| matches!(route, Some(route) if route.address == address))
+    }
+}
+
+impl<const N: usize> Default for Router<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sends frames tagged with one address over a transport shared with other
+/// endpoints.
+///
+/// Each frame is written while holding exclusive access to the
+/// [`SharedWriter`], so frames from different endpoints are never
+/// interleaved.  As with the mutex the C++ `pw_system` uses for its HDLC
+/// channels, a frame written while the transport is in use waits for the
+/// current frame to finish rather than being dropped.  See
+/// [`SharedWriter::with_blocking()`] for the contexts endpoints may be used
+/// from.
+///
+/// ```
+/// use pw_hdlc::{Decoder, Endpoint, DEFAULT_LOG_ADDRESS, DEFAULT_RPC_ADDRESS};
+/// use pw_stream::{SharedWriter, VecWriter};
+///
+/// static UART: SharedWriter<VecWriter<64>> = SharedWriter::new(VecWriter::new());
+/// static LOGS: Endpoint<VecWriter<64>> = Endpoint::new(DEFAULT_LOG_ADDRESS, &UART);
+/// static RPC: Endpoint<VecWriter<64>> = Endpoint::new(DEFAULT_RPC_ADDRESS, &UART);
+///
+/// LOGS.write_frame(b"log").unwrap();
+/// RPC.write_frame(b"packet").unwrap();
+///
+/// UART.with(|uart| {
+///     let mut decoder = Decoder::new([0u8; 32]);
+///     let mut data = uart.as_slice();
+///     let frame = decoder.next_frame(&mut data).unwrap().unwrap();
+///     assert_eq!(frame.address(), DEFAULT_LOG_ADDRESS);
+/// })
+/// .unwrap();
+/// ```
+pub struct Endpoint<'a, W: Write> {
+    address: u64,
+    transport: &'a SharedWriter<W>,
+}
+
+impl<'a, W: Write> Endpoint<'a, W> {
+    /// Creates an endpoint which sends frames to `address` over `transport`.
+    pub const fn new(address: u64, transport: &'a SharedWriter<W>) -> Self {
+        Self { address, transport }
+    }
+
+    /// Returns the address of frames sent by the endpoint.
+    pub const fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// Writes `payload` to the transport as an unnumbered information (UI)
+    /// frame tagged with the endpoint's address, waiting for the transport if
+    /// it is in use.
+    ///
+    /// # Errors
+    /// Returns any error from the transport.
+    pub fn write_frame(&self, payload: &[u8]) -> Result<()> {
+        self.transport
+            .with_blocking(|writer| write_ui_frame(writer, self.address, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_stream::VecWriter;
+
+    use super::*;
+    use crate::{encode_ui_frame, DEFAULT_LOG_ADDRESS, DEFAULT_RPC_ADDRESS};
+
+    #[test]
+    fn frames_are_dispatched_by_address() {
+        let mut logs = 0;
+        let mut rpc = 0;
+        let mut log_handler = |frame: Frame| {
+            assert_eq!(frame.payload(), b"log");
+            logs += 1;
+        };
+        let mut rpc_handler = |frame: Frame| {
+            assert_eq!(frame.payload(), b"rpc");
+            rpc += 1;
+        };
+
+        let mut router = Router::<2>::new();
+        router
+            .register(DEFAULT_LOG_ADDRESS, &mut log_handler)
+            .unwrap();
+        router
+            .register(DEFAULT_RPC_ADDRESS, &mut rpc_handler)
+            .unwrap();
+
+        let mut data = [0u8; 64];
+        let mut len = 0;
+        for (address, payload) in [
+            (DEFAULT_LOG_ADDRESS, b"log"),
+            (DEFAULT_RPC_ADDRESS, b"rpc"),
+            (DEFAULT_LOG_ADDRESS, b"log"),
+            (2, b"???"),
+        ] {
+            len += encode_ui_frame(&mut data[len..], address, payload).unwrap();
+        }
+        let mut decoder = Decoder::new([0u8; 16]);
+        router.process(&mut decoder, &data[..len]);
+        assert_eq!(router.unrouted_frames(), 1);
+
+        assert_eq!(logs, 2);
+        assert_eq!(rpc, 1);
+    }
+
+    #[test]
+    fn registration_errors() {
+        let mut a = |_: Frame| {};
+        let mut b = |_: Frame| {};
+        let mut c = |_: Frame| {};
+        let mut rejected = |_: Frame| {};
+        let mut also_rejected = |_: Frame| {};
+
+        let mut router = Router::<2>::new();
+        router.register(1, &mut a).unwrap();
+        assert_eq!(router.register(1, &mut rejected), Err(Error::AlreadyExists));
+        router.register(2, &mut b).unwrap();
+        assert_eq!(
+            router.register(3, &mut also_rejected),
+            Err(Error::ResourceExhausted)
+        );
+
+        assert_eq!(router.unregister(3), Err(Error::NotFound));
+        router.unregister(1).unwrap();
+        router.register(3, &mut c).unwrap();
+        let frame = Frame::parse(b"\x03\x03\x00\x00\x00\x00").unwrap();
+        assert_eq!(router.route(frame), Err(Error::NotFound));
+    }
+
+    #[test]
+    fn endpoints_tag_frames_with_their_address() {
+        let transport = SharedWriter::new(VecWriter::<64>::new());
+        let logs = Endpoint::new(DEFAULT_LOG_ADDRESS, &transport);
+        let rpc = Endpoint::new(DEFAULT_RPC_ADDRESS, &transport);
+        logs.write_frame(b"log").unwrap();
+        rpc.write_frame(b"rpc").unwrap();
+
+        let mut expected = [0u8; 64];
+        let mut len = encode_ui_frame(&mut expected, DEFAULT_LOG_ADDRESS, b"log").unwrap();
+        len += encode_ui_frame(&mut expected[len..], DEFAULT_RPC_ADDRESS, b"rpc").unwrap();
+        transport
+            .with(|writer| assert_eq!(writer.as_slice(), &expected[..len]))
+            .unwrap();
+    }
+
+    #[test]
+    fn endpoint_write_waits_while_transport_is_in_use() {
+        extern crate std;
+        use std::sync::mpsc;
+        use std::thread;
+        use std::time::Duration;
+
+        let transport = SharedWriter::new(VecWriter::<64>::new());
+        let logs = Endpoint::new(DEFAULT_LOG_ADDRESS, &transport);
+        let rpc = Endpoint::new(DEFAULT_RPC_ADDRESS, &transport);
+
+        // An RPC frame is sent while a log frame is being written.
+        let (writing_tx, writing_rx) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                transport.with_blocking(|writer| {
+                    writing_tx.send(()).unwrap();
+                    thread::sleep(Duration::from_millis(20));
+                    write_ui_frame(writer, logs.address(), b"log")
+                })
+            });
+            writing_rx.recv().unwrap();
+            rpc.write_frame(b"rpc").unwrap();
+        });
+
+        let mut expected = [0u8; 64];
+        let mut len = encode_ui_frame(&mut expected, DEFAULT_LOG_ADDRESS, b"log").unwrap();
+        len += encode_ui_frame(&mut expected[len..], DEFAULT_RPC_ADDRESS, b"rpc").unwrap();
+        transport
+            .with(|writer| assert_eq!(writer.as_slice(), &expected[..len]))
+            .unwrap();
+        assert_eq!(transport.dropped(), 0);
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_rpc::ChannelOutput;
+use pw_status::Result;
+use pw_stream::Write;
+
+use crate::{max_safe_payload_size, write_ui_frame, Endpoint};
+
+/// A [`ChannelOutput`] which sends each RPC packet as an unnumbered
+/// information (UI) frame, matching the C++ `pw::hdlc::RpcChannelOutput`.
+///
+/// Frames are sent to [`crate::DEFAULT_RPC_ADDRESS`] by `pw_console` and the
+/// C++ and Python tools, so RPC is usually sent to that address.
+///
+/// ```
+/// use pw_hdlc::{Decoder, RpcChannelOutput, DEFAULT_RPC_ADDRESS};
+/// use pw_rpc::{Channel, Packet, PacketType};
+/// use pw_stream::VecWriter;
+///
+/// let mut output = RpcChannelOutput::new(VecWriter::<64>::new(), DEFAULT_RPC_ADDRESS);
+/// let mut channel = Channel::new(1, &mut output);
+/// let mut buffer = [0u8; 32];
+/// channel
+///     .send(&Packet::new(PacketType::Response, 1, 2, 3, 4), &mut buffer)
+///     .unwrap();
+///
+/// let mut data = output.get_ref().as_slice();
+/// let mut decoder = Decoder::new([0u8; 64]);
+/// let frame = decoder.next_frame(&mut data).unwrap().unwrap();
+/// assert_eq!(frame.address(), DEFAULT_RPC_ADDRESS);
+/// assert_eq!(Packet::decode(frame.payload()).unwrap().call_id, 4);
+/// ```
+pub struct RpcChannelOutput<W: Write> {
+    writer: W,
+    address: u64,
+    max_frame_size: Option<usize>,
+}
+
+impl<W: Write> RpcChannelOutput<W> {
+    /// Creates an output which writes frames to `address` to `writer`.
+    pub const fn new(writer: W, address: u64) -> Self {
+        Self {
+            writer,
+            address,
+            max_frame_size: None,
+        }
+    }
+
+    /// Creates an output which writes frames of up to `max_frame_size` bytes
+    /// to `address` to `writer`, matching the C++
+    /// `pw::hdlc::FixedMtuChannelOutput`.
+    ///
+    /// Packets larger than the [`max_safe_payload_size()`] of
+    /// `max_frame_size` are rejected by the channel.
+    pub const fn with_max_frame_size(writer: W, address: u64, max_frame_size: usize) -> Self {
+        Self {
+            writer,
+            address,
+            max_frame_size: Some(max_frame_size),
+        }
+    }
+
+    /// Returns the address frames are sent to.
+    pub const fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// Returns a reference to the writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Returns a mutable reference to the writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Consumes the output and returns the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> ChannelOutput for RpcChannelOutput<W> {
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        write_ui_frame(&mut self.writer, self.address, packet)?;
+        self.writer.flush()
+    }
+
+    fn maximum_transmission_unit(&self) -> Option<usize> {
+        self.max_frame_size.map(max_safe_payload_size)
+    }
+}
+
+/// Sends RPC packets as frames tagged with the endpoint's address, so that
+/// RPC can share a transport with other protocols such as logs.
+impl<W: Write> ChannelOutput for Endpoint<'_, W> {
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        self.write_frame(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use pw_rpc::{Channel, Packet, PacketType};
+    use pw_status::Error;
+    use pw_stream::{SharedWriter, VecWriter};
+
+    use super::*;
+    use crate::{max_encoded_frame_size, Decoder, DEFAULT_RPC_ADDRESS};
+
+    // Decodes each frame in `data` to its address and RPC packet's call ID.
+    fn decode(mut data: &[u8]) -> Vec<(u64, u32)> {
+        let mut decoder = Decoder::new([0u8; 128]);
+        let mut packets = Vec::new();
+        while let Some(frame) = decoder.next_frame(&mut data) {
+            let frame = frame.unwrap();
+            let packet = Packet::decode(frame.payload()).unwrap();
+            packets.push((frame.address(), packet.call_id));
+        }
+        packets
+    }
+
+    #[test]
+    fn packets_are_framed() {
+        let mut output = RpcChannelOutput::new(VecWriter::<256>::new(), DEFAULT_RPC_ADDRESS);
+        assert_eq!(output.address(), DEFAULT_RPC_ADDRESS);
+        assert_eq!(output.maximum_transmission_unit(), None);
+        let mut channel = Channel::new(1, &mut output);
+        let mut buffer = [0u8; 64];
+        for call_id in [1, 0x7e] {
+            let packet = Packet::new(PacketType::Request, 1, 2, 3, call_id);
+            channel.send(&packet, &mut buffer).unwrap();
+        }
+
+        assert_eq!(
+            decode(output.get_ref().as_slice()),
+            [(DEFAULT_RPC_ADDRESS, 1), (DEFAULT_RPC_ADDRESS, 0x7e)]
+        );
+    }
+
+    #[test]
+    fn packets_larger_than_safe_payload_are_rejected() {
+        let max_frame_size = max_encoded_frame_size(32);
+        let mut output = RpcChannelOutput::with_max_frame_size(
+            VecWriter::<256>::new(),
+            DEFAULT_RPC_ADDRESS,
+            max_frame_size,
+        );
+        assert_eq!(output.maximum_transmission_unit(), Some(32));
+        let mut channel = Channel::new(1, &mut output);
+        let mut buffer = [0u8; 64];
+        let packet = Packet::new(PacketType::Response, 1, 2, 3, 4);
+        channel.send(&packet, &mut buffer).unwrap();
+        assert!(
+            channel.send(&packet.with_payload(&[0u8; 32]), &mut buffer)
+                == Err(Error::ResourceExhausted)
+        );
+        assert_eq!(
+            decode(output.into_inner().as_slice()),
+            [(DEFAULT_RPC_ADDRESS, 4)]
+        );
+    }
+
+    #[test]
+    fn endpoint_sends_packets_to_its_address() {
+        let transport = SharedWriter::new(VecWriter::<256>::new());
+        let mut endpoint = Endpoint::new(7, &transport);
+        let mut channel = Channel::new(1, &mut endpoint);
+        let mut buffer = [0u8; 64];
+        channel
+            .send(&Packet::new(PacketType::Response, 1, 2, 3, 9), &mut buffer)
+            .unwrap();
+
+        let data = transport.with(|writer| writer.as_slice().to_vec()).unwrap();
+        assert_eq!(decode(&data), [(7, 9)]);
+    }
+}
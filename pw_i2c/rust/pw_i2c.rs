@@ -0,0 +1,837 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_i2c` defines the interface between I2C bus drivers and the drivers of
+//! the devices on the bus.
+//!
+//! Bus drivers implement [`Initiator`], which performs write, read, and
+//! combined write-then-read transactions with deadlines from [`pw_chrono`].
+//! Device drivers use a [`Device`], which binds an initiator to one
+//! [`Address`], or a [`RegisterDevice`] for the common case of devices which
+//! are accessed as a set of registers.
+//!
+//! All errors are reported as [`pw_status::Error`]s.  Bus drivers which
+//! implement [`embedded_hal::i2c::I2c`] can be used through an
+//! [`EmbeddedHalInitiator`].
+//!
+//! ```
+//! use pw_bytes::Endian;
+//! use pw_chrono::Duration;
+//! use pw_i2c::{Address, Initiator, RegisterAddressSize, RegisterDevice};
+//! # use pw_status::Result;
+//! # struct I2c1;
+//! # impl Initiator for I2c1 {
+//! #     fn write_read_until(
+//! #         &mut self,
+//! #         _address: Address,
+//! #         _tx: &[u8],
+//! #         rx: &mut [u8],
+//! #         _deadline: pw_chrono::Instant,
+//! #     ) -> Result<()> {
+//! #         rx.fill(0x5a);
+//! #         Ok(())
+//! #     }
+//! # }
+//! # struct Clock;
+//! # impl pw_chrono::SystemClock for Clock {
+//! #     fn ticks_per_second(&self) -> u64 { 1000 }
+//! #     fn now_ticks(&self) -> u64 { 0 }
+//! # }
+//! # pw_chrono::set_system_clock(&Clock);
+//!
+//! const TIMEOUT: Duration = Duration::from_millis(10);
+//! const WHO_AM_I: u32 = 0x0f;
+//!
+//! let mut sensor = RegisterDevice::new(
+//!     I2c1,
+//!     Address::seven_bit(0x6a),
+//!     Endian::Little,
+//!     RegisterAddressSize::OneByte,
+//! );
+//! sensor.probe_for(TIMEOUT)?;
+//! assert_eq!(sensor.read_register8(WHO_AM_I, TIMEOUT)?, 0x5a);
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_bytes::Endian;
+use pw_chrono::{Duration, Instant};
+use pw_status::{Error, Result};
+
+/// The address of a device on an I2C bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Address {
+    value: u16,
+    ten_bit: bool,
+}
+
+impl Address {
+    /// The largest 7-bit address.
+    pub const MAX_SEVEN_BIT: u8 = 0x7f;
+
+    /// The largest 10-bit address.
+    pub const MAX_TEN_BIT: u16 = 0x3ff;
+
+    /// Creates a 7-bit address.
+    ///
+    /// # Panics
+    /// Panics if `address` is larger than [`Address::MAX_SEVEN_BIT`].  In a
+    /// `const` this is a compile time error.
+    pub const fn seven_bit(address: u8) -> Self {
+        assert!(address <= Self::MAX_SEVEN_BIT, "Address is not 7 bits");
+        Self {
+            value: address as u16,
+            ten_bit: false,
+        }
+    }
+
+    /// Creates a 10-bit address.
+    ///
+    /// # Panics
+    /// Panics if `address` is larger than [`Address::MAX_TEN_BIT`].  In a
+    /// `const` this is a compile time error.
+    pub const fn ten_bit(address: u16) -> Self {
+        assert!(address <= Self::MAX_TEN_BIT, "Address is not 10 bits");
+        Self {
+            value: address,
+            ten_bit: true,
+        }
+    }
+
+    /// Returns the address.
+    pub const fn value(&self) -> u16 {
+        self.value
+    }
+
+    /// Returns whether this is a 10-bit address.
+    pub const fn is_ten_bit(&self) -> bool {
+        self.ten_bit
+    }
+}
+
+/// An I2C bus driver, which other documentation may call a controller.
+///
+/// Each method performs one transaction: a write, a read, or a write
+/// followed by a read with a repeated start and no stop in between.
+/// Transactions fail with [`Error::DeadlineExceeded`] if they can not acquire
+/// the bus and complete before the [`pw_chrono`] system clock reaches the
+/// deadline.
+///
+/// Initiators are not required to support 10-bit addresses.  Devices may
+/// require sequences of transactions to be performed without others in
+/// between, which callers must arrange by owning the initiator or sharing it
+/// through a lock.
+pub trait Initiator {
+    /// Writes `tx` to the device at `address`, then reads enough bytes to fill
+    /// `rx`.  If `tx` is empty only the read is performed, and if `rx` is
+    /// empty only the write is performed.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `address` is not supported, or `tx` and
+    ///   `rx` are both empty.
+    /// - [`Error::DeadlineExceeded`] - The transaction did not complete before
+    ///   `deadline`.
+    /// - [`Error::Unavailable`] - The device did not acknowledge its address
+    ///   or the data.
+    /// - [`Error::FailedPrecondition`] - The bus is not initialized or
+    ///   enabled.
+    fn write_read_until(
+        &mut self,
+        address: Address,
+        tx: &[u8],
+        rx: &mut [u8],
+        deadline: Instant,
+    ) -> Result<()>;
+
+    /// Performs [`Initiator::write_read_until()`], blocking for at most
+    /// `timeout`.
+    fn write_read_for(
+        &mut self,
+        address: Address,
+        tx: &[u8],
+        rx: &mut [u8],
+        timeout: Duration,
+    ) -> Result<()> {
+        self.write_read_until(address, tx, rx, pw_chrono::now() + timeout)
+    }
+
+    /// Writes `tx` to the device at `address`, blocking for at most until
+    /// `deadline`.
+    fn write_until(&mut self, address: Address, tx: &[u8], deadline: Instant) -> Result<()> {
+        self.write_read_until(address, tx, &mut [], deadline)
+    }
+
+    /// Writes `tx` to the device at `address`, blocking for at most `timeout`.
+    fn write_for(&mut self, address: Address, tx: &[u8], timeout: Duration) -> Result<()> {
+        self.write_until(address, tx, pw_chrono::now() + timeout)
+    }
+
+    /// Fills `rx` from the device at `address`, blocking for at most until
+    /// `deadline`.
+    fn read_until(&mut self, address: Address, rx: &mut [u8], deadline: Instant) -> Result<()> {
+        self.write_read_until(address, &[], rx, deadline)
+    }
+
+    /// Fills `rx` from the device at `address`, blocking for at most
+    /// `timeout`.
+    fn read_for(&mut self, address: Address, rx: &mut [u8], timeout: Duration) -> Result<()> {
+        self.read_until(address, rx, pw_chrono::now() + timeout)
+    }
+
+    /// Checks whether a device acknowledges `address` by reading one byte
+    /// from it, blocking for at most `timeout`.
+    ///
+    /// # Errors
+    /// - [`Error::Unavailable`] - No device acknowledged `address`.
+    /// - [`Error::DeadlineExceeded`] - The probe did not complete in time.
+    fn probe_device_for(&mut self, address: Address, timeout: Duration) -> Result<()> {
+        self.read_for(address, &mut [0], timeout)
+    }
+}
+
+impl<I: Initiator + ?Sized> Initiator for &mut I {
+    fn write_read_until(
+        &mut self,
+        address: Address,
+        tx: &[u8],
+        rx: &mut [u8],
+        deadline: Instant,
+    ) -> Result<()> {
+        (**self).write_read_until(address, tx, rx, deadline)
+    }
+}
+
+/// A device on an I2C bus, which binds an [`Initiator`] to the device's
+/// [`Address`].
+///
+/// Each transaction is performed separately.  A `Device` which owns its
+/// initiator, or a mutable reference to it, also ensures that no other code
+/// performs transactions between its own.
+pub struct Device<I: Initiator> {
+    initiator: I,
+    address: Address,
+}
+
+impl<I: Initiator> Device<I> {
+    /// Creates a device at `address` on the bus driven by `initiator`.
+    pub const fn new(initiator: I, address: Address) -> Self {
+        Self { initiator, address }
+    }
+
+    /// Returns the device's address.
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Returns a reference to the initiator.
+    pub fn get_ref(&self) -> &I {
+        &self.initiator
+    }
+
+    /// Returns a mutable reference to the initiator.
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.initiator
+    }
+
+    /// Consumes the `Device` and returns the initiator.
+    pub fn into_inner(self) -> I {
+        self.initiator
+    }
+
+    /// Writes `tx` to the device then fills `rx`, blocking for at most
+    /// `timeout`.  See [`Initiator::write_read_until()`].
+    pub fn write_read_for(&mut self, tx: &[u8], rx: &mut [u8], timeout: Duration) -> Result<()> {
+        self.initiator.write_read_for(self.address, tx, rx, timeout)
+    }
+
+    /// Writes `tx` to the device, blocking for at most `timeout`.
+    pub fn write_for(&mut self, tx: &[u8], timeout: Duration) -> Result<()> {
+        self.initiator.write_for(self.address, tx, timeout)
+    }
+
+    /// Fills `rx` from the device, blocking for at most `timeout`.
+    pub fn read_for(&mut self, rx: &mut [u8], timeout: Duration) -> Result<()> {
+        self.initiator.read_for(self.address, rx, timeout)
+    }
+
+    /// Checks whether the device acknowledges its address, blocking for at
+    /// most `timeout`.  See [`Initiator::probe_device_for()`].
+    pub fn probe_for(&mut self, timeout: Duration) -> Result<()> {
+        self.initiator.probe_device_for(self.address, timeout)
+    }
+}
+
+/// The size of the register addresses of a [`RegisterDevice`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegisterAddressSize {
+    /// 8-bit register addresses.
+    OneByte = 1,
+    /// 16-bit register addresses.
+    TwoBytes = 2,
+    /// 32-bit register addresses.
+    FourBytes = 4,
+}
+
+impl RegisterAddressSize {
+    /// Returns the size in bytes.
+    pub const fn size_bytes(self) -> usize {
+        self as usize
+    }
+}
+
+// The longest write of a single register: a 4 byte register address followed
+// by 4 bytes of data.
+const MAX_REGISTER_WRITE_LEN: usize = 8;
+
+/// A [`Device`] which is accessed through registers.
+///
+/// Each access writes the address of the first register, in the device's
+/// register address size and byte order, followed by the data to write, or
+/// followed by a read of the registers' values.  Multi-byte register values
+/// are converted from and to the device's data byte order.
+///
+/// Register accesses fail with the errors of [`Initiator::write_read_until()`].
+pub struct RegisterDevice<I: Initiator> {
+    device: Device<I>,
+    register_address_size: RegisterAddressSize,
+    register_address_order: Endian,
+    data_order: Endian,
+}
+
+macro_rules! register_accessors {
+    ($ty:ty, $write_registers:ident, $read_registers:ident, $write_register:ident, $read_register:ident) => {
+        #[doc = concat!(
+                    "Writes `data` to consecutive `", stringify!($ty), "` registers starting at ",
+                    "`register_address`, building the transaction in `buffer`.  See ",
+                    "[`RegisterDevice::write_registers()`]."
+                )]
+        pub fn $write_registers(
+            &mut self,
+            register_address: u32,
+            data: &[$ty],
+            buffer: &mut [u8],
+            timeout: Duration,
+        ) -> Result<()> {
+            const SIZE: usize = core::mem::size_of::<$ty>();
+            self.check_register_address(register_address)?;
+            let len = self.register_address_size.size_bytes() + data.len() * SIZE;
+            let buffer = buffer.get_mut(..len).ok_or(Error::OutOfRange)?;
+            let (address, values) = buffer.split_at_mut(self.register_address_size.size_bytes());
+            self.put_register_address(address, register_address);
+            for (value, dest) in data.iter().zip(values.chunks_exact_mut(SIZE)) {
+                dest.copy_from_slice(&match self.data_order {
+                    Endian::Little => value.to_le_bytes(),
+                    Endian::Big => value.to_be_bytes(),
+                });
+            }
+            self.device.write_for(buffer, timeout)
+        }
+
+        #[doc = concat!(
+                    "Fills `data` from consecutive `", stringify!($ty), "` registers starting at ",
+                    "`register_address`, blocking for at most `timeout`."
+                )]
+        pub fn $read_registers(
+            &mut self,
+            register_address: u32,
+            data: &mut [$ty],
+            timeout: Duration,
+        ) -> Result<()> {
+            // SAFETY: Every bit pattern is a valid integer, and `u8` has no
+            // alignment requirement.
+            let bytes = unsafe {
+                core::slice::from_raw_parts_mut(
+                    data.as_mut_ptr().cast::<u8>(),
+                    core::mem::size_of_val(data),
+                )
+            };
+            self.read_registers(register_address, bytes, timeout)?;
+            for value in data.iter_mut() {
+                *value = match self.data_order {
+                    Endian::Little => <$ty>::from_le(*value),
+                    Endian::Big => <$ty>::from_be(*value),
+                };
+            }
+            Ok(())
+        }
+
+        #[doc = concat!(
+                    "Writes a `", stringify!($ty), "` to the register at `register_address`, ",
+                    "blocking for at most `timeout`."
+                )]
+        pub fn $write_register(
+            &mut self,
+            register_address: u32,
+            value: $ty,
+            timeout: Duration,
+        ) -> Result<()> {
+            let mut buffer = [0u8; MAX_REGISTER_WRITE_LEN];
+            self.$write_registers(register_address, &[value], &mut buffer, timeout)
+        }
+
+        #[doc = concat!(
+                    "Reads a `", stringify!($ty), "` from the register at `register_address`, ",
+                    "blocking for at most `timeout`."
+                )]
+        pub fn $read_register(&mut self, register_address: u32, timeout: Duration) -> Result<$ty> {
+            let mut value = [0];
+            self.$read_registers(register_address, &mut value, timeout)?;
+            Ok(value[0])
+        }
+    };
+}
+
+impl<I: Initiator> RegisterDevice<I> {
+    /// Creates a register device at `address` on the bus driven by
+    /// `initiator`, whose register addresses and data both have the byte
+    /// order `order`.
+    pub const fn new(
+        initiator: I,
+        address: Address,
+        order: Endian,
+        register_address_size: RegisterAddressSize,
+    ) -> Self {
+        Self::with_orders(initiator, address, order, order, register_address_size)
+    }
+
+    /// Creates a register device whose register addresses and data have
+    /// different byte orders.
+    pub const fn with_orders(
+        initiator: I,
+        address: Address,
+        register_address_order: Endian,
+        data_order: Endian,
+        register_address_size: RegisterAddressSize,
+    ) -> Self {
+        Self {
+            device: Device::new(initiator, address),
+            register_address_size,
+            register_address_order,
+            data_order,
+        }
+    }
+
+    /// Returns the underlying device, for transactions which are not
+    /// register accesses.
+    pub fn device(&mut self) -> &mut Device<I> {
+        &mut self.device
+    }
+
+    /// Consumes the `RegisterDevice` and returns the initiator.
+    pub fn into_inner(self) -> I {
+        self.device.into_inner()
+    }
+
+    /// Checks whether the device acknowledges its address, blocking for at
+    /// most `timeout`.  See [`Initiator::probe_device_for()`].
+    pub fn probe_for(&mut self, timeout: Duration) -> Result<()> {
+        self.device.probe_for(timeout)
+    }
+
+    /// Writes `data` to consecutive registers starting at `register_address`,
+    /// building the transaction in `buffer`, and blocking for at most
+    /// `timeout`.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - `buffer` is shorter than the register
+    ///   address plus `data`.
+    /// - [`Error::InvalidArgument`] - `register_address` does not fit in the
+    ///   register address size.
+    pub fn write_registers(
+        &mut self,
+        register_address: u32,
+        data: &[u8],
+        buffer: &mut [u8],
+        timeout: Duration,
+    ) -> Result<()> {
+        self.write_registers8(register_address, data, buffer, timeout)
+    }
+
+    /// Fills `data` from consecutive registers starting at
+    /// `register_address`, blocking for at most `timeout`.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `register_address` does not fit in the
+    ///   register address size.
+    pub fn read_registers(
+        &mut self,
+        register_address: u32,
+        data: &mut [u8],
+        timeout: Duration,
+    ) -> Result<()> {
+        self.check_register_address(register_address)?;
+        let mut address = [0u8; 4];
+        let address = &mut address[..self.register_address_size.size_bytes()];
+        self.put_register_address(address, register_address);
+        self.device.write_read_for(address, data, timeout)
+    }
+
+    register_accessors!(
+        u8,
+        write_registers8,
+        read_registers8,
+        write_register8,
+        read_register8
+    );
+    register_accessors!(
+        u16,
+        write_registers16,
+        read_registers16,
+        write_register16,
+        read_register16
+    );
+    register_accessors!(
+        u32,
+        write_registers32,
+        read_registers32,
+        write_register32,
+        read_register32
+    );
+
+    fn check_register_address(&self, register_address: u32) -> Result<()> {
+        match self.register_address_size {
+            RegisterAddressSize::OneByte if register_address > u8::MAX.into() => {
+                Err(Error::InvalidArgument)
+            }
+            RegisterAddressSize::TwoBytes if register_address > u16::MAX.into() => {
+                Err(Error::InvalidArgument)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Writes the register address to `dest`, which is the register address
+    // size.  The address must have been checked.
+    fn put_register_address(&self, dest: &mut [u8], register_address: u32) {
+        let bytes = match self.register_address_order {
+            Endian::Little => register_address.to_le_bytes(),
+            Endian::Big => register_address.to_be_bytes(),
+        };
+        let len = dest.len();
+        match self.register_address_order {
+            Endian::Little => dest.copy_from_slice(&bytes[..len]),
+            Endian::Big => dest.copy_from_slice(&bytes[4 - len..]),
+        }
+    }
+}
+
+/// Adapts a bus driver which implements [`embedded_hal::i2c::I2c`] to
+/// [`Initiator`].
+///
+/// `embedded-hal` transactions do not have deadlines, so the deadline is only
+/// checked before a transaction starts.  Bus errors are converted to
+/// [`Error`]s:
+/// - A missing acknowledgement is [`Error::Unavailable`].
+/// - Lost arbitration is [`Error::Aborted`].
+/// - A bus error is [`Error::DataLoss`].
+/// - An overrun is [`Error::ResourceExhausted`].
+/// - Other errors are [`Error::Unknown`].
+///
+/// Only 7-bit addresses are supported.
+pub struct EmbeddedHalInitiator<T: embedded_hal::i2c::I2c> {
+    i2c: T,
+}
+
+impl<T: embedded_hal::i2c::I2c> EmbeddedHalInitiator<T> {
+    /// Creates an initiator which performs transactions with `i2c`.
+    pub const fn new(i2c: T) -> Self {
+        Self { i2c }
+    }
+
+    /// Returns a reference to the bus driver.
+    pub fn get_ref(&self) -> &T {
+        &self.i2c
+    }
+
+    /// Returns a mutable reference to the bus driver.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.i2c
+    }
+
+    /// Consumes the `EmbeddedHalInitiator` and returns the bus driver.
+    pub fn into_inner(self) -> T {
+        self.i2c
+    }
+}
+
+fn error_from_embedded_hal(error: impl embedded_hal::i2c::Error) -> Error {
+    use embedded_hal::i2c::ErrorKind;
+    match error.kind() {
+        ErrorKind::NoAcknowledge(_) => Error::Unavailable,
+        ErrorKind::ArbitrationLoss => Error::Aborted,
+        ErrorKind::Bus => Error::DataLoss,
+        ErrorKind::Overrun => Error::ResourceExhausted,
+        _ => Error::Unknown,
+    }
+}
+
+impl<T: embedded_hal::i2c::I2c> Initiator for EmbeddedHalInitiator<T> {
+    fn write_read_until(
+        &mut self,
+        address: Address,
+        tx: &[u8],
+        rx: &mut [u8],
+        deadline: Instant,
+    ) -> Result<()> {
+        if address.is_ten_bit() || (tx.is_empty() && rx.is_empty()) {
+            return Err(Error::InvalidArgument);
+        }
+        if deadline.has_passed() {
+            return Err(Error::DeadlineExceeded);
+        }
+        // 7-bit addresses always fit in a `u8`.
+        let address = address.value() as u8;
+        let result = if tx.is_empty() {
+            self.i2c.read(address, rx)
+        } else if rx.is_empty() {
+            self.i2c.write(address, tx)
+        } else {
+            self.i2c.write_read(address, tx, rx)
+        };
+        result.map_err(error_from_embedded_hal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::vec::Vec;
+
+    use pw_chrono::SystemClock;
+
+    use super::*;
+
+    // A clock which advances by a millisecond each time it is read.
+    struct TestClock(AtomicU64);
+
+    impl SystemClock for TestClock {
+        fn ticks_per_second(&self) -> u64 {
+            1000
+        }
+
+        fn now_ticks(&self) -> u64 {
+            self.0.fetch_add(1, Ordering::Relaxed)
+        }
+    }
+
+    static CLOCK: TestClock = TestClock(AtomicU64::new(0));
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+    const ADDRESS: Address = Address::seven_bit(0x42);
+
+    // A bus with one device at `ADDRESS`, which records the data written to
+    // it and responds to reads with `rx`.
+    #[derive(Default)]
+    struct FakeInitiator {
+        tx: Vec<u8>,
+        rx: Vec<u8>,
+    }
+
+    impl Initiator for FakeInitiator {
+        fn write_read_until(
+            &mut self,
+            address: Address,
+            tx: &[u8],
+            rx: &mut [u8],
+            deadline: Instant,
+        ) -> Result<()> {
+            if deadline.has_passed() {
+                return Err(Error::DeadlineExceeded);
+            }
+            if address != ADDRESS {
+                return Err(Error::Unavailable);
+            }
+            self.tx = tx.to_vec();
+            rx.copy_from_slice(&self.rx[..rx.len()]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn addresses_are_range_checked() {
+        assert_eq!(Address::seven_bit(0x7f).value(), 0x7f);
+        assert!(!Address::seven_bit(0x7f).is_ten_bit());
+        assert_eq!(Address::ten_bit(0x3ff).value(), 0x3ff);
+        assert!(Address::ten_bit(0x3ff).is_ten_bit());
+        assert!(std::panic::catch_unwind(|| Address::seven_bit(0x80)).is_err());
+        assert!(std::panic::catch_unwind(|| Address::ten_bit(0x400)).is_err());
+    }
+
+    #[test]
+    fn probe_reports_missing_devices() {
+        pw_chrono::set_system_clock(&CLOCK);
+        let mut initiator = FakeInitiator {
+            rx: std::vec![0],
+            ..Default::default()
+        };
+        assert_eq!(initiator.probe_device_for(ADDRESS, TIMEOUT), Ok(()));
+        assert_eq!(
+            initiator.probe_device_for(Address::seven_bit(0x10), TIMEOUT),
+            Err(Error::Unavailable)
+        );
+        assert_eq!(
+            initiator.probe_device_for(ADDRESS, Duration::from_millis(0)),
+            Err(Error::DeadlineExceeded)
+        );
+    }
+
+    #[test]
+    fn device_performs_transactions_at_its_address() {
+        pw_chrono::set_system_clock(&CLOCK);
+        let mut initiator = FakeInitiator {
+            rx: std::vec![1, 2, 3],
+            ..Default::default()
+        };
+        let mut device = Device::new(&mut initiator, ADDRESS);
+        let mut rx = [0u8; 2];
+        device.write_read_for(&[9], &mut rx, TIMEOUT).unwrap();
+        assert_eq!(rx, [1, 2]);
+        assert_eq!(device.get_ref().tx, [9]);
+
+        let mut other = Device::new(&mut initiator, Address::seven_bit(0x10));
+        assert_eq!(other.write_for(&[1], TIMEOUT), Err(Error::Unavailable));
+    }
+
+    #[test]
+    fn register_addresses_have_configured_size_and_order() {
+        pw_chrono::set_system_clock(&CLOCK);
+        let mut device = RegisterDevice::with_orders(
+            FakeInitiator::default(),
+            ADDRESS,
+            Endian::Big,
+            Endian::Little,
+            RegisterAddressSize::TwoBytes,
+        );
+        device.write_register16(0x1234, 0xabcd, TIMEOUT).unwrap();
+        assert_eq!(device.device().get_ref().tx, [0x12, 0x34, 0xcd, 0xab]);
+        assert_eq!(
+            device.write_register8(0x1_0000, 0, TIMEOUT),
+            Err(Error::InvalidArgument)
+        );
+
+        let mut device = RegisterDevice::new(
+            FakeInitiator::default(),
+            ADDRESS,
+            Endian::Little,
+            RegisterAddressSize::FourBytes,
+        );
+        device
+            .write_register32(0x1234, 0x0102_0304, TIMEOUT)
+            .unwrap();
+        assert_eq!(
+            device.device().get_ref().tx,
+            [0x34, 0x12, 0, 0, 0x04, 0x03, 0x02, 0x01]
+        );
+    }
+
+    #[test]
+    fn registers_are_read_in_data_order() {
+        pw_chrono::set_system_clock(&CLOCK);
+        let mut device = RegisterDevice::new(
+            FakeInitiator {
+                rx: std::vec![0x01, 0x02, 0x03, 0x04],
+                ..Default::default()
+            },
+            ADDRESS,
+            Endian::Big,
+            RegisterAddressSize::OneByte,
+        );
+        assert_eq!(device.read_register8(0x0f, TIMEOUT), Ok(0x01));
+        assert_eq!(device.device().get_ref().tx, [0x0f]);
+        assert_eq!(device.read_register32(0x10, TIMEOUT), Ok(0x0102_0304));
+
+        let mut values = [0u16; 2];
+        device.read_registers16(0x20, &mut values, TIMEOUT).unwrap();
+        assert_eq!(values, [0x0102, 0x0304]);
+    }
+
+    #[test]
+    fn bulk_writes_need_large_enough_buffer() {
+        pw_chrono::set_system_clock(&CLOCK);
+        let mut device = RegisterDevice::new(
+            FakeInitiator::default(),
+            ADDRESS,
+            Endian::Big,
+            RegisterAddressSize::OneByte,
+        );
+        let mut buffer = [0u8; 5];
+        device
+            .write_registers16(0x20, &[0x0102, 0x0304], &mut buffer, TIMEOUT)
+            .unwrap();
+        assert_eq!(device.device().get_ref().tx, [0x20, 1, 2, 3, 4]);
+        assert_eq!(
+            device.write_registers(0x20, &[0; 5], &mut buffer, TIMEOUT),
+            Err(Error::OutOfRange)
+        );
+    }
+
+    // An `embedded-hal` bus with no devices which records the operations
+    // performed.
+    #[derive(Default)]
+    struct HalBus {
+        operations: Vec<&'static str>,
+    }
+
+    #[derive(Debug)]
+    struct HalError;
+
+    impl embedded_hal::i2c::Error for HalError {
+        fn kind(&self) -> embedded_hal::i2c::ErrorKind {
+            embedded_hal::i2c::ErrorKind::NoAcknowledge(
+                embedded_hal::i2c::NoAcknowledgeSource::Address,
+            )
+        }
+    }
+
+    impl embedded_hal::i2c::ErrorType for HalBus {
+        type Error = HalError;
+    }
+
+    impl embedded_hal::i2c::I2c for HalBus {
+        fn transaction(
+            &mut self,
+            _address: u8,
+            operations: &mut [embedded_hal::i2c::Operation<'_>],
+        ) -> core::result::Result<(), HalError> {
+            for operation in operations {
+                self.operations.push(match operation {
+                    embedded_hal::i2c::Operation::Read(_) => "read",
+                    embedded_hal::i2c::Operation::Write(_) => "write",
+                });
+            }
+            Err(HalError)
+        }
+    }
+
+    #[test]
+    fn embedded_hal_errors_are_converted() {
+        pw_chrono::set_system_clock(&CLOCK);
+        let mut initiator = EmbeddedHalInitiator::new(HalBus::default());
+        assert_eq!(
+            initiator.write_read_for(ADDRESS, &[1], &mut [0], TIMEOUT),
+            Err(Error::Unavailable)
+        );
+        assert_eq!(initiator.get_ref().operations, ["write", "read"]);
+        assert_eq!(
+            initiator.probe_device_for(Address::ten_bit(0x100), TIMEOUT),
+            Err(Error::InvalidArgument)
+        );
+        assert_eq!(
+            initiator.write_for(ADDRESS, &[1], Duration::from_millis(0)),
+            Err(Error::DeadlineExceeded)
+        );
+    }
+}
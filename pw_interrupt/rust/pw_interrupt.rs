@@ -0,0 +1,139 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_interrupt` masks and restores interrupts and reports whether code is
+//! running in an interrupt handler.
+//!
+//! Data shared with interrupt handlers is protected by disabling interrupts
+//! while it is accessed.  [`free()`] passes a
+//! [`critical_section::CriticalSection`] token to its closure, so the data
+//! may be held in a [`critical_section::Mutex`]:
+//!
+//! ```
+//! use core::cell::Cell;
+//! use critical_section::Mutex;
+//!
+//! static COUNT: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+//!
+//! fn increment() -> u32 {
+//!     pw_interrupt::free(|cs| {
+//!         let count = COUNT.borrow(cs);
+//!         count.set(count.get() + 1);
+//!         count.get()
+//!     })
+//! }
+//!
+//! assert_eq!(increment(), 1);
+//! assert!(!pw_interrupt::in_interrupt_context());
+//! ```
+//!
+//! `pw_interrupt` is a facade: interrupts are masked by the backend selected
+//! with the `//pw_interrupt/rust:pw_interrupt_backend` label flag.  The
+//! Cortex-M backend uses `PRIMASK` and `IPSR`.  The host backend, which is
+//! the default, fakes masking with a global lock and can simulate interrupt
+//! handlers in tests.  Other backends implement the trait in
+//! `pw_interrupt_backend_api`.
+#![no_std]
+#![deny(missing_docs)]
+
+use core::marker::PhantomData;
+
+use critical_section::CriticalSection;
+use pw_interrupt_backend_api::Interrupts;
+
+type Backend = pw_interrupt_backend::Interrupts;
+
+/// Returns `true` if the calling code is handling an interrupt request (IRQ)
+/// or non-maskable interrupt (NMI).
+pub fn in_interrupt_context() -> bool {
+    Backend::in_interrupt_context()
+}
+
+/// Masks interrupts until the returned guard is dropped.
+///
+/// Interrupts may be disabled again while they are disabled; they are only
+/// enabled once the outermost guard is dropped, and only if they were enabled
+/// when it was created.
+pub fn disable() -> InterruptGuard {
+    InterruptGuard {
+        were_enabled: Backend::disable(),
+        _not_send: PhantomData,
+    }
+}
+
+/// Calls `f` with interrupts masked, passing a token for accessing a
+/// [`critical_section::Mutex`].
+pub fn free<R>(f: impl FnOnce(CriticalSection<'_>) -> R) -> R {
+    let guard = disable();
+    f(guard.critical_section())
+}
+
+/// Keeps interrupts masked, restoring them when dropped.
+///
+/// Returned by [`disable()`].
+#[must_use = "interrupts are restored when the guard is dropped"]
+pub struct InterruptGuard {
+    were_enabled: bool,
+    // Interrupts must be restored on the thread which disabled them.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl InterruptGuard {
+    /// Returns a token for accessing a [`critical_section::Mutex`] while
+    /// interrupts are masked.
+    pub fn critical_section(&self) -> CriticalSection<'_> {
+        // Safety: Interrupts are masked for the lifetime of the token.
+        unsafe { CriticalSection::new() }
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        // Safety: Guards are dropped in the reverse order they were created,
+        // on the thread which created them, since they are not `Send`.
+        unsafe { Backend::restore(self.were_enabled) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::Cell;
+
+    use critical_section::Mutex;
+    use pw_interrupt_backend::simulate_interrupt;
+
+    use super::*;
+
+    #[test]
+    fn interrupt_context_is_reported() {
+        assert!(!in_interrupt_context());
+        simulate_interrupt(|| assert!(in_interrupt_context()));
+    }
+
+    #[test]
+    fn guards_nest() {
+        static VALUE: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+        let outer = disable();
+        {
+            let inner = disable();
+            VALUE.borrow(inner.critical_section()).set(1);
+        }
+        assert_eq!(VALUE.borrow(outer.critical_section()).get(), 1);
+        drop(outer);
+        assert_eq!(free(|cs| VALUE.borrow(cs).get()), 1);
+    }
+}
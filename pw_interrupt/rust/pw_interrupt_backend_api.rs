@@ -0,0 +1,40 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! The interface between the `pw_interrupt` facade and its backends.
+//!
+//! A backend is a crate named `pw_interrupt_backend` which exports an
+//! `Interrupts` type implementing the trait below.  Backends are provided for
+//! Cortex-M cores and for hosts, where interrupts are faked.
+#![no_std]
+#![deny(missing_docs)]
+
+/// Masks and restores interrupts and reports the current execution context.
+pub trait Interrupts {
+    /// Returns `true` if the calling code is handling an interrupt request
+    /// (IRQ) or non-maskable interrupt (NMI).
+    fn in_interrupt_context() -> bool;
+
+    /// Masks interrupts and returns whether they were enabled, to be passed to
+    /// [`Interrupts::restore()`].
+    fn disable() -> bool;
+
+    /// Enables interrupts again if they were enabled before the matching
+    /// [`Interrupts::disable()`].
+    ///
+    /// # Safety
+    /// Calls must be nested within, and the reverse order of, calls to
+    /// [`Interrupts::disable()`] on the same thread of execution.
+    unsafe fn restore(were_enabled: bool);
+}
@@ -0,0 +1,47 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_interrupt` backend for ARM Cortex-M cores.
+//!
+//! Interrupts are masked with `PRIMASK`, which masks every exception with a
+//! configurable priority, and the interrupt context is read from `IPSR`,
+//! which is zero in thread mode.
+#![no_std]
+#![deny(missing_docs)]
+
+use cortex_m::peripheral::scb::VectActive;
+use cortex_m::peripheral::SCB;
+use cortex_m::register::primask;
+
+/// Implements `pw_interrupt_backend_api::Interrupts` for Cortex-M cores.
+pub struct Interrupts;
+
+impl pw_interrupt_backend_api::Interrupts for Interrupts {
+    fn in_interrupt_context() -> bool {
+        SCB::vect_active() != VectActive::ThreadMode
+    }
+
+    fn disable() -> bool {
+        let were_enabled = primask::read().is_active();
+        cortex_m::interrupt::disable();
+        were_enabled
+    }
+
+    unsafe fn restore(were_enabled: bool) {
+        if were_enabled {
+            // Safety: The caller restores the state saved by `disable()`.
+            unsafe { cortex_m::interrupt::enable() };
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_interrupt` backend for hosts, which have no interrupts to mask.
+//!
+//! Disabling interrupts acquires a global lock instead, so code which relies
+//! on masking interrupts for mutual exclusion is also correct when it is
+//! called from several threads in host tests.  Interrupts are "disabled" for
+//! one thread at a time, and may be disabled again by the same thread.
+//!
+//! Interrupt handlers are simulated by running code with
+//! [`simulate_interrupt()`], which [`in_interrupt_context()`] reports as
+//! interrupt context:
+//!
+//! ```
+//! use pw_interrupt_backend::{in_interrupt_context, simulate_interrupt};
+//!
+//! assert!(!in_interrupt_context());
+//! simulate_interrupt(|| assert!(in_interrupt_context()));
+//! ```
+//!
+//! [`in_interrupt_context()`]: pw_interrupt_backend_api::Interrupts::in_interrupt_context
+//!
+//! *Note*: This module requires `std`.
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Whether a thread has interrupts disabled.
+static LOCKED: AtomicBool = AtomicBool::new(false);
+
+std::thread_local! {
+    static DISABLED: Cell<bool> = const { Cell::new(false) };
+    static INTERRUPT_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Runs `handler` as if it handled an interrupt.
+///
+/// Interrupt handlers may be simulated within other simulated interrupt
+/// handlers, like nested interrupts.
+pub fn simulate_interrupt<R>(handler: impl FnOnce() -> R) -> R {
+    // Leaves interrupt context even if `handler` panics.
+    struct Exit;
+    impl Drop for Exit {
+        fn drop(&mut self) {
+            INTERRUPT_DEPTH.with(|depth| depth.set(depth.get() - 1));
+        }
+    }
+
+    INTERRUPT_DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let _exit = Exit;
+    handler()
+}
+
+/// Returns `true` within [`simulate_interrupt()`].
+pub fn in_interrupt_context() -> bool {
+    INTERRUPT_DEPTH.with(|depth| depth.get() > 0)
+}
+
+/// Implements `pw_interrupt_backend_api::Interrupts` for hosts.
+pub struct Interrupts;
+
+impl pw_interrupt_backend_api::Interrupts for Interrupts {
+    fn in_interrupt_context() -> bool {
+        in_interrupt_context()
+    }
+
+    fn disable() -> bool {
+        if DISABLED.with(Cell::get) {
+            return false;
+        }
+        while LOCKED
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            std::thread::yield_now();
+        }
+        DISABLED.with(|disabled| disabled.set(true));
+        true
+    }
+
+    unsafe fn restore(were_enabled: bool) {
+        if were_enabled {
+            DISABLED.with(|disabled| disabled.set(false));
+            LOCKED.store(false, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use pw_interrupt_backend_api::Interrupts as _;
+
+    use super::*;
+
+    #[test]
+    fn simulated_interrupts_nest() {
+        simulate_interrupt(|| {
+            simulate_interrupt(|| assert!(in_interrupt_context()));
+            assert!(in_interrupt_context());
+        });
+        assert!(!in_interrupt_context());
+    }
+
+    #[test]
+    fn disabling_interrupts_is_reentrant() {
+        let outer = Interrupts::disable();
+        let inner = Interrupts::disable();
+        assert!(outer);
+        assert!(!inner);
+        unsafe {
+            Interrupts::restore(inner);
+            Interrupts::restore(outer);
+        }
+    }
+
+    #[test]
+    fn disabling_interrupts_excludes_other_threads() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let count = count.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..250 {
+                        let were_enabled = Interrupts::disable();
+                        // A non-atomic increment which is only correct with
+                        // mutual exclusion.
+                        let value = count.load(Ordering::Relaxed);
+                        std::thread::yield_now();
+                        count.store(value + 1, Ordering::Relaxed);
+                        unsafe { Interrupts::restore(were_enabled) };
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(count.load(Ordering::Relaxed), 1000);
+    }
+}
@@ -0,0 +1,527 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_status::{Error, Result};
+
+use crate::entry_cache::{EntryState, KeyDescriptor};
+use crate::flash::FlashPartition;
+use crate::format::{ChecksumState, EntryFormat, EntryHeader};
+use crate::{Address, MAX_FLASH_ALIGNMENT, MAX_KEY_LENGTH};
+
+// Entries are aligned to at least the size of their header.
+pub(crate) const MIN_ALIGNMENT_BYTES: usize = EntryHeader::SIZE;
+
+// The value size of deleted entries (tombstones).
+const DELETED_VALUE_SIZE: u16 = 0xffff;
+
+pub(crate) const fn align_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+// Returns the alignment of entries written to a partition with writes aligned
+// to `partition_alignment` bytes.
+pub(crate) const fn entry_alignment(partition_alignment: usize) -> usize {
+    align_up(partition_alignment, MIN_ALIGNMENT_BYTES)
+}
+
+const fn alignment_units(alignment_bytes: usize) -> u8 {
+    (alignment_bytes / MIN_ALIGNMENT_BYTES - 1) as u8
+}
+
+// Returns the size of an entry on flash, including padding.
+pub(crate) const fn entry_size(
+    partition_alignment: usize,
+    key_length: usize,
+    value_size: usize,
+) -> usize {
+    align_up(
+        EntryHeader::SIZE + key_length + value_size,
+        entry_alignment(partition_alignment),
+    )
+}
+
+// A key-value entry stored in flash:
+//
+//   header | key | value | zero padding to the entry's alignment
+//
+// `Entry` holds the header and location of an entry; its key and value are
+// read from flash as needed.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Entry {
+    address: Address,
+    format: EntryFormat,
+    header: EntryHeader,
+}
+
+impl Entry {
+    // Reads the header of the entry at `address`.
+    //
+    // Returns `NotFound` if the memory is erased and `DataLoss` if the header
+    // is not a valid header in one of `formats`.
+    pub(crate) fn read(
+        partition: &mut impl FlashPartition,
+        address: Address,
+        formats: &[EntryFormat],
+    ) -> Result<Self> {
+        let mut bytes = [0u8; EntryHeader::SIZE];
+        partition.read(address, &mut bytes)?;
+
+        let erased = partition.erased_memory_content();
+        if bytes[..4].iter().all(|&b| b == erased) {
+            return Err(Error::NotFound);
+        }
+
+        let header = EntryHeader::from_bytes(&bytes);
+        if usize::from(header.key_length_bytes) > MAX_KEY_LENGTH {
+            return Err(Error::DataLoss);
+        }
+        let format = *formats
+            .iter()
+            .find(|format| format.magic == header.magic)
+            .ok_or(Error::DataLoss)?;
+
+        let entry = Self {
+            address,
+            format,
+            header,
+        };
+        if entry.alignment_bytes() > MAX_FLASH_ALIGNMENT {
+            return Err(Error::DataLoss);
+        }
+        Ok(entry)
+    }
+
+    // Reads the key of a `key_length` byte key of the entry at `address` into
+    // the start of `key`.
+    pub(crate) fn read_key(
+        partition: &mut impl FlashPartition,
+        address: Address,
+        key_length: usize,
+        key: &mut [u8; MAX_KEY_LENGTH],
+    ) -> Result<()> {
+        if key_length == 0 || key_length > MAX_KEY_LENGTH {
+            return Err(Error::DataLoss);
+        }
+        partition.read(
+            address + EntryHeader::SIZE as Address,
+            &mut key[..key_length],
+        )
+    }
+
+    // Creates an entry in the `format` for `key` and `value` to be written to
+    // `address`.  Deleted entries have no value.
+    pub(crate) fn new(
+        partition_alignment: usize,
+        address: Address,
+        format: EntryFormat,
+        key: &str,
+        value: &[u8],
+        state: EntryState,
+        transaction_id: u32,
+    ) -> Self {
+        let value_size_bytes = match state {
+            EntryState::Valid => value.len() as u16,
+            EntryState::Deleted => DELETED_VALUE_SIZE,
+        };
+        let mut entry = Self {
+            address,
+            format,
+            header: EntryHeader {
+                magic: format.magic,
+                checksum: 0,
+                alignment_units: alignment_units(entry_alignment(partition_alignment)),
+                key_length_bytes: key.len() as u8,
+                value_size_bytes,
+                transaction_id,
+            },
+        };
+        entry.header.checksum = entry.calculate_checksum(key.as_bytes(), value);
+        entry
+    }
+
+    pub(crate) fn address(&self) -> Address {
+        self.address
+    }
+
+    pub(crate) fn set_address(&mut self, address: Address) {
+        self.address = address;
+    }
+
+    pub(crate) fn magic(&self) -> u32 {
+        self.header.magic
+    }
+
+    pub(crate) fn transaction_id(&self) -> u32 {
+        self.header.transaction_id
+    }
+
+    pub(crate) fn key_length(&self) -> usize {
+        self.header.key_length_bytes.into()
+    }
+
+    pub(crate) fn deleted(&self) -> bool {
+        self.header.value_size_bytes == DELETED_VALUE_SIZE
+    }
+
+    pub(crate) fn value_size(&self) -> usize {
+        if self.deleted() {
+            0
+        } else {
+            self.header.value_size_bytes.into()
+        }
+    }
+
+    pub(crate) fn alignment_bytes(&self) -> usize {
+        (usize::from(self.header.alignment_units) + 1) * MIN_ALIGNMENT_BYTES
+    }
+
+    // The size of the header, key, and value, without padding.
+    pub(crate) fn content_size(&self) -> usize {
+        EntryHeader::SIZE + self.key_length() + self.value_size()
+    }
+
+    // The size of the entry on flash, including padding.
+    pub(crate) fn size(&self) -> usize {
+        align_up(self.content_size(), self.alignment_bytes())
+    }
+
+    pub(crate) fn next_address(&self) -> Address {
+        self.address + self.size() as Address
+    }
+
+    pub(crate) fn descriptor(&self, key_hash: u32) -> KeyDescriptor {
+        KeyDescriptor {
+            key_hash,
+            transaction_id: self.transaction_id(),
+            state: if self.deleted() {
+                EntryState::Deleted
+            } else {
+                EntryState::Valid
+            },
+        }
+    }
+
+    // Writes the entry to its address, returning the number of bytes written.
+    pub(crate) fn write(
+        &self,
+        partition: &mut impl FlashPartition,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<usize> {
+        let mut writer = AlignedWriter::new(self.address, self.alignment_bytes());
+        writer.write(partition, &self.header.to_bytes())?;
+        writer.write(partition, key)?;
+        writer.write(partition, value)?;
+        writer.flush(partition)
+    }
+
+    // Changes the format and transaction ID of the entry, recalculating its
+    // checksum from the key and value in flash.  The entry is not written.
+    pub(crate) fn update(
+        &mut self,
+        partition: &mut impl FlashPartition,
+        format: EntryFormat,
+        transaction_id: u32,
+        partition_alignment: usize,
+    ) -> Result<()> {
+        self.format = format;
+        self.header.magic = format.magic;
+        self.header.alignment_units = alignment_units(entry_alignment(partition_alignment));
+        self.header.transaction_id = transaction_id;
+        self.header.checksum = self.calculate_checksum_from_flash(partition)?;
+        Ok(())
+    }
+
+    // Writes the entry's header, followed by the key and value read from its
+    // current address, to `new_address`.  Returns the number of bytes written.
+    pub(crate) fn copy(
+        &self,
+        partition: &mut impl FlashPartition,
+        new_address: Address,
+    ) -> Result<usize> {
+        let mut writer = AlignedWriter::new(new_address, self.alignment_bytes());
+        writer.write(partition, &self.header.to_bytes())?;
+
+        let mut buffer = [0u8; 2 * MIN_ALIGNMENT_BYTES];
+        let mut address = self.address + EntryHeader::SIZE as Address;
+        let end = self.address + self.content_size() as Address;
+        while address < end {
+            let len = buffer.len().min((end - address) as usize);
+            partition.read(address, &mut buffer[..len])?;
+            writer.write(partition, &buffer[..len])?;
+            address += len as Address;
+        }
+        writer.flush(partition)
+    }
+
+    // Reads the value, starting `offset` bytes into it, into `buffer`.
+    //
+    // Returns `ResourceExhausted` if `buffer` is filled before the end of the
+    // value.
+    pub(crate) fn read_value(
+        &self,
+        partition: &mut impl FlashPartition,
+        buffer: &mut [u8],
+        offset: usize,
+    ) -> Result<usize> {
+        let remaining = self
+            .value_size()
+            .checked_sub(offset)
+            .ok_or(Error::OutOfRange)?;
+        let len = buffer.len().min(remaining);
+        let address = self.address + (EntryHeader::SIZE + self.key_length() + offset) as Address;
+        partition.read(address, &mut buffer[..len])?;
+        if len != remaining {
+            return Err(Error::ResourceExhausted);
+        }
+        Ok(len)
+    }
+
+    // Returns `Ok` if the entry's value in flash is `value` and `NotFound` if
+    // it differs.
+    pub(crate) fn value_matches(
+        &self,
+        partition: &mut impl FlashPartition,
+        mut value: &[u8],
+    ) -> Result<()> {
+        if value.len() != self.value_size() {
+            return Err(Error::NotFound);
+        }
+        let mut buffer = [0u8; 2 * MIN_ALIGNMENT_BYTES];
+        let mut address = self.address + (EntryHeader::SIZE + self.key_length()) as Address;
+        while !value.is_empty() {
+            let len = buffer.len().min(value.len());
+            partition.read(address, &mut buffer[..len])?;
+            if buffer[..len] != value[..len] {
+                return Err(Error::NotFound);
+            }
+            value = &value[len..];
+            address += len as Address;
+        }
+        Ok(())
+    }
+
+    // Checks the entry's checksum against `key` and `value`.
+    pub(crate) fn verify_checksum(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.checksum(key, value).verify(self.header.checksum)
+    }
+
+    // Checks the entry's checksum against the whole entry in flash, including
+    // the header.
+    pub(crate) fn verify_checksum_in_flash(
+        &self,
+        partition: &mut impl FlashPartition,
+    ) -> Result<()> {
+        let mut buffer = [0u8; 2 * MIN_ALIGNMENT_BYTES];
+        let mut address = self.address;
+        let mut remaining = self.size();
+        let mut len = buffer.len().min(remaining);
+        partition.read(address, &mut buffer[..len])?;
+
+        // The header in flash must match the one read previously.
+        if buffer[4..8] != self.header.checksum.to_le_bytes() {
+            return Err(Error::DataLoss);
+        }
+        buffer[4..8].fill(0);
+
+        let mut checksum = self.format.checksum.start();
+        loop {
+            checksum.update(&buffer[..len]);
+            remaining -= len;
+            if remaining == 0 {
+                break;
+            }
+            address += len as Address;
+            len = buffer.len().min(remaining);
+            partition.read(address, &mut buffer[..len])?;
+        }
+        checksum.verify(self.header.checksum)
+    }
+
+    fn calculate_checksum(&self, key: &[u8], value: &[u8]) -> u32 {
+        self.checksum(key, value).value()
+    }
+
+    fn checksum(&self, key: &[u8], value: &[u8]) -> ChecksumState {
+        let mut header = self.header;
+        header.checksum = 0;
+        let mut checksum = self.format.checksum.start();
+        checksum.update(&header.to_bytes());
+        checksum.update(key);
+        checksum.update(value);
+        self.add_padding_to_checksum(&mut checksum);
+        checksum
+    }
+
+    fn calculate_checksum_from_flash(&self, partition: &mut impl FlashPartition) -> Result<u32> {
+        let mut header = self.header;
+        header.checksum = 0;
+        let mut checksum = self.format.checksum.start();
+        checksum.update(&header.to_bytes());
+
+        let mut buffer = [0u8; 2 * MIN_ALIGNMENT_BYTES];
+        let mut address = self.address + EntryHeader::SIZE as Address;
+        let end = self.address + self.content_size() as Address;
+        while address < end {
+            let len = buffer.len().min((end - address) as usize);
+            partition.read(address, &mut buffer[..len])?;
+            checksum.update(&buffer[..len]);
+            address += len as Address;
+        }
+        self.add_padding_to_checksum(&mut checksum);
+        Ok(checksum.value())
+    }
+
+    // Padding is written as zeros and included in the checksum.
+    fn add_padding_to_checksum(&self, checksum: &mut ChecksumState) {
+        const PADDING: [u8; MIN_ALIGNMENT_BYTES] = [0; MIN_ALIGNMENT_BYTES];
+        let mut padding = self.size() - self.content_size();
+        while padding > 0 {
+            let len = padding.min(PADDING.len());
+            checksum.update(&PADDING[..len]);
+            padding -= len;
+        }
+    }
+}
+
+// Buffers writes to flash so each is a whole number of `alignment` bytes,
+// padding the last write with zeros.
+struct AlignedWriter {
+    buffer: [u8; MAX_FLASH_ALIGNMENT],
+    chunk_size: usize,
+    len: usize,
+    alignment: usize,
+    address: Address,
+    bytes_written: usize,
+}
+
+impl AlignedWriter {
+    fn new(address: Address, alignment: usize) -> Self {
+        Self {
+            buffer: [0; MAX_FLASH_ALIGNMENT],
+            chunk_size: MAX_FLASH_ALIGNMENT / alignment * alignment,
+            len: 0,
+            alignment,
+            address,
+            bytes_written: 0,
+        }
+    }
+
+    fn write(&mut self, partition: &mut impl FlashPartition, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let len = (self.chunk_size - self.len).min(data.len());
+            self.buffer[self.len..self.len + len].copy_from_slice(&data[..len]);
+            self.len += len;
+            data = &data[len..];
+            if self.len == self.chunk_size {
+                self.write_buffer(partition)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes any buffered data and returns the total number of bytes written.
+    fn flush(mut self, partition: &mut impl FlashPartition) -> Result<usize> {
+        if self.len > 0 {
+            let padded_len = align_up(self.len, self.alignment);
+            self.buffer[self.len..padded_len].fill(0);
+            self.len = padded_len;
+            self.write_buffer(partition)?;
+        }
+        Ok(self.bytes_written)
+    }
+
+    fn write_buffer(&mut self, partition: &mut impl FlashPartition) -> Result<()> {
+        partition.write(self.address, &self.buffer[..self.len])?;
+        self.address += self.len as Address;
+        self.bytes_written += self.len;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::EntryChecksum;
+    use crate::FakeFlash;
+
+    const FORMAT: EntryFormat = EntryFormat {
+        magic: 0x5ab2f0b5,
+        checksum: EntryChecksum::Crc16,
+    };
+
+    #[test]
+    fn entries_are_padded_to_alignment() {
+        assert_eq!(entry_alignment(1), 16);
+        assert_eq!(entry_alignment(16), 16);
+        assert_eq!(entry_alignment(20), 32);
+        assert_eq!(entry_size(1, 4, 6), 32);
+        assert_eq!(entry_size(1, 4, 12), 32);
+        assert_eq!(entry_size(1, 4, 13), 48);
+        assert_eq!(entry_size(64, 4, 6), 64);
+    }
+
+    #[test]
+    fn written_entry_reads_back_and_verifies() {
+        let mut flash = FakeFlash::<256>::new(128, 16);
+        let entry = Entry::new(16, 32, FORMAT, "key1", b"value1", EntryState::Valid, 7);
+        assert_eq!(entry.write(&mut flash, b"key1", b"value1"), Ok(32));
+
+        let read = Entry::read(&mut flash, 32, &[FORMAT]).unwrap();
+        assert_eq!(read.transaction_id(), 7);
+        assert_eq!(read.next_address(), 64);
+        assert_eq!(read.verify_checksum_in_flash(&mut flash), Ok(()));
+        assert_eq!(read.verify_checksum(b"key1", b"value1"), Ok(()));
+        assert_eq!(
+            read.verify_checksum(b"key1", b"value2"),
+            Err(Error::DataLoss)
+        );
+
+        let mut value = [0u8; 8];
+        assert_eq!(read.read_value(&mut flash, &mut value, 0), Ok(6));
+        assert_eq!(&value[..6], b"value1");
+        assert_eq!(
+            read.read_value(&mut flash, &mut value[..2], 3),
+            Err(Error::ResourceExhausted)
+        );
+        assert_eq!(&value[..2], b"ue");
+        assert_eq!(read.value_matches(&mut flash, b"value1"), Ok(()));
+        assert_eq!(
+            read.value_matches(&mut flash, b"value2"),
+            Err(Error::NotFound)
+        );
+
+        // Corrupting the value is detected.
+        flash.buffer_mut()[32 + 20] ^= 1;
+        assert_eq!(
+            read.verify_checksum_in_flash(&mut flash),
+            Err(Error::DataLoss)
+        );
+    }
+
+    #[test]
+    fn reading_erased_or_unknown_entries_fails() {
+        let mut flash = FakeFlash::<256>::new(128, 16);
+        assert_eq!(
+            Entry::read(&mut flash, 0, &[FORMAT]).err(),
+            Some(Error::NotFound)
+        );
+        flash.buffer_mut()[..4].copy_from_slice(&0x12345678u32.to_le_bytes());
+        assert_eq!(
+            Entry::read(&mut flash, 0, &[FORMAT]).err(),
+            Some(Error::DataLoss)
+        );
+    }
+}
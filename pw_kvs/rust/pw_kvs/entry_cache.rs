@@ -0,0 +1,305 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_status::{Error, Result};
+
+use crate::entry::Entry;
+use crate::flash::FlashPartition;
+use crate::format::hash;
+use crate::sectors::Sectors;
+use crate::{Address, MAX_KEY_LENGTH};
+
+// Marks unused address slots.
+pub(crate) const NO_ADDRESS: Address = Address::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EntryState {
+    Valid,
+    Deleted,
+}
+
+// Identifies the newest entry for a key.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct KeyDescriptor {
+    pub key_hash: u32,
+    pub transaction_id: u32,
+    pub state: EntryState,
+}
+
+impl KeyDescriptor {
+    const EMPTY: Self = Self {
+        key_hash: 0,
+        transaction_id: 0,
+        state: EntryState::Deleted,
+    };
+}
+
+// Returns the used slots of an address list.
+pub(crate) fn used_addresses(addresses: &[Address]) -> &[Address] {
+    let len = addresses
+        .iter()
+        .position(|&address| address == NO_ADDRESS)
+        .unwrap_or(addresses.len());
+    &addresses[..len]
+}
+
+// Tracks the descriptor of each key in the store and the addresses of its
+// newest entry, of which there are up to `REDUNDANCY` copies.
+//
+// Keys are identified by index, which is stable until a key is removed.
+pub(crate) struct EntryCache<const MAX_ENTRIES: usize, const REDUNDANCY: usize> {
+    descriptors: [KeyDescriptor; MAX_ENTRIES],
+    addresses: [[Address; REDUNDANCY]; MAX_ENTRIES],
+    len: usize,
+}
+
+impl<const MAX_ENTRIES: usize, const REDUNDANCY: usize> EntryCache<MAX_ENTRIES, REDUNDANCY> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            descriptors: [KeyDescriptor::EMPTY; MAX_ENTRIES],
+            addresses: [[NO_ADDRESS; REDUNDANCY]; MAX_ENTRIES],
+            len: 0,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    // The number of keys, including deleted keys.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.len == MAX_ENTRIES
+    }
+
+    // The number of keys which are not deleted.
+    pub(crate) fn present_entries(&self) -> usize {
+        self.descriptors[..self.len]
+            .iter()
+            .filter(|descriptor| descriptor.state == EntryState::Valid)
+            .count()
+    }
+
+    pub(crate) fn descriptor(&self, index: usize) -> KeyDescriptor {
+        self.descriptors[index]
+    }
+
+    pub(crate) fn addresses(&self, index: usize) -> &[Address] {
+        used_addresses(&self.addresses[index])
+    }
+
+    // Returns a copy of the address slots of a key, for use while the cache is
+    // modified.
+    pub(crate) fn address_slots(&self, index: usize) -> [Address; REDUNDANCY] {
+        self.addresses[index]
+    }
+
+    // Finds the key `key`, checking its hash against the key in flash.
+    //
+    // Returns `AlreadyExists` if a different key has the same hash.  Entries
+    // whose key can not be read are marked corrupt and `error_detected` is
+    // set.
+    pub(crate) fn find<const MAX_SECTORS: usize>(
+        &self,
+        partition: &mut impl FlashPartition,
+        sectors: &mut Sectors<MAX_SECTORS>,
+        key: &str,
+        error_detected: &mut bool,
+    ) -> Result<usize> {
+        let key_hash = hash(key.as_bytes());
+        let mut key_buffer = [0u8; MAX_KEY_LENGTH];
+        for index in 0..self.len {
+            if self.descriptors[index].key_hash != key_hash {
+                continue;
+            }
+            let mut key_found = false;
+            for &address in self.addresses(index) {
+                let read = Entry::read_key(partition, address, key.len(), &mut key_buffer);
+                if read.is_ok() && hash(&key_buffer[..key.len()]) == key_hash {
+                    key_found = true;
+                    break;
+                }
+                // A mismatch is caused by invalid data or a collision of keys
+                // of different lengths.
+                sectors.mark_corrupt(sectors.index_of(address));
+                *error_detected = true;
+            }
+            if key_found {
+                if key_buffer[..key.len()] == *key.as_bytes() {
+                    return Ok(index);
+                }
+                return Err(Error::AlreadyExists);
+            }
+        }
+        Err(Error::NotFound)
+    }
+
+    pub(crate) fn find_index(&self, key_hash: u32) -> Option<usize> {
+        self.descriptors[..self.len]
+            .iter()
+            .position(|descriptor| descriptor.key_hash == key_hash)
+    }
+
+    // Adds a key, which must not already be present, returning its index.
+    pub(crate) fn add_new(&mut self, descriptor: KeyDescriptor, address: Address) -> usize {
+        let index = self.len;
+        self.reset_entry(index, descriptor, address);
+        self.len += 1;
+        index
+    }
+
+    // Updates the descriptor of a key, replacing all of its addresses.
+    pub(crate) fn reset_entry(
+        &mut self,
+        index: usize,
+        descriptor: KeyDescriptor,
+        address: Address,
+    ) {
+        self.descriptors[index] = descriptor;
+        self.addresses[index] = [NO_ADDRESS; REDUNDANCY];
+        self.addresses[index][0] = address;
+    }
+
+    // Adds an entry found while loading the store.  Newer entries replace
+    // older ones and entries with the same transaction ID are redundant
+    // copies.
+    //
+    // Returns `DataLoss` if the entry conflicts with the cached one.
+    pub(crate) fn add_new_or_update_existing(
+        &mut self,
+        descriptor: KeyDescriptor,
+        address: Address,
+        sector_size_bytes: usize,
+    ) -> Result<()> {
+        let Some(index) = self.find_index(descriptor.key_hash) else {
+            if self.is_full() {
+                return Err(Error::ResourceExhausted);
+            }
+            self.add_new(descriptor, address);
+            return Ok(());
+        };
+
+        let existing = self.descriptors[index];
+        if existing.transaction_id == descriptor.transaction_id {
+            // Redundant copies must be in different sectors.
+            let sector = address as usize / sector_size_bytes;
+            let same_sector = self
+                .addresses(index)
+                .iter()
+                .any(|&other| other as usize / sector_size_bytes == sector);
+            if existing.state != descriptor.state || same_sector {
+                return Err(Error::DataLoss);
+            }
+            if self.addresses(index).len() < REDUNDANCY {
+                self.add_address(index, address);
+            }
+        } else if existing.transaction_id < descriptor.transaction_id {
+            self.reset_entry(index, descriptor, address);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn add_address(&mut self, index: usize, address: Address) {
+        let len = self.addresses(index).len();
+        self.addresses[index][len] = address;
+    }
+
+    pub(crate) fn set_address(&mut self, index: usize, slot: usize, address: Address) {
+        self.addresses[index][slot] = address;
+    }
+
+    pub(crate) fn remove_address(&mut self, index: usize, address: Address) {
+        let len = self.addresses(index).len();
+        let slots = &mut self.addresses[index];
+        if let Some(slot) = slots[..len].iter().position(|&a| a == address) {
+            slots[slot] = slots[len - 1];
+            slots[len - 1] = NO_ADDRESS;
+        }
+    }
+
+    // Removes a key, moving the last key to its index.
+    pub(crate) fn remove(&mut self, index: usize) {
+        let last = self.len - 1;
+        self.descriptors[index] = self.descriptors[last];
+        self.addresses[index] = self.addresses[last];
+        self.len = last;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(key_hash: u32, transaction_id: u32) -> KeyDescriptor {
+        KeyDescriptor {
+            key_hash,
+            transaction_id,
+            state: EntryState::Valid,
+        }
+    }
+
+    #[test]
+    fn newest_entry_replaces_older_entries() {
+        let mut cache = EntryCache::<4, 2>::new();
+        cache
+            .add_new_or_update_existing(descriptor(1, 5), 0, 512)
+            .unwrap();
+        cache
+            .add_new_or_update_existing(descriptor(1, 3), 512, 512)
+            .unwrap();
+        assert_eq!(cache.addresses(0), [0]);
+        cache
+            .add_new_or_update_existing(descriptor(1, 7), 1024, 512)
+            .unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.descriptor(0).transaction_id, 7);
+        assert_eq!(cache.addresses(0), [1024]);
+    }
+
+    #[test]
+    fn redundant_copies_must_be_in_different_sectors() {
+        let mut cache = EntryCache::<4, 2>::new();
+        cache
+            .add_new_or_update_existing(descriptor(1, 5), 0, 512)
+            .unwrap();
+        assert_eq!(
+            cache.add_new_or_update_existing(descriptor(1, 5), 32, 512),
+            Err(Error::DataLoss)
+        );
+        cache
+            .add_new_or_update_existing(descriptor(1, 5), 544, 512)
+            .unwrap();
+        assert_eq!(cache.addresses(0), [0, 544]);
+
+        cache.remove_address(0, 0);
+        assert_eq!(cache.addresses(0), [544]);
+    }
+
+    #[test]
+    fn removing_key_moves_last_key() {
+        let mut cache = EntryCache::<4, 1>::new();
+        cache.add_new(descriptor(1, 1), 0);
+        cache.add_new(descriptor(2, 2), 32);
+        cache.add_new(descriptor(3, 3), 64);
+        assert!(!cache.is_full());
+        cache.remove(0);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.find_index(3), Some(0));
+        assert_eq!(cache.addresses(0), [64]);
+        assert_eq!(cache.find_index(1), None);
+    }
+}
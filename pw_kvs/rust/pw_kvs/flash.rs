@@ -0,0 +1,228 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_status::{Error, Result};
+
+use crate::Address;
+
+/// A region of flash memory made up of equally sized, individually erasable
+/// sectors.
+///
+/// Addresses are relative to the start of the partition.
+pub trait FlashPartition {
+    /// Returns the size of each sector in bytes.
+    fn sector_size_bytes(&self) -> usize;
+
+    /// Returns the number of sectors in the partition.
+    fn sector_count(&self) -> usize;
+
+    /// Returns the alignment of writes in bytes.  Writes start at a multiple
+    /// of this and their lengths are a multiple of it.
+    fn alignment_bytes(&self) -> usize;
+
+    /// Returns the value of each byte of erased memory.
+    fn erased_memory_content(&self) -> u8 {
+        0xff
+    }
+
+    /// Returns the size of the partition in bytes.
+    fn size_bytes(&self) -> usize {
+        self.sector_size_bytes() * self.sector_count()
+    }
+
+    /// Fills `buffer` with the data at `address`.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - The read extends past the end of the
+    ///   partition.
+    fn read(&mut self, address: Address, buffer: &mut [u8]) -> Result<()>;
+
+    /// Writes `data` to erased memory at `address`.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `address` or the length of `data` is not
+    ///   aligned to [`FlashPartition::alignment_bytes()`].
+    /// - [`Error::OutOfRange`] - The write extends past the end of the
+    ///   partition.
+    fn write(&mut self, address: Address, data: &[u8]) -> Result<()>;
+
+    /// Erases `num_sectors` sectors starting with the sector at `address`.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `address` is not the start of a sector.
+    /// - [`Error::OutOfRange`] - The sectors extend past the end of the
+    ///   partition.
+    fn erase(&mut self, address: Address, num_sectors: usize) -> Result<()>;
+}
+
+impl<T: FlashPartition + ?Sized> FlashPartition for &mut T {
+    fn sector_size_bytes(&self) -> usize {
+        (**self).sector_size_bytes()
+    }
+
+    fn sector_count(&self) -> usize {
+        (**self).sector_count()
+    }
+
+    fn alignment_bytes(&self) -> usize {
+        (**self).alignment_bytes()
+    }
+
+    fn erased_memory_content(&self) -> u8 {
+        (**self).erased_memory_content()
+    }
+
+    fn size_bytes(&self) -> usize {
+        (**self).size_bytes()
+    }
+
+    fn read(&mut self, address: Address, buffer: &mut [u8]) -> Result<()> {
+        (**self).read(address, buffer)
+    }
+
+    fn write(&mut self, address: Address, data: &[u8]) -> Result<()> {
+        (**self).write(address, data)
+    }
+
+    fn erase(&mut self, address: Address, num_sectors: usize) -> Result<()> {
+        (**self).erase(address, num_sectors)
+    }
+}
+
+const fn is_aligned(value: usize, alignment: usize) -> bool {
+    value.next_multiple_of(alignment) == value
+}
+
+/// A [`FlashPartition`] backed by a `SIZE` byte buffer in RAM, for tests and
+/// host tools.
+///
+/// Like real flash, memory must be erased before it is written again.
+pub struct FakeFlash<const SIZE: usize> {
+    buffer: [u8; SIZE],
+    sector_size_bytes: usize,
+    alignment_bytes: usize,
+}
+
+impl<const SIZE: usize> FakeFlash<SIZE> {
+    /// Create an erased `FakeFlash` with sectors of `sector_size_bytes` and
+    /// writes aligned to `alignment_bytes`.
+    ///
+    /// # Panics
+    /// Panics if `SIZE` is not a multiple of `sector_size_bytes` or
+    /// `sector_size_bytes` is not a multiple of `alignment_bytes`.
+    pub const fn new(sector_size_bytes: usize, alignment_bytes: usize) -> Self {
+        assert!(sector_size_bytes > 0 && is_aligned(SIZE, sector_size_bytes));
+        assert!(alignment_bytes > 0 && is_aligned(sector_size_bytes, alignment_bytes));
+        Self {
+            buffer: [0xff; SIZE],
+            sector_size_bytes,
+            alignment_bytes,
+        }
+    }
+
+    /// Returns the contents of the flash.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Returns the contents of the flash for modification, such as
+    /// simulating corruption.  Writes through this are not checked.
+    pub fn buffer_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    fn range(&self, address: Address, len: usize) -> Result<core::ops::Range<usize>> {
+        let start = address as usize;
+        let end = start.checked_add(len).ok_or(Error::OutOfRange)?;
+        if end > SIZE {
+            return Err(Error::OutOfRange);
+        }
+        Ok(start..end)
+    }
+}
+
+impl<const SIZE: usize> FlashPartition for FakeFlash<SIZE> {
+    fn sector_size_bytes(&self) -> usize {
+        self.sector_size_bytes
+    }
+
+    fn sector_count(&self) -> usize {
+        SIZE / self.sector_size_bytes
+    }
+
+    fn alignment_bytes(&self) -> usize {
+        self.alignment_bytes
+    }
+
+    fn read(&mut self, address: Address, buffer: &mut [u8]) -> Result<()> {
+        let range = self.range(address, buffer.len())?;
+        buffer.copy_from_slice(&self.buffer[range]);
+        Ok(())
+    }
+
+    fn write(&mut self, address: Address, data: &[u8]) -> Result<()> {
+        if !is_aligned(address as usize, self.alignment_bytes)
+            || !is_aligned(data.len(), self.alignment_bytes)
+        {
+            return Err(Error::InvalidArgument);
+        }
+        let range = self.range(address, data.len())?;
+        let destination = &mut self.buffer[range];
+        // Flash can only be written once between erases.
+        if destination.iter().any(|&b| b != 0xff) {
+            return Err(Error::Unknown);
+        }
+        destination.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: Address, num_sectors: usize) -> Result<()> {
+        if !is_aligned(address as usize, self.sector_size_bytes) {
+            return Err(Error::InvalidArgument);
+        }
+        let range = self.range(address, num_sectors * self.sector_size_bytes)?;
+        self.buffer[range].fill(0xff);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fake_flash_must_be_erased_before_writing() {
+        let mut flash = FakeFlash::<64>::new(32, 16);
+        assert_eq!(flash.sector_count(), 2);
+        flash.write(16, &[1; 16]).unwrap();
+        assert_eq!(flash.write(16, &[2; 16]), Err(Error::Unknown));
+
+        flash.erase(0, 1).unwrap();
+        flash.write(16, &[2; 16]).unwrap();
+        let mut buffer = [0u8; 4];
+        flash.read(14, &mut buffer).unwrap();
+        assert_eq!(buffer, [0xff, 0xff, 2, 2]);
+    }
+
+    #[test]
+    fn fake_flash_checks_alignment_and_bounds() {
+        let mut flash = FakeFlash::<64>::new(32, 16);
+        assert_eq!(flash.write(8, &[0; 16]), Err(Error::InvalidArgument));
+        assert_eq!(flash.write(0, &[0; 8]), Err(Error::InvalidArgument));
+        assert_eq!(flash.write(48, &[0; 32]), Err(Error::OutOfRange));
+        assert_eq!(flash.read(60, &mut [0; 8]), Err(Error::OutOfRange));
+        assert_eq!(flash.erase(16, 1), Err(Error::InvalidArgument));
+        assert_eq!(flash.erase(32, 2), Err(Error::OutOfRange));
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_checksum::{Checksum, Crc16CcittEightBit};
+use pw_status::{Error, Result};
+
+/// The algorithm used to checksum entries.
+#[derive(Clone, Copy, Debug)]
+pub enum EntryChecksum {
+    /// Entries are not checksummed.  The checksum field of each entry is zero.
+    None,
+    /// CRC16-CCITT, like `pw::kvs::ChecksumCrc16` in C++.
+    Crc16,
+    /// A custom 32 bit checksum.  The function is called with the state, which
+    /// starts at zero, and the next chunk of the entry and returns the updated
+    /// state.
+    Custom(fn(u32, &[u8]) -> u32),
+}
+
+/// Identifies a version of the entry format with a magic number and the
+/// checksum algorithm it uses.
+#[derive(Clone, Copy, Debug)]
+pub struct EntryFormat {
+    /// Magic number at the start of each entry.  It must be unique among the
+    /// formats used by a key-value store.
+    pub magic: u32,
+    /// The algorithm used to checksum entries.
+    pub checksum: EntryChecksum,
+}
+
+impl EntryChecksum {
+    pub(crate) fn start(self) -> ChecksumState {
+        match self {
+            EntryChecksum::None => ChecksumState::None,
+            EntryChecksum::Crc16 => ChecksumState::Crc16(Crc16CcittEightBit::new()),
+            EntryChecksum::Custom(update) => ChecksumState::Custom(update, 0),
+        }
+    }
+}
+
+pub(crate) enum ChecksumState {
+    None,
+    Crc16(Crc16CcittEightBit),
+    Custom(fn(u32, &[u8]) -> u32, u32),
+}
+
+impl ChecksumState {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            ChecksumState::None => {}
+            ChecksumState::Crc16(crc) => crc.update(data),
+            ChecksumState::Custom(update, state) => *state = update(*state, data),
+        }
+    }
+
+    // Returns the checksum as stored in an entry header.  Checksums smaller
+    // than 32 bits occupy the first bytes of the field and the rest are zero.
+    pub(crate) fn value(&self) -> u32 {
+        match self {
+            ChecksumState::None => 0,
+            ChecksumState::Crc16(crc) => crc.value().into(),
+            ChecksumState::Custom(_, state) => *state,
+        }
+    }
+
+    // Checks the checksum stored in an entry header.  Like C++, only the bytes
+    // used by the algorithm are compared.
+    pub(crate) fn verify(&self, stored: u32) -> Result<()> {
+        let matches = match self {
+            ChecksumState::None => stored == 0,
+            ChecksumState::Crc16(crc) => stored as u16 == crc.value(),
+            ChecksumState::Custom(_, state) => stored == *state,
+        };
+        if matches {
+            Ok(())
+        } else {
+            Err(Error::DataLoss)
+        }
+    }
+}
+
+// The header at the start of each entry.  Fields are little endian:
+//
+//   magic             u32  identifies the entry format
+//   checksum          u32  over the entry with this field zeroed
+//   alignment_units   u8   entry alignment is (units + 1) * 16 bytes
+//   key_length_bytes  u8   key length, which is never zero
+//   value_size_bytes  u16  0xffff for deleted entries
+//   transaction_id    u32  increases with each write to the store
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct EntryHeader {
+    pub magic: u32,
+    pub checksum: u32,
+    pub alignment_units: u8,
+    pub key_length_bytes: u8,
+    pub value_size_bytes: u16,
+    pub transaction_id: u32,
+}
+
+impl EntryHeader {
+    pub(crate) const SIZE: usize = 16;
+
+    pub(crate) fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes[8] = self.alignment_units;
+        bytes[9] = self.key_length_bytes;
+        bytes[10..12].copy_from_slice(&self.value_size_bytes.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.transaction_id.to_le_bytes());
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Self {
+            magic: u32_at(0),
+            checksum: u32_at(4),
+            alignment_units: bytes[8],
+            key_length_bytes: bytes[9],
+            value_size_bytes: u16::from_le_bytes([bytes[10], bytes[11]]),
+            transaction_id: u32_at(12),
+        }
+    }
+}
+
+/// Returns the hash which identifies `key` in the store.
+///
+/// This is the 65599 hash used by C++ `pw_kvs` (and `pw_tokenizer`) without
+/// a length limit.
+pub(crate) const fn hash(key: &[u8]) -> u32 {
+    const HASH_CONSTANT: u32 = 65599;
+    let mut hash: u32 = 0;
+    let mut coefficient: u32 = HASH_CONSTANT;
+    let mut i = 0;
+    while i < key.len() {
+        hash = hash.wrapping_add(coefficient.wrapping_mul(key[i] as u32));
+        coefficient = coefficient.wrapping_mul(HASH_CONSTANT);
+        i += 1;
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trips_through_bytes() {
+        let header = EntryHeader {
+            magic: 0x5ab2f0b5,
+            checksum: 0x12345678,
+            alignment_units: 1,
+            key_length_bytes: 4,
+            value_size_bytes: 6,
+            transaction_id: 0x0a0b0c0d,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(
+            bytes,
+            [
+                0xb5, 0xf0, 0xb2, 0x5a, 0x78, 0x56, 0x34, 0x12, 0x01, 0x04, 0x06, 0x00, 0x0d, 0x0c,
+                0x0b, 0x0a
+            ]
+        );
+        assert_eq!(EntryHeader::from_bytes(&bytes), header);
+    }
+
+    #[test]
+    fn crc16_occupies_low_bytes_of_checksum_field() {
+        let mut checksum = EntryChecksum::Crc16.start();
+        checksum.update(b"123456789");
+        assert_eq!(checksum.value(), 0x29b1);
+        assert_eq!(checksum.verify(0x29b1), Ok(()));
+        // Bytes beyond the CRC are not checked, as in C++.
+        assert_eq!(checksum.verify(0xffff_29b1), Ok(()));
+        assert_eq!(checksum.verify(0x29b2), Err(Error::DataLoss));
+    }
+
+    #[test]
+    fn hash_matches_cpp() {
+        assert_eq!(hash(b""), 0);
+        assert_eq!(hash(b"a"), 65599 * u32::from(b'a'));
+        assert_eq!(
+            hash(b"ab"),
+            (65599u32 * u32::from(b'a'))
+                .wrapping_add(65599u32.wrapping_mul(65599) * u32::from(b'b'))
+        );
+    }
+}
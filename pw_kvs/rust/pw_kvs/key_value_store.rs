@@ -0,0 +1,1483 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use core::fmt;
+use core::ops::Deref;
+
+use pw_status::{Error, Result};
+
+use crate::entry::{entry_size, Entry, MIN_ALIGNMENT_BYTES};
+use crate::entry_cache::{used_addresses, EntryCache, EntryState, NO_ADDRESS};
+use crate::flash::FlashPartition;
+use crate::format::{hash, EntryFormat, EntryHeader};
+use crate::sectors::{Sectors, MAX_SECTOR_SIZE_BYTES};
+use crate::{Address, MAX_FLASH_ALIGNMENT, MAX_KEY_LENGTH};
+
+// Full maintenance garbage collects all sectors once the store uses more than
+// this percentage of the partition.
+const GC_USAGE_THRESHOLD_PERCENTAGE: usize = 70;
+
+/// When writes garbage collect to make space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GarbageCollectOnWrite {
+    /// Writes fail with [`Error::ResourceExhausted`] rather than garbage
+    /// collecting.
+    Disabled,
+    /// Writes garbage collect at most one sector.
+    OneSector,
+    /// Writes garbage collect as many sectors as needed.
+    AsManySectorsNeeded,
+}
+
+/// When errors found in flash are repaired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorRecovery {
+    /// Errors are repaired as soon as they are found.
+    Immediate,
+    /// Errors are repaired during initialization and maintenance.
+    Lazy,
+    /// Errors are only repaired by [`KeyValueStore::full_maintenance()`] and
+    /// [`KeyValueStore::heavy_maintenance()`].
+    Manual,
+}
+
+/// Options for a [`KeyValueStore`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Options {
+    /// When writes garbage collect to make space.
+    pub gc_on_write: GarbageCollectOnWrite,
+    /// When errors found in flash are repaired.
+    pub recovery: ErrorRecovery,
+    /// Verify the checksum of values when they are read.
+    pub verify_on_read: bool,
+    /// Read back and verify entries after they are written.
+    pub verify_on_write: bool,
+}
+
+impl Options {
+    /// The default options, which match C++ `pw_kvs`.
+    pub const DEFAULT: Self = Self {
+        gc_on_write: GarbageCollectOnWrite::AsManySectorsNeeded,
+        recovery: ErrorRecovery::Lazy,
+        verify_on_read: true,
+        verify_on_write: true,
+    };
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Statistics about the space used by a [`KeyValueStore`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageStats {
+    /// Bytes available for new entries, excluding the sector kept empty for
+    /// garbage collection.
+    pub writable_bytes: usize,
+    /// Bytes used by the newest entry for each key.
+    pub in_use_bytes: usize,
+    /// Bytes which garbage collection can reclaim.
+    pub reclaimable_bytes: usize,
+    /// Number of sectors erased by garbage collection.
+    pub sector_erase_count: usize,
+    /// Number of corrupt sectors which were repaired.
+    pub corrupt_sectors_recovered: usize,
+    /// Number of missing redundant copies of entries which were restored.
+    pub missing_redundant_entries_recovered: usize,
+}
+
+/// A key read from a [`KeyValueStore`].
+#[derive(Clone, Copy)]
+pub struct KeyBuffer {
+    bytes: [u8; MAX_KEY_LENGTH],
+    len: usize,
+}
+
+impl KeyBuffer {
+    /// Returns the key.
+    pub fn as_str(&self) -> &str {
+        // Keys are validated as UTF-8 when they are read.
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+}
+
+impl Deref for KeyBuffer {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for KeyBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq<str> for KeyBuffer {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for KeyBuffer {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum InitializationState {
+    NotInitialized,
+    // Initialized, but errors were found which must be repaired before
+    // writing.
+    NeedsMaintenance,
+    Ready,
+}
+
+/// A flash-backed key-value store.
+///
+/// The store holds up to `MAX_ENTRIES` keys in a partition with up to
+/// `MAX_SECTORS` sectors and writes `REDUNDANCY` copies of each entry, each
+/// in a different sector.
+///
+/// Entries are appended to sectors in the first of `formats`.  Entries in the
+/// other formats are read, and rewritten in the first format by
+/// [`KeyValueStore::full_maintenance()`].  One sector is kept empty so that
+/// garbage collection can relocate entries from other sectors.
+pub struct KeyValueStore<
+    'a,
+    F: FlashPartition,
+    const MAX_ENTRIES: usize,
+    const MAX_SECTORS: usize,
+    const REDUNDANCY: usize = 1,
+> {
+    partition: F,
+    formats: &'a [EntryFormat],
+    options: Options,
+    sectors: Sectors<MAX_SECTORS>,
+    entry_cache: EntryCache<MAX_ENTRIES, REDUNDANCY>,
+    initialized: InitializationState,
+    error_detected: bool,
+    stats: StorageStats,
+    last_transaction_id: u32,
+}
+
+impl<
+        'a,
+        F: FlashPartition,
+        const MAX_ENTRIES: usize,
+        const MAX_SECTORS: usize,
+        const REDUNDANCY: usize,
+    > KeyValueStore<'a, F, MAX_ENTRIES, MAX_SECTORS, REDUNDANCY>
+{
+    /// Creates a key-value store in `partition`, which must be initialized
+    /// with [`KeyValueStore::init()`] before use.
+    ///
+    /// # Panics
+    /// Panics if `formats` is empty or `REDUNDANCY` is zero.
+    pub fn new(partition: F, formats: &'a [EntryFormat], options: Options) -> Self {
+        assert!(!formats.is_empty(), "at least one entry format is required");
+        assert!(REDUNDANCY > 0, "REDUNDANCY must be at least 1");
+        Self {
+            partition,
+            formats,
+            options,
+            sectors: Sectors::new(),
+            entry_cache: EntryCache::new(),
+            initialized: InitializationState::NotInitialized,
+            error_detected: false,
+            stats: StorageStats::default(),
+            last_transaction_id: 0,
+        }
+    }
+
+    /// Returns the flash partition.
+    pub fn partition(&self) -> &F {
+        &self.partition
+    }
+
+    /// Consumes the store, returning the flash partition.
+    pub fn into_partition(self) -> F {
+        self.partition
+    }
+
+    /// Loads the store from flash, repairing any errors found unless
+    /// [`Options::recovery`] is [`ErrorRecovery::Manual`].
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The partition has too many or too few
+    ///   sectors, or its sectors or alignment are too large.
+    /// - [`Error::DataLoss`] - Errors were found and not repaired.  The store
+    ///   can be read but not written until they are repaired by maintenance.
+    pub fn init(&mut self) -> Result<()> {
+        self.initialized = InitializationState::NotInitialized;
+        self.error_detected = false;
+        self.last_transaction_id = 0;
+
+        let sector_count = self.partition.sector_count();
+        if !(2..=MAX_SECTORS).contains(&sector_count)
+            || self.partition.sector_size_bytes() > MAX_SECTOR_SIZE_BYTES
+            || self.partition.alignment_bytes() > MAX_FLASH_ALIGNMENT
+        {
+            return Err(Error::FailedPrecondition);
+        }
+
+        let metadata_result = self.initialize_metadata();
+
+        if !self.error_detected {
+            self.initialized = InitializationState::Ready;
+        } else {
+            self.initialized = InitializationState::NeedsMaintenance;
+            if self.options.recovery != ErrorRecovery::Manual {
+                let pre_fix_redundancy_errors = self.stats.missing_redundant_entries_recovered;
+                if self.fix_errors().is_ok() && metadata_result == Err(Error::OutOfRange) {
+                    // Entries missing redundant copies, such as after
+                    // increasing the redundancy, are not counted as
+                    // recovered errors.
+                    self.stats.missing_redundant_entries_recovered = pre_fix_redundancy_errors;
+                }
+            }
+        }
+
+        if self.error_detected {
+            return Err(Error::DataLoss);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the store is initialized and has no unrepaired
+    /// errors.
+    pub fn initialized(&self) -> bool {
+        self.initialized == InitializationState::Ready
+    }
+
+    /// Reads the value of `key` into `value`, returning its size.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `key` is empty or too long.
+    /// - [`Error::FailedPrecondition`] - The store is not initialized.
+    /// - [`Error::NotFound`] - `key` is not in the store.
+    /// - [`Error::ResourceExhausted`] - `value` is smaller than the value and
+    ///   was filled with its start.
+    /// - [`Error::DataLoss`] - The value failed checksum verification.
+    pub fn get(&mut self, key: &str, value: &mut [u8]) -> Result<usize> {
+        self.get_at(key, 0, value)
+    }
+
+    /// Reads the value of `key`, starting `offset` bytes into it, into
+    /// `value`, returning the number of bytes read.
+    ///
+    /// Partial reads are not checksum verified.
+    ///
+    /// # Errors
+    /// As [`KeyValueStore::get()`], and:
+    /// - [`Error::OutOfRange`] - `offset` is beyond the end of the value.
+    pub fn get_at(&mut self, key: &str, offset: usize, value: &mut [u8]) -> Result<usize> {
+        self.check_read_operation(key)?;
+        let index = self.find_existing(key)?;
+        let entry = self.read_entry(index)?;
+        let len = entry.read_value(&mut self.partition, value, offset)?;
+        if self.options.verify_on_read && offset == 0 {
+            if let Err(e) = entry.verify_checksum(key.as_bytes(), &value[..len]) {
+                value[..len].fill(0);
+                return Err(e);
+            }
+        }
+        Ok(len)
+    }
+
+    /// Returns the size of the value of `key`.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `key` is empty or too long.
+    /// - [`Error::FailedPrecondition`] - The store is not initialized.
+    /// - [`Error::NotFound`] - `key` is not in the store.
+    pub fn value_size(&mut self, key: &str) -> Result<usize> {
+        self.check_read_operation(key)?;
+        let index = self.find_existing(key)?;
+        Ok(self.read_entry(index)?.value_size())
+    }
+
+    /// Sets the value of `key` to `value`.
+    ///
+    /// Writing the value a key already has does nothing.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `key` is empty or too long, or the entry
+    ///   does not fit in a sector.
+    /// - [`Error::FailedPrecondition`] - The store is not initialized or needs
+    ///   maintenance.
+    /// - [`Error::ResourceExhausted`] - There is not enough space, or the
+    ///   store has `MAX_ENTRIES` keys.
+    /// - [`Error::AlreadyExists`] - A different key has the same hash.
+    pub fn put(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        self.check_write_operation(key)?;
+        if entry_size(self.partition.alignment_bytes(), key.len(), value.len())
+            > self.partition.sector_size_bytes()
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        match self.find_entry(key) {
+            Ok(index) => self.write_entry_for_existing_key(index, EntryState::Valid, key, value),
+            Err(Error::NotFound) => self.write_entry_for_new_key(key, value),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes `key` from the store by writing a deleted entry for it.
+    ///
+    /// # Errors
+    /// As [`KeyValueStore::put()`], and:
+    /// - [`Error::NotFound`] - `key` is not in the store.
+    pub fn delete(&mut self, key: &str) -> Result<()> {
+        self.check_write_operation(key)?;
+        let index = self.find_existing(key)?;
+        self.write_entry_for_existing_key(index, EntryState::Deleted, key, &[])
+    }
+
+    /// Returns an iterator over the keys in the store.
+    pub fn keys(&mut self) -> Keys<'_, 'a, F, MAX_ENTRIES, MAX_SECTORS, REDUNDANCY> {
+        Keys {
+            kvs: self,
+            index: 0,
+        }
+    }
+
+    /// Returns the number of keys in the store.
+    pub fn size(&self) -> usize {
+        self.entry_cache.present_entries()
+    }
+
+    /// Returns the number of keys tracked by the store, including deleted keys
+    /// which have not been removed by [`KeyValueStore::heavy_maintenance()`].
+    pub fn total_entries_with_deleted(&self) -> usize {
+        self.entry_cache.len()
+    }
+
+    /// Returns the maximum number of keys in the store.
+    pub fn max_size(&self) -> usize {
+        MAX_ENTRIES
+    }
+
+    /// Returns `true` if the store has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.size() == 0
+    }
+
+    /// Returns the number of copies of each entry.
+    pub fn redundancy(&self) -> usize {
+        REDUNDANCY
+    }
+
+    /// Returns the transaction ID of the most recent write.
+    pub fn transaction_count(&self) -> u32 {
+        self.last_transaction_id
+    }
+
+    /// Returns the combined size of the largest key and value which can be
+    /// stored.
+    pub fn max_key_value_size_bytes(&self) -> usize {
+        self.partition.sector_size_bytes() - EntryHeader::SIZE
+    }
+
+    /// Returns statistics about the space used by the store.
+    pub fn storage_stats(&self) -> StorageStats {
+        let sector_size_bytes = self.partition.sector_size_bytes();
+        let mut stats = StorageStats {
+            writable_bytes: 0,
+            in_use_bytes: 0,
+            reclaimable_bytes: 0,
+            ..self.stats
+        };
+        let mut found_empty_sector = false;
+        for sector in self.sectors.iter() {
+            stats.in_use_bytes += sector.valid_bytes();
+            stats.reclaimable_bytes += sector.recoverable_bytes(sector_size_bytes);
+            if !found_empty_sector && sector.is_empty(sector_size_bytes) {
+                // The empty sector reserved for garbage collection is not
+                // writable.
+                found_empty_sector = true;
+                continue;
+            }
+            stats.writable_bytes += sector.writable_bytes();
+        }
+        stats
+    }
+
+    /// Returns `true` if errors have been found which are not yet repaired.
+    pub fn error_detected(&self) -> bool {
+        self.error_detected
+    }
+
+    /// Repairs errors, rewrites entries in other formats in the primary format,
+    /// garbage collects all sectors, and removes deleted keys from the store.
+    ///
+    /// Deleted keys are no longer tracked afterwards, so an old entry for one
+    /// will be loaded if it is not garbage collected before the next
+    /// initialization.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The store is not initialized.
+    pub fn heavy_maintenance(&mut self) -> Result<()> {
+        self.full_maintenance_helper(true)
+    }
+
+    /// Repairs errors, rewrites entries in other formats in the primary
+    /// format, and garbage collects sectors if the store is over 70% full.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The store is not initialized.
+    pub fn full_maintenance(&mut self) -> Result<()> {
+        self.full_maintenance_helper(false)
+    }
+
+    /// Repairs errors, unless [`Options::recovery`] is
+    /// [`ErrorRecovery::Manual`], and garbage collects one sector.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The store is not initialized.
+    /// - [`Error::NotFound`] - No sector needs garbage collection.
+    pub fn partial_maintenance(&mut self) -> Result<()> {
+        if self.initialized == InitializationState::NotInitialized {
+            return Err(Error::FailedPrecondition);
+        }
+        if self.error_detected && self.options.recovery != ErrorRecovery::Manual {
+            self.repair()?;
+        }
+        self.garbage_collect(&[])
+    }
+
+    fn full_maintenance_helper(&mut self, heavy: bool) -> Result<()> {
+        if self.initialized == InitializationState::NotInitialized {
+            return Err(Error::FailedPrecondition);
+        }
+        if self.error_detected {
+            self.repair()?;
+        }
+
+        let update_result = self.update_entries_to_primary_format();
+        let entries_updated = *update_result.as_ref().unwrap_or(&0);
+        let mut overall_status = update_result.map(|_| ());
+
+        let threshold = self.partition.size_bytes() * GC_USAGE_THRESHOLD_PERCENTAGE / 100;
+        let force_gc =
+            heavy || entries_updated > 0 || self.storage_stats().in_use_bytes > threshold;
+
+        self.garbage_collect_pass(force_gc, &mut overall_status);
+        if heavy {
+            let result = self.remove_deleted_key_entries();
+            if overall_status.is_ok() {
+                overall_status = result;
+            }
+            // Collect the space used by the removed entries.
+            self.garbage_collect_pass(force_gc, &mut overall_status);
+        }
+        overall_status
+    }
+
+    // Garbage collects each sector with reclaimable space, or only those
+    // with no valid entries unless `force_gc`.
+    fn garbage_collect_pass(&mut self, force_gc: bool, overall_status: &mut Result<()>) {
+        let sector_size_bytes = self.partition.sector_size_bytes();
+        let sector_count = self.sectors.len();
+        let mut sector = self.sectors.last_new();
+        let mut result = Ok(());
+        for _ in 0..sector_count {
+            sector = (sector + 1) % sector_count;
+            let descriptor = self.sectors.get(sector);
+            if descriptor.recoverable_bytes(sector_size_bytes) > 0
+                && (force_gc || descriptor.valid_bytes() == 0)
+            {
+                result = self.garbage_collect_sector(sector, &[]);
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+        if overall_status.is_ok() {
+            *overall_status = result;
+        }
+    }
+
+    fn check_write_operation(&self, key: &str) -> Result<()> {
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+            return Err(Error::InvalidArgument);
+        }
+        if self.initialized != InitializationState::Ready {
+            return Err(Error::FailedPrecondition);
+        }
+        Ok(())
+    }
+
+    fn check_read_operation(&self, key: &str) -> Result<()> {
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+            return Err(Error::InvalidArgument);
+        }
+        if self.initialized == InitializationState::NotInitialized {
+            return Err(Error::FailedPrecondition);
+        }
+        Ok(())
+    }
+
+    // Loads the entries in flash into the entry cache and counts the space
+    // used in each sector.
+    //
+    // Returns `FailedPrecondition` if errors were found, or `OutOfRange` if
+    // the only error is every key missing redundant copies.
+    fn initialize_metadata(&mut self) -> Result<()> {
+        let sector_size_bytes = self.partition.sector_size_bytes();
+        self.sectors
+            .reset(self.partition.sector_count(), sector_size_bytes);
+        self.entry_cache.reset();
+
+        let mut empty_sector_found = false;
+        for sector in 0..self.sectors.len() {
+            let sector_address = self.sectors.base_address(sector);
+            let mut entry_address = sector_address;
+            let mut sector_corrupt = false;
+
+            while self.sectors.address_in_sector(sector, entry_address) {
+                match self.load_entry(entry_address) {
+                    Ok(next_entry_address) => entry_address = next_entry_address,
+                    // The rest of the sector is erased.
+                    Err(Error::NotFound) => break,
+                    Err(_) => {
+                        self.error_detected = true;
+                        sector_corrupt = true;
+                        // Skip ahead to the next valid entry, if any.
+                        match self
+                            .scan_for_entry(sector, entry_address + MIN_ALIGNMENT_BYTES as Address)
+                        {
+                            Some(next_entry_address) => entry_address = next_entry_address,
+                            // The sector is marked corrupt below, so it is
+                            // not written again before it is erased.
+                            None => break,
+                        }
+                    }
+                }
+                let used_bytes = (entry_address - sector_address) as usize;
+                self.sectors
+                    .get_mut(sector)
+                    .set_writable_bytes(sector_size_bytes.saturating_sub(used_bytes));
+            }
+
+            if sector_corrupt {
+                self.sectors.mark_corrupt(sector);
+            }
+            if self.sectors.get(sector).is_empty(sector_size_bytes) {
+                empty_sector_found = true;
+            }
+        }
+
+        // Count the valid bytes in each sector, dropping copies of entries
+        // which are no longer readable.
+        let mut entry_copies_missing = 0;
+        let mut newest_key_address = 0;
+        for index in 0..self.entry_cache.len() {
+            if self.entry_cache.addresses(index).len() < REDUNDANCY {
+                entry_copies_missing += 1;
+            }
+
+            let mut slot = 0;
+            while let Some(&address) = self.entry_cache.addresses(index).get(slot) {
+                let sector = self.sectors.index_of(address);
+                match Entry::read(&mut self.partition, address, self.formats) {
+                    Ok(entry) => {
+                        self.sectors.get_mut(sector).add_valid_bytes(entry.size());
+                        slot += 1;
+                    }
+                    Err(_) => {
+                        self.error_detected = true;
+                        self.sectors.mark_corrupt(sector);
+                        self.entry_cache.remove_address(index, address);
+                    }
+                }
+            }
+
+            let descriptor = self.entry_cache.descriptor(index);
+            if descriptor.transaction_id > self.last_transaction_id {
+                self.last_transaction_id = descriptor.transaction_id;
+                if let Some(&address) = self.entry_cache.addresses(index).last() {
+                    newest_key_address = address;
+                }
+            }
+        }
+        self.sectors.set_last_new_sector(newest_key_address);
+
+        if !empty_sector_found {
+            self.error_detected = true;
+        }
+
+        if entry_copies_missing > 0 {
+            let other_errors = self.error_detected;
+            self.error_detected = true;
+            if !other_errors && entry_copies_missing == self.entry_cache.len() {
+                return Err(Error::OutOfRange);
+            }
+        }
+
+        if self.error_detected {
+            return Err(Error::FailedPrecondition);
+        }
+        Ok(())
+    }
+
+    // Adds the entry at `address` to the entry cache, returning the address
+    // of the following entry.
+    fn load_entry(&mut self, address: Address) -> Result<Address> {
+        let entry = Entry::read(&mut self.partition, address, self.formats)?;
+        let mut key = [0u8; MAX_KEY_LENGTH];
+        Entry::read_key(&mut self.partition, address, entry.key_length(), &mut key)?;
+        entry.verify_checksum_in_flash(&mut self.partition)?;
+        self.entry_cache.add_new_or_update_existing(
+            entry.descriptor(hash(&key[..entry.key_length()])),
+            address,
+            self.partition.sector_size_bytes(),
+        )?;
+        Ok(entry.next_address())
+    }
+
+    // Finds the next entry with a known magic in `sector` at or after
+    // `start_address`.
+    fn scan_for_entry(&mut self, sector: usize, start_address: Address) -> Option<Address> {
+        let mut address = start_address.next_multiple_of(MIN_ALIGNMENT_BYTES as Address);
+        while self.sectors.address_in_sector(sector, address) {
+            let mut magic = [0u8; 4];
+            if self.partition.read(address, &mut magic).is_ok() {
+                let magic = u32::from_le_bytes(magic);
+                if self.formats.iter().any(|format| format.magic == magic) {
+                    return Some(address);
+                }
+            }
+            address += MIN_ALIGNMENT_BYTES as Address;
+        }
+        None
+    }
+
+    // Finds `key`, including deleted keys.
+    fn find_entry(&mut self, key: &str) -> Result<usize> {
+        let mut error_detected = false;
+        let result = self.entry_cache.find(
+            &mut self.partition,
+            &mut self.sectors,
+            key,
+            &mut error_detected,
+        );
+        if error_detected {
+            self.error_detected = true;
+        }
+        result
+    }
+
+    // Finds `key`, which must not be deleted.
+    fn find_existing(&mut self, key: &str) -> Result<usize> {
+        match self.find_entry(key) {
+            Ok(index) if self.entry_cache.descriptor(index).state == EntryState::Deleted => {
+                Err(Error::NotFound)
+            }
+            // A different key with the same hash.
+            Err(Error::AlreadyExists) => Err(Error::NotFound),
+            result => result,
+        }
+    }
+
+    // Reads the header of the first readable copy of a key's entry.
+    fn read_entry(&mut self, index: usize) -> Result<Entry> {
+        let mut result = Err(Error::DataLoss);
+        for slot in 0..self.entry_cache.addresses(index).len() {
+            let address = self.entry_cache.addresses(index)[slot];
+            result = Entry::read(&mut self.partition, address, self.formats);
+            if result.is_ok() {
+                break;
+            }
+            self.sectors.mark_corrupt(self.sectors.index_of(address));
+            self.error_detected = true;
+        }
+        result
+    }
+
+    fn write_entry_for_existing_key(
+        &mut self,
+        index: usize,
+        state: EntryState,
+        key: &str,
+        value: &[u8],
+    ) -> Result<()> {
+        let prior_entry = self.read_entry(index)?;
+        self.write_entry(key, value, state, Some((index, prior_entry)))
+    }
+
+    fn write_entry_for_new_key(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        // Deleted keys may be removed to make room.
+        if self.entry_cache.is_full()
+            && self.options.gc_on_write == GarbageCollectOnWrite::AsManySectorsNeeded
+        {
+            self.heavy_maintenance()?;
+        }
+        if self.entry_cache.is_full() {
+            return Err(Error::ResourceExhausted);
+        }
+        self.write_entry(key, value, EntryState::Valid, None)
+    }
+
+    fn write_entry(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        state: EntryState,
+        prior: Option<(usize, Entry)>,
+    ) -> Result<()> {
+        // Skip writing entries which would not change the store.
+        if let Some((index, prior_entry)) = prior {
+            if self.entry_cache.descriptor(index).state == state
+                && prior_entry
+                    .value_matches(&mut self.partition, value)
+                    .is_ok()
+            {
+                return Ok(());
+            }
+        }
+
+        let size = entry_size(self.partition.alignment_bytes(), key.len(), value.len());
+        let mut reserved_addresses = [NO_ADDRESS; REDUNDANCY];
+        self.get_addresses_for_write(&mut reserved_addresses, size)?;
+
+        let mut entry = self.create_entry(reserved_addresses[0], key, value, state);
+        self.append_entry(&entry, key, value)?;
+
+        let index = match prior {
+            Some((index, prior_entry)) => {
+                self.update_key_descriptor(&entry, index, prior_entry.size());
+                index
+            }
+            None => self
+                .entry_cache
+                .add_new(entry.descriptor(hash(key.as_bytes())), entry.address()),
+        };
+
+        for &address in &reserved_addresses[1..] {
+            entry.set_address(address);
+            self.append_entry(&entry, key, value)?;
+            self.entry_cache.add_address(index, address);
+        }
+        Ok(())
+    }
+
+    fn create_entry(
+        &mut self,
+        address: Address,
+        key: &str,
+        value: &[u8],
+        state: EntryState,
+    ) -> Entry {
+        self.last_transaction_id += 1;
+        Entry::new(
+            self.partition.alignment_bytes(),
+            address,
+            self.formats[0],
+            key,
+            value,
+            state,
+            self.last_transaction_id,
+        )
+    }
+
+    // Replaces a key's descriptor and addresses with those of `entry`, which
+    // replaces an entry of `prior_size` bytes.
+    fn update_key_descriptor(&mut self, entry: &Entry, index: usize, prior_size: usize) {
+        for slot in 0..self.entry_cache.addresses(index).len() {
+            let address = self.entry_cache.addresses(index)[slot];
+            let sector = self.sectors.index_of(address);
+            self.sectors.get_mut(sector).remove_valid_bytes(prior_size);
+        }
+        let key_hash = self.entry_cache.descriptor(index).key_hash;
+        self.entry_cache
+            .reset_entry(index, entry.descriptor(key_hash), entry.address());
+    }
+
+    // Finds an address in a different sector for each copy of an entry of
+    // `write_size` bytes, garbage collecting if needed.
+    fn get_addresses_for_write(
+        &mut self,
+        addresses: &mut [Address; REDUNDANCY],
+        write_size: usize,
+    ) -> Result<()> {
+        for i in 0..REDUNDANCY {
+            let sector = self.get_sector_for_write(write_size, &addresses[..i])?;
+            addresses[i] = self.sectors.next_writable_address(sector);
+        }
+        Ok(())
+    }
+
+    fn get_sector_for_write(&mut self, write_size: usize, reserved: &[Address]) -> Result<usize> {
+        let mut result = self.sectors.find_space(write_size, reserved);
+
+        let mut gc_sector_count = 0;
+        let mut do_auto_gc = self.options.gc_on_write != GarbageCollectOnWrite::Disabled;
+
+        while result == Err(Error::ResourceExhausted) && do_auto_gc {
+            if self.options.gc_on_write == GarbageCollectOnWrite::OneSector {
+                do_auto_gc = false;
+            }
+            match self.garbage_collect(reserved) {
+                Ok(()) => {}
+                // Nothing to collect, so there is no space.
+                Err(Error::NotFound) => return Err(Error::ResourceExhausted),
+                Err(e) => return Err(e),
+            }
+            result = self.sectors.find_space(write_size, reserved);
+
+            gc_sector_count += 1;
+            // Give up if garbage collection is not freeing space.
+            if gc_sector_count > self.sectors.len() + 2 {
+                return Err(Error::ResourceExhausted);
+            }
+        }
+        result
+    }
+
+    // Marks `sector` corrupt if `result` is an error.
+    fn mark_sector_corrupt_if_err<T>(&mut self, result: Result<T>, sector: usize) -> Result<T> {
+        if result.is_err() {
+            self.sectors.mark_corrupt(sector);
+            self.error_detected = true;
+        }
+        result
+    }
+
+    fn append_entry(&mut self, entry: &Entry, key: &str, value: &[u8]) -> Result<()> {
+        let sector = self.sectors.index_of(entry.address());
+        let result = entry.write(&mut self.partition, key.as_bytes(), value);
+        let written = self.mark_sector_corrupt_if_err(result, sector)?;
+
+        if self.options.verify_on_write {
+            let result = entry.verify_checksum_in_flash(&mut self.partition);
+            self.mark_sector_corrupt_if_err(result, sector)?;
+        }
+
+        let descriptor = self.sectors.get_mut(sector);
+        descriptor.remove_writable_bytes(written);
+        descriptor.add_valid_bytes(written);
+        Ok(())
+    }
+
+    fn copy_entry_to_sector(
+        &mut self,
+        entry: &Entry,
+        new_sector: usize,
+        new_address: Address,
+    ) -> Result<usize> {
+        let result = entry.copy(&mut self.partition, new_address);
+        let written = self.mark_sector_corrupt_if_err(result, new_sector)?;
+
+        if self.options.verify_on_write {
+            let result = Entry::read(&mut self.partition, new_address, self.formats);
+            let new_entry = self.mark_sector_corrupt_if_err(result, new_sector)?;
+            let result = new_entry.verify_checksum_in_flash(&mut self.partition);
+            self.mark_sector_corrupt_if_err(result, new_sector)?;
+        }
+
+        let descriptor = self.sectors.get_mut(new_sector);
+        descriptor.remove_writable_bytes(written);
+        descriptor.add_valid_bytes(written);
+        Ok(written)
+    }
+
+    // Moves the copy of a key's entry in `slot` to another sector.
+    fn relocate_entry(&mut self, index: usize, slot: usize, reserved: &[Address]) -> Result<()> {
+        let entry = self.read_entry(index)?;
+
+        // Copies of an entry must be in different sectors.
+        let new_sector = self.sectors.find_space_during_gc(
+            entry.size(),
+            self.entry_cache.addresses(index),
+            reserved,
+        )?;
+        let new_address = self.sectors.next_writable_address(new_sector);
+        let size = self.copy_entry_to_sector(&entry, new_sector, new_address)?;
+
+        let old_address = self.entry_cache.addresses(index)[slot];
+        let old_sector = self.sectors.index_of(old_address);
+        self.sectors.get_mut(old_sector).remove_valid_bytes(size);
+        self.entry_cache.set_address(index, slot, new_address);
+        Ok(())
+    }
+
+    fn garbage_collect(&mut self, reserved: &[Address]) -> Result<()> {
+        let sector = self
+            .sectors
+            .find_sector_to_gc(reserved)
+            .ok_or(Error::NotFound)?;
+        self.garbage_collect_sector(sector, reserved)
+    }
+
+    // Relocates the valid entries in `sector` and erases it.
+    fn garbage_collect_sector(&mut self, sector: usize, reserved: &[Address]) -> Result<()> {
+        if self.sectors.get(sector).valid_bytes() != 0 {
+            for index in 0..self.entry_cache.len() {
+                for slot in 0..self.entry_cache.addresses(index).len() {
+                    let address = self.entry_cache.addresses(index)[slot];
+                    if self.sectors.address_in_sector(sector, address) {
+                        self.relocate_entry(index, slot, reserved)?;
+                    }
+                }
+            }
+        }
+
+        if self.sectors.get(sector).valid_bytes() != 0 {
+            // The valid bytes are out of sync with the entry cache.
+            return Err(Error::Internal);
+        }
+
+        let sector_size_bytes = self.partition.sector_size_bytes();
+        if !self.sectors.get(sector).is_empty(sector_size_bytes) {
+            // The sector is corrupt until it is successfully erased.
+            self.sectors.mark_corrupt(sector);
+            self.stats.sector_erase_count += 1;
+            self.partition.erase(self.sectors.base_address(sector), 1)?;
+            self.sectors
+                .get_mut(sector)
+                .set_writable_bytes(sector_size_bytes);
+        }
+        Ok(())
+    }
+
+    // Removes deleted keys from the entry cache, so their entries can be
+    // garbage collected.
+    fn remove_deleted_key_entries(&mut self) -> Result<()> {
+        let mut index = 0;
+        while index < self.entry_cache.len() {
+            if self.entry_cache.descriptor(index).state != EntryState::Deleted {
+                index += 1;
+                continue;
+            }
+            let entry = self.read_entry(index)?;
+            for slot in 0..self.entry_cache.addresses(index).len() {
+                let address = self.entry_cache.addresses(index)[slot];
+                let sector = self.sectors.index_of(address);
+                self.sectors
+                    .get_mut(sector)
+                    .remove_valid_bytes(entry.size());
+            }
+            self.entry_cache.remove(index);
+        }
+        Ok(())
+    }
+
+    // Rewrites entries in formats other than the primary format, returning
+    // the number of entries rewritten.
+    fn update_entries_to_primary_format(&mut self) -> Result<usize> {
+        let primary_format = self.formats[0];
+        let alignment_bytes = self.partition.alignment_bytes();
+        let mut entries_updated = 0;
+        for index in 0..self.entry_cache.len() {
+            let entry = self.read_entry(index)?;
+            if entry.magic() == primary_format.magic {
+                continue;
+            }
+
+            let size = entry_size(alignment_bytes, entry.key_length(), entry.value_size());
+            let mut new_addresses = [NO_ADDRESS; REDUNDANCY];
+            self.get_addresses_for_write(&mut new_addresses, size)?;
+
+            // Garbage collection may have relocated the entry.
+            let mut entry = self.read_entry(index)?;
+            let prior_size = entry.size();
+            self.last_transaction_id += 1;
+            entry.update(
+                &mut self.partition,
+                primary_format,
+                self.last_transaction_id,
+                alignment_bytes,
+            )?;
+
+            for (i, &address) in new_addresses.iter().enumerate() {
+                self.copy_entry_to_sector(&entry, self.sectors.index_of(address), address)?;
+                if i == 0 {
+                    let mut new_entry = entry;
+                    new_entry.set_address(address);
+                    self.update_key_descriptor(&new_entry, index, prior_size);
+                } else {
+                    self.entry_cache.add_address(index, address);
+                }
+            }
+            entries_updated += 1;
+        }
+        Ok(entries_updated)
+    }
+
+    // Writes copies of a key's entry until it has `REDUNDANCY` copies.
+    fn add_redundant_entries(&mut self, index: usize) -> Result<()> {
+        let entry = self.read_entry(index)?;
+        entry.verify_checksum_in_flash(&mut self.partition)?;
+
+        while self.entry_cache.addresses(index).len() < REDUNDANCY {
+            // Garbage collection avoids the sectors of the existing copies,
+            // so `entry` is not moved.
+            let addresses = self.entry_cache.address_slots(index);
+            let new_sector = self.get_sector_for_write(entry.size(), used_addresses(&addresses))?;
+            let new_address = self.sectors.next_writable_address(new_sector);
+            self.copy_entry_to_sector(&entry, new_sector, new_address)?;
+            self.entry_cache.add_address(index, new_address);
+        }
+        Ok(())
+    }
+
+    fn repair_corrupt_sectors(&mut self) -> Result<()> {
+        // Repairing a sector can fail for lack of space until others are
+        // repaired, so make two passes.
+        let mut repair_status = Ok(());
+        for _ in 0..2 {
+            repair_status = Ok(());
+            for sector in 0..self.sectors.len() {
+                if !self.sectors.get(sector).corrupt() {
+                    continue;
+                }
+                match self.garbage_collect_sector(sector, &[]) {
+                    Ok(()) => self.stats.corrupt_sectors_recovered += 1,
+                    Err(e) => {
+                        if repair_status.is_ok() {
+                            repair_status = Err(e);
+                        }
+                    }
+                }
+            }
+            if repair_status != Err(Error::ResourceExhausted) {
+                break;
+            }
+        }
+        repair_status
+    }
+
+    fn ensure_free_sector_exists(&mut self) -> Result<()> {
+        let sector_size_bytes = self.partition.sector_size_bytes();
+        if self
+            .sectors
+            .iter()
+            .any(|sector| sector.is_empty(sector_size_bytes))
+        {
+            return Ok(());
+        }
+        self.garbage_collect(&[])
+            .map_err(|_| Error::FailedPrecondition)
+    }
+
+    fn ensure_entry_redundancy(&mut self) -> Result<()> {
+        let mut repair_status = Ok(());
+        if REDUNDANCY == 1 {
+            return repair_status;
+        }
+        for index in 0..self.entry_cache.len() {
+            if self.entry_cache.addresses(index).len() >= REDUNDANCY {
+                continue;
+            }
+            match self.add_redundant_entries(index) {
+                Ok(()) => self.stats.missing_redundant_entries_recovered += 1,
+                Err(e) => {
+                    if repair_status.is_ok() {
+                        repair_status = Err(e);
+                    }
+                }
+            }
+        }
+        repair_status
+    }
+
+    fn fix_errors(&mut self) -> Result<()> {
+        let mut overall_status = self.repair_corrupt_sectors();
+        let free_sector_status = self.ensure_free_sector_exists();
+        let redundancy_status = self.ensure_entry_redundancy();
+        for status in [free_sector_status, redundancy_status] {
+            if overall_status.is_ok() {
+                overall_status = status;
+            }
+        }
+
+        if overall_status.is_ok() {
+            self.error_detected = false;
+            self.initialized = InitializationState::Ready;
+        }
+        overall_status
+    }
+
+    // Reloads the entries from flash and repairs any errors.
+    fn repair(&mut self) -> Result<()> {
+        // Errors are expected here; they are repaired below.
+        let _ = self.initialize_metadata();
+        self.fix_errors()
+    }
+
+    fn read_key(&mut self, index: usize) -> Result<KeyBuffer> {
+        let entry = self.read_entry(index)?;
+        let mut key = KeyBuffer {
+            bytes: [0; MAX_KEY_LENGTH],
+            len: entry.key_length(),
+        };
+        Entry::read_key(
+            &mut self.partition,
+            entry.address(),
+            key.len,
+            &mut key.bytes,
+        )?;
+        core::str::from_utf8(&key.bytes[..key.len]).map_err(|_| Error::DataLoss)?;
+        Ok(key)
+    }
+}
+
+/// An iterator over the keys in a [`KeyValueStore`], returned by
+/// [`KeyValueStore::keys()`].
+///
+/// Keys which can not be read are returned as errors.
+pub struct Keys<
+    'k,
+    'a,
+    F: FlashPartition,
+    const MAX_ENTRIES: usize,
+    const MAX_SECTORS: usize,
+    const REDUNDANCY: usize,
+> {
+    kvs: &'k mut KeyValueStore<'a, F, MAX_ENTRIES, MAX_SECTORS, REDUNDANCY>,
+    index: usize,
+}
+
+impl<
+        F: FlashPartition,
+        const MAX_ENTRIES: usize,
+        const MAX_SECTORS: usize,
+        const REDUNDANCY: usize,
+    > Iterator for Keys<'_, '_, F, MAX_ENTRIES, MAX_SECTORS, REDUNDANCY>
+{
+    type Item = Result<KeyBuffer>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.kvs.entry_cache.len() {
+            let index = self.index;
+            self.index += 1;
+            if self.kvs.entry_cache.descriptor(index).state == EntryState::Valid {
+                return Some(self.kvs.read_key(index));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::{EntryChecksum, FakeFlash};
+
+    const SECTOR_SIZE: usize = 512;
+    const FLASH_SIZE: usize = 4 * SECTOR_SIZE;
+    const MAGIC: u32 = 0x5ab2f0b5;
+
+    type Flash = FakeFlash<FLASH_SIZE>;
+
+    // The checksum used by the C++ binary format tests.
+    fn simple_checksum(state: u32, data: &[u8]) -> u32 {
+        data.iter()
+            .fold(state, |state, &b| state.wrapping_add(b.into()))
+    }
+
+    const SIMPLE_FORMAT: EntryFormat = EntryFormat {
+        magic: MAGIC,
+        checksum: EntryChecksum::Custom(simple_checksum),
+    };
+
+    const CRC16_FORMAT: EntryFormat = EntryFormat {
+        magic: 0xc4a1ce07,
+        checksum: EntryChecksum::Crc16,
+    };
+
+    // `kEntry1` and `kEntry2` from the C++ binary format tests.
+    const CPP_ENTRY_1: [u8; 32] = [
+        0xb5, 0xf0, 0xb2, 0x5a, 0x84, 0x06, 0x00, 0x00, 0x00, 0x04, 0x06, 0x00, 0x01, 0x00, 0x00,
+        0x00, 0x6b, 0x65, 0x79, 0x31, 0x76, 0x61, 0x6c, 0x75, 0x65, 0x31, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00,
+    ];
+    const CPP_ENTRY_2: [u8; 32] = [
+        0xb5, 0xf0, 0xb2, 0x5a, 0xa8, 0x05, 0x00, 0x00, 0x00, 0x02, 0x06, 0x00, 0x03, 0x00, 0x00,
+        0x00, 0x6b, 0x32, 0x76, 0x61, 0x6c, 0x75, 0x65, 0x32, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00,
+    ];
+
+    fn get<F: FlashPartition, const MAX_ENTRIES: usize, const REDUNDANCY: usize>(
+        kvs: &mut KeyValueStore<'_, F, MAX_ENTRIES, 4, REDUNDANCY>,
+        key: &str,
+    ) -> Result<Vec<u8>> {
+        let mut value = [0u8; SECTOR_SIZE];
+        let len = kvs.get(key, &mut value)?;
+        Ok(value[..len].to_vec())
+    }
+
+    #[test]
+    fn put_get_and_delete() {
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[CRC16_FORMAT], Options::DEFAULT);
+        assert_eq!(kvs.put("key", b"value"), Err(Error::FailedPrecondition));
+        kvs.init().unwrap();
+        assert!(kvs.is_empty());
+
+        kvs.put("key", b"value").unwrap();
+        assert_eq!(get(&mut kvs, "key"), Ok(b"value".to_vec()));
+        assert_eq!(kvs.value_size("key"), Ok(5));
+        kvs.put("key", b"new value").unwrap();
+        assert_eq!(get(&mut kvs, "key"), Ok(b"new value".to_vec()));
+        assert_eq!(kvs.size(), 1);
+        assert_eq!(kvs.transaction_count(), 2);
+
+        // Writing the current value is skipped.
+        kvs.put("key", b"new value").unwrap();
+        assert_eq!(kvs.transaction_count(), 2);
+
+        kvs.delete("key").unwrap();
+        assert_eq!(get(&mut kvs, "key"), Err(Error::NotFound));
+        assert_eq!(kvs.delete("key"), Err(Error::NotFound));
+        assert_eq!(kvs.delete("other"), Err(Error::NotFound));
+        assert!(kvs.is_empty());
+        assert_eq!(kvs.total_entries_with_deleted(), 1);
+    }
+
+    #[test]
+    fn partial_reads() {
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[CRC16_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        kvs.put("key", b"0123456789").unwrap();
+
+        let mut value = [0u8; 4];
+        assert_eq!(kvs.get("key", &mut value), Err(Error::ResourceExhausted));
+        assert_eq!(&value, b"0123");
+        assert_eq!(kvs.get_at("key", 8, &mut value), Ok(2));
+        assert_eq!(&value[..2], b"89");
+        assert_eq!(kvs.get_at("key", 11, &mut value), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[CRC16_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+
+        let long_key = "k".repeat(MAX_KEY_LENGTH + 1);
+        assert_eq!(kvs.put("", b"value"), Err(Error::InvalidArgument));
+        assert_eq!(kvs.put(&long_key, b"value"), Err(Error::InvalidArgument));
+        assert_eq!(kvs.put(&long_key[1..], b"value"), Ok(()));
+        assert_eq!(kvs.value_size(&long_key), Err(Error::InvalidArgument));
+
+        let max_value = [0u8; SECTOR_SIZE - EntryHeader::SIZE - 3];
+        assert_eq!(kvs.max_key_value_size_bytes(), max_value.len() + 3);
+        assert_eq!(kvs.put("big", &max_value), Ok(()));
+        assert_eq!(
+            kvs.put("big", &[0; SECTOR_SIZE]),
+            Err(Error::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn entries_persist_across_init() {
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[CRC16_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        kvs.put("a", b"1").unwrap();
+        kvs.put("b", b"2").unwrap();
+        kvs.put("a", b"3").unwrap();
+        kvs.put("c", b"4").unwrap();
+        kvs.delete("c").unwrap();
+
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[CRC16_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        assert_eq!(kvs.transaction_count(), 5);
+        assert_eq!(get(&mut kvs, "a"), Ok(b"3".to_vec()));
+        assert_eq!(get(&mut kvs, "b"), Ok(b"2".to_vec()));
+        assert_eq!(get(&mut kvs, "c"), Err(Error::NotFound));
+
+        let mut keys: Vec<_> = kvs.keys().map(|key| key.unwrap().to_string()).collect();
+        keys.sort();
+        assert_eq!(keys, ["a", "b"]);
+        assert_eq!(
+            kvs.storage_stats().in_use_bytes,
+            3 * 32,
+            "one entry each for a, b, and deleted c"
+        );
+    }
+
+    #[test]
+    fn reads_entries_written_by_cpp() {
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        flash.buffer_mut()[..32].copy_from_slice(&CPP_ENTRY_1);
+        flash.buffer_mut()[32..64].copy_from_slice(&CPP_ENTRY_2);
+
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[SIMPLE_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        assert_eq!(kvs.size(), 2);
+        assert_eq!(kvs.transaction_count(), 3);
+        assert_eq!(get(&mut kvs, "key1"), Ok(b"value1".to_vec()));
+        assert_eq!(get(&mut kvs, "k2"), Ok(b"value2".to_vec()));
+
+        // New entries are appended to the partially used sector.
+        kvs.put("k2", b"value3").unwrap();
+        assert_eq!(flash.buffer()[64..68], MAGIC.to_le_bytes());
+    }
+
+    #[test]
+    fn writes_entries_readable_by_cpp() {
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[SIMPLE_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        kvs.put("key1", b"value1").unwrap();
+        // The first sector after the most recently used one is used.
+        assert_eq!(flash.buffer()[SECTOR_SIZE..SECTOR_SIZE + 32], CPP_ENTRY_1);
+
+        // The CRC16 of the entry occupies the low bytes of the checksum.
+        const FORMAT: EntryFormat = EntryFormat {
+            magic: MAGIC,
+            checksum: EntryChecksum::Crc16,
+        };
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        kvs.put("key1", b"value1").unwrap();
+        let mut expected = CPP_ENTRY_1;
+        expected[4..8].copy_from_slice(&[0x3e, 0x97, 0x00, 0x00]);
+        assert_eq!(flash.buffer()[SECTOR_SIZE..SECTOR_SIZE + 32], expected);
+    }
+
+    #[test]
+    fn garbage_collection_reclaims_space() {
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[CRC16_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        kvs.put("constant", b"unchanged").unwrap();
+
+        // Each entry uses 128 bytes, so the partition fills many times over.
+        for i in 0..100u8 {
+            kvs.put("counter", &[i; 100]).unwrap();
+        }
+        assert_eq!(get(&mut kvs, "counter"), Ok([99; 100].to_vec()));
+        assert_eq!(get(&mut kvs, "constant"), Ok(b"unchanged".to_vec()));
+        let stats = kvs.storage_stats();
+        assert!(stats.sector_erase_count > 0);
+        assert_eq!(stats.in_use_bytes, 128 + 48);
+
+        kvs.heavy_maintenance().unwrap();
+        let stats = kvs.storage_stats();
+        assert_eq!(stats.reclaimable_bytes, 0);
+        // One empty sector is kept for garbage collection.
+        assert_eq!(stats.writable_bytes, 3 * SECTOR_SIZE - stats.in_use_bytes);
+    }
+
+    #[test]
+    fn writes_fail_when_full_without_garbage_collection() {
+        let options = Options {
+            gc_on_write: GarbageCollectOnWrite::Disabled,
+            ..Options::DEFAULT
+        };
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[CRC16_FORMAT], options);
+        kvs.init().unwrap();
+
+        // Three sectors are writable, with one kept for garbage collection.
+        for i in 0..12u8 {
+            kvs.put("counter", &[i; 100]).unwrap();
+        }
+        assert_eq!(
+            kvs.put("counter", &[12; 100]),
+            Err(Error::ResourceExhausted)
+        );
+
+        kvs.partial_maintenance().unwrap();
+        kvs.put("counter", &[12; 100]).unwrap();
+        assert_eq!(get(&mut kvs, "counter"), Ok([12; 100].to_vec()));
+    }
+
+    #[test]
+    fn deleted_keys_are_removed_to_make_room() {
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        let mut kvs = KeyValueStore::<_, 2, 4>::new(&mut flash, &[CRC16_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        kvs.put("a", b"1").unwrap();
+        kvs.put("b", b"2").unwrap();
+        assert_eq!(kvs.put("c", b"3"), Err(Error::ResourceExhausted));
+
+        kvs.delete("a").unwrap();
+        kvs.put("c", b"3").unwrap();
+        assert_eq!(kvs.total_entries_with_deleted(), 2);
+        assert_eq!(get(&mut kvs, "b"), Ok(b"2".to_vec()));
+        assert_eq!(get(&mut kvs, "c"), Ok(b"3".to_vec()));
+    }
+
+    #[test]
+    fn redundant_copies_repair_corruption() {
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        let mut kvs =
+            KeyValueStore::<_, 8, 4, 2>::new(&mut flash, &[CRC16_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        kvs.put("key", b"value").unwrap();
+        assert_eq!(kvs.storage_stats().in_use_bytes, 2 * 32);
+
+        // Corrupt the value of the first copy.
+        flash.buffer_mut()[SECTOR_SIZE + EntryHeader::SIZE + 3] ^= 0x01;
+
+        let options = Options {
+            recovery: ErrorRecovery::Manual,
+            ..Options::DEFAULT
+        };
+        let mut kvs = KeyValueStore::<_, 8, 4, 2>::new(&mut flash, &[CRC16_FORMAT], options);
+        assert_eq!(kvs.init(), Err(Error::DataLoss));
+        assert!(kvs.error_detected());
+        assert_eq!(kvs.put("key", b"other"), Err(Error::FailedPrecondition));
+        assert_eq!(get(&mut kvs, "key"), Ok(b"value".to_vec()));
+
+        kvs.full_maintenance().unwrap();
+        assert!(!kvs.error_detected());
+        let stats = kvs.storage_stats();
+        assert_eq!(stats.corrupt_sectors_recovered, 1);
+        assert_eq!(stats.missing_redundant_entries_recovered, 1);
+        assert_eq!(stats.in_use_bytes, 2 * 32);
+
+        // Errors are repaired on initialization by default.
+        flash.buffer_mut()[SECTOR_SIZE + EntryHeader::SIZE + 3] ^= 0x01;
+        let mut kvs =
+            KeyValueStore::<_, 8, 4, 2>::new(&mut flash, &[CRC16_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        assert_eq!(get(&mut kvs, "key"), Ok(b"value".to_vec()));
+    }
+
+    #[test]
+    fn entries_are_rewritten_in_primary_format() {
+        let mut flash = Flash::new(SECTOR_SIZE, 16);
+        flash.buffer_mut()[..32].copy_from_slice(&CPP_ENTRY_1);
+        flash.buffer_mut()[32..64].copy_from_slice(&CPP_ENTRY_2);
+
+        let formats = [CRC16_FORMAT, SIMPLE_FORMAT];
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &formats, Options::DEFAULT);
+        kvs.init().unwrap();
+        assert_eq!(get(&mut kvs, "key1"), Ok(b"value1".to_vec()));
+        kvs.full_maintenance().unwrap();
+
+        let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[CRC16_FORMAT], Options::DEFAULT);
+        kvs.init().unwrap();
+        assert_eq!(get(&mut kvs, "key1"), Ok(b"value1".to_vec()));
+        assert_eq!(get(&mut kvs, "k2"), Ok(b"value2".to_vec()));
+    }
+}
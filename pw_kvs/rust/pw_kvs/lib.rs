@@ -0,0 +1,79 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_kvs` is a flash-backed, persistent key-value store with wear leveling.
+//!
+//! A [`KeyValueStore`] appends entries to the sectors of a
+//! [`FlashPartition`], spreading writes across the partition, and garbage
+//! collects sectors to reclaim the space used by old entries.  Each entry can
+//! be written to multiple sectors for redundancy, and corrupt sectors are
+//! repaired from the remaining copies.
+//!
+//! ```
+//! use pw_kvs::{EntryChecksum, EntryFormat, FakeFlash, KeyValueStore, Options};
+//!
+//! const FORMAT: EntryFormat = EntryFormat {
+//!     magic: 0xd253a8a9,
+//!     checksum: EntryChecksum::Crc16,
+//! };
+//!
+//! let mut flash = FakeFlash::<2048>::new(512, 16);
+//! let mut kvs = KeyValueStore::<_, 8, 4>::new(&mut flash, &[FORMAT], Options::default());
+//! kvs.init()?;
+//!
+//! kvs.put("greeting", b"hello")?;
+//! let mut value = [0u8; 16];
+//! let len = kvs.get("greeting", &mut value)?;
+//! assert_eq!(&value[..len], b"hello");
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! # Compatibility with C++
+//!
+//! Entries are stored in the same format as the C++ `pw_kvs` module, so a
+//! store written by C++ firmware can be read and updated after migrating to
+//! Rust, and vice versa, given the same [`EntryFormat`]s and redundancy.
+//! [`EntryChecksum::Crc16`] corresponds to C++'s `ChecksumCrc16`, and other
+//! checksums can be implemented with [`EntryChecksum::Custom`].
+//!
+//! Formats are identified by their magic number.  To change the format of a
+//! store, put the new format first in the formats a store is created with,
+//! followed by the old formats.  New entries are written in the first format
+//! and existing entries are rewritten in it by
+//! [`KeyValueStore::full_maintenance()`].
+#![no_std]
+#![deny(missing_docs)]
+
+mod entry;
+mod entry_cache;
+mod flash;
+mod format;
+mod key_value_store;
+mod sectors;
+
+pub use flash::{FakeFlash, FlashPartition};
+pub use format::{EntryChecksum, EntryFormat};
+pub use key_value_store::{
+    ErrorRecovery, GarbageCollectOnWrite, KeyBuffer, KeyValueStore, Keys, Options, StorageStats,
+};
+
+/// An address in a [`FlashPartition`], relative to its start.
+pub type Address = u32;
+
+/// The maximum length of a key in bytes.
+pub const MAX_KEY_LENGTH: usize = 0b111111;
+
+/// The maximum write alignment of a [`FlashPartition`] supported by a
+/// [`KeyValueStore`].
+pub const MAX_FLASH_ALIGNMENT: usize = 256;
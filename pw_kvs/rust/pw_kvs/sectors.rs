@@ -0,0 +1,336 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_status::{Error, Result};
+
+use crate::Address;
+
+// Sizes are stored in 16 bits, with all ones marking a corrupt sector.
+pub(crate) const MAX_SECTOR_SIZE_BYTES: usize = u16::MAX as usize - 1;
+const CORRUPT_SECTOR: u16 = u16::MAX;
+
+// Tracks the space used in a sector.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct SectorDescriptor {
+    // Bytes after the last entry in the sector.
+    tail_free_bytes: u16,
+    // Bytes used by the newest entries for each key.
+    valid_bytes: u16,
+}
+
+impl SectorDescriptor {
+    const EMPTY: Self = Self {
+        tail_free_bytes: 0,
+        valid_bytes: 0,
+    };
+
+    pub(crate) fn writable_bytes(&self) -> usize {
+        if self.corrupt() {
+            0
+        } else {
+            self.tail_free_bytes.into()
+        }
+    }
+
+    pub(crate) fn valid_bytes(&self) -> usize {
+        self.valid_bytes.into()
+    }
+
+    pub(crate) fn corrupt(&self) -> bool {
+        self.tail_free_bytes == CORRUPT_SECTOR
+    }
+
+    pub(crate) fn has_space(&self, size: usize) -> bool {
+        self.writable_bytes() >= size
+    }
+
+    pub(crate) fn is_empty(&self, sector_size_bytes: usize) -> bool {
+        !self.corrupt() && self.valid_bytes == 0 && self.writable_bytes() == sector_size_bytes
+    }
+
+    // Bytes which garbage collection can reclaim.
+    pub(crate) fn recoverable_bytes(&self, sector_size_bytes: usize) -> usize {
+        sector_size_bytes - self.valid_bytes() - self.writable_bytes()
+    }
+
+    pub(crate) fn set_writable_bytes(&mut self, bytes: usize) {
+        self.tail_free_bytes = bytes as u16;
+    }
+
+    pub(crate) fn remove_writable_bytes(&mut self, bytes: usize) {
+        self.tail_free_bytes = self.writable_bytes().saturating_sub(bytes) as u16;
+    }
+
+    pub(crate) fn add_valid_bytes(&mut self, bytes: usize) {
+        self.valid_bytes = self.valid_bytes.saturating_add(bytes as u16);
+    }
+
+    pub(crate) fn remove_valid_bytes(&mut self, bytes: usize) {
+        self.valid_bytes = self.valid_bytes.saturating_sub(bytes as u16);
+    }
+}
+
+// The descriptors of each sector in a partition.
+//
+// Sectors are searched in a wear leveling order starting after the sector most
+// recently selected for new data.
+pub(crate) struct Sectors<const MAX_SECTORS: usize> {
+    descriptors: [SectorDescriptor; MAX_SECTORS],
+    len: usize,
+    sector_size_bytes: usize,
+    last_new: usize,
+}
+
+impl<const MAX_SECTORS: usize> Sectors<MAX_SECTORS> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            descriptors: [SectorDescriptor::EMPTY; MAX_SECTORS],
+            len: 0,
+            sector_size_bytes: 0,
+            last_new: 0,
+        }
+    }
+
+    // Sets all `len` sectors to empty.
+    pub(crate) fn reset(&mut self, len: usize, sector_size_bytes: usize) {
+        self.len = len;
+        self.sector_size_bytes = sector_size_bytes;
+        self.last_new = 0;
+        for descriptor in &mut self.descriptors[..len] {
+            descriptor.tail_free_bytes = sector_size_bytes as u16;
+            descriptor.valid_bytes = 0;
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn get(&self, index: usize) -> &SectorDescriptor {
+        &self.descriptors[index]
+    }
+
+    pub(crate) fn get_mut(&mut self, index: usize) -> &mut SectorDescriptor {
+        &mut self.descriptors[index]
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &SectorDescriptor> {
+        self.descriptors[..self.len].iter()
+    }
+
+    pub(crate) fn mark_corrupt(&mut self, index: usize) {
+        self.descriptors[index].tail_free_bytes = CORRUPT_SECTOR;
+    }
+
+    pub(crate) fn index_of(&self, address: Address) -> usize {
+        address as usize / self.sector_size_bytes
+    }
+
+    pub(crate) fn base_address(&self, index: usize) -> Address {
+        (index * self.sector_size_bytes) as Address
+    }
+
+    pub(crate) fn address_in_sector(&self, index: usize, address: Address) -> bool {
+        self.index_of(address) == index
+    }
+
+    pub(crate) fn next_writable_address(&self, index: usize) -> Address {
+        self.base_address(index + 1) - self.descriptors[index].writable_bytes() as Address
+    }
+
+    pub(crate) fn last_new(&self) -> usize {
+        self.last_new
+    }
+
+    pub(crate) fn set_last_new_sector(&mut self, address: Address) {
+        self.last_new = self.index_of(address);
+    }
+
+    // Finds a sector with `size` writable bytes for a new entry, avoiding the
+    // sectors of `reserved` addresses.
+    //
+    // Returns `ResourceExhausted` if no sector has space.
+    pub(crate) fn find_space(&mut self, size: usize, reserved: &[Address]) -> Result<usize> {
+        self.find(size, &[], reserved, false)
+    }
+
+    // Finds a sector to relocate an entry with addresses `skip` to during
+    // garbage collection.
+    pub(crate) fn find_space_during_gc(
+        &mut self,
+        size: usize,
+        skip: &[Address],
+        reserved: &[Address],
+    ) -> Result<usize> {
+        self.find(size, skip, reserved, true)
+    }
+
+    fn find(
+        &mut self,
+        size: usize,
+        skip: &[Address],
+        reserved: &[Address],
+        during_gc: bool,
+    ) -> Result<usize> {
+        let sector_size_bytes = self.sector_size_bytes;
+        let skipped = |sectors: &Self, index: usize| {
+            skip.iter()
+                .chain(reserved)
+                .any(|&address| sectors.address_in_sector(index, address))
+        };
+
+        // Outside of garbage collection, an empty sector is only used if
+        // another remains for garbage collection.
+        let mut first_empty = None;
+        let mut empty_count = 0;
+        let mut least_recoverable: Option<usize> = None;
+
+        // Partially used sectors are preferred over empty ones.
+        for index in self.wear_leveled_order() {
+            if skipped(self, index) {
+                continue;
+            }
+            let descriptor = self.descriptors[index];
+            if descriptor.is_empty(sector_size_bytes) {
+                first_empty.get_or_insert(index);
+                empty_count += 1;
+                continue;
+            }
+            if !descriptor.has_space(size) {
+                continue;
+            }
+            // During garbage collection, sectors which will need collecting
+            // themselves are a last resort.
+            let recoverable_bytes = descriptor.recoverable_bytes(sector_size_bytes);
+            if !during_gc || recoverable_bytes == 0 {
+                return Ok(index);
+            }
+            if least_recoverable
+                .filter(|&least| {
+                    self.descriptors[least].recoverable_bytes(sector_size_bytes)
+                        <= recoverable_bytes
+                })
+                .is_none()
+            {
+                least_recoverable = Some(index);
+            }
+        }
+
+        if let Some(index) = first_empty {
+            if during_gc || empty_count > 1 {
+                self.last_new = index;
+                return Ok(index);
+            }
+        }
+
+        least_recoverable.ok_or(Error::ResourceExhausted)
+    }
+
+    // Finds the sector which garbage collection reclaims the most space from
+    // for the least work, avoiding the sectors of `reserved` addresses.
+    pub(crate) fn find_sector_to_gc(&self, reserved: &[Address]) -> Option<usize> {
+        let sector_size_bytes = self.sector_size_bytes;
+        let candidates = || {
+            self.wear_leveled_order().filter(|&index| {
+                !reserved
+                    .iter()
+                    .any(|&address| self.address_in_sector(index, address))
+            })
+        };
+
+        // Sectors with no valid entries are erased without relocating any.
+        if let Some(index) = candidates().find(|&index| {
+            let descriptor = &self.descriptors[index];
+            descriptor.valid_bytes() == 0 && descriptor.recoverable_bytes(sector_size_bytes) > 0
+        }) {
+            return Some(index);
+        }
+
+        let most = |key: fn(&SectorDescriptor, usize) -> usize| {
+            let mut best: Option<(usize, usize)> = None;
+            for index in candidates() {
+                let value = key(&self.descriptors[index], sector_size_bytes);
+                if value > 0
+                    && best
+                        .filter(|&(_, best_value)| best_value >= value)
+                        .is_none()
+                {
+                    best = Some((index, value));
+                }
+            }
+            best.map(|(index, _)| index)
+        };
+
+        most(|descriptor, sector_size_bytes| descriptor.recoverable_bytes(sector_size_bytes))
+            // If no sector has reclaimable space, free a sector by relocating
+            // its entries.
+            .or_else(|| most(|descriptor, _| descriptor.valid_bytes()))
+    }
+
+    // Indices of sectors starting after the last sector used for new data.
+    fn wear_leveled_order(&self) -> impl Iterator<Item = usize> {
+        let len = self.len;
+        let start = self.last_new + 1;
+        (0..len).map(move |i| (start + i) % len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTOR_SIZE: usize = 512;
+
+    #[test]
+    fn partially_used_sectors_are_preferred() {
+        let mut sectors = Sectors::<4>::new();
+        sectors.reset(4, SECTOR_SIZE);
+        sectors.get_mut(2).remove_writable_bytes(100);
+        sectors.get_mut(2).add_valid_bytes(100);
+        assert_eq!(sectors.find_space(64, &[]), Ok(2));
+        assert_eq!(sectors.next_writable_address(2), 1124);
+
+        // The sector of a reserved address is avoided, in favor of the first
+        // empty sector after the last new sector.
+        assert_eq!(sectors.find_space(64, &[1124]), Ok(1));
+        assert_eq!(sectors.last_new(), 1);
+    }
+
+    #[test]
+    fn last_empty_sector_is_kept_for_garbage_collection() {
+        let mut sectors = Sectors::<2>::new();
+        sectors.reset(2, SECTOR_SIZE);
+        sectors.get_mut(0).set_writable_bytes(0);
+        sectors.get_mut(0).add_valid_bytes(200);
+        assert_eq!(sectors.find_space(64, &[]), Err(Error::ResourceExhausted));
+        assert_eq!(sectors.find_space_during_gc(64, &[], &[]), Ok(1));
+    }
+
+    #[test]
+    fn sector_with_most_reclaimable_space_is_collected() {
+        let mut sectors = Sectors::<4>::new();
+        sectors.reset(4, SECTOR_SIZE);
+        assert_eq!(sectors.find_sector_to_gc(&[]), None);
+
+        for (index, valid_bytes) in [(0, 400), (1, 100), (2, 300)] {
+            sectors.get_mut(index).set_writable_bytes(0);
+            sectors.get_mut(index).add_valid_bytes(valid_bytes);
+        }
+        assert_eq!(sectors.find_sector_to_gc(&[]), Some(1));
+        assert_eq!(sectors.find_sector_to_gc(&[600]), Some(2));
+
+        sectors.get_mut(0).remove_valid_bytes(400);
+        assert_eq!(sectors.find_sector_to_gc(&[]), Some(0));
+    }
+}
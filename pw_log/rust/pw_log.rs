@@ -31,17 +31,102 @@
 //!   "flat", "quarter", "rift");
 //! ```
 //!
+//! Each log level also has a shorthand macro:
+//!
+//! ```
+//! pw_log::info!("Log Fact: A cord of wood is %d cubic feet.", 128);
+//! pw_log::warn!("Log Fact: Untreated logs left outside will %s.", "rot");
+//! ```
+//!
 //! Today `printf` style format strings are supported with Rust
 //! [`core::fmt`]/`println!()` style strings planned
 //! ([b/311232607](https://issues.pigweed.dev/issues/311232607)).
 //!
 //! TODO: <pwbug.dev/311266298> - Document `pw_log`'s backend API.
 //!
-//! TODO: <pwbug.dev/311232605> - Document how to configure facade backends.
+//! Log messages below a minimum level can be removed at compile time by
+//! enabling one of the `log_level_info`, `log_level_warn`, `log_level_error`,
+//! `log_level_critical`, or `log_level_fatal` features.  In Bazel, these are
+//! enabled with `--define=pw_log_level=<level>`.  See [`MIN_LOG_LEVEL`].
+//!
+//! Messages can also be filtered at runtime, either globally with
+//! [`set_default_level()`] or for individual modules with
+//! [`set_module_level()`]:
+//!
+//! ```
+//! use pw_log::LogLevel;
+//!
+//! // Only log warnings and above, except from the sensor driver.
+//! pw_log::set_default_level(LogLevel::Warn);
+//! pw_log::set_module_level("my_app::sensor", LogLevel::Debug).unwrap();
+//! # pw_log::set_default_level(LogLevel::Debug);
+//! ```
+//!
+//! Messages may end with key-value fields.  Keys are added to the format
+//! string with `pw_log_tokenized`'s `■key♦` field syntax, so with the
+//! tokenized backend the host decodes each field by name rather than as a
+//! positional argument.  As with `%v` conversions, values must be cast to
+//! their type:
+//!
+//! ```
+//! let temp = 22;
+//! pw_log::info!("Sensor reading", sensor_id = 3 as u32, temp = temp as i32);
+//! ```
+//!
+//! Projects can define their own levels, such as finer grained trace levels,
+//! with [`custom_level!`], which defines a log macro for each level.  Each
+//! custom level is logged at one of the standard levels and can be removed at
+//! compile time independently of the others:
+//!
+//! ```
+//! pw_log::custom_level!(trace1, "TRACE1", debug);
+//! pw_log::custom_level!(trace2, "TRACE2", debug, cfg(feature = "trace2"));
+//!
+//! trace1!("Opening connection %d", 7 as i32);
+//! trace2!("Sending %d bytes", 128 as i32);
+//! ```
+//!
+//! Context, such as a connection ID, can be attached to every message logged
+//! within a scope with [`context::push()`].  Backends which support context
+//! append its fields to each message:
+//!
+//! ```
+//! let _context = pw_log::context::push("conn_id", 3).unwrap();
+//! pw_log::info!("Connected");
+//! ```
+//!
+//! Log statements on hot paths can be rate limited.  Each call site counts
+//! its own calls and notes how many messages were suppressed when it next
+//! logs:
+//!
+//! ```
+//! use core::time::Duration;
+//!
+//! for sample in 0..1000 {
+//!     // Logs samples 0, 100, 200, ...
+//!     pw_log::info_every_n!(100, "Sample %d", sample as i32);
+//!     // Logs at most once per second of the `pw_chrono` system clock.
+//!     pw_log::warn_every_interval!(Duration::from_secs(1), "Buffer overrun");
+//! }
+//! ```
+//!
+//! The backend is selected at build time with the
+//! `//pw_log/rust:pw_log_backend` label flag, which defaults to the
+//! `println` backend:
+//!
+//! ```text
+//! bazel build --//pw_log/rust:pw_log_backend=//my_project:my_log_backend //...
+//! ```
 #![cfg_attr(not(feature = "std"), no_std)]
 #![deny(missing_docs)]
 
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use core::time::Duration;
+
 pub use pw_log_backend_api::LogLevel;
+pub use pw_log_context as context;
+use pw_status::{Error, Result};
 
 // Re-export dependences of `pw_log` macros to be accessed via `$crate::__private`.
 #[doc(hidden)]
@@ -51,6 +136,319 @@ pub mod __private {
     pub use pw_log_backend::pw_logf_backend;
 }
 
+/// The minimum level of log messages emitted by `pw_log`.
+///
+/// Defaults to [`LogLevel::Debug`] and is raised by enabling one of the
+/// `log_level_<level>` features.  Each of these features must also enable the
+/// features for the levels below it, which the Bazel build does
+/// automatically.  If more than one is enabled, the highest level is used.
+///
+/// The level specific macros, such as [`pw_log_debugf!`] and [`info!`], expand
+/// to nothing when their level is below `MIN_LOG_LEVEL`.  Their arguments are
+/// not evaluated and their format strings are not added to the token
+/// database.  [`pw_logf!`] checks its level when called instead.
+pub const MIN_LOG_LEVEL: LogLevel = if cfg!(feature = "log_level_fatal") {
+    LogLevel::Fatal
+} else if cfg!(feature = "log_level_critical") {
+    LogLevel::Critical
+} else if cfg!(feature = "log_level_error") {
+    LogLevel::Error
+} else if cfg!(feature = "log_level_warn") {
+    LogLevel::Warn
+} else if cfg!(feature = "log_level_info") {
+    LogLevel::Info
+} else {
+    LogLevel::Debug
+};
+
+/// Maximum number of modules which can have their own runtime log level.
+pub const MAX_MODULE_FILTERS: usize = 16;
+
+const MODULE_TOKEN_MASK: u32 = 0xffff;
+
+// The runtime level of modules without a filter.
+static DEFAULT_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Debug as u8);
+
+// Per module filters packed as `module_token << 8 | level`.  Empty slots are
+// 0, which is never a valid filter since no level is 0.  Slots are read
+// without locking and modified in a critical section.
+static MODULE_FILTERS: [AtomicU32; MAX_MODULE_FILTERS] =
+    [const { AtomicU32::new(0) }; MAX_MODULE_FILTERS];
+
+fn level_from_u8(value: u8) -> LogLevel {
+    match value {
+        1 => LogLevel::Debug,
+        2 => LogLevel::Info,
+        3 => LogLevel::Warn,
+        4 => LogLevel::Error,
+        5 => LogLevel::Critical,
+        _ => LogLevel::Fatal,
+    }
+}
+
+const fn filter_token(filter: u32) -> u32 {
+    filter >> 8
+}
+
+/// Returns the token identifying `module` in runtime log filters.
+///
+/// Log messages are attributed to the [`module_path!()`] of the code which
+/// logs them.  Tokens are 16 bits, matching the module tokens in
+/// `pw_log_tokenized` metadata, so filters can be set by a host which only
+/// knows module tokens.
+pub const fn module_token(module: &str) -> u32 {
+    pw_tokenizer_core::hash_string(module) & MODULE_TOKEN_MASK
+}
+
+/// Returns the runtime log level of modules without their own filter.
+pub fn default_level() -> LogLevel {
+    level_from_u8(DEFAULT_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Sets the runtime log level of modules without their own filter.
+///
+/// Messages below [`MIN_LOG_LEVEL`] are never logged, regardless of the
+/// runtime level.
+pub fn set_default_level(level: LogLevel) {
+    DEFAULT_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns the runtime log level of the module with token `module_token`, if
+/// it has its own filter.
+pub fn module_level(module_token: u32) -> Option<LogLevel> {
+    let module_token = module_token & MODULE_TOKEN_MASK;
+    MODULE_FILTERS.iter().find_map(|slot| {
+        let filter = slot.load(Ordering::Relaxed);
+        (filter != 0 && filter_token(filter) == module_token).then(|| level_from_u8(filter as u8))
+    })
+}
+
+/// Sets the runtime log level of `module`, overriding the default level.
+///
+/// `module` is a [`module_path!()`], such as `"my_app::sensor"`.  Only
+/// messages logged from that exact module are affected.
+///
+/// # Errors
+/// - [`Error::ResourceExhausted`] - [`MAX_MODULE_FILTERS`] modules already
+///   have filters.
+pub fn set_module_level(module: &str, level: LogLevel) -> Result<()> {
+    set_module_level_by_token(module_token(module), level)
+}
+
+/// Sets the runtime log level of the module with token `module_token`.
+///
+/// See [`set_module_level()`].
+pub fn set_module_level_by_token(module_token: u32, level: LogLevel) -> Result<()> {
+    let filter = (module_token & MODULE_TOKEN_MASK) << 8 | level as u32;
+    critical_section::with(|_| {
+        let mut empty = None;
+        for slot in &MODULE_FILTERS {
+            let current = slot.load(Ordering::Relaxed);
+            if current == 0 {
+                empty = empty.or(Some(slot));
+            } else if filter_token(current) == filter_token(filter) {
+                slot.store(filter, Ordering::Relaxed);
+                return Ok(());
+            }
+        }
+        let slot = empty.ok_or(Error::ResourceExhausted)?;
+        slot.store(filter, Ordering::Relaxed);
+        Ok(())
+    })
+}
+
+/// Removes the filter for the module with token `module_token` so that it
+/// uses the default level again.
+pub fn clear_module_level(module_token: u32) {
+    let module_token = module_token & MODULE_TOKEN_MASK;
+    critical_section::with(|_| {
+        for slot in &MODULE_FILTERS {
+            let filter = slot.load(Ordering::Relaxed);
+            if filter != 0 && filter_token(filter) == module_token {
+                slot.store(0, Ordering::Relaxed);
+            }
+        }
+    })
+}
+
+/// Returns true if a message at `level` from the module with token
+/// `module_token` passes the runtime filters.
+pub fn is_enabled(module_token: u32, level: LogLevel) -> bool {
+    level >= module_level(module_token).unwrap_or_else(default_level)
+}
+
+#[derive(Clone, Copy)]
+struct RateLimiterState {
+    calls: u32,
+    suppressed: u32,
+    last_logged: Option<Duration>,
+}
+
+/// Tracks the calls to a rate limited log statement.
+///
+/// The rate limited macros, such as [`info_every_n!`], create a
+/// `RateLimiter` for each call site.  Each method records a call and returns
+/// whether it should be logged.
+pub struct RateLimiter {
+    state: critical_section::Mutex<Cell<RateLimiterState>>,
+}
+
+impl RateLimiter {
+    /// Creates a `RateLimiter` which has not been called.
+    pub const fn new() -> Self {
+        Self {
+            state: critical_section::Mutex::new(Cell::new(RateLimiterState {
+                calls: 0,
+                suppressed: 0,
+                last_logged: None,
+            })),
+        }
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut RateLimiterState) -> T) -> T {
+        critical_section::with(|cs| {
+            let cell = self.state.borrow(cs);
+            let mut state = cell.get();
+            let result = f(&mut state);
+            cell.set(state);
+            result
+        })
+    }
+
+    // Records whether a call is logged, returning the number of calls
+    // suppressed since the last logged call if it is.
+    fn record(state: &mut RateLimiterState, log: bool) -> Option<u32> {
+        state.calls = state.calls.saturating_add(1);
+        if log {
+            Some(core::mem::take(&mut state.suppressed))
+        } else {
+            state.suppressed = state.suppressed.saturating_add(1);
+            None
+        }
+    }
+
+    /// Logs the first of every `n` calls.
+    ///
+    /// Returns the number of calls suppressed since the last logged call if
+    /// this call should be logged.
+    pub fn every_n(&self, n: u32) -> Option<u32> {
+        // `calls` saturates, so the position in the cycle is taken from the
+        // calls suppressed since the last logged call instead.
+        self.update(|state| {
+            let log = state.calls == 0 || state.suppressed >= n.saturating_sub(1);
+            Self::record(state, log)
+        })
+    }
+
+    /// Returns `true` if this is one of the first `n` calls.
+    pub fn first_n(&self, n: u32) -> bool {
+        self.update(|state| Self::record(state, state.calls < n).is_some())
+    }
+
+    /// Logs a call if at least `interval` has passed since the last logged
+    /// call, as measured by the system clock set with
+    /// [`pw_chrono::set_system_clock()`].  Until it is set, every call is
+    /// logged.
+    ///
+    /// Returns the number of calls suppressed since the last logged call if
+    /// this call should be logged.
+    pub fn every_interval(&self, interval: Duration) -> Option<u32> {
+        match pw_chrono::system_clock() {
+            Some(clock) => self.every_interval_at(interval, clock.now().duration_since_epoch()),
+            None => self.update(|state| Self::record(state, true)),
+        }
+    }
+
+    fn every_interval_at(&self, interval: Duration, now: Duration) -> Option<u32> {
+        self.update(|state| {
+            let log = match state.last_logged {
+                Some(last) => now.saturating_sub(last) >= interval,
+                None => true,
+            };
+            if log {
+                state.last_logged = Some(now);
+            }
+            Self::record(state, log)
+        })
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Logs the errors of [`pw_status::Result`]s and
+/// [`pw_status::ContextResult`]s.
+///
+/// ```
+/// use pw_log::{LogLevel, LogOnErr};
+/// use pw_status::{Error, Result};
+///
+/// fn read_sensor() -> Result<u32> {
+///     Err(Error::Unavailable)
+/// }
+///
+/// // Logs "Reading sensor: UNAVAILABLE".
+/// let reading = read_sensor().log_on_err(LogLevel::Warn, "Reading sensor");
+/// assert!(reading.is_err());
+/// ```
+pub trait LogOnErr: Sized {
+    /// Logs `context` and the error at `level` if the result is an error,
+    /// then returns the result.
+    ///
+    /// The context of a [`pw_status::ContextError`] is logged after the
+    /// error, with tokenized context logged as a nested token.  Messages are
+    /// logged from the `pw_log` module, so they are filtered by its level
+    /// rather than the caller's.
+    fn log_on_err(self, level: LogLevel, context: &str) -> Self;
+}
+
+impl<T, E: Copy + Into<pw_status::ContextError>> LogOnErr for core::result::Result<T, E> {
+    fn log_on_err(self, level: LogLevel, context: &str) -> Self {
+        if let Err(e) = &self {
+            let e: pw_status::ContextError = (*e).into();
+            let error = e.error().as_str();
+            match e.context() {
+                None => pw_logf!(level, "%s: %s", context, error),
+                Some(pw_status::Context::Str(s)) => {
+                    pw_logf!(level, "%s: %s (%s)", context, error, s)
+                }
+                Some(pw_status::Context::Token(token)) => {
+                    pw_logf!(level, "%s: %s ($#%08x)", context, error, token)
+                }
+            }
+        }
+        self
+    }
+}
+
+// Expansion of log macros whose level is below `MIN_LOG_LEVEL`.  Arguments and
+// field values are referenced in a closure which is never called to avoid
+// unused variable warnings without evaluating them.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _pw_log_disabled {
+  (@reference) => {};
+
+  (@reference $key:ident = $value:expr $(, $($rest:tt)*)?) => {
+    let _ = &$value;
+    $crate::_pw_log_disabled!(@reference $($($rest)*)?)
+  };
+
+  (@reference $arg:expr $(, $($rest:tt)*)?) => {
+    let _ = &$arg;
+    $crate::_pw_log_disabled!(@reference $($($rest)*)?)
+  };
+
+  ($($args:tt)*) => {{
+    let _ = || {
+      $crate::_pw_log_disabled!(@reference $($args)*);
+    };
+  }};
+}
+
 /// Emit a log message using `printf` format string semantics.
 ///
 /// `pw_logf` takes a [`LogLevel`], a `printf style format string, and necessary
@@ -66,12 +464,20 @@ pub mod __private {
 macro_rules! pw_logf {
   ($log_level:expr, $format_string:literal) => {{
     use $crate::__private as __pw_log_crate;
-    $crate::__private::pw_logf_backend!($log_level, $format_string)
+    const MODULE_TOKEN: u32 = __pw_log_crate::module_token(module_path!());
+    let level: __pw_log_crate::LogLevel = $log_level;
+    if level >= __pw_log_crate::MIN_LOG_LEVEL && __pw_log_crate::is_enabled(MODULE_TOKEN, level) {
+      $crate::__private::pw_logf_backend!(level, $format_string)
+    }
   }};
 
-  ($log_level:expr, $format_string:literal, $($args:expr),*) => {{
+  ($log_level:expr, $format_string:literal, $($args:expr),* $(,)?) => {{
     use $crate::__private as __pw_log_crate;
-    $crate::__private::pw_logf_backend!($log_level, $format_string, $($args),*)
+    const MODULE_TOKEN: u32 = __pw_log_crate::module_token(module_path!());
+    let level: __pw_log_crate::LogLevel = $log_level;
+    if level >= __pw_log_crate::MIN_LOG_LEVEL && __pw_log_crate::is_enabled(MODULE_TOKEN, level) {
+      $crate::__private::pw_logf_backend!(level, $format_string, $($args),*)
+    }
   }};
 }
 
@@ -83,14 +489,31 @@ macro_rules! pw_logf {
 /// pw_log_debugf!("Log Fact: The American toy Lincoln Logs were inspired by the %s in %s.",
 ///     "Imperial Hotel", "Tokyo");
 /// ```
+#[cfg(not(feature = "log_level_info"))]
 #[macro_export]
 macro_rules! pw_log_debugf {
-  ($($args:expr),*) => {{
+  ($($args:tt)*) => {{
     use $crate::__private as __pw_log_crate;
-    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Debug, $($args),*)
+    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Debug, $($args)*)
   }};
 }
 
+/// Emit a debug level log message using `printf` format string semantics.
+///
+/// ```
+/// use pw_log::{pw_log_debugf, LogLevel};
+///
+/// pw_log_debugf!("Log Fact: The American toy Lincoln Logs were inspired by the %s in %s.",
+///     "Imperial Hotel", "Tokyo");
+/// ```
+#[cfg(feature = "log_level_info")]
+#[macro_export]
+macro_rules! pw_log_debugf {
+  ($($args:tt)*) => {
+    $crate::_pw_log_disabled!($($args)*)
+  };
+}
+
 /// Emit an info level log message using `printf` format string semantics.
 ///
 /// ```
@@ -100,14 +523,32 @@ macro_rules! pw_log_debugf {
 ///     "Log Fact: The American president Abraham Lincoln (born %x) once lived in a log cabin.",
 /// 0x1809);
 /// ```
+#[cfg(not(feature = "log_level_warn"))]
 #[macro_export]
 macro_rules! pw_log_infof {
-  ($($args:expr),*) => {{
+  ($($args:tt)*) => {{
     use $crate::__private as __pw_log_crate;
-    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Info, $($args),*)
+    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Info, $($args)*)
   }};
 }
 
+/// Emit an info level log message using `printf` format string semantics.
+///
+/// ```
+/// use pw_log::{pw_log_infof, LogLevel};
+///
+/// pw_log_infof!(
+///     "Log Fact: The American president Abraham Lincoln (born %x) once lived in a log cabin.",
+/// 0x1809);
+/// ```
+#[cfg(feature = "log_level_warn")]
+#[macro_export]
+macro_rules! pw_log_infof {
+  ($($args:tt)*) => {
+    $crate::_pw_log_disabled!($($args)*)
+  };
+}
+
 /// Emit a warn level log message using `printf` format string semantics.
 ///
 /// ```
@@ -117,14 +558,32 @@ macro_rules! pw_log_infof {
 ///     "Log Fact: Made from a log, an %d year old dugout canoe is the oldest discovered boat in %s.",
 ///     8000, "Africa");
 /// ```
+#[cfg(not(feature = "log_level_error"))]
 #[macro_export]
 macro_rules! pw_log_warnf {
-  ($($args:expr),*) => {{
+  ($($args:tt)*) => {{
     use $crate::__private as __pw_log_crate;
-    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Warn, $($args),*)
+    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Warn, $($args)*)
   }};
 }
 
+/// Emit a warn level log message using `printf` format string semantics.
+///
+/// ```
+/// use pw_log::{pw_log_warnf, LogLevel};
+///
+/// pw_log_warnf!(
+///     "Log Fact: Made from a log, an %d year old dugout canoe is the oldest discovered boat in %s.",
+///     8000, "Africa");
+/// ```
+#[cfg(feature = "log_level_error")]
+#[macro_export]
+macro_rules! pw_log_warnf {
+  ($($args:tt)*) => {
+    $crate::_pw_log_disabled!($($args)*)
+  };
+}
+
 /// Emit an error level log message using `printf` format string semantics.
 ///
 /// ```
@@ -134,14 +593,32 @@ macro_rules! pw_log_warnf {
 ///     "Log Fact: Before saws were invented, the %s was used prepare logs for use.",
 ///     "adze");
 /// ```
+#[cfg(not(feature = "log_level_critical"))]
 #[macro_export]
 macro_rules! pw_log_errorf {
-  ($($args:expr),*) => {{
+  ($($args:tt)*) => {{
     use $crate::__private as __pw_log_crate;
-    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Error, $($args),*)
+    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Error, $($args)*)
   }};
 }
 
+/// Emit an error level log message using `printf` format string semantics.
+///
+/// ```
+/// use pw_log::{pw_log_errorf, LogLevel};
+///
+/// pw_log_errorf!(
+///     "Log Fact: Before saws were invented, the %s was used prepare logs for use.",
+///     "adze");
+/// ```
+#[cfg(feature = "log_level_critical")]
+#[macro_export]
+macro_rules! pw_log_errorf {
+  ($($args:tt)*) => {
+    $crate::_pw_log_disabled!($($args)*)
+  };
+}
+
 /// Emit a critical level log message using `printf` format string semantics.
 ///
 /// ```
@@ -151,14 +628,32 @@ macro_rules! pw_log_errorf {
 ///     "Log Fact: Until the %dth century, all ships' masts were made from a single log.",
 ///     19);
 /// ```
+#[cfg(not(feature = "log_level_fatal"))]
 #[macro_export]
 macro_rules! pw_log_criticalf {
-  ($($args:expr),*) => {{
+  ($($args:tt)*) => {{
     use $crate::__private as __pw_log_crate;
-    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Critical, $($args),*)
+    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Critical, $($args)*)
   }};
 }
 
+/// Emit a critical level log message using `printf` format string semantics.
+///
+/// ```
+/// use pw_log::{pw_log_criticalf, LogLevel};
+///
+/// pw_log_criticalf!(
+///     "Log Fact: Until the %dth century, all ships' masts were made from a single log.",
+///     19);
+/// ```
+#[cfg(feature = "log_level_fatal")]
+#[macro_export]
+macro_rules! pw_log_criticalf {
+  ($($args:tt)*) => {
+    $crate::_pw_log_disabled!($($args)*)
+  };
+}
+
 /// Emit a fatal level log message using `printf` format string semantics.
 ///
 /// *Note*: `pw_log_fatalf` only emits a log message and does not cause a `panic!()`
@@ -170,14 +665,757 @@ macro_rules! pw_log_criticalf {
 /// ```
 #[macro_export]
 macro_rules! pw_log_fatalf {
-  ($($args:expr),*) => {{
+  ($($args:tt)*) => {{
     use $crate::__private as __pw_log_crate;
-    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Fatal, $($args),*)
+    __pw_log_crate::pw_logf!(__pw_log_crate::LogLevel::Fatal, $($args)*)
+  }};
+}
+
+/// Emit a debug level log message using `printf` format string semantics.
+///
+/// Shorthand for [`pw_log_debugf!`].
+///
+/// ```
+/// pw_log::debug!("Log Fact: A log flume ride ends with a %s.", "splash");
+/// ```
+#[macro_export]
+macro_rules! debug {
+  ($($args:tt)*) => {{
+    $crate::pw_log_debugf!($($args)*)
+  }};
+}
+
+/// Emit a info level log message using `printf` format string semantics.
+///
+/// Shorthand for [`pw_log_infof!`].
+///
+/// ```
+/// pw_log::info!("Log Fact: Log rolling is also known as %s.", "birling");
+/// ```
+#[macro_export]
+macro_rules! info {
+  ($($args:tt)*) => {{
+    $crate::pw_log_infof!($($args)*)
+  }};
+}
+
+/// Emit a warn level log message using `printf` format string semantics.
+///
+/// Shorthand for [`pw_log_warnf!`].
+///
+/// ```
+/// pw_log::warn!("Log Fact: Early ship logs were kept on wooden %s.", "shingles");
+/// ```
+#[macro_export]
+macro_rules! warn {
+  ($($args:tt)*) => {{
+    $crate::pw_log_warnf!($($args)*)
+  }};
+}
+
+/// Emit a error level log message using `printf` format string semantics.
+///
+/// Shorthand for [`pw_log_errorf!`].
+///
+/// ```
+/// pw_log::error!("Log Fact: A log drive floated timber down %s.", "rivers");
+/// ```
+#[macro_export]
+macro_rules! error {
+  ($($args:tt)*) => {{
+    $crate::pw_log_errorf!($($args)*)
+  }};
+}
+
+/// Emit a critical level log message using `printf` format string semantics.
+///
+/// Shorthand for [`pw_log_criticalf!`].
+///
+/// ```
+/// pw_log::critical!("Log Fact: Some river logjams stretched for %d miles.", 200);
+/// ```
+#[macro_export]
+macro_rules! critical {
+  ($($args:tt)*) => {{
+    $crate::pw_log_criticalf!($($args)*)
   }};
 }
 
+/// Emit a fatal level log message using `printf` format string semantics.
+///
+/// Shorthand for [`pw_log_fatalf!`].
+///
+/// *Note*: `fatal` only emits a log message and does not cause a `panic!()`
+///
+/// ```
+/// pw_log::fatal!("Log Fact: Out of logs. Time to call it a %s.", "day");
+/// ```
+#[macro_export]
+macro_rules! fatal {
+  ($($args:tt)*) => {{
+    $crate::pw_log_fatalf!($($args)*)
+  }};
+}
+
+/// Defines a macro which emits log messages at a project defined level using
+/// `printf` format string semantics.
+///
+/// `custom_level!(name, "NAME", level)` defines `name!`, which logs at the
+/// standard `level`, one of `debug`, `info`, `warn`, `error`, `critical`, or
+/// `fatal`, with the level's name added as a `level` field so host tools can
+/// render it.  Runtime filtering and backends use the standard level.
+///
+/// ```
+/// pw_log::custom_level!(trace, "TRACE", debug);
+///
+/// trace!("Log Fact: A %s is a unit of sawn log volume.", "board foot");
+/// ```
+///
+/// Like the standard level macros, messages are removed at compile time when
+/// the standard level is below [`MIN_LOG_LEVEL`].  An optional `cfg(...)`
+/// predicate, evaluated in the crate defining the level, also removes them
+/// when it is false.  Removed messages are not added to the token database
+/// and their arguments are not evaluated:
+///
+/// ```
+/// pw_log::custom_level!(trace2, "TRACE2", debug, cfg(feature = "trace2"));
+///
+/// trace2!("Sending %d bytes", 128 as i32);
+/// ```
+///
+/// The macro is scoped like any `macro_rules!` macro, so levels used across
+/// modules are defined before those modules are declared.
+#[macro_export]
+macro_rules! custom_level {
+  ($name:ident, $level_name:literal, $level:ident $(, cfg($($cfg:tt)*))? $(,)?) => {
+    $crate::_pw_log_custom_level!(($) $name, $level_name, $level, ($($($cfg)*)?));
+  };
+}
+
+// Expansion of `custom_level!`.  `$d` is a `$` token, which the defined macro
+// needs for its own metavariables.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _pw_log_custom_level {
+  (($d:tt) $name:ident, $level_name:literal, $level:ident, ($($cfg:tt)*)) => {
+    #[cfg(all($($cfg)*))]
+    #[allow(unused_macros)]
+    macro_rules! $name {
+      ($format_string:literal $d(, $d($d args:tt)*)?) => {
+        $crate::$level!($format_string, $d($d($d args)*,)? level = $level_name as &str)
+      };
+    }
+
+    // The format string is not referenced, so it is not compiled in.
+    #[cfg(not(all($($cfg)*)))]
+    #[allow(unused_macros)]
+    macro_rules! $name {
+      ($format_string:literal $d(, $d($d args:tt)*)?) => {
+        $crate::_pw_log_disabled!($d($d($d args)*)?)
+      };
+    }
+  };
+}
+
+// Expansion of the `*_every_n!` macros.  `$log` is a level macro, which
+// compiles to nothing when the level is below `MIN_LOG_LEVEL`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _pw_log_every_n {
+  ($log:ident, $n:expr, $($args:tt)*) => {{
+    static LIMITER: $crate::RateLimiter = $crate::RateLimiter::new();
+    if let Some(suppressed) = LIMITER.every_n($n) {
+      $crate::_pw_log_rate_limited!($log, suppressed, $($args)*)
+    }
+  }};
+}
+
+// Expansion of the `*_first_n!` macros.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _pw_log_first_n {
+  ($log:ident, $n:expr, $($args:tt)*) => {{
+    static LIMITER: $crate::RateLimiter = $crate::RateLimiter::new();
+    if LIMITER.first_n($n) {
+      $crate::$log!($($args)*)
+    }
+  }};
+}
+
+// Expansion of the `*_every_interval!` macros.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _pw_log_every_interval {
+  ($log:ident, $interval:expr, $($args:tt)*) => {{
+    static LIMITER: $crate::RateLimiter = $crate::RateLimiter::new();
+    if let Some(suppressed) = LIMITER.every_interval($interval) {
+      $crate::_pw_log_rate_limited!($log, suppressed, $($args)*)
+    }
+  }};
+}
+
+// Logs a rate limited message followed by the number of messages suppressed
+// since the call site last logged.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _pw_log_rate_limited {
+  ($log:ident, $suppressed:ident, $($args:tt)*) => {{
+    $crate::$log!($($args)*);
+    if $suppressed > 0 {
+      $crate::$log!("Suppressed %u similar messages", $suppressed);
+    }
+  }};
+}
+
+/// Emit a debug level log message on the first of every `n` calls.
+///
+/// Each call site counts its own calls.  When messages were suppressed since
+/// the last logged call, their count is logged after the message.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::debug_every_n!(5, "Log Fact: Logs are sorted %d at a time.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! debug_every_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_n!(debug, $n, $($args)*)
+  };
+}
+
+/// Emit a debug level log message on only the first `n` calls.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::debug_first_n!(3, "Log Fact: The first %d logs float.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! debug_first_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_first_n!(debug, $n, $($args)*)
+  };
+}
+
+/// Emit a debug level log message at most once per `interval`.
+///
+/// The interval is a [`core::time::Duration`] measured with the `pw_chrono`
+/// system clock.  Each call site tracks its own interval.  When messages
+/// were suppressed since the last logged call, their count is logged after
+/// the message.
+///
+/// ```
+/// use core::time::Duration;
+///
+/// pw_log::debug_every_interval!(Duration::from_secs(1), "Log Fact: Logs dry in %s.", "months");
+/// ```
+#[macro_export]
+macro_rules! debug_every_interval {
+  ($interval:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_interval!(debug, $interval, $($args)*)
+  };
+}
+
+/// Emit an info level log message on the first of every `n` calls.
+///
+/// Each call site counts its own calls.  When messages were suppressed since
+/// the last logged call, their count is logged after the message.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::info_every_n!(5, "Log Fact: Logs are sorted %d at a time.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! info_every_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_n!(info, $n, $($args)*)
+  };
+}
+
+/// Emit an info level log message on only the first `n` calls.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::info_first_n!(3, "Log Fact: The first %d logs float.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! info_first_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_first_n!(info, $n, $($args)*)
+  };
+}
+
+/// Emit an info level log message at most once per `interval`.
+///
+/// The interval is a [`core::time::Duration`] measured with the `pw_chrono`
+/// system clock.  Each call site tracks its own interval.  When messages
+/// were suppressed since the last logged call, their count is logged after
+/// the message.
+///
+/// ```
+/// use core::time::Duration;
+///
+/// pw_log::info_every_interval!(Duration::from_secs(1), "Log Fact: Logs dry in %s.", "months");
+/// ```
+#[macro_export]
+macro_rules! info_every_interval {
+  ($interval:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_interval!(info, $interval, $($args)*)
+  };
+}
+
+/// Emit a warn level log message on the first of every `n` calls.
+///
+/// Each call site counts its own calls.  When messages were suppressed since
+/// the last logged call, their count is logged after the message.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::warn_every_n!(5, "Log Fact: Logs are sorted %d at a time.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! warn_every_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_n!(warn, $n, $($args)*)
+  };
+}
+
+/// Emit a warn level log message on only the first `n` calls.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::warn_first_n!(3, "Log Fact: The first %d logs float.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! warn_first_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_first_n!(warn, $n, $($args)*)
+  };
+}
+
+/// Emit a warn level log message at most once per `interval`.
+///
+/// The interval is a [`core::time::Duration`] measured with the `pw_chrono`
+/// system clock.  Each call site tracks its own interval.  When messages
+/// were suppressed since the last logged call, their count is logged after
+/// the message.
+///
+/// ```
+/// use core::time::Duration;
+///
+/// pw_log::warn_every_interval!(Duration::from_secs(1), "Log Fact: Logs dry in %s.", "months");
+/// ```
+#[macro_export]
+macro_rules! warn_every_interval {
+  ($interval:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_interval!(warn, $interval, $($args)*)
+  };
+}
+
+/// Emit an error level log message on the first of every `n` calls.
+///
+/// Each call site counts its own calls.  When messages were suppressed since
+/// the last logged call, their count is logged after the message.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::error_every_n!(5, "Log Fact: Logs are sorted %d at a time.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! error_every_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_n!(error, $n, $($args)*)
+  };
+}
+
+/// Emit an error level log message on only the first `n` calls.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::error_first_n!(3, "Log Fact: The first %d logs float.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! error_first_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_first_n!(error, $n, $($args)*)
+  };
+}
+
+/// Emit an error level log message at most once per `interval`.
+///
+/// The interval is a [`core::time::Duration`] measured with the `pw_chrono`
+/// system clock.  Each call site tracks its own interval.  When messages
+/// were suppressed since the last logged call, their count is logged after
+/// the message.
+///
+/// ```
+/// use core::time::Duration;
+///
+/// pw_log::error_every_interval!(Duration::from_secs(1), "Log Fact: Logs dry in %s.", "months");
+/// ```
+#[macro_export]
+macro_rules! error_every_interval {
+  ($interval:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_interval!(error, $interval, $($args)*)
+  };
+}
+
+/// Emit a critical level log message on the first of every `n` calls.
+///
+/// Each call site counts its own calls.  When messages were suppressed since
+/// the last logged call, their count is logged after the message.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::critical_every_n!(5, "Log Fact: Logs are sorted %d at a time.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! critical_every_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_n!(critical, $n, $($args)*)
+  };
+}
+
+/// Emit a critical level log message on only the first `n` calls.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::critical_first_n!(3, "Log Fact: The first %d logs float.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! critical_first_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_first_n!(critical, $n, $($args)*)
+  };
+}
+
+/// Emit a critical level log message at most once per `interval`.
+///
+/// The interval is a [`core::time::Duration`] measured with the `pw_chrono`
+/// system clock.  Each call site tracks its own interval.  When messages
+/// were suppressed since the last logged call, their count is logged after
+/// the message.
+///
+/// ```
+/// use core::time::Duration;
+///
+/// pw_log::critical_every_interval!(Duration::from_secs(1), "Log Fact: Logs dry in %s.", "months");
+/// ```
+#[macro_export]
+macro_rules! critical_every_interval {
+  ($interval:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_interval!(critical, $interval, $($args)*)
+  };
+}
+
+/// Emit a fatal level log message on the first of every `n` calls.
+///
+/// Each call site counts its own calls.  When messages were suppressed since
+/// the last logged call, their count is logged after the message.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::fatal_every_n!(5, "Log Fact: Logs are sorted %d at a time.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! fatal_every_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_n!(fatal, $n, $($args)*)
+  };
+}
+
+/// Emit a fatal level log message on only the first `n` calls.
+///
+/// ```
+/// for i in 0..10 {
+///     pw_log::fatal_first_n!(3, "Log Fact: The first %d logs float.", i as i32);
+/// }
+/// ```
+#[macro_export]
+macro_rules! fatal_first_n {
+  ($n:expr, $($args:tt)*) => {
+    $crate::_pw_log_first_n!(fatal, $n, $($args)*)
+  };
+}
+
+/// Emit a fatal level log message at most once per `interval`.
+///
+/// The interval is a [`core::time::Duration`] measured with the `pw_chrono`
+/// system clock.  Each call site tracks its own interval.  When messages
+/// were suppressed since the last logged call, their count is logged after
+/// the message.
+///
+/// ```
+/// use core::time::Duration;
+///
+/// pw_log::fatal_every_interval!(Duration::from_secs(1), "Log Fact: Logs dry in %s.", "months");
+/// ```
+#[macro_export]
+macro_rules! fatal_every_interval {
+  ($interval:expr, $($args:tt)*) => {
+    $crate::_pw_log_every_interval!(fatal, $interval, $($args)*)
+  };
+}
+
 #[cfg(test)]
 mod tests {
     // TODO(b/311262163): Add infrastructure for testing behavior of `pw_log` API.
     // The syntax of that API is verified through doctests.
+
+    use super::*;
+
+    const LEVELS: [LogLevel; 6] = [
+        LogLevel::Debug,
+        LogLevel::Info,
+        LogLevel::Warn,
+        LogLevel::Error,
+        LogLevel::Critical,
+        LogLevel::Fatal,
+    ];
+
+    // Serializes tests which modify module filters.
+    static FILTER_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    fn enabled_levels() -> usize {
+        LEVELS
+            .iter()
+            .filter(|level| **level >= MIN_LOG_LEVEL)
+            .count()
+    }
+
+    #[test]
+    fn level_macros_below_min_level_do_not_evaluate_arguments() {
+        let mut count = 0;
+        let mut next = || {
+            count += 1;
+            count
+        };
+        debug!("%d", next());
+        info!("%d", next());
+        warn!("%d", next());
+        error!("%d", next());
+        critical!("%d", next());
+        fatal!("%d", next());
+        assert_eq!(count, enabled_levels());
+    }
+
+    #[test]
+    fn level_macros_below_min_level_do_not_evaluate_fields() {
+        let id: u8 = 1;
+        let mut count = 0;
+        let mut next = || {
+            count += 1;
+            count
+        };
+        debug!("Reading", id = id as u32, value = next() as u32);
+        info!("Reading", id = id as u32, value = next() as u32);
+        warn!("Reading", id = id as u32, value = next() as u32);
+        error!("Reading", id = id as u32, value = next() as u32);
+        critical!("Reading", id = id as u32, value = next() as u32);
+        fatal!("Reading %d", id, value = next() as u32,);
+        assert_eq!(count, enabled_levels());
+    }
+
+    #[test]
+    fn custom_levels_are_filtered_at_compile_time() {
+        custom_level!(trace1, "TRACE1", debug);
+        custom_level!(trace2, "TRACE2", debug, cfg(any()));
+        custom_level!(notice, "NOTICE", fatal, cfg(all()));
+
+        let mut count = 0;
+        let mut next = || {
+            count += 1;
+            count
+        };
+        trace1!("%d", next());
+        trace2!("%d", next());
+        notice!("Value", value = next() as u32);
+        notice!("No arguments");
+        let expected = if LogLevel::Debug >= MIN_LOG_LEVEL {
+            2
+        } else {
+            1
+        };
+        assert_eq!(count, expected);
+    }
+
+    #[test]
+    fn removed_custom_levels_do_not_add_format_strings() {
+        custom_level!(trace1, "TRACE1", debug, cfg(any()));
+        custom_level!(notice, "NOTICE", fatal);
+
+        trace1!("Removed custom level message");
+        notice!("Compiled custom level message");
+
+        // The strings are built at runtime so the test's own literals are not
+        // found.  The format string of a removed message, which a tokenizing
+        // backend would add to the token database, is not in the binary.
+        let binary = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let contains = |words: &[&str]| {
+            let needle = words.join(" ");
+            binary
+                .windows(needle.len())
+                .any(|window| window == needle.as_bytes())
+        };
+        assert!(contains(&["Compiled", "custom", "level", "message"]));
+        assert!(!contains(&["Removed", "custom", "level", "message"]));
+    }
+
+    #[test]
+    fn module_token_is_16_bits() {
+        assert_eq!(
+            module_token("pw_log::tests"),
+            pw_tokenizer_core::hash_string("pw_log::tests") & 0xffff
+        );
+    }
+
+    #[test]
+    fn module_filters_override_default_level() {
+        let _lock = FILTER_LOCK.lock().unwrap();
+        let module = module_token("pw_log::tests::module_filters");
+        assert_eq!(module_level(module), None);
+        assert!(is_enabled(module, LogLevel::Debug));
+
+        set_module_level("pw_log::tests::module_filters", LogLevel::Error).unwrap();
+        assert_eq!(module_level(module), Some(LogLevel::Error));
+        assert!(!is_enabled(module, LogLevel::Warn));
+        assert!(is_enabled(module, LogLevel::Error));
+
+        set_module_level_by_token(module, LogLevel::Info).unwrap();
+        assert_eq!(module_level(module), Some(LogLevel::Info));
+        assert!(is_enabled(module, LogLevel::Info));
+
+        clear_module_level(module);
+        assert_eq!(module_level(module), None);
+    }
+
+    #[test]
+    fn module_filters_are_limited() {
+        let _lock = FILTER_LOCK.lock().unwrap();
+        let tokens = 0x8000..0x8000 + MAX_MODULE_FILTERS as u32;
+        for token in tokens.clone() {
+            set_module_level_by_token(token, LogLevel::Warn).unwrap();
+        }
+        assert_eq!(
+            set_module_level_by_token(0x7fff, LogLevel::Warn),
+            Err(Error::ResourceExhausted)
+        );
+        // Updating an existing filter does not need a new slot.
+        set_module_level_by_token(0x8000, LogLevel::Error).unwrap();
+        for token in tokens {
+            clear_module_level(token);
+        }
+    }
+
+    // Logs from a separate module so that the filter does not affect other
+    // tests.
+    mod filtered {
+        use super::*;
+
+        #[test]
+        fn runtime_filter_applies_to_log_macros() {
+            let _lock = FILTER_LOCK.lock().unwrap();
+            let mut count = 0;
+            set_module_level(module_path!(), LogLevel::Fatal).unwrap();
+            warn!("%d", {
+                count += 1;
+                count
+            });
+            assert_eq!(count, 0);
+            clear_module_level(module_token(module_path!()));
+        }
+    }
+
+    #[test]
+    fn pw_logf_below_min_level_does_not_evaluate_arguments() {
+        let mut count = 0;
+        for level in LEVELS {
+            pw_logf!(level, "%d", {
+                count += 1;
+                count
+            });
+        }
+        assert_eq!(count, enabled_levels());
+    }
+
+    #[test]
+    fn log_on_err_returns_result() {
+        use pw_status::{Context, ContextResult, ResultExt};
+
+        let ok: Result<u32> = Ok(1);
+        assert_eq!(ok.log_on_err(LogLevel::Error, "ok"), Ok(1));
+        let err: Result<u32> = Err(Error::Unavailable);
+        assert_eq!(
+            err.log_on_err(LogLevel::Warn, "err"),
+            Err(Error::Unavailable)
+        );
+        for context in [Context::Str("reading"), Context::Token(0x1234)] {
+            let err: ContextResult<()> = Err(Error::DataLoss).context(context);
+            assert_eq!(err.log_on_err(LogLevel::Info, "context"), err);
+        }
+    }
+
+    #[test]
+    fn rate_limiter_logs_every_n() {
+        let limiter = RateLimiter::new();
+        let logged: Vec<_> = (0..7).map(|_| limiter.every_n(3)).collect();
+        assert_eq!(logged, [Some(0), None, None, Some(2), None, None, Some(2)]);
+    }
+
+    #[test]
+    fn rate_limiter_logs_every_n_past_call_count_limit() {
+        let limiter = RateLimiter::new();
+        assert_eq!(limiter.every_n(3), Some(0));
+        limiter.update(|state| state.calls = u32::MAX - 2);
+        let logged: Vec<_> = (0..7).map(|_| limiter.every_n(3)).collect();
+        assert_eq!(logged, [None, None, Some(2), None, None, Some(2), None]);
+    }
+
+    #[test]
+    fn rate_limiter_logs_first_n() {
+        let limiter = RateLimiter::new();
+        let logged: Vec<_> = (0..4).map(|_| limiter.first_n(2)).collect();
+        assert_eq!(logged, [true, true, false, false]);
+    }
+
+    #[test]
+    fn rate_limiter_logs_every_interval() {
+        let limiter = RateLimiter::new();
+        let interval = Duration::from_millis(100);
+        let at = |ms| limiter.every_interval_at(interval, Duration::from_millis(ms));
+        assert_eq!(at(1000), Some(0));
+        assert_eq!(at(1050), None);
+        assert_eq!(at(1099), None);
+        assert_eq!(at(1100), Some(2));
+        assert_eq!(at(1500), Some(0));
+    }
+
+    #[test]
+    fn rate_limited_macros_only_evaluate_logged_arguments() {
+        let mut count = 0;
+        let mut next = || {
+            count += 1;
+            count
+        };
+        for _ in 0..7 {
+            info_every_n!(3, "%d", next());
+            info_first_n!(2, "%d", next());
+        }
+        let expected = if LogLevel::Info >= MIN_LOG_LEVEL {
+            5
+        } else {
+            0
+        };
+        assert_eq!(count, expected);
+    }
 }
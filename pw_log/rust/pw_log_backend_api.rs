@@ -22,6 +22,7 @@
 ///
 /// TODO: <pwbug.dev/314168783> - Add documentation on the meaning of the
 /// log levels once it is written for Pigweed in general.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
 pub enum LogLevel {
     Debug = 1,
@@ -0,0 +1,453 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_log` backend for unit tests which captures log messages so tests can
+//! assert what was logged.
+//!
+//! Messages are tokenized exactly as by the tokenized backend and stored,
+//! still encoded, along with their level, module, and line.  Since the
+//! backend also knows each message's format string, captured messages are
+//! decoded in process without a token database:
+//!
+//! ```
+//! use pw_log_backend::{take_logs, LogLevel};
+//! # use pw_log_backend::pw_logf_backend as pw_logf;
+//!
+//! fn read_sensor(id: u32) {
+//!     pw_logf!(LogLevel::Warn, "Sensor %u not responding", id);
+//! }
+//!
+//! read_sensor(3);
+//!
+//! let logs = take_logs();
+//! assert_eq!(logs[0].level(), LogLevel::Warn);
+//! assert_eq!(logs[0].message().unwrap(), "Sensor 3 not responding");
+//! ```
+//!
+//! Messages are captured per thread, so tests which run in parallel do not
+//! see each other's messages.  Messages from other threads are not captured.
+//!
+//! *Note*: This module requires `std`.
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use pw_format::{Flag, FormatFragment, FormatString, Length, MinFieldWidth, Precision, Specifier};
+use pw_status::{Error, Result};
+use pw_varint::VarintDecode;
+
+pub use pw_log_backend_api::LogLevel;
+pub use pw_log_backend_capture_macro::_pw_log_format_string;
+
+/// Size of the buffer messages are encoded into.  Matches the tokenized
+/// backend's `ENCODING_BUFFER_SIZE_BYTES`.  Messages which do not fit are
+/// captured with [`CapturedLog::is_dropped()`] set.
+pub const ENCODING_BUFFER_SIZE_BYTES: usize = 52;
+
+/// A captured log message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedLog {
+    level: LogLevel,
+    module: &'static str,
+    line: u32,
+    format_string: &'static str,
+    encoded: Option<Vec<u8>>,
+}
+
+impl CapturedLog {
+    /// Returns the level the message was logged at.
+    pub fn level(&self) -> LogLevel {
+        self.level
+    }
+
+    /// Returns the [`module_path!()`] of the code which logged the message.
+    pub fn module(&self) -> &'static str {
+        self.module
+    }
+
+    /// Returns the line number of the log statement.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// Returns the format string as tokenized, including the `■msg♦` prefix
+    /// and any key-value fields.
+    pub fn format_string(&self) -> &'static str {
+        self.format_string
+    }
+
+    /// Returns the message's token.
+    pub fn token(&self) -> u32 {
+        pw_tokenizer_core::hash_string(self.format_string)
+    }
+
+    /// Returns the tokenized message: its token followed by its encoded
+    /// arguments.  This is what the tokenized backend passes to
+    /// `pw_log_tokenized_HandleLog()`, without a timestamp.
+    ///
+    /// Returns `None` if the message did not fit in
+    /// [`ENCODING_BUFFER_SIZE_BYTES`] and would have been dropped.
+    pub fn encoded(&self) -> Option<&[u8]> {
+        self.encoded.as_deref()
+    }
+
+    /// Returns `true` if the message would have been dropped by the tokenized
+    /// backend.
+    pub fn is_dropped(&self) -> bool {
+        self.encoded.is_none()
+    }
+
+    /// Decodes the message, including any key-value fields in their
+    /// `■key♦value` form, but without the `■msg♦` prefix.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The message was dropped or its arguments could
+    ///   not be decoded.
+    pub fn message(&self) -> Result<String> {
+        let encoded = self.encoded.as_deref().ok_or(Error::DataLoss)?;
+        let args = encoded.get(4..).ok_or(Error::DataLoss)?;
+        let message = decode(self.format_string, args)?;
+        Ok(message
+            .strip_prefix("■msg♦")
+            .map(String::from)
+            .unwrap_or(message))
+    }
+}
+
+std::thread_local! {
+    static LOGS: RefCell<Vec<CapturedLog>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Removes and returns the messages captured on this thread.
+pub fn take_logs() -> Vec<CapturedLog> {
+    LOGS.with(|logs| logs.take())
+}
+
+/// Returns `true` if a message at `level` containing `text` has been
+/// captured on this thread.
+///
+/// Messages are not removed.  Messages which can not be decoded never match.
+pub fn logged(level: LogLevel, text: &str) -> bool {
+    LOGS.with(|logs| {
+        logs.borrow().iter().any(|log| {
+            log.level == level && log.message().is_ok_and(|message| message.contains(text))
+        })
+    })
+}
+
+fn decode_varint(args: &mut &[u8], long: bool) -> Result<i64> {
+    let (len, value) = if long {
+        i64::varint_decode(args)
+    } else {
+        i32::varint_decode(args).map(|(len, value)| (len, value.into()))
+    }
+    .map_err(|_| Error::DataLoss)?;
+    *args = &args[len..];
+    Ok(value)
+}
+
+fn decode_bytes<'a>(args: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    let bytes = args.get(..len).ok_or(Error::DataLoss)?;
+    *args = &args[len..];
+    Ok(bytes)
+}
+
+// Pads `value` to the conversion's minimum field width.
+fn pad(output: &mut String, value: &str, width: usize, flags: &HashSet<Flag>, numeric: bool) {
+    let fill = width.saturating_sub(value.chars().count());
+    if flags.contains(&Flag::LeftJustify) {
+        output.push_str(value);
+        output.extend(std::iter::repeat_n(' ', fill));
+    } else if numeric && flags.contains(&Flag::LeadingZeros) {
+        let (sign, digits) = match value.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", value),
+        };
+        output.push_str(sign);
+        output.extend(std::iter::repeat_n('0', fill));
+        output.push_str(digits);
+    } else {
+        output.extend(std::iter::repeat_n(' ', fill));
+        output.push_str(value);
+    }
+}
+
+/// Decodes the arguments of a tokenized message, `args`, and formats them
+/// with `format_string`.
+///
+/// `args` is the message without its token.  Arguments are decoded as
+/// encoded by `pw_tokenizer`: integers as zig-zag varints, characters as a
+/// single byte, and strings as a length byte followed by their data.  Strings
+/// which were truncated end with `[...]`.
+///
+/// # Errors
+/// - [`Error::InvalidArgument`] - `format_string` could not be parsed or uses
+///   a conversion which `pw_tokenizer` does not support.
+/// - [`Error::DataLoss`] - `args` is too short or has extra data.
+pub fn decode(format_string: &str, mut args: &[u8]) -> Result<String> {
+    let format = FormatString::parse(format_string).map_err(|_| Error::InvalidArgument)?;
+    let mut output = String::new();
+    for fragment in &format.fragments {
+        let spec = match fragment {
+            FormatFragment::Literal(literal) => {
+                output.push_str(literal);
+                continue;
+            }
+            FormatFragment::Percent => {
+                output.push('%');
+                continue;
+            }
+            FormatFragment::Conversion(spec) => spec,
+        };
+        let width = match spec.min_field_width {
+            MinFieldWidth::None => 0,
+            MinFieldWidth::Fixed(width) => width as usize,
+            MinFieldWidth::Variable => return Err(Error::InvalidArgument),
+        };
+        let precision = match spec.precision {
+            Precision::None => None,
+            Precision::Fixed(precision) => Some(precision as usize),
+            Precision::Variable => return Err(Error::InvalidArgument),
+        };
+        let flags = &spec.flags;
+        let long = spec.length == Some(Length::LongLong);
+        // Unsigned conversions reinterpret the value's bits.
+        let unsigned = |value: i64| {
+            if long {
+                value as u64
+            } else {
+                u64::from(value as i32 as u32)
+            }
+        };
+
+        let mut value = String::new();
+        // Writing to a `String` can not fail.
+        let numeric = match spec.specifier {
+            Specifier::Decimal | Specifier::Integer => {
+                let arg = decode_varint(&mut args, long)?;
+                if arg >= 0 && flags.contains(&Flag::ForceSign) {
+                    value.push('+');
+                } else if arg >= 0 && flags.contains(&Flag::SpaceSign) {
+                    value.push(' ');
+                }
+                let _ = write!(value, "{arg}");
+                true
+            }
+            Specifier::Unsigned => {
+                let _ = write!(value, "{}", unsigned(decode_varint(&mut args, long)?));
+                true
+            }
+            Specifier::Octal => {
+                let _ = write!(value, "{:o}", unsigned(decode_varint(&mut args, long)?));
+                true
+            }
+            Specifier::Hex => {
+                let arg = unsigned(decode_varint(&mut args, long)?);
+                if flags.contains(&Flag::AlternateSyntax) {
+                    let _ = write!(value, "{arg:#x}");
+                } else {
+                    let _ = write!(value, "{arg:x}");
+                }
+                true
+            }
+            Specifier::UpperHex => {
+                let arg = unsigned(decode_varint(&mut args, long)?);
+                if flags.contains(&Flag::AlternateSyntax) {
+                    let _ = write!(value, "0X{arg:X}");
+                } else {
+                    let _ = write!(value, "{arg:X}");
+                }
+                true
+            }
+            Specifier::Pointer => {
+                let arg = unsigned(decode_varint(&mut args, long)?);
+                let _ = write!(value, "0x{arg:08x}");
+                false
+            }
+            Specifier::Char => {
+                value.push(char::from(decode_bytes(&mut args, 1)?[0]));
+                false
+            }
+            Specifier::String => {
+                let header = decode_bytes(&mut args, 1)?[0];
+                let data = decode_bytes(&mut args, usize::from(header & 0x7f))?;
+                let string = String::from_utf8_lossy(data);
+                match precision {
+                    Some(precision) => value.extend(string.chars().take(precision)),
+                    None => value.push_str(&string),
+                }
+                if header & 0x80 != 0 {
+                    value.push_str("[...]");
+                }
+                false
+            }
+            Specifier::Double
+            | Specifier::UpperDouble
+            | Specifier::Exponential
+            | Specifier::UpperExponential
+            | Specifier::SmallDouble
+            | Specifier::UpperSmallDouble => {
+                let bytes = decode_bytes(&mut args, 4)?;
+                let arg = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                let precision = precision.unwrap_or(6);
+                let _ = match spec.specifier {
+                    Specifier::Double | Specifier::UpperDouble => {
+                        write!(value, "{arg:.precision$}")
+                    }
+                    Specifier::Exponential => write!(value, "{arg:.precision$e}"),
+                    Specifier::UpperExponential => write!(value, "{arg:.precision$E}"),
+                    _ => write!(value, "{arg}"),
+                };
+                true
+            }
+            Specifier::Untyped => return Err(Error::InvalidArgument),
+        };
+        pad(&mut output, &value, width, flags, numeric);
+    }
+
+    if args.is_empty() {
+        Ok(output)
+    } else {
+        Err(Error::DataLoss)
+    }
+}
+
+// Re-export dependences of the backend macro to be accessed via
+// `$crate::__private`.
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+
+    pub use pw_bytes::concat_static_strs;
+    pub use pw_format_core::PrintfFormatter;
+    pub use pw_tokenizer::tokenize_to_buffer;
+
+    pub use crate::ENCODING_BUFFER_SIZE_BYTES;
+
+    pub fn capture(
+        level: LogLevel,
+        module: &'static str,
+        line: u32,
+        format_string: &'static str,
+        encoded: Option<&[u8]>,
+    ) {
+        let log = CapturedLog {
+            level,
+            module,
+            line,
+            format_string,
+            encoded: encoded.map(<[u8]>::to_vec),
+        };
+        LOGS.with(|logs| logs.borrow_mut().push(log));
+    }
+}
+
+/// Implements the `pw_log` backend API.
+///
+/// Use the `pw_log` macros rather than calling this directly.
+#[macro_export]
+macro_rules! pw_logf_backend {
+  ($log_level:expr, $format_string:literal $(, $args:expr)* $(,)?) => {{
+    use $crate::__private as __pw_log_backend_crate;
+    let format_string: &'static str = $crate::_pw_log_format_string!($format_string, $($args),*);
+    let mut buffer = [0u8; __pw_log_backend_crate::ENCODING_BUFFER_SIZE_BYTES];
+    let encoded = __pw_log_backend_crate::tokenize_to_buffer!(
+        &mut buffer, "■msg♦" PW_FMT_CONCAT $format_string, $($args),*);
+    __pw_log_backend_crate::capture(
+        $log_level,
+        module_path!(),
+        line!(),
+        format_string,
+        encoded.ok().map(|len| &buffer[..len]),
+    );
+  }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_captured_and_decoded() {
+        let name = "sensor";
+        let temp: i16 = -4;
+        let line = line!() + 1;
+        pw_logf_backend!(LogLevel::Info, "Reading %s: %d", name, temp);
+        let code: u16 = 7;
+        pw_logf_backend!(LogLevel::Error, "Failed", code = code as u32);
+
+        let logs = take_logs();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].level(), LogLevel::Info);
+        assert_eq!(logs[0].module(), module_path!());
+        assert_eq!(logs[0].line(), line);
+        assert_eq!(logs[0].message().unwrap(), "Reading sensor: -4");
+        assert_eq!(logs[1].format_string(), "■msg♦Failed■code♦%u");
+        assert_eq!(logs[1].message().unwrap(), "Failed■code♦7");
+        assert!(take_logs().is_empty());
+    }
+
+    #[test]
+    fn captured_messages_match_tokenized_encoding() {
+        pw_logf_backend!(LogLevel::Warn, "The answer is %d", 21);
+
+        let logs = take_logs();
+        let token = pw_tokenizer_core::hash_string("■msg♦The answer is %d");
+        assert_eq!(logs[0].token(), token);
+        let token = token.to_le_bytes();
+        // 21 is zig-zag encoded as 42.
+        assert_eq!(
+            logs[0].encoded(),
+            Some(&[token[0], token[1], token[2], token[3], 42][..])
+        );
+    }
+
+    #[test]
+    fn logged_matches_level_and_text() {
+        let percent: u32 = 9;
+        pw_logf_backend!(LogLevel::Warn, "Battery at %u%%", percent);
+        assert!(logged(LogLevel::Warn, "Battery at 9%"));
+        assert!(!logged(LogLevel::Error, "Battery"));
+        assert!(!logged(LogLevel::Warn, "Sensor"));
+        take_logs();
+    }
+
+    #[test]
+    fn message_too_long_is_dropped() {
+        let long = "x".repeat(100);
+        pw_logf_backend!(LogLevel::Info, "%s %d", long.as_str(), 1);
+
+        let logs = take_logs();
+        assert!(logs[0].is_dropped());
+        assert_eq!(logs[0].message(), Err(Error::DataLoss));
+    }
+
+    #[test]
+    fn arguments_are_decoded_with_conversions() {
+        let mut args = std::vec::Vec::new();
+        args.extend_from_slice(&[0x09]); // -5 as %05d
+        args.extend_from_slice(&[0xfe, 0x03]); // 255 as %x
+        args.extend_from_slice(&[0x01]); // -1 as %u
+        args.push(b'c');
+        args.extend_from_slice(&[0x82, b'h', b'i']); // Truncated "hi"
+        args.extend_from_slice(&1.5f32.to_le_bytes());
+        assert_eq!(
+            decode("%05d %#x %u %c %-4s| %.2f", &args).unwrap(),
+            "-0005 0xff 4294967295 c hi[...]| 1.50"
+        );
+        assert_eq!(decode("%d", &[]), Err(Error::DataLoss));
+        assert_eq!(decode("%d", &[0x02, 0x02]), Err(Error::DataLoss));
+        assert_eq!(decode("%*d", &[0x02]), Err(Error::InvalidArgument));
+    }
+}
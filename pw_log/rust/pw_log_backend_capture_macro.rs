@@ -0,0 +1,76 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use proc_macro::TokenStream;
+use proc_macro2::Ident;
+use quote::quote;
+use syn::parse_macro_input;
+
+use pw_format::macros::{
+    generate_printf, Arg, FormatAndArgs, PrintfFormatMacroGenerator, PrintfFormatStringFragment,
+    Result,
+};
+
+type TokenStream2 = proc_macro2::TokenStream;
+
+// Generator that implements [`pw_format::PrintfFormatMacroGenerator`] to
+// produce the `printf` style format string `pw_tokenizer` tokenizes for a log
+// line.  Arguments are not evaluated.
+struct FormatStringGenerator;
+
+impl PrintfFormatMacroGenerator for FormatStringGenerator {
+    fn finalize(
+        self,
+        format_string_fragments: &[PrintfFormatStringFragment],
+    ) -> Result<TokenStream2> {
+        let format_string_pieces: Vec<_> = format_string_fragments
+            .iter()
+            .map(|fragment| fragment.as_token_stream("__pw_log_backend_crate"))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(quote! {
+          __pw_log_backend_crate::concat_static_strs!("■msg♦", #(#format_string_pieces),*)
+        })
+    }
+
+    // Conversions use the same default format specifiers as `pw_tokenizer`.
+    fn string_fragment(&mut self, _string: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn integer_conversion(&mut self, _ty: Ident, _expression: Arg) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn string_conversion(&mut self, _expression: Arg) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn char_conversion(&mut self, _expression: Arg) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn untyped_conversion(&mut self, _expression: Arg) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[proc_macro]
+pub fn _pw_log_format_string(tokens: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(tokens as FormatAndArgs);
+
+    match generate_printf(FormatStringGenerator, input) {
+        Ok(token_stream) => token_stream.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_log` backend that emits log messages with [`defmt`].
+//!
+//! This backend lets projects migrating from `defmt` use `pw_log` in new code
+//! while keeping a single logging stack: `pw_log` messages are sent through
+//! `defmt`'s global logger and decoded by the existing `defmt` host tooling.
+//!
+//! `printf` style format strings are translated to `defmt` format strings at
+//! compile time, so messages are interned by `defmt` in the same way as
+//! `defmt::info!()`.  `defmt` has no critical or fatal levels so those
+//! messages are logged at the error level.  As usual for `defmt`, messages
+//! are further filtered at compile time with the `DEFMT_LOG` environment
+//! variable.
+//!
+//! To send `defmt` frames over a `pw_stream` transport instead, use
+//! `pw_stream_defmt`.
+#![no_std]
+#![deny(missing_docs)]
+
+pub use pw_log_backend_defmt_macro::_pw_logf_backend;
+
+// Re-export dependences of the backend macro to be accessed via
+// `$crate::__private`.  `defmt`'s macros refer to `defmt` by name so logging
+// crates do not need to depend on it directly.
+#[doc(hidden)]
+pub mod __private {
+    pub use defmt;
+    pub use pw_log_backend_api::LogLevel;
+}
+
+/// Implements the `pw_log` backend API.
+///
+/// Use the `pw_log` macros rather than calling this directly.
+#[macro_export]
+macro_rules! pw_logf_backend {
+  ($log_level:expr, $format_string:literal $(, $args:expr)* $(,)?) => {{
+    use $crate::__private as __pw_log_backend_crate;
+    $crate::_pw_logf_backend!($log_level, $format_string, $($args),*);
+  }};
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+
+    use pw_log_backend_api::LogLevel;
+    use pw_stream::VecWriter;
+
+    // `pw_stream_defmt` provides the global logger for tests.
+    use pw_stream_defmt as _;
+
+    // Normally provided by `defmt`'s linker script, which host tests do not
+    // use.
+    defmt::timestamp!("");
+
+    #[test]
+    fn log_is_sent_to_defmt_logger() {
+        let writer: &'static mut VecWriter<64> = Box::leak(Box::new(VecWriter::new()));
+        let writer_ptr: *const VecWriter<64> = writer;
+        pw_stream_defmt::set_writer(writer);
+
+        // `defmt` only logs errors unless `DEFMT_LOG` is set.
+        let level = LogLevel::Fatal;
+        pw_logf_backend!(level, "The answer is %d", 42);
+        assert!(pw_stream_defmt::take_writer().is_some());
+
+        // Safety: The writer has been removed from the logger.
+        let output = unsafe { (*writer_ptr).as_slice() };
+        // The frame is terminated by a zero byte.
+        assert_eq!(output.last(), Some(&0));
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_log` backend for host builds which prints readable log lines to
+//! `stdout` and `stderr`.
+//!
+//! Messages are rendered from their format strings in process, so host unit
+//! tests and simulators get readable logs from the same macros used on
+//! device without a detokenization step.  Each line is prefixed with the time
+//! since the first message was logged, the log level, and the logging
+//! module's [`module_path!()`] padded to [`MODULE_NAME_WIDTH`] characters:
+//!
+//! ```text
+//!     0.000 INF my_app           Booting
+//!     0.152 WRN my_app::sensor   Sensor 3 not responding
+//! ```
+//!
+//! Debug and info messages are printed to `stdout` while warnings and more
+//! severe messages are printed to `stderr`.  Levels are colored, matching
+//! `pw_log_basic`, when the stream is a terminal and the `NO_COLOR`
+//! environment variable is not set.  Output is written with [`std::print!`]
+//! and [`std::eprint!`] so it is captured by the Rust test harness.
+//!
+//! If the `PW_LOG_SHOW_TOKENS` environment variable is set, each line also
+//! includes the token the message would have with the tokenized backend, so
+//! lines can be matched with a token database or a detokenized serial stream:
+//!
+//! ```text
+//!     0.000 INF my_app           [4a2f31c8] Booting
+//! ```
+//!
+//! Tokens of messages which use untyped (`%v`) arguments may not match.
+//!
+//! Fields pushed with [`pw_log_context::push()`] are appended to each message
+//! as `■key♦value`.
+//!
+//! *Note*: This module requires `std`.
+use std::fmt::{self, Write};
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use pw_log_backend_api::LogLevel;
+
+pub use pw_log_backend_std_macro::_pw_logf_backend;
+
+/// Module names shorter than this are padded so messages line up.
+pub const MODULE_NAME_WIDTH: usize = 16;
+
+const RESET: &str = "\x1b[0m";
+
+// Colored level tags used by `pw_log_basic`.
+const fn colored_level_tag(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Debug => "\x1b[96m\x1b[1mDBG",
+        LogLevel::Info => "\x1b[35m\x1b[1mINF",
+        LogLevel::Warn => "\x1b[33m\x1b[1mWRN",
+        LogLevel::Error => "\x1b[31m\x1b[1mERR",
+        LogLevel::Critical => "\x1b[30m\x1b[1m\x1b[41mCRT",
+        LogLevel::Fatal => "\x1b[30m\x1b[1m\x1b[41mFTL",
+    }
+}
+
+const fn level_tag(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Debug => "DBG",
+        LogLevel::Info => "INF",
+        LogLevel::Warn => "WRN",
+        LogLevel::Error => "ERR",
+        LogLevel::Critical => "CRT",
+        LogLevel::Fatal => "FTL",
+    }
+}
+
+// How a log line is rendered.
+#[derive(Clone, Copy)]
+struct Style {
+    color: bool,
+    show_token: bool,
+}
+
+// Renders a log line, including its trailing newline.
+fn format_log_line(
+    level: LogLevel,
+    site: &__private::Site,
+    elapsed: Duration,
+    style: Style,
+    args: fmt::Arguments,
+) -> String {
+    let mut line = String::new();
+    let secs = elapsed.as_secs();
+    let millis = elapsed.subsec_millis();
+    let module = site.module;
+    // Writing to a `String` can not fail.
+    let _ = if style.color {
+        write!(
+            line,
+            "{secs:>6}.{millis:03} {}{RESET} {module:<MODULE_NAME_WIDTH$} ",
+            colored_level_tag(level)
+        )
+    } else {
+        write!(
+            line,
+            "{secs:>6}.{millis:03} {} {module:<MODULE_NAME_WIDTH$} ",
+            level_tag(level)
+        )
+    };
+    if style.show_token {
+        let _ = write!(line, "[{:08x}] ", site.token);
+    }
+    let _ = write!(line, "{args}");
+    let _ = pw_log_context::write_fields(&mut line);
+    line.push('\n');
+    line
+}
+
+fn style(is_terminal: bool) -> Style {
+    Style {
+        color: is_terminal && std::env::var_os("NO_COLOR").is_none(),
+        show_token: std::env::var_os("PW_LOG_SHOW_TOKENS").is_some(),
+    }
+}
+
+// Re-export dependences of the backend macro to be accessed via
+// `$crate::__private`.
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+
+    static START: OnceLock<Instant> = OnceLock::new();
+
+    pub use pw_log_backend_api::LogLevel;
+    pub use pw_tokenizer_core::hash_string;
+
+    // The location of a log statement.
+    pub struct Site {
+        pub module: &'static str,
+        // Token of the message as tokenized by the tokenized backend.
+        pub token: u32,
+    }
+
+    pub fn log(level: LogLevel, site: &Site, args: fmt::Arguments) {
+        let elapsed = START.get_or_init(Instant::now).elapsed();
+        if level >= LogLevel::Warn {
+            let style = style(std::io::stderr().is_terminal());
+            eprint!("{}", format_log_line(level, site, elapsed, style, args));
+        } else {
+            let style = style(std::io::stdout().is_terminal());
+            print!("{}", format_log_line(level, site, elapsed, style, args));
+        }
+    }
+}
+
+/// Implements the `pw_log` backend API.
+///
+/// Use the `pw_log` macros rather than calling this directly.
+#[macro_export]
+macro_rules! pw_logf_backend {
+  ($log_level:expr, $format_string:literal $(, $args:expr)* $(,)?) => {{
+    use $crate::__private as __pw_log_backend_crate;
+    // `_pw_logf_backend!` passes `SITE` to `log()`.
+    const SITE: __pw_log_backend_crate::Site = __pw_log_backend_crate::Site {
+        module: module_path!(),
+        token: __pw_log_backend_crate::hash_string(concat!("■msg♦", $format_string)),
+    };
+    $crate::_pw_logf_backend!($log_level, $format_string, $($args),*);
+  }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use __private::Site;
+
+    const SITE: Site = Site {
+        module: "my_app",
+        token: 0x4a2f31c8,
+    };
+
+    const PLAIN: Style = Style {
+        color: false,
+        show_token: false,
+    };
+
+    #[test]
+    fn log_line_is_timestamped() {
+        let name = "sensor";
+        let line = format_log_line(
+            LogLevel::Info,
+            &SITE,
+            Duration::from_millis(1234),
+            PLAIN,
+            format_args!("Reading {name}"),
+        );
+        assert_eq!(line, "     1.234 INF my_app           Reading sensor\n");
+    }
+
+    #[test]
+    fn log_line_level_is_colored() {
+        let style = Style {
+            color: true,
+            ..PLAIN
+        };
+        let line = format_log_line(
+            LogLevel::Error,
+            &SITE,
+            Duration::ZERO,
+            style,
+            format_args!("Failed"),
+        );
+        assert_eq!(
+            line,
+            "     0.000 \x1b[31m\x1b[1mERR\x1b[0m my_app           Failed\n"
+        );
+    }
+
+    #[test]
+    fn log_line_includes_context() {
+        let _context = pw_log_context::push("conn_id", 3).unwrap();
+        let line = format_log_line(
+            LogLevel::Info,
+            &SITE,
+            Duration::ZERO,
+            PLAIN,
+            format_args!("Connected"),
+        );
+        assert_eq!(
+            line,
+            "     0.000 INF my_app           Connected■conn_id♦3\n"
+        );
+    }
+
+    #[test]
+    fn log_line_shows_token() {
+        let style = Style {
+            show_token: true,
+            ..PLAIN
+        };
+        let site = Site {
+            module: "my_app::long_module_name",
+            ..SITE
+        };
+        let line = format_log_line(
+            LogLevel::Warn,
+            &site,
+            Duration::ZERO,
+            style,
+            format_args!("Low"),
+        );
+        assert_eq!(
+            line,
+            "     0.000 WRN my_app::long_module_name [4a2f31c8] Low\n"
+        );
+    }
+
+    #[test]
+    fn backend_macro_accepts_typed_and_untyped_arguments() {
+        let value: i32 = -5;
+        let level = LogLevel::Debug;
+        pw_logf_backend!(level, "Value %d from %s", value, "test");
+        pw_logf_backend!(LogLevel::Warn, "Untyped %v", value);
+    }
+}
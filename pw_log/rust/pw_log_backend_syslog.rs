@@ -0,0 +1,347 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_log` backend for Linux hosted components which sends log messages to
+//! journald or syslog.
+//!
+//! Messages are rendered from their format strings in process, as in the
+//! `std` backend, and sent to the journal with its native protocol when
+//! [`JOURNALD_SOCKET`] exists.  Journal entries are structured: along with
+//! `MESSAGE`, `PRIORITY`, and `SYSLOG_IDENTIFIER`, each entry has the
+//! logging module's [`module_path!()`] as `PW_MODULE`, the token the message
+//! would have with the tokenized backend as `PW_TOKEN`, and each field pushed
+//! with [`pw_log_context::push()`] under its key in upper case:
+//!
+//! ```text
+//! MESSAGE=Sensor 3 not responding
+//! PRIORITY=4
+//! SYSLOG_IDENTIFIER=my_app
+//! PW_MODULE=my_app::sensor
+//! PW_TOKEN=4a2f31c8
+//! CONN_ID=3
+//! ```
+//!
+//! Otherwise messages are sent to the syslog daemon's [`SYSLOG_SOCKET`] as
+//! lines in the traditional BSD syslog format with the `user` facility, such
+//! as `<12>my_app[42]: my_app::sensor: Sensor 3 not responding■conn_id♦3`.
+//! If neither socket exists, messages are printed to `stderr` in the same
+//! format.
+//!
+//! Messages are identified by the name of the running program unless another
+//! identifier is set with [`set_identifier()`].
+//!
+//! *Note*: This module requires `std` and a Unix host.
+use std::fmt::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use pw_log_backend_api::LogLevel;
+use pw_status::{Error, Result};
+
+pub use pw_log_backend_std_macro::_pw_logf_backend;
+
+/// The socket of the journal's native protocol.
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The socket of the syslog daemon.
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+// The `user` facility, shifted into place in a syslog priority value.
+const USER_FACILITY: u8 = 1 << 3;
+
+/// Returns the syslog severity, which journald calls the priority, of
+/// messages logged at `level`.
+pub const fn syslog_severity(level: LogLevel) -> u8 {
+    match level {
+        LogLevel::Debug => 7,
+        LogLevel::Info => 6,
+        LogLevel::Warn => 4,
+        LogLevel::Error => 3,
+        LogLevel::Critical => 2,
+        LogLevel::Fatal => 1,
+    }
+}
+
+static IDENTIFIER: OnceLock<String> = OnceLock::new();
+
+/// Sets the identifier, such as a service name, which log messages are sent
+/// with.
+///
+/// # Errors
+/// - [`Error::AlreadyExists`] - The identifier was already set, either by a
+///   previous call or by logging a message.
+pub fn set_identifier(identifier: &str) -> Result<()> {
+    IDENTIFIER
+        .set(identifier.to_string())
+        .map_err(|_| Error::AlreadyExists)
+}
+
+fn identifier() -> &'static str {
+    IDENTIFIER.get_or_init(|| {
+        std::env::args_os()
+            .next()
+            .as_deref()
+            .and_then(|program| Path::new(program).file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "pw_log".to_string())
+    })
+}
+
+// Appends a journal field to `entry`.  Values with newlines use the binary
+// form of the native protocol.
+fn append_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+// Converts a context key to a journal field name, which may only contain
+// upper case letters, digits, and underscores and may not start with an
+// underscore or digit.
+fn field_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    match name.chars().next() {
+        Some(c) if c.is_ascii_uppercase() => name,
+        _ => format!("PW{name}"),
+    }
+}
+
+// Renders a log message as a journal entry.
+fn journal_entry(
+    level: LogLevel,
+    site: &__private::Site,
+    identifier: &str,
+    args: fmt::Arguments,
+) -> Vec<u8> {
+    let mut entry = Vec::new();
+    append_field(&mut entry, "MESSAGE", &args.to_string());
+    append_field(&mut entry, "PRIORITY", &syslog_severity(level).to_string());
+    append_field(&mut entry, "SYSLOG_IDENTIFIER", identifier);
+    append_field(&mut entry, "PW_MODULE", site.module);
+    append_field(&mut entry, "PW_TOKEN", &format!("{:08x}", site.token));
+    pw_log_context::for_each(|key, value| {
+        append_field(&mut entry, &field_name(key), &value.to_string())
+    });
+    entry
+}
+
+// Renders a log message as a syslog line, without a trailing newline.
+fn syslog_line(
+    level: LogLevel,
+    site: &__private::Site,
+    identifier: &str,
+    pid: u32,
+    args: fmt::Arguments,
+) -> String {
+    let priority = USER_FACILITY | syslog_severity(level);
+    let mut line = String::new();
+    // Writing to a `String` can not fail.
+    let _ = write!(
+        line,
+        "<{priority}>{identifier}[{pid}]: {}: {args}",
+        site.module
+    );
+    let _ = pw_log_context::write_fields(&mut line);
+    line
+}
+
+enum Transport {
+    Journald(UnixDatagram),
+    Syslog(UnixDatagram),
+    Stderr,
+}
+
+impl Transport {
+    fn connect(journald_socket: &Path, syslog_socket: &Path) -> Self {
+        let connect = |path: &Path| {
+            let socket = UnixDatagram::unbound().ok()?;
+            socket.connect(path).ok()?;
+            Some(socket)
+        };
+        if let Some(socket) = connect(journald_socket) {
+            Transport::Journald(socket)
+        } else if let Some(socket) = connect(syslog_socket) {
+            Transport::Syslog(socket)
+        } else {
+            Transport::Stderr
+        }
+    }
+
+    fn send(&self, level: LogLevel, site: &__private::Site, args: fmt::Arguments) {
+        let identifier = identifier();
+        // Messages which can not be sent are dropped, since there is nowhere
+        // to report the failure.
+        match self {
+            Transport::Journald(socket) => {
+                let _ = socket.send(&journal_entry(level, site, identifier, args));
+            }
+            Transport::Syslog(socket) => {
+                let line = syslog_line(level, site, identifier, std::process::id(), args);
+                let _ = socket.send(line.as_bytes());
+            }
+            Transport::Stderr => {
+                eprintln!(
+                    "{}",
+                    syslog_line(level, site, identifier, std::process::id(), args)
+                );
+            }
+        }
+    }
+}
+
+// Re-export dependences of the backend macro to be accessed via
+// `$crate::__private`.
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+
+    static TRANSPORT: OnceLock<Transport> = OnceLock::new();
+
+    pub use pw_log_backend_api::LogLevel;
+    pub use pw_tokenizer_core::hash_string;
+
+    // The location of a log statement.
+    pub struct Site {
+        pub module: &'static str,
+        // Token of the message as tokenized by the tokenized backend.
+        pub token: u32,
+    }
+
+    pub fn log(level: LogLevel, site: &Site, args: fmt::Arguments) {
+        TRANSPORT
+            .get_or_init(|| {
+                Transport::connect(Path::new(JOURNALD_SOCKET), Path::new(SYSLOG_SOCKET))
+            })
+            .send(level, site, args);
+    }
+}
+
+/// Implements the `pw_log` backend API.
+///
+/// Use the `pw_log` macros rather than calling this directly.
+#[macro_export]
+macro_rules! pw_logf_backend {
+  ($log_level:expr, $format_string:literal $(, $args:expr)* $(,)?) => {{
+    use $crate::__private as __pw_log_backend_crate;
+    // `_pw_logf_backend!` passes `SITE` to `log()`.
+    const SITE: __pw_log_backend_crate::Site = __pw_log_backend_crate::Site {
+        module: module_path!(),
+        token: __pw_log_backend_crate::hash_string(concat!("■msg♦", $format_string)),
+    };
+    $crate::_pw_logf_backend!($log_level, $format_string, $($args),*);
+  }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use __private::Site;
+
+    const SITE: Site = Site {
+        module: "my_app::sensor",
+        token: 0x4a2f31c8,
+    };
+
+    #[test]
+    fn journal_entry_has_structured_fields() {
+        let _context = pw_log_context::push("conn_id", 3).unwrap();
+        let sensor = 3;
+        let entry = journal_entry(
+            LogLevel::Warn,
+            &SITE,
+            "my_app",
+            format_args!("Sensor {sensor} not responding"),
+        );
+        assert_eq!(
+            String::from_utf8(entry).unwrap(),
+            "MESSAGE=Sensor 3 not responding\n\
+             PRIORITY=4\n\
+             SYSLOG_IDENTIFIER=my_app\n\
+             PW_MODULE=my_app::sensor\n\
+             PW_TOKEN=4a2f31c8\n\
+             CONN_ID=3\n"
+        );
+    }
+
+    #[test]
+    fn multiline_journal_field_is_length_prefixed() {
+        let mut entry = Vec::new();
+        append_field(&mut entry, "MESSAGE", "a\nb");
+        assert_eq!(entry, b"MESSAGE\n\x03\0\0\0\0\0\0\0a\nb\n");
+    }
+
+    #[test]
+    fn context_keys_are_valid_field_names() {
+        assert_eq!(field_name("conn_id"), "CONN_ID");
+        assert_eq!(field_name("rx-bytes"), "RX_BYTES");
+        assert_eq!(field_name("2g"), "PW2G");
+        assert_eq!(field_name("_id"), "PW_ID");
+    }
+
+    #[test]
+    fn syslog_line_has_user_facility_priority() {
+        let _context = pw_log_context::push("conn_id", 3).unwrap();
+        let line = syslog_line(LogLevel::Error, &SITE, "my_app", 42, format_args!("Failed"));
+        assert_eq!(line, "<11>my_app[42]: my_app::sensor: Failed■conn_id♦3");
+    }
+
+    #[test]
+    fn entries_are_sent_to_journald_socket() {
+        let dir = std::env::temp_dir().join(format!("pw_log_syslog_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let journald = dir.join("journal");
+        let _ = std::fs::remove_file(&journald);
+        let receiver = UnixDatagram::bind(&journald).unwrap();
+
+        let transport = Transport::connect(&journald, &dir.join("missing"));
+        assert!(matches!(transport, Transport::Journald(_)));
+        transport.send(LogLevel::Info, &SITE, format_args!("Hello"));
+
+        let mut buffer = [0u8; 256];
+        let len = receiver.recv(&mut buffer).unwrap();
+        assert!(buffer[..len].starts_with(b"MESSAGE=Hello\nPRIORITY=6\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn identifier_can_only_be_set_once() {
+        let current = identifier();
+        assert_eq!(set_identifier("other"), Err(Error::AlreadyExists));
+        assert_eq!(identifier(), current);
+    }
+
+    #[test]
+    fn backend_macro_accepts_typed_and_untyped_arguments() {
+        let value: i32 = -5;
+        let level = LogLevel::Debug;
+        pw_logf_backend!(level, "Value %d from %s", value, "test");
+        pw_logf_backend!(LogLevel::Warn, "Untyped %v", value);
+    }
+}
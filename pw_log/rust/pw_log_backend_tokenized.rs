@@ -0,0 +1,1377 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_log` backend that tokenizes log messages with [`pw_tokenizer`].
+//!
+//! This backend is the Rust counterpart of the C/C++ `pw_log_tokenized`
+//! module.  Log messages are encoded into a buffer with
+//! [`pw_tokenizer::tokenize_to_buffer!`] and passed to
+//! `pw_log_tokenized_HandleLog()` along with a packed [`Metadata`] payload
+//! containing the log level, line number, flags, and module token.  Because
+//! the handler is the same C function used by `pw_log_tokenized`, existing
+//! C/C++ handlers and host tooling work with logs from Rust unchanged.
+//!
+//! As in `pw_log_tokenized`, the format string is tokenized with a `■msg♦`
+//! field prefix and any key-value fields follow the message as `■key♦`
+//! fields.  Module tokens are the lower 16 bits of the token of the
+//! logging module's [`module_path!()`].
+//!
+//! Fields pushed with [`pw_log_context::push()`] are attached to each message
+//! as a `■ctx♦` field whose string value holds the context's `■key♦value`
+//! fields.  Messages logged without context are encoded as usual.
+//!
+//! Messages can be timestamped with the ticks of the system clock set by
+//! [`pw_chrono::set_system_clock()`] by calling [`enable_timestamps()`].  The
+//! timestamp is encoded as a varint before the token, either as the clock's
+//! ticks or as the ticks since the previous message.  Host tools decode it
+//! with a [`TimestampDecoder`].
+//!
+//! Messages which do not fit in the encoding buffer are not lost silently.
+//! They are counted by level and module, and once a message is logged
+//! successfully a `"■msg♦%u messages dropped"` message is logged for each
+//! level and module which dropped messages.
+//!
+//! With the `deferred` feature, which the `pw_log_backend_tokenized_deferred`
+//! target enables, log statements only copy the token and arguments of their
+//! message into a lock-free queue with `defer()`, so they are cheap and safe
+//! to use in interrupt handlers.  A background task calls
+//! `process_deferred_logs()` to encode the queued messages and pass them to
+//! `pw_log_tokenized_HandleLog()`.  Context fields are not attached to
+//! deferred messages.
+//!
+//! State shared between log statements, such as the previous timestamp and
+//! dropped message counts, is protected by masking interrupts with
+//! `pw_interrupt`, so messages may also be logged from interrupt handlers.
+//!
+//! Code which buffers log messages adds a handler with [`add_flush_handler()`]
+//! which sends them immediately.  [`flush()`] calls each handler, and is
+//! called by [`log_panic()`] so that the final messages before a panic are not
+//! lost when the device resets.
+//!
+//! Projects without a C/C++ handler implement it in Rust:
+//!
+//! ```
+//! use pw_log_backend::Metadata;
+//!
+//! #[no_mangle]
+//! extern "C" fn pw_log_tokenized_HandleLog(
+//!     metadata: u32,
+//!     encoded_message: *const u8,
+//!     size_bytes: usize,
+//! ) {
+//!     let metadata = Metadata::from_value(metadata);
+//!     // Safety: `pw_log_backend` passes a valid message buffer.
+//!     let message = unsafe { core::slice::from_raw_parts(encoded_message, size_bytes) };
+//!     // Send `message` to the host, dropping it if `metadata.level()` is too
+//!     // low.
+//! }
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+use core::cell::{Cell, RefCell};
+use core::fmt::{self, Write};
+
+use critical_section::Mutex;
+use pw_status::{Error, Result};
+use pw_varint::{VarintDecode, VarintEncode};
+
+// Re-export dependences of the backend macro to be accessed via
+// `$crate::__private`.
+#[doc(hidden)]
+pub mod __private {
+    pub use pw_log_context::{format_fields, is_empty as context_is_empty};
+    pub use pw_tokenizer::tokenize_to_buffer;
+    pub use pw_tokenizer_core::hash_string;
+
+    pub use crate::{log, Metadata, CONTEXT_BUFFER_SIZE_BYTES};
+
+    #[cfg(feature = "deferred")]
+    pub use {crate::defer, pw_tokenizer::tokenize_to_args};
+}
+
+/// Size of the buffer each log message is encoded into.
+///
+/// Matches the default `PW_LOG_TOKENIZED_ENCODING_BUFFER_SIZE_BYTES` of
+/// `pw_log_tokenized`.  Messages which do not fit are dropped.
+pub const ENCODING_BUFFER_SIZE_BYTES: usize = 52;
+
+/// Size of the buffer context fields are formatted into.  Fields which do not
+/// fit are omitted.
+pub const CONTEXT_BUFFER_SIZE_BYTES: usize = 32;
+
+/// Log metadata packed into the payload passed to
+/// `pw_log_tokenized_HandleLog()`.
+///
+/// The layout matches `pw::log_tokenized::Metadata` with its default
+/// configuration.  From least to most significant bit the fields are:
+///
+/// | Field  | Bits |
+/// | ------ | ---- |
+/// | level  | 3    |
+/// | line   | 11   |
+/// | flags  | 2    |
+/// | module | 16   |
+///
+/// # Example
+///
+/// ```
+/// use pw_log_backend::Metadata;
+///
+/// let metadata = Metadata::new(2, 0x1234, 1, 42);
+/// assert_eq!(metadata.level(), 2);
+/// assert_eq!(metadata.module(), 0x1234);
+/// assert_eq!(metadata.flags(), 1);
+/// assert_eq!(metadata.line_number(), 42);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata(u32);
+
+impl Metadata {
+    /// Number of bits used to store the log level.
+    pub const LEVEL_BITS: u32 = 3;
+    /// Number of bits used to store the line number.
+    pub const LINE_BITS: u32 = 11;
+    /// Number of bits used to store the flags.
+    pub const FLAG_BITS: u32 = 2;
+    /// Number of bits used to store the module token.
+    pub const MODULE_BITS: u32 = 16;
+
+    const LINE_SHIFT: u32 = Self::LEVEL_BITS;
+    const FLAG_SHIFT: u32 = Self::LINE_SHIFT + Self::LINE_BITS;
+    const MODULE_SHIFT: u32 = Self::FLAG_SHIFT + Self::FLAG_BITS;
+
+    const fn mask(bits: u32) -> u32 {
+        (1 << bits) - 1
+    }
+
+    /// Packs metadata for a log message.
+    ///
+    /// As in `pw_log_tokenized`, levels which are too large are clamped to
+    /// the largest level, line numbers which are too large are stored as 0,
+    /// and flags and module tokens are truncated to their field widths.
+    pub const fn new(level: u32, module: u32, flags: u32, line: u32) -> Self {
+        let line = if line <= Self::mask(Self::LINE_BITS) {
+            line
+        } else {
+            0
+        };
+        Self(
+            line << Self::LINE_SHIFT
+                | (flags & Self::mask(Self::FLAG_BITS)) << Self::FLAG_SHIFT
+                | (module & Self::mask(Self::MODULE_BITS)) << Self::MODULE_SHIFT,
+        )
+        .with_level(level)
+    }
+
+    /// Returns a copy of the metadata with its level replaced by `level`.
+    ///
+    /// The log macros pack the module, flags, and line number of each call
+    /// site into a constant and add the level with `with_level()` when the
+    /// message is logged.
+    pub const fn with_level(self, level: u32) -> Self {
+        let level_mask = Self::mask(Self::LEVEL_BITS);
+        let level = if level <= level_mask {
+            level
+        } else {
+            level_mask
+        };
+        Self(self.0 & !level_mask | level)
+    }
+
+    /// Create a `Metadata` from a packed value.
+    pub const fn from_value(value: u32) -> Self {
+        Self(value)
+    }
+
+    /// Returns the log level of the message.
+    pub const fn level(&self) -> u32 {
+        self.0 & Self::mask(Self::LEVEL_BITS)
+    }
+
+    /// Returns the line number of the log call.  If the line number is 0, it
+    /// was too large to be stored.
+    pub const fn line_number(&self) -> u32 {
+        (self.0 >> Self::LINE_SHIFT) & Self::mask(Self::LINE_BITS)
+    }
+
+    /// Returns the flags of the log call.
+    pub const fn flags(&self) -> u32 {
+        (self.0 >> Self::FLAG_SHIFT) & Self::mask(Self::FLAG_BITS)
+    }
+
+    /// Returns the token of the logging module.
+    pub const fn module(&self) -> u32 {
+        (self.0 >> Self::MODULE_SHIFT) & Self::mask(Self::MODULE_BITS)
+    }
+
+    /// Returns the packed metadata.
+    pub const fn value(&self) -> u32 {
+        self.0
+    }
+
+    /// Returns the packed module, flags, and line number, which identify the
+    /// log call site.
+    ///
+    /// Host tools can group messages by this key without a separate call site
+    /// table.  Call sites in the same module share a key if they are on the
+    /// same line of different files or if their line numbers are too large to
+    /// be stored.
+    pub const fn site_key(&self) -> u32 {
+        self.0 >> Self::LINE_SHIFT
+    }
+}
+
+extern "C" {
+    fn pw_log_tokenized_HandleLog(metadata: u32, encoded_message: *const u8, size_bytes: usize);
+}
+
+/// Passes an encoded log message to `pw_log_tokenized_HandleLog()`.
+pub fn handle_log(metadata: Metadata, encoded_message: &[u8]) {
+    // Safety: The message buffer is valid for the duration of the call and
+    // handlers must not retain it.
+    unsafe {
+        pw_log_tokenized_HandleLog(
+            metadata.value(),
+            encoded_message.as_ptr(),
+            encoded_message.len(),
+        )
+    }
+}
+
+/// How timestamps are encoded in log messages.
+///
+/// Timestamps are the system clock's ticks, so its ticks per second must be
+/// known to the host.  Messages logged before the system clock is set are
+/// timestamped with 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampEncoding {
+    /// Each message carries the clock's ticks.
+    Absolute,
+
+    /// Each message carries the ticks since the previous message, which is
+    /// usually encoded in fewer bytes.  The first message after timestamps
+    /// are enabled carries the clock's ticks.
+    ///
+    /// Only the clock and the previous timestamp are read with interrupts
+    /// masked.  A message logged by an interrupt handler while another is
+    /// being passed to `pw_log_tokenized_HandleLog()` may be handled first,
+    /// which shifts the decoded timestamps of those two messages but not of
+    /// later ones.
+    Delta,
+}
+
+static TIMESTAMP_ENCODING: Mutex<Cell<Option<TimestampEncoding>>> = Mutex::new(Cell::new(None));
+
+// Timestamp of the previous message in `TimestampEncoding::Delta` mode.
+static LAST_TIMESTAMP: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+
+/// Timestamps all subsequent log messages with the system clock.
+pub fn enable_timestamps(encoding: TimestampEncoding) {
+    pw_interrupt::free(|cs| {
+        TIMESTAMP_ENCODING.borrow(cs).set(Some(encoding));
+        LAST_TIMESTAMP.borrow(cs).set(0);
+    })
+}
+
+/// Stops timestamping log messages.
+pub fn disable_timestamps() {
+    pw_interrupt::free(|cs| TIMESTAMP_ENCODING.borrow(cs).set(None))
+}
+
+fn now_ticks() -> u64 {
+    pw_chrono::system_clock().map_or(0, |clock| clock.now_ticks())
+}
+
+/// Maximum number of level and module pairs whose dropped messages are
+/// counted separately.
+///
+/// Once this many pairs have pending drops, further drops are combined and
+/// reported with a module token of 0 and the highest of their levels.
+pub const MAX_DROP_COUNTERS: usize = 8;
+
+#[derive(Clone, Copy)]
+struct DropCounter {
+    // Level and module of the dropped messages.  The line number is 0.
+    metadata: Metadata,
+    count: u32,
+}
+
+struct DropCounters {
+    counters: [Option<DropCounter>; MAX_DROP_COUNTERS],
+    other: Option<DropCounter>,
+}
+
+static DROPS: Mutex<RefCell<DropCounters>> = Mutex::new(RefCell::new(DropCounters {
+    counters: [None; MAX_DROP_COUNTERS],
+    other: None,
+}));
+
+// Counts `count` dropped messages with the level and module of `metadata`.
+fn record_drops(metadata: Metadata, count: u32) {
+    let level = metadata.level();
+    let metadata = Metadata::new(level, metadata.module(), 0, 0);
+    pw_interrupt::free(|cs| {
+        let mut drops = DROPS.borrow_ref_mut(cs);
+        let drops = &mut *drops;
+        let (counter, metadata) = match drops
+            .counters
+            .iter()
+            .position(|counter| counter.is_some_and(|c| c.metadata == metadata))
+            .or_else(|| drops.counters.iter().position(Option::is_none))
+        {
+            Some(index) => (&mut drops.counters[index], metadata),
+            None => (&mut drops.other, Metadata::new(level, 0, 0, 0)),
+        };
+        let counter = counter.get_or_insert(DropCounter { metadata, count: 0 });
+        if counter.metadata.level() < level {
+            counter.metadata = metadata;
+        }
+        counter.count = counter.count.saturating_add(count);
+    })
+}
+
+// Logs a message for each level and module which dropped messages.
+fn report_drops() {
+    let drops = pw_interrupt::free(|cs| {
+        let mut drops = DROPS.borrow_ref_mut(cs);
+        let counters = core::mem::replace(&mut drops.counters, [None; MAX_DROP_COUNTERS]);
+        (counters, drops.other.take())
+    });
+    let (counters, other) = drops;
+    for DropCounter { metadata, count } in counters.into_iter().chain([other]).flatten() {
+        let encode = |buffer: &mut [u8]| {
+            pw_tokenizer::tokenize_to_buffer!(buffer, "■msg♦%u messages dropped", count)
+        };
+        if try_log(metadata, encode).is_err() {
+            record_drops(metadata, count);
+        }
+    }
+}
+
+/// Encodes a log message with `encode_message` and passes it to
+/// `pw_log_tokenized_HandleLog()`, prefixed with a timestamp if timestamps
+/// are enabled.
+///
+/// Messages which do not fit in [`ENCODING_BUFFER_SIZE_BYTES`] are dropped and
+/// reported after the next message which is logged.
+pub fn log(metadata: Metadata, encode_message: impl FnOnce(&mut [u8]) -> Result<usize>) {
+    match try_log(metadata, encode_message) {
+        Ok(()) => report_drops(),
+        Err(_) => record_drops(metadata, 1),
+    }
+}
+
+fn try_log(
+    metadata: Metadata,
+    encode_message: impl FnOnce(&mut [u8]) -> Result<usize>,
+) -> Result<()> {
+    let timestamp = pw_interrupt::free(|cs| TIMESTAMP_ENCODING.borrow(cs).get())
+        .map(|encoding| (now_ticks, encoding));
+    try_log_at(metadata, timestamp, encode_message)
+}
+
+// Logs a message prefixed with the value returned by `read_timestamp`.
+//
+// Only the delta is computed with interrupts masked; the message is encoded
+// and handled with interrupts enabled.
+fn try_log_at(
+    metadata: Metadata,
+    timestamp: Option<(impl FnOnce() -> u64, TimestampEncoding)>,
+    encode_message: impl FnOnce(&mut [u8]) -> Result<usize>,
+) -> Result<()> {
+    let timestamp = match timestamp {
+        None => None,
+        Some((read_timestamp, TimestampEncoding::Absolute)) => Some(read_timestamp()),
+        // The clock is read in the same critical section as the previous
+        // timestamp so that deltas are never negative.
+        Some((read_timestamp, TimestampEncoding::Delta)) => Some(pw_interrupt::free(|cs| {
+            let now = read_timestamp();
+            now.wrapping_sub(LAST_TIMESTAMP.borrow(cs).replace(now))
+        })),
+    };
+
+    let mut buffer = [0u8; ENCODING_BUFFER_SIZE_BYTES];
+    let timestamp_len = match timestamp {
+        Some(timestamp) => timestamp.varint_encode(&mut buffer)?,
+        None => 0,
+    };
+    let message_len = encode_message(&mut buffer[timestamp_len..])?;
+    handle_log(metadata, &buffer[..timestamp_len + message_len]);
+    Ok(())
+}
+
+/// Number of messages the deferred log queue holds.
+///
+/// Messages which are logged while the queue is full are dropped.
+#[cfg(feature = "deferred")]
+pub const DEFERRED_QUEUE_ENTRIES: usize = 16;
+
+/// Maximum number of arguments of a deferred log message.  Messages with more
+/// arguments are dropped.
+#[cfg(feature = "deferred")]
+pub const MAX_DEFERRED_ARGS: usize = 8;
+
+/// Size of the buffer the string arguments of a deferred log message are
+/// copied into.  Strings which do not fit are truncated.
+///
+/// Strings longer than this would not fit in the encoded message either.
+#[cfg(feature = "deferred")]
+pub const DEFERRED_STRING_BUFFER_SIZE_BYTES: usize = ENCODING_BUFFER_SIZE_BYTES - 4;
+
+#[cfg(feature = "deferred")]
+mod deferred {
+    use core::cell::UnsafeCell;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use pw_status::{Error, Result};
+    use pw_tokenizer::Argument;
+    use pw_varint::VarintEncode;
+
+    use super::{
+        now_ticks, record_drops, report_drops, try_log_at, Metadata, TimestampEncoding,
+        DEFERRED_QUEUE_ENTRIES, DEFERRED_STRING_BUFFER_SIZE_BYTES, MAX_DEFERRED_ARGS,
+        TIMESTAMP_ENCODING,
+    };
+
+    const _: () = assert!(DEFERRED_QUEUE_ENTRIES.is_power_of_two());
+
+    // Longest string `pw_tokenizer` encodes.
+    const MAX_STRING_LENGTH: usize = 0x7f;
+
+    #[derive(Clone, Copy)]
+    enum RawArgument {
+        Varint(i32),
+        Varint64(i64),
+        Char(u8),
+        // The string's bytes follow those of the message's previous string
+        // arguments in `DeferredMessage::strings`.
+        String { len: u8, truncated: bool },
+    }
+
+    #[derive(Clone, Copy)]
+    struct DeferredMessage {
+        metadata: Metadata,
+        timestamp: Option<(u64, TimestampEncoding)>,
+        token: u32,
+        args: [RawArgument; MAX_DEFERRED_ARGS],
+        arg_count: usize,
+        strings: [u8; DEFERRED_STRING_BUFFER_SIZE_BYTES],
+    }
+
+    impl DeferredMessage {
+        const EMPTY: Self = Self {
+            metadata: Metadata::from_value(0),
+            timestamp: None,
+            token: 0,
+            args: [RawArgument::Char(0); MAX_DEFERRED_ARGS],
+            arg_count: 0,
+            strings: [0; DEFERRED_STRING_BUFFER_SIZE_BYTES],
+        };
+
+        // Copies the token and arguments of a message without encoding them.
+        fn capture(&mut self, metadata: Metadata, token: u32, args: &[Argument<'_>]) {
+            self.metadata = metadata;
+            self.timestamp = pw_interrupt::free(|cs| TIMESTAMP_ENCODING.borrow(cs).get())
+                .map(|encoding| (now_ticks(), encoding));
+            self.token = token;
+            self.arg_count = args.len();
+            let mut strings_len = 0;
+            for (raw, arg) in self.args.iter_mut().zip(args) {
+                *raw = match *arg {
+                    Argument::Varint(value) => RawArgument::Varint(value),
+                    Argument::Varint64(value) => RawArgument::Varint64(value),
+                    Argument::Char(value) => RawArgument::Char(value),
+                    Argument::String(value) => {
+                        let bytes = value.as_bytes();
+                        let len = bytes
+                            .len()
+                            .min(MAX_STRING_LENGTH)
+                            .min(DEFERRED_STRING_BUFFER_SIZE_BYTES - strings_len);
+                        self.strings[strings_len..strings_len + len].copy_from_slice(&bytes[..len]);
+                        strings_len += len;
+                        RawArgument::String {
+                            len: len as u8,
+                            truncated: len < bytes.len(),
+                        }
+                    }
+                };
+            }
+        }
+
+        // Encodes the message as `tokenize_to_buffer!` would have.
+        fn encode(&self, buffer: &mut [u8]) -> Result<usize> {
+            let token = self.token.to_le_bytes();
+            buffer
+                .get_mut(..token.len())
+                .ok_or(Error::OutOfRange)?
+                .copy_from_slice(&token);
+            let mut len = token.len();
+            let mut strings = &self.strings[..];
+            for arg in &self.args[..self.arg_count] {
+                let buffer = &mut buffer[len..];
+                len += match *arg {
+                    RawArgument::Varint(value) => value.varint_encode(buffer)?,
+                    RawArgument::Varint64(value) => value.varint_encode(buffer)?,
+                    RawArgument::Char(value) => {
+                        *buffer.first_mut().ok_or(Error::OutOfRange)? = value;
+                        1
+                    }
+                    RawArgument::String {
+                        len: string_len,
+                        truncated,
+                    } => {
+                        let (string, rest) = strings.split_at(string_len.into());
+                        strings = rest;
+                        encode_string(buffer, string, truncated)?
+                    }
+                };
+            }
+            Ok(len)
+        }
+    }
+
+    // Encodes a string as `pw_tokenizer` does: a length byte, whose high bit is
+    // set if the string was truncated, followed by the string's bytes.
+    fn encode_string(buffer: &mut [u8], string: &[u8], truncated: bool) -> Result<usize> {
+        let (header, data) = buffer.split_first_mut().ok_or(Error::OutOfRange)?;
+        let len = string.len().min(data.len());
+        *header = len as u8;
+        if truncated || len < string.len() {
+            *header |= 0x80;
+        }
+        data[..len].copy_from_slice(&string[..len]);
+        Ok(1 + len)
+    }
+
+    struct Slot {
+        // The position the slot may be written at, or one past the position it
+        // may be read at once written.  Stored relative to the slot's index so
+        // that every slot is initialized to 0.
+        sequence: AtomicUsize,
+        message: UnsafeCell<DeferredMessage>,
+    }
+
+    impl Slot {
+        const fn new() -> Self {
+            Self {
+                sequence: AtomicUsize::new(0),
+                message: UnsafeCell::new(DeferredMessage::EMPTY),
+            }
+        }
+    }
+
+    // Advances `position` from `current`, or returns the actual position if
+    // another writer (or reader) advanced it first.
+    #[cfg(target_has_atomic = "ptr")]
+    fn advance(position: &AtomicUsize, current: usize) -> core::result::Result<usize, usize> {
+        position.compare_exchange_weak(
+            current,
+            current.wrapping_add(1),
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        )
+    }
+
+    // Cores without compare-and-swap, such as ARMv6-M, mask interrupts for the
+    // comparison instead.
+    #[cfg(not(target_has_atomic = "ptr"))]
+    fn advance(position: &AtomicUsize, current: usize) -> core::result::Result<usize, usize> {
+        pw_interrupt::free(|_| {
+            let actual = position.load(Ordering::Relaxed);
+            if actual == current {
+                position.store(current.wrapping_add(1), Ordering::Relaxed);
+                Ok(actual)
+            } else {
+                Err(actual)
+            }
+        })
+    }
+
+    // A bounded lock-free queue which may be written from any context,
+    // including interrupt handlers, based on Dmitry Vyukov's bounded MPMC
+    // queue.
+    struct DeferredQueue {
+        slots: [Slot; DEFERRED_QUEUE_ENTRIES],
+        write_position: AtomicUsize,
+        read_position: AtomicUsize,
+    }
+
+    // Safety: A slot's message is only accessed by the writer or reader which
+    // claimed its position, until the slot's sequence is updated.
+    unsafe impl Sync for DeferredQueue {}
+
+    impl DeferredQueue {
+        const fn new() -> Self {
+            Self {
+                slots: [const { Slot::new() }; DEFERRED_QUEUE_ENTRIES],
+                write_position: AtomicUsize::new(0),
+                read_position: AtomicUsize::new(0),
+            }
+        }
+
+        fn sequence(&self, index: usize) -> usize {
+            self.slots[index]
+                .sequence
+                .load(Ordering::Acquire)
+                .wrapping_add(index)
+        }
+
+        fn set_sequence(&self, index: usize, sequence: usize) {
+            self.slots[index]
+                .sequence
+                .store(sequence.wrapping_sub(index), Ordering::Release)
+        }
+
+        // Claims the next position for which `position` has `ready_offset`
+        // and calls `access` with its slot.
+        fn claim<T>(
+            &self,
+            position: &AtomicUsize,
+            ready_offset: usize,
+            access: impl FnOnce(usize, &Slot) -> T,
+        ) -> Option<T> {
+            let mut current = position.load(Ordering::Relaxed);
+            loop {
+                let index = current % DEFERRED_QUEUE_ENTRIES;
+                let lag = self
+                    .sequence(index)
+                    .wrapping_sub(current.wrapping_add(ready_offset))
+                    as isize;
+                if lag < 0 {
+                    // The slot has not been read (or written) since the
+                    // previous pass over the queue.
+                    return None;
+                }
+                if lag > 0 {
+                    // Another writer (or reader) claimed the position.
+                    current = position.load(Ordering::Relaxed);
+                    continue;
+                }
+                match advance(position, current) {
+                    Ok(_) => return Some(access(current, &self.slots[index])),
+                    Err(actual) => current = actual,
+                }
+            }
+        }
+
+        fn push(&self, write: impl FnOnce(&mut DeferredMessage)) -> Result<()> {
+            self.claim(&self.write_position, 0, |position, slot| {
+                // Safety: Readers do not access the slot until its sequence
+                // is updated.
+                write(unsafe { &mut *slot.message.get() });
+                self.set_sequence(position % DEFERRED_QUEUE_ENTRIES, position.wrapping_add(1));
+            })
+            .ok_or(Error::ResourceExhausted)
+        }
+
+        fn pop(&self) -> Option<DeferredMessage> {
+            self.claim(&self.read_position, 1, |position, slot| {
+                // Safety: Writers do not access the slot until its sequence
+                // is updated.
+                let message = unsafe { *slot.message.get() };
+                self.set_sequence(
+                    position % DEFERRED_QUEUE_ENTRIES,
+                    position.wrapping_add(DEFERRED_QUEUE_ENTRIES),
+                );
+                message
+            })
+        }
+    }
+
+    static QUEUE: DeferredQueue = DeferredQueue::new();
+
+    /// Queues a tokenized log message to be encoded and handled by
+    /// [`process_deferred_logs()`].
+    ///
+    /// The token and arguments are copied into a lock-free queue without
+    /// being encoded, so `defer()` is cheap and may be called from interrupt
+    /// handlers.  If timestamps are enabled, the message is timestamped when
+    /// it is queued.
+    ///
+    /// Messages which are dropped because the queue is full or they have more
+    /// than [`MAX_DEFERRED_ARGS`] arguments are reported after the next
+    /// message which is handled.
+    pub fn defer(metadata: Metadata, token: u32, args: &[Argument<'_>]) {
+        let queued = if args.len() <= MAX_DEFERRED_ARGS {
+            QUEUE.push(|message| message.capture(metadata, token, args))
+        } else {
+            Err(Error::ResourceExhausted)
+        };
+        if queued.is_err() {
+            record_drops(metadata, 1);
+        }
+    }
+
+    /// Encodes each queued log message and passes it to
+    /// `pw_log_tokenized_HandleLog()`, in the order the messages were logged.
+    ///
+    /// Call this from a low priority task, which is the only context the log
+    /// handler runs in.  Returns the number of messages processed.
+    pub fn process_deferred_logs() -> usize {
+        let mut count = 0;
+        while let Some(message) = QUEUE.pop() {
+            let encode = |buffer: &mut [u8]| message.encode(buffer);
+            let timestamp = message
+                .timestamp
+                .map(|(timestamp, encoding)| (move || timestamp, encoding));
+            match try_log_at(message.metadata, timestamp, encode) {
+                Ok(()) => report_drops(),
+                Err(_) => record_drops(message.metadata, 1),
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+#[cfg(feature = "deferred")]
+pub use deferred::{defer, process_deferred_logs};
+
+/// Maximum number of flush handlers which can be added with
+/// [`add_flush_handler()`].
+pub const MAX_FLUSH_HANDLERS: usize = 4;
+
+type FlushHandlers = [Option<fn()>; MAX_FLUSH_HANDLERS];
+
+static FLUSH_HANDLERS: Mutex<Cell<FlushHandlers>> =
+    Mutex::new(Cell::new([None; MAX_FLUSH_HANDLERS]));
+
+static FLUSHING: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+/// Adds `handler` to the routines called by [`flush()`].
+///
+/// Code which buffers log messages, such as a drain which sends messages from
+/// a multisink to a UART, adds a handler which sends the buffered messages
+/// immediately.  Handlers may be called from a panic or fault handler with
+/// interrupts disabled, so they must not block or wait for other tasks and
+/// should give up on a transport which is not ready.
+///
+/// # Errors
+/// - [`Error::ResourceExhausted`] - [`MAX_FLUSH_HANDLERS`] handlers have been
+///   added.
+pub fn add_flush_handler(handler: fn()) -> Result<()> {
+    pw_interrupt::free(|cs| {
+        let handlers = FLUSH_HANDLERS.borrow(cs);
+        let mut updated = handlers.get();
+        *updated
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(Error::ResourceExhausted)? = Some(handler);
+        handlers.set(updated);
+        Ok(())
+    })
+}
+
+/// Sends buffered log messages on a best effort basis, such as before the
+/// device resets.
+///
+/// Processes deferred messages if the `deferred` feature is enabled, then
+/// calls each handler added with [`add_flush_handler()`] in the order they
+/// were added.  Calls made while a flush is in progress, such as from a
+/// handler which panics, return immediately.
+pub fn flush() {
+    if pw_interrupt::free(|cs| FLUSHING.borrow(cs).replace(true)) {
+        return;
+    }
+    #[cfg(feature = "deferred")]
+    process_deferred_logs();
+    let handlers = pw_interrupt::free(|cs| FLUSH_HANDLERS.borrow(cs).get());
+    for handler in handlers.into_iter().flatten() {
+        handler();
+    }
+    pw_interrupt::free(|cs| FLUSHING.borrow(cs).set(false));
+}
+
+// Longest panic message which fits in the encoding buffer after the token and
+// the string's length byte.
+const PANIC_MESSAGE_SIZE_BYTES: usize = ENCODING_BUFFER_SIZE_BYTES - 5;
+
+// Formats into a buffer, truncating text which does not fit.
+struct TruncatingWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for TruncatingWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(self.buffer.len() - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// Logs a panic as a fatal message and calls [`flush()`] so that it, and any
+/// buffered messages, are sent before the device resets.
+///
+/// `info` is usually the [`core::panic::PanicInfo`] passed to the panic
+/// handler.  Messages which are too long are truncated.
+///
+/// The `panic_handler` feature provides a panic handler which calls
+/// `log_panic()` and then halts.  Projects with their own panic handler call
+/// `log_panic()` before resetting the device.
+pub fn log_panic(info: impl fmt::Display) {
+    let mut buffer = [0u8; PANIC_MESSAGE_SIZE_BYTES];
+    let mut writer = TruncatingWriter {
+        buffer: &mut buffer,
+        len: 0,
+    };
+    // Truncated messages are logged.
+    let _ = write!(writer, "{info}");
+    let len = writer.len;
+    // Only whole `char`s are written to the buffer.
+    let message = core::str::from_utf8(&buffer[..len]).unwrap_or_default();
+    pw_logf_backend!(pw_log_backend_api::LogLevel::Fatal, "%s", message);
+    flush();
+}
+
+#[cfg(feature = "panic_handler")]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo<'_>) -> ! {
+    log_panic(info);
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Decodes the timestamps of log messages on the host.
+///
+/// # Example
+///
+/// ```
+/// use pw_log_backend::{TimestampDecoder, TimestampEncoding};
+///
+/// let mut decoder = TimestampDecoder::new(TimestampEncoding::Delta);
+/// // Timestamps of 1000 and 1000 + 2.
+/// let (timestamp, message) = decoder.decode(&[0xe8, 0x07, 0xaa]).unwrap();
+/// assert_eq!((timestamp, message), (1000, &[0xaa][..]));
+/// let (timestamp, message) = decoder.decode(&[0x02, 0xbb]).unwrap();
+/// assert_eq!((timestamp, message), (1002, &[0xbb][..]));
+/// ```
+pub struct TimestampDecoder {
+    encoding: TimestampEncoding,
+    last: u64,
+}
+
+impl TimestampDecoder {
+    /// Create a new `TimestampDecoder` for messages with timestamps encoded
+    /// with `encoding`.
+    pub const fn new(encoding: TimestampEncoding) -> Self {
+        Self { encoding, last: 0 }
+    }
+
+    /// Splits a message into its timestamp and its tokenized message.
+    ///
+    /// With [`TimestampEncoding::Delta`], messages must be decoded in the
+    /// order they were logged.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The message does not start with a valid
+    ///   timestamp.
+    pub fn decode<'a>(&mut self, message: &'a [u8]) -> Result<(u64, &'a [u8])> {
+        let (len, value) = u64::varint_decode(message).map_err(|_| Error::DataLoss)?;
+        let timestamp = match self.encoding {
+            TimestampEncoding::Absolute => value,
+            TimestampEncoding::Delta => self.last.wrapping_add(value),
+        };
+        self.last = timestamp;
+        Ok((timestamp, &message[len..]))
+    }
+}
+
+/// Implements the `pw_log` backend API.
+///
+/// Use the `pw_log` macros rather than calling this directly.
+#[cfg(not(feature = "deferred"))]
+#[macro_export]
+macro_rules! pw_logf_backend {
+  ($log_level:expr, $format_string:literal $(, $args:expr)* $(,)?) => {{
+    use $crate::__private as __pw_log_backend_crate;
+    const MODULE_TOKEN: u32 = __pw_log_backend_crate::hash_string(module_path!());
+    const SITE: __pw_log_backend_crate::Metadata =
+        __pw_log_backend_crate::Metadata::new(0, MODULE_TOKEN, 0, line!());
+    __pw_log_backend_crate::log(
+        SITE.with_level($log_level as u32),
+        |buffer: &mut [u8]| if __pw_log_backend_crate::context_is_empty() {
+          __pw_log_backend_crate::tokenize_to_buffer!(
+              buffer, "■msg♦" PW_FMT_CONCAT $format_string, $($args),*)
+        } else {
+          let mut context = [0u8; __pw_log_backend_crate::CONTEXT_BUFFER_SIZE_BYTES];
+          let context = __pw_log_backend_crate::format_fields(&mut context);
+          __pw_log_backend_crate::tokenize_to_buffer!(
+              buffer, "■msg♦" PW_FMT_CONCAT $format_string, $($args,)* ctx = context as &str)
+        },
+    );
+  }};
+}
+
+/// Implements the `pw_log` backend API by deferring messages with [`defer()`].
+///
+/// Use the `pw_log` macros rather than calling this directly.
+#[cfg(feature = "deferred")]
+#[macro_export]
+macro_rules! pw_logf_backend {
+  ($log_level:expr, $format_string:literal $(, $args:expr)* $(,)?) => {{
+    use $crate::__private as __pw_log_backend_crate;
+    const MODULE_TOKEN: u32 = __pw_log_backend_crate::hash_string(module_path!());
+    const SITE: __pw_log_backend_crate::Metadata =
+        __pw_log_backend_crate::Metadata::new(0, MODULE_TOKEN, 0, line!());
+    let (token, args) = __pw_log_backend_crate::tokenize_to_args!(
+        "■msg♦" PW_FMT_CONCAT $format_string, $($args),*);
+    __pw_log_backend_crate::defer(SITE.with_level($log_level as u32), token, &args);
+  }};
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use std::cell::RefCell;
+    use std::sync::{Mutex, MutexGuard};
+    use std::vec::Vec;
+
+    use pw_log_backend_api::LogLevel;
+    use pw_tokenizer_core::hash_string;
+
+    use super::*;
+
+    std::thread_local! {
+        static LOGS: RefCell<Vec<(Metadata, Vec<u8>)>> = const { RefCell::new(Vec::new()) };
+        // When set, the handler records whether it was called in a critical
+        // section instead of recording the message.
+        static CRITICAL_SECTION_CHECK: RefCell<Option<Vec<bool>>> = const { RefCell::new(None) };
+    }
+
+    // Returns whether another thread is kept out of a critical section.
+    fn in_critical_section() -> bool {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || pw_interrupt::free(|_| sender.send(()).unwrap()));
+        receiver
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err()
+    }
+
+    #[no_mangle]
+    extern "C" fn pw_log_tokenized_HandleLog(
+        metadata: u32,
+        encoded_message: *const u8,
+        size_bytes: usize,
+    ) {
+        // Safety: `handle_log()` passes a valid slice.
+        let message = unsafe { core::slice::from_raw_parts(encoded_message, size_bytes) };
+        if CRITICAL_SECTION_CHECK.with(|check| {
+            check
+                .borrow_mut()
+                .as_mut()
+                .map(|check| check.push(in_critical_section()))
+                .is_some()
+        }) {
+            return;
+        }
+        LOGS.with(|logs| {
+            logs.borrow_mut()
+                .push((Metadata::from_value(metadata), message.to_vec()))
+        });
+    }
+
+    fn take_logs() -> Vec<(Metadata, Vec<u8>)> {
+        #[cfg(feature = "deferred")]
+        process_deferred_logs();
+        LOGS.with(|logs| logs.take())
+    }
+
+    // Serializes tests which log since the clock is shared.
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    fn lock() -> MutexGuard<'static, ()> {
+        let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        pw_interrupt::free(|cs| {
+            let mut drops = DROPS.borrow_ref_mut(cs);
+            drops.counters = [None; MAX_DROP_COUNTERS];
+            drops.other = None;
+        });
+        guard
+    }
+
+    // Logs a message which does not fit in the encoding buffer.
+    macro_rules! log_too_long {
+        ($level:expr) => {{
+            // Each argument is encoded as 5 bytes.
+            let x = i32::MIN;
+            pw_logf_backend!(
+                $level,
+                "%d %d %d %d %d %d %d %d %d %d",
+                x,
+                x,
+                x,
+                x,
+                x,
+                x,
+                x,
+                x,
+                x,
+                x
+            )
+        }};
+    }
+
+    fn dropped_message(count: u8) -> Vec<u8> {
+        let mut message = hash_string("■msg♦%u messages dropped")
+            .to_le_bytes()
+            .to_vec();
+        // Small counts are zig-zag encoded in a single byte.
+        message.push(count * 2);
+        message
+    }
+
+    struct TestClock(AtomicU64);
+
+    impl pw_chrono::SystemClock for TestClock {
+        fn ticks_per_second(&self) -> u64 {
+            1000
+        }
+
+        fn now_ticks(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    static CLOCK: TestClock = TestClock(AtomicU64::new(0));
+
+    #[test]
+    fn metadata_packs_fields() {
+        let metadata = Metadata::new(7, 0xffff, 3, 2047);
+        assert_eq!(metadata.value(), 0xffff_ffff);
+
+        let metadata = Metadata::new(1, 0xabcd, 2, 100);
+        assert_eq!(metadata.level(), 1);
+        assert_eq!(metadata.module(), 0xabcd);
+        assert_eq!(metadata.flags(), 2);
+        assert_eq!(metadata.line_number(), 100);
+        assert_eq!(Metadata::from_value(metadata.value()), metadata);
+    }
+
+    #[test]
+    fn metadata_handles_out_of_range_fields() {
+        let metadata = Metadata::new(9, 0x1_0001, 5, 2048);
+        assert_eq!(metadata.level(), 7);
+        assert_eq!(metadata.module(), 1);
+        assert_eq!(metadata.flags(), 1);
+        assert_eq!(metadata.line_number(), 0);
+    }
+
+    #[test]
+    fn metadata_level_is_replaced() {
+        let metadata = Metadata::new(2, 0x1234, 1, 42).with_level(5);
+        assert_eq!(metadata, Metadata::new(5, 0x1234, 1, 42));
+        assert_eq!(metadata.with_level(8).level(), 7);
+    }
+
+    #[test]
+    fn site_key_identifies_call_site() {
+        let _lock = lock();
+        for level in [LogLevel::Info, LogLevel::Error] {
+            pw_logf_backend!(level, "Same site");
+        }
+        pw_logf_backend!(LogLevel::Info, "Other site");
+
+        let logs = take_logs();
+        assert_eq!(logs[0].0.site_key(), logs[1].0.site_key());
+        assert_ne!(logs[0].0.level(), logs[1].0.level());
+        assert_ne!(logs[0].0.site_key(), logs[2].0.site_key());
+        assert_eq!(
+            logs[2].0.site_key(),
+            Metadata::new(0, logs[2].0.module(), 0, logs[2].0.line_number()).value() >> 3
+        );
+    }
+
+    #[test]
+    fn log_is_tokenized_with_metadata() {
+        let _lock = lock();
+        let line = line!() + 1;
+        pw_logf_backend!(LogLevel::Warn, "The answer is %d", 21);
+
+        let logs = take_logs();
+        assert_eq!(logs.len(), 1);
+        let (metadata, message) = &logs[0];
+        assert_eq!(metadata.level(), LogLevel::Warn as u32);
+        assert_eq!(metadata.line_number(), line);
+        assert_eq!(metadata.flags(), 0);
+        assert_eq!(metadata.module(), hash_string(module_path!()) & 0xffff);
+
+        let token = hash_string("■msg♦The answer is %d").to_le_bytes();
+        // 21 is zig-zag encoded as 42.
+        assert_eq!(message[..], [token[0], token[1], token[2], token[3], 42]);
+    }
+
+    #[test]
+    fn fields_are_tokenized_by_key() {
+        let _lock = lock();
+        let sensor_id: u8 = 3;
+        let temp: i16 = -4;
+        pw_logf_backend!(
+            LogLevel::Info,
+            "Reading",
+            sensor_id = sensor_id as i32,
+            temp = temp as i32
+        );
+
+        let logs = take_logs();
+        assert_eq!(logs.len(), 1);
+        let token = hash_string("■msg♦Reading■sensor_id♦%d■temp♦%d").to_le_bytes();
+        // 3 and -4 are zig-zag encoded as 6 and 7.
+        assert_eq!(
+            logs[0].1[..],
+            [token[0], token[1], token[2], token[3], 6, 7]
+        );
+    }
+
+    #[cfg(not(feature = "deferred"))]
+    #[test]
+    fn context_is_tokenized_as_field() {
+        let _lock = lock();
+        let sensor_id: u8 = 3;
+        let _context = pw_log_context::push("conn_id", 3).unwrap();
+        pw_logf_backend!(LogLevel::Info, "Reading", sensor_id = sensor_id as i32);
+
+        let logs = take_logs();
+        let token = hash_string("■msg♦Reading■sensor_id♦%d■ctx♦%s").to_le_bytes();
+        let context = "■conn_id♦3".as_bytes();
+        let mut expected = std::vec![token[0], token[1], token[2], token[3], 6];
+        expected.push(context.len() as u8);
+        expected.extend_from_slice(context);
+        assert_eq!(logs[0].1, expected);
+    }
+
+    #[test]
+    fn long_string_argument_is_truncated() {
+        let _lock = lock();
+        let long = "a".repeat(ENCODING_BUFFER_SIZE_BYTES);
+        pw_logf_backend!(LogLevel::Info, "%s", long.as_str());
+
+        let logs = take_logs();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].1.len(), ENCODING_BUFFER_SIZE_BYTES);
+    }
+
+    #[test]
+    fn log_which_does_not_fit_is_dropped() {
+        let _lock = lock();
+        log_too_long!(LogLevel::Info);
+        assert!(take_logs().is_empty());
+    }
+
+    #[test]
+    fn drops_are_reported_after_next_log() {
+        let _lock = lock();
+        log_too_long!(LogLevel::Info);
+        log_too_long!(LogLevel::Warn);
+        log_too_long!(LogLevel::Info);
+        pw_logf_backend!(LogLevel::Debug, "Tick");
+        pw_logf_backend!(LogLevel::Debug, "Tick");
+
+        let module = hash_string(module_path!()) & 0xffff;
+        let token = hash_string("■msg♦Tick").to_le_bytes().to_vec();
+        let logs = take_logs();
+        assert_eq!(
+            logs,
+            [
+                (
+                    Metadata::new(1, module, 0, logs[0].0.line_number()),
+                    token.clone()
+                ),
+                (Metadata::new(2, module, 0, 0), dropped_message(2)),
+                (Metadata::new(3, module, 0, 0), dropped_message(1)),
+                (Metadata::new(1, module, 0, logs[3].0.line_number()), token),
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_beyond_counter_limit_are_combined() {
+        let _lock = lock();
+        for module in 0..MAX_DROP_COUNTERS as u32 + 2 {
+            let level = if module == 0 { 7 } else { 2 };
+            record_drops(Metadata::new(level, module + 1, 0, 0), 1);
+        }
+        record_drops(Metadata::new(4, MAX_DROP_COUNTERS as u32 + 5, 0, 0), 1);
+        report_drops();
+
+        let logs = take_logs();
+        assert_eq!(logs.len(), MAX_DROP_COUNTERS + 1);
+        assert_eq!(
+            logs[MAX_DROP_COUNTERS],
+            (Metadata::new(4, 0, 0, 0), dropped_message(3))
+        );
+    }
+
+    #[test]
+    fn absolute_timestamp_precedes_token() {
+        let _lock = lock();
+        CLOCK.0.store(1000, Ordering::Relaxed);
+        pw_chrono::set_system_clock(&CLOCK);
+        enable_timestamps(TimestampEncoding::Absolute);
+        pw_logf_backend!(LogLevel::Info, "Tick");
+        pw_logf_backend!(LogLevel::Info, "Tick");
+        disable_timestamps();
+        pw_logf_backend!(LogLevel::Info, "Tick");
+
+        let token = hash_string("■msg♦Tick").to_le_bytes();
+        let logs = take_logs();
+        assert_eq!(logs.len(), 3);
+        // 1000 is varint encoded as 0xe8 0x07.
+        assert_eq!(
+            logs[0].1,
+            [0xe8, 0x07, token[0], token[1], token[2], token[3]]
+        );
+        assert_eq!(logs[1].1, logs[0].1);
+        assert_eq!(logs[2].1, token);
+    }
+
+    #[test]
+    fn delta_timestamps_are_decoded() {
+        let _lock = lock();
+        CLOCK.0.store(1000, Ordering::Relaxed);
+        pw_chrono::set_system_clock(&CLOCK);
+        enable_timestamps(TimestampEncoding::Delta);
+        pw_logf_backend!(LogLevel::Info, "Tick");
+        CLOCK.0.store(1003, Ordering::Relaxed);
+        pw_logf_backend!(LogLevel::Info, "Tick");
+        disable_timestamps();
+
+        let token = hash_string("■msg♦Tick").to_le_bytes();
+        let logs = take_logs();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[1].1, [0x03, token[0], token[1], token[2], token[3]]);
+
+        let mut decoder = TimestampDecoder::new(TimestampEncoding::Delta);
+        assert_eq!(decoder.decode(&logs[0].1), Ok((1000, &token[..])));
+        assert_eq!(decoder.decode(&logs[1].1), Ok((1003, &token[..])));
+    }
+
+    #[test]
+    fn delta_timestamped_logs_are_handled_outside_critical_section() {
+        let _lock = lock();
+        pw_chrono::set_system_clock(&CLOCK);
+        enable_timestamps(TimestampEncoding::Delta);
+        CRITICAL_SECTION_CHECK.with(|check| check.replace(Some(Vec::new())));
+        pw_logf_backend!(LogLevel::Info, "Tick");
+        #[cfg(feature = "deferred")]
+        process_deferred_logs();
+        let checks = CRITICAL_SECTION_CHECK.with(|check| check.take());
+        disable_timestamps();
+
+        assert_eq!(checks, Some(std::vec![false]));
+    }
+
+    #[cfg(feature = "deferred")]
+    #[test]
+    fn deferred_messages_are_handled_when_processed() {
+        let _lock = lock();
+        let mut name = std::string::String::from("rx");
+        pw_logf_backend!(LogLevel::Info, "%s ready: %c", name.as_str(), b'y');
+        // String arguments are copied when the message is logged.
+        name.replace_range(.., "tx");
+        assert!(LOGS.with(|logs| logs.borrow().is_empty()));
+
+        assert_eq!(process_deferred_logs(), 1);
+        let logs = take_logs();
+        let token = hash_string("■msg♦%s ready: %c").to_le_bytes();
+        assert_eq!(
+            logs[0].1,
+            [token[0], token[1], token[2], token[3], 2, b'r', b'x', b'y']
+        );
+        assert_eq!(process_deferred_logs(), 0);
+    }
+
+    #[cfg(feature = "deferred")]
+    #[test]
+    fn deferred_messages_are_dropped_when_queue_is_full() {
+        let _lock = lock();
+        for i in 0..DEFERRED_QUEUE_ENTRIES as u32 + 2 {
+            pw_logf_backend!(LogLevel::Info, "Tick %u", i);
+        }
+        pw_logf_backend!(LogLevel::Info, "Tick %u", 0u32);
+        process_deferred_logs();
+        pw_logf_backend!(LogLevel::Info, "Tick %u", 0u32);
+
+        let mut logs = take_logs();
+        assert_eq!(logs.len(), DEFERRED_QUEUE_ENTRIES + 2);
+        // Drops are reported after the first message is handled.
+        let module = hash_string(module_path!()) & 0xffff;
+        assert_eq!(
+            logs.remove(1),
+            (Metadata::new(2, module, 0, 0), dropped_message(3))
+        );
+        // Messages are handled in the order they were logged.
+        for (i, (_, message)) in logs[..DEFERRED_QUEUE_ENTRIES].iter().enumerate() {
+            assert_eq!(message[4..], [i as u8 * 2]);
+        }
+    }
+
+    static FLUSHES: AtomicU64 = AtomicU64::new(0);
+
+    fn count_flush() {
+        FLUSHES.fetch_add(1, Ordering::Relaxed);
+        // Flushes from flush handlers are ignored.
+        flush();
+    }
+
+    #[test]
+    fn flush_calls_handlers() {
+        let _lock = lock();
+        add_flush_handler(count_flush).unwrap();
+        while add_flush_handler(|| {}).is_ok() {}
+        assert_eq!(add_flush_handler(|| {}), Err(Error::ResourceExhausted));
+
+        flush();
+        assert_eq!(FLUSHES.load(Ordering::Relaxed), 1);
+        log_panic(format_args!("panicked at src/main.rs:3:5:\noops"));
+        assert_eq!(FLUSHES.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn panic_is_logged_as_fatal() {
+        let _lock = lock();
+        log_panic(format_args!(
+            "panicked at src/main.rs:3:5:\n{}",
+            "é".repeat(30)
+        ));
+
+        let logs = take_logs();
+        assert_eq!(logs.len(), 1);
+        let (metadata, message) = &logs[0];
+        assert_eq!(metadata.level(), LogLevel::Fatal as u32);
+        assert_eq!(message[..4], hash_string("■msg♦%s").to_le_bytes());
+        // The message is truncated to whole characters which fit in the
+        // encoding buffer.
+        let text = "panicked at src/main.rs:3:5:\nééééééééé";
+        assert_eq!(message[4], text.len() as u8);
+        assert_eq!(&message[5..], text.as_bytes());
+    }
+
+    #[test]
+    fn decode_rejects_invalid_timestamp() {
+        let mut decoder = TimestampDecoder::new(TimestampEncoding::Absolute);
+        assert_eq!(decoder.decode(&[0x80, 0x80]), Err(Error::DataLoss));
+    }
+}
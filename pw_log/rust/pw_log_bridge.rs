@@ -0,0 +1,204 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_log_bridge` forwards records from the [`log`] crate to the `pw_log`
+//! backend.
+//!
+//! Third party crates commonly log through the [`log`] crate's facade.
+//! Installing [`PwLogger`] as the global logger sends those records through
+//! the same backend and transport as `pw_log` messages.
+//!
+//! `log` records are formatted at runtime so they can not be tokenized at
+//! compile time like `pw_log` messages.  Instead each record is rendered into
+//! a fixed size buffer and passed to the backend as a single `%s` argument.
+//! With the tokenized backend this results in a `■msg♦%s` token followed by
+//! the message text.  Messages which do not fit in the buffer are truncated.
+//!
+//! Records are filtered with `pw_log`'s [`MIN_LOG_LEVEL`](pw_log::MIN_LOG_LEVEL)
+//! and runtime filters.  A record's target, which defaults to the
+//! [`module_path!()`] of the code which logged it, is used as its module:
+//!
+//! ```
+//! use pw_log::LogLevel;
+//!
+//! pw_log_bridge::init().unwrap();
+//! pw_log::set_module_level("chatty_dependency", LogLevel::Warn).unwrap();
+//!
+//! log::info!("The answer is {}", 42);
+//! // Dropped by the module filter.
+//! log::info!(target: "chatty_dependency", "Hello");
+//! ```
+//!
+//! `log`'s trace level has no `pw_log` equivalent and is logged at
+//! [`LogLevel::Debug`].
+#![cfg_attr(not(feature = "std"), no_std)]
+#![deny(missing_docs)]
+
+use core::fmt::Arguments;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use pw_log::LogLevel;
+use pw_status::{Error, Result};
+use pw_stream::{Cursor, FmtWriter};
+
+/// Default size of the buffer each record is rendered into.
+pub const DEFAULT_MESSAGE_SIZE_BYTES: usize = 128;
+
+/// A [`log::Log`] implementation which forwards records to the `pw_log`
+/// backend.
+///
+/// Records are rendered into an `N` byte buffer on the stack.
+pub struct PwLogger<const N: usize = DEFAULT_MESSAGE_SIZE_BYTES>;
+
+impl<const N: usize> PwLogger<N> {
+    /// Create a new `PwLogger`.
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+impl<const N: usize> Default for PwLogger<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Log for PwLogger<N> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level = log_level(metadata.level());
+        level >= pw_log::MIN_LOG_LEVEL
+            && pw_log::is_enabled(pw_log::module_token(metadata.target()), level)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut buffer = [0u8; N];
+        let message = render(&mut buffer, *record.args());
+        pw_log_backend::pw_logf_backend!(log_level(record.level()), "%s", message);
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: PwLogger = PwLogger::new();
+
+/// Installs a [`PwLogger`] as the [`log`] crate's global logger.
+///
+/// The [`log`] crate's maximum level is set to the most verbose level which
+/// passes [`MIN_LOG_LEVEL`](pw_log::MIN_LOG_LEVEL) so that disabled records
+/// are skipped without being formatted.
+///
+/// # Errors
+/// - [`Error::AlreadyExists`] - A global logger has already been installed.
+pub fn init() -> Result<()> {
+    log::set_logger(&LOGGER).map_err(|_| Error::AlreadyExists)?;
+    log::set_max_level(max_level());
+    Ok(())
+}
+
+/// Returns the `pw_log` level that records at `level` are logged at.
+pub const fn log_level(level: Level) -> LogLevel {
+    match level {
+        Level::Error => LogLevel::Error,
+        Level::Warn => LogLevel::Warn,
+        Level::Info => LogLevel::Info,
+        Level::Debug | Level::Trace => LogLevel::Debug,
+    }
+}
+
+// The most verbose `log` level which is not removed by `MIN_LOG_LEVEL`.
+fn max_level() -> LevelFilter {
+    match pw_log::MIN_LOG_LEVEL {
+        LogLevel::Debug => LevelFilter::Trace,
+        LogLevel::Info => LevelFilter::Info,
+        LogLevel::Warn => LevelFilter::Warn,
+        LogLevel::Error => LevelFilter::Error,
+        LogLevel::Critical | LogLevel::Fatal => LevelFilter::Off,
+    }
+}
+
+// Renders `args` into `buffer`, truncating the message at a character
+// boundary if it does not fit.
+fn render<'a>(buffer: &'a mut [u8], args: Arguments<'_>) -> &'a str {
+    if let Some(message) = args.as_str() {
+        return message;
+    }
+
+    let mut writer = FmtWriter::new(Cursor::new(&mut *buffer));
+    // A message which does not fit is truncated rather than dropped.
+    let _ = writer.write_args(args);
+    let len = writer.get_ref().position();
+
+    let message = &buffer[..len];
+    match core::str::from_utf8(message) {
+        Ok(message) => message,
+        Err(e) => {
+            // Safety: `valid_up_to()` is the length of the valid UTF-8 prefix.
+            unsafe { core::str::from_utf8_unchecked(&message[..e.valid_up_to()]) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_map_to_pw_log_levels() {
+        assert_eq!(log_level(Level::Error), LogLevel::Error);
+        assert_eq!(log_level(Level::Warn), LogLevel::Warn);
+        assert_eq!(log_level(Level::Info), LogLevel::Info);
+        assert_eq!(log_level(Level::Debug), LogLevel::Debug);
+        assert_eq!(log_level(Level::Trace), LogLevel::Debug);
+    }
+
+    #[test]
+    fn render_formats_arguments() {
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            render(&mut buffer, format_args!("answer={}", 42)),
+            "answer=42"
+        );
+        assert_eq!(render(&mut buffer, format_args!("static")), "static");
+    }
+
+    #[test]
+    fn render_truncates_at_character_boundary() {
+        let mut buffer = [0u8; 8];
+        let message = "long message";
+        assert_eq!(render(&mut buffer, format_args!("{message}")), "long mes");
+        // 'é' is two bytes and does not fit after "abcdefg".
+        let message = "abcdefgé";
+        assert_eq!(render(&mut buffer, format_args!("{message}")), "abcdefg");
+    }
+
+    #[test]
+    fn enabled_applies_module_filters() {
+        let logger = PwLogger::<16>::new();
+        let metadata = |target| {
+            Metadata::builder()
+                .level(Level::Info)
+                .target(target)
+                .build()
+        };
+        assert!(logger.enabled(&metadata("pw_log_bridge::tests::enabled")));
+
+        pw_log::set_module_level("pw_log_bridge::tests::filtered", LogLevel::Error).unwrap();
+        assert!(!logger.enabled(&metadata("pw_log_bridge::tests::filtered")));
+        pw_log::clear_module_level(pw_log::module_token("pw_log_bridge::tests::filtered"));
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Key-value context which is attached to every log message logged within a
+//! scope.
+//!
+//! Context is pushed with [`push()`] and removed when the returned
+//! [`ContextGuard`] is dropped.  Backends which support context, such as the
+//! `std` and tokenized backends, append each field to messages with
+//! `pw_log_tokenized`'s `■key♦value` field syntax, so host tools decode them
+//! like the key-value fields of a log statement:
+//!
+//! ```
+//! fn handle_request(connection_id: u32, request_id: u32) {
+//!     let _connection = pw_log_context::push("conn_id", connection_id).unwrap();
+//!     let _request = pw_log_context::push("req_id", request_id).unwrap();
+//!
+//!     // Logged as "Handling request■conn_id♦3■req_id♦17".
+//!     // pw_log::info!("Handling request");
+//! #   let mut buffer = [0u8; 32];
+//! #   assert_eq!(pw_log_context::format_fields(&mut buffer), "■conn_id♦3■req_id♦17");
+//! }
+//! # handle_request(3, 17);
+//! ```
+//!
+//! With the `std` feature, each thread has its own context.  Otherwise the
+//! context is shared by all code which logs, so it should only be pushed by
+//! a single thread or task, or by a scheduler as it switches tasks.
+#![cfg_attr(not(feature = "std"), no_std)]
+#![deny(missing_docs)]
+
+use core::cell::RefCell;
+use core::fmt::{self, Write};
+use core::marker::PhantomData;
+
+use pw_status::{Error, Result};
+
+/// Maximum number of context fields which can be pushed at once.
+pub const MAX_CONTEXT_FIELDS: usize = 4;
+
+#[derive(Clone, Copy)]
+struct Field {
+    key: &'static str,
+    value: u32,
+}
+
+struct Context {
+    fields: [Option<Field>; MAX_CONTEXT_FIELDS],
+    len: usize,
+}
+
+impl Context {
+    const fn new() -> Self {
+        Self {
+            fields: [None; MAX_CONTEXT_FIELDS],
+            len: 0,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn with_context<T>(f: impl FnOnce(&mut Context) -> T) -> T {
+    std::thread_local! {
+        static CONTEXT: RefCell<Context> = const { RefCell::new(Context::new()) };
+    }
+    CONTEXT.with(|context| f(&mut context.borrow_mut()))
+}
+
+#[cfg(not(feature = "std"))]
+fn with_context<T>(f: impl FnOnce(&mut Context) -> T) -> T {
+    static CONTEXT: critical_section::Mutex<RefCell<Context>> =
+        critical_section::Mutex::new(RefCell::new(Context::new()));
+    critical_section::with(|cs| f(&mut CONTEXT.borrow_ref_mut(cs)))
+}
+
+/// Removes a context field, and any pushed after it, when dropped.
+#[must_use = "the context field is removed when the guard is dropped"]
+pub struct ContextGuard {
+    depth: usize,
+    // Guards may not be sent to other threads, which have their own context.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        with_context(|context| {
+            for field in &mut context.fields[self.depth..] {
+                *field = None;
+            }
+            context.len = context.len.min(self.depth);
+        })
+    }
+}
+
+/// Attaches `key` and `value` to messages logged until the returned guard is
+/// dropped.
+///
+/// Fields are attached in the order they were pushed.  `key` should be a
+/// short identifier, such as `"conn_id"`.
+///
+/// # Errors
+/// - [`Error::ResourceExhausted`] - [`MAX_CONTEXT_FIELDS`] fields are already
+///   pushed.
+pub fn push(key: &'static str, value: u32) -> Result<ContextGuard> {
+    with_context(|context| {
+        let depth = context.len;
+        let slot = context
+            .fields
+            .get_mut(depth)
+            .ok_or(Error::ResourceExhausted)?;
+        *slot = Some(Field { key, value });
+        context.len += 1;
+        Ok(ContextGuard {
+            depth,
+            _not_send: PhantomData,
+        })
+    })
+}
+
+/// Returns `true` if no context fields are pushed.
+pub fn is_empty() -> bool {
+    with_context(|context| context.len == 0)
+}
+
+/// Calls `f` with the key and value of each context field, in the order they
+/// were pushed.
+///
+/// `f` must not push context or log.
+pub fn for_each(mut f: impl FnMut(&'static str, u32)) {
+    with_context(|context| {
+        for field in context.fields[..context.len].iter().flatten() {
+            f(field.key, field.value);
+        }
+    })
+}
+
+/// Writes each context field as `■key♦value` to `writer`.
+pub fn write_fields(writer: &mut impl Write) -> fmt::Result {
+    let mut result = Ok(());
+    for_each(|key, value| {
+        if result.is_ok() {
+            result = write!(writer, "■{key}♦{value}");
+        }
+    });
+    result
+}
+
+// Writes whole fields to a buffer, stopping at the first one which does not
+// fit.
+struct FieldWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for FieldWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buffer
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Formats each context field as `■key♦value` into `buffer` and returns the
+/// formatted fields.
+///
+/// Fields which do not fit in `buffer` are omitted.
+pub fn format_fields(buffer: &mut [u8]) -> &str {
+    let mut writer = FieldWriter { buffer, len: 0 };
+    for_each(|key, value| {
+        let start = writer.len;
+        if write!(writer, "■{key}♦{value}").is_err() {
+            writer.len = start;
+        }
+    });
+    let len = writer.len;
+    // Only whole `str`s are written to the buffer.
+    core::str::from_utf8(&buffer[..len]).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> std::vec::Vec<(&'static str, u32)> {
+        let mut fields = std::vec::Vec::new();
+        for_each(|key, value| fields.push((key, value)));
+        fields
+    }
+
+    #[test]
+    fn context_is_removed_when_guard_is_dropped() {
+        assert!(is_empty());
+        let outer = push("conn_id", 3).unwrap();
+        {
+            let _inner = push("req_id", 17).unwrap();
+            assert_eq!(fields(), [("conn_id", 3), ("req_id", 17)]);
+        }
+        assert_eq!(fields(), [("conn_id", 3)]);
+        drop(outer);
+        assert!(is_empty());
+    }
+
+    #[test]
+    fn dropping_outer_guard_removes_inner_fields() {
+        let outer = push("a", 1).unwrap();
+        let inner = push("b", 2).unwrap();
+        drop(outer);
+        assert!(is_empty());
+        drop(inner);
+        let _next = push("c", 3).unwrap();
+        assert_eq!(fields(), [("c", 3)]);
+    }
+
+    #[test]
+    fn push_fails_when_full() {
+        let _guards: std::vec::Vec<_> = (0..MAX_CONTEXT_FIELDS as u32)
+            .map(|i| push("key", i).unwrap())
+            .collect();
+        assert!(matches!(push("key", 0), Err(Error::ResourceExhausted)));
+    }
+
+    #[test]
+    fn fields_are_formatted() {
+        let _a = push("conn_id", 3).unwrap();
+        let _b = push("req_id", 17).unwrap();
+        let mut buffer = [0u8; 32];
+        assert_eq!(format_fields(&mut buffer), "■conn_id♦3■req_id♦17");
+
+        // "■conn_id♦3" uses 14 bytes.
+        let mut buffer = [0u8; 20];
+        assert_eq!(format_fields(&mut buffer), "■conn_id♦3");
+
+        let mut line = std::string::String::new();
+        write_fields(&mut line).unwrap();
+        assert_eq!(line, "■conn_id♦3■req_id♦17");
+    }
+
+    #[test]
+    fn threads_have_their_own_context() {
+        let _a = push("conn_id", 3).unwrap();
+        std::thread::spawn(|| assert!(is_empty())).join().unwrap();
+    }
+}
@@ -0,0 +1,737 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_log_rpc` streams buffered logs to a client with the `pw.log.Logs` RPC
+//! service.
+//!
+//! This is the Rust counterpart of the C++ `pw_log_rpc` module and is
+//! compatible with the standard host tooling such as `pw_console`.  Log
+//! entries are encoded as `pw.log.LogEntry` protos with [`LogEntry`] and
+//! pushed to a [`pw_multisink::MultiSink`].  Each client reads them through
+//! an [`RpcLogDrain`], which packs them into `pw.log.LogEntries` responses.
+//!
+//! When the `pw.log.Logs.Listen` method ([`SERVICE_ID`], [`LISTEN_METHOD_ID`])
+//! is called, open the drain for the call's channel with its
+//! [`ServerWriter`].  Then periodically call [`RpcLogDrain::flush()`] to send
+//! the available entries:
+//!
+//! ```
+//! use pw_log_backend_api::LogLevel;
+//! use pw_log_rpc::{ErrorHandling, LogEntry, RpcLogDrain, ServerWriter};
+//! use pw_multisink::MultiSink;
+//! use pw_status::Result;
+//!
+//! struct Stream;
+//!
+//! impl ServerWriter for Stream {
+//!     fn write(&mut self, response: &[u8]) -> Result<()> {
+//!         // Send `response` as a server stream packet.
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let sink = MultiSink::<256>::new();
+//! let mut entry_buffer = [0u8; 64];
+//! let mut drain = RpcLogDrain::new(
+//!     sink.attach_drain(),
+//!     &mut entry_buffer,
+//!     ErrorHandling::IgnoreWriterErrors,
+//! );
+//!
+//! let mut buffer = [0u8; 64];
+//! let len = LogEntry::new(LogLevel::Info, b"Hello").encode(&mut buffer).unwrap();
+//! sink.push_entry(&buffer[..len]).unwrap();
+//!
+//! // Called from the `Listen` handler.
+//! drain.open(Stream).unwrap();
+//!
+//! let mut encoding_buffer = [0u8; 128];
+//! drain.flush(&mut encoding_buffer).unwrap();
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_log_backend_api::LogLevel;
+use pw_multisink::Drain;
+use pw_protobuf::{size_of_delimited_field, MemoryEncoder};
+use pw_status::{Error, Result};
+use pw_tokenizer_core::hash_string;
+
+/// ID of the `pw.log.Logs` service.
+pub const SERVICE_ID: u32 = hash_string("pw.log.Logs");
+
+/// ID of the `pw.log.Logs.Listen` method.
+pub const LISTEN_METHOD_ID: u32 = hash_string("Listen");
+
+/// Message of the entry sent when entries were not added to the sink.
+pub const INGRESS_ERROR_MESSAGE: &str = "Ingress error";
+/// Message of the entry sent when entries were overwritten before being sent.
+pub const SLOW_DRAIN_MESSAGE: &str = "Slow drain";
+/// Message of the entry sent when entries did not fit in a response.
+pub const SMALL_OUTBOUND_BUFFER_MESSAGE: &str = "Outbound log buffer too small";
+/// Message of the entry sent when entries did not fit in the entry buffer.
+pub const SMALL_STACK_BUFFER_MESSAGE: &str = "Stack log buffer too small";
+/// Message of the entry sent when responses failed to be written.
+pub const WRITER_ERROR_MESSAGE: &str = "Writer error";
+
+// Field numbers of `pw.log.LogEntry`.
+const LOG_ENTRY_MESSAGE: u32 = 1;
+const LOG_ENTRY_LINE_LEVEL: u32 = 2;
+const LOG_ENTRY_FLAGS: u32 = 3;
+const LOG_ENTRY_TIMESTAMP: u32 = 4;
+const LOG_ENTRY_TIME_SINCE_LAST_ENTRY: u32 = 5;
+const LOG_ENTRY_DROPPED: u32 = 6;
+const LOG_ENTRY_MODULE: u32 = 7;
+const LOG_ENTRY_FILE: u32 = 8;
+const LOG_ENTRY_THREAD: u32 = 9;
+
+// Field numbers of `pw.log.LogEntries`.
+const LOG_ENTRIES_ENTRIES: u32 = 1;
+const LOG_ENTRIES_FIRST_ENTRY_SEQUENCE_ID: u32 = 2;
+
+// Space reserved in each response for `first_entry_sequence_id`.
+const SEQUENCE_ID_FIELD_SIZE_BYTES: usize = 6;
+
+// Large enough for any of the drop messages.
+const DROP_MESSAGE_SIZE_BYTES: usize = 48;
+
+// Number of bits used by the level in `line_level`.
+const LEVEL_BITS: u32 = 3;
+
+/// Packs a line number and log level into a `pw.log.LogEntry` `line_level`
+/// field.
+///
+/// The level is limited to 3 bits and the line number to 29 bits.  Larger
+/// values are truncated.
+pub const fn pack_line_level(line: u32, level: u32) -> u32 {
+    let level_mask = (1 << LEVEL_BITS) - 1;
+    (level & level_mask) | (line << LEVEL_BITS)
+}
+
+/// The time of a [`LogEntry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timestamp {
+    /// Time since an arbitrary epoch, such as boot, in ticks.
+    Absolute(i64),
+    /// Time since the previous entry in ticks.
+    SinceLastEntry(i64),
+}
+
+/// A `pw.log.LogEntry` to be encoded.
+///
+/// Empty and zero fields are omitted from the encoded entry.  Tokenized
+/// fields are passed as their encoded bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogEntry<'a> {
+    /// The log message, either plain text or tokenized.
+    pub message: &'a [u8],
+    /// Log level of the entry.
+    pub level: LogLevel,
+    /// Line number of the log statement, or 0 if unknown.
+    pub line: u32,
+    /// Project specific flags.
+    pub flags: u32,
+    /// Time the entry was logged.
+    pub timestamp: Option<Timestamp>,
+    /// Number of entries dropped before this one.
+    pub dropped: u32,
+    /// Module name or token.
+    pub module: &'a [u8],
+    /// Source file name or token.
+    pub file: &'a [u8],
+    /// Thread name or token.
+    pub thread: &'a [u8],
+}
+
+impl<'a> LogEntry<'a> {
+    /// Creates an entry with the given level and message and no other fields.
+    pub const fn new(level: LogLevel, message: &'a [u8]) -> Self {
+        Self {
+            message,
+            level,
+            line: 0,
+            flags: 0,
+            timestamp: None,
+            dropped: 0,
+            module: &[],
+            file: &[],
+            thread: &[],
+        }
+    }
+
+    /// Encodes the entry as a `pw.log.LogEntry` proto into `buffer`, returning
+    /// the encoded length.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - `buffer` is too small.
+    pub fn encode(&self, buffer: &mut [u8]) -> Result<usize> {
+        let mut encoder = MemoryEncoder::new(buffer);
+        if !self.message.is_empty() {
+            encoder.write_bytes(LOG_ENTRY_MESSAGE, self.message)?;
+        }
+        let line_level = pack_line_level(self.line, self.level as u32);
+        encoder.write_uint32(LOG_ENTRY_LINE_LEVEL, line_level)?;
+        if self.flags != 0 {
+            encoder.write_uint32(LOG_ENTRY_FLAGS, self.flags)?;
+        }
+        match self.timestamp {
+            Some(Timestamp::Absolute(ticks)) => encoder.write_int64(LOG_ENTRY_TIMESTAMP, ticks)?,
+            Some(Timestamp::SinceLastEntry(ticks)) => {
+                encoder.write_int64(LOG_ENTRY_TIME_SINCE_LAST_ENTRY, ticks)?
+            }
+            None => (),
+        }
+        if self.dropped != 0 {
+            encoder.write_uint32(LOG_ENTRY_DROPPED, self.dropped)?;
+        }
+        for (field, value) in [
+            (LOG_ENTRY_MODULE, self.module),
+            (LOG_ENTRY_FILE, self.file),
+            (LOG_ENTRY_THREAD, self.thread),
+        ] {
+            if !value.is_empty() {
+                encoder.write_bytes(field, value)?;
+            }
+        }
+        Ok(encoder.len())
+    }
+}
+
+/// Encodes a tokenized log message as a `pw.log.LogEntry` proto into
+/// `buffer`, returning the encoded length.
+///
+/// This matches `pw::log::EncodeTokenizedLog()` in C++.  `metadata` is the
+/// packed metadata passed to `pw_log_tokenized_HandleLog()` with its default
+/// layout, as produced by the `pw_log` tokenized backend's `Metadata`.  A
+/// non-zero module token is encoded as its 4 little endian bytes.
+///
+/// ```
+/// use pw_log_rpc::encode_tokenized_log;
+///
+/// // Info level message from line 12 of module 0x1234.
+/// let metadata = 0x1234 << 16 | 12 << 3 | 2;
+/// let mut buffer = [0u8; 32];
+/// let len = encode_tokenized_log(metadata, &[1, 2, 3, 4], 1000, &mut buffer).unwrap();
+/// assert_eq!(len, 17);
+/// ```
+///
+/// # Errors
+/// - [`Error::ResourceExhausted`] - `buffer` is too small.
+pub fn encode_tokenized_log(
+    metadata: u32,
+    tokenized_message: &[u8],
+    ticks_since_epoch: i64,
+    buffer: &mut [u8],
+) -> Result<usize> {
+    // Default `pw_log_tokenized` metadata layout.
+    let level = metadata & 0x7;
+    let line = (metadata >> 3) & 0x7ff;
+    let flags = (metadata >> 14) & 0x3;
+    let module = metadata >> 16;
+
+    let mut encoder = MemoryEncoder::new(buffer);
+    encoder.write_bytes(LOG_ENTRY_MESSAGE, tokenized_message)?;
+    encoder.write_uint32(LOG_ENTRY_LINE_LEVEL, pack_line_level(line, level))?;
+    if flags != 0 {
+        encoder.write_uint32(LOG_ENTRY_FLAGS, flags)?;
+    }
+    encoder.write_int64(LOG_ENTRY_TIMESTAMP, ticks_since_epoch)?;
+    if module != 0 {
+        encoder.write_bytes(LOG_ENTRY_MODULE, &module.to_le_bytes())?;
+    }
+    Ok(encoder.len())
+}
+
+/// The writer of a `pw.log.Logs.Listen` server stream.
+pub trait ServerWriter {
+    /// Sends an encoded `pw.log.LogEntries` response to the client.
+    fn write(&mut self, response: &[u8]) -> Result<()>;
+}
+
+/// How an [`RpcLogDrain`] handles errors from its [`ServerWriter`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorHandling {
+    /// Entries in failed responses are lost without being counted as drops.
+    IgnoreWriterErrors,
+    /// The stream is closed and the entries in the failed response are
+    /// reported as drops when the stream is reopened.
+    CloseStreamOnWriterError,
+}
+
+// Why entries were dropped, in the order drop messages are sent.
+#[derive(Default)]
+struct DropCounts {
+    slow_drain: u32,
+    ingress: u32,
+    small_stack_buffer: u32,
+    small_outbound_buffer: u32,
+    writer: u32,
+}
+
+impl DropCounts {
+    fn counts(&mut self) -> [(&'static str, &mut u32); 5] {
+        [
+            (SLOW_DRAIN_MESSAGE, &mut self.slow_drain),
+            (INGRESS_ERROR_MESSAGE, &mut self.ingress),
+            (SMALL_STACK_BUFFER_MESSAGE, &mut self.small_stack_buffer),
+            (
+                SMALL_OUTBOUND_BUFFER_MESSAGE,
+                &mut self.small_outbound_buffer,
+            ),
+            (WRITER_ERROR_MESSAGE, &mut self.writer),
+        ]
+    }
+
+    // Adds an entry reporting each non-zero drop count to `encoder`, resetting
+    // the counts which fit.
+    fn encode_drop_messages(&mut self, encoder: &mut MemoryEncoder) {
+        for (reason, count) in self.counts() {
+            if *count == 0 {
+                continue;
+            }
+            let mut buffer = [0u8; DROP_MESSAGE_SIZE_BYTES];
+            let Ok(len) = encode_drop_message(reason, *count, &mut buffer) else {
+                continue;
+            };
+            if size_of_delimited_field(LOG_ENTRIES_ENTRIES, len) <= encoder.remaining() {
+                // Cannot fail since the field fits.
+                let _ = encoder.write_bytes(LOG_ENTRIES_ENTRIES, &buffer[..len]);
+                *count = 0;
+            }
+        }
+    }
+}
+
+// Drop messages only contain the reason and count.
+fn encode_drop_message(reason: &str, count: u32, buffer: &mut [u8]) -> Result<usize> {
+    let mut encoder = MemoryEncoder::new(buffer);
+    encoder.write_bytes(LOG_ENTRY_MESSAGE, reason.as_bytes())?;
+    encoder.write_uint32(LOG_ENTRY_DROPPED, count)?;
+    Ok(encoder.len())
+}
+
+/// Sends the entries of a [`pw_multisink::Drain`] to a `pw.log.Logs.Listen`
+/// stream.
+///
+/// Entries are packed into `pw.log.LogEntries` responses, each tagged with
+/// the sequence ID of its first entry.  Entries which are dropped are reported
+/// to the client with entries containing the drop count and reason.
+pub struct RpcLogDrain<'a, W: ServerWriter, const N: usize> {
+    drain: Drain<'a, N>,
+    entry_buffer: &'a mut [u8],
+    error_handling: ErrorHandling,
+    writer: Option<W>,
+    sequence_id: u32,
+    drops: DropCounts,
+}
+
+impl<'a, W: ServerWriter, const N: usize> RpcLogDrain<'a, W, N> {
+    /// Creates a closed drain which reads entries from `drain`.
+    ///
+    /// Entries are copied into `entry_buffer` before being sent, so it must be
+    /// large enough for the largest entry in the sink.
+    pub fn new(
+        drain: Drain<'a, N>,
+        entry_buffer: &'a mut [u8],
+        error_handling: ErrorHandling,
+    ) -> Self {
+        Self {
+            drain,
+            entry_buffer,
+            error_handling,
+            writer: None,
+            sequence_id: 0,
+            drops: DropCounts::default(),
+        }
+    }
+
+    /// Starts sending entries to `writer`.
+    ///
+    /// # Errors
+    /// - [`Error::AlreadyExists`] - The drain already has an open stream.
+    pub fn open(&mut self, writer: W) -> Result<()> {
+        if self.writer.is_some() {
+            return Err(Error::AlreadyExists);
+        }
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    /// Stops sending entries, returning the writer of the open stream, if any.
+    ///
+    /// Entries continue to be buffered in the sink while the drain is closed.
+    pub fn close(&mut self) -> Option<W> {
+        self.writer.take()
+    }
+
+    /// Returns `true` if the drain has an open stream.
+    pub fn is_open(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Sends all available entries to the open stream, using
+    /// `encoding_buffer` to encode each response.
+    ///
+    /// # Errors
+    /// - [`Error::Unavailable`] - The drain does not have an open stream.
+    /// - [`Error::Aborted`] - Writing a response failed and the stream was
+    ///   closed.  Only returned with
+    ///   [`ErrorHandling::CloseStreamOnWriterError`].
+    pub fn flush(&mut self, encoding_buffer: &mut [u8]) -> Result<()> {
+        loop {
+            let Some(writer) = self.writer.as_mut() else {
+                return Err(Error::Unavailable);
+            };
+
+            let limit = encoding_buffer
+                .len()
+                .saturating_sub(SEQUENCE_ID_FIELD_SIZE_BYTES);
+            let mut encoder = MemoryEncoder::new(&mut encoding_buffer[..limit]);
+            let (packed, caught_up) = Self::encode_response(
+                &mut self.drain,
+                self.entry_buffer,
+                &mut self.drops,
+                &mut encoder,
+            );
+            let len = encoder.len();
+
+            // Avoid sending empty responses.
+            if len > 0 {
+                let mut encoder = MemoryEncoder::new(&mut encoding_buffer[len..]);
+                encoder.write_uint32(LOG_ENTRIES_FIRST_ENTRY_SEQUENCE_ID, self.sequence_id)?;
+                let len = len + encoder.len();
+                self.sequence_id = self.sequence_id.wrapping_add(packed);
+
+                if writer.write(&encoding_buffer[..len]).is_err()
+                    && self.error_handling == ErrorHandling::CloseStreamOnWriterError
+                {
+                    self.drops.writer = self.drops.writer.wrapping_add(packed);
+                    self.writer = None;
+                    return Err(Error::Aborted);
+                }
+            }
+
+            if caught_up {
+                return Ok(());
+            }
+        }
+    }
+
+    // Packs entries and drop messages into `encoder` until it is full or there
+    // are no more entries.  Returns the number of entries packed and whether
+    // the drain is caught up.
+    fn encode_response(
+        drain: &mut Drain<'a, N>,
+        entry_buffer: &mut [u8],
+        drops: &mut DropCounts,
+        encoder: &mut MemoryEncoder,
+    ) -> (u32, bool) {
+        let capacity = encoder.remaining();
+        let mut packed = 0;
+        loop {
+            let (result, drop_counts) = drain.peek_entry(entry_buffer);
+            drops.ingress = drops.ingress.wrapping_add(drop_counts.ingress);
+            drops.slow_drain = drops.slow_drain.wrapping_add(drop_counts.drain);
+
+            let entry = match result {
+                Ok(entry) => entry,
+                Err(Error::ResourceExhausted) => {
+                    // The multisink counts the skipped entry as a drain drop.
+                    drops.slow_drain = drops.slow_drain.wrapping_sub(1);
+                    drops.small_stack_buffer = drops.small_stack_buffer.wrapping_add(1);
+                    continue;
+                }
+                Err(_) => {
+                    drops.encode_drop_messages(encoder);
+                    return (packed, true);
+                }
+            };
+
+            let entry_size = size_of_delimited_field(LOG_ENTRIES_ENTRIES, entry.entry().len());
+            if entry_size > capacity {
+                drops.small_outbound_buffer = drops.small_outbound_buffer.wrapping_add(1);
+                drain.pop_peeked(&entry);
+                continue;
+            }
+
+            drops.encode_drop_messages(encoder);
+            if entry_size > encoder.remaining() {
+                return (packed, false);
+            }
+
+            // Cannot fail since the field fits.
+            let _ = encoder.write_bytes(LOG_ENTRIES_ENTRIES, entry.entry());
+            drain.pop_peeked(&entry);
+            packed += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use pw_multisink::MultiSink;
+    use pw_varint::VarintDecode;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct TestWriter {
+        responses: Vec<Vec<u8>>,
+        fail: bool,
+    }
+
+    impl ServerWriter for &mut TestWriter {
+        fn write(&mut self, response: &[u8]) -> Result<()> {
+            if self.fail {
+                return Err(Error::Unknown);
+            }
+            self.responses.push(response.to_vec());
+            Ok(())
+        }
+    }
+
+    enum Value<'a> {
+        Varint(u64),
+        Bytes(&'a [u8]),
+    }
+
+    // Decodes the fields of a protobuf message.
+    fn decode(mut data: &[u8]) -> Vec<(u32, Value<'_>)> {
+        let mut fields = Vec::new();
+        while !data.is_empty() {
+            let (len, key) = u32::varint_decode(data).unwrap();
+            data = &data[len..];
+            let (len, value) = u64::varint_decode(data).unwrap();
+            data = &data[len..];
+            let value = match key & 0x7 {
+                0 => Value::Varint(value),
+                2 => {
+                    let (bytes, rest) = data.split_at(value as usize);
+                    data = rest;
+                    Value::Bytes(bytes)
+                }
+                wire_type => panic!("unexpected wire type {wire_type}"),
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    // Returns the entries and first sequence ID of a `LogEntries` message.
+    fn decode_log_entries(response: &[u8]) -> (Vec<&[u8]>, u64) {
+        let mut entries = Vec::new();
+        let mut sequence_id = None;
+        for (field, value) in decode(response) {
+            match (field, value) {
+                (LOG_ENTRIES_ENTRIES, Value::Bytes(entry)) => entries.push(entry),
+                (LOG_ENTRIES_FIRST_ENTRY_SEQUENCE_ID, Value::Varint(id)) => sequence_id = Some(id),
+                _ => panic!("unexpected field {field}"),
+            }
+        }
+        (entries, sequence_id.unwrap())
+    }
+
+    // Returns the message and drop count of a `LogEntry` message.
+    fn decode_log_entry(entry: &[u8]) -> (&[u8], u64) {
+        let mut message: &[u8] = &[];
+        let mut dropped = 0;
+        for (field, value) in decode(entry) {
+            match (field, value) {
+                (LOG_ENTRY_MESSAGE, Value::Bytes(bytes)) => message = bytes,
+                (LOG_ENTRY_DROPPED, Value::Varint(count)) => dropped = count,
+                _ => (),
+            }
+        }
+        (message, dropped)
+    }
+
+    fn push_log<const N: usize>(sink: &MultiSink<N>, message: &[u8]) {
+        let mut buffer = [0u8; 64];
+        let len = LogEntry::new(LogLevel::Info, message)
+            .encode(&mut buffer)
+            .unwrap();
+        sink.push_entry(&buffer[..len]).unwrap();
+    }
+
+    #[test]
+    fn service_ids_match_pw_rpc() {
+        assert_eq!(SERVICE_ID, 0x0fcd342b);
+        assert_eq!(LISTEN_METHOD_ID, 0xa7a01a2d);
+    }
+
+    #[test]
+    fn log_entry_is_encoded() {
+        let mut entry = LogEntry::new(LogLevel::Warn, b"Hi");
+        entry.line = 2;
+        entry.timestamp = Some(Timestamp::Absolute(-1));
+        entry.module = b"M";
+
+        let mut buffer = [0u8; 32];
+        let len = entry.encode(&mut buffer).unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            0x0a, 2, b'H', b'i',
+            0x10, 0x13,
+            0x20, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+            0x3a, 1, b'M',
+        ];
+        assert_eq!(&buffer[..len], &expected);
+        assert_eq!(
+            entry.encode(&mut buffer[..len - 1]),
+            Err(Error::ResourceExhausted)
+        );
+    }
+
+    #[test]
+    fn tokenized_log_is_encoded() {
+        let metadata = 0x1234 << 16 | 1 << 14 | 12 << 3 | LogLevel::Warn as u32;
+        let mut buffer = [0u8; 32];
+        let len = encode_tokenized_log(metadata, &[0xaa, 0xbb], -2, &mut buffer).unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            0x0a, 2, 0xaa, 0xbb,
+            0x10, 0x63,
+            0x18, 0x01,
+            0x20, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+            0x3a, 4, 0x34, 0x12, 0x00, 0x00,
+        ];
+        assert_eq!(&buffer[..len], &expected);
+        assert_eq!(
+            encode_tokenized_log(metadata, &[0xaa], 0, &mut buffer[..8]),
+            Err(Error::ResourceExhausted)
+        );
+    }
+
+    #[test]
+    fn flush_without_stream_is_unavailable() {
+        let sink = MultiSink::<64>::new();
+        let mut entry_buffer = [0u8; 32];
+        let mut drain: RpcLogDrain<&mut TestWriter, 64> = RpcLogDrain::new(
+            sink.attach_drain(),
+            &mut entry_buffer,
+            ErrorHandling::IgnoreWriterErrors,
+        );
+        assert_eq!(drain.flush(&mut [0u8; 64]), Err(Error::Unavailable));
+    }
+
+    #[test]
+    fn entries_are_packed_into_responses() {
+        let sink = MultiSink::<256>::new();
+        let mut entry_buffer = [0u8; 32];
+        let mut writer = TestWriter::default();
+        let mut other_writer = TestWriter::default();
+        let mut drain = RpcLogDrain::new(
+            sink.attach_drain(),
+            &mut entry_buffer,
+            ErrorHandling::IgnoreWriterErrors,
+        );
+        drain.open(&mut writer).unwrap();
+        assert_eq!(drain.open(&mut other_writer), Err(Error::AlreadyExists));
+
+        for message in [&b"one"[..], b"two", b"three"] {
+            push_log(&sink, message);
+        }
+        // Room for two entries per response.
+        drain.flush(&mut [0u8; 28]).unwrap();
+        push_log(&sink, b"four");
+        drain.flush(&mut [0u8; 28]).unwrap();
+
+        let responses: Vec<_> = writer
+            .responses
+            .iter()
+            .map(|r| decode_log_entries(r))
+            .collect();
+        let messages = |entries: &Vec<&[u8]>| -> Vec<Vec<u8>> {
+            entries
+                .iter()
+                .map(|e| decode_log_entry(e).0.to_vec())
+                .collect()
+        };
+        assert_eq!(responses.len(), 3);
+        assert_eq!(messages(&responses[0].0), [&b"one"[..], b"two"]);
+        assert_eq!(responses[0].1, 0);
+        assert_eq!(messages(&responses[1].0), [&b"three"[..]]);
+        assert_eq!(responses[1].1, 2);
+        assert_eq!(messages(&responses[2].0), [&b"four"[..]]);
+        assert_eq!(responses[2].1, 3);
+    }
+
+    #[test]
+    fn drops_are_reported_with_reason() {
+        let sink = MultiSink::<256>::new();
+        let mut entry_buffer = [0u8; 8];
+        let mut writer = TestWriter::default();
+        let mut drain = RpcLogDrain::new(
+            sink.attach_drain(),
+            &mut entry_buffer,
+            ErrorHandling::IgnoreWriterErrors,
+        );
+        drain.open(&mut writer).unwrap();
+
+        sink.handle_dropped(2);
+        push_log(&sink, b"too long for the entry buffer");
+        push_log(&sink, b"ok");
+        drain.flush(&mut [0u8; 128]).unwrap();
+
+        assert_eq!(writer.responses.len(), 1);
+        let (entries, sequence_id) = decode_log_entries(&writer.responses[0]);
+        let entries: Vec<_> = entries.into_iter().map(decode_log_entry).collect();
+        assert_eq!(
+            entries,
+            [
+                (INGRESS_ERROR_MESSAGE.as_bytes(), 2),
+                (SMALL_STACK_BUFFER_MESSAGE.as_bytes(), 1),
+                (&b"ok"[..], 0),
+            ]
+        );
+        assert_eq!(sequence_id, 0);
+    }
+
+    #[test]
+    fn writer_error_closes_stream_and_is_reported() {
+        let sink = MultiSink::<256>::new();
+        let mut entry_buffer = [0u8; 32];
+        let mut failing = TestWriter {
+            fail: true,
+            ..Default::default()
+        };
+        let mut writer = TestWriter::default();
+        let mut drain = RpcLogDrain::new(
+            sink.attach_drain(),
+            &mut entry_buffer,
+            ErrorHandling::CloseStreamOnWriterError,
+        );
+
+        drain.open(&mut failing).unwrap();
+        push_log(&sink, b"lost");
+        assert_eq!(drain.flush(&mut [0u8; 64]), Err(Error::Aborted));
+        assert!(!drain.is_open());
+
+        drain.open(&mut writer).unwrap();
+        push_log(&sink, b"sent");
+        drain.flush(&mut [0u8; 64]).unwrap();
+
+        let (entries, sequence_id) = decode_log_entries(&writer.responses[0]);
+        let entries: Vec<_> = entries.into_iter().map(decode_log_entry).collect();
+        assert_eq!(
+            entries,
+            [(WRITER_ERROR_MESSAGE.as_bytes(), 1), (&b"sent"[..], 0)]
+        );
+        assert_eq!(sequence_id, 1);
+    }
+}
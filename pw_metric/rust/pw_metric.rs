@@ -0,0 +1,562 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_metric` provides counters and gauges named by tokens, organized into
+//! groups, which are compatible with the C++ `pw_metric` module and its host
+//! tooling.
+//!
+//! Metric and group names are tokenized into the `"metrics"` domain of the
+//! token database, so names cost four bytes each on the device.  Metrics are
+//! usually declared as statics with [`counter!`], [`gauge!`], and [`group!`]:
+//!
+//! ```
+//! use pw_metric::{counter, gauge, group, Counter, Gauge, Group};
+//!
+//! static TRANSACTIONS: Counter = counter!("transactions", 0);
+//! static FAILURES: Counter = counter!("failures", 0);
+//! static TEMPERATURE: Gauge = gauge!("temperature", 0.0);
+//! static I2C: Group = group!("i2c_bus_1", [&TRANSACTIONS, &FAILURES], []);
+//! static ROOT: Group = group!("device", [&TEMPERATURE], [&I2C]);
+//!
+//! TRANSACTIONS.increment();
+//! TEMPERATURE.set(27.5);
+//! # assert_eq!(TRANSACTIONS.value(), 1);
+//! ```
+//!
+//! Metrics are exported by walking a group tree with [`Group::walk()`], which
+//! provides each metric with its token path, or as `pw.metric.proto.Metric`
+//! protos with [`encode_metric_response()`] for the `pw.metric.MetricService`
+//! RPC service.
+#![no_std]
+#![deny(missing_docs)]
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use pw_protobuf::MemoryEncoder;
+use pw_status::{Error, Result};
+use pw_tokenizer_core::hash_string;
+
+/// ID of the `pw.metric.proto.MetricService` service.
+pub const SERVICE_ID: u32 = hash_string("pw.metric.proto.MetricService");
+
+/// ID of the `pw.metric.proto.MetricService.Get` method.
+pub const GET_METHOD_ID: u32 = hash_string("Get");
+
+/// Mask applied to metric name tokens.  The top bit of a metric's token
+/// stores its type.
+pub const METRIC_TOKEN_MASK: u32 = 0x7fff_ffff;
+
+/// Maximum depth of nested groups which can be walked.
+pub const MAX_GROUP_DEPTH: usize = 8;
+
+const TYPE_MASK: u32 = !METRIC_TOKEN_MASK;
+const TYPE_FLOAT: u32 = TYPE_MASK;
+
+// Field numbers of `pw.metric.proto.Metric`.
+const METRIC_TOKEN_PATH: u32 = 1;
+const METRIC_AS_FLOAT: u32 = 3;
+const METRIC_AS_INT: u32 = 4;
+
+// Field numbers of `pw.metric.proto.MetricResponse`.
+const METRIC_RESPONSE_METRICS: u32 = 1;
+
+/// The value of a [`Metric`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetricValue {
+    /// The value of a [`Counter`].
+    Int(u32),
+    /// The value of a [`Gauge`].
+    Float(f32),
+}
+
+/// A metric named by a token, which is either a [`Counter`] or a [`Gauge`].
+pub struct Metric {
+    name_and_type: u32,
+    value: Mutex<Cell<MetricValue>>,
+}
+
+impl Metric {
+    const fn new(name: u32, value: MetricValue) -> Self {
+        let type_bit = match value {
+            MetricValue::Int(_) => 0,
+            MetricValue::Float(_) => TYPE_FLOAT,
+        };
+        Self {
+            name_and_type: (name & METRIC_TOKEN_MASK) | type_bit,
+            value: Mutex::new(Cell::new(value)),
+        }
+    }
+
+    /// Returns the metric's name token.
+    pub const fn name(&self) -> u32 {
+        self.name_and_type & METRIC_TOKEN_MASK
+    }
+
+    /// Returns `true` if the metric is a [`Gauge`].
+    pub const fn is_float(&self) -> bool {
+        self.name_and_type & TYPE_MASK == TYPE_FLOAT
+    }
+
+    /// Returns the metric's current value.
+    pub fn value(&self) -> MetricValue {
+        critical_section::with(|cs| self.value.borrow(cs).get())
+    }
+
+    fn update(&self, f: impl FnOnce(MetricValue) -> MetricValue) {
+        critical_section::with(|cs| {
+            let value = self.value.borrow(cs);
+            value.set(f(value.get()))
+        })
+    }
+}
+
+/// An integer metric, such as a count of events.
+///
+/// Arithmetic wraps on overflow, as in C++.
+pub struct Counter(Metric);
+
+impl Counter {
+    /// Creates a counter named by `name`, which is usually created with
+    /// [`counter!`].
+    pub const fn new(name: u32, value: u32) -> Self {
+        Self(Metric::new(name, MetricValue::Int(value)))
+    }
+
+    /// Adds one to the counter.
+    pub fn increment(&self) {
+        self.add(1)
+    }
+
+    /// Adds `amount` to the counter.
+    pub fn add(&self, amount: u32) {
+        self.update(|value| value.wrapping_add(amount))
+    }
+
+    /// Subtracts one from the counter.
+    pub fn decrement(&self) {
+        self.subtract(1)
+    }
+
+    /// Subtracts `amount` from the counter.
+    pub fn subtract(&self, amount: u32) {
+        self.update(|value| value.wrapping_sub(amount))
+    }
+
+    /// Sets the counter's value.
+    pub fn set(&self, value: u32) {
+        self.update(|_| value)
+    }
+
+    /// Returns the counter's value.
+    pub fn value(&self) -> u32 {
+        match self.0.value() {
+            MetricValue::Int(value) => value,
+            MetricValue::Float(_) => 0,
+        }
+    }
+
+    /// Returns the counter as a [`Metric`].
+    pub const fn metric(&self) -> &Metric {
+        &self.0
+    }
+
+    fn update(&self, f: impl FnOnce(u32) -> u32) {
+        self.0.update(|value| match value {
+            MetricValue::Int(value) => MetricValue::Int(f(value)),
+            value => value,
+        })
+    }
+}
+
+/// A floating point metric, such as a measurement.
+pub struct Gauge(Metric);
+
+impl Gauge {
+    /// Creates a gauge named by `name`, which is usually created with
+    /// [`gauge!`].
+    pub const fn new(name: u32, value: f32) -> Self {
+        Self(Metric::new(name, MetricValue::Float(value)))
+    }
+
+    /// Sets the gauge's value.
+    pub fn set(&self, value: f32) {
+        self.0.update(|_| MetricValue::Float(value))
+    }
+
+    /// Returns the gauge's value.
+    pub fn value(&self) -> f32 {
+        match self.0.value() {
+            MetricValue::Float(value) => value,
+            MetricValue::Int(_) => 0.0,
+        }
+    }
+
+    /// Returns the gauge as a [`Metric`].
+    pub const fn metric(&self) -> &Metric {
+        &self.0
+    }
+}
+
+/// A group of metrics and child groups, named by a token.
+pub struct Group<'a> {
+    name: u32,
+    metrics: &'a [&'a Metric],
+    children: &'a [&'a Group<'a>],
+}
+
+impl<'a> Group<'a> {
+    /// Creates a group named by `name`, which is usually created with
+    /// [`group!`].
+    pub const fn new(name: u32, metrics: &'a [&'a Metric], children: &'a [&'a Group<'a>]) -> Self {
+        Self {
+            name,
+            metrics,
+            children,
+        }
+    }
+
+    /// Returns the group's name token.
+    pub const fn name(&self) -> u32 {
+        self.name
+    }
+
+    /// Returns the group's metrics.
+    pub const fn metrics(&self) -> &'a [&'a Metric] {
+        self.metrics
+    }
+
+    /// Returns the group's child groups.
+    pub const fn children(&self) -> &'a [&'a Group<'a>] {
+        self.children
+    }
+
+    /// Calls `f` with each metric in the group and its descendants, along
+    /// with the metric's token path: the names of the groups from this one to
+    /// the metric's group, followed by the metric's name.
+    ///
+    /// A group's metrics are visited before its children.  Walking stops at
+    /// the first error returned by `f`.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - Groups are nested deeper than
+    ///   [`MAX_GROUP_DEPTH`].
+    pub fn walk(&self, mut f: impl FnMut(&[u32], &Metric) -> Result<()>) -> Result<()> {
+        let mut path = [0; MAX_GROUP_DEPTH + 1];
+        self.walk_at_depth(&mut path, 0, &mut f)
+    }
+
+    fn walk_at_depth(
+        &self,
+        path: &mut [u32; MAX_GROUP_DEPTH + 1],
+        depth: usize,
+        f: &mut impl FnMut(&[u32], &Metric) -> Result<()>,
+    ) -> Result<()> {
+        if depth >= MAX_GROUP_DEPTH {
+            return Err(Error::ResourceExhausted);
+        }
+        path[depth] = self.name;
+        for metric in self.metrics {
+            path[depth + 1] = metric.name();
+            f(&path[..depth + 2], metric)?;
+        }
+        for child in self.children {
+            child.walk_at_depth(path, depth + 1, f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a metric and its token path as a `pw.metric.proto.Metric` proto
+/// into `buffer`, returning the encoded length.
+///
+/// # Errors
+/// - [`Error::ResourceExhausted`] - `buffer` is too small.
+pub fn encode_metric(token_path: &[u32], metric: &Metric, buffer: &mut [u8]) -> Result<usize> {
+    let mut encoder = MemoryEncoder::new(buffer);
+    write_metric(&mut encoder, token_path, metric)?;
+    Ok(encoder.len())
+}
+
+fn write_metric(encoder: &mut MemoryEncoder, token_path: &[u32], metric: &Metric) -> Result<()> {
+    // `token_path` is a packed `repeated fixed32`.
+    let mut packed_path = [0u8; 4 * (MAX_GROUP_DEPTH + 1)];
+    let packed_path = packed_path
+        .get_mut(..4 * token_path.len())
+        .ok_or(Error::InvalidArgument)?;
+    for (bytes, token) in packed_path.chunks_exact_mut(4).zip(token_path) {
+        bytes.copy_from_slice(&token.to_le_bytes());
+    }
+    encoder.write_bytes(METRIC_TOKEN_PATH, packed_path)?;
+    match metric.value() {
+        MetricValue::Int(value) => encoder.write_uint32(METRIC_AS_INT, value),
+        MetricValue::Float(value) => encoder.write_float(METRIC_AS_FLOAT, value),
+    }
+}
+
+/// Encodes the metrics in `groups` as a `pw.metric.proto.MetricResponse`
+/// into `buffer`, starting with the metric at index `start` of the walk.
+/// Returns the encoded length and the number of metrics encoded.
+///
+/// A response holds as many metrics as fit in `buffer`.  To stream all of
+/// the metrics as `MetricService.Get` does, send responses until one encodes
+/// no metrics, increasing `start` by the number encoded each time:
+///
+/// ```
+/// use pw_metric::{counter, encode_metric_response, group, Counter, Group};
+///
+/// static A: Counter = counter!("a", 1);
+/// static B: Counter = counter!("b", 2);
+/// static ROOT: Group = group!("root", [&A, &B], []);
+///
+/// let mut buffer = [0u8; 20];
+/// let mut start = 0;
+/// loop {
+///     let (len, count) = encode_metric_response(&[&ROOT], start, &mut buffer)?;
+///     if count == 0 {
+///         break;
+///     }
+///     // Send `buffer[..len]` as a server stream packet.
+///     start += count;
+/// }
+/// # assert_eq!(start, 2);
+/// # Ok::<(), pw_status::Error>(())
+/// ```
+///
+/// # Errors
+/// - [`Error::ResourceExhausted`] - The first metric does not fit in
+///   `buffer`, or groups are nested deeper than [`MAX_GROUP_DEPTH`].
+pub fn encode_metric_response(
+    groups: &[&Group],
+    start: usize,
+    buffer: &mut [u8],
+) -> Result<(usize, usize)> {
+    let mut encoder = MemoryEncoder::new(buffer);
+    let mut index = 0;
+    let mut count = 0;
+    for group in groups {
+        let result = group.walk(|token_path, metric| {
+            index += 1;
+            if index <= start {
+                return Ok(());
+            }
+            encoder.write_nested(METRIC_RESPONSE_METRICS, |encoder| {
+                write_metric(encoder, token_path, metric)
+            })?;
+            count += 1;
+            Ok(())
+        });
+        match result {
+            // The response is full.
+            Err(Error::ResourceExhausted) if count > 0 => break,
+            result => result?,
+        }
+    }
+    Ok((encoder.len(), count))
+}
+
+/// Declares a [`Counter`] named `name`, with an initial value.
+///
+/// `name` is tokenized into the `"metrics"` domain.
+#[macro_export]
+macro_rules! counter {
+    ($name:literal, $value:expr) => {
+        $crate::Counter::new(
+            $crate::__private::masked_token!("metrics", $crate::METRIC_TOKEN_MASK, $name),
+            $value,
+        )
+    };
+}
+
+/// Declares a [`Gauge`] named `name`, with an initial value.
+///
+/// `name` is tokenized into the `"metrics"` domain.
+#[macro_export]
+macro_rules! gauge {
+    ($name:literal, $value:expr) => {
+        $crate::Gauge::new(
+            $crate::__private::masked_token!("metrics", $crate::METRIC_TOKEN_MASK, $name),
+            $value,
+        )
+    };
+}
+
+/// Declares a [`Group`] named `name`, with lists of references to its
+/// counters and gauges and to its child groups.
+///
+/// `name` is tokenized into the `"metrics"` domain.
+#[macro_export]
+macro_rules! group {
+    ($name:literal, [$($metric:expr),* $(,)?], [$($child:expr),* $(,)?]) => {
+        $crate::Group::new(
+            $crate::__private::masked_token!("metrics", u32::MAX, $name),
+            &[$($metric.metric()),*],
+            &[$($child),*],
+        )
+    };
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use pw_tokenizer::masked_token;
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec;
+    use std::vec::Vec;
+
+    use pw_protobuf::{Decoder, Value};
+
+    use super::*;
+
+    static TRANSACTIONS: Counter = counter!("transactions", 0);
+    static FAILURES: Counter = counter!("failures", 3);
+    static TEMPERATURE: Gauge = gauge!("temperature", 1.5);
+    static I2C: Group = group!("i2c_bus_1", [&TRANSACTIONS, &FAILURES], []);
+    static ROOT: Group = group!("device", [&TEMPERATURE], [&I2C]);
+
+    #[test]
+    fn counter_arithmetic_wraps() {
+        let counter = Counter::new(1, u32::MAX);
+        counter.increment();
+        assert_eq!(counter.value(), 0);
+        counter.subtract(2);
+        assert_eq!(counter.value(), u32::MAX - 1);
+        counter.add(3);
+        counter.decrement();
+        assert_eq!(counter.value(), 0);
+        counter.set(7);
+        assert_eq!(counter.metric().value(), MetricValue::Int(7));
+    }
+
+    #[test]
+    fn names_are_masked_tokens_with_type_bit() {
+        let name = hash_string("temperature") & METRIC_TOKEN_MASK;
+        assert_eq!(TEMPERATURE.metric().name(), name);
+        assert!(TEMPERATURE.metric().is_float());
+        assert!(!FAILURES.metric().is_float());
+        assert_eq!(ROOT.name(), hash_string("device"));
+
+        let gauge = Gauge::new(u32::MAX, 0.0);
+        assert_eq!(gauge.metric().name(), METRIC_TOKEN_MASK);
+        gauge.set(-2.25);
+        assert_eq!(gauge.value(), -2.25);
+    }
+
+    #[test]
+    fn walk_visits_metrics_with_token_paths() {
+        let mut visited = Vec::new();
+        ROOT.walk(|path, metric| {
+            visited.push((path.to_vec(), metric.name()));
+            Ok(())
+        })
+        .unwrap();
+        let device = hash_string("device");
+        let i2c = hash_string("i2c_bus_1");
+        assert_eq!(
+            visited,
+            [
+                (
+                    vec![device, TEMPERATURE.metric().name()],
+                    TEMPERATURE.metric().name()
+                ),
+                (
+                    vec![device, i2c, TRANSACTIONS.metric().name()],
+                    TRANSACTIONS.metric().name()
+                ),
+                (
+                    vec![device, i2c, FAILURES.metric().name()],
+                    FAILURES.metric().name()
+                ),
+            ]
+        );
+
+        let mut count = 0;
+        let result = ROOT.walk(|_, _| {
+            count += 1;
+            Err(Error::Cancelled)
+        });
+        assert_eq!(result, Err(Error::Cancelled));
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn deep_groups_are_rejected() {
+        static LEAF: Group = Group::new(0, &[], &[]);
+        static L1: Group = Group::new(1, &[], &[&LEAF]);
+        static L2: Group = Group::new(2, &[], &[&L1]);
+        static L3: Group = Group::new(3, &[], &[&L2]);
+        static L4: Group = Group::new(4, &[], &[&L3]);
+        static L5: Group = Group::new(5, &[], &[&L4]);
+        static L6: Group = Group::new(6, &[], &[&L5]);
+        static L7: Group = Group::new(7, &[], &[&L6]);
+        assert_eq!(L7.walk(|_, _| Ok(())), Ok(()));
+        static L8: Group = Group::new(8, &[], &[&L7]);
+        assert_eq!(L8.walk(|_, _| Ok(())), Err(Error::ResourceExhausted));
+    }
+
+    #[test]
+    fn metric_is_encoded_with_packed_token_path() {
+        let counter = Counter::new(0x1234, 5);
+        let mut buffer = [0u8; 32];
+        let len = encode_metric(&[0xaabbccdd, 0x1234], counter.metric(), &mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..len],
+            b"\x0a\x08\xdd\xcc\xbb\xaa\x34\x12\x00\x00\x20\x05"
+        );
+
+        let gauge = Gauge::new(1, 1.0);
+        let len = encode_metric(&[1], gauge.metric(), &mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..len],
+            b"\x0a\x04\x01\x00\x00\x00\x1d\x00\x00\x80\x3f"
+        );
+    }
+
+    #[test]
+    fn responses_hold_metrics_which_fit() {
+        // Each metric in the response uses 16 to 20 bytes.
+        let mut buffer = [0u8; 40];
+        let (len, count) = encode_metric_response(&[&ROOT], 0, &mut buffer).unwrap();
+        assert_eq!(count, 2);
+        let metrics: Vec<_> = Decoder::new(&buffer[..len]).map(Result::unwrap).collect();
+        assert_eq!(metrics.len(), 2);
+        let Value::Delimited(first) = metrics[0].value else {
+            panic!("expected a metric");
+        };
+        let mut buffer = [0u8; 32];
+        let first_len = encode_metric(
+            &[ROOT.name(), TEMPERATURE.metric().name()],
+            TEMPERATURE.metric(),
+            &mut buffer,
+        )
+        .unwrap();
+        assert_eq!(first, &buffer[..first_len]);
+
+        let mut buffer = [0u8; 40];
+        assert_eq!(
+            encode_metric_response(&[&ROOT], 2, &mut buffer).map(|(_, count)| count),
+            Ok(1)
+        );
+        assert_eq!(encode_metric_response(&[&ROOT], 3, &mut buffer), Ok((0, 0)));
+
+        let mut buffer = [0u8; 8];
+        assert_eq!(
+            encode_metric_response(&[&ROOT], 0, &mut buffer),
+            Err(Error::ResourceExhausted)
+        );
+    }
+}
@@ -0,0 +1,515 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut, Range};
+use core::ptr::NonNull;
+
+use pw_status::{Error, Result};
+
+// The portion of a region owned by a chunk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Span {
+    start: usize,
+    end: usize,
+}
+
+impl Span {
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+/// A region of memory that can be divided into up to `MAX_CHUNKS` [`Chunk`]s.
+///
+/// `ChunkRegion` is the Rust analog of the C++ `ChunkRegionTracker`.  It keeps
+/// track of which parts of the region are owned by live chunks so that chunks
+/// can safely grow into unused memory around them.  Once all chunks are
+/// dropped, the region may be reused by calling
+/// [`first_chunk()`](ChunkRegion::first_chunk) again.
+pub struct ChunkRegion<'a, const MAX_CHUNKS: usize> {
+    data: NonNull<u8>,
+    len: usize,
+    slots: [Cell<Option<Span>>; MAX_CHUNKS],
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a, const MAX_CHUNKS: usize> ChunkRegion<'a, MAX_CHUNKS> {
+    /// Create a new `ChunkRegion` tracking `buffer`.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            len: buffer.len(),
+            data: NonNull::from(buffer).cast(),
+            slots: core::array::from_fn(|_| Cell::new(None)),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the total size of the region in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the region has a size of zero bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of live chunks referencing this region.
+    pub fn chunk_count(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| slot.get().is_some())
+            .count()
+    }
+
+    /// Creates a chunk spanning the entire region.
+    ///
+    /// Returns `None` if any chunks referencing the region are still alive.
+    pub fn first_chunk(&self) -> Option<Chunk<'_>> {
+        if self.chunk_count() != 0 {
+            return None;
+        }
+        Chunk::new(
+            self.data,
+            self.len,
+            &self.slots,
+            Span {
+                start: 0,
+                end: self.len,
+            },
+        )
+    }
+}
+
+/// A contiguous, owned portion of a [`ChunkRegion`].
+///
+/// `Chunk` dereferences to a `[u8]` slice of its data.  Dropping a `Chunk`
+/// releases its portion of the region for use by other chunks.
+///
+/// `Chunk` combines the C++ `Chunk` and `OwnedChunk` types.
+pub struct Chunk<'r> {
+    data: NonNull<u8>,
+    region_len: usize,
+    slots: &'r [Cell<Option<Span>>],
+    index: usize,
+    span: Span,
+}
+
+impl<'r> Chunk<'r> {
+    // Allocates a slot for a chunk owning `span`.
+    fn new(
+        data: NonNull<u8>,
+        region_len: usize,
+        slots: &'r [Cell<Option<Span>>],
+        span: Span,
+    ) -> Option<Self> {
+        let index = slots.iter().position(|slot| slot.get().is_none())?;
+        slots[index].set(Some(span));
+        Some(Self {
+            data,
+            region_len,
+            slots,
+            index,
+            span,
+        })
+    }
+
+    fn set_span(&mut self, span: Span) {
+        self.span = span;
+        self.slots[self.index].set(Some(span));
+    }
+
+    // Returns the spans of all other live chunks in the region.
+    fn other_spans(&self) -> impl Iterator<Item = Span> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| *i != self.index)
+            .filter_map(|(_, slot)| slot.get())
+    }
+
+    /// Returns the number of unowned bytes directly preceding this chunk in
+    /// its region.
+    ///
+    /// This is the maximum number of bytes that can be claimed with
+    /// [`claim_prefix()`](Chunk::claim_prefix).
+    pub fn available_prefix(&self) -> usize {
+        let limit = self
+            .other_spans()
+            .map(|s| s.end)
+            .filter(|&end| end <= self.span.start)
+            .max()
+            .unwrap_or(0);
+        self.span.start - limit
+    }
+
+    /// Returns the number of unowned bytes directly following this chunk in
+    /// its region.
+    ///
+    /// This is the maximum number of bytes that can be claimed with
+    /// [`claim_suffix()`](Chunk::claim_suffix).
+    pub fn available_suffix(&self) -> usize {
+        let limit = self
+            .other_spans()
+            .map(|s| s.start)
+            .filter(|&start| start >= self.span.end)
+            .min()
+            .unwrap_or(self.region_len);
+        limit - self.span.end
+    }
+
+    /// Grows the chunk to include `len` bytes of the region preceding it.
+    ///
+    /// The contents of the newly claimed bytes are unspecified.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - The bytes are outside of the region or
+    ///   owned by another chunk.
+    pub fn claim_prefix(&mut self, len: usize) -> Result<()> {
+        if len > self.available_prefix() {
+            return Err(Error::ResourceExhausted);
+        }
+        self.set_span(Span {
+            start: self.span.start - len,
+            end: self.span.end,
+        });
+        Ok(())
+    }
+
+    /// Grows the chunk to include `len` bytes of the region following it.
+    ///
+    /// The contents of the newly claimed bytes are unspecified.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - The bytes are outside of the region or
+    ///   owned by another chunk.
+    pub fn claim_suffix(&mut self, len: usize) -> Result<()> {
+        if len > self.available_suffix() {
+            return Err(Error::ResourceExhausted);
+        }
+        self.set_span(Span {
+            start: self.span.start,
+            end: self.span.end + len,
+        });
+        Ok(())
+    }
+
+    /// Shrinks the chunk by releasing its first `len` bytes.
+    ///
+    /// The released bytes may be reclaimed with
+    /// [`claim_prefix()`](Chunk::claim_prefix).
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - `len` is larger than the chunk.
+    pub fn discard_prefix(&mut self, len: usize) -> Result<()> {
+        self.slice(len..self.len())
+    }
+
+    /// Shrinks the chunk to `range`, relative to its current data.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - `range` is not contained in the chunk.
+    pub fn slice(&mut self, range: Range<usize>) -> Result<()> {
+        if range.start > range.end || range.end > self.len() {
+            return Err(Error::OutOfRange);
+        }
+        self.set_span(Span {
+            start: self.span.start + range.start,
+            end: self.span.start + range.end,
+        });
+        Ok(())
+    }
+
+    /// Shrinks the chunk to its first `len` bytes.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - `len` is larger than the chunk.
+    pub fn truncate(&mut self, len: usize) -> Result<()> {
+        self.slice(0..len)
+    }
+
+    /// Splits off the first `len` bytes of this chunk into a new chunk.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - `len` is larger than the chunk.
+    /// - [`Error::ResourceExhausted`] - The region can not track any more
+    ///   chunks.
+    pub fn take_prefix(&mut self, len: usize) -> Result<Chunk<'r>> {
+        if len > self.len() {
+            return Err(Error::OutOfRange);
+        }
+        let split = self.span.start + len;
+        // Shrink `self` first so the new chunk does not overlap it.
+        let span = self.span;
+        self.set_span(Span {
+            start: split,
+            end: span.end,
+        });
+        Chunk::new(
+            self.data,
+            self.region_len,
+            self.slots,
+            Span {
+                start: span.start,
+                end: split,
+            },
+        )
+        .ok_or_else(|| {
+            self.set_span(span);
+            Error::ResourceExhausted
+        })
+    }
+
+    /// Splits off the last `len` bytes of this chunk into a new chunk.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - `len` is larger than the chunk.
+    /// - [`Error::ResourceExhausted`] - The region can not track any more
+    ///   chunks.
+    pub fn take_suffix(&mut self, len: usize) -> Result<Chunk<'r>> {
+        if len > self.len() {
+            return Err(Error::OutOfRange);
+        }
+        let split = self.span.end - len;
+        let span = self.span;
+        self.set_span(Span {
+            start: span.start,
+            end: split,
+        });
+        Chunk::new(
+            self.data,
+            self.region_len,
+            self.slots,
+            Span {
+                start: split,
+                end: span.end,
+            },
+        )
+        .ok_or_else(|| {
+            self.set_span(span);
+            Error::ResourceExhausted
+        })
+    }
+
+    /// Returns true if `next` directly follows this chunk in the same region.
+    pub fn can_merge(&self, next: &Chunk<'_>) -> bool {
+        core::ptr::eq(self.slots.as_ptr(), next.slots.as_ptr()) && self.span.end == next.span.start
+    }
+
+    /// Merges `next` into the end of this chunk.
+    ///
+    /// If the chunks can not be merged (see [`can_merge()`](Chunk::can_merge)),
+    /// `next` is returned unmodified.
+    pub fn merge(&mut self, next: Chunk<'r>) -> core::result::Result<(), Chunk<'r>> {
+        if !self.can_merge(&next) {
+            return Err(next);
+        }
+        let end = next.span.end;
+        // Dropping `next` releases its part of the region which is then
+        // claimed by `self`.
+        drop(next);
+        self.set_span(Span {
+            start: self.span.start,
+            end,
+        });
+        Ok(())
+    }
+
+    /// Returns the number of bytes in the chunk.
+    pub fn len(&self) -> usize {
+        self.span.len()
+    }
+
+    /// Returns true if the chunk contains no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.span.len() == 0
+    }
+}
+
+impl Deref for Chunk<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // Safety: The region's memory is valid for the lifetime of the
+        // `ChunkRegion` which outlives `self`.  The spans of live chunks never
+        // overlap so no other chunk can mutably reference this memory.
+        unsafe { core::slice::from_raw_parts(self.data.as_ptr().add(self.span.start), self.len()) }
+    }
+}
+
+impl DerefMut for Chunk<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // Safety: See `deref()`.  Holding `&mut self` guarantees this is the
+        // only reference to the chunk's memory.
+        unsafe {
+            core::slice::from_raw_parts_mut(self.data.as_ptr().add(self.span.start), self.len())
+        }
+    }
+}
+
+impl Drop for Chunk<'_> {
+    fn drop(&mut self) {
+        self.slots[self.index].set(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_chunk_spans_region() {
+        let mut buffer = [0u8; 16];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let chunk = region.first_chunk().unwrap();
+        assert_eq!(chunk.len(), 16);
+        assert_eq!(region.chunk_count(), 1);
+
+        // Only one first chunk can exist at a time.
+        assert!(region.first_chunk().is_none());
+        drop(chunk);
+        assert_eq!(region.chunk_count(), 0);
+        assert!(region.first_chunk().is_some());
+    }
+
+    #[test]
+    fn chunk_data_is_backed_by_region() {
+        let mut buffer = [0u8; 8];
+        {
+            let region = ChunkRegion::<4>::new(&mut buffer);
+            let mut chunk = region.first_chunk().unwrap();
+            chunk.discard_prefix(2).unwrap();
+            chunk.copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        }
+        assert_eq!(buffer, [0, 0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn discarded_prefix_can_be_reclaimed() {
+        let mut buffer = [0u8; 16];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut chunk = region.first_chunk().unwrap();
+        chunk.discard_prefix(4).unwrap();
+        assert_eq!(chunk.len(), 12);
+        assert_eq!(chunk.available_prefix(), 4);
+
+        assert_eq!(chunk.claim_prefix(5), Err(Error::ResourceExhausted));
+        assert_eq!(chunk.claim_prefix(4), Ok(()));
+        assert_eq!(chunk.len(), 16);
+    }
+
+    #[test]
+    fn truncated_suffix_can_be_reclaimed() {
+        let mut buffer = [0u8; 16];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut chunk = region.first_chunk().unwrap();
+        chunk.truncate(10).unwrap();
+        assert_eq!(chunk.available_suffix(), 6);
+
+        assert_eq!(chunk.claim_suffix(7), Err(Error::ResourceExhausted));
+        assert_eq!(chunk.claim_suffix(6), Ok(()));
+        assert_eq!(chunk.len(), 16);
+    }
+
+    #[test]
+    fn out_of_range_resizes_fail() {
+        let mut buffer = [0u8; 16];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut chunk = region.first_chunk().unwrap();
+        assert_eq!(chunk.discard_prefix(17), Err(Error::OutOfRange));
+        assert_eq!(chunk.truncate(17), Err(Error::OutOfRange));
+        assert_eq!(
+            chunk.slice(Range { start: 4, end: 2 }),
+            Err(Error::OutOfRange)
+        );
+        assert_eq!(chunk.len(), 16);
+    }
+
+    #[test]
+    fn claims_can_not_overlap_other_chunks() {
+        let mut buffer = [0u8; 16];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut back = region.first_chunk().unwrap();
+        let mut front = back.take_prefix(8).unwrap();
+        assert_eq!(front.len(), 8);
+        assert_eq!(back.len(), 8);
+
+        assert_eq!(front.available_suffix(), 0);
+        assert_eq!(front.claim_suffix(1), Err(Error::ResourceExhausted));
+        assert_eq!(back.claim_prefix(1), Err(Error::ResourceExhausted));
+
+        // Once the front chunk shrinks, the back chunk may claim the space.
+        front.truncate(6).unwrap();
+        assert_eq!(back.available_prefix(), 2);
+        assert_eq!(back.claim_prefix(2), Ok(()));
+        assert_eq!(back.len(), 10);
+    }
+
+    #[test]
+    fn take_prefix_and_suffix_split_data() {
+        let mut buffer = [0u8, 1, 2, 3, 4, 5, 6, 7];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut chunk = region.first_chunk().unwrap();
+        let prefix = chunk.take_prefix(2).unwrap();
+        let suffix = chunk.take_suffix(3).unwrap();
+        assert_eq!(&prefix[..], &[0, 1]);
+        assert_eq!(&chunk[..], &[2, 3, 4]);
+        assert_eq!(&suffix[..], &[5, 6, 7]);
+        assert_eq!(region.chunk_count(), 3);
+    }
+
+    #[test]
+    fn take_prefix_fails_when_out_of_slots() {
+        let mut buffer = [0u8; 8];
+        let region = ChunkRegion::<1>::new(&mut buffer);
+        let mut chunk = region.first_chunk().unwrap();
+        assert_eq!(chunk.take_prefix(2).err(), Some(Error::ResourceExhausted));
+        assert_eq!(chunk.take_suffix(2).err(), Some(Error::ResourceExhausted));
+        assert_eq!(chunk.take_prefix(9).err(), Some(Error::OutOfRange));
+
+        // The chunk is unmodified by the failed splits.
+        assert_eq!(chunk.len(), 8);
+    }
+
+    #[test]
+    fn adjacent_chunks_merge() {
+        let mut buffer = [0u8, 1, 2, 3, 4, 5, 6, 7];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut back = region.first_chunk().unwrap();
+        let mut front = back.take_prefix(4).unwrap();
+
+        assert!(front.can_merge(&back));
+        assert!(!back.can_merge(&front));
+        assert!(front.merge(back).is_ok());
+        assert_eq!(&front[..], &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(region.chunk_count(), 1);
+    }
+
+    #[test]
+    fn chunks_from_different_regions_do_not_merge() {
+        let mut buffer_a = [0u8; 8];
+        let mut buffer_b = [0u8; 8];
+        let region_a = ChunkRegion::<4>::new(&mut buffer_a);
+        let region_b = ChunkRegion::<4>::new(&mut buffer_b);
+        let mut a = region_a.first_chunk().unwrap();
+        a.truncate(0).unwrap();
+        let b = region_b.first_chunk().unwrap();
+
+        assert!(!a.can_merge(&b));
+        let b = a.merge(b).unwrap_err();
+        assert_eq!(b.len(), 8);
+    }
+}
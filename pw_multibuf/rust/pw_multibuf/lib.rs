@@ -0,0 +1,71 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_multibuf` provides a buffer API optimized for zero-copy messaging.
+//!
+//! Sending or receiving messages via RPC, transfer, or sockets often requires
+//! a series of intermediate buffers, each requiring their own copy of the data.
+//! `pw_multibuf` allows data to be written *once*, eliminating the memory, CPU
+//! and latency overhead of copying.  See
+//! [Pigweed's pw_multibuf documentation](https://pigweed.dev/pw_multibuf/)
+//! for an overview of the design.
+//!
+//! This crate is a `no_std`, allocation free port of the C++ API:
+//! * A [`ChunkRegion`] tracks a region of memory that is divided into
+//!   [`Chunk`]s.  Chunks own disjoint parts of the region and may grow into
+//!   neighboring unused memory with [`Chunk::claim_prefix()`] and
+//!   [`Chunk::claim_suffix()`].  This allows lower layers to add headers and
+//!   footers without moving the payload.  The region's memory is available for
+//!   reuse once every chunk is dropped.
+//! * A [`MultiBuf`] is an ordered sequence of [`Chunk`]s which may come from
+//!   different regions.  It implements [`pw_stream::Read`] and
+//!   [`pw_stream::Write`].
+//!
+//! Unlike the C++ implementation, chunk bookkeeping is not protected by a
+//! lock and a [`ChunkRegion`] may not be shared between threads.
+//!
+//! # Example
+//!
+//! ```
+//! use pw_multibuf::{ChunkRegion, MultiBuf};
+//! use pw_stream::Write;
+//!
+//! let mut buffer = [0u8; 32];
+//! let region = ChunkRegion::<4>::new(&mut buffer);
+//!
+//! // Reserve 2 bytes at the front of the region for a header and start with
+//! // an empty payload.
+//! let mut chunk = region.first_chunk().unwrap();
+//! chunk.discard_prefix(2).unwrap();
+//! chunk.truncate(0).unwrap();
+//!
+//! // Write the payload.
+//! let mut buf = MultiBuf::<4>::from_chunk(chunk);
+//! buf.write_all(b"payload").unwrap();
+//!
+//! // A lower layer adds a header without copying the payload.
+//! buf.prepend(&[0x7e, 7]).unwrap();
+//!
+//! let mut packet = [0u8; 9];
+//! assert_eq!(buf.copy_to(&mut packet), 9);
+//! assert_eq!(&packet, b"\x7e\x07payload");
+//! ```
+#![cfg_attr(not(feature = "std"), no_std)]
+#![deny(missing_docs)]
+
+mod chunk;
+mod multibuf;
+
+pub use chunk::{Chunk, ChunkRegion};
+pub use multibuf::MultiBuf;
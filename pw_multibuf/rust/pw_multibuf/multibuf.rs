@@ -0,0 +1,395 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use core::cmp::min;
+
+use heapless::Deque;
+use pw_status::{Error, Result};
+use pw_stream::{Read, Write};
+
+use crate::Chunk;
+
+/// A buffer made up of a sequence of up to `N` [`Chunk`]s.
+///
+/// `MultiBuf` allows building up packets from non-contiguous memory without
+/// copying.  Protocol layers can add headers with
+/// [`prepend()`](MultiBuf::prepend) or strip them with
+/// [`discard_prefix()`](MultiBuf::discard_prefix) as the buffer moves through
+/// the stack.
+///
+/// `MultiBuf` implements [`pw_stream::Read`], which consumes bytes from the
+/// front of the buffer, and [`pw_stream::Write`], which appends bytes by
+/// growing the last chunk into its region's unused memory.
+pub struct MultiBuf<'r, const N: usize> {
+    chunks: Deque<Chunk<'r>, N>,
+}
+
+impl<'r, const N: usize> MultiBuf<'r, N> {
+    /// Create an empty `MultiBuf`.
+    pub const fn new() -> Self {
+        Self {
+            chunks: Deque::new(),
+        }
+    }
+
+    /// Create a `MultiBuf` containing a single chunk.
+    pub fn from_chunk(chunk: Chunk<'r>) -> Self {
+        let mut buf = Self::new();
+        if buf.push_back_chunk(chunk).is_err() {
+            unreachable!("Pushing to an empty MultiBuf only fails when N is 0");
+        }
+        buf
+    }
+
+    /// Returns the total number of bytes in all chunks.
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.len()).sum()
+    }
+
+    /// Returns true if the `MultiBuf` contains no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.iter().all(|chunk| chunk.is_empty())
+    }
+
+    /// Returns the number of chunks in the `MultiBuf`.
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Adds `chunk` to the front of the `MultiBuf`.
+    ///
+    /// Returns `chunk` back if the `MultiBuf` already holds `N` chunks.
+    pub fn push_front_chunk(&mut self, chunk: Chunk<'r>) -> core::result::Result<(), Chunk<'r>> {
+        self.chunks.push_front(chunk)
+    }
+
+    /// Adds `chunk` to the back of the `MultiBuf`.
+    ///
+    /// Returns `chunk` back if the `MultiBuf` already holds `N` chunks.
+    pub fn push_back_chunk(&mut self, chunk: Chunk<'r>) -> core::result::Result<(), Chunk<'r>> {
+        self.chunks.push_back(chunk)
+    }
+
+    /// Removes and returns the first chunk of the `MultiBuf`.
+    pub fn take_front_chunk(&mut self) -> Option<Chunk<'r>> {
+        self.chunks.pop_front()
+    }
+
+    /// Removes and returns the last chunk of the `MultiBuf`.
+    pub fn take_back_chunk(&mut self) -> Option<Chunk<'r>> {
+        self.chunks.pop_back()
+    }
+
+    /// Returns an iterator over the chunks of the `MultiBuf`.
+    pub fn chunks(&self) -> impl Iterator<Item = &Chunk<'r>> {
+        self.chunks.iter()
+    }
+
+    /// Returns an iterator over mutable references to the chunks of the
+    /// `MultiBuf`.
+    pub fn chunks_mut(&mut self) -> impl Iterator<Item = &mut Chunk<'r>> {
+        self.chunks.iter_mut()
+    }
+
+    /// Returns an iterator over the bytes of the `MultiBuf`.
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        self.chunks.iter().flat_map(|chunk| chunk.iter().copied())
+    }
+
+    /// Grows the first chunk to include `len` bytes preceding it.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The `MultiBuf` has no chunks.
+    /// - [`Error::ResourceExhausted`] - The first chunk's region does not have
+    ///   `len` unused bytes before it.
+    pub fn claim_prefix(&mut self, len: usize) -> Result<()> {
+        self.chunks
+            .front_mut()
+            .ok_or(Error::FailedPrecondition)?
+            .claim_prefix(len)
+    }
+
+    /// Grows the last chunk to include `len` bytes following it.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The `MultiBuf` has no chunks.
+    /// - [`Error::ResourceExhausted`] - The last chunk's region does not have
+    ///   `len` unused bytes after it.
+    pub fn claim_suffix(&mut self, len: usize) -> Result<()> {
+        self.chunks
+            .back_mut()
+            .ok_or(Error::FailedPrecondition)?
+            .claim_suffix(len)
+    }
+
+    /// Adds `header` to the front of the `MultiBuf` by claiming memory
+    /// preceding the first chunk.
+    ///
+    /// # Errors
+    /// See [`claim_prefix()`](MultiBuf::claim_prefix).
+    pub fn prepend(&mut self, header: &[u8]) -> Result<()> {
+        self.claim_prefix(header.len())?;
+        if let Some(chunk) = self.chunks.front_mut() {
+            chunk[..header.len()].copy_from_slice(header);
+        }
+        Ok(())
+    }
+
+    /// Removes the first `len` bytes of the `MultiBuf`.
+    ///
+    /// Chunks which become empty are dropped, releasing their memory.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - `len` is larger than the `MultiBuf`.
+    pub fn discard_prefix(&mut self, mut len: usize) -> Result<()> {
+        if len > self.len() {
+            return Err(Error::OutOfRange);
+        }
+        while let Some(chunk) = self.chunks.front_mut() {
+            if len < chunk.len() {
+                return chunk.discard_prefix(len);
+            }
+            len -= chunk.len();
+            self.chunks.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Shrinks the `MultiBuf` to its first `len` bytes.
+    ///
+    /// Chunks which become empty are dropped, releasing their memory.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - `len` is larger than the `MultiBuf`.
+    pub fn truncate(&mut self, len: usize) -> Result<()> {
+        let mut excess = self.len().checked_sub(len).ok_or(Error::OutOfRange)?;
+        while let Some(chunk) = self.chunks.back_mut() {
+            if excess < chunk.len() {
+                let new_len = chunk.len() - excess;
+                return chunk.truncate(new_len);
+            }
+            excess -= chunk.len();
+            self.chunks.pop_back();
+        }
+        Ok(())
+    }
+
+    /// Copies the contents of the `MultiBuf` into `buf`.
+    ///
+    /// Returns the number of bytes copied which is the smaller of the length
+    /// of the `MultiBuf` and the length of `buf`.
+    pub fn copy_to(&self, buf: &mut [u8]) -> usize {
+        let mut copied = 0;
+        for chunk in self.chunks.iter() {
+            let len = min(chunk.len(), buf.len() - copied);
+            buf[copied..copied + len].copy_from_slice(&chunk[..len]);
+            copied += len;
+            if copied == buf.len() {
+                break;
+            }
+        }
+        copied
+    }
+}
+
+impl<const N: usize> Default for MultiBuf<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Read for MultiBuf<'_, N> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.copy_to(buf);
+        self.discard_prefix(len)?;
+        Ok(len)
+    }
+}
+
+impl<const N: usize> Write for MultiBuf<'_, N> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let Some(chunk) = self.chunks.back_mut() else {
+            return Err(Error::OutOfRange);
+        };
+        let len = min(buf.len(), chunk.available_suffix());
+        if len == 0 {
+            return Err(Error::OutOfRange);
+        }
+
+        let start = chunk.len();
+        chunk.claim_suffix(len)?;
+        chunk[start..].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChunkRegion;
+
+    #[test]
+    fn empty_multibuf_has_no_data() {
+        let buf = MultiBuf::<4>::new();
+        assert_eq!(buf.len(), 0);
+        assert!(buf.is_empty());
+        assert_eq!(buf.chunk_count(), 0);
+    }
+
+    #[test]
+    fn len_spans_chunks() {
+        let mut buffer = [0u8; 16];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut back = region.first_chunk().unwrap();
+        let front = back.take_prefix(6).unwrap();
+
+        let mut buf = MultiBuf::<4>::from_chunk(back);
+        buf.push_front_chunk(front).ok().unwrap();
+        assert_eq!(buf.len(), 16);
+        assert_eq!(buf.chunk_count(), 2);
+    }
+
+    #[test]
+    fn push_fails_when_full() {
+        let mut buffer = [0u8; 16];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut chunk = region.first_chunk().unwrap();
+        let extra = chunk.take_prefix(4).unwrap();
+
+        let mut buf = MultiBuf::<1>::from_chunk(chunk);
+        let extra = buf.push_back_chunk(extra).unwrap_err();
+        assert_eq!(extra.len(), 4);
+    }
+
+    #[test]
+    fn iter_and_copy_to_span_chunks() {
+        let mut buffer = [0u8, 1, 2, 3, 4, 5, 6, 7];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut back = region.first_chunk().unwrap();
+        let front = back.take_prefix(3).unwrap();
+
+        let mut buf = MultiBuf::<4>::from_chunk(front);
+        buf.push_back_chunk(back).ok().unwrap();
+        assert!(buf.iter().eq(0..8));
+
+        let mut out = [0u8; 5];
+        assert_eq!(buf.copy_to(&mut out), 5);
+        assert_eq!(out, [0, 1, 2, 3, 4]);
+
+        let mut out = [0u8; 10];
+        assert_eq!(buf.copy_to(&mut out), 8);
+        assert_eq!(&out[..8], &[0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn prepend_claims_reserved_space() {
+        let mut buffer = [0u8; 16];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut chunk = region.first_chunk().unwrap();
+        chunk.discard_prefix(4).unwrap();
+        chunk.truncate(2).unwrap();
+        chunk.copy_from_slice(b"hi");
+
+        let mut buf = MultiBuf::<4>::from_chunk(chunk);
+        assert_eq!(buf.prepend(b"abc"), Ok(()));
+        assert!(buf.iter().eq(b"abchi".iter().copied()));
+        assert_eq!(buf.prepend(b"de"), Err(Error::ResourceExhausted));
+        assert_eq!(buf.len(), 5);
+    }
+
+    #[test]
+    fn prepend_to_empty_multibuf_fails() {
+        let mut buf = MultiBuf::<4>::new();
+        assert_eq!(buf.prepend(b"a"), Err(Error::FailedPrecondition));
+    }
+
+    #[test]
+    fn discard_prefix_drops_empty_chunks() {
+        let mut buffer = [0u8, 1, 2, 3, 4, 5, 6, 7];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut back = region.first_chunk().unwrap();
+        let front = back.take_prefix(3).unwrap();
+
+        let mut buf = MultiBuf::<4>::from_chunk(front);
+        buf.push_back_chunk(back).ok().unwrap();
+        assert_eq!(buf.discard_prefix(9), Err(Error::OutOfRange));
+        assert_eq!(buf.discard_prefix(4), Ok(()));
+        assert_eq!(buf.chunk_count(), 1);
+        assert!(buf.iter().eq(4..8));
+        assert_eq!(region.chunk_count(), 1);
+    }
+
+    #[test]
+    fn truncate_drops_empty_chunks() {
+        let mut buffer = [0u8, 1, 2, 3, 4, 5, 6, 7];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut back = region.first_chunk().unwrap();
+        let front = back.take_prefix(3).unwrap();
+
+        let mut buf = MultiBuf::<4>::from_chunk(front);
+        buf.push_back_chunk(back).ok().unwrap();
+        assert_eq!(buf.truncate(9), Err(Error::OutOfRange));
+        assert_eq!(buf.truncate(2), Ok(()));
+        assert_eq!(buf.chunk_count(), 1);
+        assert!(buf.iter().eq(0..2));
+    }
+
+    #[test]
+    fn read_consumes_from_front() {
+        let mut buffer = [0u8, 1, 2, 3, 4, 5, 6, 7];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut back = region.first_chunk().unwrap();
+        let front = back.take_prefix(3).unwrap();
+
+        let mut buf = MultiBuf::<4>::from_chunk(front);
+        buf.push_back_chunk(back).ok().unwrap();
+
+        let mut out = [0u8; 5];
+        assert_eq!(buf.read(&mut out), Ok(5));
+        assert_eq!(out, [0, 1, 2, 3, 4]);
+        assert_eq!(buf.read(&mut out), Ok(3));
+        assert_eq!(&out[..3], &[5, 6, 7]);
+        assert_eq!(buf.read(&mut out), Ok(0));
+        assert_eq!(region.chunk_count(), 0);
+    }
+
+    #[test]
+    fn write_appends_to_back_chunk() {
+        let mut buffer = [0u8; 8];
+        let region = ChunkRegion::<4>::new(&mut buffer);
+        let mut chunk = region.first_chunk().unwrap();
+        chunk.truncate(0).unwrap();
+
+        let mut buf = MultiBuf::<4>::from_chunk(chunk);
+        assert_eq!(buf.write(b"hello"), Ok(5));
+        assert_eq!(buf.write(b"world"), Ok(3));
+        assert_eq!(buf.write(b"!"), Err(Error::OutOfRange));
+        assert_eq!(buf.write(b""), Ok(0));
+        assert!(buf.iter().eq(b"hellowor".iter().copied()));
+    }
+
+    #[test]
+    fn write_to_empty_multibuf_fails() {
+        let mut buf = MultiBuf::<4>::new();
+        assert_eq!(buf.write(b"a"), Err(Error::OutOfRange));
+    }
+}
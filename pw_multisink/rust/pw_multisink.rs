@@ -0,0 +1,861 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_multisink` is a buffer of entries which any number of readers can
+//! consume independently.
+//!
+//! This is the Rust counterpart of the C++ `pw_multisink` module.  Producers,
+//! such as a log backend or `pw_trace_tokenized`, push encoded entries into a
+//! [`MultiSink`] and each consumer, such as a UART, an RPC stream, or a crash
+//! dump, reads them through its own [`Drain`].  When the sink is full the
+//! oldest entries are overwritten.  Each drain reports how many entries it
+//! missed, either because they were overwritten before it read them or
+//! because they were never added to the sink.
+//!
+//! Entries pushed with their log metadata can be filtered per drain with a
+//! [`Filter`], so that, for example, a UART only reads INFO and higher
+//! messages while a crash buffer reads every message.
+//!
+//! ```
+//! use pw_multisink::MultiSink;
+//!
+//! let sink = MultiSink::<64>::new();
+//! let mut uart = sink.attach_drain();
+//! let mut rpc = sink.attach_drain();
+//!
+//! sink.push_entry(b"Hello").unwrap();
+//!
+//! let mut buffer = [0u8; 16];
+//! let (entry, drops) = uart.pop_entry(&mut buffer);
+//! assert_eq!(entry.unwrap().entry(), b"Hello");
+//! assert_eq!(drops.drain, 0);
+//!
+//! // Each drain reads every entry.
+//! let (entry, _) = rpc.pop_entry(&mut buffer);
+//! assert_eq!(entry.unwrap().entry(), b"Hello");
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use pw_status::{Error, Result};
+use pw_stream::{HdlcWriter, Write};
+
+pub use pw_stream::hdlc::DEFAULT_LOG_ADDRESS;
+
+// Each entry is stored with a little endian `u16` length prefix.  If the
+// prefix's high bit is set, the entry's little endian `u32` metadata follows
+// it.
+const ENTRY_HEADER_SIZE: usize = 2;
+const HAS_METADATA: u16 = 0x8000;
+const METADATA_SIZE: usize = 4;
+
+/// The largest entry that can be stored in a [`MultiSink`].
+pub const MAX_ENTRY_SIZE_BYTES: usize = 0x7fff;
+
+// Location and metadata of an entry in a sink's buffer.
+struct StoredEntry {
+    len: usize,
+    metadata: Option<u32>,
+    data_offset: usize,
+    next_offset: usize,
+    // Number of bytes used by the entry, its header, and its metadata.
+    size: usize,
+}
+
+struct Inner<const N: usize> {
+    data: [u8; N],
+    // Offset of the oldest entry's header.
+    head: usize,
+    // Number of bytes used by entries and their headers.
+    used: usize,
+    // Sequence ID of the oldest entry in the buffer.
+    oldest_sequence: u32,
+    // Sequence ID of the next entry to be pushed.
+    next_sequence: u32,
+    ingress_drops: u32,
+}
+
+impl<const N: usize> Inner<N> {
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        let first = buf.len().min(N - offset);
+        let (start, end) = buf.split_at_mut(first);
+        start.copy_from_slice(&self.data[offset..offset + first]);
+        end.copy_from_slice(&self.data[..end.len()]);
+    }
+
+    fn write(&mut self, offset: usize, buf: &[u8]) {
+        let first = buf.len().min(N - offset);
+        self.data[offset..offset + first].copy_from_slice(&buf[..first]);
+        self.data[..buf.len() - first].copy_from_slice(&buf[first..]);
+    }
+
+    fn entry(&self, offset: usize) -> StoredEntry {
+        let mut header = [0u8; ENTRY_HEADER_SIZE];
+        self.read(offset, &mut header);
+        let header = u16::from_le_bytes(header);
+        let mut data_offset = self.advance(offset, ENTRY_HEADER_SIZE);
+        let mut size = ENTRY_HEADER_SIZE;
+        let metadata = if header & HAS_METADATA != 0 {
+            let mut metadata = [0u8; METADATA_SIZE];
+            self.read(data_offset, &mut metadata);
+            data_offset = self.advance(data_offset, METADATA_SIZE);
+            size += METADATA_SIZE;
+            Some(u32::from_le_bytes(metadata))
+        } else {
+            None
+        };
+        let len = usize::from(header & !HAS_METADATA);
+        StoredEntry {
+            len,
+            metadata,
+            data_offset,
+            next_offset: self.advance(data_offset, len),
+            size: size + len,
+        }
+    }
+
+    fn advance(&self, offset: usize, len: usize) -> usize {
+        (offset + len) % N
+    }
+
+    fn pop_oldest(&mut self) {
+        let size = self.entry(self.head).size;
+        self.head = self.advance(self.head, size);
+        self.used -= size;
+        self.oldest_sequence = self.oldest_sequence.wrapping_add(1);
+    }
+}
+
+/// Drop counts reported by a [`Drain`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DropCounts {
+    /// Number of entries the drain missed because they were overwritten
+    /// before it read them or were too large for the read buffer.
+    pub drain: u32,
+
+    /// Number of entries which were never added to the sink.
+    pub ingress: u32,
+}
+
+/// A ring buffer of entries which is read through [`Drain`]s.
+///
+/// The sink holds `N` bytes.  Each entry uses two bytes in addition to its
+/// data, and four more if it is pushed with metadata.
+pub struct MultiSink<const N: usize> {
+    inner: Mutex<RefCell<Inner<N>>>,
+}
+
+impl<const N: usize> MultiSink<N> {
+    /// Create a new, empty `MultiSink`.
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                data: [0; N],
+                head: 0,
+                used: 0,
+                oldest_sequence: 0,
+                next_sequence: 0,
+                ingress_drops: 0,
+            })),
+        }
+    }
+
+    /// Adds an entry to the sink, overwriting the oldest entries if there is
+    /// not enough space.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - The entry is larger than the sink or
+    ///   [`MAX_ENTRY_SIZE_BYTES`].  It is counted as an ingress drop.
+    pub fn push_entry(&self, entry: &[u8]) -> Result<()> {
+        self.push(None, entry)
+    }
+
+    /// Adds an entry along with its packed log metadata, which drains
+    /// compare to their [`Filter`] without decoding the entry.  The metadata
+    /// uses four bytes in addition to the entry's data.
+    ///
+    /// Errors are the same as [`MultiSink::push_entry()`].
+    pub fn push_entry_with_metadata(&self, metadata: u32, entry: &[u8]) -> Result<()> {
+        self.push(Some(metadata), entry)
+    }
+
+    fn push(&self, metadata: Option<u32>, entry: &[u8]) -> Result<()> {
+        pw_interrupt::free(|cs| {
+            let mut inner = self.inner.borrow_ref_mut(cs);
+            let metadata_size = if metadata.is_some() { METADATA_SIZE } else { 0 };
+            let size = ENTRY_HEADER_SIZE + metadata_size + entry.len();
+            if size > N || entry.len() > MAX_ENTRY_SIZE_BYTES {
+                inner.ingress_drops = inner.ingress_drops.wrapping_add(1);
+                return Err(Error::ResourceExhausted);
+            }
+
+            while N - inner.used < size {
+                inner.pop_oldest();
+            }
+
+            let tail = inner.advance(inner.head, inner.used);
+            let mut header = entry.len() as u16;
+            if metadata.is_some() {
+                header |= HAS_METADATA;
+            }
+            inner.write(tail, &header.to_le_bytes());
+            let mut data_offset = inner.advance(tail, ENTRY_HEADER_SIZE);
+            if let Some(metadata) = metadata {
+                inner.write(data_offset, &metadata.to_le_bytes());
+                data_offset = inner.advance(data_offset, METADATA_SIZE);
+            }
+            inner.write(data_offset, entry);
+            inner.used += size;
+            inner.next_sequence = inner.next_sequence.wrapping_add(1);
+            Ok(())
+        })
+    }
+
+    /// Records that `count` entries were dropped before being pushed to the
+    /// sink, such as when a log message fails to encode.  Drains report these
+    /// as ingress drops.
+    pub fn handle_dropped(&self, count: u32) {
+        pw_interrupt::free(|cs| {
+            let mut inner = self.inner.borrow_ref_mut(cs);
+            inner.ingress_drops = inner.ingress_drops.wrapping_add(count);
+        })
+    }
+
+    /// Copies each of the newest `max_entries` entries into `buffer`, oldest
+    /// first, and passes them to `callback`.
+    ///
+    /// This reads the sink without a [`Drain`], so it does not affect what
+    /// drains read or the drops they report.  It is intended for dumping
+    /// recent entries in a crash handler or a shell command.  `callback` runs
+    /// within a critical section, so it should only copy or encode the entry.
+    ///
+    /// Returns the number of entries which were skipped because they did not
+    /// fit in `buffer`.
+    pub fn for_each_entry(
+        &self,
+        max_entries: usize,
+        buffer: &mut [u8],
+        mut callback: impl FnMut(PeekedEntry<'_>),
+    ) -> usize {
+        pw_interrupt::free(|cs| {
+            let inner = self.inner.borrow_ref(cs);
+            let stored = inner.next_sequence.wrapping_sub(inner.oldest_sequence) as usize;
+            let mut skipped = 0;
+            let mut offset = inner.head;
+            for i in 0..stored {
+                let stored_entry = inner.entry(offset);
+                if stored - i <= max_entries {
+                    if let Some(entry) = buffer.get_mut(..stored_entry.len) {
+                        inner.read(stored_entry.data_offset, entry);
+                        callback(PeekedEntry {
+                            entry,
+                            metadata: stored_entry.metadata,
+                            sequence_id: inner.oldest_sequence.wrapping_add(i as u32),
+                            next_offset: stored_entry.next_offset,
+                        });
+                    } else {
+                        skipped += 1;
+                    }
+                }
+                offset = stored_entry.next_offset;
+            }
+            skipped
+        })
+    }
+
+    /// Attaches a new [`Drain`] which starts reading at the oldest entry in
+    /// the sink.
+    pub fn attach_drain(&self) -> Drain<'_, N> {
+        pw_interrupt::free(|cs| {
+            let inner = self.inner.borrow_ref(cs);
+            Drain {
+                sink: self,
+                sequence: inner.oldest_sequence,
+                offset: inner.head,
+                ingress_drops: inner.ingress_drops,
+                filter: Filter::ALL,
+            }
+        })
+    }
+}
+
+impl<const N: usize> Default for MultiSink<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An entry read from a [`Drain`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct PeekedEntry<'a> {
+    entry: &'a [u8],
+    metadata: Option<u32>,
+    sequence_id: u32,
+    next_offset: usize,
+}
+
+impl<'a> PeekedEntry<'a> {
+    /// Returns the entry's data.
+    pub fn entry(&self) -> &'a [u8] {
+        self.entry
+    }
+
+    /// Returns the metadata the entry was pushed with, if any.
+    pub fn metadata(&self) -> Option<u32> {
+        self.metadata
+    }
+
+    /// Returns the entry's sequence ID.  Sequence IDs increase by one for each
+    /// entry pushed to the sink.
+    pub fn sequence_id(&self) -> u32 {
+        self.sequence_id
+    }
+}
+
+/// Selects the entries a [`Drain`] reads by the metadata they were pushed with.
+///
+/// Metadata is compared in the default packed layout of `pw_log_tokenized`,
+/// which the `pw_log` tokenized backend passes to
+/// `pw_log_tokenized_HandleLog()`: the level in bits 0-2, the flags in bits
+/// 14-15, and the module token in bits 16-31.  Projects which log to several
+/// domains, such as application and security logs, mark them with flags.
+///
+/// ```
+/// use pw_multisink::{Filter, MultiSink};
+///
+/// const INFO: u32 = 2;
+/// const DEBUG: u32 = 1;
+///
+/// let sink = MultiSink::<64>::new();
+/// let mut uart = sink.attach_drain();
+/// uart.set_filter(Filter::min_level(INFO));
+/// // The crash buffer keeps every entry.
+/// let mut crash_buffer = sink.attach_drain();
+///
+/// sink.push_entry_with_metadata(DEBUG, b"debug").unwrap();
+/// sink.push_entry_with_metadata(INFO, b"info").unwrap();
+///
+/// let mut buffer = [0u8; 16];
+/// assert_eq!(uart.pop_entry(&mut buffer).0.unwrap().entry(), b"info");
+/// assert_eq!(crash_buffer.pop_entry(&mut buffer).0.unwrap().entry(), b"debug");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Filter {
+    min_level: u32,
+    module: Option<u32>,
+    any_flags: u32,
+}
+
+impl Filter {
+    const LEVEL_MASK: u32 = 0x7;
+    const FLAG_SHIFT: u32 = 14;
+    const FLAG_MASK: u32 = 0x3;
+    const MODULE_SHIFT: u32 = 16;
+
+    /// A filter which matches every entry.
+    pub const ALL: Self = Self {
+        min_level: 0,
+        module: None,
+        any_flags: 0,
+    };
+
+    /// Returns a filter which matches entries with a level of at least
+    /// `level`.
+    pub const fn min_level(level: u32) -> Self {
+        Self {
+            min_level: level,
+            ..Self::ALL
+        }
+    }
+
+    /// Returns a copy of the filter which only matches entries from the
+    /// module with the 16 bit token `module`.
+    pub const fn with_module(self, module: u32) -> Self {
+        Self {
+            module: Some(module),
+            ..self
+        }
+    }
+
+    /// Returns a copy of the filter which only matches entries with any of
+    /// `flags` set.
+    pub const fn with_any_flags(self, flags: u32) -> Self {
+        Self {
+            any_flags: flags,
+            ..self
+        }
+    }
+
+    /// Returns `true` if an entry with the packed `metadata` matches the
+    /// filter.
+    pub const fn matches(&self, metadata: u32) -> bool {
+        let level = metadata & Self::LEVEL_MASK;
+        let flags = (metadata >> Self::FLAG_SHIFT) & Self::FLAG_MASK;
+        let module = metadata >> Self::MODULE_SHIFT;
+        level >= self.min_level
+            && (self.any_flags == 0 || flags & self.any_flags != 0)
+            && match self.module {
+                Some(token) => module == token,
+                None => true,
+            }
+    }
+}
+
+/// An independent reader of the entries in a [`MultiSink`].
+///
+/// Drains do not prevent entries from being overwritten.  Drop counts are
+/// 32 bits so a drain must be read at least once every `u32::MAX` entries
+/// for them to be accurate.
+pub struct Drain<'a, const N: usize> {
+    sink: &'a MultiSink<N>,
+    // Sequence ID and offset of the next entry to read.
+    sequence: u32,
+    offset: usize,
+    // Ingress drop count of the sink when drops were last reported.
+    ingress_drops: u32,
+    filter: Filter,
+}
+
+impl<'a, const N: usize> Drain<'a, N> {
+    /// Sets the filter which decides which entries the drain reads.
+    ///
+    /// Entries pushed with metadata which `filter` rejects are skipped
+    /// without being counted as drops.  Entries pushed without metadata are
+    /// always read.
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    /// Returns the drain's filter.
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// Copies the next entry into `buffer` without removing it from the
+    /// drain.  Pass the entry to [`Drain::pop_peeked()`] once it has been
+    /// handled.
+    ///
+    /// Drops since the last call to `peek_entry()` or `pop_entry()` are
+    /// returned regardless of the result.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - No entries are available.
+    /// - [`Error::ResourceExhausted`] - The next entry did not fit in
+    ///   `buffer`.  It is skipped and counted as a drain drop.
+    pub fn peek_entry<'b>(
+        &mut self,
+        buffer: &'b mut [u8],
+    ) -> (Result<PeekedEntry<'b>>, DropCounts) {
+        pw_interrupt::free(|cs| {
+            let inner = self.sink.inner.borrow_ref(cs);
+            let mut drops = DropCounts {
+                drain: 0,
+                ingress: inner.ingress_drops.wrapping_sub(self.ingress_drops),
+            };
+            self.ingress_drops = inner.ingress_drops;
+
+            // Entries which were overwritten since the last read are dropped.
+            let available = inner.next_sequence.wrapping_sub(self.sequence);
+            let stored = inner.next_sequence.wrapping_sub(inner.oldest_sequence);
+            if available > stored {
+                drops.drain = available - stored;
+                self.sequence = inner.oldest_sequence;
+                self.offset = inner.head;
+            }
+
+            // Entries which the filter rejects are skipped.
+            let stored_entry = loop {
+                if self.sequence == inner.next_sequence {
+                    return (Err(Error::OutOfRange), drops);
+                }
+                let stored_entry = inner.entry(self.offset);
+                match stored_entry.metadata {
+                    Some(metadata) if !self.filter.matches(metadata) => {
+                        self.sequence = self.sequence.wrapping_add(1);
+                        self.offset = stored_entry.next_offset;
+                    }
+                    _ => break stored_entry,
+                }
+            };
+
+            if stored_entry.len > buffer.len() {
+                self.sequence = self.sequence.wrapping_add(1);
+                self.offset = stored_entry.next_offset;
+                drops.drain += 1;
+                return (Err(Error::ResourceExhausted), drops);
+            }
+
+            let entry = &mut buffer[..stored_entry.len];
+            inner.read(stored_entry.data_offset, entry);
+            let peeked = PeekedEntry {
+                entry,
+                metadata: stored_entry.metadata,
+                sequence_id: self.sequence,
+                next_offset: stored_entry.next_offset,
+            };
+            (Ok(peeked), drops)
+        })
+    }
+
+    /// Removes an entry returned by [`Drain::peek_entry()`] from the drain.
+    ///
+    /// Has no effect if the drain has moved past the entry since it was
+    /// peeked.
+    pub fn pop_peeked(&mut self, peeked: &PeekedEntry) {
+        if peeked.sequence_id == self.sequence {
+            self.sequence = self.sequence.wrapping_add(1);
+            self.offset = peeked.next_offset;
+        }
+    }
+
+    /// Copies the next entry into `buffer` and removes it from the drain.
+    ///
+    /// Equivalent to [`Drain::peek_entry()`] followed by
+    /// [`Drain::pop_peeked()`].
+    pub fn pop_entry<'b>(&mut self, buffer: &'b mut [u8]) -> (Result<PeekedEntry<'b>>, DropCounts) {
+        let (result, drops) = self.peek_entry(buffer);
+        if let Ok(peeked) = &result {
+            self.pop_peeked(peeked);
+        }
+        (result, drops)
+    }
+}
+
+/// Writes the entries read from a [`Drain`] to a stream as HDLC unnumbered
+/// information frames.
+///
+/// This sends encoded log entries over a UART alongside other HDLC traffic,
+/// such as RPC packets on a different address, as `pw_system` does in C++.
+///
+/// ```
+/// use pw_multisink::{HdlcDrain, MultiSink, DEFAULT_LOG_ADDRESS};
+/// use pw_stream::Cursor;
+///
+/// let sink = MultiSink::<64>::new();
+/// let mut uart = HdlcDrain::new(sink.attach_drain(), Cursor::new([0u8; 32]), DEFAULT_LOG_ADDRESS);
+///
+/// sink.push_entry(b"log").unwrap();
+///
+/// let mut buffer = [0u8; 16];
+/// let (written, drops) = uart.flush(&mut buffer);
+/// assert_eq!(written, Ok(1));
+/// assert_eq!(drops.drain, 0);
+/// ```
+pub struct HdlcDrain<'a, W: Write, const N: usize> {
+    drain: Drain<'a, N>,
+    writer: HdlcWriter<W>,
+    address: u64,
+}
+
+impl<'a, W: Write, const N: usize> HdlcDrain<'a, W, N> {
+    /// Creates a drain which writes the entries read from `drain` to `writer`
+    /// in frames addressed to `address`.
+    pub fn new(drain: Drain<'a, N>, writer: W, address: u64) -> Self {
+        Self {
+            drain,
+            writer: HdlcWriter::new(writer),
+            address,
+        }
+    }
+
+    /// Writes each available entry as a frame, reading entries into
+    /// `buffer`.
+    ///
+    /// Returns the number of entries written and the drops reported by the
+    /// drain, so the caller can report them.  Entries larger than `buffer`
+    /// are counted as drain drops.
+    ///
+    /// # Errors
+    /// Errors from the writer are returned.  The entry which failed to be
+    /// written is written again by the next call to `flush()`.
+    pub fn flush(&mut self, buffer: &mut [u8]) -> (Result<usize>, DropCounts) {
+        let mut written = 0;
+        let mut drops = DropCounts::default();
+        loop {
+            let (result, new_drops) = self.drain.peek_entry(buffer);
+            drops.drain = drops.drain.wrapping_add(new_drops.drain);
+            drops.ingress = drops.ingress.wrapping_add(new_drops.ingress);
+            match result {
+                Ok(entry) => {
+                    if let Err(e) = self.writer.write_ui_frame(self.address, entry.entry()) {
+                        return (Err(e), drops);
+                    }
+                    self.drain.pop_peeked(&entry);
+                    written += 1;
+                }
+                // The entry was skipped and counted as a drop.
+                Err(Error::ResourceExhausted) => {}
+                Err(Error::OutOfRange) => return (Ok(written), drops),
+                Err(e) => return (Err(e), drops),
+            }
+        }
+    }
+
+    /// Consumes the `HdlcDrain` and returns its drain and writer.
+    pub fn into_inner(self) -> (Drain<'a, N>, W) {
+        (self.drain, self.writer.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drain_reads_entries_in_order() {
+        let sink = MultiSink::<32>::new();
+        let mut drain = sink.attach_drain();
+        let mut buffer = [0u8; 8];
+
+        sink.push_entry(b"one").unwrap();
+        sink.push_entry(b"two").unwrap();
+
+        let (entry, drops) = drain.pop_entry(&mut buffer);
+        let entry = entry.unwrap();
+        assert_eq!((entry.entry(), entry.sequence_id()), (&b"one"[..], 0));
+        assert_eq!(drops, DropCounts::default());
+
+        let (entry, _) = drain.pop_entry(&mut buffer);
+        assert_eq!(entry.unwrap().sequence_id(), 1);
+
+        let (entry, drops) = drain.pop_entry(&mut buffer);
+        assert_eq!(entry, Err(Error::OutOfRange));
+        assert_eq!(drops, DropCounts::default());
+    }
+
+    #[test]
+    fn drains_read_independently() {
+        let sink = MultiSink::<32>::new();
+        let mut fast = sink.attach_drain();
+        let mut slow = sink.attach_drain();
+        let mut buffer = [0u8; 8];
+
+        sink.push_entry(b"a").unwrap();
+        assert_eq!(fast.pop_entry(&mut buffer).0.unwrap().entry(), b"a");
+        sink.push_entry(b"b").unwrap();
+        assert_eq!(fast.pop_entry(&mut buffer).0.unwrap().entry(), b"b");
+
+        assert_eq!(slow.pop_entry(&mut buffer).0.unwrap().entry(), b"a");
+        assert_eq!(slow.pop_entry(&mut buffer).0.unwrap().entry(), b"b");
+    }
+
+    #[test]
+    fn overwritten_entries_are_counted_per_drain() {
+        // Room for three 6 byte entries.
+        let sink = MultiSink::<20>::new();
+        let mut fast = sink.attach_drain();
+        let mut slow = sink.attach_drain();
+        let mut buffer = [0u8; 8];
+
+        for i in 0..5u8 {
+            sink.push_entry(&[i; 4]).unwrap();
+            let (entry, drops) = fast.pop_entry(&mut buffer);
+            assert_eq!(entry.unwrap().entry(), &[i; 4]);
+            assert_eq!(drops.drain, 0);
+        }
+
+        let (entry, drops) = slow.pop_entry(&mut buffer);
+        let entry = entry.unwrap();
+        assert_eq!((entry.entry(), entry.sequence_id()), (&[2u8; 4][..], 2));
+        assert_eq!(drops.drain, 2);
+    }
+
+    #[test]
+    fn entries_wrap_around_buffer() {
+        let sink = MultiSink::<9>::new();
+        let mut drain = sink.attach_drain();
+        let mut buffer = [0u8; 8];
+
+        for entry in [&b"abcde"[..], b"fgh", b"ijklmn", b"op"] {
+            sink.push_entry(entry).unwrap();
+            assert_eq!(drain.pop_entry(&mut buffer).0.unwrap().entry(), entry);
+        }
+    }
+
+    #[test]
+    fn entry_too_large_for_buffer_is_dropped() {
+        let sink = MultiSink::<32>::new();
+        let mut drain = sink.attach_drain();
+        sink.push_entry(b"too long").unwrap();
+        sink.push_entry(b"ok").unwrap();
+
+        let mut buffer = [0u8; 4];
+        let (entry, drops) = drain.pop_entry(&mut buffer);
+        assert_eq!(entry, Err(Error::ResourceExhausted));
+        assert_eq!(drops.drain, 1);
+        assert_eq!(drain.pop_entry(&mut buffer).0.unwrap().entry(), b"ok");
+    }
+
+    #[test]
+    fn filter_matches_packed_metadata() {
+        // Level 2 entry from line 12 of module 0x1234 with flag 1 set.
+        let metadata = 0x1234 << 16 | 1 << 14 | 12 << 3 | 2;
+        assert!(Filter::ALL.matches(metadata));
+        assert!(Filter::min_level(2).matches(metadata));
+        assert!(!Filter::min_level(3).matches(metadata));
+        assert!(Filter::ALL.with_module(0x1234).matches(metadata));
+        assert!(!Filter::ALL.with_module(0x1235).matches(metadata));
+        assert!(Filter::ALL.with_any_flags(0x3).matches(metadata));
+        assert!(!Filter::ALL.with_any_flags(0x2).matches(metadata));
+    }
+
+    #[test]
+    fn filtered_entries_are_skipped_without_drops() {
+        // Entries wrap around the buffer in the second pass.
+        let sink = MultiSink::<30>::new();
+        let mut drain = sink.attach_drain();
+        drain.set_filter(Filter::min_level(2));
+        assert_eq!(drain.filter(), Filter::min_level(2));
+        let mut buffer = [0u8; 8];
+
+        for _ in 0..2 {
+            sink.push_entry_with_metadata(1, b"dbg").unwrap();
+            sink.push_entry_with_metadata(3, b"wrn").unwrap();
+            sink.push_entry(b"raw").unwrap();
+
+            let (entry, drops) = drain.pop_entry(&mut buffer);
+            let entry = entry.unwrap();
+            assert_eq!((entry.entry(), entry.metadata()), (&b"wrn"[..], Some(3)));
+            assert_eq!(drops, DropCounts::default());
+            let (entry, _) = drain.pop_entry(&mut buffer);
+            assert_eq!(entry.unwrap().metadata(), None);
+            assert_eq!(drain.pop_entry(&mut buffer).0, Err(Error::OutOfRange));
+        }
+    }
+
+    #[test]
+    fn entry_with_metadata_uses_four_more_bytes() {
+        let sink = MultiSink::<10>::new();
+        assert_eq!(
+            sink.push_entry_with_metadata(0, &[0; 5]),
+            Err(Error::ResourceExhausted)
+        );
+        sink.push_entry_with_metadata(7, &[0; 4]).unwrap();
+
+        let mut buffer = [0u8; 8];
+        let mut metadata = None;
+        sink.for_each_entry(1, &mut buffer, |entry| metadata = entry.metadata());
+        assert_eq!(metadata, Some(7));
+    }
+
+    #[test]
+    fn ingress_drops_are_reported_to_each_drain() {
+        let sink = MultiSink::<8>::new();
+        let mut first = sink.attach_drain();
+        let mut second = sink.attach_drain();
+        let mut buffer = [0u8; 8];
+
+        assert_eq!(sink.push_entry(&[0; 7]), Err(Error::ResourceExhausted));
+        sink.handle_dropped(2);
+
+        assert_eq!(first.pop_entry(&mut buffer).1.ingress, 3);
+        assert_eq!(first.pop_entry(&mut buffer).1.ingress, 0);
+        assert_eq!(second.pop_entry(&mut buffer).1.ingress, 3);
+    }
+
+    #[test]
+    fn for_each_entry_reads_newest_entries() {
+        extern crate std;
+        use std::vec::Vec;
+
+        let sink = MultiSink::<16>::new();
+        let mut drain = sink.attach_drain();
+        for entry in [&b"one"[..], b"two", b"three", b"four"] {
+            sink.push_entry(entry).unwrap();
+        }
+
+        let mut buffer = [0u8; 4];
+        let mut entries = Vec::new();
+        let skipped = sink.for_each_entry(usize::MAX, &mut buffer, |entry| {
+            entries.push((entry.entry().to_vec(), entry.sequence_id()));
+        });
+        assert_eq!(skipped, 1);
+        assert_eq!(entries, [(b"four".to_vec(), 3)]);
+
+        entries.clear();
+        let mut buffer = [0u8; 8];
+        sink.for_each_entry(1, &mut buffer, |entry| {
+            entries.push((entry.entry().to_vec(), 0))
+        });
+        assert_eq!(entries, [(b"four".to_vec(), 0)]);
+
+        // Drains are unaffected.
+        let (entry, drops) = drain.pop_entry(&mut buffer);
+        assert_eq!(entry.unwrap().entry(), b"three");
+        assert_eq!(drops.drain, 2);
+    }
+
+    #[test]
+    fn hdlc_drain_writes_ui_frames() {
+        use pw_checksum::Crc32Ieee;
+        use pw_stream::VecWriter;
+
+        let sink = MultiSink::<32>::new();
+        let mut drain = HdlcDrain::new(sink.attach_drain(), VecWriter::<64>::new(), 1);
+        sink.push_entry(b"a").unwrap();
+        sink.push_entry(&[0x7e; 20]).unwrap();
+        sink.push_entry(b"b").unwrap();
+
+        let mut buffer = [0u8; 8];
+        let (written, drops) = drain.flush(&mut buffer);
+        assert_eq!(written, Ok(2));
+        assert_eq!(drops.drain, 1);
+
+        let mut expected = VecWriter::<64>::new();
+        let mut hdlc = HdlcWriter::new(&mut expected);
+        for frame in [&[0x03, 0x03, b'a'], &[0x03, 0x03, b'b']] {
+            hdlc.write_all(frame).unwrap();
+            hdlc.write_all(&Crc32Ieee::calculate(frame).to_le_bytes())
+                .unwrap();
+            hdlc.finish().unwrap();
+        }
+        let (_, writer) = drain.into_inner();
+        assert_eq!(writer.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn hdlc_drain_retries_entry_after_writer_error() {
+        use pw_stream::VecWriter;
+
+        let sink = MultiSink::<32>::new();
+        let mut drain = HdlcDrain::new(sink.attach_drain(), VecWriter::<8>::new(), 1);
+        sink.push_entry(b"abcdef").unwrap();
+
+        let mut buffer = [0u8; 8];
+        let (written, _) = drain.flush(&mut buffer);
+        assert_eq!(written, Err(Error::OutOfRange));
+        let (mut drain, _) = drain.into_inner();
+        let (entry, _) = drain.pop_entry(&mut buffer);
+        assert_eq!(entry.unwrap().entry(), b"abcdef");
+    }
+
+    #[test]
+    fn peeked_entry_is_not_removed_until_popped() {
+        let sink = MultiSink::<32>::new();
+        let mut drain = sink.attach_drain();
+        let mut buffer = [0u8; 8];
+        sink.push_entry(b"entry").unwrap();
+
+        let sequence_id = drain.peek_entry(&mut buffer).0.unwrap().sequence_id();
+        let (peeked, _) = drain.peek_entry(&mut buffer);
+        let peeked = peeked.unwrap();
+        assert_eq!(peeked.sequence_id(), sequence_id);
+
+        drain.pop_peeked(&peeked);
+        assert_eq!(drain.peek_entry(&mut buffer).0, Err(Error::OutOfRange));
+    }
+}
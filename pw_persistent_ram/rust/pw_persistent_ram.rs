@@ -0,0 +1,630 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Containers for persistent RAM, which is not initialized by the hardware,
+//! bootloader(s), or runtime across warm reboots.
+//!
+//! Persistent RAM may decay or bit rot between reboots, and holds arbitrary
+//! data after a cold boot, so the containers in this crate check the
+//! integrity of their contents.  Each container is only considered to hold a
+//! value if its signature is intact and the CRC16-CCITT of its contents
+//! matches, as in the C++ `pw_persistent_ram` module.  Zeroed memory never
+//! holds a value.
+//!
+//! - [`Persistent`] holds a single value, such as a boot counter or the
+//!   reason for the last crash.
+//! - [`PersistentBuffer`] holds variable-length serialized data, such as
+//!   tokenized logs written by a crash handler.
+//!
+//! Containers are placed in a persistent linker section, `.noinit` by
+//! default, with [`persistent!`]:
+//!
+//! ```
+//! use pw_persistent_ram::{persistent, Persistent};
+//!
+//! persistent! {
+//!     static BOOT_COUNT: Persistent<u32>;
+//! }
+//!
+//! // Counts from 1 on the first boot after the memory is lost.
+//! let boot_count = BOOT_COUNT.update_or(0, |count| *count += 1);
+//! # assert_eq!(boot_count, 1);
+//! ```
+//!
+//! The linker script must keep the section from being initialized or
+//! clobbered at boot, for example:
+//!
+//! ```text
+//! .noinit (NOLOAD) : { *(.noinit .noinit.*) } > RAM
+//! ```
+//!
+//! Writes are made with volatile accesses within a critical section, so that
+//! containers may be shared between threads and interrupt handlers and are
+//! up to date in memory when the device resets.  A write interrupted by a
+//! reset loses the container's value, as containers are not double buffered.
+#![no_std]
+#![deny(missing_docs)]
+
+use core::cell::UnsafeCell;
+use core::mem::{size_of, MaybeUninit};
+
+use pw_checksum::{Checksum, Crc16Ccitt};
+use pw_status::{Error, Result};
+
+/// The signature which marks an initialized container.
+///
+/// Containers are also invalidated when the type or size of their contents
+/// changes, as the signature is combined with the size of the contents.
+pub const SIGNATURE: u32 = 0x5057_5052;
+
+// Computes the CRC16-CCITT of `len` bytes of persistent memory starting at
+// `data`, continuing from `crc`.
+//
+// # Safety
+// `data` must be valid for reads of `len` bytes.
+unsafe fn crc_of(crc: Crc16Ccitt, data: *const u8, len: usize) -> Crc16Ccitt {
+    let mut crc = crc;
+    for i in 0..len {
+        crc.update(&[data.add(i).read_volatile()]);
+    }
+    crc
+}
+
+// Returns the signature of a container whose contents are `size` bytes.
+const fn signature(size: usize) -> u32 {
+    SIGNATURE ^ (size as u32)
+}
+
+/// Declares statics in a persistent linker section.
+///
+/// Each static is a [`Persistent`] or [`PersistentBuffer`], which are placed
+/// in the `.noinit` section unless another section is given:
+///
+/// ```
+/// use pw_persistent_ram::{persistent, Persistent, PersistentBuffer};
+///
+/// #[derive(Clone, Copy)]
+/// struct CrashInfo {
+///     uptime_ms: u32,
+///     pc: u32,
+/// }
+///
+/// persistent! {
+///     static CRASH_INFO: Persistent<CrashInfo>;
+///     pub static CRASH_LOGS: PersistentBuffer<2048>;
+/// }
+///
+/// persistent! {
+///     section = ".persistent";
+///     static BOOT_REASON: Persistent<u8>;
+/// }
+/// ```
+///
+/// The initial values of the statics are only used if the section is
+/// initialized like `.data`, as on hosts, in which case they do not hold a
+/// value.
+#[macro_export]
+macro_rules! persistent {
+    (section = $section:literal; $($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty;)*) => {
+        $(
+            $(#[$attr])*
+            #[link_section = $section]
+            $vis static $name: $ty = <$ty>::new();
+        )*
+    };
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty;)*) => {
+        $crate::persistent! {
+            section = ".noinit";
+            $($(#[$attr])* $vis static $name: $ty;)*
+        }
+    };
+}
+
+/// A value in persistent RAM with integrity checking.
+///
+/// `Persistent` is the Rust counterpart of the C++
+/// `pw::persistent_ram::Persistent<T>`.  Values are read and written by
+/// copy, so that invalid contents are never interpreted as a `T`.
+#[repr(C)]
+pub struct Persistent<T: Copy> {
+    signature: UnsafeCell<u32>,
+    crc: UnsafeCell<u16>,
+    contents: UnsafeCell<MaybeUninit<T>>,
+}
+
+// SAFETY: All access to the contents is made within a critical section.
+unsafe impl<T: Copy + Send> Sync for Persistent<T> {}
+
+impl<T: Copy> Persistent<T> {
+    /// Creates a `Persistent` without a value.
+    ///
+    /// When placed in a persistent section, the container instead holds
+    /// whatever the memory held before the device reset.
+    pub const fn new() -> Self {
+        Self {
+            signature: UnsafeCell::new(0),
+            crc: UnsafeCell::new(0),
+            contents: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    fn contents_crc(&self) -> u16 {
+        // SAFETY: The contents are valid for reads of `size_of::<T>()` bytes.
+        unsafe {
+            crc_of(
+                Crc16Ccitt::new(),
+                self.contents.get().cast(),
+                size_of::<T>(),
+            )
+        }
+        .value()
+    }
+
+    fn is_valid(&self) -> bool {
+        // SAFETY: Called within a critical section.
+        unsafe {
+            self.signature.get().read_volatile() == signature(size_of::<T>())
+                && self.crc.get().read_volatile() == self.contents_crc()
+        }
+    }
+
+    fn read(&self) -> Option<T> {
+        if !self.is_valid() {
+            return None;
+        }
+        // SAFETY: Called within a critical section, and the contents were
+        // written as a `T` since their CRC matches.
+        Some(unsafe { self.contents.get().read_volatile().assume_init() })
+    }
+
+    fn write(&self, value: T) {
+        // SAFETY: Called within a critical section.  The signature is
+        // cleared first so that a write interrupted by a reset is not valid.
+        unsafe {
+            self.signature.get().write_volatile(0);
+            self.contents.get().write_volatile(MaybeUninit::new(value));
+            self.crc.get().write_volatile(self.contents_crc());
+            self.signature
+                .get()
+                .write_volatile(signature(size_of::<T>()));
+        }
+    }
+
+    /// Returns whether the container holds a value.
+    pub fn has_value(&self) -> bool {
+        critical_section::with(|_| self.is_valid())
+    }
+
+    /// Returns the value, or `None` if the container does not hold one.
+    pub fn get(&self) -> Option<T> {
+        critical_section::with(|_| self.read())
+    }
+
+    /// Stores `value`.
+    pub fn set(&self, value: T) {
+        critical_section::with(|_| self.write(value))
+    }
+
+    /// Modifies the value with `f` and returns the new value.
+    ///
+    /// `f` is called within a critical section.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The container does not hold a value.
+    pub fn update(&self, f: impl FnOnce(&mut T)) -> Result<T> {
+        critical_section::with(|_| {
+            let mut value = self.read().ok_or(Error::FailedPrecondition)?;
+            f(&mut value);
+            self.write(value);
+            Ok(value)
+        })
+    }
+
+    /// Modifies the value with `f`, starting from `initial` if the container
+    /// does not hold a value, and returns the new value.
+    ///
+    /// `f` is called within a critical section.
+    pub fn update_or(&self, initial: T, f: impl FnOnce(&mut T)) -> T {
+        critical_section::with(|_| {
+            let mut value = self.read().unwrap_or(initial);
+            f(&mut value);
+            self.write(value);
+            value
+        })
+    }
+
+    /// Returns the value and invalidates the container, or `None` if it does
+    /// not hold a value.
+    pub fn take(&self) -> Option<T> {
+        critical_section::with(|_| {
+            let value = self.read();
+            self.clear();
+            value
+        })
+    }
+
+    /// Zeroes the container so that it does not hold a value.
+    pub fn invalidate(&self) {
+        critical_section::with(|_| self.clear())
+    }
+
+    fn clear(&self) {
+        // SAFETY: Called within a critical section, and the contents are
+        // valid for writes of `size_of::<T>()` bytes.
+        unsafe {
+            self.signature.get().write_volatile(0);
+            self.crc.get().write_volatile(0);
+            let contents: *mut u8 = self.contents.get().cast();
+            for i in 0..size_of::<T>() {
+                contents.add(i).write_volatile(0);
+            }
+        }
+    }
+}
+
+impl<T: Copy> Default for Persistent<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A buffer of up to `N` bytes of variable-length serialized data in
+/// persistent RAM with integrity checking.
+///
+/// `PersistentBuffer` is the Rust counterpart of the C++
+/// `pw::persistent_ram::PersistentBuffer`.  Data is appended with a
+/// [`PersistentBufferWriter`], and the buffer holds a value once any data
+/// has been written:
+///
+/// ```
+/// use pw_persistent_ram::{persistent, PersistentBuffer};
+/// use pw_stream::Write;
+///
+/// persistent! {
+///     static CRASH_LOGS: PersistentBuffer<256>;
+/// }
+///
+/// // After a reboot, emit the logs written before the crash.
+/// CRASH_LOGS.with_contents(|logs| {
+///     if !logs.is_empty() {
+///         // Dump the logs.
+///     }
+/// });
+/// CRASH_LOGS.clear();
+///
+/// // In the crash handler.
+/// let mut writer = CRASH_LOGS.writer();
+/// writer.write_all(b"Sensor 3 not responding")?;
+/// # assert_eq!(CRASH_LOGS.len(), 23);
+/// # Ok::<(), pw_status::Error>(())
+/// ```
+#[repr(C)]
+pub struct PersistentBuffer<const N: usize> {
+    signature: UnsafeCell<u32>,
+    crc: UnsafeCell<u16>,
+    len: UnsafeCell<usize>,
+    buffer: UnsafeCell<[MaybeUninit<u8>; N]>,
+}
+
+// SAFETY: All access to the buffer is made within a critical section.
+unsafe impl<const N: usize> Sync for PersistentBuffer<N> {}
+
+impl<const N: usize> PersistentBuffer<N> {
+    /// Creates a `PersistentBuffer` without a value.
+    ///
+    /// When placed in a persistent section, the buffer instead holds
+    /// whatever the memory held before the device reset.
+    pub const fn new() -> Self {
+        Self {
+            signature: UnsafeCell::new(0),
+            crc: UnsafeCell::new(0),
+            len: UnsafeCell::new(0),
+            buffer: UnsafeCell::new([MaybeUninit::uninit(); N]),
+        }
+    }
+
+    fn data(&self) -> *mut u8 {
+        self.buffer.get().cast()
+    }
+
+    // Returns the length of the data, or zero if the buffer is invalid.
+    fn valid_len(&self) -> usize {
+        // SAFETY: Called within a critical section, and the data is valid for
+        // reads of `len` bytes since `len` is checked against `N`.
+        unsafe {
+            let len = self.len.get().read_volatile();
+            if self.signature.get().read_volatile() != signature(N) || len == 0 || len > N {
+                return 0;
+            }
+            let crc = crc_of(Crc16Ccitt::new(), self.data(), len).value();
+            if self.crc.get().read_volatile() != crc {
+                return 0;
+            }
+            len
+        }
+    }
+
+    fn reset(&self) {
+        // SAFETY: Called within a critical section.
+        unsafe {
+            self.signature.get().write_volatile(0);
+            self.len.get().write_volatile(0);
+            self.crc.get().write_volatile(Crc16Ccitt::new().value());
+            self.signature.get().write_volatile(signature(N));
+        }
+    }
+
+    /// Returns the maximum number of bytes the buffer can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns whether the buffer holds any data.
+    pub fn has_value(&self) -> bool {
+        !self.is_empty()
+    }
+
+    /// Returns the number of bytes in the buffer, or zero if it does not
+    /// hold a value.
+    pub fn len(&self) -> usize {
+        critical_section::with(|_| self.valid_len())
+    }
+
+    /// Returns whether the buffer is empty or does not hold a value.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Calls `f` with the data in the buffer, which is empty if the buffer
+    /// does not hold a value, and returns its result.
+    ///
+    /// `f` is called within a critical section.
+    pub fn with_contents<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        critical_section::with(|_| {
+            let len = self.valid_len();
+            // SAFETY: The first `len` bytes of the buffer were written by a
+            // `PersistentBufferWriter`, and are not modified until the
+            // critical section ends.
+            f(unsafe { core::slice::from_raw_parts(self.data(), len) })
+        })
+    }
+
+    /// Discards the data in the buffer.
+    pub fn clear(&self) {
+        critical_section::with(|_| self.reset())
+    }
+
+    /// Returns a writer which appends to the data in the buffer.
+    ///
+    /// If the buffer does not hold a value, it is cleared first.
+    pub fn writer(&self) -> PersistentBufferWriter<'_, N> {
+        critical_section::with(|_| {
+            if self.valid_len() == 0 {
+                self.reset();
+            }
+        });
+        PersistentBufferWriter { buffer: self }
+    }
+}
+
+impl<const N: usize> Default for PersistentBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Appends data to a [`PersistentBuffer`].
+///
+/// Each write updates the buffer's CRC, so the buffer holds all data
+/// written before the device resets.
+pub struct PersistentBufferWriter<'a, const N: usize> {
+    buffer: &'a PersistentBuffer<N>,
+}
+
+impl<const N: usize> PersistentBufferWriter<'_, N> {
+    /// Returns the number of bytes which may still be written.
+    pub fn remaining(&self) -> usize {
+        N - self.buffer.len()
+    }
+}
+
+impl<const N: usize> pw_stream::Write for PersistentBufferWriter<'_, N> {
+    /// Appends all of `buf` to the buffer.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - The buffer is full.
+    /// - [`Error::ResourceExhausted`] - `buf` does not fit in the buffer.
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let buffer = self.buffer;
+        critical_section::with(|_| {
+            let len = buffer.valid_len();
+            if len == 0 {
+                // The buffer was cleared, or corrupted, since the writer was
+                // created.
+                buffer.reset();
+            }
+            if len == N {
+                return Err(Error::OutOfRange);
+            }
+            if N - len < buf.len() {
+                return Err(Error::ResourceExhausted);
+            }
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            // SAFETY: Called within a critical section, and `len + buf.len()`
+            // is at most `N`.
+            unsafe {
+                let data = buffer.data().add(len);
+                for (i, byte) in buf.iter().enumerate() {
+                    data.add(i).write_volatile(*byte);
+                }
+                // Only the new data is added to the CRC.
+                let crc = Crc16Ccitt::from_value(buffer.crc.get().read_volatile());
+                buffer
+                    .crc
+                    .get()
+                    .write_volatile(crc_of(crc, data, buf.len()).value());
+                buffer.len.get().write_volatile(len + buf.len());
+            }
+            Ok(buf.len())
+        })
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_stream::Write;
+
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct CrashInfo {
+        uptime_ms: u32,
+        pc: u32,
+    }
+
+    #[test]
+    fn new_persistent_has_no_value() {
+        let persistent = Persistent::<u32>::new();
+        assert!(!persistent.has_value());
+        assert_eq!(persistent.get(), None);
+        assert_eq!(persistent.update(|_| ()), Err(Error::FailedPrecondition));
+    }
+
+    #[test]
+    fn zeroed_persistent_has_no_value() {
+        // SAFETY: All fields of a `Persistent` may be zero.
+        let persistent: Persistent<u32> = unsafe { core::mem::zeroed() };
+        assert!(!persistent.has_value());
+    }
+
+    #[test]
+    fn persistent_holds_value() {
+        let persistent = Persistent::new();
+        let info = CrashInfo {
+            uptime_ms: 1000,
+            pc: 0x0800_1234,
+        };
+        persistent.set(info);
+        assert!(persistent.has_value());
+        assert_eq!(persistent.get(), Some(info));
+        assert_eq!(
+            persistent.update(|info| info.uptime_ms += 1),
+            Ok(CrashInfo {
+                uptime_ms: 1001,
+                pc: 0x0800_1234
+            })
+        );
+    }
+
+    #[test]
+    fn update_or_starts_from_initial_value() {
+        let boot_count = Persistent::<u16>::new();
+        assert_eq!(boot_count.update_or(0, |count| *count += 1), 1);
+        assert_eq!(boot_count.update_or(0, |count| *count += 1), 2);
+        assert_eq!(boot_count.get(), Some(2));
+    }
+
+    #[test]
+    fn corrupted_persistent_has_no_value() {
+        let persistent = Persistent::<u32>::new();
+        persistent.set(0x1234_5678);
+        // SAFETY: The test has exclusive access to `persistent`.
+        unsafe { *persistent.contents.get().cast::<u8>() ^= 0x1 };
+        assert_eq!(persistent.get(), None);
+
+        persistent.set(0x1234_5678);
+        // SAFETY: The test has exclusive access to `persistent`.
+        unsafe { *persistent.signature.get() ^= 0x1 };
+        assert_eq!(persistent.get(), None);
+    }
+
+    #[test]
+    fn take_invalidates_persistent() {
+        let persistent = Persistent::<u32>::new();
+        persistent.set(5);
+        assert_eq!(persistent.take(), Some(5));
+        assert_eq!(persistent.take(), None);
+
+        persistent.set(5);
+        persistent.invalidate();
+        assert!(!persistent.has_value());
+    }
+
+    #[test]
+    fn buffer_appends_writes() {
+        let buffer = PersistentBuffer::<16>::new();
+        assert!(!buffer.has_value());
+        assert_eq!(buffer.capacity(), 16);
+
+        let mut writer = buffer.writer();
+        writer.write_all(b"Hello, ").unwrap();
+        // A new writer appends to the existing data.
+        buffer.writer().write_all(b"world").unwrap();
+        assert!(buffer.has_value());
+        assert_eq!(buffer.len(), 12);
+        buffer.with_contents(|data| assert_eq!(data, b"Hello, world"));
+        assert_eq!(writer.remaining(), 4);
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        buffer.with_contents(|data| assert!(data.is_empty()));
+        writer.write_all(b"Again").unwrap();
+        buffer.with_contents(|data| assert_eq!(data, b"Again"));
+    }
+
+    #[test]
+    fn buffer_rejects_writes_which_do_not_fit() {
+        let buffer = PersistentBuffer::<8>::new();
+        let mut writer = buffer.writer();
+        assert_eq!(writer.write(b"123456789"), Err(Error::ResourceExhausted));
+        assert_eq!(writer.write(b"12345678"), Ok(8));
+        assert_eq!(writer.write(b"9"), Err(Error::OutOfRange));
+        buffer.with_contents(|data| assert_eq!(data, b"12345678"));
+    }
+
+    #[test]
+    fn corrupted_buffer_has_no_value() {
+        let buffer = PersistentBuffer::<16>::new();
+        buffer.writer().write_all(b"Crash log").unwrap();
+        // SAFETY: The test has exclusive access to `buffer`.
+        unsafe { *buffer.data().add(3) ^= 0x80 };
+        assert!(!buffer.has_value());
+
+        // Writing to a corrupted buffer starts over.
+        buffer.writer().write_all(b"New log").unwrap();
+        buffer.with_contents(|data| assert_eq!(data, b"New log"));
+    }
+
+    persistent! {
+        static BOOT_COUNT: Persistent<u32>;
+    }
+
+    persistent! {
+        section = ".noinit.logs";
+        static LOGS: PersistentBuffer<32>;
+    }
+
+    #[test]
+    fn persistent_statics_are_usable() {
+        assert_eq!(BOOT_COUNT.update_or(0, |count| *count += 1), 1);
+        LOGS.writer().write_all(b"Boot").unwrap();
+        assert_eq!(LOGS.len(), 4);
+    }
+}
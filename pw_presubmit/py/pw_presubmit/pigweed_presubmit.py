@@ -727,9 +727,11 @@ def bazel_build(ctx: PresubmitContext) -> None:
     targets_for_platform = {
         "//pw_build/platforms:lm3s6965evb": [
             "//pw_rust/examples/embedded_hello:hello",
+            "//pw_transfer/rust:pw_transfer",
         ],
         "//pw_build/platforms:microbit": [
             "//pw_rust/examples/embedded_hello:hello",
+            "//pw_transfer/rust:pw_transfer",
         ],
     }
 
@@ -0,0 +1,811 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_protobuf` encodes and decodes protobuf messages without allocation.
+//!
+//! This is a minimal Rust counterpart of the C++ `pw_protobuf` module's
+//! `MemoryEncoder` and `Decoder`.  Fields are written one at a time into a caller provided
+//! buffer, so messages can be encoded on devices without `std` or a heap:
+//!
+//! ```
+//! use pw_protobuf::MemoryEncoder;
+//!
+//! let mut buffer = [0u8; 16];
+//! let mut encoder = MemoryEncoder::new(&mut buffer);
+//! encoder.write_uint32(1, 150).unwrap();
+//! encoder.write_string(2, "hi").unwrap();
+//! assert_eq!(encoder.as_slice(), &[0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i']);
+//! ```
+//!
+//! Messages are decoded one field at a time by iterating over a [`Decoder`].
+//!
+//! Messages which are too large to buffer, or which are read from or written
+//! to a transport or flash, can be encoded to a [`pw_stream::Write`] with a
+//! [`StreamEncoder`] and decoded from a [`pw_stream::Read`] with a
+//! [`StreamDecoder`].
+//!
+//! `pw_protobuf_codegen` generates structs which encode and decode messages
+//! with these types from `.proto` files in a build script.
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_status::{Error, Result};
+use pw_varint::VarintEncode;
+
+mod stream;
+
+pub use stream::{StreamDecoder, StreamEncoder};
+
+/// The largest valid field number.
+pub const MAX_FIELD_NUMBER: u32 = (1 << 29) - 1;
+
+// Field numbers reserved for the protobuf implementation.
+const FIRST_RESERVED_NUMBER: u32 = 19000;
+const LAST_RESERVED_NUMBER: u32 = 19999;
+
+const FIELD_NUMBER_SHIFT: u32 = 3;
+
+/// Returns `true` if `field_number` may be used for a field.
+///
+/// Valid field numbers range from 1 to [`MAX_FIELD_NUMBER`], excluding the
+/// numbers from 19000 to 19999 which are reserved.
+pub const fn valid_field_number(field_number: u32) -> bool {
+    field_number != 0
+        && field_number <= MAX_FIELD_NUMBER
+        && !(field_number >= FIRST_RESERVED_NUMBER && field_number <= LAST_RESERVED_NUMBER)
+}
+
+/// The encoding of a field's value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum WireType {
+    /// A varint encoded integer.
+    Varint = 0,
+    /// A little endian 64 bit value.
+    Fixed64 = 1,
+    /// A length prefixed value such as bytes, a string, or a nested message.
+    Delimited = 2,
+    // Wire types 3 and 4 are deprecated per the protobuf specification.
+    /// A little endian 32 bit value.
+    Fixed32 = 5,
+}
+
+/// Returns the key which precedes a field with the given number and type.
+pub const fn field_key(field_number: u32, wire_type: WireType) -> u32 {
+    field_number << FIELD_NUMBER_SHIFT | wire_type as u32
+}
+
+/// Returns the number of bytes used to varint encode `value`.
+pub const fn varint_size(value: u64) -> usize {
+    pw_varint::encoded_size(value)
+}
+
+/// Returns the number of bytes used by a length delimited field with
+/// `data_size` bytes of data.
+pub const fn size_of_delimited_field(field_number: u32, data_size: usize) -> usize {
+    varint_size(field_key(field_number, WireType::Delimited) as u64)
+        + varint_size(data_size as u64)
+        + data_size
+}
+
+/// Encodes a protobuf message into a buffer.
+///
+/// Fields are written in the order the `write_*` methods are called.  A field
+/// which fails to be written is not added to the message, so the encoder
+/// remains usable.
+pub struct MemoryEncoder<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> MemoryEncoder<'a> {
+    /// Creates an encoder which writes to `buffer`.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, len: 0 }
+    }
+
+    /// Returns the number of bytes written.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no fields have been written.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of unused bytes in the buffer.
+    pub fn remaining(&self) -> usize {
+        self.buffer.len() - self.len
+    }
+
+    /// Returns the encoded message.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+
+    /// Returns the encoded message, consuming the encoder.
+    pub fn into_slice(self) -> &'a [u8] {
+        &self.buffer[..self.len]
+    }
+
+    // Writes a key followed by the value written by `write_value`, leaving the
+    // message unchanged on error.
+    fn write_field(
+        &mut self,
+        field_number: u32,
+        wire_type: WireType,
+        write_value: impl FnOnce(&mut [u8]) -> Result<usize>,
+    ) -> Result<()> {
+        if !valid_field_number(field_number) {
+            return Err(Error::InvalidArgument);
+        }
+        let buffer = &mut self.buffer[self.len..];
+        let key_len = u64::from(field_key(field_number, wire_type))
+            .varint_encode(buffer)
+            .map_err(|_| Error::ResourceExhausted)?;
+        let value_len = write_value(&mut buffer[key_len..])?;
+        self.len += key_len + value_len;
+        Ok(())
+    }
+
+    fn write_varint(&mut self, field_number: u32, value: u64) -> Result<()> {
+        self.write_field(field_number, WireType::Varint, |buffer| {
+            value
+                .varint_encode(buffer)
+                .map_err(|_| Error::ResourceExhausted)
+        })
+    }
+
+    fn write_fixed(&mut self, field_number: u32, wire_type: WireType, data: &[u8]) -> Result<()> {
+        self.write_field(field_number, wire_type, |buffer| {
+            buffer
+                .get_mut(..data.len())
+                .ok_or(Error::ResourceExhausted)?
+                .copy_from_slice(data);
+            Ok(data.len())
+        })
+    }
+
+    /// Writes a `uint32` field.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `field_number` is not valid.
+    /// - [`Error::ResourceExhausted`] - The field does not fit in the buffer.
+    pub fn write_uint32(&mut self, field_number: u32, value: u32) -> Result<()> {
+        self.write_varint(field_number, value.into())
+    }
+
+    /// Writes a `uint64` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_uint64(&mut self, field_number: u32, value: u64) -> Result<()> {
+        self.write_varint(field_number, value)
+    }
+
+    /// Writes an `int32` field.  Negative values use 10 bytes.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_int32(&mut self, field_number: u32, value: i32) -> Result<()> {
+        self.write_int64(field_number, value.into())
+    }
+
+    /// Writes an `int64` field.  Negative values use 10 bytes.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_int64(&mut self, field_number: u32, value: i64) -> Result<()> {
+        // `int64` values are encoded as two's complement.
+        self.write_varint(field_number, value as u64)
+    }
+
+    /// Writes a ZigZag encoded `sint32` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_sint32(&mut self, field_number: u32, value: i32) -> Result<()> {
+        self.write_sint64(field_number, value.into())
+    }
+
+    /// Writes a ZigZag encoded `sint64` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_sint64(&mut self, field_number: u32, value: i64) -> Result<()> {
+        self.write_field(field_number, WireType::Varint, |buffer| {
+            value
+                .varint_encode(buffer)
+                .map_err(|_| Error::ResourceExhausted)
+        })
+    }
+
+    /// Writes a `bool` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_bool(&mut self, field_number: u32, value: bool) -> Result<()> {
+        self.write_varint(field_number, value.into())
+    }
+
+    /// Writes a `fixed32` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_fixed32(&mut self, field_number: u32, value: u32) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed32, &value.to_le_bytes())
+    }
+
+    /// Writes a `fixed64` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_fixed64(&mut self, field_number: u32, value: u64) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed64, &value.to_le_bytes())
+    }
+
+    /// Writes an `sfixed32` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_sfixed32(&mut self, field_number: u32, value: i32) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed32, &value.to_le_bytes())
+    }
+
+    /// Writes an `sfixed64` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_sfixed64(&mut self, field_number: u32, value: i64) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed64, &value.to_le_bytes())
+    }
+
+    /// Writes a `float` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_float(&mut self, field_number: u32, value: f32) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed32, &value.to_le_bytes())
+    }
+
+    /// Writes a `double` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_double(&mut self, field_number: u32, value: f64) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed64, &value.to_le_bytes())
+    }
+
+    /// Writes a `bytes` field or an encoded nested message.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_bytes(&mut self, field_number: u32, value: &[u8]) -> Result<()> {
+        self.write_field(field_number, WireType::Delimited, |buffer| {
+            let len_size = (value.len() as u64)
+                .varint_encode(buffer)
+                .map_err(|_| Error::ResourceExhausted)?;
+            buffer
+                .get_mut(len_size..len_size + value.len())
+                .ok_or(Error::ResourceExhausted)?
+                .copy_from_slice(value);
+            Ok(len_size + value.len())
+        })
+    }
+
+    /// Writes a `string` field.
+    ///
+    /// Errors are the same as [`MemoryEncoder::write_uint32()`].
+    pub fn write_string(&mut self, field_number: u32, value: &str) -> Result<()> {
+        self.write_bytes(field_number, value.as_bytes())
+    }
+
+    /// Writes a nested message field whose fields are written by `encode`
+    /// with an encoder for the rest of the buffer.
+    ///
+    /// ```
+    /// use pw_protobuf::MemoryEncoder;
+    ///
+    /// let mut buffer = [0u8; 16];
+    /// let mut encoder = MemoryEncoder::new(&mut buffer);
+    /// encoder
+    ///     .write_nested(1, |nested| nested.write_uint32(1, 150))
+    ///     .unwrap();
+    /// assert_eq!(encoder.as_slice(), &[0x0a, 0x03, 0x08, 0x96, 0x01]);
+    /// ```
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `field_number` is not valid.
+    /// - [`Error::ResourceExhausted`] - The field does not fit in the buffer.
+    ///
+    /// Any error returned by `encode` is also returned.  The message is
+    /// unchanged if an error is returned.
+    pub fn write_nested(
+        &mut self,
+        field_number: u32,
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+    ) -> Result<()> {
+        self.write_field(field_number, WireType::Delimited, |buffer| {
+            // Reserve enough space for the length of any message which fits,
+            // then move the message next to its actual length.
+            let reserved = varint_size(buffer.len() as u64);
+            let nested_buffer = buffer.get_mut(reserved..).ok_or(Error::ResourceExhausted)?;
+            let mut nested = MemoryEncoder::new(nested_buffer);
+            encode(&mut nested)?;
+            let len = nested.len();
+            let len_size = (len as u64)
+                .varint_encode(buffer)
+                .map_err(|_| Error::ResourceExhausted)?;
+            buffer.copy_within(reserved..reserved + len, len_size);
+            Ok(len_size + len)
+        })
+    }
+}
+
+/// The value of a decoded field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    /// A varint encoded integer.  Signed and zig-zag encoded types must be
+    /// converted by the caller.
+    Varint(u64),
+    /// A little endian 64 bit value.
+    Fixed64(u64),
+    /// The data of a length delimited field.
+    Delimited(&'a [u8]),
+    /// A little endian 32 bit value.
+    Fixed32(u32),
+}
+
+impl<'a> Value<'a> {
+    /// Returns the value of a `uint32` or enum field.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The value is not a varint or does not fit in a
+    ///   `u32`.
+    pub fn as_u32(&self) -> Result<u32> {
+        match *self {
+            Value::Varint(value) => u32::try_from(value).map_err(|_| Error::DataLoss),
+            _ => Err(Error::DataLoss),
+        }
+    }
+
+    /// Returns the value of a `uint64` field.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The value is not a varint.
+    pub fn as_u64(&self) -> Result<u64> {
+        match *self {
+            Value::Varint(value) => Ok(value),
+            _ => Err(Error::DataLoss),
+        }
+    }
+
+    /// Returns the value of an `int32` or enum field.
+    ///
+    /// As in other protobuf implementations, `int64` values are truncated.
+    ///
+    /// Errors are the same as [`Value::as_u64()`].
+    pub fn as_i32(&self) -> Result<i32> {
+        Ok(self.as_u64()? as i32)
+    }
+
+    /// Returns the value of an `int64` field.
+    ///
+    /// Errors are the same as [`Value::as_u64()`].
+    pub fn as_i64(&self) -> Result<i64> {
+        Ok(self.as_u64()? as i64)
+    }
+
+    /// Returns the value of a ZigZag encoded `sint32` field.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The value is not a varint or does not fit in an
+    ///   `i32`.
+    pub fn as_sint32(&self) -> Result<i32> {
+        i32::try_from(self.as_sint64()?).map_err(|_| Error::DataLoss)
+    }
+
+    /// Returns the value of a ZigZag encoded `sint64` field.
+    ///
+    /// Errors are the same as [`Value::as_u64()`].
+    pub fn as_sint64(&self) -> Result<i64> {
+        let value = self.as_u64()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    /// Returns the value of a `bool` field.
+    ///
+    /// Errors are the same as [`Value::as_u64()`].
+    pub fn as_bool(&self) -> Result<bool> {
+        Ok(self.as_u64()? != 0)
+    }
+
+    /// Returns the value of a `fixed32` field.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The value is not a fixed 32 bit value.
+    pub fn as_fixed32(&self) -> Result<u32> {
+        match *self {
+            Value::Fixed32(value) => Ok(value),
+            _ => Err(Error::DataLoss),
+        }
+    }
+
+    /// Returns the value of an `sfixed32` field.
+    ///
+    /// Errors are the same as [`Value::as_fixed32()`].
+    pub fn as_sfixed32(&self) -> Result<i32> {
+        Ok(self.as_fixed32()? as i32)
+    }
+
+    /// Returns the value of a `float` field.
+    ///
+    /// Errors are the same as [`Value::as_fixed32()`].
+    pub fn as_float(&self) -> Result<f32> {
+        Ok(f32::from_bits(self.as_fixed32()?))
+    }
+
+    /// Returns the value of a `fixed64` field.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The value is not a fixed 64 bit value.
+    pub fn as_fixed64(&self) -> Result<u64> {
+        match *self {
+            Value::Fixed64(value) => Ok(value),
+            _ => Err(Error::DataLoss),
+        }
+    }
+
+    /// Returns the value of an `sfixed64` field.
+    ///
+    /// Errors are the same as [`Value::as_fixed64()`].
+    pub fn as_sfixed64(&self) -> Result<i64> {
+        Ok(self.as_fixed64()? as i64)
+    }
+
+    /// Returns the value of a `double` field.
+    ///
+    /// Errors are the same as [`Value::as_fixed64()`].
+    pub fn as_double(&self) -> Result<f64> {
+        Ok(f64::from_bits(self.as_fixed64()?))
+    }
+
+    /// Returns the data of a `bytes`, `string`, or message field.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The value is not length delimited.
+    pub fn as_bytes(&self) -> Result<&'a [u8]> {
+        match *self {
+            Value::Delimited(data) => Ok(data),
+            _ => Err(Error::DataLoss),
+        }
+    }
+
+    /// Returns the data of a `string` field.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The value is not length delimited or is not
+    ///   valid UTF-8.
+    pub fn as_str(&self) -> Result<&'a str> {
+        core::str::from_utf8(self.as_bytes()?).map_err(|_| Error::DataLoss)
+    }
+
+    /// Returns the values of a repeated scalar field whose values have
+    /// `wire_type`.
+    ///
+    /// Repeated scalar fields may be encoded as a field per value or packed
+    /// into a single length delimited field, which are both accepted.
+    ///
+    /// ```
+    /// use pw_protobuf::{Decoder, WireType};
+    ///
+    /// let mut decoder = Decoder::new(&[0x0a, 0x03, 0x01, 0x96, 0x01]);
+    /// let field = decoder.next().unwrap().unwrap();
+    /// let mut values = field.value.repeated(WireType::Varint);
+    /// assert_eq!(values.next().unwrap().and_then(|value| value.as_u32()), Ok(1));
+    /// assert_eq!(values.next().unwrap().and_then(|value| value.as_u32()), Ok(150));
+    /// assert!(values.next().is_none());
+    /// ```
+    pub fn repeated(self, wire_type: WireType) -> RepeatedValues<'a> {
+        match self {
+            Value::Delimited(data) if wire_type != WireType::Delimited => RepeatedValues {
+                value: None,
+                packed: Decoder::new(data),
+                wire_type,
+            },
+            value => RepeatedValues {
+                value: Some(value),
+                packed: Decoder::new(&[]),
+                wire_type,
+            },
+        }
+    }
+}
+
+/// An iterator over the values of a repeated scalar field, returned by
+/// [`Value::repeated()`].
+///
+/// A malformed packed field is returned as [`Error::DataLoss`], which ends the
+/// iteration.
+pub struct RepeatedValues<'a> {
+    value: Option<Value<'a>>,
+    packed: Decoder<'a>,
+    wire_type: WireType,
+}
+
+impl<'a> Iterator for RepeatedValues<'a> {
+    type Item = Result<Value<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(value) = self.value.take() {
+            return Some(Ok(value));
+        }
+        if self.packed.data.is_empty() {
+            return None;
+        }
+        let value = self.packed.decode_value(self.wire_type as u64);
+        if value.is_err() {
+            self.packed.data = &[];
+        }
+        Some(value)
+    }
+}
+
+/// A field decoded from a protobuf message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field<'a> {
+    /// The field's number.
+    pub number: u32,
+    /// The field's value.
+    pub value: Value<'a>,
+}
+
+/// Decodes the fields of a protobuf message in a buffer.
+///
+/// The decoder is an iterator over the message's fields in the order they
+/// were encoded.  Decoding stops after the first malformed field, which is
+/// returned as [`Error::DataLoss`].
+///
+/// ```
+/// use pw_protobuf::{Decoder, Field, Value};
+///
+/// let mut decoder = Decoder::new(&[0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i']);
+/// assert_eq!(
+///     decoder.next(),
+///     Some(Ok(Field { number: 1, value: Value::Varint(150) }))
+/// );
+/// assert_eq!(
+///     decoder.next(),
+///     Some(Ok(Field { number: 2, value: Value::Delimited(b"hi") }))
+/// );
+/// assert_eq!(decoder.next(), None);
+/// ```
+pub struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    /// Creates a decoder for the message in `data`.
+    pub const fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn decode_varint(&mut self) -> Result<u64> {
+        let (len, value) = pw_varint::decode_u64(self.data).map_err(|_| Error::DataLoss)?;
+        self.data = &self.data[len..];
+        Ok(value)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(Error::DataLoss);
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn decode_field(&mut self) -> Result<Field<'a>> {
+        let key = self.decode_varint()?;
+        let number = u32::try_from(key >> FIELD_NUMBER_SHIFT).map_err(|_| Error::DataLoss)?;
+        if !valid_field_number(number) {
+            return Err(Error::DataLoss);
+        }
+        let value = self.decode_value(key & 0x7)?;
+        Ok(Field { number, value })
+    }
+
+    fn decode_value(&mut self, wire_type: u64) -> Result<Value<'a>> {
+        Ok(match wire_type {
+            0 => Value::Varint(self.decode_varint()?),
+            1 => Value::Fixed64(u64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+            2 => {
+                let len = usize::try_from(self.decode_varint()?).map_err(|_| Error::DataLoss)?;
+                Value::Delimited(self.take(len)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            _ => return Err(Error::DataLoss),
+        })
+    }
+}
+
+impl<'a> Iterator for Decoder<'a> {
+    type Item = Result<Field<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let field = self.decode_field();
+        if field.is_err() {
+            self.data = &[];
+        }
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn varint_size_matches_encoding() {
+        for (value, size) in [
+            (0, 1),
+            (127, 1),
+            (128, 2),
+            (u32::MAX.into(), 5),
+            (u64::MAX, 10),
+        ] {
+            assert_eq!(varint_size(value), size, "{value}");
+        }
+        assert_eq!(size_of_delimited_field(1, 200), 1 + 2 + 200);
+        assert_eq!(size_of_delimited_field(16, 0), 2 + 1);
+    }
+
+    #[test]
+    fn integer_fields_are_encoded() {
+        let mut buffer = [0u8; 64];
+        let mut encoder = MemoryEncoder::new(&mut buffer);
+        encoder.write_int32(1, -1).unwrap();
+        encoder.write_sint32(2, -1).unwrap();
+        encoder.write_bool(3, true).unwrap();
+        encoder.write_fixed32(4, 0x01020304).unwrap();
+        encoder.write_uint64(16, 1).unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+            0x10, 0x01,
+            0x18, 0x01,
+            0x25, 0x04, 0x03, 0x02, 0x01,
+            0x80, 0x01, 0x01,
+        ];
+        assert_eq!(encoder.as_slice(), &expected);
+    }
+
+    #[test]
+    fn field_which_does_not_fit_is_not_written() {
+        let mut buffer = [0u8; 6];
+        let mut encoder = MemoryEncoder::new(&mut buffer);
+        encoder.write_uint32(1, 1).unwrap();
+        assert_eq!(
+            encoder.write_bytes(2, b"long"),
+            Err(Error::ResourceExhausted)
+        );
+        assert_eq!(encoder.write_fixed64(3, 0), Err(Error::ResourceExhausted));
+        assert_eq!(encoder.as_slice(), &[0x08, 0x01]);
+        encoder.write_bytes(2, b"ok").unwrap();
+        assert_eq!(encoder.into_slice(), &[0x08, 0x01, 0x12, 0x02, b'o', b'k']);
+    }
+
+    #[test]
+    fn invalid_field_numbers_are_rejected() {
+        let mut buffer = [0u8; 16];
+        let mut encoder = MemoryEncoder::new(&mut buffer);
+        for field_number in [0, 19000, 19999, MAX_FIELD_NUMBER + 1] {
+            assert_eq!(
+                encoder.write_uint32(field_number, 1),
+                Err(Error::InvalidArgument)
+            );
+        }
+        assert!(encoder.is_empty());
+        encoder.write_uint32(MAX_FIELD_NUMBER, 1).unwrap();
+        assert_eq!(encoder.len(), 6);
+    }
+
+    #[test]
+    fn fields_are_decoded() {
+        let mut buffer = [0u8; 64];
+        let mut encoder = MemoryEncoder::new(&mut buffer);
+        encoder.write_uint32(1, 150).unwrap();
+        encoder.write_fixed64(2, 0x0102030405060708).unwrap();
+        encoder.write_bytes(3, b"hi").unwrap();
+        encoder.write_fixed32(MAX_FIELD_NUMBER, 7).unwrap();
+
+        let fields: [_; 4] = core::array::from_fn({
+            let mut decoder = Decoder::new(encoder.as_slice());
+            move |_| decoder.next().unwrap().unwrap()
+        });
+        assert_eq!(fields[0].value.as_u32(), Ok(150));
+        assert_eq!(fields[1].value, Value::Fixed64(0x0102030405060708));
+        assert_eq!(fields[2].value.as_bytes(), Ok(&b"hi"[..]));
+        assert_eq!(fields[3].number, MAX_FIELD_NUMBER);
+        assert_eq!(fields[3].value.as_fixed32(), Ok(7));
+        assert_eq!(fields[3].value.as_u32(), Err(Error::DataLoss));
+    }
+
+    #[test]
+    fn values_are_converted_to_field_types() {
+        assert_eq!(Value::Varint(u64::MAX).as_i32(), Ok(-1));
+        assert_eq!(Value::Varint(u64::MAX).as_i64(), Ok(-1));
+        assert_eq!(Value::Varint(3).as_sint32(), Ok(-2));
+        assert_eq!(Value::Varint(u64::MAX).as_sint32(), Err(Error::DataLoss));
+        assert_eq!(Value::Varint(u64::MAX).as_sint64(), Ok(i64::MIN));
+        assert_eq!(Value::Varint(2).as_bool(), Ok(true));
+        assert_eq!(Value::Fixed32(u32::MAX).as_sfixed32(), Ok(-1));
+        assert_eq!(Value::Fixed32(0x3fc00000).as_float(), Ok(1.5));
+        assert_eq!(Value::Fixed64(u64::MAX).as_sfixed64(), Ok(-1));
+        assert_eq!(Value::Fixed64(0x3ff8000000000000).as_double(), Ok(1.5));
+        assert_eq!(Value::Delimited(b"hi").as_str(), Ok("hi"));
+        assert_eq!(Value::Delimited(&[0xff]).as_str(), Err(Error::DataLoss));
+        assert_eq!(Value::Fixed32(1).as_u64(), Err(Error::DataLoss));
+        assert_eq!(Value::Varint(1).as_double(), Err(Error::DataLoss));
+    }
+
+    #[test]
+    fn float_fields_are_encoded() {
+        let mut buffer = [0u8; 32];
+        let mut encoder = MemoryEncoder::new(&mut buffer);
+        encoder.write_float(1, 1.5).unwrap();
+        encoder.write_double(2, -1.5).unwrap();
+        encoder.write_sfixed32(3, -1).unwrap();
+        let mut decoder = Decoder::new(encoder.as_slice());
+        assert_eq!(decoder.next().unwrap().unwrap().value.as_float(), Ok(1.5));
+        assert_eq!(decoder.next().unwrap().unwrap().value.as_double(), Ok(-1.5));
+        assert_eq!(decoder.next().unwrap().unwrap().value.as_sfixed32(), Ok(-1));
+    }
+
+    #[test]
+    fn repeated_values_may_be_packed() {
+        let single: std::vec::Vec<_> = Value::Fixed32(7).repeated(WireType::Fixed32).collect();
+        assert_eq!(single, [Ok(Value::Fixed32(7))]);
+
+        let packed = Value::Delimited(&[0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00]);
+        let values: std::vec::Vec<_> = packed.repeated(WireType::Fixed32).collect();
+        assert_eq!(values, [Ok(Value::Fixed32(1)), Ok(Value::Fixed32(2))]);
+
+        let truncated = Value::Delimited(&[0x01, 0x80]);
+        let values: std::vec::Vec<_> = truncated.repeated(WireType::Varint).collect();
+        assert_eq!(values, [Ok(Value::Varint(1)), Err(Error::DataLoss)]);
+
+        let bytes: std::vec::Vec<_> = Value::Delimited(b"hi")
+            .repeated(WireType::Delimited)
+            .collect();
+        assert_eq!(bytes, [Ok(Value::Delimited(b"hi"))]);
+    }
+
+    #[test]
+    fn malformed_fields_are_data_loss() {
+        for data in [
+            &[0x08][..],
+            &[0x12, 0x03, b'h', b'i'],
+            &[0x1d, 0x01, 0x02],
+            &[0x0b],
+            &[0x00, 0x01],
+        ] {
+            let mut decoder = Decoder::new(data);
+            assert_eq!(decoder.next(), Some(Err(Error::DataLoss)), "{data:?}");
+            assert_eq!(decoder.next(), None);
+        }
+        assert_eq!(
+            Decoder::new(&[0x08, 0x80, 0x80, 0x80, 0x80, 0x10])
+                .next()
+                .unwrap()
+                .unwrap()
+                .value
+                .as_u32(),
+            Err(Error::DataLoss)
+        );
+    }
+}
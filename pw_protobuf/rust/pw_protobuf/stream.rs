@@ -0,0 +1,800 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_status::{Error, Result};
+use pw_stream::{Read, Write};
+use pw_varint::VarintEncode;
+
+use crate::{field_key, valid_field_number, MemoryEncoder, WireType, FIELD_NUMBER_SHIFT};
+
+// Bytes used by a key and a varint or fixed value.
+const MAX_SCALAR_FIELD_SIZE: usize = 2 * pw_varint::MAX_VARINT64_SIZE_BYTES;
+
+/// Encodes a protobuf message to a [`pw_stream::Write`], matching the C++
+/// `pw::protobuf::StreamEncoder`.
+///
+/// Fields are written to the stream as the `write_*` methods are called, so
+/// messages of any size can be encoded without buffering them.  The length
+/// of a nested message must be known before it is written, so nested
+/// messages are encoded into a scratch buffer with
+/// [`StreamEncoder::write_nested()`].
+///
+/// If writing to the stream fails, part of the field may have been written.
+///
+/// ```
+/// use pw_protobuf::StreamEncoder;
+/// use pw_stream::Cursor;
+///
+/// let mut encoder = StreamEncoder::new(Cursor::new([0u8; 16]));
+/// encoder.write_uint32(1, 150).unwrap();
+/// encoder.write_string(2, "hi").unwrap();
+///
+/// let cursor = encoder.into_inner();
+/// let len = cursor.position();
+/// assert_eq!(&cursor.into_inner()[..len], &[0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i']);
+/// ```
+pub struct StreamEncoder<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> StreamEncoder<W> {
+    /// Creates an encoder which writes to `writer`.
+    pub const fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Returns a reference to the writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Consumes the encoder and returns the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    // Writes a key followed by `value_size` bytes of the value encoded into
+    // the rest of the scratch buffer by `encode_value`.
+    fn write_scalar(
+        &mut self,
+        field_number: u32,
+        wire_type: WireType,
+        encode_value: impl FnOnce(&mut [u8]) -> usize,
+    ) -> Result<()> {
+        if !valid_field_number(field_number) {
+            return Err(Error::InvalidArgument);
+        }
+        let mut buffer = [0u8; MAX_SCALAR_FIELD_SIZE];
+        // The buffer fits any key and value, so encoding can not fail.
+        let key_len = u64::from(field_key(field_number, wire_type))
+            .varint_encode(&mut buffer)
+            .unwrap_or_default();
+        let value_len = encode_value(&mut buffer[key_len..]);
+        self.writer.write_all(&buffer[..key_len + value_len])
+    }
+
+    fn write_varint(&mut self, field_number: u32, value: u64) -> Result<()> {
+        self.write_scalar(field_number, WireType::Varint, |buffer| {
+            value.varint_encode(buffer).unwrap_or_default()
+        })
+    }
+
+    fn write_fixed(&mut self, field_number: u32, wire_type: WireType, data: &[u8]) -> Result<()> {
+        self.write_scalar(field_number, wire_type, |buffer| {
+            buffer[..data.len()].copy_from_slice(data);
+            data.len()
+        })
+    }
+
+    /// Writes a `uint32` field.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `field_number` is not valid.
+    ///
+    /// Any error writing to the stream is also returned.
+    pub fn write_uint32(&mut self, field_number: u32, value: u32) -> Result<()> {
+        self.write_varint(field_number, value.into())
+    }
+
+    /// Writes a `uint64` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_uint64(&mut self, field_number: u32, value: u64) -> Result<()> {
+        self.write_varint(field_number, value)
+    }
+
+    /// Writes an `int32` field.  Negative values use 10 bytes.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_int32(&mut self, field_number: u32, value: i32) -> Result<()> {
+        self.write_int64(field_number, value.into())
+    }
+
+    /// Writes an `int64` field.  Negative values use 10 bytes.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_int64(&mut self, field_number: u32, value: i64) -> Result<()> {
+        // `int64` values are encoded as two's complement.
+        self.write_varint(field_number, value as u64)
+    }
+
+    /// Writes a ZigZag encoded `sint32` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_sint32(&mut self, field_number: u32, value: i32) -> Result<()> {
+        self.write_sint64(field_number, value.into())
+    }
+
+    /// Writes a ZigZag encoded `sint64` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_sint64(&mut self, field_number: u32, value: i64) -> Result<()> {
+        self.write_scalar(field_number, WireType::Varint, |buffer| {
+            value.varint_encode(buffer).unwrap_or_default()
+        })
+    }
+
+    /// Writes a `bool` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_bool(&mut self, field_number: u32, value: bool) -> Result<()> {
+        self.write_varint(field_number, value.into())
+    }
+
+    /// Writes a `fixed32` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_fixed32(&mut self, field_number: u32, value: u32) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed32, &value.to_le_bytes())
+    }
+
+    /// Writes a `fixed64` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_fixed64(&mut self, field_number: u32, value: u64) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed64, &value.to_le_bytes())
+    }
+
+    /// Writes an `sfixed32` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_sfixed32(&mut self, field_number: u32, value: i32) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed32, &value.to_le_bytes())
+    }
+
+    /// Writes an `sfixed64` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_sfixed64(&mut self, field_number: u32, value: i64) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed64, &value.to_le_bytes())
+    }
+
+    /// Writes a `float` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_float(&mut self, field_number: u32, value: f32) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed32, &value.to_le_bytes())
+    }
+
+    /// Writes a `double` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_double(&mut self, field_number: u32, value: f64) -> Result<()> {
+        self.write_fixed(field_number, WireType::Fixed64, &value.to_le_bytes())
+    }
+
+    /// Writes a `bytes` field or an encoded nested message.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_bytes(&mut self, field_number: u32, value: &[u8]) -> Result<()> {
+        self.write_scalar(field_number, WireType::Delimited, |buffer| {
+            (value.len() as u64)
+                .varint_encode(buffer)
+                .unwrap_or_default()
+        })?;
+        self.writer.write_all(value)
+    }
+
+    /// Writes a `string` field.
+    ///
+    /// Errors are the same as [`StreamEncoder::write_uint32()`].
+    pub fn write_string(&mut self, field_number: u32, value: &str) -> Result<()> {
+        self.write_bytes(field_number, value.as_bytes())
+    }
+
+    /// Writes a `bytes` field with `len` bytes read from `reader`, without
+    /// buffering them.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `field_number` is not valid.
+    /// - [`Error::OutOfRange`] - `reader` has fewer than `len` bytes.
+    ///
+    /// Any error reading from `reader` or writing to the stream is also
+    /// returned.
+    pub fn write_bytes_from(
+        &mut self,
+        field_number: u32,
+        len: usize,
+        reader: &mut impl Read,
+    ) -> Result<()> {
+        self.write_scalar(field_number, WireType::Delimited, |buffer| {
+            (len as u64).varint_encode(buffer).unwrap_or_default()
+        })?;
+        let mut chunk = [0u8; 32];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = &mut chunk[..remaining.min(32)];
+            reader.read_exact(chunk)?;
+            self.writer.write_all(chunk)?;
+            remaining -= chunk.len();
+        }
+        Ok(())
+    }
+
+    /// Writes a nested message field whose fields are written by `encode`
+    /// with an encoder for `scratch`, which must fit the nested message.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `field_number` is not valid.
+    /// - [`Error::ResourceExhausted`] - The nested message does not fit in
+    ///   `scratch`.
+    ///
+    /// Any error returned by `encode` or writing to the stream is also
+    /// returned.  Nothing is written if `encode` fails.
+    pub fn write_nested(
+        &mut self,
+        field_number: u32,
+        scratch: &mut [u8],
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+    ) -> Result<()> {
+        if !valid_field_number(field_number) {
+            return Err(Error::InvalidArgument);
+        }
+        let mut nested = MemoryEncoder::new(scratch);
+        encode(&mut nested)?;
+        self.write_bytes(field_number, nested.as_slice())
+    }
+}
+
+// What remains to be read of the current field.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pending {
+    // No field has been read, or its value was read.
+    Nothing,
+    // The value of a field with this wire type.
+    Value(WireType),
+    // The data of a length delimited field whose length was read.
+    Data(usize),
+}
+
+/// Decodes a protobuf message from a [`pw_stream::Read`] one field at a
+/// time, matching the C++ `pw::protobuf::StreamDecoder`.
+///
+/// Call [`StreamDecoder::next_field()`] to advance to each field, then read
+/// its value with the `read_*` method for its type.  Values which are not
+/// read are skipped.  Length delimited values are copied into a caller
+/// provided buffer or, for nested messages, decoded with another
+/// `StreamDecoder` by [`StreamDecoder::read_nested()`], so messages of any
+/// size can be decoded without buffering them.
+///
+/// Malformed messages return [`Error::DataLoss`], after which the decoder
+/// should not be used.
+///
+/// ```
+/// use pw_protobuf::StreamDecoder;
+/// use pw_stream::Cursor;
+///
+/// let data = [0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i', 0x1a, 0x02, 0x08, 0x07];
+/// let mut decoder = StreamDecoder::new(Cursor::new(data));
+/// let mut buffer = [0u8; 8];
+///
+/// assert_eq!(decoder.next_field(), Ok(Some(1)));
+/// assert_eq!(decoder.read_uint32(), Ok(150));
+/// assert_eq!(decoder.next_field(), Ok(Some(2)));
+/// assert_eq!(decoder.read_string(&mut buffer), Ok("hi"));
+/// assert_eq!(decoder.next_field(), Ok(Some(3)));
+/// let nested = decoder.read_nested(|nested| {
+///     nested.next_field()?;
+///     nested.read_uint32()
+/// });
+/// assert_eq!(nested, Ok(7));
+/// assert_eq!(decoder.next_field(), Ok(None));
+/// ```
+pub struct StreamDecoder<R: Read> {
+    reader: R,
+    // Bytes left in the message, if it is a nested message.
+    limit: Option<usize>,
+    pending: Pending,
+}
+
+impl<R: Read> StreamDecoder<R> {
+    /// Creates a decoder for the message read from `reader`, which ends when
+    /// `reader` does.
+    pub const fn new(reader: R) -> Self {
+        Self {
+            reader,
+            limit: None,
+            pending: Pending::Nothing,
+        }
+    }
+
+    /// Creates a decoder for a message of `len` bytes read from `reader`.
+    pub const fn with_len(reader: R, len: usize) -> Self {
+        Self {
+            reader,
+            limit: Some(len),
+            pending: Pending::Nothing,
+        }
+    }
+
+    /// Consumes the decoder and returns the reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    // Reads exactly `buffer.len()` bytes of the message.
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+        if let Some(limit) = &mut self.limit {
+            *limit = limit.checked_sub(buffer.len()).ok_or(Error::DataLoss)?;
+        }
+        self.reader.read_exact(buffer).map_err(|error| match error {
+            // The message ended in the middle of a field.
+            Error::OutOfRange => Error::DataLoss,
+            error => error,
+        })
+    }
+
+    // Reads a varint, or returns `None` if the message ended before it.
+    fn read_varint_or_end(&mut self) -> Result<Option<u64>> {
+        let mut value = 0u64;
+        for i in 0..pw_varint::MAX_VARINT64_SIZE_BYTES {
+            let mut byte = [0u8];
+            if i == 0 {
+                let ended = match self.limit {
+                    Some(limit) => limit == 0,
+                    None => self.reader.read(&mut byte)? == 0,
+                };
+                if ended {
+                    return Ok(None);
+                }
+                if self.limit.is_some() {
+                    self.read_exact(&mut byte)?;
+                }
+            } else {
+                self.read_exact(&mut byte)?;
+            }
+            let bits = u64::from(byte[0] & 0x7f);
+            if i == pw_varint::MAX_VARINT64_SIZE_BYTES - 1 && bits > 1 {
+                return Err(Error::DataLoss);
+            }
+            value |= bits << (7 * i);
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(value));
+            }
+        }
+        Err(Error::DataLoss)
+    }
+
+    fn read_raw_varint(&mut self) -> Result<u64> {
+        self.read_varint_or_end()?.ok_or(Error::DataLoss)
+    }
+
+    fn skip(&mut self, mut len: usize) -> Result<()> {
+        let mut buffer = [0u8; 16];
+        while len > 0 {
+            let chunk = len.min(buffer.len());
+            self.read_exact(&mut buffer[..chunk])?;
+            len -= chunk;
+        }
+        Ok(())
+    }
+
+    // Skips the rest of the current field.
+    fn skip_pending(&mut self) -> Result<()> {
+        match core::mem::replace(&mut self.pending, Pending::Nothing) {
+            Pending::Nothing => Ok(()),
+            Pending::Value(WireType::Varint) => self.read_raw_varint().map(|_| ()),
+            Pending::Value(WireType::Fixed64) => self.skip(8),
+            Pending::Value(WireType::Fixed32) => self.skip(4),
+            Pending::Value(WireType::Delimited) => {
+                let len = self.read_len()?;
+                self.skip(len)
+            }
+            Pending::Data(len) => self.skip(len),
+        }
+    }
+
+    fn read_len(&mut self) -> Result<usize> {
+        usize::try_from(self.read_raw_varint()?).map_err(|_| Error::DataLoss)
+    }
+
+    /// Advances to the next field, skipping the value of the current field
+    /// if it was not read, and returns its field number.
+    ///
+    /// Returns `None` at the end of the message.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The message is malformed.
+    ///
+    /// Any error reading from the stream is also returned.
+    pub fn next_field(&mut self) -> Result<Option<u32>> {
+        self.skip_pending()?;
+        let Some(key) = self.read_varint_or_end()? else {
+            return Ok(None);
+        };
+        let number = u32::try_from(key >> FIELD_NUMBER_SHIFT).map_err(|_| Error::DataLoss)?;
+        if !valid_field_number(number) {
+            return Err(Error::DataLoss);
+        }
+        let wire_type = match key & 0x7 {
+            0 => WireType::Varint,
+            1 => WireType::Fixed64,
+            2 => WireType::Delimited,
+            5 => WireType::Fixed32,
+            _ => return Err(Error::DataLoss),
+        };
+        self.pending = Pending::Value(wire_type);
+        Ok(Some(number))
+    }
+
+    /// Returns the wire type of the current field, or `None` if its value was
+    /// read or there is no current field.
+    pub fn wire_type(&self) -> Option<WireType> {
+        match self.pending {
+            Pending::Nothing => None,
+            Pending::Value(wire_type) => Some(wire_type),
+            Pending::Data(_) => Some(WireType::Delimited),
+        }
+    }
+
+    // Starts reading the value of the current field, which must have
+    // `wire_type`.
+    fn take_value(&mut self, wire_type: WireType) -> Result<()> {
+        match self.pending {
+            Pending::Value(pending) if pending == wire_type => {
+                self.pending = Pending::Nothing;
+                Ok(())
+            }
+            Pending::Nothing => Err(Error::FailedPrecondition),
+            _ => Err(Error::DataLoss),
+        }
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        self.take_value(WireType::Varint)?;
+        self.read_raw_varint()
+    }
+
+    /// Reads the value of a `uint32` or enum field.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - There is no current field or its
+    ///   value was already read.
+    /// - [`Error::DataLoss`] - The field has a different wire type, its value
+    ///   does not fit in the type, or the message is malformed.
+    ///
+    /// Any error reading from the stream is also returned.
+    pub fn read_uint32(&mut self) -> Result<u32> {
+        u32::try_from(self.read_varint()?).map_err(|_| Error::DataLoss)
+    }
+
+    /// Reads the value of a `uint64` field.
+    ///
+    /// Errors are the same as [`StreamDecoder::read_uint32()`].
+    pub fn read_uint64(&mut self) -> Result<u64> {
+        self.read_varint()
+    }
+
+    /// Reads the value of an `int32` field.
+    ///
+    /// Errors are the same as [`StreamDecoder::read_uint32()`].
+    pub fn read_int32(&mut self) -> Result<i32> {
+        i32::try_from(self.read_int64()?).map_err(|_| Error::DataLoss)
+    }
+
+    /// Reads the value of an `int64` field.
+    ///
+    /// Errors are the same as [`StreamDecoder::read_uint32()`].
+    pub fn read_int64(&mut self) -> Result<i64> {
+        Ok(self.read_varint()? as i64)
+    }
+
+    /// Reads the value of a ZigZag encoded `sint32` field.
+    ///
+    /// Errors are the same as [`StreamDecoder::read_uint32()`].
+    pub fn read_sint32(&mut self) -> Result<i32> {
+        i32::try_from(self.read_sint64()?).map_err(|_| Error::DataLoss)
+    }
+
+    /// Reads the value of a ZigZag encoded `sint64` field.
+    ///
+    /// Errors are the same as [`StreamDecoder::read_uint32()`].
+    pub fn read_sint64(&mut self) -> Result<i64> {
+        let value = self.read_varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    /// Reads the value of a `bool` field.
+    ///
+    /// Errors are the same as [`StreamDecoder::read_uint32()`].
+    pub fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_varint()? != 0)
+    }
+
+    /// Reads the value of a `fixed32` field.
+    ///
+    /// Errors are the same as [`StreamDecoder::read_uint32()`].
+    pub fn read_fixed32(&mut self) -> Result<u32> {
+        self.take_value(WireType::Fixed32)?;
+        let mut value = [0u8; 4];
+        self.read_exact(&mut value)?;
+        Ok(u32::from_le_bytes(value))
+    }
+
+    /// Reads the value of a `fixed64` field.
+    ///
+    /// Errors are the same as [`StreamDecoder::read_uint32()`].
+    pub fn read_fixed64(&mut self) -> Result<u64> {
+        self.take_value(WireType::Fixed64)?;
+        let mut value = [0u8; 8];
+        self.read_exact(&mut value)?;
+        Ok(u64::from_le_bytes(value))
+    }
+
+    /// Returns the length of the current `bytes`, `string`, or message
+    /// field, without reading its data.
+    ///
+    /// Errors are the same as [`StreamDecoder::read_uint32()`].
+    pub fn bytes_len(&mut self) -> Result<usize> {
+        if let Pending::Data(len) = self.pending {
+            return Ok(len);
+        }
+        self.take_value(WireType::Delimited)?;
+        let len = self.read_len()?;
+        self.pending = Pending::Data(len);
+        Ok(len)
+    }
+
+    // Starts reading the data of the current delimited field and returns its
+    // length.
+    fn take_data(&mut self) -> Result<usize> {
+        let len = self.bytes_len()?;
+        self.pending = Pending::Nothing;
+        Ok(len)
+    }
+
+    /// Reads the data of a `bytes` field into `buffer` and returns it.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - The data does not fit in `buffer`.
+    ///   The field is not read.
+    ///
+    /// Other errors are the same as [`StreamDecoder::read_uint32()`].
+    pub fn read_bytes<'b>(&mut self, buffer: &'b mut [u8]) -> Result<&'b [u8]> {
+        let len = self.bytes_len()?;
+        let data = buffer.get_mut(..len).ok_or(Error::ResourceExhausted)?;
+        self.take_data()?;
+        self.read_exact(data)?;
+        Ok(data)
+    }
+
+    /// Reads the data of a `string` field into `buffer` and returns it.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The string is not valid UTF-8.
+    ///
+    /// Other errors are the same as [`StreamDecoder::read_bytes()`].
+    pub fn read_string<'b>(&mut self, buffer: &'b mut [u8]) -> Result<&'b str> {
+        let data = self.read_bytes(buffer)?;
+        core::str::from_utf8(data).map_err(|_| Error::DataLoss)
+    }
+
+    /// Decodes a nested message field with a decoder passed to `decode`, and
+    /// returns its result.
+    ///
+    /// The rest of the nested message is skipped after `decode` returns, so
+    /// it does not need to read every field.
+    ///
+    /// Errors are the same as [`StreamDecoder::read_uint32()`].  Any error
+    /// returned by `decode` is also returned.
+    pub fn read_nested<T>(
+        &mut self,
+        decode: impl FnOnce(&mut StreamDecoder<&mut R>) -> Result<T>,
+    ) -> Result<T> {
+        let len = self.take_data()?;
+        if let Some(limit) = &mut self.limit {
+            *limit = limit.checked_sub(len).ok_or(Error::DataLoss)?;
+        }
+        let mut nested = StreamDecoder::with_len(&mut self.reader, len);
+        let result = decode(&mut nested)?;
+        nested.skip_pending()?;
+        let remaining = nested.limit.unwrap_or_default();
+        nested.limit = Some(remaining);
+        nested.skip(remaining)?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_stream::Cursor;
+
+    use super::*;
+    use crate::{Decoder, MAX_FIELD_NUMBER};
+
+    // Encodes a message with every type of field.
+    fn encode_message<W: Write>(encoder: &mut StreamEncoder<W>) -> Result<()> {
+        encoder.write_uint32(1, 150)?;
+        encoder.write_int32(2, -1)?;
+        encoder.write_sint64(3, -2)?;
+        encoder.write_bool(4, true)?;
+        encoder.write_fixed32(5, 0x01020304)?;
+        encoder.write_fixed64(6, 0x0102030405060708)?;
+        encoder.write_string(7, "hi")?;
+        encoder.write_nested(8, &mut [0u8; 16], |nested| {
+            nested.write_uint32(1, 7)?;
+            nested.write_bytes(2, b"skipped")
+        })?;
+        encoder.write_uint64(MAX_FIELD_NUMBER, u64::MAX)
+    }
+
+    #[test]
+    fn stream_encoder_matches_memory_encoder() {
+        let mut encoder = StreamEncoder::new(Cursor::new([0u8; 128]));
+        encode_message(&mut encoder).unwrap();
+        let cursor = encoder.into_inner();
+        let len = cursor.position();
+
+        let mut buffer = [0u8; 128];
+        let mut expected = MemoryEncoder::new(&mut buffer);
+        expected.write_uint32(1, 150).unwrap();
+        expected.write_int32(2, -1).unwrap();
+        expected.write_sint64(3, -2).unwrap();
+        expected.write_bool(4, true).unwrap();
+        expected.write_fixed32(5, 0x01020304).unwrap();
+        expected.write_fixed64(6, 0x0102030405060708).unwrap();
+        expected.write_string(7, "hi").unwrap();
+        expected
+            .write_nested(8, |nested| {
+                nested.write_uint32(1, 7)?;
+                nested.write_bytes(2, b"skipped")
+            })
+            .unwrap();
+        expected.write_uint64(MAX_FIELD_NUMBER, u64::MAX).unwrap();
+        assert_eq!(&cursor.into_inner()[..len], expected.as_slice());
+    }
+
+    #[test]
+    fn stream_encoder_errors_are_returned() {
+        let mut encoder = StreamEncoder::new(Cursor::new([0u8; 4]));
+        assert_eq!(encoder.write_uint32(0, 1), Err(Error::InvalidArgument));
+        assert_eq!(
+            encoder.write_nested(19000, &mut [], |_| Ok(())),
+            Err(Error::InvalidArgument)
+        );
+        assert_eq!(encoder.write_bytes(1, b"long"), Err(Error::OutOfRange));
+        assert_eq!(
+            encoder.write_nested(1, &mut [0u8; 1], |nested| nested.write_uint32(1, 1)),
+            Err(Error::ResourceExhausted)
+        );
+    }
+
+    #[test]
+    fn bytes_are_copied_from_reader() {
+        let expected: [u8; 100] = core::array::from_fn(|i| i as u8);
+        let mut encoder = StreamEncoder::new(Cursor::new([0u8; 128]));
+        encoder
+            .write_bytes_from(1, expected.len(), &mut Cursor::new(expected))
+            .unwrap();
+        assert_eq!(
+            encoder.write_bytes_from(2, 10, &mut Cursor::new([0u8; 4])),
+            Err(Error::OutOfRange)
+        );
+
+        let data = encoder.into_inner().into_inner();
+        let mut decoder = Decoder::new(&data[..102]);
+        let field = decoder.next().unwrap().unwrap();
+        assert_eq!(field.number, 1);
+        assert_eq!(field.value.as_bytes(), Ok(&expected[..]));
+    }
+
+    #[test]
+    fn stream_decoder_reads_every_type() {
+        let mut encoder = StreamEncoder::new(Cursor::new([0u8; 128]));
+        encode_message(&mut encoder).unwrap();
+        let cursor = encoder.into_inner();
+        let len = cursor.position();
+        let data = cursor.into_inner();
+
+        let mut decoder = StreamDecoder::new(Cursor::new(&data[..len]));
+        let mut buffer = [0u8; 4];
+        assert_eq!(decoder.read_uint32(), Err(Error::FailedPrecondition));
+        assert_eq!(decoder.next_field(), Ok(Some(1)));
+        assert_eq!(decoder.wire_type(), Some(WireType::Varint));
+        assert_eq!(decoder.read_fixed32(), Err(Error::DataLoss));
+        assert_eq!(decoder.read_uint32(), Ok(150));
+        assert_eq!(decoder.wire_type(), None);
+        assert_eq!(decoder.next_field(), Ok(Some(2)));
+        assert_eq!(decoder.read_int32(), Ok(-1));
+        assert_eq!(decoder.next_field(), Ok(Some(3)));
+        assert_eq!(decoder.read_sint64(), Ok(-2));
+        assert_eq!(decoder.next_field(), Ok(Some(4)));
+        assert_eq!(decoder.read_bool(), Ok(true));
+        assert_eq!(decoder.next_field(), Ok(Some(5)));
+        assert_eq!(decoder.read_fixed32(), Ok(0x01020304));
+        assert_eq!(decoder.next_field(), Ok(Some(6)));
+        assert_eq!(decoder.read_fixed64(), Ok(0x0102030405060708));
+        assert_eq!(decoder.next_field(), Ok(Some(7)));
+        assert_eq!(decoder.bytes_len(), Ok(2));
+        assert_eq!(decoder.read_string(&mut buffer), Ok("hi"));
+        assert_eq!(decoder.next_field(), Ok(Some(8)));
+        assert_eq!(
+            decoder.read_bytes(&mut buffer),
+            Err(Error::ResourceExhausted)
+        );
+        let nested = decoder.read_nested(|nested| {
+            assert_eq!(nested.next_field(), Ok(Some(1)));
+            nested.read_uint32()
+        });
+        assert_eq!(nested, Ok(7));
+        assert_eq!(decoder.next_field(), Ok(Some(MAX_FIELD_NUMBER)));
+        assert_eq!(decoder.read_uint64(), Ok(u64::MAX));
+        assert_eq!(decoder.next_field(), Ok(None));
+    }
+
+    #[test]
+    fn unread_values_are_skipped() {
+        let mut encoder = StreamEncoder::new(Cursor::new([0u8; 128]));
+        encode_message(&mut encoder).unwrap();
+        let cursor = encoder.into_inner();
+        let len = cursor.position();
+        let data = cursor.into_inner();
+
+        let mut decoder = StreamDecoder::new(Cursor::new(&data[..len]));
+        let mut fields = [0u32; 9];
+        for field in &mut fields {
+            *field = decoder.next_field().unwrap().unwrap();
+        }
+        assert_eq!(fields, [1, 2, 3, 4, 5, 6, 7, 8, MAX_FIELD_NUMBER]);
+        assert_eq!(decoder.next_field(), Ok(None));
+    }
+
+    #[test]
+    fn malformed_messages_are_data_loss() {
+        for data in [
+            &[0x08][..],
+            &[0x12, 0x03, b'h', b'i'],
+            &[0x1d, 0x01, 0x02],
+            &[0x0b],
+            &[0x00, 0x01],
+            &[
+                0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f,
+            ],
+        ] {
+            let mut decoder = StreamDecoder::new(Cursor::new(data));
+            let result = decoder.next_field().and_then(|_| decoder.next_field());
+            assert_eq!(result, Err(Error::DataLoss), "{data:?}");
+        }
+
+        // A nested field which is longer than its message.
+        let data = [0x0a, 0x02, 0x12, 0x05, b'h', b'e', b'l', b'l', b'o'];
+        let mut decoder = StreamDecoder::new(Cursor::new(data));
+        decoder.next_field().unwrap();
+        let result = decoder.read_nested(|nested| {
+            nested.next_field()?;
+            nested.read_bytes(&mut [0u8; 8]).map(|_| ())
+        });
+        assert_eq!(result, Err(Error::DataLoss));
+    }
+}
@@ -0,0 +1,1022 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Generates Rust structs for protobuf messages from `.proto` files in a
+//! build script.
+//!
+//! Each message becomes a struct with a public field for each of its fields,
+//! and methods which encode and decode it with `pw_protobuf`:
+//!
+//! - `encode(&self, buffer)` encodes the message into a buffer, and
+//!   `encode_fields(&self, encoder)` writes its fields to a
+//!   [`pw_protobuf::MemoryEncoder`].
+//! - `decode(data)` decodes a message, and `merge(&mut self, data)` decodes
+//!   fields into an existing message.  Unknown fields are skipped.
+//! - If every field has a bounded size, `MAX_ENCODED_SIZE` is the size of
+//!   the largest encoding of the message, so buffers can be sized at compile
+//!   time.
+//!
+//! Enums become a newtype of `i32` with a constant for each value, since
+//! decoded enum fields may hold values the `.proto` file does not declare.
+//! Nested messages and enums are generated in a module named after the
+//! message which declares them.
+//!
+//! Field types are:
+//!
+//! | Protobuf                                   | Rust                            |
+//! |--------------------------------------------|---------------------------------|
+//! | `int32`, `sint32`, `sfixed32`              | `i32`                           |
+//! | `int64`, `sint64`, `sfixed64`              | `i64`                           |
+//! | `uint32`, `fixed32`                        | `u32`                           |
+//! | `uint64`, `fixed64`                        | `u64`                           |
+//! | `float`, `double`, `bool`                  | `f32`, `f64`, `bool`            |
+//! | `string` with `max_size`                   | `heapless::String<max_size>`    |
+//! | `bytes` with `max_size`                    | `heapless::Vec<u8, max_size>`   |
+//! | `string`, `bytes`                          | `alloc::string::String`, `alloc::vec::Vec<u8>` |
+//! | Message                                    | `Option<Message>`               |
+//! | `optional` scalar                          | `Option<T>`                     |
+//! | `repeated` with `max_count`                | `heapless::Vec<T, max_count>`   |
+//! | `repeated`                                 | `alloc::vec::Vec<T>`            |
+//!
+//! Bounds are set in an options file next to the `.proto` file, in the same
+//! format as the C++ `pw_protobuf` code generator.  Each line has a field's
+//! fully qualified name, which may contain `*` wildcards, and its options:
+//!
+//! ```text
+//! pw.example.Reading.sensor_name max_size:16
+//! pw.example.Batch.readings max_count:8
+//! ```
+//!
+//! Messages with only bounded fields can be used without `alloc`, and need
+//! the `heapless` crate if they have strings, bytes, or repeated fields.
+//! Other messages need `alloc`.  `map` fields, `oneof`s, and groups are not
+//! supported.
+//!
+//! Call [`compile_protos()`] from `build.rs`:
+//!
+//! ```no_run
+//! pw_protobuf_codegen::compile_protos(&["protos/sensor.proto"]).unwrap();
+//! ```
+//!
+//! Then include the generated code, which is named after the `.proto` file:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/sensor.pb.rs"));
+//!
+//! let reading = Reading { sensor_id: 3, value: 21.5, ..Default::default() };
+//! let mut buffer = [0u8; Reading::MAX_ENCODED_SIZE];
+//! let encoded = reading.encode(&mut buffer)?;
+//! assert_eq!(Reading::decode(encoded)?, reading);
+//! ```
+//!
+//! *Note*: This module requires `std`.
+//!
+//! [`pw_protobuf::MemoryEncoder`]: ../pw_protobuf/struct.MemoryEncoder.html
+#![deny(missing_docs)]
+
+use std::fmt::{self, Write};
+use std::path::Path;
+
+mod parser;
+
+use parser::parse_error;
+
+pub use parser::{
+    parse, Enum, EnumValue, Error, Field, Label, Message, Method, ProtoFile, Service,
+};
+
+// Returns true if `name` matches `pattern`, in which `*` matches any text.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| matches_pattern(rest, &name[i..]))
+        }
+    }
+}
+
+fn apply_to_fields(
+    messages: &mut [Message],
+    scope: &str,
+    pattern: &str,
+    apply: &mut impl FnMut(&mut Field),
+) {
+    for message in messages {
+        let name = if scope.is_empty() {
+            message.name.clone()
+        } else {
+            format!("{scope}.{}", message.name)
+        };
+        for field in &mut message.fields {
+            if matches_pattern(pattern, &format!("{name}.{}", field.name)) {
+                apply(field);
+            }
+        }
+        apply_to_fields(&mut message.messages, &name, pattern, apply);
+    }
+}
+
+/// Sets the `max_size` and `max_count` of fields from the contents of an
+/// options file.
+///
+/// Options which this generator does not use, such as those for other
+/// languages, are ignored.
+pub fn apply_options(file: &mut ProtoFile, options: &str) -> Result<(), Error> {
+    let package = file.package.clone().unwrap_or_default();
+    for (i, line) in options.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let line = line.split("//").next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(pattern) = words.next() else {
+            continue;
+        };
+        let mut max_size = None;
+        let mut max_count = None;
+        for option in words {
+            let Some((key, value)) = option.split_once(':') else {
+                return Err(parse_error(i + 1, format!("invalid option `{option}`")));
+            };
+            let target = match key {
+                "max_size" => &mut max_size,
+                "max_count" => &mut max_count,
+                _ => continue,
+            };
+            *target = Some(
+                value
+                    .parse::<usize>()
+                    .map_err(|_| parse_error(i + 1, format!("invalid {key} `{value}`")))?,
+            );
+        }
+        apply_to_fields(&mut file.messages, &package, pattern, &mut |field| {
+            field.max_size = max_size.or(field.max_size);
+            field.max_count = max_count.or(field.max_count);
+        });
+    }
+    Ok(())
+}
+
+/// Converts a `CamelCase` name to `snake_case`, as the generated code names
+/// modules and functions.
+pub fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() {
+            let previous = i.checked_sub(1).map(|i| chars[i]);
+            let next = chars.get(i + 1);
+            if previous.is_some_and(|p| p.is_ascii_lowercase() || p.is_ascii_digit())
+                || (previous.is_some_and(|p| p.is_ascii_uppercase())
+                    && next.is_some_and(|n| n.is_ascii_lowercase()))
+            {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// Returns `name` as an identifier, escaping keywords.
+pub fn identifier(name: String) -> String {
+    const KEYWORDS: &[&str] = &[
+        "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do",
+        "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in",
+        "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+        "return", "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe",
+        "unsized", "use", "virtual", "where", "while", "yield",
+    ];
+    match name.as_str() {
+        // These keywords can not be raw identifiers.
+        "crate" | "self" | "super" | "Self" => format!("{name}_"),
+        _ if KEYWORDS.contains(&name.as_str()) => format!("r#{name}"),
+        _ => name,
+    }
+}
+
+// A scalar field type.
+struct Scalar {
+    rust_type: &'static str,
+    wire_type: &'static str,
+    // The `pw_protobuf::Value` method which decodes it.
+    decode: &'static str,
+    // The `pw_protobuf::MemoryEncoder` method which encodes it.
+    encode: &'static str,
+    max_size: usize,
+}
+
+fn scalar(type_name: &str) -> Option<Scalar> {
+    let (rust_type, wire_type, decode, encode, max_size) = match type_name {
+        "double" => ("f64", "Fixed64", "as_double", "write_double", 8),
+        "float" => ("f32", "Fixed32", "as_float", "write_float", 4),
+        "int32" => ("i32", "Varint", "as_i32", "write_int32", 10),
+        "int64" => ("i64", "Varint", "as_i64", "write_int64", 10),
+        "uint32" => ("u32", "Varint", "as_u32", "write_uint32", 5),
+        "uint64" => ("u64", "Varint", "as_u64", "write_uint64", 10),
+        "sint32" => ("i32", "Varint", "as_sint32", "write_sint32", 5),
+        "sint64" => ("i64", "Varint", "as_sint64", "write_sint64", 10),
+        "fixed32" => ("u32", "Fixed32", "as_fixed32", "write_fixed32", 4),
+        "fixed64" => ("u64", "Fixed64", "as_fixed64", "write_fixed64", 8),
+        "sfixed32" => ("i32", "Fixed32", "as_sfixed32", "write_sfixed32", 4),
+        "sfixed64" => ("i64", "Fixed64", "as_sfixed64", "write_sfixed64", 8),
+        "bool" => ("bool", "Varint", "as_bool", "write_bool", 1),
+        _ => return None,
+    };
+    Some(Scalar {
+        rust_type,
+        wire_type,
+        decode,
+        encode,
+        max_size,
+    })
+}
+
+// The type of a field, resolved against the types declared in the file.
+enum FieldType<'f> {
+    Scalar(Scalar),
+    String,
+    Bytes,
+    // The Rust path of an enum.
+    Enum(String),
+    // The Rust path of a message, and the message if it is in this file.
+    Message(String, Option<&'f Message>),
+}
+
+// A message or enum declared in the file.
+struct Declaration<'f> {
+    // The names of the messages it is nested in, then its name.
+    path: Vec<&'f str>,
+    message: Option<&'f Message>,
+}
+
+fn collect_declarations<'f>(
+    messages: &'f [Message],
+    enums: &'f [Enum],
+    scope: &[&'f str],
+    declarations: &mut Vec<Declaration<'f>>,
+) {
+    for enumeration in enums {
+        let mut path = scope.to_vec();
+        path.push(&enumeration.name);
+        declarations.push(Declaration {
+            path,
+            message: None,
+        });
+    }
+    for message in messages {
+        let mut path = scope.to_vec();
+        path.push(&message.name);
+        collect_declarations(&message.messages, &message.enums, &path, declarations);
+        declarations.push(Declaration {
+            path,
+            message: Some(message),
+        });
+    }
+}
+
+struct Generator<'f> {
+    package: Option<&'f str>,
+    declarations: Vec<Declaration<'f>>,
+}
+
+impl<'f> Generator<'f> {
+    fn new(file: &'f ProtoFile) -> Self {
+        let mut declarations = Vec::new();
+        collect_declarations(&file.messages, &file.enums, &[], &mut declarations);
+        Generator {
+            package: file.package.as_deref(),
+            declarations,
+        }
+    }
+
+    // Resolves a type name used by a field of the message at `scope`, which
+    // ends with the message's name, to a Rust path relative to the module
+    // the message is generated in, as protobuf name resolution does.
+    fn resolve(&self, type_name: &str, scope: &[&str]) -> FieldType<'f> {
+        match type_name {
+            "string" => return FieldType::String,
+            "bytes" => return FieldType::Bytes,
+            _ => {}
+        }
+        if let Some(scalar) = scalar(type_name) {
+            return FieldType::Scalar(scalar);
+        }
+        let mut name = type_name.trim_start_matches('.');
+        let absolute = name.len() != type_name.len();
+        if let Some(package) = self.package {
+            if let Some(relative) = name
+                .strip_prefix(package)
+                .and_then(|name| name.strip_prefix('.'))
+            {
+                name = relative;
+            }
+        }
+        let parts: Vec<&str> = name.split('.').collect();
+        // Search the scope of the message and each enclosing scope.
+        let scopes = if absolute { 0..=0 } else { 0..=scope.len() };
+        let found = scopes.rev().find_map(|depth| {
+            let mut path = scope[..depth].to_vec();
+            path.extend(&parts);
+            self.declarations
+                .iter()
+                .find(|declaration| declaration.path == path)
+        });
+        let up = "super::".repeat(scope.len().saturating_sub(1));
+        match found {
+            Some(declaration) => {
+                let (name, modules) = declaration.path.split_last().unwrap();
+                let mut rust_path = up;
+                for module in modules {
+                    rust_path.push_str(&identifier(snake_case(module)));
+                    rust_path.push_str("::");
+                }
+                rust_path.push_str(name);
+                match declaration.message {
+                    Some(message) => FieldType::Message(rust_path, Some(message)),
+                    None => FieldType::Enum(rust_path),
+                }
+            }
+            // Types from imported files must be in scope where the code is
+            // included.
+            None => FieldType::Message(format!("{up}{}", parts[parts.len() - 1]), None),
+        }
+    }
+
+    fn message_scope(&self, message: &Message) -> Vec<&'f str> {
+        let declaration = self
+            .declarations
+            .iter()
+            .find(|declaration| {
+                declaration
+                    .message
+                    .is_some_and(|m| std::ptr::eq(m, message))
+            })
+            .unwrap();
+        declaration.path[..declaration.path.len() - 1].to_vec()
+    }
+
+    // Returns the Rust type of a single value of `field`.
+    fn value_type(&self, field: &Field, field_type: &FieldType) -> String {
+        match field_type {
+            FieldType::Scalar(scalar) => scalar.rust_type.to_string(),
+            FieldType::String => match field.max_size {
+                Some(max_size) => format!("heapless::String<{max_size}>"),
+                None => "alloc::string::String".to_string(),
+            },
+            FieldType::Bytes => match field.max_size {
+                Some(max_size) => format!("heapless::Vec<u8, {max_size}>"),
+                None => "alloc::vec::Vec<u8>".to_string(),
+            },
+            FieldType::Enum(path) | FieldType::Message(path, _) => path.clone(),
+        }
+    }
+
+    fn rust_type(&self, field: &Field, field_type: &FieldType) -> String {
+        let value_type = self.value_type(field, field_type);
+        match (field.label, field_type) {
+            (Label::Repeated, _) => match field.max_count {
+                Some(max_count) => format!("heapless::Vec<{value_type}, {max_count}>"),
+                None => format!("alloc::vec::Vec<{value_type}>"),
+            },
+            (Label::Optional, _) | (_, FieldType::Message(..)) => format!("Option<{value_type}>"),
+            (Label::Implicit, _) => value_type,
+        }
+    }
+
+    // Returns the largest encoded size of a message as a constant expression,
+    // or `None` if it is unbounded.  `visiting` holds the messages whose size
+    // is being computed, to detect recursive messages.
+    fn max_size(&self, message: &'f Message, visiting: &mut Vec<*const Message>) -> Option<String> {
+        if visiting.contains(&(message as *const _)) {
+            return None;
+        }
+        visiting.push(message);
+        let scope = {
+            let mut scope = self.message_scope(message);
+            scope.push(&message.name);
+            scope
+        };
+        let mut total = 0;
+        let mut terms = Vec::new();
+        for field in &message.fields {
+            let count = match field.label {
+                Label::Repeated => field.max_count?,
+                _ => 1,
+            };
+            let key_size = |wire_type: u32| varint_size(u64::from(field.number << 3 | wire_type));
+            match self.resolve(&field.type_name, &scope) {
+                FieldType::Scalar(scalar) => {
+                    let wire_type = match scalar.wire_type {
+                        "Varint" => 0,
+                        "Fixed64" => 1,
+                        _ => 5,
+                    };
+                    total += count * (key_size(wire_type) + scalar.max_size);
+                }
+                FieldType::Enum(_) => total += count * (key_size(0) + 10),
+                FieldType::String | FieldType::Bytes => {
+                    let max_size = field.max_size?;
+                    total += count * (key_size(2) + varint_size(max_size as u64) + max_size);
+                }
+                FieldType::Message(path, Some(nested)) => {
+                    self.max_size(nested, visiting)?;
+                    let size = format!(
+                        "pw_protobuf::size_of_delimited_field({}, {path}::MAX_ENCODED_SIZE)",
+                        field.number
+                    );
+                    terms.push(if count == 1 {
+                        size
+                    } else {
+                        format!("{count} * {size}")
+                    });
+                }
+                FieldType::Message(_, None) => return None,
+            }
+        }
+        visiting.pop();
+        if total != 0 || terms.is_empty() {
+            terms.insert(0, total.to_string());
+        }
+        Some(terms.join(" + "))
+    }
+
+    fn generate_enum(&self, code: &mut String, indent: &str, enumeration: &Enum) -> fmt::Result {
+        let name = &enumeration.name;
+        writeln!(code, "{indent}/// The `{name}` enum.")?;
+        writeln!(
+            code,
+            "{indent}#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]"
+        )?;
+        writeln!(code, "{indent}pub struct {name}(pub i32);")?;
+        writeln!(code)?;
+        writeln!(code, "{indent}impl {name} {{")?;
+        for value in &enumeration.values {
+            writeln!(code, "{indent}    /// `{}`.", value.name)?;
+            writeln!(
+                code,
+                "{indent}    pub const {}: Self = Self({});",
+                identifier(value.name.clone()),
+                value.number
+            )?;
+        }
+        writeln!(code, "{indent}}}")
+    }
+
+    fn generate_message(
+        &self,
+        code: &mut String,
+        indent: &str,
+        message: &'f Message,
+    ) -> fmt::Result {
+        let name = &message.name;
+        let mut scope = self.message_scope(message);
+        let full_name = self
+            .package
+            .iter()
+            .copied()
+            .chain(scope.iter().copied())
+            .chain([name.as_str()])
+            .collect::<Vec<_>>()
+            .join(".");
+        scope.push(name);
+        let fields: Vec<(&Field, FieldType, String)> = message
+            .fields
+            .iter()
+            .map(|field| {
+                (
+                    field,
+                    self.resolve(&field.type_name, &scope),
+                    identifier(field.name.clone()),
+                )
+            })
+            .collect();
+
+        // The struct is `PartialEq` but not `Eq`, since fields may be floats.
+        writeln!(code, "{indent}/// The `{full_name}` message.")?;
+        writeln!(code, "{indent}#[derive(Clone, Debug, Default, PartialEq)]")?;
+        writeln!(code, "{indent}pub struct {name} {{")?;
+        for (field, field_type, rust_name) in &fields {
+            writeln!(code, "{indent}    /// `{}`.", field.name)?;
+            writeln!(
+                code,
+                "{indent}    pub {rust_name}: {},",
+                self.rust_type(field, field_type)
+            )?;
+        }
+        writeln!(code, "{indent}}}")?;
+        writeln!(code)?;
+
+        writeln!(code, "{indent}impl {name} {{")?;
+        if let Some(max_size) = self.max_size(message, &mut Vec::new()) {
+            writeln!(
+                code,
+                "{indent}    /// The size of the largest encoding of the message."
+            )?;
+            writeln!(
+                code,
+                "{indent}    pub const MAX_ENCODED_SIZE: usize = {max_size};"
+            )?;
+            writeln!(code)?;
+        }
+        writeln!(
+            code,
+            "{indent}    /// Writes the message's fields with `encoder`."
+        )?;
+        let encoder = if fields.is_empty() {
+            "_encoder"
+        } else {
+            "encoder"
+        };
+        writeln!(code, "{indent}    pub fn encode_fields(&self, {encoder}: &mut pw_protobuf::MemoryEncoder) -> pw_status::Result<()> {{")?;
+        for (field, field_type, rust_name) in &fields {
+            let number = field.number;
+            let write = |value: &str| match field_type {
+                FieldType::Scalar(scalar) => {
+                    format!("encoder.{}({number}, {value})?", scalar.encode)
+                }
+                FieldType::Enum(_) => format!("encoder.write_int32({number}, {value}.0)?"),
+                FieldType::String => format!("encoder.write_string({number}, {value})?"),
+                FieldType::Bytes => format!("encoder.write_bytes({number}, {value})?"),
+                FieldType::Message(..) => format!(
+                    "encoder.write_nested({number}, |encoder| {value}.encode_fields(encoder))?"
+                ),
+            };
+            match (field.label, field_type) {
+                (Label::Repeated, _) | (Label::Optional, _) | (_, FieldType::Message(..)) => {
+                    let value = match field_type {
+                        FieldType::Scalar(_) => "*value",
+                        _ => "value",
+                    };
+                    let head = if field.label == Label::Repeated {
+                        format!("for value in &self.{rust_name}")
+                    } else {
+                        format!("if let Some(value) = &self.{rust_name}")
+                    };
+                    writeln!(code, "{indent}        {head} {{")?;
+                    writeln!(code, "{indent}            {};", write(value))?;
+                    writeln!(code, "{indent}        }}")?;
+                }
+                (Label::Implicit, _) => {
+                    let value = format!("self.{rust_name}");
+                    // Fields with implicit presence are not encoded when they
+                    // have their default value.
+                    let condition = match field_type {
+                        FieldType::Scalar(scalar) => match scalar.rust_type {
+                            "bool" => value.clone(),
+                            "f32" | "f64" => format!("{value}.to_bits() != 0"),
+                            _ => format!("{value} != 0"),
+                        },
+                        FieldType::Enum(_) => format!("{value}.0 != 0"),
+                        _ => format!("!{value}.is_empty()"),
+                    };
+                    let value = match field_type {
+                        FieldType::String | FieldType::Bytes => format!("&{value}"),
+                        _ => value,
+                    };
+                    writeln!(code, "{indent}        if {condition} {{")?;
+                    writeln!(code, "{indent}            {};", write(&value))?;
+                    writeln!(code, "{indent}        }}")?;
+                }
+            }
+        }
+        writeln!(code, "{indent}        Ok(())")?;
+        writeln!(code, "{indent}    }}")?;
+        writeln!(code)?;
+
+        writeln!(
+            code,
+            "{indent}    /// Encodes the message into `buffer` and returns the encoded message."
+        )?;
+        writeln!(code, "{indent}    pub fn encode<'b>(&self, buffer: &'b mut [u8]) -> pw_status::Result<&'b [u8]> {{")?;
+        writeln!(
+            code,
+            "{indent}        let mut encoder = pw_protobuf::MemoryEncoder::new(buffer);"
+        )?;
+        writeln!(code, "{indent}        self.encode_fields(&mut encoder)?;")?;
+        writeln!(code, "{indent}        Ok(encoder.into_slice())")?;
+        writeln!(code, "{indent}    }}")?;
+        writeln!(code)?;
+
+        writeln!(code, "{indent}    /// Decodes a message from `data`.")?;
+        writeln!(
+            code,
+            "{indent}    pub fn decode(data: &[u8]) -> pw_status::Result<Self> {{"
+        )?;
+        writeln!(code, "{indent}        let mut message = Self::default();")?;
+        writeln!(code, "{indent}        message.merge(data)?;")?;
+        writeln!(code, "{indent}        Ok(message)")?;
+        writeln!(code, "{indent}    }}")?;
+        writeln!(code)?;
+
+        writeln!(
+            code,
+            "{indent}    /// Decodes the fields in `data` into the message, replacing singular fields,"
+        )?;
+        writeln!(
+            code,
+            "{indent}    /// merging messages, and appending to repeated fields."
+        )?;
+        if fields.len() == 1 {
+            writeln!(code, "{indent}    #[allow(clippy::single_match)]")?;
+        }
+        writeln!(
+            code,
+            "{indent}    pub fn merge(&mut self, data: &[u8]) -> pw_status::Result<()> {{"
+        )?;
+        writeln!(
+            code,
+            "{indent}        for field in pw_protobuf::Decoder::new(data) {{"
+        )?;
+        if fields.is_empty() {
+            writeln!(code, "{indent}            field?;")?;
+            writeln!(code, "{indent}        }}")?;
+            writeln!(code, "{indent}        Ok(())")?;
+            writeln!(code, "{indent}    }}")?;
+            writeln!(code, "{indent}}}")?;
+            return self.generate_nested(code, indent, message, &full_name);
+        }
+        writeln!(
+            code,
+            "{indent}            let pw_protobuf::Field {{ number, value }} = field?;"
+        )?;
+        writeln!(code, "{indent}            match number {{")?;
+        for (field, field_type, rust_name) in &fields {
+            let number = field.number;
+            let bounded = field.max_size.is_some();
+            let decode = |value: &str| {
+                match field_type {
+                FieldType::Scalar(scalar) => format!("{value}.{}()?", scalar.decode),
+                FieldType::Enum(path) => format!("{path}({value}.as_i32()?)"),
+                FieldType::String if bounded => format!("{{ let mut string = heapless::String::new(); string.push_str({value}.as_str()?).map_err(|_| pw_status::Error::ResourceExhausted)?; string }}"),
+                FieldType::String => format!("{value}.as_str()?.into()"),
+                FieldType::Bytes if bounded => format!("heapless::Vec::from_slice({value}.as_bytes()?).map_err(|_| pw_status::Error::ResourceExhausted)?"),
+                FieldType::Bytes => format!("{value}.as_bytes()?.into()"),
+                FieldType::Message(path, _) => format!("{{ let mut message = {path}::default(); message.merge({value}.as_bytes()?)?; message }}"),
+            }
+            };
+            match (field.label, field_type) {
+                (Label::Repeated, _) => {
+                    let push = |value: &str| {
+                        if field.max_count.is_some() {
+                            format!("self.{rust_name}.push({value}).map_err(|_| pw_status::Error::ResourceExhausted)?;")
+                        } else {
+                            format!("self.{rust_name}.push({value});")
+                        }
+                    };
+                    let packable = match field_type {
+                        FieldType::Scalar(scalar) => Some(scalar.wire_type),
+                        FieldType::Enum(_) => Some("Varint"),
+                        _ => None,
+                    };
+                    match packable {
+                        Some(wire_type) => {
+                            writeln!(code, "{indent}                {number} => {{")?;
+                            writeln!(code, "{indent}                    for value in value.repeated(pw_protobuf::WireType::{wire_type}) {{")?;
+                            writeln!(
+                                code,
+                                "{indent}                        {}",
+                                push(&decode("value?"))
+                            )?;
+                            writeln!(code, "{indent}                    }}")?;
+                            writeln!(code, "{indent}                }}")?;
+                        }
+                        None => {
+                            writeln!(code, "{indent}                {number} => {{")?;
+                            writeln!(code, "{indent}                    {}", push(&decode("value")))?;
+                            writeln!(code, "{indent}                }}")?;
+                        }
+                    }
+                }
+                (_, FieldType::Message(..)) => writeln!(
+                    code,
+                    "{indent}                {number} => self.{rust_name}.get_or_insert_with(Default::default).merge(value.as_bytes()?)?,"
+                )?,
+                (Label::Optional, _) => writeln!(
+                    code,
+                    "{indent}                {number} => self.{rust_name} = Some({}),",
+                    decode("value")
+                )?,
+                (Label::Implicit, _) => writeln!(
+                    code,
+                    "{indent}                {number} => self.{rust_name} = {},",
+                    decode("value")
+                )?,
+            }
+        }
+        writeln!(code, "{indent}                _ => {{}}")?;
+        writeln!(code, "{indent}            }}")?;
+        writeln!(code, "{indent}        }}")?;
+        writeln!(code, "{indent}        Ok(())")?;
+        writeln!(code, "{indent}    }}")?;
+        writeln!(code, "{indent}}}")?;
+        self.generate_nested(code, indent, message, &full_name)
+    }
+
+    // Generates the module of the messages and enums declared in `message`.
+    fn generate_nested(
+        &self,
+        code: &mut String,
+        indent: &str,
+        message: &'f Message,
+        full_name: &str,
+    ) -> fmt::Result {
+        if message.messages.is_empty() && message.enums.is_empty() {
+            return Ok(());
+        }
+        let module = identifier(snake_case(&message.name));
+        writeln!(code)?;
+        writeln!(
+            code,
+            "{indent}/// The messages and enums declared in `{full_name}`."
+        )?;
+        writeln!(code, "{indent}pub mod {module} {{")?;
+        self.generate_declarations(
+            code,
+            &format!("{indent}    "),
+            &message.messages,
+            &message.enums,
+        )?;
+        writeln!(code, "{indent}}}")
+    }
+
+    fn generate_declarations(
+        &self,
+        code: &mut String,
+        indent: &str,
+        messages: &'f [Message],
+        enums: &'f [Enum],
+    ) -> fmt::Result {
+        let mut first = true;
+        for enumeration in enums {
+            if !std::mem::take(&mut first) {
+                writeln!(code)?;
+            }
+            self.generate_enum(code, indent, enumeration)?;
+        }
+        for message in messages {
+            if !std::mem::take(&mut first) {
+                writeln!(code)?;
+            }
+            self.generate_message(code, indent, message)?;
+        }
+        Ok(())
+    }
+}
+
+fn varint_size(value: u64) -> usize {
+    pw_protobuf::varint_size(value)
+}
+
+/// Returns the Rust path of the message type `type_name`, as written in a
+/// method of `file`, relative to where the code for `file` is included.
+///
+/// Types from imported files are referred to by their name, so they must be
+/// in scope where the code is included.
+pub fn message_path(file: &ProtoFile, type_name: &str) -> String {
+    match Generator::new(file).resolve(type_name, &[]) {
+        FieldType::Enum(path) | FieldType::Message(path, _) => path,
+        _ => type_name.to_string(),
+    }
+}
+
+/// Generates the code for the messages and enums of a `.proto` file.
+pub fn generate(file: &ProtoFile) -> String {
+    let generator = Generator::new(file);
+    let mut code = String::new();
+    // Writing to a `String` can not fail.
+    let _ = generator.generate_declarations(&mut code, "", &file.messages, &file.enums);
+    code
+}
+
+// Adds the path of the file a parse error is in to its message.
+fn in_file(error: Error, path: &Path) -> Error {
+    match error {
+        Error::Parse { line, message } => Error::Parse {
+            line,
+            message: format!("{}: {message}", path.display()),
+        },
+        error => error,
+    }
+}
+
+/// Generates code for each `.proto` file in `protos` into `out_dir`.
+///
+/// The code for `sensor.proto` is written to `sensor.pb.rs`, with the
+/// options in `sensor.options` if it exists.
+pub fn compile_protos_to(protos: &[impl AsRef<Path>], out_dir: &Path) -> Result<(), Error> {
+    for proto in protos {
+        let proto = proto.as_ref();
+        let source =
+            std::fs::read_to_string(proto).map_err(|e| Error::Io(proto.to_path_buf(), e))?;
+        let mut file = parse(&source).map_err(|error| in_file(error, proto))?;
+
+        let options_path = proto.with_extension("options");
+        if options_path.exists() {
+            let options = std::fs::read_to_string(&options_path)
+                .map_err(|e| Error::Io(options_path.clone(), e))?;
+            apply_options(&mut file, &options).map_err(|error| in_file(error, &options_path))?;
+        }
+
+        let stem = proto.file_stem().unwrap_or_default().to_string_lossy();
+        let output = out_dir.join(format!("{stem}.pb.rs"));
+        let code = format!(
+            "// Generated by pw_protobuf_codegen from {}. Do not edit.\n\n{}",
+            proto.display(),
+            generate(&file)
+        );
+        std::fs::write(&output, code).map_err(|e| Error::Io(output, e))?;
+    }
+    Ok(())
+}
+
+/// Generates code for each `.proto` file in `protos` into Cargo's `OUT_DIR`
+/// from a build script, and reruns the build script when they or their
+/// options files change.
+pub fn compile_protos(protos: &[impl AsRef<Path>]) -> Result<(), Error> {
+    let out_dir = std::env::var_os("OUT_DIR").ok_or(Error::NoOutDir)?;
+    for proto in protos {
+        let proto = proto.as_ref();
+        println!("cargo:rerun-if-changed={}", proto.display());
+        let options_path = proto.with_extension("options");
+        if options_path.exists() {
+            println!("cargo:rerun-if-changed={}", options_path.display());
+        }
+    }
+    compile_protos_to(protos, Path::new(&out_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PROTO: &str = r#"
+syntax = "proto3";
+
+package pw.example;
+
+import "other.proto";
+
+/* A block comment with a message Hidden { } */
+message Reading {
+  enum Unit {
+    UNIT_NONE = 0;
+    UNIT_CELSIUS = 1 [deprecated = true];
+    UNIT_OTHER = -1;
+  }
+
+  reserved 4;
+  uint32 sensor_id = 1;
+  float value = 2;
+  Unit unit = 3;
+  string sensor_name = 5;
+  optional sint64 offset = 6;
+}
+
+message Batch {
+  message Header { fixed64 timestamp = 1; }
+
+  Header header = 1;
+  repeated Reading readings = 2;
+  repeated uint32 sequence = 0x10 [packed = true];
+  .pw.example.Reading.Unit unit = 17;
+  other.Imported imported = 18;
+  bytes type = 19;
+}
+
+service Ignored {
+  rpc Get(Reading) returns (Batch);
+}
+"#;
+
+    const TEST_OPTIONS: &str = "
+# Comment
+pw.example.Reading.sensor_name max_size:16 use_callback:true
+pw.example.Batch.* max_count:4 // Comment
+pw.example.Batch.type max_size:8
+";
+
+    #[test]
+    fn options_set_field_bounds() {
+        let mut file = parse(TEST_PROTO).unwrap();
+        apply_options(&mut file, TEST_OPTIONS).unwrap();
+        assert_eq!(file.messages[0].fields[3].max_size, Some(16));
+        assert_eq!(file.messages[0].fields[3].max_count, None);
+        for field in &file.messages[1].fields {
+            assert_eq!(field.max_count, Some(4), "{}", field.name);
+        }
+        assert_eq!(file.messages[1].fields[5].max_size, Some(8));
+        // As in the C++ generator, `*` also matches nested messages.
+        assert_eq!(file.messages[1].messages[0].fields[0].max_count, Some(4));
+        assert_eq!(file.messages[0].fields[0].max_count, None);
+
+        let error =
+            apply_options(&mut file, "\npw.example.Reading.value max_size:big").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: invalid max_size `big`");
+        assert!(matches_pattern("a.*.c*", "a.b.c.d"));
+        assert!(!matches_pattern("a.*.c", "a.b.d"));
+    }
+
+    #[test]
+    fn names_are_converted_to_identifiers() {
+        assert_eq!(snake_case("Echo"), "echo");
+        assert_eq!(snake_case("TestUnaryRpc"), "test_unary_rpc");
+        assert_eq!(snake_case("GetHTTPStatus"), "get_http_status");
+        assert_eq!(snake_case("Sha256Hash"), "sha256_hash");
+        assert_eq!(identifier(snake_case("Type")), "r#type");
+        assert_eq!(identifier(snake_case("Self")), "self_");
+    }
+
+    #[test]
+    fn code_is_generated_for_each_field() {
+        let mut file = parse(TEST_PROTO).unwrap();
+        apply_options(&mut file, TEST_OPTIONS).unwrap();
+        let code = generate(&file);
+        for expected in [
+            "pub struct Reading {",
+            "pub sensor_id: u32,",
+            "pub unit: reading::Unit,",
+            "pub sensor_name: heapless::String<16>,",
+            "pub offset: Option<i64>,",
+            "pub const MAX_ENCODED_SIZE: usize = 51;",
+            "pub mod reading {",
+            "pub struct Unit(pub i32);",
+            "pub const UNIT_OTHER: Self = Self(-1);",
+            "pub header: Option<batch::Header>,",
+            "pub readings: heapless::Vec<Reading, 4>,",
+            "pub unit: reading::Unit,",
+            "pub imported: Option<Imported>,",
+            "pub r#type: heapless::Vec<u8, 8>,",
+            "encoder.write_uint32(16, *value)?;",
+            "for value in value.repeated(pw_protobuf::WireType::Varint) {",
+            "encoder.write_nested(1, |encoder| value.encode_fields(encoder))?;",
+            "1 => self.header.get_or_insert_with(Default::default).merge(value.as_bytes()?)?,",
+        ] {
+            assert!(code.contains(expected), "{expected}\n{code}");
+        }
+        // `Batch` has a field from another file, whose size is unknown.
+        assert_eq!(code.matches("MAX_ENCODED_SIZE: usize").count(), 2);
+    }
+
+    #[test]
+    fn method_types_are_resolved() {
+        let file = parse(TEST_PROTO).unwrap();
+        assert_eq!(message_path(&file, "Reading"), "Reading");
+        assert_eq!(message_path(&file, "pw.example.Batch"), "Batch");
+        assert_eq!(
+            message_path(&file, ".pw.example.Batch.Header"),
+            "batch::Header"
+        );
+        assert_eq!(message_path(&file, "other.Imported"), "Imported");
+    }
+
+    #[test]
+    fn unbounded_fields_use_alloc() {
+        let code = generate(&parse(TEST_PROTO).unwrap());
+        assert!(code.contains("pub sensor_name: alloc::string::String,"));
+        assert!(code.contains("pub readings: alloc::vec::Vec<Reading>,"));
+        assert!(code.contains("pub const MAX_ENCODED_SIZE: usize = 9;"));
+        assert_eq!(code.matches("MAX_ENCODED_SIZE: usize").count(), 1);
+    }
+
+    #[test]
+    fn protos_are_compiled_to_out_dir() {
+        let dir = std::env::temp_dir().join(format!("pw_protobuf_codegen_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let proto = dir.join("sensor.proto");
+        std::fs::write(
+            &proto,
+            "package pw;\nmessage Name {\n  string name = 1;\n}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("sensor.options"), "pw.Name.name max_size:8\n").unwrap();
+
+        compile_protos_to(&[&proto], &dir).unwrap();
+        let code = std::fs::read_to_string(dir.join("sensor.pb.rs")).unwrap();
+        assert!(code.starts_with("// Generated by pw_protobuf_codegen"));
+        assert!(code.contains("pub name: heapless::String<8>,"));
+
+        std::fs::write(dir.join("sensor.options"), "pw.Name.name max_size\n").unwrap();
+        let error = compile_protos_to(&[&proto], &dir).unwrap_err();
+        assert!(error.to_string().contains("sensor.options: invalid option"));
+        let error = compile_protos_to(&[dir.join("missing.proto")], &dir).unwrap_err();
+        assert!(matches!(error, Error::Io(..)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
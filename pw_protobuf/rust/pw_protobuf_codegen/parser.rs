@@ -0,0 +1,676 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Parses the `.proto` files which `pw_protobuf_codegen` and
+//! `pw_rpc_codegen` generate code for.
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// An error reading, parsing, or generating code for a `.proto` file.
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be read or written.
+    Io(PathBuf, std::io::Error),
+    /// A `.proto` or options file is invalid or not supported.
+    Parse {
+        /// The line of the error, starting at 1.
+        line: usize,
+        /// What is wrong.
+        message: String,
+    },
+    /// The `OUT_DIR` environment variable, which Cargo sets for build
+    /// scripts, is not set.
+    NoOutDir,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(path, error) => write!(f, "{}: {error}", path.display()),
+            Error::Parse { line, message } => write!(f, "line {line}: {message}"),
+            Error::NoOutDir => write!(f, "OUT_DIR is not set"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// How many values a field has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Label {
+    /// A single value, which is not encoded if it is the default.  proto2
+    /// `required` fields are also treated as implicit.
+    Implicit,
+    /// An `optional` value, which is encoded if it is set.
+    Optional,
+    /// A `repeated` field.
+    Repeated,
+}
+
+/// A field of a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    /// The field's name, such as `sensor_id`.
+    pub name: String,
+    /// The field's type, as written in the `.proto`.
+    pub type_name: String,
+    /// The field's number.
+    pub number: u32,
+    /// How many values the field has.
+    pub label: Label,
+    /// The maximum length of a `string` or `bytes` field, from the options
+    /// file.
+    pub max_size: Option<usize>,
+    /// The maximum number of values of a `repeated` field, from the options
+    /// file.
+    pub max_count: Option<usize>,
+}
+
+/// A value of an enum.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnumValue {
+    /// The value's name, such as `STATUS_OK`.
+    pub name: String,
+    /// The value's number.
+    pub number: i32,
+}
+
+/// An enum.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Enum {
+    /// The enum's name, such as `Status`.
+    pub name: String,
+    /// The enum's values, in the order they were declared.
+    pub values: Vec<EnumValue>,
+}
+
+/// A message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// The message's name, such as `Reading`.
+    pub name: String,
+    /// The message's fields, in the order they were declared.
+    pub fields: Vec<Field>,
+    /// The messages declared in the message.
+    pub messages: Vec<Message>,
+    /// The enums declared in the message.
+    pub enums: Vec<Enum>,
+}
+
+/// An RPC method of a service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Method {
+    /// The method's name, such as `Echo`.
+    pub name: String,
+    /// The name of the request message type, as written in the `.proto`.
+    pub request_type: String,
+    /// The name of the response message type, as written in the `.proto`.
+    pub response_type: String,
+    /// True if the client sends a stream of requests.
+    pub client_streaming: bool,
+    /// True if the server sends a stream of responses.
+    pub server_streaming: bool,
+}
+
+/// An RPC service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Service {
+    /// The service's name, such as `EchoService`.
+    pub name: String,
+    /// The service's methods, in the order they were declared.
+    pub methods: Vec<Method>,
+}
+
+/// The messages, enums, and services declared in a `.proto` file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProtoFile {
+    /// The file's package, such as `pw.example`, if it declares one.
+    pub package: Option<String>,
+    /// The file's top level messages, in the order they were declared.
+    pub messages: Vec<Message>,
+    /// The file's top level enums, in the order they were declared.
+    pub enums: Vec<Enum>,
+    /// The file's services, in the order they were declared.
+    pub services: Vec<Service>,
+}
+
+impl ProtoFile {
+    /// Returns the fully qualified name of `service`, which its ID is
+    /// computed from.
+    pub fn full_name(&self, service: &Service) -> String {
+        match &self.package {
+            Some(package) => format!("{package}.{}", service.name),
+            None => service.name.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Word(&'a str),
+    Symbol(char),
+    String,
+}
+
+// Splits `.proto` source into tokens and their line numbers, skipping
+// comments.
+fn tokenize(source: &str) -> Result<Vec<(Token<'_>, usize)>, Error> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek().map(|&(_, c)| c) == Some('/') => {
+                while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            }
+            '/' if chars.peek().map(|&(_, c)| c) == Some('*') => {
+                chars.next();
+                let start_line = line;
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some((_, '/')) if previous == '*' => break,
+                        Some((_, c)) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            previous = c;
+                        }
+                        None => return Err(parse_error(start_line, "unterminated comment")),
+                    }
+                }
+            }
+            '"' | '\'' => {
+                let start_line = line;
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => {
+                            chars.next();
+                        }
+                        Some((_, q)) if q == c => break,
+                        Some((_, '\n')) | None => {
+                            return Err(parse_error(start_line, "unterminated string"))
+                        }
+                        Some(_) => {}
+                    }
+                }
+                tokens.push((Token::String, start_line));
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '.' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) =
+                    chars.next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+                {
+                    end = i + c.len_utf8();
+                }
+                tokens.push((Token::Word(&source[start..end]), line));
+            }
+            c => tokens.push((Token::Symbol(c), line)),
+        }
+    }
+    Ok(tokens)
+}
+
+pub(crate) fn parse_error(line: usize, message: impl Into<String>) -> Error {
+    Error::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+// Parses a decimal or hexadecimal integer.
+fn parse_integer(word: &str) -> Option<u64> {
+    match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token<'a>, usize)>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .or(self.tokens.last())
+            .map_or(1, |&(_, line)| line)
+    }
+
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, Error> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| parse_error(self.line(), "unexpected end of file"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn word(&mut self) -> Result<&'a str, Error> {
+        match self.next()? {
+            Token::Word(word) => Ok(word),
+            token => Err(self.unexpected(&token)),
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), Error> {
+        match self.next()? {
+            Token::Symbol(c) if c == symbol => Ok(()),
+            token => Err(self.unexpected(&token)),
+        }
+    }
+
+    fn unexpected(&self, token: &Token) -> Error {
+        let line = self.tokens[self.position - 1].1;
+        match token {
+            Token::Word(word) => parse_error(line, format!("unexpected `{word}`")),
+            Token::Symbol(c) => parse_error(line, format!("unexpected `{c}`")),
+            Token::String => parse_error(line, "unexpected string"),
+        }
+    }
+
+    // Skips tokens through the next `;` or balanced `{ ... }` block.
+    fn skip_statement(&mut self) -> Result<(), Error> {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Token::Symbol(';') if depth == 0 => return Ok(()),
+                Token::Symbol('{') => depth += 1,
+                Token::Symbol('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                    if depth < 0 {
+                        return Err(self.unexpected(&Token::Symbol('}')));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Skips options in brackets, such as `[deprecated = true]`, if present.
+    fn skip_options(&mut self) -> Result<(), Error> {
+        if self.peek() != Some(&Token::Symbol('[')) {
+            return Ok(());
+        }
+        loop {
+            if self.next()? == Token::Symbol(']') {
+                return Ok(());
+            }
+        }
+    }
+
+    // Parses an integer, with a sign if `signed`.
+    fn integer(&mut self, signed: bool) -> Result<i64, Error> {
+        let negative = signed && self.peek() == Some(&Token::Symbol('-'));
+        if negative {
+            self.position += 1;
+        }
+        let word = self.word()?;
+        let value = parse_integer(word)
+            .and_then(|value| i64::try_from(value).ok())
+            .ok_or_else(|| self.unexpected(&Token::Word(word)))?;
+        Ok(if negative { -value } else { value })
+    }
+
+    fn file(&mut self) -> Result<ProtoFile, Error> {
+        let mut file = ProtoFile::default();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Word("package") => {
+                    self.position += 1;
+                    file.package = Some(self.word()?.to_string());
+                    self.expect(';')?;
+                }
+                Token::Word("message") => {
+                    self.position += 1;
+                    file.messages.push(self.message()?);
+                }
+                Token::Word("enum") => {
+                    self.position += 1;
+                    file.enums.push(self.enumeration()?);
+                }
+                Token::Word("service") => {
+                    self.position += 1;
+                    file.services.push(self.service()?);
+                }
+                Token::Symbol(';') => self.position += 1,
+                _ => self.skip_statement()?,
+            }
+        }
+        Ok(file)
+    }
+
+    fn message(&mut self) -> Result<Message, Error> {
+        let mut message = Message {
+            name: self.word()?.to_string(),
+            fields: Vec::new(),
+            messages: Vec::new(),
+            enums: Vec::new(),
+        };
+        self.expect('{')?;
+        loop {
+            match self.next()? {
+                Token::Symbol('}') => return Ok(message),
+                Token::Symbol(';') => {}
+                Token::Word("message") => message.messages.push(self.message()?),
+                Token::Word("enum") => message.enums.push(self.enumeration()?),
+                Token::Word("option" | "reserved" | "extensions" | "extend") => {
+                    self.skip_statement()?
+                }
+                Token::Word(word @ ("oneof" | "map" | "group")) => {
+                    return Err(parse_error(
+                        self.tokens[self.position - 1].1,
+                        format!("`{word}` is not supported"),
+                    ))
+                }
+                Token::Word(word) => message.fields.push(self.field(word)?),
+                token => return Err(self.unexpected(&token)),
+            }
+        }
+    }
+
+    // Parses a field, which starts with `first`.
+    fn field(&mut self, first: &'a str) -> Result<Field, Error> {
+        let (label, type_name) = match first {
+            "repeated" => (Label::Repeated, self.word()?),
+            "optional" => (Label::Optional, self.word()?),
+            "required" => (Label::Implicit, self.word()?),
+            _ => (Label::Implicit, first),
+        };
+        if type_name == "map" || type_name == "group" {
+            return Err(parse_error(
+                self.tokens[self.position - 1].1,
+                format!("`{type_name}` is not supported"),
+            ));
+        }
+        let name = self.word()?.to_string();
+        self.expect('=')?;
+        let line = self.line();
+        let number = u32::try_from(self.integer(false)?)
+            .ok()
+            .filter(|&number| pw_protobuf::valid_field_number(number))
+            .ok_or_else(|| parse_error(line, format!("invalid field number for `{name}`")))?;
+        self.skip_options()?;
+        self.expect(';')?;
+        Ok(Field {
+            name,
+            type_name: type_name.to_string(),
+            number,
+            label,
+            max_size: None,
+            max_count: None,
+        })
+    }
+
+    fn enumeration(&mut self) -> Result<Enum, Error> {
+        let mut enumeration = Enum {
+            name: self.word()?.to_string(),
+            values: Vec::new(),
+        };
+        self.expect('{')?;
+        loop {
+            match self.next()? {
+                Token::Symbol('}') => return Ok(enumeration),
+                Token::Symbol(';') => {}
+                Token::Word("option" | "reserved") => self.skip_statement()?,
+                Token::Word(name) => {
+                    self.expect('=')?;
+                    let line = self.line();
+                    let number = i32::try_from(self.integer(true)?)
+                        .map_err(|_| parse_error(line, format!("invalid value for `{name}`")))?;
+                    self.skip_options()?;
+                    self.expect(';')?;
+                    enumeration.values.push(EnumValue {
+                        name: name.to_string(),
+                        number,
+                    });
+                }
+                token => return Err(self.unexpected(&token)),
+            }
+        }
+    }
+
+    fn service(&mut self) -> Result<Service, Error> {
+        let line = self.line();
+        let name = self.word()?.to_string();
+        // `pw_rpc_codegen` generates a `Service` and a `Client` next to the
+        // service's trait.
+        if matches!(name.as_str(), "Service" | "Client") {
+            return Err(parse_error(
+                line,
+                format!("service `{name}` conflicts with the generated `{name}`"),
+            ));
+        }
+        let mut service = Service {
+            name,
+            methods: Vec::new(),
+        };
+        self.expect('{')?;
+        loop {
+            match self.next()? {
+                Token::Symbol('}') => return Ok(service),
+                Token::Symbol(';') => {}
+                Token::Word("rpc") => service.methods.push(self.method()?),
+                Token::Word("option") => self.skip_statement()?,
+                token => return Err(self.unexpected(&token)),
+            }
+        }
+    }
+
+    // Parses a message type argument, such as `(stream Request)`.
+    fn message_type(&mut self) -> Result<(String, bool), Error> {
+        self.expect('(')?;
+        let mut name = self.word()?;
+        let streaming = name == "stream" && self.peek() != Some(&Token::Symbol(')'));
+        if streaming {
+            name = self.word()?;
+        }
+        self.expect(')')?;
+        Ok((name.to_string(), streaming))
+    }
+
+    fn method(&mut self) -> Result<Method, Error> {
+        let name = self.word()?.to_string();
+        let (request_type, client_streaming) = self.message_type()?;
+        match self.word()? {
+            "returns" => {}
+            word => return Err(self.unexpected(&Token::Word(word))),
+        }
+        let (response_type, server_streaming) = self.message_type()?;
+        // The method ends with `;` or a block of options.
+        match self.peek() {
+            Some(Token::Symbol('{')) => self.skip_statement()?,
+            _ => self.expect(';')?,
+        }
+        Ok(Method {
+            name,
+            request_type,
+            response_type,
+            client_streaming,
+            server_streaming,
+        })
+    }
+}
+
+/// Parses the package, messages, enums, and services of a `.proto` file.
+///
+/// Other declarations, such as imports and options, are skipped.
+pub fn parse(source: &str) -> Result<ProtoFile, Error> {
+    Parser {
+        tokens: tokenize(source)?,
+        position: 0,
+    }
+    .file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PROTO: &str = r#"
+syntax = "proto3";
+
+package pw.example;
+
+import "other.proto";
+
+/* A block comment with a message Hidden { } */
+message Reading {
+  enum Unit {
+    UNIT_NONE = 0;
+    UNIT_CELSIUS = 1 [deprecated = true];
+    UNIT_OTHER = -1;
+  }
+
+  reserved 4;
+  uint32 sensor_id = 1;
+  float value = 2;
+  Unit unit = 3;
+  string sensor_name = 5;
+  optional sint64 offset = 6;
+}
+
+message Batch {
+  message Header { fixed64 timestamp = 1; }
+
+  Header header = 1;
+  repeated Reading readings = 2;
+  repeated uint32 sequence = 0x10 [packed = true];
+}
+
+service TestService {
+  option deprecated = false;
+  // The "stream" is a message type here.
+  rpc TestUnaryRpc(stream) returns (Batch);
+  rpc TestServerStreamRpc(Reading) returns (stream Batch) {
+    option idempotency_level = NO_SIDE_EFFECTS;
+  }
+  rpc TestClientStreamRpc(stream Reading) returns (Batch);
+  rpc TestBidirectionalStreamRpc(stream Reading)
+      returns (stream Batch);
+}
+"#;
+
+    fn field(name: &str, type_name: &str, number: u32, label: Label) -> Field {
+        Field {
+            name: name.to_string(),
+            type_name: type_name.to_string(),
+            number,
+            label,
+            max_size: None,
+            max_count: None,
+        }
+    }
+
+    #[test]
+    fn messages_are_parsed() {
+        let file = parse(TEST_PROTO).unwrap();
+        assert_eq!(file.package.as_deref(), Some("pw.example"));
+        assert!(file.enums.is_empty());
+        let reading = &file.messages[0];
+        assert_eq!(reading.name, "Reading");
+        assert_eq!(
+            reading.fields,
+            [
+                field("sensor_id", "uint32", 1, Label::Implicit),
+                field("value", "float", 2, Label::Implicit),
+                field("unit", "Unit", 3, Label::Implicit),
+                field("sensor_name", "string", 5, Label::Implicit),
+                field("offset", "sint64", 6, Label::Optional),
+            ]
+        );
+        assert_eq!(
+            reading.enums[0].values,
+            [
+                EnumValue {
+                    name: "UNIT_NONE".to_string(),
+                    number: 0
+                },
+                EnumValue {
+                    name: "UNIT_CELSIUS".to_string(),
+                    number: 1
+                },
+                EnumValue {
+                    name: "UNIT_OTHER".to_string(),
+                    number: -1
+                },
+            ]
+        );
+        let batch = &file.messages[1];
+        assert_eq!(batch.messages[0].name, "Header");
+        assert_eq!(
+            batch.fields[2],
+            field("sequence", "uint32", 16, Label::Repeated)
+        );
+    }
+
+    #[test]
+    fn parse_errors_have_line_numbers() {
+        let error = parse("message A {\n  uint32 a = 0;\n}").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: invalid field number for `a`");
+        let error = parse("message A {\n\n  map<string, uint32> a = 1;\n}").unwrap_err();
+        assert_eq!(error.to_string(), "line 3: `map` is not supported");
+        let error = parse("message A {\n  oneof b {}\n}").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: `oneof` is not supported");
+        let error = parse("enum E {\n  A = 1\n}").unwrap_err();
+        assert_eq!(error.to_string(), "line 3: unexpected `}`");
+        let error = parse("package a;\n\nservice S {\n  rpc M(A) gives (B);\n}").unwrap_err();
+        assert_eq!(error.to_string(), "line 4: unexpected `gives`");
+        let error = parse("service S {\n  rpc M(A) returns (B);\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: unexpected end of file");
+        let error = parse("/* comment").unwrap_err();
+        assert_eq!(error.to_string(), "line 1: unterminated comment");
+        assert!(parse("service Client {}").is_err());
+    }
+
+    fn method(name: &str, request: &str, response: &str, client: bool, server: bool) -> Method {
+        Method {
+            name: name.to_string(),
+            request_type: request.to_string(),
+            response_type: response.to_string(),
+            client_streaming: client,
+            server_streaming: server,
+        }
+    }
+
+    #[test]
+    fn services_are_parsed() {
+        let file = parse(TEST_PROTO).unwrap();
+        assert_eq!(
+            file.services,
+            [Service {
+                name: "TestService".to_string(),
+                methods: vec![
+                    method("TestUnaryRpc", "stream", "Batch", false, false),
+                    method("TestServerStreamRpc", "Reading", "Batch", false, true),
+                    method("TestClientStreamRpc", "Reading", "Batch", true, false),
+                    method("TestBidirectionalStreamRpc", "Reading", "Batch", true, true),
+                ],
+            }]
+        );
+        assert_eq!(file.full_name(&file.services[0]), "pw.example.TestService");
+    }
+}
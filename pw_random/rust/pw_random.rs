@@ -0,0 +1,400 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Random number generation for embedded systems.
+//!
+//! This is the Rust counterpart of the C++ `pw_random` module.  Code which
+//! needs random numbers, such as retry backoff jitter, RPC call IDs, or
+//! flash wear leveling, takes a [`RandomGenerator`] so that tests can inject
+//! a [`DeterministicGenerator`] in place of a real one:
+//!
+//! ```
+//! use pw_random::{RandomGenerator, XorShiftStarRng64};
+//!
+//! fn backoff_jitter_ms(rng: &mut impl RandomGenerator, max_ms: u32) -> u32 {
+//!     rng.bounded_u32(max_ms)
+//! }
+//!
+//! let mut rng = XorShiftStarRng64::new(0x21fe_abcd_5fb3_7474);
+//! rng.inject_entropy_bits(adc_noise(), 4);
+//! assert!(backoff_jitter_ms(&mut rng, 100) < 100);
+//! # fn adc_noise() -> u32 { 0x5 }
+//! ```
+//!
+//! Hardware random number generators implement [`EntropySource`], which a
+//! generator may be seeded from with [`XorShiftStarRng64::from_entropy()`]
+//! or [`RandomGenerator::inject_entropy_from()`].
+//!
+//! *Note*: None of the generators in this crate are cryptographically secure,
+//! and the uniformity of their output is not guaranteed.
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_status::Result;
+
+/// A source of true entropy, such as a hardware random number generator.
+pub trait EntropySource {
+    /// Fills `dest` with entropy.
+    ///
+    /// # Errors
+    /// - [`pw_status::Error::Unavailable`] - Not enough entropy is available yet.
+    ///
+    /// Implementations may return other errors for hardware failures.
+    fn fill_entropy(&mut self, dest: &mut [u8]) -> Result<()>;
+}
+
+/// A random generator uses injected entropy to generate random values.
+///
+/// As in C++, generators always succeed, but are not assumed to be
+/// cryptographically secure or to produce uniform data.
+pub trait RandomGenerator {
+    /// Fills `dest` with random data.
+    fn fill(&mut self, dest: &mut [u8]);
+
+    /// Injects up to 32 bits of entropy into the generator.
+    ///
+    /// If `num_bits` is less than 32, the entropy is in the least significant
+    /// bits of `data`.
+    fn inject_entropy_bits(&mut self, data: u32, num_bits: u8);
+
+    /// Injects entropy into the generator byte by byte.
+    fn inject_entropy(&mut self, data: &[u8]) {
+        for byte in data {
+            self.inject_entropy_bits(u32::from(*byte), 8);
+        }
+    }
+
+    /// Injects `len` bytes of entropy read from `source`.
+    ///
+    /// # Errors
+    /// Returns any error from [`EntropySource::fill_entropy()`], in which
+    /// case no entropy is injected.
+    fn inject_entropy_from(&mut self, source: &mut dyn EntropySource, len: usize) -> Result<()> {
+        let mut buffer = [0u8; 8];
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = &mut buffer[..remaining.min(8)];
+            source.fill_entropy(chunk)?;
+            self.inject_entropy(chunk);
+            remaining -= chunk.len();
+        }
+        Ok(())
+    }
+
+    /// Returns a random `u32`.
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.fill(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Returns a random `u64`.
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Returns a random number in the range `[0, exclusive_upper_bound)`.
+    ///
+    /// This avoids modulo bias by discarding values outside the range, so it
+    /// is uniform if the generator's data is uniform.
+    ///
+    /// # Panics
+    /// Panics if `exclusive_upper_bound` is zero.
+    fn bounded_u32(&mut self, exclusive_upper_bound: u32) -> u32 {
+        assert!(exclusive_upper_bound != 0, "Upper bound must be nonzero");
+        if exclusive_upper_bound < 2 {
+            return 0;
+        }
+        // Discarding the bits above the bound's highest bit means fewer than
+        // half of the values are discarded, so fewer than two values are
+        // generated on average.
+        let mask = u32::MAX >> exclusive_upper_bound.leading_zeros();
+        loop {
+            let value = self.next_u32() & mask;
+            if value < exclusive_upper_bound {
+                return value;
+            }
+        }
+    }
+
+    /// Returns a random number in the range `[0, exclusive_upper_bound)`.
+    ///
+    /// See [`RandomGenerator::bounded_u32()`].
+    ///
+    /// # Panics
+    /// Panics if `exclusive_upper_bound` is zero.
+    fn bounded_u64(&mut self, exclusive_upper_bound: u64) -> u64 {
+        assert!(exclusive_upper_bound != 0, "Upper bound must be nonzero");
+        if exclusive_upper_bound < 2 {
+            return 0;
+        }
+        let mask = u64::MAX >> exclusive_upper_bound.leading_zeros();
+        loop {
+            let value = self.next_u64() & mask;
+            if value < exclusive_upper_bound {
+                return value;
+            }
+        }
+    }
+}
+
+/// A random generator based on the
+/// [xorshift*](https://en.wikipedia.org/wiki/Xorshift) algorithm, which
+/// produces the same values as the C++ `pw::random::XorShiftStarRng64`.
+///
+/// Injecting entropy reseeds the generator, after which its results are no
+/// longer determined by the original seed alone.
+///
+/// *Note*: This generator is **not** cryptographically secure.
+#[derive(Clone, Debug)]
+pub struct XorShiftStarRng64 {
+    state: u64,
+}
+
+impl XorShiftStarRng64 {
+    // See https://www.jstatsoft.org/article/view/v008i14 and
+    // https://vigna.di.unimi.it/ftp/papers/xorshift.pdf for why this
+    // constant was selected.
+    const MULTIPLIER: u64 = 0x2545_f491_4f6c_dd1d;
+
+    /// Creates a generator with an initial seed.
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Creates a generator seeded from `source`.
+    ///
+    /// # Errors
+    /// Returns any error from [`EntropySource::fill_entropy()`].
+    pub fn from_entropy(source: &mut dyn EntropySource) -> Result<Self> {
+        let mut seed = [0u8; 8];
+        source.fill_entropy(&mut seed)?;
+        Ok(Self::new(u64::from_le_bytes(seed)))
+    }
+
+    fn regenerate(&mut self) -> u64 {
+        // The state must be nonzero, or the generator always returns zero.
+        if self.state == 0 {
+            self.state = u64::MAX;
+        }
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(Self::MULTIPLIER)
+    }
+}
+
+impl RandomGenerator for XorShiftStarRng64 {
+    fn fill(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let random = self.regenerate().to_le_bytes();
+            chunk.copy_from_slice(&random[..chunk.len()]);
+        }
+    }
+
+    /// Rotates the state by `num_bits` before XORing the entropy into it, so
+    /// that injecting single bits progressively fills the state.
+    fn inject_entropy_bits(&mut self, data: u32, num_bits: u8) {
+        let num_bits = u32::from(num_bits.min(32));
+        if num_bits == 0 {
+            return;
+        }
+        let mask = ((1u64 << num_bits) - 1) as u32;
+        self.state = self.state.rotate_left(num_bits) ^ u64::from(data & mask);
+    }
+}
+
+/// A generator which repeats a fixed sequence of bytes, for tests which need
+/// reproducible "random" values.
+///
+/// It is also an [`EntropySource`] for testing code which reads from a
+/// hardware random number generator.  Injected entropy is ignored.
+///
+/// ```
+/// use pw_random::{DeterministicGenerator, RandomGenerator};
+///
+/// let mut rng = DeterministicGenerator::new(&[1, 0, 0, 0, 2, 0, 0, 0]);
+/// assert_eq!(rng.next_u32(), 1);
+/// assert_eq!(rng.next_u32(), 2);
+/// assert_eq!(rng.next_u32(), 1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct DeterministicGenerator<'a> {
+    sequence: &'a [u8],
+    position: usize,
+}
+
+impl<'a> DeterministicGenerator<'a> {
+    /// Creates a generator which repeats `sequence`.
+    ///
+    /// # Panics
+    /// Panics if `sequence` is empty.
+    pub const fn new(sequence: &'a [u8]) -> Self {
+        assert!(!sequence.is_empty(), "Sequence must not be empty");
+        Self {
+            sequence,
+            position: 0,
+        }
+    }
+}
+
+impl RandomGenerator for DeterministicGenerator<'_> {
+    fn fill(&mut self, dest: &mut [u8]) {
+        for byte in dest {
+            *byte = self.sequence[self.position];
+            self.position = (self.position + 1) % self.sequence.len();
+        }
+    }
+
+    fn inject_entropy_bits(&mut self, _data: u32, _num_bits: u8) {}
+}
+
+impl EntropySource for DeterministicGenerator<'_> {
+    fn fill_entropy(&mut self, dest: &mut [u8]) -> Result<()> {
+        self.fill(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_status::Error;
+
+    use super::*;
+
+    const SEED1: u64 = 5;
+    const RESULT1: [u64; 4] = [
+        0x423212e85fb37474,
+        0x96051f25a1aadc74,
+        0x8ac1f520f5595a79,
+        0x7587fe57095b7c11,
+    ];
+
+    const SEED2: u64 = 0x21feabcd5fb37474;
+    const RESULT2: [u64; 3] = [0x568ea260a4f3e793, 0x5ea87d669ab04d36, 0x77a8675eec48ae8b];
+
+    #[test]
+    fn xor_shift_matches_cpp_series() {
+        let mut rng = XorShiftStarRng64::new(SEED1);
+        for expected in RESULT1 {
+            assert_eq!(rng.next_u64(), expected);
+        }
+        let mut rng = XorShiftStarRng64::new(SEED2);
+        for expected in RESULT2 {
+            assert_eq!(rng.next_u64(), expected);
+        }
+    }
+
+    #[test]
+    fn xor_shift_fills_partial_values() {
+        let mut rng = XorShiftStarRng64::new(SEED1);
+        let mut buffer = [0u8; 11];
+        rng.fill(&mut buffer);
+        assert_eq!(buffer[..8], RESULT1[0].to_le_bytes());
+        assert_eq!(buffer[8..], RESULT1[1].to_le_bytes()[..3]);
+        assert_eq!(rng.next_u64(), RESULT1[2]);
+    }
+
+    #[test]
+    fn zero_seed_does_not_get_stuck() {
+        let mut rng = XorShiftStarRng64::new(0);
+        assert_ne!(rng.next_u64(), 0);
+        assert_ne!(rng.next_u64(), 0);
+    }
+
+    #[test]
+    fn injected_entropy_changes_series() {
+        let mut rng = XorShiftStarRng64::new(SEED1);
+        rng.inject_entropy_bits(0x1, 1);
+        assert_ne!(rng.next_u64(), RESULT1[0]);
+
+        let mut rng = XorShiftStarRng64::new(SEED1);
+        rng.inject_entropy_bits(0x1234_5678, 32);
+        assert_ne!(rng.next_u64(), RESULT1[0]);
+
+        // The same entropy with different bit counts gives different values.
+        let mut rng_1 = XorShiftStarRng64::new(SEED1);
+        let mut rng_2 = XorShiftStarRng64::new(SEED1);
+        rng_1.inject_entropy_bits(0x1, 1);
+        rng_2.inject_entropy_bits(0x1, 2);
+        assert_ne!(rng_1.next_u64(), rng_2.next_u64());
+
+        // No bits of entropy leaves the state unchanged.
+        let mut rng = XorShiftStarRng64::new(SEED1);
+        rng.inject_entropy_bits(0xffff_ffff, 0);
+        assert_eq!(rng.next_u64(), RESULT1[0]);
+    }
+
+    #[test]
+    fn bounded_values_are_in_range() {
+        let mut rng = XorShiftStarRng64::new(SEED2);
+        for bound in [1, 2, 3, 7, 100, 1 << 31, u32::MAX] {
+            for _ in 0..100 {
+                assert!(rng.bounded_u32(bound) < bound);
+                assert!(rng.bounded_u64(u64::from(bound)) < u64::from(bound));
+            }
+        }
+    }
+
+    #[test]
+    fn bounded_values_discard_out_of_range_values() {
+        // 0x0f and 0x0e are out of range after masking to four bits.
+        let mut rng = DeterministicGenerator::new(&[0xff, 0, 0, 0, 0xfe, 0, 0, 0, 0x09, 0, 0, 0]);
+        assert_eq!(rng.bounded_u32(10), 9);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_bound_panics() {
+        XorShiftStarRng64::new(SEED1).bounded_u32(0);
+    }
+
+    #[test]
+    fn generator_can_be_seeded_from_entropy() {
+        let seed = SEED1.to_le_bytes();
+        let mut source = DeterministicGenerator::new(&seed);
+        let mut rng = XorShiftStarRng64::from_entropy(&mut source).unwrap();
+        assert_eq!(rng.next_u64(), RESULT1[0]);
+
+        let mut seeded = XorShiftStarRng64::new(SEED1);
+        seeded.inject_entropy_from(&mut source, 3).unwrap();
+        let mut expected = XorShiftStarRng64::new(SEED1);
+        expected.inject_entropy(&[5, 0, 0]);
+        assert_eq!(seeded.next_u64(), expected.next_u64());
+    }
+
+    struct UnavailableSource;
+
+    impl EntropySource for UnavailableSource {
+        fn fill_entropy(&mut self, _dest: &mut [u8]) -> Result<()> {
+            Err(Error::Unavailable)
+        }
+    }
+
+    #[test]
+    fn entropy_errors_are_returned() {
+        assert_eq!(
+            XorShiftStarRng64::from_entropy(&mut UnavailableSource).err(),
+            Some(Error::Unavailable)
+        );
+        let mut rng = XorShiftStarRng64::new(SEED1);
+        assert_eq!(
+            rng.inject_entropy_from(&mut UnavailableSource, 4),
+            Err(Error::Unavailable)
+        );
+        assert_eq!(rng.next_u64(), RESULT1[0]);
+    }
+}
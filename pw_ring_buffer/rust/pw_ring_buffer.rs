@@ -0,0 +1,747 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_ring_buffer` provides [`PrefixedEntryRingBuffer`], a ring buffer of
+//! variable length entries which several readers consume independently.
+//!
+//! This is the Rust counterpart of the C++ `pw_ring_buffer` module, and uses
+//! the same layout: each entry is prefixed with its length as a varint, and
+//! optionally with a varint user preamble before that.  When the buffer is
+//! full, [`PrefixedEntryRingBuffer::push_back()`] drops the oldest entries to
+//! make room, and each reader counts the entries which were dropped before it
+//! read them.
+//!
+//! ```
+//! use pw_ring_buffer::PrefixedEntryRingBuffer;
+//!
+//! let mut ring = PrefixedEntryRingBuffer::<_, 2>::new([0u8; 16], false);
+//! let fast = ring.attach_reader()?;
+//! let slow = ring.attach_reader()?;
+//!
+//! ring.push_back(b"hello", 0)?;
+//! let mut buffer = [0u8; 8];
+//! let len = ring.peek_front(fast, &mut buffer)?;
+//! assert_eq!(&buffer[..len], b"hello");
+//! ring.pop_front(fast)?;
+//!
+//! // Entries which do not fit replace the oldest entries.
+//! ring.push_back(b"abcdef", 0)?;
+//! ring.push_back(b"ghijkl", 0)?;
+//! assert_eq!(ring.entry_count(fast), 2);
+//! assert_eq!(ring.drop_count(fast), 0);
+//! assert_eq!(ring.entry_count(slow), 2);
+//! assert_eq!(ring.drop_count(slow), 1);
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_status::{Error, Result};
+use pw_varint::{VarintEncode, MAX_VARINT32_SIZE_BYTES};
+
+/// Identifies a reader attached to a [`PrefixedEntryRingBuffer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReaderId(usize);
+
+#[derive(Clone, Copy, Debug)]
+struct ReaderState {
+    // Index of the reader's front entry.
+    read_idx: usize,
+    // Number of entries the reader has not popped.
+    entry_count: usize,
+    // Number of entries dropped before the reader popped them.
+    drop_count: u32,
+}
+
+// The sizes of the prefix and data of an entry.
+#[derive(Clone, Copy, Debug)]
+struct EntryInfo {
+    preamble_bytes: usize,
+    user_preamble: u32,
+    data_bytes: usize,
+}
+
+impl EntryInfo {
+    fn total_bytes(&self) -> usize {
+        self.preamble_bytes + self.data_bytes
+    }
+}
+
+/// An entry returned by [`PrefixedEntryRingBuffer::iter()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry<'a> {
+    /// The entry's data.
+    pub data: &'a [u8],
+    /// The entry's user preamble, or zero if the buffer has none.
+    pub preamble: u32,
+}
+
+/// A ring buffer of variable length entries, each prefixed by its length,
+/// which up to `MAX_READERS` readers consume independently.
+///
+/// Entries are stored in `buffer`, which may be an owned array or a borrowed
+/// slice, such as one in persistent memory.  If `user_preamble` is set, each
+/// entry is stored with a `u32` preamble, such as a timestamp or an ID.
+///
+/// An entry is kept until every reader has popped it or it is dropped to
+/// make room for a new entry.  Readers attached to a buffer start at the
+/// oldest entry any other reader has not popped.
+pub struct PrefixedEntryRingBuffer<B, const MAX_READERS: usize = 1> {
+    buffer: B,
+    write_idx: usize,
+    user_preamble: bool,
+    readers: [Option<ReaderState>; MAX_READERS],
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>, const MAX_READERS: usize>
+    PrefixedEntryRingBuffer<B, MAX_READERS>
+{
+    /// Create an empty ring buffer in `buffer`, with a user preamble on each
+    /// entry if `user_preamble` is set.
+    pub const fn new(buffer: B, user_preamble: bool) -> Self {
+        Self {
+            buffer,
+            write_idx: 0,
+            user_preamble,
+            readers: [None; MAX_READERS],
+        }
+    }
+
+    /// Returns the underlying buffer.
+    pub fn into_inner(self) -> B {
+        self.buffer
+    }
+
+    /// Attaches a reader, which starts at the oldest entry not popped by
+    /// every other reader.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - `MAX_READERS` readers are attached.
+    pub fn attach_reader(&mut self) -> Result<ReaderId> {
+        let state = match self.slowest_reader() {
+            Some(slowest) => ReaderState {
+                drop_count: 0,
+                ..slowest
+            },
+            None => ReaderState {
+                read_idx: self.write_idx,
+                entry_count: 0,
+                drop_count: 0,
+            },
+        };
+        let index = self
+            .readers
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::ResourceExhausted)?;
+        self.readers[index] = Some(state);
+        Ok(ReaderId(index))
+    }
+
+    /// Detaches `reader`.  Entries which only it had not popped may be
+    /// overwritten.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `reader` is not attached.
+    pub fn detach_reader(&mut self, reader: ReaderId) -> Result<()> {
+        self.reader(reader)?;
+        self.readers[reader.0] = None;
+        Ok(())
+    }
+
+    /// Removes all entries.
+    pub fn clear(&mut self) {
+        self.write_idx = 0;
+        for reader in self.readers.iter_mut().flatten() {
+            reader.read_idx = 0;
+            reader.entry_count = 0;
+        }
+    }
+
+    /// Adds an entry, dropping the oldest entries to make room if needed.
+    ///
+    /// `user_preamble` is ignored unless the buffer was created with user
+    /// preambles.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - The entry and its prefix are larger than the
+    ///   buffer.
+    pub fn push_back(&mut self, data: &[u8], user_preamble: u32) -> Result<()> {
+        self.internal_push_back(data, user_preamble, true)
+    }
+
+    /// Adds an entry if there is room without dropping entries.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - The entry and its prefix are larger than the
+    ///   buffer.
+    /// - [`Error::ResourceExhausted`] - There is not enough free space.
+    pub fn try_push_back(&mut self, data: &[u8], user_preamble: u32) -> Result<()> {
+        self.internal_push_back(data, user_preamble, false)
+    }
+
+    /// Copies the data of `reader`'s front entry to `buffer`, returning its
+    /// length.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `reader` is not attached.
+    /// - [`Error::OutOfRange`] - `reader` has no entries.
+    /// - [`Error::ResourceExhausted`] - `buffer` is smaller than the entry and
+    ///   was filled with its start.
+    /// - [`Error::DataLoss`] - The entry's prefix is corrupt.
+    pub fn peek_front(&self, reader: ReaderId, buffer: &mut [u8]) -> Result<usize> {
+        let (read_idx, info) = self.front_entry(reader)?;
+        self.read_out(
+            self.increment_index(read_idx, info.preamble_bytes),
+            info.data_bytes,
+            buffer,
+        )
+    }
+
+    /// Copies `reader`'s front entry to `buffer` including its prefix,
+    /// returning its length.
+    ///
+    /// Errors are the same as [`PrefixedEntryRingBuffer::peek_front()`].
+    pub fn peek_front_with_preamble(&self, reader: ReaderId, buffer: &mut [u8]) -> Result<usize> {
+        let (read_idx, info) = self.front_entry(reader)?;
+        self.read_out(read_idx, info.total_bytes(), buffer)
+    }
+
+    /// Returns the user preamble of `reader`'s front entry.
+    ///
+    /// Errors are the same as [`PrefixedEntryRingBuffer::peek_front()`].
+    pub fn peek_front_preamble(&self, reader: ReaderId) -> Result<u32> {
+        Ok(self.front_entry(reader)?.1.user_preamble)
+    }
+
+    /// Removes `reader`'s front entry.  Other readers are unaffected.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `reader` is not attached.
+    /// - [`Error::OutOfRange`] - `reader` has no entries.
+    /// - [`Error::DataLoss`] - The entry's prefix is corrupt.
+    pub fn pop_front(&mut self, reader: ReaderId) -> Result<()> {
+        let (read_idx, info) = self.front_entry(reader)?;
+        let read_idx = self.increment_index(read_idx, info.total_bytes());
+        if let Some(state) = &mut self.readers[reader.0] {
+            state.read_idx = read_idx;
+            state.entry_count -= 1;
+        }
+        Ok(())
+    }
+
+    /// Returns the size of the data of `reader`'s front entry, or zero if it
+    /// has no entries.
+    pub fn front_entry_data_size_bytes(&self, reader: ReaderId) -> usize {
+        self.front_entry(reader)
+            .map_or(0, |(_, info)| info.data_bytes)
+    }
+
+    /// Returns the size of `reader`'s front entry including its prefix, or
+    /// zero if it has no entries.
+    pub fn front_entry_total_size_bytes(&self, reader: ReaderId) -> usize {
+        self.front_entry(reader)
+            .map_or(0, |(_, info)| info.total_bytes())
+    }
+
+    /// Returns the number of entries `reader` has not popped.
+    pub fn entry_count(&self, reader: ReaderId) -> usize {
+        self.reader(reader).map_or(0, |state| state.entry_count)
+    }
+
+    /// Returns the number of entries which were dropped to make room for new
+    /// entries before `reader` popped them.
+    pub fn drop_count(&self, reader: ReaderId) -> u32 {
+        self.reader(reader).map_or(0, |state| state.drop_count)
+    }
+
+    /// Returns the number of bytes used by entries not popped by every reader.
+    pub fn total_used_bytes(&self) -> usize {
+        self.total_size_bytes() - self.raw_available_bytes()
+    }
+
+    /// Returns the size of the buffer.
+    pub fn total_size_bytes(&self) -> usize {
+        self.buffer.as_ref().len()
+    }
+
+    /// Rotates the buffer so the slowest reader's front entry is at its
+    /// start, making the stored entries contiguous.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - No readers are attached.
+    pub fn dering(&mut self) -> Result<()> {
+        let slowest = self
+            .slowest_reader_index()
+            .ok_or(Error::FailedPrecondition)?;
+        self.internal_dering(slowest);
+        Ok(())
+    }
+
+    /// Returns an iterator over the entries not popped by every reader, from
+    /// oldest to newest.
+    ///
+    /// The buffer is deringed first so each entry is contiguous.  If an
+    /// entry's prefix is corrupt, the iterator returns an error and ends.
+    pub fn iter(&mut self) -> Entries<'_> {
+        let Some(slowest) = self.slowest_reader_index() else {
+            return Entries {
+                buffer: &[],
+                user_preamble: self.user_preamble,
+                read_idx: 0,
+                entry_count: 0,
+            };
+        };
+        self.internal_dering(slowest);
+        let used_bytes = self.total_used_bytes();
+        let entry_count = self.readers[slowest].map_or(0, |state| state.entry_count);
+        Entries {
+            buffer: &self.buffer.as_ref()[..used_bytes],
+            user_preamble: self.user_preamble,
+            read_idx: 0,
+            entry_count,
+        }
+    }
+
+    /// Checks that the prefix of every entry is valid by iterating over them.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - An entry's prefix is corrupt.
+    pub fn check_for_corruption(&mut self) -> Result<()> {
+        self.iter().try_for_each(|entry| entry.map(|_| ()))
+    }
+
+    fn reader(&self, reader: ReaderId) -> Result<&ReaderState> {
+        self.readers
+            .get(reader.0)
+            .and_then(Option::as_ref)
+            .ok_or(Error::InvalidArgument)
+    }
+
+    // Returns the index and prefix of a reader's front entry.
+    fn front_entry(&self, reader: ReaderId) -> Result<(usize, EntryInfo)> {
+        let state = self.reader(reader)?;
+        if state.entry_count == 0 {
+            return Err(Error::OutOfRange);
+        }
+        Ok((state.read_idx, self.raw_entry_info(state.read_idx)?))
+    }
+
+    // The slowest reader has the most entries left to pop.
+    fn slowest_reader_index(&self) -> Option<usize> {
+        self.readers
+            .iter()
+            .enumerate()
+            .filter_map(|(index, state)| Some((index, state.as_ref()?.entry_count)))
+            .max_by_key(|&(_, entry_count)| entry_count)
+            .map(|(index, _)| index)
+    }
+
+    fn slowest_reader(&self) -> Option<ReaderState> {
+        self.slowest_reader_index()
+            .and_then(|index| self.readers[index])
+    }
+
+    fn internal_push_back(
+        &mut self,
+        data: &[u8],
+        user_preamble: u32,
+        pop_front_if_needed: bool,
+    ) -> Result<()> {
+        let mut prefix = [0u8; 2 * MAX_VARINT32_SIZE_BYTES];
+        let mut prefix_len = 0;
+        if self.user_preamble {
+            prefix_len += user_preamble.varint_encode(&mut prefix)?;
+        }
+        let data_len = u32::try_from(data.len()).map_err(|_| Error::OutOfRange)?;
+        prefix_len += data_len.varint_encode(&mut prefix[prefix_len..])?;
+
+        let total_write_bytes = prefix_len + data.len();
+        if self.total_size_bytes() < total_write_bytes {
+            return Err(Error::OutOfRange);
+        }
+
+        if pop_front_if_needed {
+            while self.raw_available_bytes() < total_write_bytes {
+                self.pop_front_all()?;
+            }
+        } else if self.raw_available_bytes() < total_write_bytes {
+            return Err(Error::ResourceExhausted);
+        }
+
+        self.raw_write(&prefix[..prefix_len]);
+        self.raw_write(data);
+        for reader in self.readers.iter_mut().flatten() {
+            reader.entry_count += 1;
+        }
+        Ok(())
+    }
+
+    // Drops the oldest entry, popping it from each reader which has not.
+    fn pop_front_all(&mut self) -> Result<()> {
+        let slowest = self
+            .slowest_reader()
+            .filter(|state| state.entry_count > 0)
+            .ok_or(Error::Internal)?;
+        let info = self.raw_entry_info(slowest.read_idx)?;
+        let next_idx = self.increment_index(slowest.read_idx, info.total_bytes());
+        for reader in self.readers.iter_mut().flatten() {
+            if reader.entry_count == slowest.entry_count {
+                reader.read_idx = next_idx;
+                reader.entry_count -= 1;
+                reader.drop_count = reader.drop_count.wrapping_add(1);
+            }
+        }
+        Ok(())
+    }
+
+    fn internal_dering(&mut self, dering_reader: usize) {
+        let Some(dering_idx) = self.readers[dering_reader].map(|state| state.read_idx) else {
+            return;
+        };
+        if dering_idx == 0 {
+            return;
+        }
+        let len = self.total_size_bytes();
+        self.buffer.as_mut().rotate_left(dering_idx);
+        let rebase = |index: usize| (index + len - dering_idx) % len;
+        self.write_idx = rebase(self.write_idx);
+        for reader in self.readers.iter_mut().flatten() {
+            reader.read_idx = rebase(reader.read_idx);
+        }
+    }
+
+    // Decodes the prefix of the entry at `index`.
+    fn raw_entry_info(&self, index: usize) -> Result<EntryInfo> {
+        let mut preamble_bytes = 0;
+        let mut user_preamble = 0;
+        if self.user_preamble {
+            let (len, value) = self.raw_read_varint(index)?;
+            preamble_bytes = len;
+            user_preamble = value;
+        }
+        let (len, data_bytes) =
+            self.raw_read_varint(self.increment_index(index, preamble_bytes))?;
+        Ok(EntryInfo {
+            preamble_bytes: preamble_bytes + len,
+            user_preamble,
+            data_bytes: data_bytes as usize,
+        })
+    }
+
+    fn raw_read_varint(&self, index: usize) -> Result<(usize, u32)> {
+        let mut bytes = [0u8; MAX_VARINT32_SIZE_BYTES];
+        let len = bytes.len().min(self.total_size_bytes());
+        self.raw_read(index, &mut bytes[..len]);
+        let (len, value) = pw_varint::decode_u64(&bytes[..len]).map_err(|_| Error::DataLoss)?;
+        let value = u32::try_from(value).map_err(|_| Error::DataLoss)?;
+        Ok((len, value))
+    }
+
+    fn raw_available_bytes(&self) -> usize {
+        let len = self.total_size_bytes();
+        let Some(slowest) = self.slowest_reader() else {
+            return len;
+        };
+        let read_idx = slowest.read_idx;
+        if read_idx < self.write_idx {
+            return len - (self.write_idx - read_idx);
+        }
+        if read_idx > self.write_idx {
+            return read_idx - self.write_idx;
+        }
+        // The read and write indices match when the buffer is empty or full.
+        if slowest.entry_count != 0 {
+            0
+        } else {
+            len
+        }
+    }
+
+    fn raw_write(&mut self, data: &[u8]) {
+        let write_idx = self.write_idx;
+        let buffer = self.buffer.as_mut();
+        let first = data.len().min(buffer.len() - write_idx);
+        buffer[write_idx..write_idx + first].copy_from_slice(&data[..first]);
+        buffer[..data.len() - first].copy_from_slice(&data[first..]);
+        self.write_idx = self.increment_index(write_idx, data.len());
+    }
+
+    fn raw_read(&self, index: usize, destination: &mut [u8]) {
+        let buffer = self.buffer.as_ref();
+        let first = destination.len().min(buffer.len() - index);
+        let (start, end) = destination.split_at_mut(first);
+        start.copy_from_slice(&buffer[index..index + first]);
+        end.copy_from_slice(&buffer[..end.len()]);
+    }
+
+    // Reads `len` bytes at `index` into `buffer`, filling it if it is too
+    // small.
+    fn read_out(&self, index: usize, len: usize, buffer: &mut [u8]) -> Result<usize> {
+        let copy_len = len.min(buffer.len());
+        self.raw_read(index, &mut buffer[..copy_len]);
+        if copy_len < len {
+            return Err(Error::ResourceExhausted);
+        }
+        Ok(len)
+    }
+
+    fn increment_index(&self, index: usize, count: usize) -> usize {
+        let index = index + count;
+        let len = self.total_size_bytes();
+        if index >= len {
+            index - len
+        } else {
+            index
+        }
+    }
+}
+
+/// An iterator over the entries in a [`PrefixedEntryRingBuffer`], returned by
+/// [`PrefixedEntryRingBuffer::iter()`].
+pub struct Entries<'a> {
+    buffer: &'a [u8],
+    user_preamble: bool,
+    read_idx: usize,
+    entry_count: usize,
+}
+
+impl<'a> Entries<'a> {
+    fn next_entry(&mut self) -> Result<Entry<'a>> {
+        let mut data = self.buffer.get(self.read_idx..).ok_or(Error::DataLoss)?;
+        let mut preamble = 0;
+        if self.user_preamble {
+            let (len, value) = decode_u32(data)?;
+            preamble = value;
+            data = &data[len..];
+        }
+        let (len, data_bytes) = decode_u32(data)?;
+        let data = data
+            .get(len..len + data_bytes as usize)
+            .ok_or(Error::DataLoss)?;
+        self.read_idx = data.as_ptr() as usize - self.buffer.as_ptr() as usize + data.len();
+        Ok(Entry { data, preamble })
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.entry_count == 0 {
+            return None;
+        }
+        let entry = self.next_entry();
+        self.entry_count = if entry.is_ok() {
+            self.entry_count - 1
+        } else {
+            0
+        };
+        Some(entry)
+    }
+}
+
+fn decode_u32(data: &[u8]) -> Result<(usize, u32)> {
+    let data = &data[..data.len().min(MAX_VARINT32_SIZE_BYTES)];
+    let (len, value) = pw_varint::decode_u64(data).map_err(|_| Error::DataLoss)?;
+    Ok((len, u32::try_from(value).map_err(|_| Error::DataLoss)?))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn pop<B: AsRef<[u8]> + AsMut<[u8]>, const N: usize>(
+        ring: &mut PrefixedEntryRingBuffer<B, N>,
+        reader: ReaderId,
+    ) -> Vec<u8> {
+        let mut buffer = [0u8; 64];
+        let len = ring.peek_front(reader, &mut buffer).unwrap();
+        ring.pop_front(reader).unwrap();
+        buffer[..len].to_vec()
+    }
+
+    #[test]
+    fn entries_are_read_in_order() {
+        let mut ring = PrefixedEntryRingBuffer::<_>::new([0u8; 32], false);
+        let reader = ring.attach_reader().unwrap();
+        let mut buffer = [0u8; 8];
+        assert_eq!(ring.peek_front(reader, &mut buffer), Err(Error::OutOfRange));
+        assert_eq!(ring.pop_front(reader), Err(Error::OutOfRange));
+
+        ring.push_back(b"one", 0).unwrap();
+        ring.push_back(b"three", 0).unwrap();
+        assert_eq!(ring.entry_count(reader), 2);
+        assert_eq!(ring.total_used_bytes(), 10);
+        assert_eq!(ring.front_entry_data_size_bytes(reader), 3);
+        assert_eq!(ring.front_entry_total_size_bytes(reader), 4);
+        assert_eq!(ring.peek_front_with_preamble(reader, &mut buffer), Ok(4));
+        assert_eq!(&buffer[..4], b"\x03one");
+
+        assert_eq!(pop(&mut ring, reader), b"one");
+        assert_eq!(pop(&mut ring, reader), b"three");
+        assert_eq!(ring.entry_count(reader), 0);
+        assert_eq!(ring.total_used_bytes(), 0);
+    }
+
+    #[test]
+    fn peek_into_small_buffer_is_partial() {
+        let mut ring = PrefixedEntryRingBuffer::<_>::new([0u8; 16], false);
+        let reader = ring.attach_reader().unwrap();
+        ring.push_back(b"hello", 0).unwrap();
+        let mut buffer = [0u8; 3];
+        assert_eq!(
+            ring.peek_front(reader, &mut buffer),
+            Err(Error::ResourceExhausted)
+        );
+        assert_eq!(&buffer, b"hel");
+        assert_eq!(ring.entry_count(reader), 1);
+    }
+
+    #[test]
+    fn user_preamble_is_stored_with_entry() {
+        let mut ring = PrefixedEntryRingBuffer::<_>::new([0u8; 32], true);
+        let reader = ring.attach_reader().unwrap();
+        ring.push_back(b"data", 300).unwrap();
+        assert_eq!(ring.peek_front_preamble(reader), Ok(300));
+        assert_eq!(ring.front_entry_total_size_bytes(reader), 7);
+        let mut buffer = [0u8; 8];
+        assert_eq!(ring.peek_front_with_preamble(reader, &mut buffer), Ok(7));
+        assert_eq!(&buffer[..7], b"\xac\x02\x04data");
+        assert_eq!(pop(&mut ring, reader), b"data");
+    }
+
+    #[test]
+    fn entries_wrap_around_end_of_buffer() {
+        let mut ring = PrefixedEntryRingBuffer::<_>::new([0u8; 10], true);
+        let reader = ring.attach_reader().unwrap();
+        for i in 0..20u8 {
+            let data = [i; 3];
+            ring.push_back(&data, u32::from(i) + 1000).unwrap();
+            assert_eq!(ring.peek_front_preamble(reader), Ok(u32::from(i) + 1000));
+            assert_eq!(pop(&mut ring, reader), data);
+        }
+    }
+
+    #[test]
+    fn push_back_drops_oldest_entries() {
+        let mut ring = PrefixedEntryRingBuffer::<_>::new([0u8; 12], false);
+        let reader = ring.attach_reader().unwrap();
+        for i in 0..5u8 {
+            ring.push_back(&[i; 3], 0).unwrap();
+        }
+        assert_eq!(ring.entry_count(reader), 3);
+        assert_eq!(ring.drop_count(reader), 2);
+        assert_eq!(pop(&mut ring, reader), [2; 3]);
+        assert_eq!(pop(&mut ring, reader), [3; 3]);
+        assert_eq!(pop(&mut ring, reader), [4; 3]);
+    }
+
+    #[test]
+    fn try_push_back_fails_when_full() {
+        let mut ring = PrefixedEntryRingBuffer::<_>::new([0u8; 8], false);
+        let reader = ring.attach_reader().unwrap();
+        assert_eq!(ring.try_push_back(&[0; 8], 0), Err(Error::OutOfRange));
+        assert_eq!(ring.push_back(&[0; 8], 0), Err(Error::OutOfRange));
+        ring.try_push_back(&[1; 3], 0).unwrap();
+        ring.try_push_back(&[2; 3], 0).unwrap();
+        assert_eq!(
+            ring.try_push_back(&[3; 1], 0),
+            Err(Error::ResourceExhausted)
+        );
+        assert_eq!(ring.drop_count(reader), 0);
+        assert_eq!(pop(&mut ring, reader), [1; 3]);
+        ring.try_push_back(&[3; 2], 0).unwrap();
+    }
+
+    #[test]
+    fn readers_are_independent() {
+        let mut ring = PrefixedEntryRingBuffer::<_, 2>::new([0u8; 12], false);
+        let fast = ring.attach_reader().unwrap();
+        let slow = ring.attach_reader().unwrap();
+        assert_eq!(ring.attach_reader(), Err(Error::ResourceExhausted));
+
+        ring.push_back(b"abc", 0).unwrap();
+        ring.push_back(b"def", 0).unwrap();
+        assert_eq!(pop(&mut ring, fast), b"abc");
+        assert_eq!(ring.entry_count(fast), 1);
+        assert_eq!(ring.entry_count(slow), 2);
+        // The slow reader keeps the entry the fast reader popped.
+        assert_eq!(ring.total_used_bytes(), 8);
+
+        // Only the slow reader had not popped the dropped entries.
+        ring.push_back(b"ghi", 0).unwrap();
+        ring.push_back(b"jkl", 0).unwrap();
+        assert_eq!(ring.drop_count(fast), 0);
+        assert_eq!(ring.drop_count(slow), 1);
+        assert_eq!(pop(&mut ring, fast), b"def");
+        assert_eq!(pop(&mut ring, slow), b"def");
+
+        // A new reader starts with the slowest reader's entries.
+        ring.detach_reader(fast).unwrap();
+        assert_eq!(ring.detach_reader(fast), Err(Error::InvalidArgument));
+        assert_eq!(ring.entry_count(fast), 0);
+        let late = ring.attach_reader().unwrap();
+        assert_eq!(ring.entry_count(late), 2);
+        assert_eq!(pop(&mut ring, late), b"ghi");
+        assert_eq!(pop(&mut ring, slow), b"ghi");
+    }
+
+    #[test]
+    fn iter_returns_entries_after_dering() {
+        let mut ring = PrefixedEntryRingBuffer::<_, 2>::new([0u8; 16], true);
+        assert_eq!(ring.dering(), Err(Error::FailedPrecondition));
+        assert_eq!(ring.iter().count(), 0);
+        let _reader = ring.attach_reader().unwrap();
+        for i in 0..8u8 {
+            ring.push_back(&[i; 2], u32::from(i)).unwrap();
+        }
+        let entries: Vec<_> = ring
+            .iter()
+            .map(|entry| entry.map(|entry| (entry.data.to_vec(), entry.preamble)))
+            .collect();
+        assert_eq!(
+            entries,
+            [4, 5, 6, 7].map(|i| Ok((vec![i; 2], u32::from(i))))
+        );
+        let buffer = ring.into_inner();
+        assert_eq!(&buffer[..8], b"\x04\x02\x04\x04\x05\x02\x05\x05");
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let mut ring = PrefixedEntryRingBuffer::<_>::new([0u8; 16], false);
+        let reader = ring.attach_reader().unwrap();
+        ring.push_back(b"abc", 0).unwrap();
+        ring.push_back(b"def", 0).unwrap();
+        assert_eq!(ring.check_for_corruption(), Ok(()));
+
+        // Corrupt the second entry's length so it runs past the used bytes.
+        ring.buffer[4] = 0x7f;
+        assert_eq!(ring.check_for_corruption(), Err(Error::DataLoss));
+        let entries: Vec<_> = ring.iter().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1], Err(Error::DataLoss));
+
+        // An unterminated varint is also corrupt.
+        ring.buffer[4..9].fill(0x80);
+        pop(&mut ring, reader);
+        let mut buffer = [0u8; 8];
+        assert_eq!(ring.peek_front(reader, &mut buffer), Err(Error::DataLoss));
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use crate::{Packet, PacketType, LEGACY_OPEN_CALL_ID, OPEN_CALL_ID};
+
+/// Identifies an RPC call between a client and a server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Call {
+    channel_id: u32,
+    service_id: u32,
+    method_id: u32,
+    call_id: u32,
+}
+
+impl Call {
+    pub(crate) const fn new(
+        channel_id: u32,
+        service_id: u32,
+        method_id: u32,
+        call_id: u32,
+    ) -> Self {
+        Self {
+            channel_id,
+            service_id,
+            method_id,
+            call_id,
+        }
+    }
+
+    // Returns the call a packet is for.
+    pub(crate) const fn of(packet: &Packet) -> Self {
+        Self::new(
+            packet.channel_id,
+            packet.service_id,
+            packet.method_id,
+            packet.call_id,
+        )
+    }
+
+    /// Returns the ID of the channel the call is on.
+    pub const fn channel_id(&self) -> u32 {
+        self.channel_id
+    }
+
+    /// Returns the ID of the called service.
+    pub const fn service_id(&self) -> u32 {
+        self.service_id
+    }
+
+    /// Returns the ID of the called method.
+    pub const fn method_id(&self) -> u32 {
+        self.method_id
+    }
+
+    /// Returns the call's ID, which distinguishes concurrent calls to the
+    /// same method.
+    pub const fn call_id(&self) -> u32 {
+        self.call_id
+    }
+
+    // Returns a packet of `packet_type` for the call.
+    pub(crate) const fn packet(&self, packet_type: PacketType) -> Packet<'static> {
+        Packet::new(
+            packet_type,
+            self.channel_id,
+            self.service_id,
+            self.method_id,
+            self.call_id,
+        )
+    }
+
+    // Returns true if `packet` was sent by a server for this call.  Servers
+    // which did not receive the request or do not support call IDs send
+    // packets which match any call to the method.
+    pub(crate) fn matches_response(&self, packet: &Packet) -> bool {
+        self.channel_id == packet.channel_id
+            && self.service_id == packet.service_id
+            && self.method_id == packet.method_id
+            && (self.call_id == packet.call_id
+                || packet.call_id == OPEN_CALL_ID
+                || packet.call_id == LEGACY_OPEN_CALL_ID)
+    }
+}
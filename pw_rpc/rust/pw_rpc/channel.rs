@@ -0,0 +1,177 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_protobuf::MemoryEncoder;
+use pw_status::{Error, Result};
+use pw_stream::Write;
+
+use crate::Packet;
+
+/// Sends encoded packets over a transport, such as HDLC frames on a UART or
+/// a socket, matching the C++ `pw::rpc::ChannelOutput`.
+///
+/// Implemented for closures which take an encoded packet.
+pub trait ChannelOutput {
+    /// Sends an encoded packet.
+    fn send(&mut self, packet: &[u8]) -> Result<()>;
+
+    /// Returns the size of the largest packet the output can send, if it is
+    /// limited.
+    fn maximum_transmission_unit(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<F: FnMut(&[u8]) -> Result<()>> ChannelOutput for F {
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        self(packet)
+    }
+}
+
+/// A [`ChannelOutput`] which writes each packet to a `pw_stream` writer and
+/// flushes it.
+///
+/// The writer must preserve the boundaries between packets, for example by
+/// sending each write as a datagram.
+pub struct WriterOutput<W: Write> {
+    writer: W,
+    mtu: Option<usize>,
+}
+
+impl<W: Write> WriterOutput<W> {
+    /// Creates an output which writes packets to `writer`.
+    pub const fn new(writer: W) -> Self {
+        Self { writer, mtu: None }
+    }
+
+    /// Creates an output which writes packets of up to `mtu` bytes to
+    /// `writer`.
+    pub const fn with_mtu(writer: W, mtu: usize) -> Self {
+        Self {
+            writer,
+            mtu: Some(mtu),
+        }
+    }
+
+    /// Returns a reference to the writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Consumes the output and returns the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> ChannelOutput for WriterOutput<W> {
+    fn send(&mut self, packet: &[u8]) -> Result<()> {
+        self.writer.write_all(packet)?;
+        self.writer.flush()
+    }
+
+    fn maximum_transmission_unit(&self) -> Option<usize> {
+        self.mtu
+    }
+}
+
+/// A channel through which RPC packets are exchanged with another endpoint.
+///
+/// Channels are identified by an ID which both endpoints agree on and send
+/// their packets to a [`ChannelOutput`].
+pub struct Channel<'a> {
+    id: u32,
+    output: &'a mut dyn ChannelOutput,
+}
+
+impl<'a> Channel<'a> {
+    /// Creates a channel with the ID `id` which sends packets to `output`.
+    pub fn new(id: u32, output: &'a mut dyn ChannelOutput) -> Self {
+        Self { id, output }
+    }
+
+    /// Returns the channel's ID.
+    pub const fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Encodes `packet` into `buffer` and sends it.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - The packet does not fit in `buffer` or
+    ///   is larger than the output's maximum transmission unit.
+    ///
+    /// Any error from the output is also returned.
+    pub fn send(&mut self, packet: &Packet, buffer: &mut [u8]) -> Result<()> {
+        let len = packet.encode(buffer)?;
+        self.send_encoded(&buffer[..len])
+    }
+
+    // Sends `packet` with a payload written by `encode`.
+    pub(crate) fn send_with(
+        &mut self,
+        packet: &Packet,
+        buffer: &mut [u8],
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+    ) -> Result<()> {
+        let len = packet.encode_with(buffer, encode)?;
+        self.send_encoded(&buffer[..len])
+    }
+
+    fn send_encoded(&mut self, packet: &[u8]) -> Result<()> {
+        if self
+            .output
+            .maximum_transmission_unit()
+            .is_some_and(|mtu| packet.len() > mtu)
+        {
+            return Err(Error::ResourceExhausted);
+        }
+        self.output.send(packet)
+    }
+}
+
+/// Returns the channel with the ID `id`.
+pub(crate) fn find<'c, 'a>(
+    channels: &'c mut [Channel<'a>],
+    id: u32,
+) -> Option<&'c mut Channel<'a>> {
+    channels.iter_mut().find(|channel| channel.id == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_stream::Cursor;
+
+    use super::*;
+    use crate::PacketType;
+
+    #[test]
+    fn writer_output_writes_packets() {
+        let mut output = WriterOutput::with_mtu(Cursor::new([0u8; 64]), 16);
+        let mut channel = Channel::new(1, &mut output);
+        let mut buffer = [0u8; 64];
+        let packet = Packet::new(PacketType::Response, 1, 2, 3, 4);
+        channel.send(&packet, &mut buffer).unwrap();
+        assert!(
+            channel.send(&packet.with_payload(&[0u8; 16]), &mut buffer)
+                == Err(Error::ResourceExhausted)
+        );
+
+        let len = output.get_ref().position();
+        let data = output.into_inner().into_inner();
+        let sent = Packet::decode(&data[..len]).unwrap();
+        assert_eq!(sent.packet_type, PacketType::Response);
+        assert_eq!(sent.call_id, 4);
+    }
+}
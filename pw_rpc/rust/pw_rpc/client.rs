@@ -0,0 +1,610 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_protobuf::MemoryEncoder;
+use pw_status::{Error, Result};
+
+use crate::channel::{self, Channel};
+use crate::{Call, Packet, PacketType, LEGACY_OPEN_CALL_ID, OPEN_CALL_ID};
+
+/// A packet a server sent for an active call.
+pub enum ClientEvent<'p> {
+    /// The server finished the call.  The call is no longer active.
+    Completed {
+        /// The finished call.
+        call: Call,
+        /// The response message of a unary or client streaming call.
+        payload: &'p [u8],
+        /// The status the server finished the call with.
+        status: Result<()>,
+    },
+    /// The server sent a stream message.
+    Stream {
+        /// The call the message is for.
+        call: Call,
+        /// The encoded message.
+        payload: &'p [u8],
+    },
+    /// The server could not process the call.  The call is no longer
+    /// active.
+    Error {
+        /// The failed call.
+        call: Call,
+        /// The error the server reported.
+        error: Error,
+    },
+}
+
+/// The client side of the `pw_rpc` protocol, compatible with C++, Python,
+/// and TypeScript servers.
+///
+/// The client starts calls to services on its channels and tracks up to
+/// `MAX_CALLS` active calls.  Packets received from servers are passed to
+/// [`Client::process_packet()`], which returns the event for the call the
+/// packet is for.  Request messages and responses are encoded protobufs.
+///
+/// ```
+/// use pw_rpc::{id, Channel, Client, ClientEvent, Packet, PacketType};
+/// use pw_status::Result;
+///
+/// let mut sent = [0u8; 64];
+/// let mut sent_len = 0;
+/// let mut output = |packet: &[u8]| -> Result<()> {
+///     sent[..packet.len()].copy_from_slice(packet);
+///     sent_len = packet.len();
+///     Ok(())
+/// };
+/// let mut channels = [Channel::new(1, &mut output)];
+/// let mut buffer = [0u8; 64];
+/// let mut client = Client::<4>::new(&mut channels, &mut buffer);
+///
+/// let call = client
+///     .invoke(1, id("pw.rpc.EchoService"), id("Echo"), b"\x0a\x02hi")
+///     .unwrap();
+///
+/// // The server's response.
+/// let mut response = [0u8; 64];
+/// let len = Packet::new(PacketType::Response, 1, call.service_id(), call.method_id(), call.call_id())
+///     .with_payload(b"\x0a\x02hi")
+///     .encode(&mut response)
+///     .unwrap();
+/// match client.process_packet(&response[..len]).unwrap() {
+///     Some(ClientEvent::Completed { payload, status, .. }) => {
+///         assert_eq!(payload, b"\x0a\x02hi");
+///         assert!(status.is_ok());
+///     }
+///     _ => panic!("unexpected event"),
+/// }
+/// assert!(!client.is_active(&call));
+/// ```
+pub struct Client<'a, const MAX_CALLS: usize> {
+    channels: &'a mut [Channel<'a>],
+    encoding_buffer: &'a mut [u8],
+    calls: [Option<Call>; MAX_CALLS],
+    next_call_id: u32,
+}
+
+impl<'a, const MAX_CALLS: usize> Client<'a, MAX_CALLS> {
+    /// Creates a client which communicates over `channels`.
+    ///
+    /// Packets are encoded in `encoding_buffer`, which limits the size of
+    /// request messages.
+    pub fn new(channels: &'a mut [Channel<'a>], encoding_buffer: &'a mut [u8]) -> Self {
+        Self {
+            channels,
+            encoding_buffer,
+            calls: [None; MAX_CALLS],
+            next_call_id: 1,
+        }
+    }
+
+    /// Starts a call to a method by sending `request` to the server on
+    /// channel `channel_id`.
+    ///
+    /// # Errors
+    /// - [`Error::NotFound`] - The client has no channel `channel_id`.
+    /// - [`Error::ResourceExhausted`] - `MAX_CALLS` calls are already active
+    ///   or the request does not fit in the encoding buffer.
+    ///
+    /// Any error sending the request is also returned.
+    pub fn invoke(
+        &mut self,
+        channel_id: u32,
+        service_id: u32,
+        method_id: u32,
+        request: &[u8],
+    ) -> Result<Call> {
+        let (slot, call) = self.new_call(channel_id, service_id, method_id)?;
+        self.send(&call, PacketType::Request, request, Ok(()))?;
+        self.calls[slot] = Some(call);
+        Ok(call)
+    }
+
+    /// Starts a call like [`Client::invoke()`], with a request whose fields
+    /// are written by `encode`, such as a generated message's
+    /// `encode_fields()`.
+    pub fn invoke_with(
+        &mut self,
+        channel_id: u32,
+        service_id: u32,
+        method_id: u32,
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+    ) -> Result<Call> {
+        let (slot, call) = self.new_call(channel_id, service_id, method_id)?;
+        self.send_with(&call.packet(PacketType::Request), encode)?;
+        self.calls[slot] = Some(call);
+        Ok(call)
+    }
+
+    /// Sends a client stream message for `call`.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - `call` is not active.
+    ///
+    /// Any error sending the message is also returned.
+    pub fn write(&mut self, call: &Call, payload: &[u8]) -> Result<()> {
+        self.check_active(call)?;
+        self.send(call, PacketType::ClientStream, payload, Ok(()))
+    }
+
+    /// Sends a client stream message like [`Client::write()`], whose fields
+    /// are written by `encode`.
+    pub fn write_with(
+        &mut self,
+        call: &Call,
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+    ) -> Result<()> {
+        self.check_active(call)?;
+        self.send_with(&call.packet(PacketType::ClientStream), encode)
+    }
+
+    /// Tells the server that the client is done sending stream messages for
+    /// `call`.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - `call` is not active.
+    ///
+    /// Any error sending the packet is also returned.
+    pub fn request_completion(&mut self, call: &Call) -> Result<()> {
+        self.check_active(call)?;
+        self.send(call, PacketType::ClientRequestCompletion, &[], Ok(()))
+    }
+
+    /// Cancels `call` and tells the server.
+    ///
+    /// The call is no longer active even if the server could not be told.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - `call` is not active.
+    ///
+    /// Any error sending the packet is also returned.
+    pub fn cancel(&mut self, call: &Call) -> Result<()> {
+        self.abandon(call)?;
+        self.send(call, PacketType::ClientError, &[], Err(Error::Cancelled))
+    }
+
+    /// Stops tracking `call` without telling the server.  Later packets for
+    /// the call are rejected.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - `call` is not active.
+    pub fn abandon(&mut self, call: &Call) -> Result<()> {
+        let slot = self.find(call).ok_or(Error::FailedPrecondition)?;
+        self.calls[slot] = None;
+        Ok(())
+    }
+
+    /// Returns true if `call` has not finished.
+    pub fn is_active(&self, call: &Call) -> bool {
+        self.find(call).is_some()
+    }
+
+    /// Returns the number of active calls.
+    pub fn active_calls(&self) -> usize {
+        self.calls.iter().flatten().count()
+    }
+
+    /// Processes a packet received from a server and returns the event for
+    /// its call.
+    ///
+    /// Returns `Ok(None)` for packets which are not for an active call.  The
+    /// server is told of the unexpected packet with a
+    /// [`PacketType::ClientError`] packet.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The packet could not be decoded.
+    /// - [`Error::InvalidArgument`] - The packet is addressed to a server.
+    /// - [`Error::NotFound`] - The packet is for a channel the client does
+    ///   not have.
+    pub fn process_packet<'p>(&mut self, data: &'p [u8]) -> Result<Option<ClientEvent<'p>>> {
+        let packet = Packet::decode(data)?;
+        if packet.packet_type.is_to_server() {
+            return Err(Error::InvalidArgument);
+        }
+        if !self
+            .channels
+            .iter()
+            .any(|channel| channel.id() == packet.channel_id)
+        {
+            return Err(Error::NotFound);
+        }
+
+        let Some(slot) = self
+            .calls
+            .iter()
+            .position(|call| call.is_some_and(|call| call.matches_response(&packet)))
+        else {
+            if packet.packet_type != PacketType::ServerError {
+                // A failure to notify the server is not an error processing
+                // the packet.
+                let reply = packet
+                    .reply(PacketType::ClientError)
+                    .with_status(Err(Error::FailedPrecondition));
+                let _ = self.send_packet(&reply);
+            }
+            return Ok(None);
+        };
+
+        let Some(call) = self.calls[slot] else {
+            return Ok(None);
+        };
+        let event = match packet.packet_type {
+            PacketType::ServerStream => ClientEvent::Stream {
+                call,
+                payload: packet.payload,
+            },
+            PacketType::Response => {
+                self.calls[slot] = None;
+                ClientEvent::Completed {
+                    call,
+                    payload: packet.payload,
+                    status: packet.status,
+                }
+            }
+            _ => {
+                self.calls[slot] = None;
+                ClientEvent::Error {
+                    call,
+                    error: packet.status.err().unwrap_or(Error::Unknown),
+                }
+            }
+        };
+        Ok(Some(event))
+    }
+
+    // Returns a free slot and a new call, which is not active until its
+    // request is sent.
+    fn new_call(
+        &mut self,
+        channel_id: u32,
+        service_id: u32,
+        method_id: u32,
+    ) -> Result<(usize, Call)> {
+        if !self
+            .channels
+            .iter()
+            .any(|channel| channel.id() == channel_id)
+        {
+            return Err(Error::NotFound);
+        }
+        let slot = self
+            .calls
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Error::ResourceExhausted)?;
+
+        let call = Call::new(channel_id, service_id, method_id, self.next_call_id);
+        self.next_call_id = match self.next_call_id.wrapping_add(1) {
+            LEGACY_OPEN_CALL_ID | OPEN_CALL_ID => 1,
+            id => id,
+        };
+        Ok((slot, call))
+    }
+
+    fn find(&self, call: &Call) -> Option<usize> {
+        self.calls
+            .iter()
+            .position(|active| active.as_ref() == Some(call))
+    }
+
+    fn check_active(&self, call: &Call) -> Result<()> {
+        self.find(call).map(|_| ()).ok_or(Error::FailedPrecondition)
+    }
+
+    fn send(
+        &mut self,
+        call: &Call,
+        packet_type: PacketType,
+        payload: &[u8],
+        status: Result<()>,
+    ) -> Result<()> {
+        let packet = call
+            .packet(packet_type)
+            .with_payload(payload)
+            .with_status(status);
+        self.send_packet(&packet)
+    }
+
+    fn send_packet(&mut self, packet: &Packet) -> Result<()> {
+        channel::find(self.channels, packet.channel_id)
+            .ok_or(Error::NotFound)?
+            .send(packet, self.encoding_buffer)
+    }
+
+    fn send_with(
+        &mut self,
+        packet: &Packet,
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+    ) -> Result<()> {
+        channel::find(self.channels, packet.channel_id)
+            .ok_or(Error::NotFound)?
+            .send_with(packet, self.encoding_buffer, encode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::id;
+
+    const SERVICE_ID: u32 = id("pw.rpc.test.TestService");
+    const METHOD_ID: u32 = id("TestMethod");
+
+    // Decodes the packets sent to `sent`.
+    fn decode(sent: &[Vec<u8>]) -> Vec<Packet<'_>> {
+        sent.iter()
+            .map(|packet| Packet::decode(packet).unwrap())
+            .collect()
+    }
+
+    fn server_packet<'b>(buffer: &'b mut [u8], packet: Packet) -> &'b [u8] {
+        let len = packet.encode(buffer).unwrap();
+        &buffer[..len]
+    }
+
+    #[test]
+    fn invoke_sends_request_with_call_id() {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            sent.push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(3, &mut output)];
+        let mut buffer = [0u8; 64];
+        let mut client = Client::<2>::new(&mut channels, &mut buffer);
+
+        let first = client.invoke(3, SERVICE_ID, METHOD_ID, b"req").unwrap();
+        let second = client.invoke(3, SERVICE_ID, METHOD_ID, b"").unwrap();
+        assert_ne!(first.call_id(), second.call_id());
+        assert_eq!(client.active_calls(), 2);
+        assert!(matches!(
+            client.invoke(3, SERVICE_ID, METHOD_ID, b""),
+            Err(Error::ResourceExhausted)
+        ));
+        assert!(matches!(
+            client.invoke(4, SERVICE_ID, METHOD_ID, b""),
+            Err(Error::NotFound)
+        ));
+
+        let packets = decode(&sent);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].packet_type, PacketType::Request);
+        assert_eq!(
+            (
+                packets[0].channel_id,
+                packets[0].service_id,
+                packets[0].method_id
+            ),
+            (3, SERVICE_ID, METHOD_ID)
+        );
+        assert_eq!(packets[0].call_id, first.call_id());
+        assert_eq!(packets[0].payload, b"req");
+    }
+
+    #[test]
+    fn server_stream_messages_are_returned_until_completion() {
+        let mut output = |_: &[u8]| -> Result<()> { Ok(()) };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut buffer = [0u8; 64];
+        let mut client = Client::<2>::new(&mut channels, &mut buffer);
+        let call = client.invoke(1, SERVICE_ID, METHOD_ID, b"").unwrap();
+
+        let mut packet = [0u8; 64];
+        let reply =
+            |packet_type| Packet::new(packet_type, 1, SERVICE_ID, METHOD_ID, call.call_id());
+        for message in [b"one", b"two"] {
+            let data = server_packet(
+                &mut packet,
+                reply(PacketType::ServerStream).with_payload(message),
+            );
+            match client.process_packet(data).unwrap() {
+                Some(ClientEvent::Stream { call: c, payload }) => {
+                    assert_eq!(c, call);
+                    assert_eq!(payload, message);
+                }
+                _ => panic!("expected stream message"),
+            }
+        }
+        assert!(client.is_active(&call));
+
+        let data = server_packet(
+            &mut packet,
+            reply(PacketType::Response).with_status(Err(Error::Aborted)),
+        );
+        match client.process_packet(data).unwrap() {
+            Some(ClientEvent::Completed { status, .. }) => {
+                assert!(status == Err(Error::Aborted))
+            }
+            _ => panic!("expected completion"),
+        }
+        assert_eq!(client.active_calls(), 0);
+    }
+
+    #[test]
+    fn server_error_ends_call() {
+        let mut output = |_: &[u8]| -> Result<()> { Ok(()) };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut buffer = [0u8; 64];
+        let mut client = Client::<1>::new(&mut channels, &mut buffer);
+        let call = client.invoke(1, SERVICE_ID, METHOD_ID, b"").unwrap();
+
+        let mut packet = [0u8; 64];
+        // Servers which do not support call IDs respond with call ID 0.
+        let data = server_packet(
+            &mut packet,
+            Packet::new(PacketType::ServerError, 1, SERVICE_ID, METHOD_ID, 0)
+                .with_status(Err(Error::NotFound)),
+        );
+        match client.process_packet(data).unwrap() {
+            Some(ClientEvent::Error { call: c, error }) => {
+                assert_eq!(c, call);
+                assert!(error == Error::NotFound);
+            }
+            _ => panic!("expected error"),
+        }
+        assert!(!client.is_active(&call));
+    }
+
+    #[test]
+    fn unexpected_packets_are_rejected() {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            sent.push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut buffer = [0u8; 64];
+        let mut client = Client::<1>::new(&mut channels, &mut buffer);
+
+        let mut packet = [0u8; 64];
+        let response = Packet::new(PacketType::Response, 1, SERVICE_ID, METHOD_ID, 9);
+        let data = server_packet(&mut packet, response);
+        assert!(matches!(client.process_packet(data), Ok(None)));
+
+        let data = server_packet(&mut packet, response.reply(PacketType::Request));
+        assert!(matches!(
+            client.process_packet(data),
+            Err(Error::InvalidArgument)
+        ));
+        let mut other_channel = response;
+        other_channel.channel_id = 2;
+        let data = server_packet(&mut packet, other_channel);
+        assert!(matches!(client.process_packet(data), Err(Error::NotFound)));
+        assert!(matches!(
+            client.process_packet(&[0x08]),
+            Err(Error::DataLoss)
+        ));
+
+        let packets = decode(&sent);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].packet_type, PacketType::ClientError);
+        assert_eq!(packets[0].call_id, 9);
+        assert!(packets[0].status == Err(Error::FailedPrecondition));
+    }
+
+    #[test]
+    fn client_stream_and_cancellation() {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            sent.push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut buffer = [0u8; 64];
+        let mut client = Client::<2>::new(&mut channels, &mut buffer);
+
+        let call = client.invoke(1, SERVICE_ID, METHOD_ID, b"").unwrap();
+        client.write(&call, b"msg").unwrap();
+        client.request_completion(&call).unwrap();
+        client.cancel(&call).unwrap();
+        assert!(matches!(
+            client.write(&call, b"late"),
+            Err(Error::FailedPrecondition)
+        ));
+        assert!(matches!(
+            client.cancel(&call),
+            Err(Error::FailedPrecondition)
+        ));
+
+        let other = client.invoke(1, SERVICE_ID, METHOD_ID, b"").unwrap();
+        client.abandon(&other).unwrap();
+        assert_eq!(client.active_calls(), 0);
+
+        let types: Vec<_> = decode(&sent).iter().map(|p| p.packet_type).collect();
+        assert_eq!(
+            types,
+            [
+                PacketType::Request,
+                PacketType::ClientStream,
+                PacketType::ClientRequestCompletion,
+                PacketType::ClientError,
+                PacketType::Request,
+            ]
+        );
+        assert!(decode(&sent)[3].status == Err(Error::Cancelled));
+    }
+
+    #[test]
+    fn requests_can_be_encoded_in_place() {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            sent.push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut buffer = [0u8; 64];
+        let mut client = Client::<1>::new(&mut channels, &mut buffer);
+
+        let call = client
+            .invoke_with(1, SERVICE_ID, METHOD_ID, |encoder| {
+                encoder.write_uint32(1, 1)
+            })
+            .unwrap();
+        client
+            .write_with(&call, |encoder| encoder.write_string(2, "hi"))
+            .unwrap();
+        assert!(matches!(
+            client.invoke_with(1, SERVICE_ID, METHOD_ID, |_| Ok(())),
+            Err(Error::ResourceExhausted)
+        ));
+
+        let packets = decode(&sent);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].packet_type, PacketType::Request);
+        assert_eq!(packets[0].payload, b"\x08\x01");
+        assert_eq!(packets[1].packet_type, PacketType::ClientStream);
+        assert_eq!(packets[1].payload, b"\x12\x02hi");
+    }
+
+    #[test]
+    fn failed_request_does_not_start_call() {
+        let mut output = |_: &[u8]| -> Result<()> { Err(Error::Unavailable) };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut buffer = [0u8; 64];
+        let mut client = Client::<1>::new(&mut channels, &mut buffer);
+        assert!(matches!(
+            client.invoke(1, SERVICE_ID, METHOD_ID, b""),
+            Err(Error::Unavailable)
+        ));
+        assert!(matches!(
+            client.invoke(1, SERVICE_ID, METHOD_ID, &[0u8; 64]),
+            Err(Error::ResourceExhausted)
+        ));
+        assert_eq!(client.active_calls(), 0);
+    }
+}
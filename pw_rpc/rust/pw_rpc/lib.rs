@@ -0,0 +1,76 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_rpc` implements Pigweed's RPC protocol, compatible with the C++,
+//! Python, and TypeScript `pw_rpc` implementations.
+//!
+//! RPC endpoints exchange [`Packet`]s over [`Channel`]s.  Each channel sends
+//! its packets to a [`ChannelOutput`], such as an HDLC encoder writing to a
+//! UART.  Services and methods are identified by the [`id()`] of their
+//! names.  Request and response messages are encoded protobufs, for example
+//! with `pw_protobuf`.
+//!
+//! A [`Client`] invokes methods on servers and tracks its calls until they
+//! finish.  A [`Server`] dispatches requests to [`Service`]s without
+//! allocating, so it can run on devices.  The standard [`EchoService`] and
+//! [`BenchmarkService`] check and measure a server from other clients.
+#![no_std]
+#![deny(missing_docs)]
+
+mod call;
+mod channel;
+mod client;
+mod packet;
+mod server;
+mod services;
+
+pub use call::Call;
+pub use channel::{Channel, ChannelOutput, WriterOutput};
+pub use client::{Client, ClientEvent};
+pub use packet::{Packet, PacketType, LEGACY_OPEN_CALL_ID, MAX_PACKET_OVERHEAD, OPEN_CALL_ID};
+pub use server::{MethodType, Responder, Server, Service};
+pub use services::{BenchmarkService, EchoService};
+
+/// Returns the ID of a service or method, which is the `pw_tokenizer` hash
+/// of its name.
+///
+/// Services are named by their fully qualified protobuf name, such as
+/// `"pw.rpc.EchoService"`, and methods by their name, such as `"Echo"`.
+pub const fn id(name: &str) -> u32 {
+    pw_tokenizer_core::hash_string(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_match_python_and_cpp() {
+        // Test cases from `pw_rpc/py/tests/ids_test.py`.
+        for (expected, name) in [
+            (0x00000000, ""),
+            (0x00000001, "\0"),
+            (0x00010040, "\x01"),
+            (0x003F0F82, "?"),
+            (0xD3556087, "\0\0\0\x01\x01\x01\x01"),
+            (0x63D43D8C, "Pigweed?"),
+            (
+                0x79AB6494,
+                "Pigweed!Pigweed!Pigweed!Pigweed!Pigweed!Pigweed!",
+            ),
+        ] {
+            assert_eq!(id(name), expected, "{name:?}");
+        }
+    }
+}
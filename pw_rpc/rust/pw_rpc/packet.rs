@@ -0,0 +1,336 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_protobuf::{Decoder, MemoryEncoder};
+use pw_status::{from_c_status, to_c_status, Error, Result};
+
+// Field numbers of the `pw.rpc.internal.RpcPacket` message.
+const TYPE_FIELD: u32 = 1;
+const CHANNEL_ID_FIELD: u32 = 2;
+const SERVICE_ID_FIELD: u32 = 3;
+const METHOD_ID_FIELD: u32 = 4;
+const PAYLOAD_FIELD: u32 = 5;
+const STATUS_FIELD: u32 = 6;
+const CALL_ID_FIELD: u32 = 7;
+
+/// The call ID a server uses for responses to calls it did not receive a
+/// request for, which match any call to the method.
+pub const OPEN_CALL_ID: u32 = u32::MAX;
+
+/// The call ID of packets from endpoints which do not support call IDs.
+pub const LEGACY_OPEN_CALL_ID: u32 = 0;
+
+/// The largest size of an encoded packet's fields other than its payload
+/// data.
+pub const MAX_PACKET_OVERHEAD: usize = 2 // type
+    + 6 // channel_id
+    + 5 // service_id
+    + 5 // method_id
+    + 6 // payload key and length
+    + 2 // status
+    + 6; // call_id
+
+/// The type of an RPC packet, from the `pw.rpc.internal.PacketType` enum.
+///
+/// Packets sent to servers have even values and packets sent to clients odd
+/// values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PacketType {
+    /// The client invokes an RPC.  Always the first packet of a call.
+    Request = 0,
+    /// The RPC has finished.
+    Response = 1,
+    /// A message in a client stream.
+    ClientStream = 2,
+    /// The client received a packet for an RPC it did not request, or is
+    /// cancelling the RPC.
+    ClientError = 4,
+    /// The server was unable to process a request.
+    ServerError = 5,
+    /// A message in a server stream.
+    ServerStream = 7,
+    /// The client is done sending requests.
+    ClientRequestCompletion = 8,
+}
+
+impl PacketType {
+    /// Returns the packet type with the wire value `value`, if it is valid.
+    pub const fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Request),
+            1 => Some(Self::Response),
+            2 => Some(Self::ClientStream),
+            4 => Some(Self::ClientError),
+            5 => Some(Self::ServerError),
+            7 => Some(Self::ServerStream),
+            8 => Some(Self::ClientRequestCompletion),
+            _ => None,
+        }
+    }
+
+    /// Returns true if packets of this type are sent to servers.
+    pub const fn is_to_server(self) -> bool {
+        self as u32 & 1 == 0
+    }
+}
+
+/// An RPC packet, matching the C++ `pw::rpc::internal::Packet`.
+#[derive(Clone, Copy)]
+pub struct Packet<'a> {
+    /// The type of the packet.
+    pub packet_type: PacketType,
+    /// The channel the packet is sent on.
+    pub channel_id: u32,
+    /// The ID of the service the packet is for.
+    pub service_id: u32,
+    /// The ID of the method the packet is for.
+    pub method_id: u32,
+    /// The ID of the call the packet is for.
+    pub call_id: u32,
+    /// The encoded protobuf message carried by the packet.
+    pub payload: &'a [u8],
+    /// The status of a finished RPC or error.
+    pub status: Result<()>,
+}
+
+impl<'a> Packet<'a> {
+    /// Creates a packet with an empty payload and OK status.
+    pub const fn new(
+        packet_type: PacketType,
+        channel_id: u32,
+        service_id: u32,
+        method_id: u32,
+        call_id: u32,
+    ) -> Self {
+        Self {
+            packet_type,
+            channel_id,
+            service_id,
+            method_id,
+            call_id,
+            payload: &[],
+            status: Ok(()),
+        }
+    }
+
+    /// Returns the packet with its payload set to `payload`.
+    pub const fn with_payload(mut self, payload: &'a [u8]) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Returns the packet with its status set to `status`.
+    pub const fn with_status(mut self, status: Result<()>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Returns a packet of `packet_type` for the same call as this packet.
+    pub const fn reply(&self, packet_type: PacketType) -> Packet<'static> {
+        Packet::new(
+            packet_type,
+            self.channel_id,
+            self.service_id,
+            self.method_id,
+            self.call_id,
+        )
+    }
+
+    /// Decodes a packet.  Missing fields take their default values.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - `data` is not a valid packet.
+    pub fn decode(data: &'a [u8]) -> Result<Self> {
+        let mut packet = Self::new(PacketType::Request, 0, 0, 0, 0);
+        for field in Decoder::new(data) {
+            let field = field?;
+            match field.number {
+                TYPE_FIELD => {
+                    packet.packet_type =
+                        PacketType::from_u32(field.value.as_u32()?).ok_or(Error::DataLoss)?
+                }
+                CHANNEL_ID_FIELD => packet.channel_id = field.value.as_u32()?,
+                SERVICE_ID_FIELD => packet.service_id = field.value.as_fixed32()?,
+                METHOD_ID_FIELD => packet.method_id = field.value.as_fixed32()?,
+                PAYLOAD_FIELD => packet.payload = field.value.as_bytes()?,
+                STATUS_FIELD => {
+                    let code = field.value.as_u32()?;
+                    packet.status = from_c_status(code.try_into().map_err(|_| Error::DataLoss)?)
+                }
+                CALL_ID_FIELD => packet.call_id = field.value.as_u32()?,
+                // Unknown fields are ignored for forward compatibility.
+                _ => {}
+            }
+        }
+        Ok(packet)
+    }
+
+    /// Encodes the packet into `buffer` and returns the encoded size.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - The packet does not fit in `buffer`.
+    pub fn encode(&self, buffer: &mut [u8]) -> Result<usize> {
+        let mut encoder = MemoryEncoder::new(buffer);
+        if !self.payload.is_empty() {
+            encoder.write_bytes(PAYLOAD_FIELD, self.payload)?;
+        }
+        self.encode_fields(encoder)
+    }
+
+    // Encodes the packet into `buffer` with a payload written by `encode` in
+    // place of its payload, and returns the encoded size.
+    pub(crate) fn encode_with(
+        &self,
+        buffer: &mut [u8],
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+    ) -> Result<usize> {
+        let mut encoder = MemoryEncoder::new(buffer);
+        encoder.write_nested(PAYLOAD_FIELD, encode)?;
+        self.encode_fields(encoder)
+    }
+
+    // Writes the fields other than the payload.
+    fn encode_fields(&self, mut encoder: MemoryEncoder) -> Result<usize> {
+        encoder.write_uint32(TYPE_FIELD, self.packet_type as u32)?;
+        encoder.write_uint32(CHANNEL_ID_FIELD, self.channel_id)?;
+        encoder.write_fixed32(SERVICE_ID_FIELD, self.service_id)?;
+        encoder.write_fixed32(METHOD_ID_FIELD, self.method_id)?;
+        // An OK status and a zero call ID are the default values, so they are
+        // not encoded.
+        if self.status.is_err() {
+            encoder.write_uint32(STATUS_FIELD, to_c_status(&self.status) as u32)?;
+        }
+        if self.call_id != 0 {
+            encoder.write_uint32(CALL_ID_FIELD, self.call_id)?;
+        }
+        Ok(encoder.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packet_matches_cpp_encoding() {
+        let packet = Packet::new(PacketType::Response, 1, 42, 100, 7)
+            .with_payload(b"\x08\x01")
+            .with_status(Err(Error::Unavailable));
+        let mut buffer = [0u8; 64];
+        let len = packet.encode(&mut buffer).unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            0x2a, 0x02, 0x08, 0x01,
+            0x08, 0x01,
+            0x10, 0x01,
+            0x1d, 42, 0, 0, 0,
+            0x25, 100, 0, 0, 0,
+            0x30, 14,
+            0x38, 7,
+        ];
+        assert_eq!(&buffer[..len], &expected);
+
+        let decoded = Packet::decode(&buffer[..len]).unwrap();
+        assert_eq!(decoded.packet_type, PacketType::Response);
+        assert_eq!(
+            (
+                decoded.channel_id,
+                decoded.service_id,
+                decoded.method_id,
+                decoded.call_id
+            ),
+            (1, 42, 100, 7)
+        );
+        assert_eq!(decoded.payload, b"\x08\x01");
+        assert!(decoded.status == Err(Error::Unavailable));
+    }
+
+    #[test]
+    fn payload_can_be_encoded_in_place() {
+        let packet = Packet::new(PacketType::Response, 1, 42, 100, 7);
+        let mut expected = [0u8; 64];
+        let len = packet
+            .with_payload(b"\x08\x01")
+            .encode(&mut expected)
+            .unwrap();
+        let mut buffer = [0u8; 64];
+        assert_eq!(
+            packet.encode_with(&mut buffer, |encoder| encoder.write_uint32(1, 1)),
+            Ok(len)
+        );
+        assert_eq!(buffer[..len], expected[..len]);
+        assert_eq!(
+            packet.encode_with(&mut buffer[..8], |encoder| encoder.write_bytes(1, &[0; 8])),
+            Err(Error::ResourceExhausted)
+        );
+    }
+
+    #[test]
+    fn default_fields_are_omitted() {
+        let mut buffer = [0u8; 64];
+        let len = Packet::new(PacketType::Request, 1, 2, 3, 0)
+            .encode(&mut buffer)
+            .unwrap();
+        assert_eq!(len, 2 + 2 + 5 + 5);
+        let decoded = Packet::decode(&buffer[..len]).unwrap();
+        assert_eq!(decoded.call_id, 0);
+        assert!(decoded.status.is_ok());
+        assert!(decoded.payload.is_empty());
+    }
+
+    #[test]
+    fn largest_packet_overhead_fits() {
+        let payload = [0u8; 300];
+        let packet = Packet::new(
+            PacketType::ClientRequestCompletion,
+            u32::MAX,
+            1,
+            2,
+            u32::MAX,
+        )
+        .with_payload(&payload)
+        .with_status(Err(Error::Unauthenticated));
+        let mut buffer = [0u8; MAX_PACKET_OVERHEAD + 300];
+        assert!(packet.encode(&mut buffer).is_ok());
+        assert_eq!(
+            packet.encode(&mut buffer[..MAX_PACKET_OVERHEAD]),
+            Err(Error::ResourceExhausted)
+        );
+    }
+
+    #[test]
+    fn malformed_packets_are_data_loss() {
+        // A truncated payload, an invalid type, and a wrongly typed field.
+        for data in [&[0x2a, 0x02, 0x08][..], &[0x08, 0x03], &[0x18, 0x01]] {
+            assert!(matches!(Packet::decode(data), Err(Error::DataLoss)));
+        }
+    }
+
+    #[test]
+    fn packet_destination_is_determined_by_type() {
+        for (packet_type, to_server) in [
+            (PacketType::Request, true),
+            (PacketType::Response, false),
+            (PacketType::ClientStream, true),
+            (PacketType::ClientError, true),
+            (PacketType::ServerError, false),
+            (PacketType::ServerStream, false),
+            (PacketType::ClientRequestCompletion, true),
+        ] {
+            assert_eq!(packet_type.is_to_server(), to_server);
+            assert_eq!(PacketType::from_u32(packet_type as u32), Some(packet_type));
+        }
+    }
+}
@@ -0,0 +1,839 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_protobuf::MemoryEncoder;
+use pw_status::{Error, Result};
+
+use crate::channel::{self, Channel};
+use crate::{Call, Packet, PacketType};
+
+/// The kinds of RPC methods.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MethodType {
+    /// The client sends one request and the server sends one response.
+    Unary,
+    /// The client sends one request and the server sends any number of
+    /// responses.
+    ServerStreaming,
+    /// The client sends any number of requests and the server sends one
+    /// response.
+    ClientStreaming,
+    /// The client and server both send any number of messages.
+    BidirectionalStreaming,
+}
+
+impl MethodType {
+    /// Returns true if the client sends a stream of requests.
+    pub const fn has_client_stream(self) -> bool {
+        matches!(
+            self,
+            MethodType::ClientStreaming | MethodType::BidirectionalStreaming
+        )
+    }
+
+    /// Returns true if the server sends a stream of responses.
+    pub const fn has_server_stream(self) -> bool {
+        matches!(
+            self,
+            MethodType::ServerStreaming | MethodType::BidirectionalStreaming
+        )
+    }
+}
+
+/// An RPC service which a [`Server`] dispatches requests to.
+///
+/// Client and bidirectional streaming calls start with an empty request.
+/// [`Service::invoke()`] must defer them to receive the client's stream
+/// messages in [`Service::client_stream()`].
+pub trait Service {
+    /// Returns the service's ID, which is the [`crate::id()`] of its fully
+    /// qualified name.
+    fn id(&self) -> u32;
+
+    /// Returns the type of the method with the ID `method_id`, or `None` if
+    /// the service has no such method.
+    fn method_type(&self, method_id: u32) -> Option<MethodType>;
+
+    /// Handles a request to one of the service's methods.
+    ///
+    /// Respond through `responder`.  If the call is neither finished nor
+    /// deferred when this returns, the server finishes it with
+    /// [`Error::Internal`].
+    fn invoke(&mut self, request: &[u8], responder: &mut Responder<'_, '_>);
+
+    /// Handles a stream message from the client of a deferred client or
+    /// bidirectional streaming call.
+    fn client_stream(&mut self, _request: &[u8], _responder: &mut Responder<'_, '_>) {}
+
+    /// Called when the client of a deferred client or bidirectional
+    /// streaming call finishes sending stream messages.
+    fn client_stream_end(&mut self, _responder: &mut Responder<'_, '_>) {}
+
+    /// Called when the client cancels a deferred call.
+    fn cancel(&mut self, _call: &Call) {}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct ActiveCall {
+    call: Call,
+    method_type: MethodType,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ResponderState {
+    Open,
+    Deferred,
+    Finished,
+}
+
+/// Sends the responses to a call while a [`Service`] handles one of its
+/// requests.
+pub struct Responder<'r, 'a> {
+    call: Call,
+    method_type: MethodType,
+    channel: &'r mut Channel<'a>,
+    buffer: &'r mut [u8],
+    slot: Option<&'r mut Option<ActiveCall>>,
+    state: ResponderState,
+}
+
+impl Responder<'_, '_> {
+    /// Returns the call being handled.
+    pub fn call(&self) -> Call {
+        self.call
+    }
+
+    /// Returns the type of the called method.
+    pub fn method_type(&self) -> MethodType {
+        self.method_type
+    }
+
+    /// Sends a stream message for a server or bidirectional streaming call.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The call is finished or the server
+    ///   does not stream its responses.
+    ///
+    /// Any error sending the message is also returned.
+    pub fn write(&mut self, payload: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        let packet = self
+            .call
+            .packet(PacketType::ServerStream)
+            .with_payload(payload);
+        self.channel.send(&packet, self.buffer)
+    }
+
+    /// Sends a stream message like [`Responder::write()`], whose fields are
+    /// written by `encode`, such as a generated message's `encode_fields()`.
+    pub fn write_with(
+        &mut self,
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let packet = self.call.packet(PacketType::ServerStream);
+        self.channel.send_with(&packet, self.buffer, encode)
+    }
+
+    /// Finishes the call with `response` and `status`.  The response of a
+    /// server streaming call is normally empty.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The call is already finished.
+    ///
+    /// Any error sending the response is also returned.  The call is
+    /// finished either way.
+    pub fn finish(&mut self, response: &[u8], status: Result<()>) -> Result<()> {
+        self.close()?;
+        let packet = self
+            .call
+            .packet(PacketType::Response)
+            .with_payload(response)
+            .with_status(status);
+        self.channel.send(&packet, self.buffer)
+    }
+
+    /// Finishes the call like [`Responder::finish()`], with a response whose
+    /// fields are written by `encode`.
+    pub fn finish_with(
+        &mut self,
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+        status: Result<()>,
+    ) -> Result<()> {
+        self.close()?;
+        let packet = self.call.packet(PacketType::Response).with_status(status);
+        self.channel.send_with(&packet, self.buffer, encode)
+    }
+
+    /// Keeps the call open after [`Service::invoke()`] returns, so that the
+    /// service can send stream messages and finish the call later with
+    /// [`Server::write()`] and [`Server::finish()`].
+    ///
+    /// Returns the call to respond to.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The call is already finished.
+    /// - [`Error::ResourceExhausted`] - The server's deferred calls are
+    ///   exhausted.
+    pub fn defer(&mut self) -> Result<Call> {
+        match self.state {
+            ResponderState::Finished => Err(Error::FailedPrecondition),
+            ResponderState::Deferred => Ok(self.call),
+            ResponderState::Open => {
+                let slot = self.slot.as_deref_mut().ok_or(Error::ResourceExhausted)?;
+                *slot = Some(ActiveCall {
+                    call: self.call,
+                    method_type: self.method_type,
+                });
+                self.state = ResponderState::Deferred;
+                Ok(self.call)
+            }
+        }
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.state == ResponderState::Finished || !self.method_type.has_server_stream() {
+            return Err(Error::FailedPrecondition);
+        }
+        Ok(())
+    }
+
+    // Marks the call finished and frees its slot if it was deferred.
+    fn close(&mut self) -> Result<()> {
+        if self.state == ResponderState::Finished {
+            return Err(Error::FailedPrecondition);
+        }
+        if self.state == ResponderState::Deferred {
+            if let Some(slot) = self.slot.as_deref_mut() {
+                *slot = None;
+            }
+        }
+        self.state = ResponderState::Finished;
+        Ok(())
+    }
+}
+
+/// The server side of the `pw_rpc` protocol, compatible with C++, Python,
+/// and TypeScript clients.
+///
+/// The server dispatches requests received on its channels to a fixed set of
+/// services, without allocating.  Up to `MAX_CALLS` calls may be deferred to
+/// respond to after [`Service::invoke()`] returns.
+///
+/// ```
+/// use pw_rpc::{id, Channel, Packet, PacketType, Responder, Server, Service, MethodType};
+/// use pw_status::Result;
+///
+/// struct EchoService;
+///
+/// impl Service for EchoService {
+///     fn id(&self) -> u32 {
+///         id("pw.rpc.EchoService")
+///     }
+///
+///     fn method_type(&self, method_id: u32) -> Option<MethodType> {
+///         (method_id == id("Echo")).then_some(MethodType::Unary)
+///     }
+///
+///     fn invoke(&mut self, request: &[u8], responder: &mut Responder) {
+///         let _ = responder.finish(request, Ok(()));
+///     }
+/// }
+///
+/// let mut responses = 0;
+/// let mut output = |packet: &[u8]| -> Result<()> {
+///     let packet = Packet::decode(packet)?;
+///     assert_eq!(packet.payload, b"\x0a\x02hi");
+///     responses += 1;
+///     Ok(())
+/// };
+/// let mut channels = [Channel::new(1, &mut output)];
+/// let mut echo = EchoService;
+/// let mut services: [&mut dyn Service; 1] = [&mut echo];
+/// let mut buffer = [0u8; 64];
+/// let mut server = Server::<0>::new(&mut channels, &mut services, &mut buffer);
+///
+/// let mut request = [0u8; 64];
+/// let len = Packet::new(PacketType::Request, 1, id("pw.rpc.EchoService"), id("Echo"), 1)
+///     .with_payload(b"\x0a\x02hi")
+///     .encode(&mut request)
+///     .unwrap();
+/// server.process_packet(&request[..len]).unwrap();
+/// assert_eq!(responses, 1);
+/// ```
+pub struct Server<'a, const MAX_CALLS: usize> {
+    channels: &'a mut [Channel<'a>],
+    services: &'a mut [&'a mut dyn Service],
+    encoding_buffer: &'a mut [u8],
+    calls: [Option<ActiveCall>; MAX_CALLS],
+}
+
+impl<'a, const MAX_CALLS: usize> Server<'a, MAX_CALLS> {
+    /// Creates a server for `services` which communicates over `channels`.
+    ///
+    /// Packets are encoded in `encoding_buffer`, which limits the size of
+    /// responses.
+    pub fn new(
+        channels: &'a mut [Channel<'a>],
+        services: &'a mut [&'a mut dyn Service],
+        encoding_buffer: &'a mut [u8],
+    ) -> Self {
+        Self {
+            channels,
+            services,
+            encoding_buffer,
+            calls: [None; MAX_CALLS],
+        }
+    }
+
+    /// Processes a packet received from a client.
+    ///
+    /// Requests are dispatched to their service.  Requests for unknown
+    /// services or methods and packets for calls which are not active are
+    /// answered with a [`PacketType::ServerError`] packet.  Errors sending
+    /// responses are not returned.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The packet could not be decoded.
+    /// - [`Error::InvalidArgument`] - The packet is addressed to a client.
+    /// - [`Error::NotFound`] - The packet is for a channel the server does
+    ///   not have.
+    pub fn process_packet(&mut self, data: &[u8]) -> Result<()> {
+        let packet = Packet::decode(data)?;
+        if !packet.packet_type.is_to_server() {
+            return Err(Error::InvalidArgument);
+        }
+        let channel = channel::find(self.channels, packet.channel_id).ok_or(Error::NotFound)?;
+        let call = Call::of(&packet);
+        let active = self
+            .calls
+            .iter_mut()
+            .find(|active| active.is_some_and(|active| active.call == call));
+        let service = self
+            .services
+            .iter_mut()
+            .find(|service| service.id() == packet.service_id);
+
+        match packet.packet_type {
+            PacketType::Request => {
+                // A repeated request replaces the call.
+                if let Some(active) = active {
+                    *active = None;
+                }
+                let Some((service, method_type)) = service.and_then(|service| {
+                    let method_type = service.method_type(packet.method_id)?;
+                    Some((service, method_type))
+                }) else {
+                    return send_error(channel, self.encoding_buffer, &call, Error::NotFound);
+                };
+
+                let mut responder = Responder {
+                    call,
+                    method_type,
+                    channel,
+                    buffer: self.encoding_buffer,
+                    slot: self.calls.iter_mut().find(|slot| slot.is_none()),
+                    state: ResponderState::Open,
+                };
+                service.invoke(packet.payload, &mut responder);
+                if responder.state == ResponderState::Open {
+                    let _ = responder.finish(&[], Err(Error::Internal));
+                }
+            }
+            PacketType::ClientError => {
+                if let Some(active) = active {
+                    *active = None;
+                    if let Some(service) = service {
+                        service.cancel(&call);
+                    }
+                }
+            }
+            _ => {
+                let (Some(slot), Some(service)) = (active, service) else {
+                    return send_error(
+                        channel,
+                        self.encoding_buffer,
+                        &call,
+                        Error::FailedPrecondition,
+                    );
+                };
+                // The messages of calls without a client stream are ignored.
+                let Some(active) = slot.filter(|active| active.method_type.has_client_stream())
+                else {
+                    return Ok(());
+                };
+                let mut responder = Responder {
+                    call,
+                    method_type: active.method_type,
+                    channel,
+                    buffer: self.encoding_buffer,
+                    slot: Some(slot),
+                    state: ResponderState::Deferred,
+                };
+                if packet.packet_type == PacketType::ClientStream {
+                    service.client_stream(packet.payload, &mut responder);
+                } else {
+                    service.client_stream_end(&mut responder);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a stream message for a deferred server or bidirectional
+    /// streaming `call`.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - `call` is not active or the server
+    ///   does not stream its responses.
+    ///
+    /// Any error sending the message is also returned.
+    pub fn write(&mut self, call: &Call, payload: &[u8]) -> Result<()> {
+        self.check_writable(call)?;
+        self.send(&call.packet(PacketType::ServerStream).with_payload(payload))
+    }
+
+    /// Sends a stream message like [`Server::write()`], whose fields are
+    /// written by `encode`, such as a generated message's `encode_fields()`.
+    pub fn write_with(
+        &mut self,
+        call: &Call,
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+    ) -> Result<()> {
+        self.check_writable(call)?;
+        self.send_with(&call.packet(PacketType::ServerStream), encode)
+    }
+
+    /// Finishes a deferred `call` with `response` and `status`.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - `call` is not active.
+    ///
+    /// Any error sending the response is also returned.  The call is
+    /// finished either way.
+    pub fn finish(&mut self, call: &Call, response: &[u8], status: Result<()>) -> Result<()> {
+        self.close(call)?;
+        let packet = call
+            .packet(PacketType::Response)
+            .with_payload(response)
+            .with_status(status);
+        self.send(&packet)
+    }
+
+    /// Finishes a deferred `call` like [`Server::finish()`], with a response
+    /// whose fields are written by `encode`.
+    pub fn finish_with(
+        &mut self,
+        call: &Call,
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+        status: Result<()>,
+    ) -> Result<()> {
+        self.close(call)?;
+        let packet = call.packet(PacketType::Response).with_status(status);
+        self.send_with(&packet, encode)
+    }
+
+    /// Returns true if `call` is deferred and not finished.
+    pub fn is_active(&self, call: &Call) -> bool {
+        self.find(call).is_some()
+    }
+
+    /// Returns the number of deferred calls which are not finished.
+    pub fn active_calls(&self) -> usize {
+        self.calls.iter().flatten().count()
+    }
+
+    fn find(&self, call: &Call) -> Option<usize> {
+        self.calls
+            .iter()
+            .position(|active| active.is_some_and(|active| active.call == *call))
+    }
+
+    fn check_writable(&self, call: &Call) -> Result<()> {
+        let slot = self.find(call).ok_or(Error::FailedPrecondition)?;
+        if !self.calls[slot].is_some_and(|active| active.method_type.has_server_stream()) {
+            return Err(Error::FailedPrecondition);
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, call: &Call) -> Result<()> {
+        let slot = self.find(call).ok_or(Error::FailedPrecondition)?;
+        self.calls[slot] = None;
+        Ok(())
+    }
+
+    fn send(&mut self, packet: &Packet) -> Result<()> {
+        channel::find(self.channels, packet.channel_id)
+            .ok_or(Error::NotFound)?
+            .send(packet, self.encoding_buffer)
+    }
+
+    fn send_with(
+        &mut self,
+        packet: &Packet,
+        encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>,
+    ) -> Result<()> {
+        channel::find(self.channels, packet.channel_id)
+            .ok_or(Error::NotFound)?
+            .send_with(packet, self.encoding_buffer, encode)
+    }
+}
+
+// Sends a server error for `call`.  Errors sending the packet are ignored.
+fn send_error(channel: &mut Channel, buffer: &mut [u8], call: &Call, error: Error) -> Result<()> {
+    let packet = call.packet(PacketType::ServerError).with_status(Err(error));
+    let _ = channel.send(&packet, buffer);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::Cell;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::id;
+
+    const SERVICE_ID: u32 = id("pw.rpc.test.TestService");
+    const UNARY_ID: u32 = id("Unary");
+    const STREAM_ID: u32 = id("ServerStream");
+    const DEFERRED_ID: u32 = id("DeferredStream");
+    const CLIENT_STREAM_ID: u32 = id("ClientStream");
+    const BIDI_STREAM_ID: u32 = id("BidiStream");
+    const ENCODED_STREAM_ID: u32 = id("EncodedStream");
+
+    #[derive(Default)]
+    struct TestService<'s> {
+        deferred: Option<&'s Cell<Option<Call>>>,
+        cancelled: Option<&'s Cell<Option<Call>>>,
+        client_messages: u8,
+    }
+
+    impl Service for TestService<'_> {
+        fn id(&self) -> u32 {
+            SERVICE_ID
+        }
+
+        fn method_type(&self, method_id: u32) -> Option<MethodType> {
+            match method_id {
+                UNARY_ID => Some(MethodType::Unary),
+                STREAM_ID | DEFERRED_ID | ENCODED_STREAM_ID => Some(MethodType::ServerStreaming),
+                CLIENT_STREAM_ID => Some(MethodType::ClientStreaming),
+                BIDI_STREAM_ID => Some(MethodType::BidirectionalStreaming),
+                _ => None,
+            }
+        }
+
+        fn invoke(&mut self, request: &[u8], responder: &mut Responder) {
+            match responder.call().method_id() {
+                UNARY_ID => {
+                    assert!(responder.write(b"x") == Err(Error::FailedPrecondition));
+                    // An empty request is left unanswered.
+                    if !request.is_empty() {
+                        responder.finish(request, Ok(())).unwrap();
+                        assert!(responder.defer() == Err(Error::FailedPrecondition));
+                    }
+                }
+                STREAM_ID => {
+                    for message in request.chunks(1) {
+                        responder.write(message).unwrap();
+                    }
+                    responder.finish(&[], Ok(())).unwrap();
+                }
+                ENCODED_STREAM_ID => {
+                    responder
+                        .write_with(|encoder| encoder.write_bytes(1, request))
+                        .unwrap();
+                    responder
+                        .finish_with(|encoder| encoder.write_uint32(2, 1), Ok(()))
+                        .unwrap();
+                }
+                _ => match responder.defer() {
+                    Ok(call) => self.deferred.unwrap().set(Some(call)),
+                    Err(error) => responder.finish(&[], Err(error)).unwrap(),
+                },
+            }
+        }
+
+        // Counts client stream messages and echoes those of bidirectional
+        // streams.
+        fn client_stream(&mut self, request: &[u8], responder: &mut Responder) {
+            self.client_messages += 1;
+            if responder.method_type() == MethodType::BidirectionalStreaming {
+                responder.write(request).unwrap();
+            } else {
+                assert!(responder.write(request) == Err(Error::FailedPrecondition));
+            }
+        }
+
+        // Responds with the number of client stream messages.
+        fn client_stream_end(&mut self, responder: &mut Responder) {
+            responder.finish(&[self.client_messages], Ok(())).unwrap();
+        }
+
+        fn cancel(&mut self, call: &Call) {
+            self.cancelled.unwrap().set(Some(*call));
+        }
+    }
+
+    fn request(
+        buffer: &mut [u8],
+        packet_type: PacketType,
+        method_id: u32,
+        payload: &[u8],
+    ) -> usize {
+        Packet::new(packet_type, 1, SERVICE_ID, method_id, 5)
+            .with_payload(payload)
+            .encode(buffer)
+            .unwrap()
+    }
+
+    // Checks the type, payload, and status of each sent packet.
+    fn assert_responses(sent: &[Vec<u8>], expected: &[(PacketType, &[u8], Result<()>)]) {
+        assert_eq!(sent.len(), expected.len());
+        for (data, (packet_type, payload, status)) in sent.iter().zip(expected) {
+            let packet = Packet::decode(data).unwrap();
+            assert_eq!(packet.call_id, 5);
+            assert_eq!(packet.packet_type, *packet_type);
+            assert_eq!(packet.payload, *payload);
+            assert!(packet.status == *status);
+        }
+    }
+
+    #[test]
+    fn unary_and_server_streaming_requests_are_dispatched() {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            sent.push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut service = TestService::default();
+        let mut services: [&mut dyn Service; 1] = [&mut service];
+        let mut buffer = [0u8; 64];
+        let mut server = Server::<1>::new(&mut channels, &mut services, &mut buffer);
+
+        let mut packet = [0u8; 64];
+        let len = request(&mut packet, PacketType::Request, UNARY_ID, b"hi");
+        server.process_packet(&packet[..len]).unwrap();
+        let len = request(&mut packet, PacketType::Request, STREAM_ID, b"ab");
+        server.process_packet(&packet[..len]).unwrap();
+        let len = request(&mut packet, PacketType::Request, UNARY_ID, b"");
+        server.process_packet(&packet[..len]).unwrap();
+        let len = request(&mut packet, PacketType::Request, ENCODED_STREAM_ID, b"c");
+        server.process_packet(&packet[..len]).unwrap();
+        assert_eq!(server.active_calls(), 0);
+
+        assert_responses(
+            &sent,
+            &[
+                (PacketType::Response, b"hi", Ok(())),
+                (PacketType::ServerStream, b"a", Ok(())),
+                (PacketType::ServerStream, b"b", Ok(())),
+                (PacketType::Response, b"", Ok(())),
+                (PacketType::Response, b"", Err(Error::Internal)),
+                (PacketType::ServerStream, b"\x0a\x01c", Ok(())),
+                (PacketType::Response, b"\x10\x01", Ok(())),
+            ],
+        );
+    }
+
+    #[test]
+    fn deferred_calls_are_finished_later() {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            sent.push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let deferred = Cell::new(None);
+        let mut service = TestService {
+            deferred: Some(&deferred),
+            ..Default::default()
+        };
+        let mut services: [&mut dyn Service; 1] = [&mut service];
+        let mut buffer = [0u8; 64];
+        let mut server = Server::<1>::new(&mut channels, &mut services, &mut buffer);
+
+        let mut packet = [0u8; 64];
+        let len = request(&mut packet, PacketType::Request, DEFERRED_ID, b"");
+        server.process_packet(&packet[..len]).unwrap();
+        let call = deferred.get().unwrap();
+        assert!(server.is_active(&call));
+
+        server.write(&call, b"one").unwrap();
+        server
+            .write_with(&call, |encoder| encoder.write_uint32(1, 2))
+            .unwrap();
+        // Client stream packets for active calls are ignored.
+        let len = request(&mut packet, PacketType::ClientStream, DEFERRED_ID, b"?");
+        server.process_packet(&packet[..len]).unwrap();
+        server.finish(&call, &[], Err(Error::Aborted)).unwrap();
+        assert!(server.write(&call, b"late") == Err(Error::FailedPrecondition));
+        assert!(server.finish_with(&call, |_| Ok(()), Ok(())) == Err(Error::FailedPrecondition));
+        assert_eq!(server.active_calls(), 0);
+
+        assert_responses(
+            &sent,
+            &[
+                (PacketType::ServerStream, b"one", Ok(())),
+                (PacketType::ServerStream, b"\x08\x02", Ok(())),
+                (PacketType::Response, b"", Err(Error::Aborted)),
+            ],
+        );
+    }
+
+    #[test]
+    fn deferring_fails_without_free_calls() {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            sent.push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut service = TestService::default();
+        let mut services: [&mut dyn Service; 1] = [&mut service];
+        let mut buffer = [0u8; 64];
+        let mut server = Server::<0>::new(&mut channels, &mut services, &mut buffer);
+
+        let mut packet = [0u8; 64];
+        let len = request(&mut packet, PacketType::Request, DEFERRED_ID, b"");
+        server.process_packet(&packet[..len]).unwrap();
+
+        assert_responses(
+            &sent,
+            &[(PacketType::Response, b"", Err(Error::ResourceExhausted))],
+        );
+    }
+
+    #[test]
+    fn client_error_cancels_deferred_call() {
+        let mut output = |_: &[u8]| -> Result<()> { Ok(()) };
+        let mut channels = [Channel::new(1, &mut output)];
+        let deferred = Cell::new(None);
+        let cancelled = Cell::new(None);
+        let mut service = TestService {
+            deferred: Some(&deferred),
+            cancelled: Some(&cancelled),
+            ..Default::default()
+        };
+        let mut services: [&mut dyn Service; 1] = [&mut service];
+        let mut buffer = [0u8; 64];
+        let mut server = Server::<1>::new(&mut channels, &mut services, &mut buffer);
+
+        let mut packet = [0u8; 64];
+        let len = request(&mut packet, PacketType::Request, DEFERRED_ID, b"");
+        server.process_packet(&packet[..len]).unwrap();
+        assert_eq!(server.active_calls(), 1);
+        let len = request(&mut packet, PacketType::ClientError, DEFERRED_ID, b"");
+        server.process_packet(&packet[..len]).unwrap();
+
+        assert_eq!(server.active_calls(), 0);
+        assert!(cancelled.get().is_some());
+        assert_eq!(cancelled.get(), deferred.get());
+    }
+
+    #[test]
+    fn invalid_requests_are_answered_with_errors() {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            sent.push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut service = TestService::default();
+        let mut services: [&mut dyn Service; 1] = [&mut service];
+        let mut buffer = [0u8; 64];
+        let mut server = Server::<0>::new(&mut channels, &mut services, &mut buffer);
+
+        let mut packet = [0u8; 64];
+        for (packet_type, method_id) in [
+            (PacketType::Request, id("Missing")),
+            (PacketType::ClientStream, UNARY_ID),
+        ] {
+            let len = request(&mut packet, packet_type, method_id, b"");
+            server.process_packet(&packet[..len]).unwrap();
+        }
+        let len = Packet::new(PacketType::Request, 1, id("Missing"), UNARY_ID, 5)
+            .encode(&mut packet)
+            .unwrap();
+        server.process_packet(&packet[..len]).unwrap();
+
+        let len = request(&mut packet, PacketType::Response, UNARY_ID, b"");
+        assert!(server.process_packet(&packet[..len]) == Err(Error::InvalidArgument));
+        let len = Packet::new(PacketType::Request, 2, SERVICE_ID, UNARY_ID, 5)
+            .encode(&mut packet)
+            .unwrap();
+        assert!(server.process_packet(&packet[..len]) == Err(Error::NotFound));
+        assert!(server.process_packet(&[0x12]) == Err(Error::DataLoss));
+
+        assert_responses(
+            &sent,
+            &[
+                (PacketType::ServerError, b"", Err(Error::NotFound)),
+                (PacketType::ServerError, b"", Err(Error::FailedPrecondition)),
+                (PacketType::ServerError, b"", Err(Error::NotFound)),
+            ],
+        );
+    }
+
+    #[test]
+    fn client_streams_are_delivered_to_deferred_calls() {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            sent.push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let deferred = Cell::new(None);
+        let mut service = TestService {
+            deferred: Some(&deferred),
+            ..Default::default()
+        };
+        let mut services: [&mut dyn Service; 1] = [&mut service];
+        let mut buffer = [0u8; 64];
+        let mut server = Server::<2>::new(&mut channels, &mut services, &mut buffer);
+
+        let mut packet = [0u8; 64];
+        for (packet_type, method_id, payload) in [
+            (PacketType::Request, CLIENT_STREAM_ID, &b""[..]),
+            (PacketType::Request, BIDI_STREAM_ID, b""),
+            (PacketType::ClientStream, CLIENT_STREAM_ID, b"a"),
+            (PacketType::ClientStream, BIDI_STREAM_ID, b"b"),
+            (PacketType::ClientRequestCompletion, CLIENT_STREAM_ID, b""),
+        ] {
+            let len = request(&mut packet, packet_type, method_id, payload);
+            server.process_packet(&packet[..len]).unwrap();
+        }
+        assert_eq!(server.active_calls(), 1);
+
+        let bidi = deferred.get().unwrap();
+        assert_eq!(bidi.method_id(), BIDI_STREAM_ID);
+        server.write(&bidi, b"c").unwrap();
+        server.finish(&bidi, &[], Ok(())).unwrap();
+
+        assert_responses(
+            &sent,
+            &[
+                (PacketType::ServerStream, b"b", Ok(())),
+                (PacketType::Response, &[2], Ok(())),
+                (PacketType::ServerStream, b"c", Ok(())),
+                (PacketType::Response, b"", Ok(())),
+            ],
+        );
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use crate::{id, MethodType, Responder, Service};
+
+/// The standard `pw.rpc.EchoService`, which responds to each `Echo` request
+/// with the request's message.
+///
+/// The service is compatible with the C++ `pw::rpc::EchoService` and the
+/// `pw_rpc` Python and C++ clients, which use it to check that a device's
+/// RPC server works.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EchoService;
+
+impl EchoService {
+    /// The ID of `pw.rpc.EchoService`.
+    pub const SERVICE_ID: u32 = id("pw.rpc.EchoService");
+    /// The ID of the `Echo` method.
+    pub const ECHO: u32 = id("Echo");
+}
+
+impl Service for EchoService {
+    fn id(&self) -> u32 {
+        Self::SERVICE_ID
+    }
+
+    fn method_type(&self, method_id: u32) -> Option<MethodType> {
+        (method_id == Self::ECHO).then_some(MethodType::Unary)
+    }
+
+    fn invoke(&mut self, request: &[u8], responder: &mut Responder<'_, '_>) {
+        // The `pw.rpc.EchoMessage` response is the request message.
+        let _ = responder.finish(request, Ok(()));
+    }
+}
+
+/// The `pw.rpc.Benchmark` service, which echoes payloads so clients can
+/// measure the latency and throughput of RPCs to a device.
+///
+/// `UnaryEcho` responds with its request.  `BidirectionalEcho` responds to
+/// each stream message with the message until the client finishes or
+/// cancels the call, so it needs a free deferred call in the server.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BenchmarkService;
+
+impl BenchmarkService {
+    /// The ID of `pw.rpc.Benchmark`.
+    pub const SERVICE_ID: u32 = id("pw.rpc.Benchmark");
+    /// The ID of the `UnaryEcho` method.
+    pub const UNARY_ECHO: u32 = id("UnaryEcho");
+    /// The ID of the `BidirectionalEcho` method.
+    pub const BIDIRECTIONAL_ECHO: u32 = id("BidirectionalEcho");
+}
+
+impl Service for BenchmarkService {
+    fn id(&self) -> u32 {
+        Self::SERVICE_ID
+    }
+
+    fn method_type(&self, method_id: u32) -> Option<MethodType> {
+        match method_id {
+            Self::UNARY_ECHO => Some(MethodType::Unary),
+            Self::BIDIRECTIONAL_ECHO => Some(MethodType::BidirectionalStreaming),
+            _ => None,
+        }
+    }
+
+    fn invoke(&mut self, request: &[u8], responder: &mut Responder<'_, '_>) {
+        if responder.method_type() == MethodType::Unary {
+            let _ = responder.finish(request, Ok(()));
+        } else if let Err(error) = responder.defer() {
+            let _ = responder.finish(&[], Err(error));
+        }
+    }
+
+    fn client_stream(&mut self, request: &[u8], responder: &mut Responder<'_, '_>) {
+        // Stream messages which can not be sent are dropped, as they would
+        // be if the transport lost them.
+        let _ = responder.write(request);
+    }
+
+    fn client_stream_end(&mut self, responder: &mut Responder<'_, '_>) {
+        let _ = responder.finish(&[], Ok(()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use pw_status::{Error, Result};
+
+    use super::*;
+    use crate::{Channel, Packet, PacketType, Server};
+
+    // Sends each packet to a server with the echo and benchmark services and
+    // returns the type and payload of each response.
+    fn exchange<const MAX_CALLS: usize>(
+        packets: &[(PacketType, u32, u32, &[u8])],
+    ) -> Vec<(PacketType, Vec<u8>, Result<()>)> {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            let packet = Packet::decode(packet)?;
+            sent.push((packet.packet_type, packet.payload.to_vec(), packet.status));
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut echo = EchoService;
+        let mut benchmark = BenchmarkService;
+        let mut services: [&mut dyn Service; 2] = [&mut echo, &mut benchmark];
+        let mut buffer = [0u8; 64];
+        let mut server = Server::<MAX_CALLS>::new(&mut channels, &mut services, &mut buffer);
+
+        let mut data = [0u8; 64];
+        for &(packet_type, service_id, method_id, payload) in packets {
+            let len = Packet::new(packet_type, 1, service_id, method_id, 1)
+                .with_payload(payload)
+                .encode(&mut data)
+                .unwrap();
+            server.process_packet(&data[..len]).unwrap();
+        }
+        sent
+    }
+
+    #[test]
+    fn echo_responds_with_request() {
+        let sent = exchange::<0>(&[(
+            PacketType::Request,
+            EchoService::SERVICE_ID,
+            EchoService::ECHO,
+            b"\x0a\x05hello",
+        )]);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].0, PacketType::Response);
+        assert_eq!(sent[0].1, b"\x0a\x05hello");
+        assert!(sent[0].2.is_ok());
+    }
+
+    #[test]
+    fn benchmark_echoes_unary_and_stream_payloads() {
+        const ID: u32 = BenchmarkService::SERVICE_ID;
+        let sent = exchange::<1>(&[
+            (
+                PacketType::Request,
+                ID,
+                BenchmarkService::UNARY_ECHO,
+                b"one",
+            ),
+            (
+                PacketType::Request,
+                ID,
+                BenchmarkService::BIDIRECTIONAL_ECHO,
+                b"",
+            ),
+            (
+                PacketType::ClientStream,
+                ID,
+                BenchmarkService::BIDIRECTIONAL_ECHO,
+                b"two",
+            ),
+            (
+                PacketType::ClientStream,
+                ID,
+                BenchmarkService::BIDIRECTIONAL_ECHO,
+                b"three",
+            ),
+            (
+                PacketType::ClientRequestCompletion,
+                ID,
+                BenchmarkService::BIDIRECTIONAL_ECHO,
+                b"",
+            ),
+        ]);
+        let expected: [(PacketType, &[u8]); 4] = [
+            (PacketType::Response, b"one"),
+            (PacketType::ServerStream, b"two"),
+            (PacketType::ServerStream, b"three"),
+            (PacketType::Response, b""),
+        ];
+        assert_eq!(sent.len(), expected.len());
+        for (sent, expected) in sent.iter().zip(expected) {
+            assert_eq!((sent.0, sent.1.as_slice()), expected);
+            assert!(sent.2.is_ok());
+        }
+    }
+
+    #[test]
+    fn benchmark_stream_fails_without_free_call() {
+        let sent = exchange::<0>(&[(
+            PacketType::Request,
+            BenchmarkService::SERVICE_ID,
+            BenchmarkService::BIDIRECTIONAL_ECHO,
+            b"",
+        )]);
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].2 == Err(Error::ResourceExhausted));
+    }
+}
@@ -0,0 +1,592 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Generates `pw_rpc` service traits and client stubs from `.proto` files in
+//! a build script.
+//!
+//! For each service, the generated code has a module named after the service
+//! containing:
+//!
+//! - `SERVICE_ID` and, in `methods`, the ID of each method.
+//! - A trait named after the service with a method for each RPC, and a
+//!   `Service` adapter which dispatches requests to it from a
+//!   [`pw_rpc::Server`].
+//! - For each RPC, a responder which sends its responses, such as
+//!   `EchoResponder`.
+//! - A `Client` which invokes each RPC with a [`pw_rpc::Client`].
+//!
+//! Requests and responses are the message structs which
+//! `pw_protobuf_codegen` generates from the same `.proto` file.  The adapter
+//! decodes requests before passing them to the trait, and finishes calls
+//! whose requests can not be decoded with the decoding error.  Responders
+//! and clients encode messages directly into the packets they send.
+//! Client-streaming and bidirectional streaming calls start without a
+//! request, and their stream messages are passed to the trait's `_stream`
+//! methods.  Responses sent to deferred calls through the server are
+//! encoded with `Server::write_with()` and `Server::finish_with()`.
+//!
+//! Call [`compile_protos()`] and `pw_protobuf_codegen::compile_protos()`
+//! from `build.rs`:
+//!
+//! ```no_run
+//! pw_rpc_codegen::compile_protos(&["protos/echo.proto"]).unwrap();
+//! ```
+//!
+//! Then include the generated code, which is named after the `.proto` file,
+//! in the same module as its messages, and implement the services:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/echo.pb.rs"));
+//! include!(concat!(env!("OUT_DIR"), "/echo.rpc.rs"));
+//!
+//! struct Echo;
+//!
+//! impl echo_service::EchoService for Echo {
+//!     fn echo(&mut self, request: EchoMessage, responder: &mut echo_service::EchoResponder) {
+//!         let _ = responder.finish(&request, Ok(()));
+//!     }
+//! }
+//!
+//! let mut echo = echo_service::Service(Echo);
+//! let mut services: [&mut dyn pw_rpc::Service; 1] = [&mut echo];
+//! ```
+//!
+//! [`pw_rpc::Server`]: ../pw_rpc/struct.Server.html
+//! [`pw_rpc::Client`]: ../pw_rpc/struct.Client.html
+#![deny(missing_docs)]
+
+use std::fmt::{self, Write};
+use std::path::Path;
+
+use pw_protobuf_codegen::{identifier, message_path, snake_case};
+
+pub use pw_protobuf_codegen::{parse, Error, Method, ProtoFile, Service};
+
+fn method_type(method: &Method) -> &'static str {
+    match (method.client_streaming, method.server_streaming) {
+        (false, false) => "Unary",
+        (false, true) => "ServerStreaming",
+        (true, false) => "ClientStreaming",
+        (true, true) => "BidirectionalStreaming",
+    }
+}
+
+// The names generated for a method.
+struct MethodNames<'m> {
+    method: &'m Method,
+    // The constant of its ID.
+    constant: String,
+    // The function which handles or invokes it.
+    function: String,
+    // The functions which handle its client stream, and the client function
+    // which writes to it.
+    stream: String,
+    stream_end: String,
+    write: String,
+    // Its typed responder.
+    responder: String,
+    // The Rust paths of its request and response messages.
+    request: String,
+    response: String,
+}
+
+fn generate_service(code: &mut String, file: &ProtoFile, service: &Service) -> fmt::Result {
+    let full_name = file.full_name(service);
+    let module = identifier(snake_case(&service.name));
+    let methods: Vec<MethodNames> = service
+        .methods
+        .iter()
+        .map(|method| {
+            let snake = snake_case(&method.name);
+            MethodNames {
+                method,
+                constant: identifier(snake.to_uppercase()),
+                stream: identifier(format!("{snake}_stream")),
+                stream_end: identifier(format!("{snake}_stream_end")),
+                write: identifier(format!("write_{snake}")),
+                function: identifier(snake),
+                responder: format!("{}Responder", method.name),
+                request: format!("super::{}", message_path(file, &method.request_type)),
+                response: format!("super::{}", message_path(file, &method.response_type)),
+            }
+        })
+        .collect();
+    let client_streams = methods.iter().any(|m| m.method.client_streaming);
+    writeln!(code, "/// The `{full_name}` service.")?;
+    writeln!(code, "pub mod {module} {{")?;
+    // Services with few methods have trivial `match`es.
+    writeln!(
+        code,
+        "    #![allow(clippy::match_single_binding, clippy::single_match)]"
+    )?;
+    writeln!(code)?;
+    writeln!(code, "    /// The ID of the `{full_name}` service.")?;
+    writeln!(
+        code,
+        "    pub const SERVICE_ID: u32 = pw_rpc::id({full_name:?});"
+    )?;
+    writeln!(code)?;
+    writeln!(code, "    /// The IDs of the service's methods.")?;
+    writeln!(code, "    pub mod methods {{")?;
+    for m in &methods {
+        writeln!(code, "        /// The ID of `{}`.", m.method.name)?;
+        writeln!(
+            code,
+            "        pub const {}: u32 = pw_rpc::id({:?});",
+            m.constant, m.method.name
+        )?;
+    }
+    writeln!(code, "    }}")?;
+    writeln!(code)?;
+
+    writeln!(code, "    /// Implements the methods of `{full_name}`.")?;
+    writeln!(code, "    pub trait {} {{", service.name)?;
+    for m in &methods {
+        let (method, function, responder) = (m.method, &m.function, &m.responder);
+        let responses = if method.server_streaming {
+            "a stream of "
+        } else {
+            ""
+        };
+        if method.client_streaming {
+            writeln!(
+                code,
+                "        /// Starts a `{}` call, which must be deferred to receive the client's stream of `{}`, and responds with {responses}`{}`.",
+                method.name, method.request_type, method.response_type,
+            )?;
+            writeln!(
+                code,
+                "        fn {function}(&mut self, responder: &mut {responder}<'_, '_, '_>);"
+            )?;
+            writeln!(code)?;
+            writeln!(
+                code,
+                "        /// Handles a `{}` stream message from the client of a deferred `{}` call.",
+                method.request_type, method.name,
+            )?;
+            writeln!(code, "        fn {}(&mut self, _request: {}, _responder: &mut {responder}<'_, '_, '_>) {{}}", m.stream, m.request)?;
+            writeln!(code)?;
+            writeln!(
+                code,
+                "        /// Called when the client of a deferred `{}` call finishes its stream.",
+                method.name
+            )?;
+            writeln!(
+                code,
+                "        fn {}(&mut self, _responder: &mut {responder}<'_, '_, '_>) {{}}",
+                m.stream_end
+            )?;
+        } else {
+            writeln!(
+                code,
+                "        /// Handles a `{}` request and responds with {responses}`{}`.",
+                method.name, method.response_type,
+            )?;
+            writeln!(
+                code,
+                "        fn {function}(&mut self, request: {}, responder: &mut {responder}<'_, '_, '_>);",
+                m.request
+            )?;
+        }
+        writeln!(code)?;
+    }
+    writeln!(
+        code,
+        "        /// Called when the client cancels a deferred call."
+    )?;
+    writeln!(
+        code,
+        "        fn cancel(&mut self, _call: &pw_rpc::Call) {{}}"
+    )?;
+    writeln!(code, "    }}")?;
+
+    for m in &methods {
+        let (method, responder, response) = (m.method, &m.responder, &m.response);
+        writeln!(code)?;
+        writeln!(
+            code,
+            "    /// Sends the `{}` responses of a `{}` call.",
+            method.response_type, method.name
+        )?;
+        writeln!(
+            code,
+            "    pub struct {responder}<'r, 'p, 'a>(&'r mut pw_rpc::Responder<'p, 'a>);"
+        )?;
+        writeln!(code)?;
+        writeln!(code, "    impl {responder}<'_, '_, '_> {{")?;
+        writeln!(code, "        /// Returns the call being handled.")?;
+        writeln!(code, "        pub fn call(&self) -> pw_rpc::Call {{")?;
+        writeln!(code, "            self.0.call()")?;
+        writeln!(code, "        }}")?;
+        writeln!(code)?;
+        if method.server_streaming {
+            writeln!(code, "        /// Sends a stream message.")?;
+            writeln!(code, "        pub fn write(&mut self, response: &{response}) -> pw_status::Result<()> {{")?;
+            writeln!(
+                code,
+                "            self.0.write_with(|encoder| response.encode_fields(encoder))"
+            )?;
+            writeln!(code, "        }}")?;
+            writeln!(code)?;
+            writeln!(code, "        /// Finishes the call with `status`.")?;
+            writeln!(code, "        pub fn finish(&mut self, status: pw_status::Result<()>) -> pw_status::Result<()> {{")?;
+            writeln!(code, "            self.0.finish(&[], status)")?;
+        } else {
+            writeln!(
+                code,
+                "        /// Finishes the call with `response` and `status`."
+            )?;
+            writeln!(code, "        pub fn finish(&mut self, response: &{response}, status: pw_status::Result<()>) -> pw_status::Result<()> {{")?;
+            writeln!(
+                code,
+                "            self.0.finish_with(|encoder| response.encode_fields(encoder), status)"
+            )?;
+        }
+        writeln!(code, "        }}")?;
+        writeln!(code)?;
+        writeln!(
+            code,
+            "        /// Keeps the call open after the method returns, as `pw_rpc::Responder::defer()` does."
+        )?;
+        writeln!(
+            code,
+            "        pub fn defer(&mut self) -> pw_status::Result<pw_rpc::Call> {{"
+        )?;
+        writeln!(code, "            self.0.defer()")?;
+        writeln!(code, "        }}")?;
+        writeln!(code, "    }}")?;
+    }
+    writeln!(code)?;
+
+    writeln!(
+        code,
+        "    /// Dispatches requests from a `pw_rpc::Server` to a [`{}`].",
+        service.name
+    )?;
+    writeln!(
+        code,
+        "    ///\n    /// Requests which can not be decoded finish their call with the decoding error."
+    )?;
+    writeln!(code, "    pub struct Service<T>(pub T);")?;
+    writeln!(code)?;
+    writeln!(
+        code,
+        "    impl<T: {}> pw_rpc::Service for Service<T> {{",
+        service.name
+    )?;
+    writeln!(code, "        fn id(&self) -> u32 {{")?;
+    writeln!(code, "            SERVICE_ID")?;
+    writeln!(code, "        }}")?;
+    writeln!(code)?;
+    writeln!(
+        code,
+        "        fn method_type(&self, method_id: u32) -> Option<pw_rpc::MethodType> {{"
+    )?;
+    writeln!(code, "            match method_id {{")?;
+    for m in &methods {
+        writeln!(
+            code,
+            "                methods::{} => Some(pw_rpc::MethodType::{}),",
+            m.constant,
+            method_type(m.method)
+        )?;
+    }
+    writeln!(code, "                _ => None,")?;
+    writeln!(code, "            }}")?;
+    writeln!(code, "        }}")?;
+    writeln!(code)?;
+    let request = if methods.iter().all(|m| m.method.client_streaming) {
+        "_request"
+    } else {
+        "request"
+    };
+    writeln!(
+        code,
+        "        fn invoke(&mut self, {request}: &[u8], responder: &mut pw_rpc::Responder<'_, '_>) {{"
+    )?;
+    writeln!(code, "            match responder.call().method_id() {{")?;
+    for m in &methods {
+        let (constant, function, responder) = (&m.constant, &m.function, &m.responder);
+        if m.method.client_streaming {
+            writeln!(
+                code,
+                "                methods::{constant} => self.0.{function}(&mut {responder}(responder)),"
+            )?;
+        } else {
+            generate_decode(
+                code,
+                &format!("methods::{constant}"),
+                &m.request,
+                &format!("self.0.{function}(request, &mut {responder}(responder))"),
+            )?;
+        }
+    }
+    writeln!(code, "                _ => {{}}")?;
+    writeln!(code, "            }}")?;
+    writeln!(code, "        }}")?;
+    if client_streams {
+        writeln!(code)?;
+        writeln!(code, "        fn client_stream(&mut self, request: &[u8], responder: &mut pw_rpc::Responder<'_, '_>) {{")?;
+        writeln!(code, "            match responder.call().method_id() {{")?;
+        for m in methods.iter().filter(|m| m.method.client_streaming) {
+            let (constant, stream, responder) = (&m.constant, &m.stream, &m.responder);
+            generate_decode(
+                code,
+                &format!("methods::{constant}"),
+                &m.request,
+                &format!("self.0.{stream}(request, &mut {responder}(responder))"),
+            )?;
+        }
+        writeln!(code, "                _ => {{}}")?;
+        writeln!(code, "            }}")?;
+        writeln!(code, "        }}")?;
+        writeln!(code)?;
+        writeln!(
+            code,
+            "        fn client_stream_end(&mut self, responder: &mut pw_rpc::Responder<'_, '_>) {{"
+        )?;
+        writeln!(code, "            match responder.call().method_id() {{")?;
+        for m in methods.iter().filter(|m| m.method.client_streaming) {
+            writeln!(
+                code,
+                "                methods::{} => self.0.{}(&mut {}(responder)),",
+                m.constant, m.stream_end, m.responder
+            )?;
+        }
+        writeln!(code, "                _ => {{}}")?;
+        writeln!(code, "            }}")?;
+        writeln!(code, "        }}")?;
+    }
+    writeln!(code)?;
+    writeln!(code, "        fn cancel(&mut self, call: &pw_rpc::Call) {{")?;
+    writeln!(code, "            self.0.cancel(call)")?;
+    writeln!(code, "        }}")?;
+    writeln!(code, "    }}")?;
+    writeln!(code)?;
+
+    writeln!(
+        code,
+        "    /// Invokes the methods of `{full_name}` with a `pw_rpc::Client`."
+    )?;
+    writeln!(
+        code,
+        "    pub struct Client<'c, 'a, const MAX_CALLS: usize> {{"
+    )?;
+    writeln!(
+        code,
+        "        client: &'c mut pw_rpc::Client<'a, MAX_CALLS>,"
+    )?;
+    writeln!(code, "        channel_id: u32,")?;
+    writeln!(code, "    }}")?;
+    writeln!(code)?;
+    writeln!(
+        code,
+        "    impl<'c, 'a, const MAX_CALLS: usize> Client<'c, 'a, MAX_CALLS> {{"
+    )?;
+    writeln!(
+        code,
+        "        /// Creates a client which calls the service on channel `channel_id`."
+    )?;
+    writeln!(code, "        pub fn new(client: &'c mut pw_rpc::Client<'a, MAX_CALLS>, channel_id: u32) -> Self {{")?;
+    writeln!(code, "            Self {{ client, channel_id }}")?;
+    writeln!(code, "        }}")?;
+    for m in &methods {
+        let (method, constant, function) = (m.method, &m.constant, &m.function);
+        writeln!(code)?;
+        if method.client_streaming {
+            writeln!(
+                code,
+                "        /// Starts a `{}` call.  Send each request with `{}()`.",
+                method.name, m.write
+            )?;
+            writeln!(
+                code,
+                "        pub fn {function}(&mut self) -> pw_status::Result<pw_rpc::Call> {{"
+            )?;
+            writeln!(code, "            self.client.invoke(self.channel_id, SERVICE_ID, methods::{constant}, &[])")?;
+            writeln!(code, "        }}")?;
+            writeln!(code)?;
+            writeln!(
+                code,
+                "        /// Sends a `{}` stream message for a `{}` call.",
+                method.request_type, method.name
+            )?;
+            writeln!(code, "        pub fn {}(&mut self, call: &pw_rpc::Call, request: &{}) -> pw_status::Result<()> {{", m.write, m.request)?;
+            writeln!(
+                code,
+                "            self.client.write_with(call, |encoder| request.encode_fields(encoder))"
+            )?;
+        } else {
+            writeln!(
+                code,
+                "        /// Calls `{}` with `request`.  Decode the responses with `{}::decode()`.",
+                method.name, method.response_type
+            )?;
+            writeln!(code, "        pub fn {function}(&mut self, request: &{}) -> pw_status::Result<pw_rpc::Call> {{", m.request)?;
+            writeln!(code, "            self.client.invoke_with(self.channel_id, SERVICE_ID, methods::{constant}, |encoder| request.encode_fields(encoder))")?;
+        }
+        writeln!(code, "        }}")?;
+    }
+    writeln!(code, "    }}")?;
+    writeln!(code, "}}")
+}
+
+// Generates a `match` arm which decodes a request of type `request` and
+// passes it to `handle`, or finishes the call with the decoding error.
+fn generate_decode(code: &mut String, pattern: &str, request: &str, handle: &str) -> fmt::Result {
+    writeln!(
+        code,
+        "                {pattern} => match {request}::decode(request) {{"
+    )?;
+    writeln!(code, "                    Ok(request) => {handle},")?;
+    writeln!(code, "                    Err(error) => {{")?;
+    writeln!(
+        code,
+        "                        let _ = responder.finish(&[], Err(error));"
+    )?;
+    writeln!(code, "                    }}")?;
+    writeln!(code, "                }},")
+}
+
+/// Generates the code for the services of a `.proto` file.
+pub fn generate(file: &ProtoFile) -> String {
+    let mut code = String::new();
+    for service in &file.services {
+        if !code.is_empty() {
+            code.push('\n');
+        }
+        // Writing to a `String` can not fail.
+        let _ = generate_service(&mut code, file, service);
+    }
+    code
+}
+
+/// Generates code for each `.proto` file in `protos` into `out_dir`.
+///
+/// The code for `echo.proto` is written to `echo.rpc.rs`.
+pub fn compile_protos_to(protos: &[impl AsRef<Path>], out_dir: &Path) -> Result<(), Error> {
+    for proto in protos {
+        let proto = proto.as_ref();
+        let source =
+            std::fs::read_to_string(proto).map_err(|e| Error::Io(proto.to_path_buf(), e))?;
+        let file = parse(&source).map_err(|error| match error {
+            Error::Parse { line, message } => Error::Parse {
+                line,
+                message: format!("{}: {message}", proto.display()),
+            },
+            error => error,
+        })?;
+
+        let stem = proto.file_stem().unwrap_or_default().to_string_lossy();
+        let output = out_dir.join(format!("{stem}.rpc.rs"));
+        let code = format!(
+            "// Generated by pw_rpc_codegen from {}. Do not edit.\n\n{}",
+            proto.display(),
+            generate(&file)
+        );
+        std::fs::write(&output, code).map_err(|e| Error::Io(output, e))?;
+    }
+    Ok(())
+}
+
+/// Generates code for each `.proto` file in `protos` into Cargo's `OUT_DIR`
+/// from a build script, and reruns the build script when they change.
+pub fn compile_protos(protos: &[impl AsRef<Path>]) -> Result<(), Error> {
+    let out_dir = std::env::var_os("OUT_DIR").ok_or(Error::NoOutDir)?;
+    for proto in protos {
+        println!("cargo:rerun-if-changed={}", proto.as_ref().display());
+    }
+    compile_protos_to(protos, Path::new(&out_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PROTO: &str = r#"
+syntax = "proto3";
+
+package pw.rpc.test;
+
+/* A block comment with a service TestService { } */
+message TestRequest {
+  int64 integer = 1;
+  message Nested { string s = 1; }
+}
+
+service TestService {
+  option deprecated = false;
+  // The "stream" is a message type here.
+  rpc TestUnaryRpc(stream) returns (TestResponse);
+  rpc TestServerStreamRpc(TestRequest) returns (stream TestStreamResponse) {
+    option idempotency_level = NO_SIDE_EFFECTS;
+  }
+  rpc TestClientStreamRpc(stream .pw.rpc.test.TestRequest.Nested)
+      returns (TestStreamResponse);
+  rpc TestBidirectionalStreamRpc(stream TestRequest)
+      returns (stream TestStreamResponse);
+}
+"#;
+
+    #[test]
+    fn code_is_generated_for_each_method() {
+        let code = generate(&parse(TEST_PROTO).unwrap());
+        for expected in [
+            "pub mod test_service {",
+            "pub const SERVICE_ID: u32 = pw_rpc::id(\"pw.rpc.test.TestService\");",
+            "pub const TEST_BIDIRECTIONAL_STREAM_RPC: u32 = pw_rpc::id(\"TestBidirectionalStreamRpc\");",
+            "pub trait TestService {",
+            "fn test_unary_rpc(&mut self, request: super::stream, responder: &mut TestUnaryRpcResponder<'_, '_, '_>);",
+            "fn test_client_stream_rpc_stream(&mut self, _request: super::test_request::Nested, _responder: &mut TestClientStreamRpcResponder<'_, '_, '_>) {}",
+            "pub struct TestServerStreamRpcResponder<'r, 'p, 'a>(&'r mut pw_rpc::Responder<'p, 'a>);",
+            "pub fn write(&mut self, response: &super::TestStreamResponse) -> pw_status::Result<()> {",
+            "self.0.finish_with(|encoder| response.encode_fields(encoder), status)",
+            "methods::TEST_SERVER_STREAM_RPC => Some(pw_rpc::MethodType::ServerStreaming),",
+            "methods::TEST_CLIENT_STREAM_RPC => Some(pw_rpc::MethodType::ClientStreaming),",
+            "methods::TEST_SERVER_STREAM_RPC => match super::TestRequest::decode(request) {",
+            "Ok(request) => self.0.test_server_stream_rpc(request, &mut TestServerStreamRpcResponder(responder)),",
+            "let _ = responder.finish(&[], Err(error));",
+            "methods::TEST_CLIENT_STREAM_RPC => self.0.test_client_stream_rpc(&mut TestClientStreamRpcResponder(responder)),",
+            "methods::TEST_CLIENT_STREAM_RPC => match super::test_request::Nested::decode(request) {",
+            "pub fn test_client_stream_rpc(&mut self) -> pw_status::Result<pw_rpc::Call> {",
+            "pub fn write_test_client_stream_rpc(&mut self, call: &pw_rpc::Call, request: &super::test_request::Nested) -> pw_status::Result<()> {",
+            "pub fn test_unary_rpc(&mut self, request: &super::stream) -> pw_status::Result<pw_rpc::Call> {",
+        ] {
+            assert!(code.contains(expected), "{expected}\n{code}");
+        }
+    }
+
+    #[test]
+    fn protos_are_compiled_to_out_dir() {
+        let dir = std::env::temp_dir().join(format!("pw_rpc_codegen_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let proto = dir.join("echo.proto");
+        std::fs::write(
+            &proto,
+            "package pw.rpc;\nservice EchoService {\n  rpc Echo(EchoMessage) returns (EchoMessage) {}\n}\n",
+        )
+        .unwrap();
+
+        compile_protos_to(&[&proto], &dir).unwrap();
+        let code = std::fs::read_to_string(dir.join("echo.rpc.rs")).unwrap();
+        assert!(code.starts_with("// Generated by pw_rpc_codegen"));
+        assert!(code.contains("pub mod echo_service {"));
+        // Services without client streams use the default handlers.
+        assert!(!code.contains("fn client_stream("));
+
+        let error = compile_protos_to(&[dir.join("missing.proto")], &dir).unwrap_err();
+        assert!(matches!(error, Error::Io(..)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Connects `pw_rpc` to devices and simulators over TCP sockets, as
+//! `pw_console` and `pw_system` do.
+//!
+//! Packets are sent in HDLC frames to [`pw_hdlc::DEFAULT_RPC_ADDRESS`], so a
+//! Rust host test can take `pw_console`'s place on a `pw_system` socket, such
+//! as a simulated device listening on [`DEFAULT_PORT`] or a socket bridged to
+//! a serial port.  [`connect()`] returns an output for the channel and a
+//! reader for the packets the device sends:
+//!
+//! ```no_run
+//! use pw_rpc::{Channel, Client, ClientEvent};
+//! use pw_rpc_socket::DEFAULT_PORT;
+//!
+//! let (mut output, mut reader) = pw_rpc_socket::connect(("localhost", DEFAULT_PORT))?;
+//! let mut channels = [Channel::new(1, &mut output)];
+//! let mut buffer = [0u8; 1024];
+//! let mut client = Client::<4>::new(&mut channels, &mut buffer);
+//!
+//! client.invoke(1, pw_rpc::id("pw.rpc.EchoService"), pw_rpc::id("Echo"), b"\x0a\x02hi")?;
+//! let completed = reader.read_packet(|packet| {
+//!     matches!(
+//!         client.process_packet(packet),
+//!         Ok(Some(ClientEvent::Completed { .. }))
+//!     )
+//! })?;
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! *Note*: This module requires `std`.
+#![deny(missing_docs)]
+
+use std::net::{TcpStream, ToSocketAddrs};
+
+use pw_hdlc::{Decoder, Frame, RpcChannelOutput, DEFAULT_RPC_ADDRESS};
+use pw_status::{Error, Result};
+use pw_stream::{Read, TcpAdapter};
+
+/// The port `pw_system` simulators listen on and `pw_console` connects to
+/// by default.
+pub const DEFAULT_PORT: u16 = 33000;
+
+/// The size of the largest frame a [`SocketReader`] decodes.
+pub const MAX_FRAME_SIZE: usize = 4096;
+
+/// Sends RPC packets to the socket in HDLC frames.
+pub type SocketOutput = RpcChannelOutput<TcpAdapter>;
+
+/// Reads HDLC frames from the socket.
+pub struct SocketReader {
+    socket: TcpAdapter,
+    decoder: Decoder<Vec<u8>>,
+    rpc_address: u64,
+    received: Vec<u8>,
+    // The range of `received` which has not been decoded yet.
+    start: usize,
+    end: usize,
+}
+
+impl SocketReader {
+    /// Creates a reader for RPC packets sent to
+    /// [`pw_hdlc::DEFAULT_RPC_ADDRESS`] on `socket`.
+    pub fn new(socket: TcpStream) -> Self {
+        Self {
+            socket: TcpAdapter::new(socket),
+            decoder: Decoder::new(vec![0; MAX_FRAME_SIZE]),
+            rpc_address: DEFAULT_RPC_ADDRESS,
+            received: vec![0; MAX_FRAME_SIZE],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Sets the address of the frames [`SocketReader::read_packet()`]
+    /// returns.
+    pub fn set_rpc_address(&mut self, address: u64) {
+        self.rpc_address = address;
+    }
+
+    /// Returns the decoder, whose statistics count the malformed frames
+    /// received.
+    pub fn decoder(&self) -> &Decoder<Vec<u8>> {
+        &self.decoder
+    }
+
+    /// Reads from the socket until a valid frame is received and returns the
+    /// result of calling `f` with it.  Malformed frames are skipped.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - The socket was closed.
+    ///
+    /// Any error reading from the socket, such as
+    /// [`Error::DeadlineExceeded`] if its read timeout expired, is also
+    /// returned.
+    pub fn read_frame<R>(&mut self, mut f: impl FnMut(Frame) -> Option<R>) -> Result<R> {
+        loop {
+            let mut data = &self.received[self.start..self.end];
+            while !data.is_empty() {
+                let before = data.len();
+                let result = self.decoder.next_frame(&mut data);
+                self.start += before - data.len();
+                if let Some(Ok(frame)) = result {
+                    if let Some(value) = f(frame) {
+                        return Ok(value);
+                    }
+                }
+            }
+
+            self.start = 0;
+            self.end = self.socket.read(&mut self.received)?;
+            if self.end == 0 {
+                return Err(Error::OutOfRange);
+            }
+        }
+    }
+
+    /// Reads from the socket until an RPC packet is received and returns the
+    /// result of calling `f` with it.  Frames sent to other addresses, such
+    /// as logs, are skipped.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - The socket was closed.
+    ///
+    /// Any error reading from the socket is also returned.
+    pub fn read_packet<R>(&mut self, f: impl FnOnce(&[u8]) -> R) -> Result<R> {
+        let address = self.rpc_address;
+        let mut f = Some(f);
+        self.read_frame(|frame| {
+            if frame.address() == address {
+                f.take().map(|f| f(frame.payload()))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Splits a connected socket into an output which sends RPC packets to
+/// [`pw_hdlc::DEFAULT_RPC_ADDRESS`] and a reader for the packets received.
+///
+/// # Errors
+/// - [`Error::Unavailable`] - The socket could not be cloned.
+pub fn split(socket: TcpStream) -> Result<(SocketOutput, SocketReader)> {
+    let writer = socket.try_clone().map_err(|_| Error::Unavailable)?;
+    // Packets are small and sent one at a time.
+    let _ = socket.set_nodelay(true);
+    Ok((
+        RpcChannelOutput::new(TcpAdapter::new(writer), DEFAULT_RPC_ADDRESS),
+        SocketReader::new(socket),
+    ))
+}
+
+/// Connects to `address` and splits the socket as [`split()`] does.
+///
+/// # Errors
+/// - [`Error::Unavailable`] - The connection failed.
+pub fn connect(address: impl ToSocketAddrs) -> Result<(SocketOutput, SocketReader)> {
+    split(TcpStream::connect(address).map_err(|_| Error::Unavailable)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Shutdown, TcpListener};
+    use std::thread;
+
+    use pw_hdlc::{write_ui_frame, DEFAULT_LOG_ADDRESS};
+    use pw_rpc::{Channel, Client, ClientEvent, EchoService, Server, Service};
+
+    use super::*;
+
+    const ECHO_SERVICE: u32 = EchoService::SERVICE_ID;
+    const ECHO: u32 = EchoService::ECHO;
+
+    // Serves one connection with an echo service, sending a log frame
+    // before each response.
+    fn serve(listener: TcpListener) {
+        let (socket, _) = listener.accept().unwrap();
+        let (mut output, mut reader) = split(socket).unwrap();
+        let mut log = output
+            .get_ref()
+            .get_ref()
+            .try_clone()
+            .map(TcpAdapter::new)
+            .unwrap();
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut echo = EchoService;
+        let mut services: [&mut dyn Service; 1] = [&mut echo];
+        let mut buffer = [0u8; 256];
+        let mut server = Server::<0>::new(&mut channels, &mut services, &mut buffer);
+
+        while let Ok(result) = reader.read_packet(|packet| {
+            write_ui_frame(&mut log, DEFAULT_LOG_ADDRESS, b"log").unwrap();
+            server.process_packet(packet)
+        }) {
+            result.unwrap();
+        }
+    }
+
+    #[test]
+    fn client_calls_server_over_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || serve(listener));
+
+        let (mut output, mut reader) = connect(address).unwrap();
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut buffer = [0u8; 256];
+        let mut client = Client::<1>::new(&mut channels, &mut buffer);
+
+        for message in [&b"hello"[..], &[0x7e; 100]] {
+            let call = client.invoke(1, ECHO_SERVICE, ECHO, message).unwrap();
+            let response = reader
+                .read_packet(|packet| match client.process_packet(packet) {
+                    Ok(Some(ClientEvent::Completed {
+                        call: completed,
+                        payload,
+                        status,
+                    })) => {
+                        assert_eq!(completed, call);
+                        assert!(status.is_ok());
+                        payload.to_vec()
+                    }
+                    _ => panic!("unexpected packet"),
+                })
+                .unwrap();
+            assert_eq!(response, message);
+        }
+
+        output
+            .get_ref()
+            .get_ref()
+            .shutdown(Shutdown::Write)
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(reader.decoder().stats().malformed_frames(), 0);
+    }
+
+    #[test]
+    fn reading_closed_socket_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (_, mut reader) = connect(address).unwrap();
+        drop(listener.accept().unwrap());
+        assert!(reader.read_packet(|_| ()) == Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn connect_fails_without_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        assert!(matches!(connect(address), Err(Error::Unavailable)));
+    }
+}
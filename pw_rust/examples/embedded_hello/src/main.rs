@@ -18,15 +18,16 @@
 // Panic handler that halts the CPU on panic.
 use panic_halt as _;
 
-// Cortex M runtime entry macro.
-use cortex_m_rt::entry;
+// Cortex M boot support.
+use pw_boot_cortex_m::{entry, vector_table};
 
 // Semihosting support which is well supported for QEMU targets.
 use cortex_m_semihosting::{debug, hprintln};
 
-#[entry]
-fn main() -> ! {
+entry!(main);
+vector_table!();
+
+fn main() {
     hprintln!("Hello, Pigweed!");
     debug::exit(debug::EXIT_SUCCESS);
-    loop {}
 }
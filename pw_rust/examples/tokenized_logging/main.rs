@@ -24,16 +24,18 @@
 // Panic handler that halts the CPU on panic.
 use panic_halt as _;
 
-// Cortex M runtime entry macro.
-use cortex_m_rt::entry;
+// Cortex M boot support.
+use pw_boot_cortex_m::{entry, vector_table};
 
 // Semihosting support which is well supported for QEMU targets.
 use cortex_m_semihosting::{debug, hprintln};
 
 use pw_log::{pw_log_infof, pw_log_warnf};
 
-#[entry]
-fn main() -> ! {
+entry!(main);
+vector_table!();
+
+fn main() {
     // Plain text printout without `pw_log`
     hprintln!("Hello, Pigweed!");
 
@@ -42,5 +44,4 @@ fn main() -> ! {
     pw_log_warnf!("Integer value %d", 42);
     pw_log_infof!("generic arguments %v %v", 42u32, -42);
     debug::exit(debug::EXIT_SUCCESS);
-    loop {}
 }
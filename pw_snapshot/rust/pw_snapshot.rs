@@ -0,0 +1,716 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_snapshot` captures the state of a device, usually after a crash, as a
+//! `pw.snapshot.Snapshot` proto for post-mortem analysis.
+//!
+//! A [`SnapshotEncoder`] writes the fields of a snapshot into a buffer, such
+//! as one in persistent memory which is uploaded after reboot.  The encoded
+//! snapshot is processed by the standard host tooling, such as
+//! `pw_snapshot.processor`, which symbolizes backtraces, detokenizes logs, and
+//! decodes CPU state:
+//!
+//! ```
+//! use pw_snapshot::{Metadata, SnapshotEncoder, Thread, ThreadState};
+//!
+//! let mut buffer = [0u8; 256];
+//! let mut snapshot = SnapshotEncoder::new(&mut buffer);
+//! snapshot.metadata(&Metadata {
+//!     reason: b"Null-pointer dereference",
+//!     fatal: true,
+//!     project_name: b"propellerhat",
+//!     software_version: "propellerhat-release-193",
+//!     ..Metadata::new()
+//! })?;
+//! snapshot.tag("battery", "low")?;
+//! snapshot.thread(&Thread {
+//!     name: b"main",
+//!     state: ThreadState::Running,
+//!     stack_pointer: Some(0x2000_1f00),
+//!     ..Thread::new()
+//! })?;
+//! let snapshot = snapshot.into_slice();
+//! # assert!(!snapshot.is_empty());
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! Fields may be written in any order, and repeated fields may be written
+//! any number of times.  Projects may write their own fields to the buffer in
+//! the ranges the `Snapshot` proto reserves for users with
+//! [`SnapshotEncoder::encoder()`].
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_protobuf::{size_of_delimited_field, MemoryEncoder};
+use pw_ring_buffer::PrefixedEntryRingBuffer;
+use pw_status::Result;
+
+// Field numbers of `pw.snapshot.Snapshot`.
+const SNAPSHOT_LOGS: u32 = 1;
+const SNAPSHOT_METADATA: u32 = 16;
+const SNAPSHOT_TAGS: u32 = 17;
+const SNAPSHOT_THREADS: u32 = 18;
+const SNAPSHOT_RELATED_SNAPSHOTS: u32 = 19;
+const SNAPSHOT_ARMV7M_CPU_STATE: u32 = 20;
+const SNAPSHOT_TRACE_DATA: u32 = 21;
+const SNAPSHOT_TIMESTAMPS: u32 = 22;
+
+// Field numbers of a `map<string, string>` entry.
+const MAP_ENTRY_KEY: u32 = 1;
+const MAP_ENTRY_VALUE: u32 = 2;
+
+// Field numbers of `pw.snapshot.Metadata`.
+const METADATA_REASON: u32 = 1;
+const METADATA_FATAL: u32 = 2;
+const METADATA_PROJECT_NAME: u32 = 3;
+const METADATA_SOFTWARE_VERSION: u32 = 4;
+const METADATA_SOFTWARE_BUILD_UUID: u32 = 5;
+const METADATA_DEVICE_NAME: u32 = 6;
+const METADATA_SNAPSHOT_UUID: u32 = 7;
+const METADATA_CPU_ARCH: u32 = 8;
+
+// Field numbers of `pw.thread.proto.Thread`.
+const THREAD_NAME: u32 = 1;
+const THREAD_STATE: u32 = 3;
+const THREAD_RAW_BACKTRACE: u32 = 4;
+const THREAD_RAW_STACK: u32 = 6;
+const THREAD_STACK_START_POINTER: u32 = 7;
+const THREAD_STACK_END_POINTER: u32 = 8;
+const THREAD_STACK_POINTER: u32 = 9;
+const THREAD_CPU_USAGE_HUNDREDTHS: u32 = 10;
+const THREAD_STACK_POINTER_EST_PEAK: u32 = 11;
+
+// Field numbers of `pw.chrono.TimePoint`.
+const TIME_POINT_TIMESTAMP: u32 = 1;
+const TIME_POINT_CLOCK_PARAMETERS: u32 = 2;
+
+// Field numbers of `pw.chrono.ClockParameters`.
+const CLOCK_PARAMETERS_NUMERATOR: u32 = 1;
+const CLOCK_PARAMETERS_DENOMINATOR: u32 = 2;
+const CLOCK_PARAMETERS_EPOCH_TYPE: u32 = 3;
+
+/// The architecture of the CPU which captured a snapshot, as a
+/// `pw.snapshot.CpuArchitecture.Enum`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum CpuArchitecture {
+    /// The architecture is not known.
+    #[default]
+    Unknown = 0,
+    /// Armv6-M.
+    Armv6m = 1,
+    /// Armv7-M.
+    Armv7m = 2,
+    /// Armv8-M.
+    Armv8m = 3,
+    /// RV32E.
+    Rv32e = 4,
+    /// RV32I.
+    Rv32i = 5,
+    /// RV64E.
+    Rv64e = 6,
+    /// RV64I.
+    Rv64i = 7,
+    /// RV128I.
+    Rv128i = 8,
+}
+
+/// A `pw.snapshot.Metadata` which identifies a snapshot and what captured it.
+///
+/// Byte fields may be human readable text or tokenized.  Empty fields are
+/// omitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata<'a> {
+    /// Why the snapshot was captured, such as `"STACK_OVERFLOW"`.
+    pub reason: &'a [u8],
+    /// Whether the snapshot was captured because of a crash.
+    pub fatal: bool,
+    /// Name of the project which produced the snapshot.
+    pub project_name: &'a [u8],
+    /// Human readable version of the software, such as
+    /// `"codename-release-193"`.
+    pub software_version: &'a str,
+    /// UUID of the software build.
+    pub software_build_uuid: &'a [u8],
+    /// Name of the device, as specific as possible, such as
+    /// `"alarm-clock-dsp-p1"`.
+    pub device_name: &'a [u8],
+    /// 128-bit UUID of the snapshot, used for de-duplication.
+    pub snapshot_uuid: &'a [u8],
+    /// Architecture of the CPU which captured the snapshot.
+    pub cpu_arch: CpuArchitecture,
+}
+
+impl Metadata<'_> {
+    /// Creates metadata with all fields empty.
+    pub const fn new() -> Self {
+        Self {
+            reason: &[],
+            fatal: false,
+            project_name: &[],
+            software_version: "",
+            software_build_uuid: &[],
+            device_name: &[],
+            snapshot_uuid: &[],
+            cpu_arch: CpuArchitecture::Unknown,
+        }
+    }
+
+    fn encode(&self, encoder: &mut MemoryEncoder) -> Result<()> {
+        for (field, value) in [
+            (METADATA_REASON, self.reason),
+            (METADATA_PROJECT_NAME, self.project_name),
+            (METADATA_SOFTWARE_VERSION, self.software_version.as_bytes()),
+            (METADATA_SOFTWARE_BUILD_UUID, self.software_build_uuid),
+            (METADATA_DEVICE_NAME, self.device_name),
+            (METADATA_SNAPSHOT_UUID, self.snapshot_uuid),
+        ] {
+            if !value.is_empty() {
+                encoder.write_bytes(field, value)?;
+            }
+        }
+        if self.fatal {
+            encoder.write_bool(METADATA_FATAL, true)?;
+        }
+        if self.cpu_arch != CpuArchitecture::Unknown {
+            encoder.write_uint32(METADATA_CPU_ARCH, self.cpu_arch as u32)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Metadata<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The state of a thread, as a `pw.thread.proto.ThreadState.Enum`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ThreadState {
+    /// The state is not known.
+    #[default]
+    Unknown = 0,
+    /// The "thread" is an interrupt handler.
+    InterruptHandler = 1,
+    /// The thread was running when the snapshot was captured.
+    Running = 2,
+    /// The thread is ready to run.
+    Ready = 3,
+    /// The thread will not run until it is resumed.
+    Suspended = 4,
+    /// The thread is waiting for something before it can run.
+    Blocked = 5,
+    /// The thread was not started or has terminated.
+    Inactive = 6,
+}
+
+/// A `pw.thread.proto.Thread` describing a thread when a snapshot was
+/// captured.
+///
+/// Empty fields and fields which are `None` are omitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Thread<'a> {
+    /// The thread's unique name, which may be tokenized.
+    pub name: &'a [u8],
+    /// The thread's state.
+    pub state: ThreadState,
+    /// Return addresses of the thread's stack, most recent first.
+    pub raw_backtrace: &'a [u64],
+    /// Contents of all or part of the thread's stack.
+    pub raw_stack: &'a [u8],
+    /// The address the stack pointer started at.
+    pub stack_start_pointer: Option<u64>,
+    /// The furthest address the stack pointer may reach.
+    pub stack_end_pointer: Option<u64>,
+    /// The stack pointer when the snapshot was captured.
+    pub stack_pointer: Option<u64>,
+    /// The thread's CPU usage, in hundredths of a percent.
+    pub cpu_usage_hundredths: Option<u32>,
+    /// The furthest address the stack pointer is estimated to have reached.
+    pub stack_pointer_est_peak: Option<u64>,
+}
+
+impl Thread<'_> {
+    /// Creates a thread with all fields empty.
+    pub const fn new() -> Self {
+        Self {
+            name: &[],
+            state: ThreadState::Unknown,
+            raw_backtrace: &[],
+            raw_stack: &[],
+            stack_start_pointer: None,
+            stack_end_pointer: None,
+            stack_pointer: None,
+            cpu_usage_hundredths: None,
+            stack_pointer_est_peak: None,
+        }
+    }
+
+    fn encode(&self, encoder: &mut MemoryEncoder) -> Result<()> {
+        if !self.name.is_empty() {
+            encoder.write_bytes(THREAD_NAME, self.name)?;
+        }
+        if self.state != ThreadState::Unknown {
+            encoder.write_uint32(THREAD_STATE, self.state as u32)?;
+        }
+        for &address in self.raw_backtrace {
+            encoder.write_uint64(THREAD_RAW_BACKTRACE, address)?;
+        }
+        if !self.raw_stack.is_empty() {
+            encoder.write_bytes(THREAD_RAW_STACK, self.raw_stack)?;
+        }
+        for (field, value) in [
+            (THREAD_STACK_START_POINTER, self.stack_start_pointer),
+            (THREAD_STACK_END_POINTER, self.stack_end_pointer),
+            (THREAD_STACK_POINTER, self.stack_pointer),
+            (THREAD_STACK_POINTER_EST_PEAK, self.stack_pointer_est_peak),
+        ] {
+            if let Some(value) = value {
+                encoder.write_uint64(field, value)?;
+            }
+        }
+        if let Some(usage) = self.cpu_usage_hundredths {
+            encoder.write_uint32(THREAD_CPU_USAGE_HUNDREDTHS, usage)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Thread<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The registers of an Armv7-M or Armv8-M CPU, as a
+/// `pw.cpu_exception.cortex_m.ArmV7mCpuState`.
+///
+/// These are usually captured by the fault handler from the exception stack
+/// frame and the system control block.  Registers which are `None` were not
+/// captured and are omitted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArmV7mCpuState {
+    /// Program counter.
+    pub pc: Option<u32>,
+    /// Link register.
+    pub lr: Option<u32>,
+    /// Program status register.
+    pub psr: Option<u32>,
+    /// Main stack pointer.
+    pub msp: Option<u32>,
+    /// Process stack pointer.
+    pub psp: Option<u32>,
+    /// `EXC_RETURN` value of the exception.
+    pub exc_return: Option<u32>,
+    /// Configurable Fault Status Register.
+    pub cfsr: Option<u32>,
+    /// Main stack pointer limit (Armv8-M).
+    pub msplim: Option<u32>,
+    /// Process stack pointer limit (Armv8-M).
+    pub psplim: Option<u32>,
+    /// MemManage Fault Address Register.
+    pub mmfar: Option<u32>,
+    /// BusFault Address Register.
+    pub bfar: Option<u32>,
+    /// Interrupt Control and State Register.
+    pub icsr: Option<u32>,
+    /// HardFault Status Register.
+    pub hfsr: Option<u32>,
+    /// System Handler Control and State Register.
+    pub shcsr: Option<u32>,
+    /// Control register.
+    pub control: Option<u32>,
+    /// General purpose registers `r0` to `r12`.
+    pub r: [Option<u32>; 13],
+}
+
+impl ArmV7mCpuState {
+    fn encode(&self, encoder: &mut MemoryEncoder) -> Result<()> {
+        for (field, value) in [
+            (1, self.pc),
+            (2, self.lr),
+            (3, self.psr),
+            (4, self.msp),
+            (5, self.psp),
+            (6, self.exc_return),
+            (7, self.cfsr),
+            (27, self.msplim),
+            (28, self.psplim),
+            (8, self.mmfar),
+            (9, self.bfar),
+            (10, self.icsr),
+            (25, self.hfsr),
+            (26, self.shcsr),
+            (11, self.control),
+        ] {
+            if let Some(value) = value {
+                encoder.write_uint32(field, value)?;
+            }
+        }
+        // `r0` to `r12` are fields 12 to 24.
+        for (field, value) in (12..).zip(self.r) {
+            if let Some(value) = value {
+                encoder.write_uint32(field, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The epoch of a [`TimePoint`], as a `pw.chrono.EpochType.Enum`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum EpochType {
+    /// The epoch is not known.
+    Unknown = 0,
+    /// Time since the device booted.
+    TimeSinceBoot = 1,
+    /// UTC time since the Unix epoch.
+    UtcWallClock = 2,
+    /// GPS time since 6 January 1980.
+    GpsWallClock = 3,
+    /// TAI time since 1 January 1958.
+    TaiWallClock = 4,
+}
+
+/// A `pw.chrono.TimePoint` marking when a snapshot was captured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimePoint {
+    /// Ticks since the clock's epoch.
+    pub timestamp: i64,
+    /// The numerator of the clock's tick period in seconds.
+    pub tick_period_seconds_numerator: i32,
+    /// The denominator of the clock's tick period in seconds.
+    pub tick_period_seconds_denominator: i32,
+    /// The clock's epoch, if known.
+    pub epoch_type: Option<EpochType>,
+}
+
+impl TimePoint {
+    fn encode(&self, encoder: &mut MemoryEncoder) -> Result<()> {
+        encoder.write_int64(TIME_POINT_TIMESTAMP, self.timestamp)?;
+        encoder.write_nested(TIME_POINT_CLOCK_PARAMETERS, |parameters| {
+            parameters.write_int32(
+                CLOCK_PARAMETERS_NUMERATOR,
+                self.tick_period_seconds_numerator,
+            )?;
+            parameters.write_int32(
+                CLOCK_PARAMETERS_DENOMINATOR,
+                self.tick_period_seconds_denominator,
+            )?;
+            if let Some(epoch_type) = self.epoch_type {
+                parameters.write_uint32(CLOCK_PARAMETERS_EPOCH_TYPE, epoch_type as u32)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Writes the fields of a `pw.snapshot.Snapshot` into a buffer.
+///
+/// Each method returns [`pw_status::Error::ResourceExhausted`] if its field
+/// does not fit in the rest of the buffer, leaving the fields already written
+/// intact.  A snapshot which ran out of space is still valid, so capture
+/// should continue with smaller fields.
+pub struct SnapshotEncoder<'a> {
+    encoder: MemoryEncoder<'a>,
+}
+
+impl<'a> SnapshotEncoder<'a> {
+    /// Creates an encoder which writes a snapshot to `buffer`.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self {
+            encoder: MemoryEncoder::new(buffer),
+        }
+    }
+
+    /// Writes the snapshot's metadata.
+    pub fn metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.encoder
+            .write_nested(SNAPSHOT_METADATA, |encoder| metadata.encode(encoder))
+    }
+
+    /// Adds a tag to highlight data in the snapshot, such as
+    /// `("battery", "low")`.
+    pub fn tag(&mut self, key: &str, value: &str) -> Result<()> {
+        self.encoder.write_nested(SNAPSHOT_TAGS, |entry| {
+            entry.write_string(MAP_ENTRY_KEY, key)?;
+            entry.write_string(MAP_ENTRY_VALUE, value)
+        })
+    }
+
+    /// Adds a thread.
+    pub fn thread(&mut self, thread: &Thread) -> Result<()> {
+        self.encoder
+            .write_nested(SNAPSHOT_THREADS, |encoder| thread.encode(encoder))
+    }
+
+    /// Writes the registers of an Armv7-M or Armv8-M CPU.
+    pub fn armv7m_cpu_state(&mut self, state: &ArmV7mCpuState) -> Result<()> {
+        self.encoder
+            .write_nested(SNAPSHOT_ARMV7M_CPU_STATE, |encoder| state.encode(encoder))
+    }
+
+    /// Adds a time at which the snapshot was captured.  Several timestamps
+    /// may be written for different clocks.
+    pub fn timestamp(&mut self, time_point: &TimePoint) -> Result<()> {
+        self.encoder
+            .write_nested(SNAPSHOT_TIMESTAMPS, |encoder| time_point.encode(encoder))
+    }
+
+    /// Writes binary trace data in the `pw_trace_tokenized` buffer format.
+    pub fn trace_data(&mut self, data: &[u8]) -> Result<()> {
+        self.encoder.write_bytes(SNAPSHOT_TRACE_DATA, data)
+    }
+
+    /// Adds a `pw.log.LogEntry` proto, such as one encoded by `pw_log_rpc`.
+    pub fn log_entry(&mut self, encoded_entry: &[u8]) -> Result<()> {
+        self.encoder.write_bytes(SNAPSHOT_LOGS, encoded_entry)
+    }
+
+    /// Adds the newest `pw.log.LogEntry` protos in `logs` which fit in
+    /// `max_bytes`, returning the number of entries written.
+    ///
+    /// Entries are written from oldest to newest without being popped, so
+    /// logs kept for crash retention are captured intact.  Unlike the other
+    /// methods, entries which do not fit are skipped rather than returning an
+    /// error.
+    ///
+    /// # Errors
+    /// - [`pw_status::Error::DataLoss`] - The ring buffer is corrupt.  The
+    ///   entries before the corruption which fit are written.
+    pub fn log_tail<B: AsRef<[u8]> + AsMut<[u8]>, const MAX_READERS: usize>(
+        &mut self,
+        logs: &mut PrefixedEntryRingBuffer<B, MAX_READERS>,
+        max_bytes: usize,
+    ) -> Result<usize> {
+        let max_bytes = max_bytes.min(self.encoder.remaining());
+        let field_size = |entry: &[u8]| size_of_delimited_field(SNAPSHOT_LOGS, entry.len());
+
+        // Skip the oldest entries until the rest fit.
+        let mut total_bytes = 0;
+        let mut skip = 0;
+        for entry in logs.iter() {
+            let Ok(entry) = entry else { break };
+            total_bytes += field_size(entry.data);
+        }
+        for entry in logs.iter() {
+            if total_bytes <= max_bytes {
+                break;
+            }
+            let Ok(entry) = entry else { break };
+            total_bytes -= field_size(entry.data);
+            skip += 1;
+        }
+
+        let mut written = 0;
+        for entry in logs.iter().skip(skip) {
+            self.log_entry(entry?.data)?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Adds a snapshot from another core of the device, which was encoded
+    /// with its own `SnapshotEncoder`.
+    pub fn related_snapshot(&mut self, encoded_snapshot: &[u8]) -> Result<()> {
+        self.encoder
+            .write_bytes(SNAPSHOT_RELATED_SNAPSHOTS, encoded_snapshot)
+    }
+
+    /// Returns the underlying encoder, to write project specific fields.
+    pub fn encoder(&mut self) -> &mut MemoryEncoder<'a> {
+        &mut self.encoder
+    }
+
+    /// Returns the length of the snapshot.
+    pub fn len(&self) -> usize {
+        self.encoder.len()
+    }
+
+    /// Returns `true` if nothing has been written.
+    pub fn is_empty(&self) -> bool {
+        self.encoder.is_empty()
+    }
+
+    /// Returns the encoded snapshot.
+    pub fn into_slice(self) -> &'a [u8] {
+        self.encoder.into_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use pw_protobuf::{Decoder, Field, Value};
+    use pw_status::Error;
+
+    use super::*;
+
+    fn fields(message: &[u8]) -> Vec<(u32, Value<'_>)> {
+        Decoder::new(message)
+            .map(|field| {
+                let Field { number, value } = field.unwrap();
+                (number, value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn metadata_omits_empty_fields() {
+        let mut buffer = [0u8; 64];
+        let mut snapshot = SnapshotEncoder::new(&mut buffer);
+        snapshot
+            .metadata(&Metadata {
+                reason: b"oops",
+                fatal: true,
+                software_version: "v1",
+                cpu_arch: CpuArchitecture::Armv7m,
+                ..Metadata::new()
+            })
+            .unwrap();
+        assert_eq!(
+            snapshot.into_slice(),
+            b"\x82\x01\x0e\x0a\x04oops\x22\x02v1\x10\x01\x40\x02"
+        );
+    }
+
+    #[test]
+    fn tags_are_map_entries() {
+        let mut buffer = [0u8; 64];
+        let mut snapshot = SnapshotEncoder::new(&mut buffer);
+        snapshot.tag("a", "bc").unwrap();
+        assert_eq!(snapshot.into_slice(), b"\x8a\x01\x07\x0a\x01a\x12\x02bc");
+    }
+
+    #[test]
+    fn thread_fields_are_encoded() {
+        let mut buffer = [0u8; 64];
+        let mut snapshot = SnapshotEncoder::new(&mut buffer);
+        snapshot
+            .thread(&Thread {
+                name: b"idle",
+                state: ThreadState::Ready,
+                raw_backtrace: &[0x100, 0x200],
+                stack_pointer: Some(0),
+                cpu_usage_hundredths: Some(500),
+                ..Thread::new()
+            })
+            .unwrap();
+        let snapshot = snapshot.into_slice();
+        let [(18, Value::Delimited(thread))] = fields(snapshot)[..] else {
+            panic!("expected a thread");
+        };
+        assert_eq!(
+            fields(thread),
+            [
+                (1, Value::Delimited(b"idle")),
+                (3, Value::Varint(3)),
+                (4, Value::Varint(0x100)),
+                (4, Value::Varint(0x200)),
+                (9, Value::Varint(0)),
+                (10, Value::Varint(500)),
+            ]
+        );
+    }
+
+    #[test]
+    fn cpu_state_omits_missing_registers() {
+        let mut r = [None; 13];
+        r[0] = Some(7);
+        r[12] = Some(8);
+        let mut buffer = [0u8; 64];
+        let mut snapshot = SnapshotEncoder::new(&mut buffer);
+        snapshot
+            .armv7m_cpu_state(&ArmV7mCpuState {
+                pc: Some(0x0800_0100),
+                msplim: Some(1),
+                r,
+                ..Default::default()
+            })
+            .unwrap();
+        let snapshot = snapshot.into_slice();
+        let [(20, Value::Delimited(state))] = fields(snapshot)[..] else {
+            panic!("expected CPU state");
+        };
+        assert_eq!(
+            fields(state),
+            [
+                (1, Value::Varint(0x0800_0100)),
+                (27, Value::Varint(1)),
+                (12, Value::Varint(7)),
+                (24, Value::Varint(8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn timestamp_has_clock_parameters() {
+        let mut buffer = [0u8; 64];
+        let mut snapshot = SnapshotEncoder::new(&mut buffer);
+        snapshot
+            .timestamp(&TimePoint {
+                timestamp: 1234,
+                tick_period_seconds_numerator: 1,
+                tick_period_seconds_denominator: 1000,
+                epoch_type: Some(EpochType::TimeSinceBoot),
+            })
+            .unwrap();
+        assert_eq!(
+            snapshot.into_slice(),
+            b"\xb2\x01\x0c\x08\xd2\x09\x12\x07\x08\x01\x10\xe8\x07\x18\x01"
+        );
+    }
+
+    #[test]
+    fn log_tail_keeps_newest_entries() {
+        let mut logs = PrefixedEntryRingBuffer::<_>::new([0u8; 64], false);
+        let reader = logs.attach_reader().unwrap();
+        for entry in [b"first", b"secnd", b"third"] {
+            logs.push_back(entry, 0).unwrap();
+        }
+
+        // Each entry uses 7 bytes in the snapshot.
+        let mut buffer = [0u8; 64];
+        let mut snapshot = SnapshotEncoder::new(&mut buffer);
+        assert_eq!(snapshot.log_tail(&mut logs, 15), Ok(2));
+        assert_eq!(
+            fields(snapshot.into_slice()),
+            [
+                (1, Value::Delimited(b"secnd")),
+                (1, Value::Delimited(b"third")),
+            ]
+        );
+        // The entries are not popped.
+        assert_eq!(logs.entry_count(reader), 3);
+
+        let mut buffer = [0u8; 10];
+        let mut snapshot = SnapshotEncoder::new(&mut buffer);
+        assert_eq!(snapshot.log_tail(&mut logs, usize::MAX), Ok(1));
+        assert_eq!(snapshot.len(), 7);
+    }
+
+    #[test]
+    fn full_snapshot_keeps_written_fields() {
+        let mut buffer = [0u8; 8];
+        let mut snapshot = SnapshotEncoder::new(&mut buffer);
+        snapshot.trace_data(b"trace").unwrap();
+        assert_eq!(snapshot.tag("key", "value"), Err(Error::ResourceExhausted));
+        assert_eq!(snapshot.into_slice(), b"\xaa\x01\x05trace");
+    }
+}
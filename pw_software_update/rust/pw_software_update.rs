@@ -0,0 +1,1158 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! `pw_software_update` parses and verifies the update bundles built by the
+//! `pw_software_update` Python tools, so that a Rust bootloader or updater
+//! can gate the application of new images on their authenticity.
+//!
+//! This is the Rust counterpart of the C++ `UpdateBundleAccessor`.  Bundles
+//! follow [TUF](https://theupdateframework.io/): the targets metadata which
+//! lists each image's length and SHA-256 hash is signed with keys listed in
+//! the root metadata, and the root metadata itself may be rotated by a bundle
+//! if the new root is signed by both the trusted root's keys and its own.
+//!
+//! Messages are accessed in place, without copying or allocation.  Hashing
+//! and signature verification are done by any implementation of the
+//! `pw_crypto` traits, which may use a hardware accelerator.
+//!
+//! ```ignore
+//! use pw_crypto::software::Software;
+//! use pw_software_update::{BundleVerifier, Manifest};
+//!
+//! let mut verifier = BundleVerifier::new(Software, storage.root_metadata());
+//! if let Some(manifest) = storage.manifest() {
+//!     verifier = verifier.with_device_manifest(Manifest::parse(manifest)?);
+//! }
+//! let bundle = verifier.verify(staged_bundle, |root| storage.persist_root_metadata(root))?;
+//! for target in bundle.manifest().target_files()? {
+//!     let target = target?;
+//!     if let Some(payload) = bundle.target_payload(target.name)? {
+//!         flash.install(target.name, payload)?;
+//!     }
+//! }
+//! storage.write_manifest(|encoder| bundle.manifest().encode(encoder))?;
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_crypto::ecdsa::P256Verify;
+use pw_crypto::sha256::Sha256;
+use pw_protobuf::{Decoder, MemoryEncoder, Value};
+use pw_status::{Error, Result};
+
+/// The name of the top level targets metadata in a bundle.
+pub const TOP_LEVEL_TARGETS_NAME: &str = "targets";
+
+/// The name of the target holding the user manifest, which is not an image.
+pub const USER_MANIFEST_TARGET_FILE_NAME: &str = "user_manifest";
+
+/// The default limit on the length of a target payload.
+pub const DEFAULT_MAX_TARGET_PAYLOAD_SIZE: u64 = 100 * 1024 * 1024;
+
+// Field numbers from `update_bundle.proto` and `tuf.proto`.
+mod fields {
+    pub mod update_bundle {
+        pub const TARGETS_METADATA: u32 = 3;
+        pub const TARGET_PAYLOADS: u32 = 4;
+        pub const ROOT_METADATA: u32 = 5;
+    }
+
+    pub mod manifest {
+        pub const TARGETS_METADATA: u32 = 1;
+        pub const USER_MANIFEST: u32 = 2;
+    }
+
+    pub mod map_entry {
+        pub const KEY: u32 = 1;
+        pub const VALUE: u32 = 2;
+    }
+
+    pub mod signed_metadata {
+        pub const SERIALIZED_METADATA: u32 = 1;
+        pub const SIGNATURES: u32 = 2;
+    }
+
+    pub mod signature {
+        pub const KEY_ID: u32 = 1;
+        pub const SIG: u32 = 2;
+    }
+
+    pub mod common_metadata {
+        pub const VERSION: u32 = 2;
+    }
+
+    pub mod root_metadata {
+        pub const COMMON_METADATA: u32 = 1;
+        pub const KEYS: u32 = 3;
+        pub const ROOT_SIGNATURE_REQUIREMENT: u32 = 4;
+        pub const TARGETS_SIGNATURE_REQUIREMENT: u32 = 7;
+    }
+
+    pub mod key {
+        pub const KEYVAL: u32 = 3;
+    }
+
+    pub mod signature_requirement {
+        pub const KEY_IDS: u32 = 1;
+        pub const THRESHOLD: u32 = 2;
+    }
+
+    pub mod targets_metadata {
+        pub const COMMON_METADATA: u32 = 1;
+        pub const TARGET_FILES: u32 = 2;
+    }
+
+    pub mod target_file {
+        pub const FILE_NAME: u32 = 1;
+        pub const LENGTH: u32 = 2;
+        pub const HASHES: u32 = 3;
+    }
+
+    pub mod hash {
+        pub const FUNCTION: u32 = 1;
+        pub const HASH: u32 = 2;
+    }
+}
+
+// `HashFunction.SHA256` in `tuf.proto`.
+const HASH_FUNCTION_SHA256: u32 = 1;
+
+// Returns the last value of field `number` in `message`, which is the value
+// of a singular field encoded more than once.
+fn field(message: &[u8], number: u32) -> Result<Option<Value<'_>>> {
+    let mut value = None;
+    for field in Decoder::new(message) {
+        let field = field?;
+        if field.number == number {
+            value = Some(field.value);
+        }
+    }
+    Ok(value)
+}
+
+fn bytes_field(message: &[u8], number: u32) -> Result<Option<&[u8]>> {
+    field(message, number)?
+        .map(|value| value.as_bytes())
+        .transpose()
+}
+
+// Returns the value of a `uint32` field, which is zero if it is not encoded.
+fn u32_field(message: &[u8], number: u32) -> Result<u32> {
+    field(message, number)?.map_or(Ok(0), |value| value.as_u32())
+}
+
+fn repeated_bytes(message: &[u8], number: u32) -> impl Iterator<Item = Result<&[u8]>> {
+    Decoder::new(message).filter_map(move |field| match field {
+        Ok(field) if field.number == number => Some(field.value.as_bytes()),
+        Ok(_) => None,
+        Err(e) => Some(Err(e)),
+    })
+}
+
+// Returns the value for `key` in the map field `number`, which may be a
+// `map<string, _>` or a repeated message with the same layout.
+fn map_value<'a>(message: &'a [u8], number: u32, key: &[u8]) -> Result<Option<&'a [u8]>> {
+    for entry in repeated_bytes(message, number) {
+        let entry = entry?;
+        if bytes_field(entry, fields::map_entry::KEY)?.unwrap_or_default() == key {
+            return Ok(Some(
+                bytes_field(entry, fields::map_entry::VALUE)?.unwrap_or_default(),
+            ));
+        }
+    }
+    Ok(None)
+}
+
+fn common_metadata_version(metadata: &[u8], number: u32) -> Result<u32> {
+    let common_metadata = bytes_field(metadata, number)?.ok_or(Error::NotFound)?;
+    u32_field(common_metadata, fields::common_metadata::VERSION)
+}
+
+/// A signature of serialized metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Signature<'a> {
+    /// The ID of the signing key, which is the SHA-256 digest of the key.
+    pub key_id: &'a [u8],
+    /// The signature.
+    pub sig: &'a [u8],
+}
+
+/// A `SignedRootMetadata` or `SignedTargetsMetadata` message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SignedMetadata<'a> {
+    message: &'a [u8],
+    serialized: &'a [u8],
+}
+
+impl<'a> SignedMetadata<'a> {
+    /// Parses a signed metadata message.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The message is malformed.
+    /// - [`Error::NotFound`] - The message has no serialized metadata.
+    pub fn parse(message: &'a [u8]) -> Result<Self> {
+        let serialized = bytes_field(message, fields::signed_metadata::SERIALIZED_METADATA)?
+            .ok_or(Error::NotFound)?;
+        Ok(Self {
+            message,
+            serialized,
+        })
+    }
+
+    /// Returns the encoded message.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.message
+    }
+
+    /// Returns the serialized metadata which is signed.
+    pub fn serialized(&self) -> &'a [u8] {
+        self.serialized
+    }
+
+    /// Returns the metadata's signatures.
+    pub fn signatures(&self) -> impl Iterator<Item = Result<Signature<'a>>> {
+        repeated_bytes(self.message, fields::signed_metadata::SIGNATURES).map(|signature| {
+            let signature = signature?;
+            Ok(Signature {
+                key_id: bytes_field(signature, fields::signature::KEY_ID)?.unwrap_or_default(),
+                sig: bytes_field(signature, fields::signature::SIG)?.unwrap_or_default(),
+            })
+        })
+    }
+}
+
+/// The signatures required to trust metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SignatureRequirement<'a> {
+    message: &'a [u8],
+}
+
+impl<'a> SignatureRequirement<'a> {
+    /// Returns the IDs of keys whose signatures are counted.
+    pub fn key_ids(&self) -> impl Iterator<Item = Result<&'a [u8]>> {
+        repeated_bytes(self.message, fields::signature_requirement::KEY_IDS)
+    }
+
+    /// Returns the number of valid signatures required.
+    pub fn threshold(&self) -> Result<u32> {
+        u32_field(self.message, fields::signature_requirement::THRESHOLD)
+    }
+
+    fn allows(&self, key_id: &[u8]) -> Result<bool> {
+        for allowed in self.key_ids() {
+            if allowed? == key_id {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// A `RootMetadata` message, which lists the keys trusted to sign metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RootMetadata<'a> {
+    message: &'a [u8],
+}
+
+impl<'a> RootMetadata<'a> {
+    /// Creates a view of serialized root metadata.
+    pub const fn new(message: &'a [u8]) -> Self {
+        Self { message }
+    }
+
+    /// Returns the version of the root metadata.
+    pub fn version(&self) -> Result<u32> {
+        common_metadata_version(self.message, fields::root_metadata::COMMON_METADATA)
+    }
+
+    /// Returns the value of the key with `key_id`, or `None` if the root has
+    /// no such key.
+    pub fn key_value(&self, key_id: &[u8]) -> Result<Option<&'a [u8]>> {
+        map_value(self.message, fields::root_metadata::KEYS, key_id)?
+            .map(|key| Ok(bytes_field(key, fields::key::KEYVAL)?.unwrap_or_default()))
+            .transpose()
+    }
+
+    /// Returns the signatures required to trust new root metadata.
+    pub fn root_signature_requirement(&self) -> Result<SignatureRequirement<'a>> {
+        self.signature_requirement(fields::root_metadata::ROOT_SIGNATURE_REQUIREMENT)
+    }
+
+    /// Returns the signatures required to trust targets metadata.
+    pub fn targets_signature_requirement(&self) -> Result<SignatureRequirement<'a>> {
+        self.signature_requirement(fields::root_metadata::TARGETS_SIGNATURE_REQUIREMENT)
+    }
+
+    fn signature_requirement(&self, number: u32) -> Result<SignatureRequirement<'a>> {
+        let message = bytes_field(self.message, number)?.ok_or(Error::NotFound)?;
+        Ok(SignatureRequirement { message })
+    }
+}
+
+/// A target file listed in targets metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetFile<'a> {
+    /// The name of the target.
+    pub name: &'a str,
+    /// The length of the target's payload in bytes.
+    pub length: u64,
+    /// The SHA-256 digest of the target's payload, if listed.
+    pub sha256: Option<&'a [u8]>,
+}
+
+impl<'a> TargetFile<'a> {
+    /// Parses a `TargetFile` message.
+    pub fn parse(message: &'a [u8]) -> Result<Self> {
+        let name = field(message, fields::target_file::FILE_NAME)?
+            .map_or(Ok(""), |value| value.as_str())?;
+        let length =
+            field(message, fields::target_file::LENGTH)?.map_or(Ok(0), |value| value.as_u64())?;
+        let mut sha256 = None;
+        for hash in repeated_bytes(message, fields::target_file::HASHES) {
+            let hash = hash?;
+            if u32_field(hash, fields::hash::FUNCTION)? == HASH_FUNCTION_SHA256 {
+                sha256 = Some(bytes_field(hash, fields::hash::HASH)?.unwrap_or_default());
+                break;
+            }
+        }
+        Ok(Self {
+            name,
+            length,
+            sha256,
+        })
+    }
+}
+
+/// A `TargetsMetadata` message, which lists the targets of a bundle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetsMetadata<'a> {
+    message: &'a [u8],
+}
+
+impl<'a> TargetsMetadata<'a> {
+    /// Creates a view of serialized targets metadata.
+    pub const fn new(message: &'a [u8]) -> Self {
+        Self { message }
+    }
+
+    /// Returns the encoded message.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.message
+    }
+
+    /// Returns the version of the targets metadata.
+    pub fn version(&self) -> Result<u32> {
+        common_metadata_version(self.message, fields::targets_metadata::COMMON_METADATA)
+    }
+
+    /// Returns the listed target files.
+    pub fn target_files(&self) -> impl Iterator<Item = Result<TargetFile<'a>>> {
+        repeated_bytes(self.message, fields::targets_metadata::TARGET_FILES)
+            .map(|target_file| TargetFile::parse(target_file?))
+    }
+
+    /// Returns the target file named `name`, or `None` if it is not listed.
+    pub fn target_file(&self, name: &str) -> Result<Option<TargetFile<'a>>> {
+        for target_file in self.target_files() {
+            let target_file = target_file?;
+            if target_file.name == name {
+                return Ok(Some(target_file));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The manifest of a bundle or device, which is the top level targets
+/// metadata and an optional user manifest.
+///
+/// After a bundle is applied, its manifest is stored on the device with
+/// [`Manifest::encode()`].  The device manifest is used to prevent rollbacks
+/// and to verify targets which were personalized out of later bundles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Manifest<'a> {
+    targets_metadata: Option<TargetsMetadata<'a>>,
+    user_manifest: Option<&'a [u8]>,
+}
+
+impl<'a> Manifest<'a> {
+    /// Parses an encoded `Manifest` message, as stored on a device.
+    pub fn parse(message: &'a [u8]) -> Result<Self> {
+        Ok(Self {
+            targets_metadata: map_value(
+                message,
+                fields::manifest::TARGETS_METADATA,
+                TOP_LEVEL_TARGETS_NAME.as_bytes(),
+            )?
+            .map(TargetsMetadata::new),
+            user_manifest: bytes_field(message, fields::manifest::USER_MANIFEST)?,
+        })
+    }
+
+    /// Returns the manifest of `bundle`.
+    ///
+    /// The manifest is only trustworthy once the bundle is verified.
+    pub fn from_bundle(bundle: &UpdateBundle<'a>) -> Result<Self> {
+        Ok(Self {
+            targets_metadata: bundle
+                .targets_metadata(TOP_LEVEL_TARGETS_NAME)?
+                .map(|signed| TargetsMetadata::new(signed.serialized())),
+            user_manifest: bundle.target_payload(USER_MANIFEST_TARGET_FILE_NAME)?,
+        })
+    }
+
+    /// Returns the top level targets metadata, if present.
+    pub fn targets_metadata(&self) -> Option<TargetsMetadata<'a>> {
+        self.targets_metadata
+    }
+
+    /// Returns the user manifest, if present.
+    pub fn user_manifest(&self) -> Option<&'a [u8]> {
+        self.user_manifest
+    }
+
+    /// Returns the version of the top level targets metadata.
+    ///
+    /// # Errors
+    /// - [`Error::NotFound`] - The manifest has no targets metadata.
+    pub fn version(&self) -> Result<u32> {
+        self.targets_metadata.ok_or(Error::NotFound)?.version()
+    }
+
+    /// Returns the target files listed in the top level targets metadata.
+    ///
+    /// # Errors
+    /// - [`Error::NotFound`] - The manifest has no targets metadata.
+    pub fn target_files(&self) -> Result<impl Iterator<Item = Result<TargetFile<'a>>>> {
+        Ok(self.targets_metadata.ok_or(Error::NotFound)?.target_files())
+    }
+
+    /// Returns the target file named `name`, or `None` if it is not listed.
+    ///
+    /// # Errors
+    /// - [`Error::NotFound`] - The manifest has no targets metadata.
+    pub fn target_file(&self, name: &str) -> Result<Option<TargetFile<'a>>> {
+        self.targets_metadata
+            .ok_or(Error::NotFound)?
+            .target_file(name)
+    }
+
+    /// Encodes the manifest as a `Manifest` message, to be stored on the
+    /// device.
+    ///
+    /// # Errors
+    /// - [`Error::NotFound`] - The manifest has no targets metadata.
+    /// - [`Error::ResourceExhausted`] - The manifest does not fit in the
+    ///   encoder's buffer.
+    pub fn encode(&self, encoder: &mut MemoryEncoder) -> Result<()> {
+        let targets_metadata = self.targets_metadata.ok_or(Error::NotFound)?;
+        encoder.write_nested(fields::manifest::TARGETS_METADATA, |entry| {
+            entry.write_string(fields::map_entry::KEY, TOP_LEVEL_TARGETS_NAME)?;
+            entry.write_bytes(fields::map_entry::VALUE, targets_metadata.as_bytes())
+        })?;
+        if let Some(user_manifest) = self.user_manifest {
+            encoder.write_bytes(fields::manifest::USER_MANIFEST, user_manifest)?;
+        }
+        Ok(())
+    }
+}
+
+/// An `UpdateBundle` message.
+///
+/// Nothing in a bundle is trustworthy until it is verified with a
+/// [`BundleVerifier`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpdateBundle<'a> {
+    message: &'a [u8],
+}
+
+impl<'a> UpdateBundle<'a> {
+    /// Creates a view of an encoded bundle.
+    pub const fn new(message: &'a [u8]) -> Self {
+        Self { message }
+    }
+
+    /// Returns the root metadata included to rotate the device's root of
+    /// trust, if any.
+    pub fn root_metadata(&self) -> Result<Option<SignedMetadata<'a>>> {
+        bytes_field(self.message, fields::update_bundle::ROOT_METADATA)?
+            .map(SignedMetadata::parse)
+            .transpose()
+    }
+
+    /// Returns the targets metadata named `name`, if present.
+    pub fn targets_metadata(&self, name: &str) -> Result<Option<SignedMetadata<'a>>> {
+        map_value(
+            self.message,
+            fields::update_bundle::TARGETS_METADATA,
+            name.as_bytes(),
+        )?
+        .map(SignedMetadata::parse)
+        .transpose()
+    }
+
+    /// Returns the payload of the target named `name`, or `None` if it is not
+    /// in the bundle.
+    pub fn target_payload(&self, name: &str) -> Result<Option<&'a [u8]>> {
+        map_value(
+            self.message,
+            fields::update_bundle::TARGET_PAYLOADS,
+            name.as_bytes(),
+        )
+    }
+}
+
+// Verifies that at least `requirement`'s threshold of `metadata`'s signatures
+// are valid signatures by keys in `keys` which `requirement` allows.
+//
+// Returns `Error::NotFound` if the metadata has no signatures, which self
+// verification uses to tell unsigned bundles apart, and
+// `Error::Unauthenticated` if too few signatures are valid.
+fn verify_signatures(
+    crypto: &(impl Sha256 + P256Verify),
+    metadata: &SignedMetadata,
+    requirement: &SignatureRequirement,
+    keys: &RootMetadata,
+) -> Result<()> {
+    let threshold = requirement.threshold()?;
+    let digest = crypto.hash(metadata.serialized())?;
+    let mut verified = 0;
+    let mut signatures = 0;
+    for signature in metadata.signatures() {
+        let signature = signature?;
+        signatures += 1;
+        if !requirement.allows(signature.key_id)? {
+            continue;
+        }
+        let Some(key) = keys.key_value(signature.key_id)? else {
+            continue;
+        };
+        if crypto
+            .verify_p256_signature(key, &digest, signature.sig)
+            .is_ok()
+        {
+            verified += 1;
+            if verified == threshold {
+                return Ok(());
+            }
+        }
+    }
+    if signatures == 0 {
+        return Err(Error::NotFound);
+    }
+    Err(Error::Unauthenticated)
+}
+
+// Verifies `new_root` against the root requirement and keys of `trusted`.
+fn verify_root_signatures(
+    crypto: &(impl Sha256 + P256Verify),
+    trusted: &SignedMetadata,
+    new_root: &SignedMetadata,
+) -> Result<()> {
+    let trusted = RootMetadata::new(trusted.serialized());
+    verify_signatures(
+        crypto,
+        new_root,
+        &trusted.root_signature_requirement()?,
+        &trusted,
+    )
+}
+
+/// Verifies update bundles against a device's root of trust.
+///
+/// Verification follows the C++ `UpdateBundleAccessor`:
+///
+/// 1. If the bundle includes root metadata, it must be signed by the keys
+///    which the trusted root requires for new roots, and by the keys which
+///    it requires itself.  Its version may not be older than the trusted
+///    root's.  The new root replaces the trusted root for the rest of the
+///    verification, and is passed to the caller to persist.
+/// 2. The top level targets metadata must be signed by the keys which the
+///    trusted root requires.  Its version may not be older than the device
+///    manifest's, if there is one.
+/// 3. Each target's payload must have the listed length and SHA-256 digest.
+///    A target whose payload is not in the bundle, since it was personalized
+///    out, must be listed with the same length and digest in the device
+///    manifest.
+///
+/// Bundles can also be verified without a device's root of trust with
+/// [`BundleVerifier::for_self_verification()`], which is used by tools to
+/// check a bundle against the root metadata it includes.
+pub struct BundleVerifier<'a, C: Sha256 + P256Verify> {
+    crypto: C,
+    trusted_root: Option<&'a [u8]>,
+    device_manifest: Option<Manifest<'a>>,
+    max_target_payload_size: u64,
+}
+
+impl<'a, C: Sha256 + P256Verify> BundleVerifier<'a, C> {
+    /// Creates a verifier for a device whose root of trust is the encoded
+    /// `SignedRootMetadata` message in `trusted_root`.
+    pub fn new(crypto: C, trusted_root: &'a [u8]) -> Self {
+        Self {
+            crypto,
+            trusted_root: Some(trusted_root),
+            device_manifest: None,
+            max_target_payload_size: DEFAULT_MAX_TARGET_PAYLOAD_SIZE,
+        }
+    }
+
+    /// Creates a verifier which trusts the root metadata included in each
+    /// bundle.
+    ///
+    /// Self verification does not prove that a bundle is authentic.  It
+    /// checks that a bundle is consistent: unsigned bundles and bundles
+    /// without root metadata are accepted as long as their payloads match
+    /// their targets metadata.  Rollbacks are not checked.
+    pub fn for_self_verification(crypto: C) -> Self {
+        Self {
+            crypto,
+            trusted_root: None,
+            device_manifest: None,
+            max_target_payload_size: DEFAULT_MAX_TARGET_PAYLOAD_SIZE,
+        }
+    }
+
+    /// Sets the manifest of the bundle which was last applied to the device.
+    pub fn with_device_manifest(mut self, manifest: Manifest<'a>) -> Self {
+        self.device_manifest = Some(manifest);
+        self
+    }
+
+    /// Sets the limit on the length of a target payload, which is
+    /// [`DEFAULT_MAX_TARGET_PAYLOAD_SIZE`] by default.
+    pub fn with_max_target_payload_size(mut self, size: u64) -> Self {
+        self.max_target_payload_size = size;
+        self
+    }
+
+    fn self_verification(&self) -> bool {
+        self.trusted_root.is_none()
+    }
+
+    /// Verifies the encoded `UpdateBundle` message in `bundle`.
+    ///
+    /// If the bundle rotates the root of trust, `persist_root` is called
+    /// with the new encoded `SignedRootMetadata` message as soon as it is
+    /// verified, before the rest of the bundle.  Rotating keys does not
+    /// depend on the targets, so that compromised keys can be revoked even
+    /// if an update is not applied.  `persist_root` is not called during self
+    /// verification.
+    ///
+    /// # Errors
+    /// - [`Error::Unauthenticated`] - The bundle is not authentic, would roll
+    ///   back the device, or has a payload which does not match its targets
+    ///   metadata.
+    /// - [`Error::OutOfRange`] - A target is longer than the maximum payload
+    ///   size.
+    /// - [`Error::NotFound`] - The bundle has no top level targets metadata,
+    ///   or a target has no SHA-256 digest.
+    /// - [`Error::DataLoss`] - The bundle or a trusted message is malformed.
+    ///
+    /// Any error returned by `persist_root` or by hashing is also returned.
+    pub fn verify<'b>(
+        &self,
+        bundle: &'b [u8],
+        persist_root: impl FnOnce(&'b [u8]) -> Result<()>,
+    ) -> Result<VerifiedBundle<'b>> {
+        let bundle = UpdateBundle::new(bundle);
+        let bundle_root = bundle.root_metadata()?;
+        let trusted_root = match self.trusted_root {
+            Some(trusted_root) => Some(SignedMetadata::parse(trusted_root)?),
+            None => bundle_root,
+        };
+
+        let trusted_root = match (trusted_root, bundle_root) {
+            (Some(trusted_root), Some(new_root)) => {
+                self.upgrade_root(&trusted_root, &new_root)?;
+                if !self.self_verification() {
+                    persist_root(new_root.as_bytes())?;
+                }
+                Some(new_root)
+            }
+            (trusted_root, _) => trusted_root,
+        };
+
+        if let Some(trusted_root) = trusted_root {
+            self.verify_targets_metadata(&trusted_root, &bundle)?;
+        }
+
+        let manifest = Manifest::from_bundle(&bundle)?;
+        self.verify_targets_payloads(&manifest, &bundle)?;
+
+        Ok(VerifiedBundle { bundle, manifest })
+    }
+
+    fn upgrade_root(&self, trusted_root: &SignedMetadata, new_root: &SignedMetadata) -> Result<()> {
+        verify_root_signatures(&self.crypto, trusted_root, new_root)
+            .map_err(|_| Error::Unauthenticated)?;
+        verify_root_signatures(&self.crypto, new_root, new_root)
+            .map_err(|_| Error::Unauthenticated)?;
+
+        let trusted_version = RootMetadata::new(trusted_root.serialized()).version()?;
+        let new_version = RootMetadata::new(new_root.serialized()).version()?;
+        if trusted_version > new_version {
+            return Err(Error::Unauthenticated);
+        }
+        Ok(())
+    }
+
+    fn verify_targets_metadata(
+        &self,
+        trusted_root: &SignedMetadata,
+        bundle: &UpdateBundle,
+    ) -> Result<()> {
+        let targets = bundle
+            .targets_metadata(TOP_LEVEL_TARGETS_NAME)?
+            .ok_or(Error::NotFound)?;
+        let root = RootMetadata::new(trusted_root.serialized());
+        match verify_signatures(
+            &self.crypto,
+            &targets,
+            &root.targets_signature_requirement()?,
+            &root,
+        ) {
+            Ok(()) => (),
+            Err(Error::NotFound) if self.self_verification() => return Ok(()),
+            Err(_) => return Err(Error::Unauthenticated),
+        }
+
+        if self.self_verification() {
+            return Ok(());
+        }
+        let current_version = match self.device_manifest.map(|manifest| manifest.version()) {
+            None | Some(Err(Error::NotFound)) => return Ok(()),
+            Some(version) => version?,
+        };
+        if current_version > TargetsMetadata::new(targets.serialized()).version()? {
+            return Err(Error::Unauthenticated);
+        }
+        Ok(())
+    }
+
+    fn verify_targets_payloads(&self, manifest: &Manifest, bundle: &UpdateBundle) -> Result<()> {
+        for target_file in manifest.target_files()? {
+            let target_file = target_file?;
+            if target_file.length > self.max_target_payload_size {
+                return Err(Error::OutOfRange);
+            }
+            let sha256 = target_file.sha256.ok_or(Error::NotFound)?;
+            match bundle.target_payload(target_file.name)? {
+                Some(payload) => {
+                    if payload.len() as u64 != target_file.length
+                        || self.crypto.hash(payload)?[..] != *sha256
+                    {
+                        return Err(Error::Unauthenticated);
+                    }
+                }
+                None => self.verify_out_of_bundle_target(&target_file, sha256)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Verifies a target which was personalized out of a bundle against the
+    // device manifest.
+    fn verify_out_of_bundle_target(&self, target_file: &TargetFile, sha256: &[u8]) -> Result<()> {
+        let cached = self
+            .device_manifest
+            .and_then(|manifest| manifest.target_file(target_file.name).ok().flatten())
+            .ok_or(Error::Unauthenticated)?;
+        if cached.length != target_file.length || cached.sha256 != Some(sha256) {
+            return Err(Error::Unauthenticated);
+        }
+        Ok(())
+    }
+}
+
+/// A bundle which passed verification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VerifiedBundle<'a> {
+    bundle: UpdateBundle<'a>,
+    manifest: Manifest<'a>,
+}
+
+impl<'a> VerifiedBundle<'a> {
+    /// Returns the bundle.
+    pub fn bundle(&self) -> &UpdateBundle<'a> {
+        &self.bundle
+    }
+
+    /// Returns the bundle's manifest, which is stored on the device once the
+    /// bundle is applied.
+    pub fn manifest(&self) -> &Manifest<'a> {
+        &self.manifest
+    }
+
+    /// Returns the payload of the target named `name`, or `None` if the
+    /// target is not in the bundle.
+    ///
+    /// # Errors
+    /// - [`Error::NotFound`] - `name` is not listed in the manifest.
+    pub fn target_payload(&self, name: &str) -> Result<Option<&'a [u8]>> {
+        self.manifest.target_file(name)?.ok_or(Error::NotFound)?;
+        self.bundle.target_payload(name)
+    }
+
+    /// Returns the total length of the target payloads in the bundle.
+    pub fn total_payload_size(&self) -> Result<u64> {
+        let mut total = 0;
+        for target_file in self.manifest.target_files()? {
+            let target_file = target_file?;
+            if self.bundle.target_payload(target_file.name)?.is_some() {
+                total += target_file.length;
+            }
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::cell::Cell;
+    use std::vec::Vec;
+
+    use pw_crypto::software::{Sha256Hasher, Software};
+
+    use super::*;
+
+    // Hashes with the software SHA-256, and stands in for ECDSA.  A key's
+    // value is 0x04 followed by its ID repeated, and a signature is the
+    // digest followed by the first half of the signing key's value.
+    struct FakeCrypto;
+
+    impl Sha256 for FakeCrypto {
+        type Hasher<'a> = Sha256Hasher;
+
+        fn hasher(&self) -> Result<Sha256Hasher> {
+            Software.hasher()
+        }
+    }
+
+    impl P256Verify for FakeCrypto {
+        fn verify_p256_signature(
+            &self,
+            public_key: &[u8],
+            digest: &[u8],
+            signature: &[u8],
+        ) -> Result<()> {
+            if public_key.len() == 65
+                && signature.len() == 64
+                && signature[..32] == *digest
+                && signature[32..] == public_key[1..33]
+            {
+                Ok(())
+            } else {
+                Err(Error::Unauthenticated)
+            }
+        }
+    }
+
+    fn key_id(key: u8) -> [u8; 32] {
+        [key; 32]
+    }
+
+    fn key_value(key: u8) -> [u8; 65] {
+        let mut value = [key; 65];
+        value[0] = 0x04;
+        value
+    }
+
+    fn encode(encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>) -> Vec<u8> {
+        let mut buffer = [0u8; 2048];
+        let mut encoder = MemoryEncoder::new(&mut buffer);
+        encode(&mut encoder).unwrap();
+        encoder.as_slice().to_vec()
+    }
+
+    fn signature_requirement(
+        encoder: &mut MemoryEncoder,
+        keys: &[u8],
+        threshold: u32,
+    ) -> Result<()> {
+        for &key in keys {
+            encoder.write_bytes(1, &key_id(key))?;
+        }
+        encoder.write_uint32(2, threshold)
+    }
+
+    // Returns serialized root metadata which trusts `root_keys` to sign new
+    // roots and `targets_keys` to sign targets metadata.
+    fn root_metadata(version: u32, root_keys: &[u8], targets_keys: &[u8]) -> Vec<u8> {
+        encode(|encoder| {
+            encoder.write_nested(1, |common| common.write_uint32(2, version))?;
+            for &key in root_keys.iter().chain(targets_keys) {
+                encoder.write_nested(3, |mapping| {
+                    mapping.write_bytes(1, &key_id(key))?;
+                    mapping.write_nested(2, |value| {
+                        value.write_uint32(1, 1)?;
+                        value.write_uint32(2, 1)?;
+                        value.write_bytes(3, &key_value(key))
+                    })
+                })?;
+            }
+            encoder.write_nested(4, |requirement| {
+                signature_requirement(requirement, root_keys, 1)
+            })?;
+            encoder.write_nested(7, |requirement| {
+                signature_requirement(requirement, targets_keys, 1)
+            })
+        })
+    }
+
+    fn targets_metadata(version: u32, targets: &[(&str, &[u8])]) -> Vec<u8> {
+        encode(|encoder| {
+            encoder.write_nested(1, |common| common.write_uint32(2, version))?;
+            for (name, payload) in targets {
+                encoder.write_nested(2, |target_file| {
+                    target_file.write_string(1, name)?;
+                    target_file.write_uint64(2, payload.len() as u64)?;
+                    target_file.write_nested(3, |hash| {
+                        hash.write_uint32(1, HASH_FUNCTION_SHA256)?;
+                        hash.write_bytes(2, &FakeCrypto.hash(payload).unwrap())
+                    })
+                })?;
+            }
+            Ok(())
+        })
+    }
+
+    fn sign(serialized: &[u8], keys: &[u8]) -> Vec<u8> {
+        encode(|encoder| {
+            encoder.write_bytes(1, serialized)?;
+            for &key in keys {
+                let mut sig = FakeCrypto.hash(serialized).unwrap().to_vec();
+                sig.extend_from_slice(&key_value(key)[1..33]);
+                encoder.write_nested(2, |signature| {
+                    signature.write_bytes(1, &key_id(key))?;
+                    signature.write_bytes(2, &sig)
+                })?;
+            }
+            Ok(())
+        })
+    }
+
+    fn update_bundle(root: Option<&[u8]>, targets: &[u8], payloads: &[(&str, &[u8])]) -> Vec<u8> {
+        encode(|encoder| {
+            encoder.write_nested(3, |entry| {
+                entry.write_string(1, TOP_LEVEL_TARGETS_NAME)?;
+                entry.write_bytes(2, targets)
+            })?;
+            for (name, payload) in payloads {
+                encoder.write_nested(4, |entry| {
+                    entry.write_string(1, name)?;
+                    entry.write_bytes(2, payload)
+                })?;
+            }
+            if let Some(root) = root {
+                encoder.write_bytes(5, root)?;
+            }
+            Ok(())
+        })
+    }
+
+    const FIRMWARE: &[u8] = b"firmware image";
+    const USER_MANIFEST: &[u8] = b"user manifest";
+
+    fn no_rotation(_: &[u8]) -> Result<()> {
+        panic!("root should not be rotated");
+    }
+
+    #[test]
+    fn verifies_signed_bundle() {
+        let trusted_root = sign(&root_metadata(1, &[1], &[2]), &[1]);
+        let targets = targets_metadata(
+            3,
+            &[
+                ("firmware", FIRMWARE),
+                (USER_MANIFEST_TARGET_FILE_NAME, USER_MANIFEST),
+            ],
+        );
+        let bundle = update_bundle(
+            None,
+            &sign(&targets, &[2]),
+            &[
+                ("firmware", FIRMWARE),
+                (USER_MANIFEST_TARGET_FILE_NAME, USER_MANIFEST),
+            ],
+        );
+
+        let verifier = BundleVerifier::new(FakeCrypto, &trusted_root);
+        let verified = verifier.verify(&bundle, no_rotation).unwrap();
+        assert_eq!(verified.manifest().version(), Ok(3));
+        assert_eq!(verified.manifest().user_manifest(), Some(USER_MANIFEST));
+        assert_eq!(verified.target_payload("firmware"), Ok(Some(FIRMWARE)));
+        assert_eq!(verified.target_payload("bootloader"), Err(Error::NotFound));
+        assert_eq!(
+            verified.total_payload_size(),
+            Ok((FIRMWARE.len() + USER_MANIFEST.len()) as u64)
+        );
+    }
+
+    #[test]
+    fn rejects_targets_without_trusted_signatures() {
+        let trusted_root = sign(&root_metadata(1, &[1], &[2]), &[1]);
+        let targets = targets_metadata(1, &[("firmware", FIRMWARE)]);
+        let verifier = BundleVerifier::new(FakeCrypto, &trusted_root);
+
+        // Signed by the root key, which may not sign targets.
+        let bundle = update_bundle(None, &sign(&targets, &[1]), &[("firmware", FIRMWARE)]);
+        assert_eq!(
+            verifier.verify(&bundle, no_rotation),
+            Err(Error::Unauthenticated)
+        );
+
+        // Not signed.
+        let bundle = update_bundle(None, &sign(&targets, &[]), &[("firmware", FIRMWARE)]);
+        assert_eq!(
+            verifier.verify(&bundle, no_rotation),
+            Err(Error::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_payload() {
+        let trusted_root = sign(&root_metadata(1, &[1], &[2]), &[1]);
+        let targets = targets_metadata(1, &[("firmware", FIRMWARE)]);
+        let verifier = BundleVerifier::new(FakeCrypto, &trusted_root);
+
+        let bundle = update_bundle(
+            None,
+            &sign(&targets, &[2]),
+            &[("firmware", b"firmware imagf")],
+        );
+        assert_eq!(
+            verifier.verify(&bundle, no_rotation),
+            Err(Error::Unauthenticated)
+        );
+
+        let bundle = update_bundle(None, &sign(&targets, &[2]), &[("firmware", b"firmware")]);
+        assert_eq!(
+            verifier.verify(&bundle, no_rotation),
+            Err(Error::Unauthenticated)
+        );
+
+        let verifier = verifier.with_max_target_payload_size(4);
+        let bundle = update_bundle(None, &sign(&targets, &[2]), &[("firmware", FIRMWARE)]);
+        assert_eq!(
+            verifier.verify(&bundle, no_rotation),
+            Err(Error::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn rotates_root_signed_by_trusted_and_new_keys() {
+        let trusted_root = sign(&root_metadata(1, &[1], &[2]), &[1]);
+        let new_root = sign(&root_metadata(2, &[3], &[4]), &[1, 3]);
+        // Only the new root's targets key is trusted once the root rotates.
+        let targets = sign(&targets_metadata(1, &[("firmware", FIRMWARE)]), &[4]);
+        let bundle = update_bundle(Some(&new_root), &targets, &[("firmware", FIRMWARE)]);
+
+        let persisted = Cell::new(None);
+        let verifier = BundleVerifier::new(FakeCrypto, &trusted_root);
+        verifier
+            .verify(&bundle, |root| {
+                persisted.set(Some(root));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(persisted.get(), Some(&new_root[..]));
+
+        // The new root is not signed by its own key.
+        let new_root = sign(&root_metadata(2, &[3], &[4]), &[1]);
+        let bundle = update_bundle(Some(&new_root), &targets, &[("firmware", FIRMWARE)]);
+        assert_eq!(
+            verifier.verify(&bundle, no_rotation),
+            Err(Error::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn rejects_rollbacks() {
+        let trusted_root = sign(&root_metadata(2, &[1], &[2]), &[1]);
+        let old_root = sign(&root_metadata(1, &[1], &[2]), &[1]);
+        let targets = sign(&targets_metadata(4, &[("firmware", FIRMWARE)]), &[2]);
+        let bundle = update_bundle(Some(&old_root), &targets, &[("firmware", FIRMWARE)]);
+        let verifier = BundleVerifier::new(FakeCrypto, &trusted_root);
+        assert_eq!(
+            verifier.verify(&bundle, no_rotation),
+            Err(Error::Unauthenticated)
+        );
+
+        let device_manifest = encode(|encoder| {
+            Manifest::from_bundle(&UpdateBundle::new(&update_bundle(
+                None,
+                &sign(&targets_metadata(5, &[]), &[2]),
+                &[],
+            )))?
+            .encode(encoder)
+        });
+        let verifier = BundleVerifier::new(FakeCrypto, &trusted_root)
+            .with_device_manifest(Manifest::parse(&device_manifest).unwrap());
+        let bundle = update_bundle(None, &targets, &[("firmware", FIRMWARE)]);
+        assert_eq!(
+            verifier.verify(&bundle, no_rotation),
+            Err(Error::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn verifies_personalized_out_target_against_device_manifest() {
+        let trusted_root = sign(&root_metadata(1, &[1], &[2]), &[1]);
+        let targets = sign(&targets_metadata(2, &[("firmware", FIRMWARE)]), &[2]);
+        let applied = update_bundle(None, &targets, &[("firmware", FIRMWARE)]);
+        let verifier = BundleVerifier::new(FakeCrypto, &trusted_root);
+        let device_manifest = encode(|encoder| {
+            verifier
+                .verify(&applied, no_rotation)?
+                .manifest()
+                .encode(encoder)
+        });
+
+        let personalized = update_bundle(None, &targets, &[]);
+        assert_eq!(
+            verifier.verify(&personalized, no_rotation).map(|_| ()),
+            Err(Error::Unauthenticated)
+        );
+        let verifier = verifier.with_device_manifest(Manifest::parse(&device_manifest).unwrap());
+        let verified = verifier.verify(&personalized, no_rotation).unwrap();
+        assert_eq!(verified.target_payload("firmware"), Ok(None));
+        assert_eq!(verified.total_payload_size(), Ok(0));
+
+        let changed = sign(&targets_metadata(2, &[("firmware", b"other")]), &[2]);
+        assert_eq!(
+            verifier.verify(&update_bundle(None, &changed, &[]), no_rotation),
+            Err(Error::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn self_verification_accepts_unsigned_consistent_bundle() {
+        let verifier = BundleVerifier::for_self_verification(FakeCrypto);
+        let targets = sign(&targets_metadata(1, &[("firmware", FIRMWARE)]), &[]);
+        let bundle = update_bundle(None, &targets, &[("firmware", FIRMWARE)]);
+        assert!(verifier.verify(&bundle, no_rotation).is_ok());
+
+        let root = sign(&root_metadata(1, &[1], &[2]), &[1]);
+        let bundle = update_bundle(Some(&root), &targets, &[("firmware", FIRMWARE)]);
+        assert!(verifier.verify(&bundle, no_rotation).is_ok());
+
+        let bundle = update_bundle(Some(&root), &targets, &[("firmware", b"tampered")]);
+        assert_eq!(
+            verifier.verify(&bundle, no_rotation),
+            Err(Error::Unauthenticated)
+        );
+    }
+}
@@ -0,0 +1,725 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_spi` defines the interface between SPI bus drivers and the drivers of
+//! the devices on the bus.
+//!
+//! Bus drivers implement [`Initiator`], which configures the bus and
+//! performs full-duplex transfers, and [`ChipSelector`], which controls a
+//! device's chip-select signal.  Device drivers use a [`Device`], which
+//! combines a bus shared through a [`pw_sync::Mutex`], the device's bus
+//! [`Config`], and its chip selector.  Each transfer locks the bus,
+//! configures it for the device, and activates the device's chip select, so
+//! devices with different configurations can share one bus.
+//!
+//! A [`Transaction`] performs several transfers while the bus is locked, with
+//! the chip select held active for the whole transaction if required:
+//!
+//! ```
+//! use pw_spi::{ChipSelectBehavior, Config, Device};
+//! use pw_sync::Mutex;
+//! # use pw_status::Result;
+//! # struct Spi1;
+//! # impl pw_spi::Initiator for Spi1 {
+//! #     fn configure(&mut self, _config: &Config) -> Result<()> { Ok(()) }
+//! #     fn write_read(&mut self, _tx: &[u8], rx: &mut [u8]) -> Result<()> {
+//! #         rx.fill(0);
+//! #         Ok(())
+//! #     }
+//! # }
+//! # struct FlashCs;
+//! # impl pw_spi::ChipSelector for FlashCs {
+//! #     fn set_active(&mut self, _active: bool) -> Result<()> { Ok(()) }
+//! # }
+//!
+//! static SPI1: Mutex<Spi1> = Mutex::new(Spi1);
+//!
+//! const READ_DATA: u8 = 0x03;
+//!
+//! let mut flash = Device::new(&SPI1, Config::default(), FlashCs);
+//! let mut page = [0u8; 256];
+//! let mut transaction =
+//!     flash.start_transaction(ChipSelectBehavior::PerTransaction)?;
+//! transaction.write(&[READ_DATA, 0x00, 0x10, 0x00])?;
+//! transaction.read(&mut page)?;
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! Buses and pins which implement the `embedded-hal` traits can be used
+//! through an [`EmbeddedHalInitiator`] and an [`EmbeddedHalChipSelector`].
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_chrono::Duration;
+use pw_status::{Error, Result};
+use pw_sync::{Mutex, MutexGuard};
+
+/// The level of the clock signal when the bus is idle.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClockPolarity {
+    /// The clock is low when idle, which is CPOL = 0.
+    #[default]
+    ActiveHigh,
+    /// The clock is high when idle, which is CPOL = 1.
+    ActiveLow,
+}
+
+/// The clock edge on which data is sampled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClockPhase {
+    /// Data is sampled on the leading edge, which is CPHA = 0.
+    #[default]
+    RisingEdge,
+    /// Data is sampled on the trailing edge, which is CPHA = 1.
+    FallingEdge,
+}
+
+/// The order in which the bits of each word are transferred.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Least significant bit first.
+    LsbFirst,
+    /// Most significant bit first.
+    #[default]
+    MsbFirst,
+}
+
+/// The bus settings a device requires.
+///
+/// The default is SPI mode 0 with 8-bit words, most significant bit first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Clock polarity.
+    pub polarity: ClockPolarity,
+    /// Clock phase.
+    pub phase: ClockPhase,
+    /// Number of bits in each word, from 3 to 32.  Words of more than 8 bits
+    /// are transferred in as few bytes as hold them.
+    pub bits_per_word: u8,
+    /// Bit order.
+    pub bit_order: BitOrder,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            polarity: ClockPolarity::ActiveHigh,
+            phase: ClockPhase::RisingEdge,
+            bits_per_word: 8,
+            bit_order: BitOrder::MsbFirst,
+        }
+    }
+}
+
+/// A SPI bus driver, which other documentation may call a controller.
+pub trait Initiator {
+    /// Applies `config` to the bus for the following transfers.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - The bus does not support `config`.
+    fn configure(&mut self, config: &Config) -> Result<()>;
+
+    /// Performs a full-duplex transfer, writing `tx` to the bus while filling
+    /// `rx` with the data received.
+    ///
+    /// If `rx` is shorter than `tx` the remaining data received is discarded,
+    /// and if `tx` is shorter than `rx` zeros are written for the rest of the
+    /// transfer.
+    fn write_read(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()>;
+}
+
+impl<I: Initiator + ?Sized> Initiator for &mut I {
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        (**self).configure(config)
+    }
+
+    fn write_read(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+        (**self).write_read(tx, rx)
+    }
+}
+
+/// Controls the chip-select signal of one device.
+///
+/// Active does not imply a logic level; implementations map active and
+/// inactive to the levels the device requires.
+pub trait ChipSelector {
+    /// Activates the chip-select signal if `active` is `true` and deactivates
+    /// it otherwise.
+    fn set_active(&mut self, active: bool) -> Result<()>;
+
+    /// Activates the chip-select signal.
+    fn activate(&mut self) -> Result<()> {
+        self.set_active(true)
+    }
+
+    /// Deactivates the chip-select signal.
+    fn deactivate(&mut self) -> Result<()> {
+        self.set_active(false)
+    }
+}
+
+impl<C: ChipSelector + ?Sized> ChipSelector for &mut C {
+    fn set_active(&mut self, active: bool) -> Result<()> {
+        (**self).set_active(active)
+    }
+}
+
+/// When the chip-select signal of a [`Transaction`] is active.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChipSelectBehavior {
+    /// The chip select is activated for each transfer.
+    PerWriteRead,
+    /// The chip select is active from the start to the end of the
+    /// transaction.
+    PerTransaction,
+}
+
+/// A device on a SPI bus which may be shared with other devices.
+///
+/// The bus is borrowed from a [`pw_sync::Mutex`] which is locked for each
+/// transfer or [`Transaction`], so that transfers to different devices are
+/// not interleaved.
+pub struct Device<'a, I: Initiator, C: ChipSelector> {
+    bus: &'a Mutex<I>,
+    config: Config,
+    selector: C,
+}
+
+impl<'a, I: Initiator, C: ChipSelector> Device<'a, I, C> {
+    /// Creates a device on `bus` with the bus settings `config`, selected by
+    /// `selector`.
+    pub const fn new(bus: &'a Mutex<I>, config: Config, selector: C) -> Self {
+        Self {
+            bus,
+            config,
+            selector,
+        }
+    }
+
+    /// Returns the device's bus settings.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Fills `rx` from the device.  See [`Initiator::write_read()`].
+    pub fn read(&mut self, rx: &mut [u8]) -> Result<()> {
+        self.write_read(&[], rx)
+    }
+
+    /// Writes `tx` to the device.  See [`Initiator::write_read()`].
+    pub fn write(&mut self, tx: &[u8]) -> Result<()> {
+        self.write_read(tx, &mut [])
+    }
+
+    /// Writes `tx` to the device while filling `rx`, blocking until the bus
+    /// is available.  See [`Initiator::write_read()`].
+    pub fn write_read(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+        self.start_transaction(ChipSelectBehavior::PerWriteRead)?
+            .write_read(tx, rx)
+    }
+
+    /// Locks the bus, blocking until it is available, and configures it for
+    /// the device.  The bus is unlocked when the transaction is dropped.
+    pub fn start_transaction(
+        &mut self,
+        behavior: ChipSelectBehavior,
+    ) -> Result<Transaction<'_, I, C>> {
+        Transaction::start(self.bus.lock(), &self.config, &mut self.selector, behavior)
+    }
+
+    /// Starts a transaction like [`Device::start_transaction()`], blocking
+    /// for at most `timeout` until the bus is available.
+    ///
+    /// # Errors
+    /// - [`Error::DeadlineExceeded`] - The bus was not available in time.
+    pub fn try_start_transaction_for(
+        &mut self,
+        behavior: ChipSelectBehavior,
+        timeout: Duration,
+    ) -> Result<Transaction<'_, I, C>> {
+        let bus = self
+            .bus
+            .try_lock_for(timeout)
+            .ok_or(Error::DeadlineExceeded)?;
+        Transaction::start(bus, &self.config, &mut self.selector, behavior)
+    }
+}
+
+/// Exclusive access to a [`Device`]'s bus for a sequence of transfers.
+///
+/// With [`ChipSelectBehavior::PerTransaction`] the chip select is activated
+/// when the transaction starts and deactivated when it is dropped.
+#[must_use = "the bus is unlocked when the transaction is dropped"]
+pub struct Transaction<'a, I: Initiator, C: ChipSelector> {
+    bus: MutexGuard<'a, I>,
+    selector: &'a mut C,
+    behavior: ChipSelectBehavior,
+}
+
+impl<'a, I: Initiator, C: ChipSelector> Transaction<'a, I, C> {
+    fn start(
+        mut bus: MutexGuard<'a, I>,
+        config: &Config,
+        selector: &'a mut C,
+        behavior: ChipSelectBehavior,
+    ) -> Result<Self> {
+        bus.configure(config)?;
+        if behavior == ChipSelectBehavior::PerTransaction {
+            selector.activate()?;
+        }
+        Ok(Self {
+            bus,
+            selector,
+            behavior,
+        })
+    }
+
+    /// Fills `rx` from the device.  See [`Initiator::write_read()`].
+    pub fn read(&mut self, rx: &mut [u8]) -> Result<()> {
+        self.write_read(&[], rx)
+    }
+
+    /// Writes `tx` to the device.  See [`Initiator::write_read()`].
+    pub fn write(&mut self, tx: &[u8]) -> Result<()> {
+        self.write_read(tx, &mut [])
+    }
+
+    /// Writes `tx` to the device while filling `rx`.  See
+    /// [`Initiator::write_read()`].
+    pub fn write_read(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+        if self.behavior == ChipSelectBehavior::PerTransaction {
+            return self.bus.write_read(tx, rx);
+        }
+        self.selector.activate()?;
+        let result = self.bus.write_read(tx, rx);
+        // The chip select is deactivated even if the transfer failed.
+        let deactivated = self.selector.deactivate();
+        result.and(deactivated)
+    }
+}
+
+impl<I: Initiator, C: ChipSelector> Drop for Transaction<'_, I, C> {
+    fn drop(&mut self) {
+        if self.behavior == ChipSelectBehavior::PerTransaction {
+            // There is no way to report an error from `drop()`.
+            let _ = self.selector.deactivate();
+        }
+    }
+}
+
+/// Adapts a bus driver which implements [`embedded_hal::spi::SpiBus`] to
+/// [`Initiator`].
+///
+/// `embedded-hal` buses are configured when they are created, so
+/// [`Initiator::configure()`] only accepts the configuration the bus was
+/// created with.  When `tx` is shorter than `rx`, the rest of the transfer
+/// writes the bus driver's fill word, which is not necessarily zero.
+///
+/// Bus errors are converted to [`Error`]s:
+/// - An overrun is [`Error::ResourceExhausted`].
+/// - A mode fault is [`Error::FailedPrecondition`].
+/// - A frame format error is [`Error::DataLoss`].
+/// - A chip-select fault is [`Error::Internal`].
+/// - Other errors are [`Error::Unknown`].
+pub struct EmbeddedHalInitiator<T: embedded_hal::spi::SpiBus> {
+    bus: T,
+    config: Config,
+}
+
+impl<T: embedded_hal::spi::SpiBus> EmbeddedHalInitiator<T> {
+    /// Creates an initiator which transfers data with `bus`, which was
+    /// created with the settings `config`.
+    pub const fn new(bus: T, config: Config) -> Self {
+        Self { bus, config }
+    }
+
+    /// Returns a reference to the bus driver.
+    pub fn get_ref(&self) -> &T {
+        &self.bus
+    }
+
+    /// Returns a mutable reference to the bus driver.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.bus
+    }
+
+    /// Consumes the `EmbeddedHalInitiator` and returns the bus driver.
+    pub fn into_inner(self) -> T {
+        self.bus
+    }
+}
+
+fn error_from_embedded_hal(error: impl embedded_hal::spi::Error) -> Error {
+    use embedded_hal::spi::ErrorKind;
+    match error.kind() {
+        ErrorKind::Overrun => Error::ResourceExhausted,
+        ErrorKind::ModeFault => Error::FailedPrecondition,
+        ErrorKind::FrameFormat => Error::DataLoss,
+        ErrorKind::ChipSelectFault => Error::Internal,
+        _ => Error::Unknown,
+    }
+}
+
+impl<T: embedded_hal::spi::SpiBus> Initiator for EmbeddedHalInitiator<T> {
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        if *config != self.config {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    fn write_read(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+        self.bus.transfer(rx, tx).map_err(error_from_embedded_hal)?;
+        self.bus.flush().map_err(error_from_embedded_hal)
+    }
+}
+
+/// Adapts an [`embedded_hal::digital::OutputPin`] to [`ChipSelector`].
+pub struct EmbeddedHalChipSelector<P: embedded_hal::digital::OutputPin> {
+    pin: P,
+    active_high: bool,
+}
+
+impl<P: embedded_hal::digital::OutputPin> EmbeddedHalChipSelector<P> {
+    /// Creates a chip selector which drives `pin` low when active, as most
+    /// devices require.
+    pub const fn new(pin: P) -> Self {
+        Self {
+            pin,
+            active_high: false,
+        }
+    }
+
+    /// Creates a chip selector which drives `pin` high when active.
+    pub const fn active_high(pin: P) -> Self {
+        Self {
+            pin,
+            active_high: true,
+        }
+    }
+
+    /// Consumes the `EmbeddedHalChipSelector` and returns the pin.
+    pub fn into_inner(self) -> P {
+        self.pin
+    }
+}
+
+impl<P: embedded_hal::digital::OutputPin> ChipSelector for EmbeddedHalChipSelector<P> {
+    fn set_active(&mut self, active: bool) -> Result<()> {
+        let result = if active == self.active_high {
+            self.pin.set_high()
+        } else {
+            self.pin.set_low()
+        };
+        result.map_err(|_| Error::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::cell::RefCell;
+    use std::string::String;
+    use std::vec::Vec;
+
+    use super::*;
+
+    // A bus which records configuration and transfers to `log`, and responds
+    // to each transfer with an incrementing byte.
+    struct FakeBus<'a> {
+        log: &'a RefCell<Vec<String>>,
+    }
+
+    impl Initiator for FakeBus<'_> {
+        fn configure(&mut self, config: &Config) -> Result<()> {
+            if !(3..=32).contains(&config.bits_per_word) {
+                return Err(Error::InvalidArgument);
+            }
+            self.log
+                .borrow_mut()
+                .push(std::format!("configure {}", config.bits_per_word));
+            Ok(())
+        }
+
+        fn write_read(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<()> {
+            for (i, byte) in rx.iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+            self.log
+                .borrow_mut()
+                .push(std::format!("transfer {tx:?} {}", rx.len()));
+            Ok(())
+        }
+    }
+
+    struct FakeSelector<'a> {
+        name: &'static str,
+        log: &'a RefCell<Vec<String>>,
+    }
+
+    impl ChipSelector for FakeSelector<'_> {
+        fn set_active(&mut self, active: bool) -> Result<()> {
+            self.log
+                .borrow_mut()
+                .push(std::format!("{} {active}", self.name));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn each_transfer_selects_device() {
+        let log = RefCell::new(Vec::new());
+        let bus = Mutex::new(FakeBus { log: &log });
+        let mut device = Device::new(
+            &bus,
+            Config::default(),
+            FakeSelector {
+                name: "cs",
+                log: &log,
+            },
+        );
+        let mut rx = [0u8; 2];
+        device.write_read(&[1], &mut rx).unwrap();
+        assert_eq!(rx, [0, 1]);
+        assert_eq!(
+            log.take(),
+            ["configure 8", "cs true", "transfer [1] 2", "cs false"]
+        );
+    }
+
+    #[test]
+    fn transaction_holds_chip_select() {
+        let log = RefCell::new(Vec::new());
+        let bus = Mutex::new(FakeBus { log: &log });
+        let mut device = Device::new(
+            &bus,
+            Config::default(),
+            FakeSelector {
+                name: "cs",
+                log: &log,
+            },
+        );
+        {
+            let mut transaction = device
+                .start_transaction(ChipSelectBehavior::PerTransaction)
+                .unwrap();
+            transaction.write(&[3]).unwrap();
+            transaction.read(&mut [0; 4]).unwrap();
+        }
+        assert_eq!(
+            log.take(),
+            [
+                "configure 8",
+                "cs true",
+                "transfer [3] 0",
+                "transfer [] 4",
+                "cs false"
+            ]
+        );
+    }
+
+    #[test]
+    fn devices_share_bus_with_own_config() {
+        let log = RefCell::new(Vec::new());
+        let bus = Mutex::new(FakeBus { log: &log });
+        let mut flash = Device::new(
+            &bus,
+            Config::default(),
+            FakeSelector {
+                name: "flash",
+                log: &log,
+            },
+        );
+        let mut adc = Device::new(
+            &bus,
+            Config {
+                bits_per_word: 12,
+                ..Config::default()
+            },
+            FakeSelector {
+                name: "adc",
+                log: &log,
+            },
+        );
+        flash.write(&[1]).unwrap();
+        adc.write(&[2]).unwrap();
+        assert_eq!(
+            log.take(),
+            [
+                "configure 8",
+                "flash true",
+                "transfer [1] 0",
+                "flash false",
+                "configure 12",
+                "adc true",
+                "transfer [2] 0",
+                "adc false"
+            ]
+        );
+
+        let mut invalid = Device::new(
+            &bus,
+            Config {
+                bits_per_word: 40,
+                ..Config::default()
+            },
+            FakeSelector {
+                name: "x",
+                log: &log,
+            },
+        );
+        assert_eq!(invalid.write(&[1]), Err(Error::InvalidArgument));
+        assert!(log.take().is_empty());
+    }
+
+    #[test]
+    fn busy_bus_times_out() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        struct TestClock(AtomicU64);
+
+        impl pw_chrono::SystemClock for TestClock {
+            fn ticks_per_second(&self) -> u64 {
+                1000
+            }
+
+            fn now_ticks(&self) -> u64 {
+                self.0.fetch_add(1, Ordering::Relaxed)
+            }
+        }
+
+        static CLOCK: TestClock = TestClock(AtomicU64::new(0));
+        pw_chrono::set_system_clock(&CLOCK);
+
+        let log = RefCell::new(Vec::new());
+        let bus = Mutex::new(FakeBus { log: &log });
+        let mut device = Device::new(
+            &bus,
+            Config::default(),
+            FakeSelector {
+                name: "cs",
+                log: &log,
+            },
+        );
+        let _locked = bus.lock();
+        assert!(matches!(
+            device.try_start_transaction_for(
+                ChipSelectBehavior::PerWriteRead,
+                Duration::from_millis(5)
+            ),
+            Err(Error::DeadlineExceeded)
+        ));
+    }
+
+    // An `embedded-hal` bus which echoes the data written, and a pin which
+    // records its level.
+    #[derive(Default)]
+    struct HalBus {
+        flushed: bool,
+    }
+
+    impl embedded_hal::spi::ErrorType for HalBus {
+        type Error = embedded_hal::spi::ErrorKind;
+    }
+
+    impl embedded_hal::spi::SpiBus for HalBus {
+        fn read(&mut self, words: &mut [u8]) -> core::result::Result<(), Self::Error> {
+            words.fill(0xff);
+            Ok(())
+        }
+
+        fn write(&mut self, _words: &[u8]) -> core::result::Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn transfer(
+            &mut self,
+            read: &mut [u8],
+            write: &[u8],
+        ) -> core::result::Result<(), Self::Error> {
+            if write.len() > 4 {
+                return Err(embedded_hal::spi::ErrorKind::Overrun);
+            }
+            for (i, byte) in read.iter_mut().enumerate() {
+                *byte = write.get(i).copied().unwrap_or(0xff);
+            }
+            Ok(())
+        }
+
+        fn transfer_in_place(
+            &mut self,
+            _words: &mut [u8],
+        ) -> core::result::Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> core::result::Result<(), Self::Error> {
+            self.flushed = true;
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct HalPin {
+        high: bool,
+    }
+
+    impl embedded_hal::digital::ErrorType for HalPin {
+        type Error = core::convert::Infallible;
+    }
+
+    impl embedded_hal::digital::OutputPin for HalPin {
+        fn set_low(&mut self) -> core::result::Result<(), Self::Error> {
+            self.high = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> core::result::Result<(), Self::Error> {
+            self.high = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn embedded_hal_bus_and_pin_are_adapted() {
+        let mut initiator = EmbeddedHalInitiator::new(HalBus::default(), Config::default());
+        assert_eq!(initiator.configure(&Config::default()), Ok(()));
+        assert_eq!(
+            initiator.configure(&Config {
+                phase: ClockPhase::FallingEdge,
+                ..Config::default()
+            }),
+            Err(Error::InvalidArgument)
+        );
+
+        let mut rx = [0u8; 3];
+        initiator.write_read(&[1, 2], &mut rx).unwrap();
+        assert_eq!(rx, [1, 2, 0xff]);
+        assert!(initiator.get_ref().flushed);
+        assert_eq!(
+            initiator.write_read(&[0; 5], &mut []),
+            Err(Error::ResourceExhausted)
+        );
+
+        let mut selector = EmbeddedHalChipSelector::new(HalPin { high: true });
+        selector.activate().unwrap();
+        assert!(!selector.pin.high);
+        selector.deactivate().unwrap();
+        assert!(selector.pin.high);
+
+        let mut selector = EmbeddedHalChipSelector::active_high(HalPin::default());
+        selector.activate().unwrap();
+        assert!(selector.into_inner().high);
+    }
+}
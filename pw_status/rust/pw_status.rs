@@ -51,6 +51,9 @@ pub const OK: u32 = 0;
 ///
 /// For an in depth explanation of the values of the `Error` enum, see
 /// the [Pigweed status codes documentation](https://pigweed.dev/pw_status/#status-codes).
+///
+/// Each value is the same as the corresponding C `pw_Status` value.
+#[repr(i32)]
 pub enum Error {
     Cancelled = 1,
     Unknown = 2,
@@ -87,6 +90,516 @@ impl<T> StatusCode for Result<T> {
     }
 }
 
+impl Error {
+    /// Returns the error's name in the C++ API, such as `NOT_FOUND`.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Error::Cancelled => "CANCELLED",
+            Error::Unknown => "UNKNOWN",
+            Error::InvalidArgument => "INVALID_ARGUMENT",
+            Error::DeadlineExceeded => "DEADLINE_EXCEEDED",
+            Error::NotFound => "NOT_FOUND",
+            Error::AlreadyExists => "ALREADY_EXISTS",
+            Error::PermissionDenied => "PERMISSION_DENIED",
+            Error::ResourceExhausted => "RESOURCE_EXHAUSTED",
+            Error::FailedPrecondition => "FAILED_PRECONDITION",
+            Error::Aborted => "ABORTED",
+            Error::OutOfRange => "OUT_OF_RANGE",
+            Error::Unimplemented => "UNIMPLEMENTED",
+            Error::Internal => "INTERNAL",
+            Error::Unavailable => "UNAVAILABLE",
+            Error::DataLoss => "DATA_LOSS",
+            Error::Unauthenticated => "UNAUTHENTICATED",
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for Error {
+    /// Formats the error as its name in the C++ API, such as `NOT_FOUND`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// Converts a [`std::io::Error`] to the closest matching status.
+///
+/// | [`std::io::ErrorKind`]                                   | [`Error`]              |
+/// |----------------------------------------------------------|------------------------|
+/// | `NotFound`, `AddrNotAvailable`                           | `NotFound`             |
+/// | `PermissionDenied`                                       | `PermissionDenied`     |
+/// | `AlreadyExists`, `AddrInUse`                             | `AlreadyExists`        |
+/// | `WouldBlock`                                             | `Unavailable`          |
+/// | `InvalidInput`                                           | `InvalidArgument`      |
+/// | `InvalidData`                                            | `DataLoss`             |
+/// | `TimedOut`                                               | `DeadlineExceeded`     |
+/// | `Interrupted`                                            | `Aborted`              |
+/// | `UnexpectedEof`, `WriteZero`                             | `OutOfRange`           |
+/// | `Unsupported`                                            | `Unimplemented`        |
+/// | `OutOfMemory`                                            | `ResourceExhausted`    |
+/// | `ConnectionRefused`, `ConnectionReset`,                  | `FailedPrecondition`   |
+/// | `ConnectionAborted`, `NotConnected`, `BrokenPipe`        |                        |
+/// | Others                                                   | `Unknown`              |
+///
+/// Errors created from an [`Error`] convert back to the original [`Error`].
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        if let Some(status) = error.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            return *status;
+        }
+        match error.kind() {
+            ErrorKind::NotFound | ErrorKind::AddrNotAvailable => Error::NotFound,
+            ErrorKind::PermissionDenied => Error::PermissionDenied,
+            ErrorKind::AlreadyExists | ErrorKind::AddrInUse => Error::AlreadyExists,
+            ErrorKind::WouldBlock => Error::Unavailable,
+            ErrorKind::InvalidInput => Error::InvalidArgument,
+            ErrorKind::InvalidData => Error::DataLoss,
+            ErrorKind::TimedOut => Error::DeadlineExceeded,
+            ErrorKind::Interrupted => Error::Aborted,
+            ErrorKind::UnexpectedEof | ErrorKind::WriteZero => Error::OutOfRange,
+            ErrorKind::Unsupported => Error::Unimplemented,
+            ErrorKind::OutOfMemory => Error::ResourceExhausted,
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe => Error::FailedPrecondition,
+            _ => Error::Unknown,
+        }
+    }
+}
+
+/// Converts an [`Error`] to a [`std::io::Error`] of the closest matching
+/// kind which wraps the original [`Error`].
+///
+/// | [`Error`]                                        | [`std::io::ErrorKind`] |
+/// |--------------------------------------------------|------------------------|
+/// | `InvalidArgument`                                | `InvalidInput`         |
+/// | `DeadlineExceeded`                               | `TimedOut`             |
+/// | `NotFound`                                       | `NotFound`             |
+/// | `AlreadyExists`                                  | `AlreadyExists`        |
+/// | `PermissionDenied`, `Unauthenticated`            | `PermissionDenied`     |
+/// | `ResourceExhausted`                              | `OutOfMemory`          |
+/// | `Aborted`                                        | `Interrupted`          |
+/// | `OutOfRange`                                     | `UnexpectedEof`        |
+/// | `Unimplemented`                                  | `Unsupported`          |
+/// | `Unavailable`                                    | `WouldBlock`           |
+/// | `DataLoss`                                       | `InvalidData`          |
+/// | Others                                           | `Other`                |
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(error: Error) -> Self {
+        use std::io::ErrorKind;
+        let kind = match error {
+            Error::InvalidArgument => ErrorKind::InvalidInput,
+            Error::DeadlineExceeded => ErrorKind::TimedOut,
+            Error::NotFound => ErrorKind::NotFound,
+            Error::AlreadyExists => ErrorKind::AlreadyExists,
+            Error::PermissionDenied | Error::Unauthenticated => ErrorKind::PermissionDenied,
+            Error::ResourceExhausted => ErrorKind::OutOfMemory,
+            Error::Aborted => ErrorKind::Interrupted,
+            Error::OutOfRange => ErrorKind::UnexpectedEof,
+            Error::Unimplemented => ErrorKind::Unsupported,
+            Error::Unavailable => ErrorKind::WouldBlock,
+            Error::DataLoss => ErrorKind::InvalidData,
+            Error::Cancelled | Error::Unknown | Error::FailedPrecondition | Error::Internal => {
+                ErrorKind::Other
+            }
+        };
+        std::io::Error::new(kind, error)
+    }
+}
+
+// Returns the error with status `code`, which must not be `OK`.  Unknown
+// codes are reported as `Error::Unknown`.
+const fn error_from_code(code: u32) -> Error {
+    match code {
+        1 => Error::Cancelled,
+        3 => Error::InvalidArgument,
+        4 => Error::DeadlineExceeded,
+        5 => Error::NotFound,
+        6 => Error::AlreadyExists,
+        7 => Error::PermissionDenied,
+        8 => Error::ResourceExhausted,
+        9 => Error::FailedPrecondition,
+        10 => Error::Aborted,
+        11 => Error::OutOfRange,
+        12 => Error::Unimplemented,
+        13 => Error::Internal,
+        14 => Error::Unavailable,
+        15 => Error::DataLoss,
+        16 => Error::Unauthenticated,
+        _ => Error::Unknown,
+    }
+}
+
+/// The C `pw_Status` type, for use in FFI function signatures.
+#[allow(non_camel_case_types)]
+pub type pw_Status = core::ffi::c_int;
+
+/// Converts `result` to a C `pw_Status`.
+///
+/// ```
+/// use pw_status::{pw_Status, to_c_status, Error, Result};
+///
+/// fn reset() -> Result<()> {
+///     Err(Error::Unavailable)
+/// }
+///
+/// #[no_mangle]
+/// pub extern "C" fn my_module_Reset() -> pw_Status {
+///     to_c_status(&reset())
+/// }
+///
+/// assert_eq!(my_module_Reset(), 14);
+/// ```
+pub const fn to_c_status<T>(result: &Result<T>) -> pw_Status {
+    match result {
+        Ok(_) => OK as pw_Status,
+        Err(e) => *e as pw_Status,
+    }
+}
+
+/// Converts a C `pw_Status` to a [`Result`].
+///
+/// Values which are not valid status codes are converted to
+/// [`Error::Unknown`].
+pub const fn from_c_status(status: pw_Status) -> Result<()> {
+    match status {
+        0 => Ok(()),
+        1..=16 => Err(error_from_code(status as u32)),
+        _ => Err(Error::Unknown),
+    }
+}
+
+impl From<Error> for i32 {
+    fn from(error: Error) -> Self {
+        error as i32
+    }
+}
+
+/// A status and a size packed into a single `usize`, compatible with the C++
+/// [`pw::StatusWithSize`](https://pigweed.dev/pw_status/reference.html#_CPPv4N2pw14StatusWithSizeE).
+///
+/// The status code is stored in the upper 5 bits and the size in the
+/// remaining bits, so it can be returned across FFI boundaries with
+/// [`StatusWithSize::into_raw()`] and is cheaper to return than a
+/// `Result<usize>` on size critical paths.  Unlike a [`Result`], the size is
+/// present even when the status is an error, such as the number of bytes
+/// written before a write failed.
+///
+/// # Example
+///
+/// ```
+/// use pw_status::{Error, Result, StatusWithSize};
+///
+/// fn write(buffer: &mut [u8], data: &[u8]) -> StatusWithSize {
+///     let len = data.len().min(buffer.len());
+///     buffer[..len].copy_from_slice(&data[..len]);
+///     if len < data.len() {
+///         StatusWithSize::with_error(Error::ResourceExhausted, len)
+///     } else {
+///         StatusWithSize::new(len)
+///     }
+/// }
+///
+/// let mut buffer = [0u8; 4];
+/// let written = write(&mut buffer, b"hello");
+/// assert_eq!(written.size(), 4);
+/// assert_eq!(Result::<usize>::from(written), Err(Error::ResourceExhausted));
+/// assert_eq!(Result::<usize>::from(write(&mut buffer, b"hi")), Ok(2));
+/// ```
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct StatusWithSize(usize);
+
+impl StatusWithSize {
+    const STATUS_BITS: u32 = 5;
+    const STATUS_SHIFT: u32 = usize::BITS - Self::STATUS_BITS;
+
+    /// The largest size a `StatusWithSize` can hold.
+    pub const MAX_SIZE: usize = usize::MAX >> Self::STATUS_BITS;
+
+    /// Creates an OK `StatusWithSize` with `size`.
+    ///
+    /// Sizes larger than [`StatusWithSize::MAX_SIZE`] are clamped.
+    pub const fn new(size: usize) -> Self {
+        Self(Self::clamp(size))
+    }
+
+    /// Creates a `StatusWithSize` with `error` and `size`.
+    ///
+    /// Sizes larger than [`StatusWithSize::MAX_SIZE`] are clamped.
+    pub const fn with_error(error: Error, size: usize) -> Self {
+        Self(((error as usize) << Self::STATUS_SHIFT) | Self::clamp(size))
+    }
+
+    const fn clamp(size: usize) -> usize {
+        if size > Self::MAX_SIZE {
+            Self::MAX_SIZE
+        } else {
+            size
+        }
+    }
+
+    /// Creates a `StatusWithSize` from the raw value of a C++
+    /// `pw::StatusWithSize`.
+    pub const fn from_raw(raw: usize) -> Self {
+        Self(raw)
+    }
+
+    /// Returns the raw value, as stored by a C++ `pw::StatusWithSize`.
+    pub const fn into_raw(self) -> usize {
+        self.0
+    }
+
+    /// Returns the size.  The size is present even if the status is an error.
+    pub const fn size(self) -> usize {
+        self.0 & Self::MAX_SIZE
+    }
+
+    /// Returns `true` if the status is OK.
+    pub const fn ok(self) -> bool {
+        self.0 >> Self::STATUS_SHIFT == 0
+    }
+
+    /// Returns the status.
+    pub const fn status(self) -> Result<()> {
+        match (self.0 >> Self::STATUS_SHIFT) as u32 {
+            OK => Ok(()),
+            code => Err(error_from_code(code)),
+        }
+    }
+
+    /// Returns the sum of both sizes along with this status if it is an
+    /// error, or `other`'s status otherwise.
+    pub const fn update_and_add(self, other: StatusWithSize) -> Self {
+        let size = self.size().saturating_add(other.size());
+        match (self.status(), other.status()) {
+            (Err(error), _) | (Ok(()), Err(error)) => Self::with_error(error, size),
+            (Ok(()), Ok(())) => Self::new(size),
+        }
+    }
+
+    /// Returns this status with a size of zero if the status is an error.
+    pub const fn zero_if_not_ok(self) -> Self {
+        match self.status() {
+            Ok(()) => self,
+            Err(error) => Self::with_error(error, 0),
+        }
+    }
+}
+
+impl StatusCode for StatusWithSize {
+    fn status_code(self) -> u32 {
+        (self.0 >> Self::STATUS_SHIFT) as u32
+    }
+}
+
+impl From<StatusWithSize> for Result<usize> {
+    /// Returns the size if the status is OK.  The size of errors is dropped.
+    fn from(status: StatusWithSize) -> Self {
+        status.status().map(|()| status.size())
+    }
+}
+
+impl From<Result<usize>> for StatusWithSize {
+    /// Converts errors to a `StatusWithSize` with a size of zero.
+    fn from(result: Result<usize>) -> Self {
+        match result {
+            Ok(size) => Self::new(size),
+            Err(error) => Self::with_error(error, 0),
+        }
+    }
+}
+
+/// Describes what was being done when an error occurred.
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub enum Context {
+    /// The token of a string tokenized with `pw_tokenizer::token!()`, which
+    /// keeps the string out of the binary.  Host tools detokenize it.
+    Token(u32),
+    /// A string.
+    Str(&'static str),
+}
+
+impl From<&'static str> for Context {
+    fn from(context: &'static str) -> Self {
+        Self::Str(context)
+    }
+}
+
+/// An [`Error`] along with the context it occurred in and the error which
+/// caused it.
+///
+/// `ContextError` is `Copy` and does not allocate, so it may be returned
+/// from `no_std` code in place of a bare [`Error`].  It converts to its
+/// [`Error`], so `?` passes it to functions which return [`Result`].
+///
+/// # Example
+///
+/// ```
+/// use pw_status::{Context, ContextResult, Error, Result, ResultExt};
+///
+/// fn read_header(buffer: &[u8]) -> Result<u8> {
+///     buffer.first().copied().ok_or(Error::OutOfRange)
+/// }
+///
+/// fn parse(buffer: &[u8]) -> ContextResult<u8> {
+///     read_header(buffer).map_err_context(Error::DataLoss, "reading header")
+/// }
+///
+/// let error = parse(&[]).unwrap_err();
+/// assert_eq!(error.error(), Error::DataLoss);
+/// assert_eq!(error.context(), Some(Context::Str("reading header")));
+/// assert_eq!(error.source(), Some(Error::OutOfRange));
+/// ```
+#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct ContextError {
+    error: Error,
+    context: Option<Context>,
+    source: Option<Error>,
+}
+
+impl ContextError {
+    /// Creates a `ContextError` for `error` without context.
+    pub const fn new(error: Error) -> Self {
+        Self {
+            error,
+            context: None,
+            source: None,
+        }
+    }
+
+    /// Returns a copy of the error with `context`, unless it already has
+    /// context.
+    ///
+    /// The context closest to where the error occurred is kept since it is
+    /// the most specific.
+    pub fn with_context(self, context: impl Into<Context>) -> Self {
+        Self {
+            context: self.context.or(Some(context.into())),
+            ..self
+        }
+    }
+
+    /// Returns the error.
+    pub const fn error(&self) -> Error {
+        self.error
+    }
+
+    /// Returns the context the error occurred in, if any.
+    pub const fn context(&self) -> Option<Context> {
+        self.context
+    }
+
+    /// Returns the error which caused this one, if any.
+    pub const fn source(&self) -> Option<Error> {
+        self.source
+    }
+}
+
+impl From<Error> for ContextError {
+    fn from(error: Error) -> Self {
+        Self::new(error)
+    }
+}
+
+impl From<ContextError> for Error {
+    fn from(error: ContextError) -> Self {
+        error.error
+    }
+}
+
+/// A [`Result`] whose error carries context.
+pub type ContextResult<T> = core::result::Result<T, ContextError>;
+
+impl<T> StatusCode for ContextResult<T> {
+    fn status_code(self) -> u32 {
+        self.map_err(Error::from).status_code()
+    }
+}
+
+/// Attaches context to and inspects the errors of [`Result`]s and
+/// [`ContextResult`]s.
+pub trait ResultExt<T> {
+    /// The error type of the result.
+    type Error;
+
+    /// Attaches `context` to the error, unless it already has context.
+    fn context(self, context: impl Into<Context>) -> ContextResult<T>;
+
+    /// Replaces the error with `error`, keeping the original error as its
+    /// source, and attaches `context`.
+    ///
+    /// If the original error already has a source, that source is kept since
+    /// it is the root cause.
+    fn map_err_context(self, error: Error, context: impl Into<Context>) -> ContextResult<T>;
+
+    /// Returns the value, or panics with the status code if the result is an
+    /// error.
+    ///
+    /// Unlike `unwrap()`, this does not require [`Error`] to implement
+    /// `Debug`, which it only does with the `std` feature.
+    fn ok_or_assert(self) -> T;
+
+    /// Returns `Ok(None)` if the result is `error`, such as
+    /// [`Error::NotFound`] when looking up an optional value, and
+    /// `Ok(Some(value))` if it is OK.  Other errors are returned unchanged.
+    fn ok_if(self, error: Error) -> core::result::Result<Option<T>, Self::Error>;
+
+    /// Returns `true` if the result is `error`.
+    fn is_error(&self, error: Error) -> bool;
+}
+
+impl<T, E: Copy + Into<ContextError>> ResultExt<T> for core::result::Result<T, E> {
+    type Error = E;
+
+    fn context(self, context: impl Into<Context>) -> ContextResult<T> {
+        self.map_err(|e| e.into().with_context(context))
+    }
+
+    fn map_err_context(self, error: Error, context: impl Into<Context>) -> ContextResult<T> {
+        self.map_err(|e| {
+            let e = e.into();
+            ContextError {
+                error,
+                source: e.source.or(Some(e.error)),
+                ..e
+            }
+            .with_context(context)
+        })
+    }
+
+    #[track_caller]
+    fn ok_or_assert(self) -> T {
+        match self {
+            Ok(value) => value,
+            Err(e) => panic!("unexpected error status {}", e.into().error() as u32),
+        }
+    }
+
+    fn ok_if(self, error: Error) -> core::result::Result<Option<T>, E> {
+        match self {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.into().error() == error => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn is_error(&self, error: Error) -> bool {
+        matches!(self, Err(e) if (*e).into().error() == error)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,4 +626,170 @@ mod tests {
         assert_eq!(Result::<()>::Err(Error::DataLoss).status_code(), 15);
         assert_eq!(Result::<()>::Err(Error::Unauthenticated).status_code(), 16);
     }
+
+    #[test]
+    fn context_is_attached_to_error() {
+        let result: Result<()> = Err(Error::NotFound);
+        let error = result.context(Context::Token(0x1234)).unwrap_err();
+        assert!(error.error() == Error::NotFound);
+        assert!(error.context() == Some(Context::Token(0x1234)));
+        assert!(error.source().is_none());
+        assert!(Error::from(error) == Error::NotFound);
+        assert!(ContextResult::<()>::Err(error).status_code() == 5);
+    }
+
+    #[test]
+    fn innermost_context_and_root_source_are_kept() {
+        fn read() -> ContextResult<()> {
+            Err(Error::OutOfRange).map_err_context(Error::DataLoss, "decoding entry")
+        }
+        let error = read()
+            .map_err_context(Error::Internal, "loading config")
+            .context("starting")
+            .unwrap_err();
+        assert!(error.error() == Error::Internal);
+        assert!(error.context() == Some(Context::Str("decoding entry")));
+        assert!(error.source() == Some(Error::OutOfRange));
+    }
+
+    #[test]
+    fn context_error_converts_with_question_mark() {
+        fn inner() -> ContextResult<u32> {
+            Err(ContextError::new(Error::Unavailable).with_context("polling"))
+        }
+        fn outer() -> Result<u32> {
+            Ok(inner()? + 1)
+        }
+        assert!(outer() == Err(Error::Unavailable));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn io_errors_map_to_status() {
+        use std::io::{self, ErrorKind};
+        let error = |kind| Error::from(io::Error::from(kind));
+        assert_eq!(error(ErrorKind::WouldBlock), Error::Unavailable);
+        assert_eq!(error(ErrorKind::NotFound), Error::NotFound);
+        assert_eq!(error(ErrorKind::WriteZero), Error::OutOfRange);
+        assert_eq!(error(ErrorKind::BrokenPipe), Error::FailedPrecondition);
+        assert_eq!(error(ErrorKind::Other), Error::Unknown);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn status_converts_to_io_error_and_back() {
+        use std::io::{self, ErrorKind};
+        let error = io::Error::from(Error::Unavailable);
+        assert_eq!(error.kind(), ErrorKind::WouldBlock);
+        assert_eq!(error.to_string(), "UNAVAILABLE");
+
+        // Statuses without a matching kind survive the round trip.
+        let error = io::Error::from(Error::FailedPrecondition);
+        assert_eq!(error.kind(), ErrorKind::Other);
+        assert_eq!(Error::from(error), Error::FailedPrecondition);
+
+        fn read() -> io::Result<()> {
+            Err(Error::DataLoss)?
+        }
+        assert_eq!(read().map_err(Error::from), Err(Error::DataLoss));
+    }
+
+    #[test]
+    fn status_with_size_packs_status_and_size() {
+        let status = StatusWithSize::with_error(Error::DataLoss, 12);
+        assert!(!status.ok());
+        assert!(status.size() == 12);
+        assert!(status.status() == Err(Error::DataLoss));
+        assert!(status.status_code() == 15);
+        // Matches the layout of the C++ `pw::StatusWithSize`.
+        assert!(status.into_raw() == (15 << (usize::BITS - 5)) | 12);
+        assert!(StatusWithSize::from_raw(status.into_raw()) == status);
+
+        let status = StatusWithSize::new(usize::MAX);
+        assert!(status.ok());
+        assert!(status.size() == StatusWithSize::MAX_SIZE);
+        assert!(StatusWithSize::default() == StatusWithSize::new(0));
+    }
+
+    #[test]
+    fn status_with_size_converts_to_and_from_result() {
+        assert!(Result::<usize>::from(StatusWithSize::new(3)) == Ok(3));
+        assert!(
+            Result::<usize>::from(StatusWithSize::with_error(Error::Unavailable, 3))
+                == Err(Error::Unavailable)
+        );
+        assert!(StatusWithSize::from(Ok(7)) == StatusWithSize::new(7));
+        assert!(
+            StatusWithSize::from(Err(Error::NotFound))
+                == StatusWithSize::with_error(Error::NotFound, 0)
+        );
+    }
+
+    #[test]
+    fn status_with_size_update_and_add_keeps_first_error() {
+        let status = StatusWithSize::new(2)
+            .update_and_add(StatusWithSize::with_error(Error::OutOfRange, 3))
+            .update_and_add(StatusWithSize::with_error(Error::Internal, 4));
+        assert!(status == StatusWithSize::with_error(Error::OutOfRange, 9));
+        assert!(status.zero_if_not_ok() == StatusWithSize::with_error(Error::OutOfRange, 0));
+        assert!(StatusWithSize::new(1).zero_if_not_ok() == StatusWithSize::new(1));
+    }
+
+    #[test]
+    fn result_ext_helpers_match_errors() {
+        let found: Result<u32> = Ok(3);
+        let missing: Result<u32> = Err(Error::NotFound);
+        let failed: ContextResult<u32> = Err(Error::DataLoss).context("reading");
+
+        assert!(found.ok_if(Error::NotFound) == Ok(Some(3)));
+        assert!(missing.ok_if(Error::NotFound) == Ok(None));
+        assert!(failed.ok_if(Error::NotFound) == failed.map(Some));
+
+        assert!(missing.is_error(Error::NotFound));
+        assert!(!missing.is_error(Error::DataLoss));
+        assert!(failed.is_error(Error::DataLoss));
+        assert!(!found.is_error(Error::NotFound));
+
+        assert!(found.ok_or_assert() == 3);
+        assert!(Error::DataLoss.as_str() == "DATA_LOSS");
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected error status 14")]
+    fn ok_or_assert_panics_on_error() {
+        Result::<()>::Err(Error::Unavailable).ok_or_assert();
+    }
+
+    #[test]
+    fn c_status_values_match_pw_status_h() {
+        // From `pw_status/status.h`.
+        const C_STATUSES: [(pw_Status, Result<()>); 17] = [
+            (0, Ok(())),
+            (1, Err(Error::Cancelled)),
+            (2, Err(Error::Unknown)),
+            (3, Err(Error::InvalidArgument)),
+            (4, Err(Error::DeadlineExceeded)),
+            (5, Err(Error::NotFound)),
+            (6, Err(Error::AlreadyExists)),
+            (7, Err(Error::PermissionDenied)),
+            (8, Err(Error::ResourceExhausted)),
+            (9, Err(Error::FailedPrecondition)),
+            (10, Err(Error::Aborted)),
+            (11, Err(Error::OutOfRange)),
+            (12, Err(Error::Unimplemented)),
+            (13, Err(Error::Internal)),
+            (14, Err(Error::Unavailable)),
+            (15, Err(Error::DataLoss)),
+            (16, Err(Error::Unauthenticated)),
+        ];
+        for (status, result) in C_STATUSES {
+            assert!(from_c_status(status) == result);
+            assert!(to_c_status(&result) == status);
+            if let Err(e) = result {
+                assert!(i32::from(e) == status);
+            }
+        }
+        assert!(from_c_status(17) == Err(Error::Unknown));
+        assert!(from_c_status(-1) == Err(Error::Unknown));
+    }
 }
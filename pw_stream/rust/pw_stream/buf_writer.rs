@@ -0,0 +1,271 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use heapless::Vec;
+use pw_status::{Error, Result};
+
+use super::Write;
+
+/// A writer adapter which coalesces small writes into larger writes to an
+/// underlying writer.
+///
+/// Transports such as USB or radios often have a high per-transfer overhead.
+/// Producers like the tokenizer emit many writes of only a few bytes.
+/// `BufWriter` stores data in an `N` byte buffer and only writes to the inner
+/// writer once the buffer is full or [`Write::flush()`] is called.  Writes of
+/// `N` or more bytes bypass the buffer.
+///
+/// Unlike [`std::io::BufWriter`], buffered data is **not** flushed when a
+/// `BufWriter` is dropped.  Call [`Write::flush()`] or
+/// [`BufWriter::into_inner()`] to write out any remaining data.
+///
+/// # Example
+///
+/// ```
+/// use pw_stream::{BufWriter, Cursor, Write};
+///
+/// let mut writer = BufWriter::<8, _>::new(Cursor::new([0u8; 16]));
+/// writer.write_all(b"abc").unwrap();
+/// writer.write_all(b"def").unwrap();
+///
+/// // Nothing reaches the inner writer until the buffer is flushed.
+/// assert_eq!(writer.get_ref().position(), 0);
+///
+/// writer.flush().unwrap();
+/// assert_eq!(writer.get_ref().position(), 6);
+/// ```
+pub struct BufWriter<const N: usize, W: Write> {
+    inner: W,
+    buf: Vec<u8, N>,
+}
+
+impl<const N: usize, W: Write> BufWriter<N, W> {
+    /// Create a new `BufWriter` wrapping `inner`.
+    pub const fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    ///
+    /// Writing directly to the underlying writer may reorder data with
+    /// respect to data still held in the buffer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Returns the data currently held in the buffer.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Returns the size of the buffer.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Writes any buffered data to the underlying writer then returns it.
+    ///
+    /// # Errors
+    /// Returns any error encountered writing the buffered data.  In that case
+    /// the writer and any unwritten data are dropped.  Use
+    /// [`BufWriter::into_parts()`] to recover them instead.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush_buf()?;
+        Ok(self.inner)
+    }
+
+    /// Returns the underlying writer and any buffered data without writing
+    /// the data.
+    pub fn into_parts(self) -> (W, Vec<u8, N>) {
+        (self.inner, self.buf)
+    }
+
+    // Writes the contents of the buffer to the inner writer.  On error, any
+    // data not accepted by the inner writer is retained in the buffer.
+    fn flush_buf(&mut self) -> Result<()> {
+        let mut written = 0;
+        let result = loop {
+            if written == self.buf.len() {
+                break Ok(());
+            }
+            match self.inner.write(&self.buf[written..]) {
+                Ok(0) => break Err(Error::OutOfRange),
+                Ok(len) => written += len,
+                Err(e) => break Err(e),
+            }
+        };
+
+        let len = self.buf.len();
+        self.buf.copy_within(written..len, 0);
+        self.buf.truncate(len - written);
+        result
+    }
+
+    // Appends `data` to the buffer.  Callers must ensure it fits.
+    fn buffer_data(&mut self, data: &[u8]) -> Result<()> {
+        self.buf
+            .extend_from_slice(data)
+            .map_err(|_| Error::Internal)
+    }
+}
+
+impl<const N: usize, W: Write> Write for BufWriter<N, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.buf.len() + buf.len() > N {
+            self.flush_buf()?;
+        }
+
+        if buf.len() >= N {
+            self.inner.write(buf)
+        } else {
+            self.buffer_data(buf)?;
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.flush_buf()?;
+        self.inner.flush()
+    }
+
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<usize> {
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        if self.buf.len() + total > N {
+            self.flush_buf()?;
+        }
+
+        if total >= N {
+            self.inner.write_vectored(bufs)
+        } else {
+            for buf in bufs {
+                self.buffer_data(buf)?;
+            }
+            Ok(total)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cursor, VecWriter};
+
+    // Records the size of each write.
+    struct WriteRecorder {
+        data: VecWriter<64>,
+        writes: Vec<usize, 16>,
+        flushes: usize,
+    }
+
+    impl WriteRecorder {
+        fn new() -> Self {
+            Self {
+                data: VecWriter::new(),
+                writes: Vec::new(),
+                flushes: 0,
+            }
+        }
+    }
+
+    impl Write for WriteRecorder {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.writes.push(buf.len()).unwrap();
+            self.data.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn small_writes_are_coalesced() {
+        let mut writer = BufWriter::<8, _>::new(WriteRecorder::new());
+        for byte in b"abcdefghij" {
+            assert_eq!(writer.write(&[*byte]), Ok(1));
+        }
+        assert_eq!(&writer.get_ref().writes[..], &[8]);
+        assert_eq!(writer.buffer(), b"ij");
+
+        writer.flush().unwrap();
+        let recorder = writer.into_inner().unwrap();
+        assert_eq!(&recorder.writes[..], &[8, 2]);
+        assert_eq!(recorder.flushes, 1);
+        assert_eq!(recorder.data.as_slice(), b"abcdefghij");
+    }
+
+    #[test]
+    fn large_writes_bypass_buffer() {
+        let mut writer = BufWriter::<4, _>::new(WriteRecorder::new());
+        writer.write_all(b"ab").unwrap();
+        writer.write_all(b"cdefgh").unwrap();
+        assert!(writer.buffer().is_empty());
+
+        let recorder = writer.into_inner().unwrap();
+        assert_eq!(&recorder.writes[..], &[2, 6]);
+        assert_eq!(recorder.data.as_slice(), b"abcdefgh");
+    }
+
+    #[test]
+    fn into_inner_flushes_buffer() {
+        let mut writer = BufWriter::<8, _>::new(WriteRecorder::new());
+        writer.write_all(b"abc").unwrap();
+        let recorder = writer.into_inner().unwrap();
+        assert_eq!(recorder.data.as_slice(), b"abc");
+    }
+
+    #[test]
+    fn into_parts_returns_buffered_data() {
+        let mut writer = BufWriter::<8, _>::new(WriteRecorder::new());
+        writer.write_all(b"abc").unwrap();
+        let (recorder, buf) = writer.into_parts();
+        assert!(recorder.writes.is_empty());
+        assert_eq!(&buf[..], b"abc");
+    }
+
+    #[test]
+    fn failed_flush_retains_unwritten_data() {
+        let mut writer = BufWriter::<4, _>::new(Cursor::new([0u8; 3]));
+        writer.write_all(b"ab").unwrap();
+        writer.write_all(b"cd").unwrap();
+        assert_eq!(writer.flush(), Err(Error::OutOfRange));
+        assert_eq!(writer.buffer(), b"d");
+        assert_eq!(writer.get_ref().position(), 3);
+
+        let (cursor, _) = writer.into_parts();
+        assert_eq!(&cursor.into_inner(), b"abc");
+    }
+
+    #[test]
+    fn vectored_writes_are_coalesced() {
+        let mut writer = BufWriter::<8, _>::new(WriteRecorder::new());
+        assert_eq!(writer.write_vectored(&[b"ab", b"", b"cd"]), Ok(4));
+        assert_eq!(writer.write_vectored(&[b"efg"]), Ok(3));
+        assert!(writer.get_ref().writes.is_empty());
+
+        assert_eq!(writer.write_vectored(&[b"hi", b"jk"]), Ok(4));
+        let recorder = writer.into_inner().unwrap();
+        assert_eq!(&recorder.writes[..], &[7, 4]);
+        assert_eq!(recorder.data.as_slice(), b"abcdefghijk");
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_checksum::Checksum;
+use pw_status::Result;
+
+use super::Write;
+
+/// A writer adapter which computes a checksum of all data written through it.
+///
+/// Only data accepted by the inner writer is included in the checksum.  This
+/// allows framing protocols to compute a frame check sequence while writing
+/// a frame instead of hashing the buffer a second time.
+///
+/// # Example
+///
+/// ```
+/// use pw_checksum::Crc32Ieee;
+/// use pw_stream::{CrcWriter, Cursor, Write};
+///
+/// let mut writer = CrcWriter::new(Cursor::new([0u8; 16]), Crc32Ieee::new());
+/// writer.write_all(b"12345").unwrap();
+/// writer.write_all(b"6789").unwrap();
+/// assert_eq!(writer.checksum(), 0xcbf4_3926);
+/// ```
+pub struct CrcWriter<W: Write, C: Checksum> {
+    inner: W,
+    checksum: C,
+}
+
+impl<W: Write, C: Checksum> CrcWriter<W, C> {
+    /// Create a new `CrcWriter` which writes to `inner` and updates
+    /// `checksum`.
+    pub const fn new(inner: W, checksum: C) -> Self {
+        Self { inner, checksum }
+    }
+
+    /// Returns the checksum of the data written so far.
+    pub fn checksum(&self) -> C::Output {
+        self.checksum.value()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    ///
+    /// Data written directly to the underlying writer is not included in the
+    /// checksum.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consumes the `CrcWriter` and returns the underlying writer and
+    /// checksum state.
+    pub fn into_parts(self) -> (W, C) {
+        (self.inner, self.checksum)
+    }
+}
+
+impl<W: Write, C: Checksum> Write for CrcWriter<W, C> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.checksum.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<usize> {
+        let len = self.inner.write_vectored(bufs)?;
+        let mut remaining = len;
+        for buf in bufs {
+            let consumed = remaining.min(buf.len());
+            self.checksum.update(&buf[..consumed]);
+            remaining -= consumed;
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_checksum::{Crc16Ccitt, Crc32Ieee};
+
+    use super::*;
+    use crate::Cursor;
+
+    const CHECK_DATA: &[u8] = b"123456789";
+
+    #[test]
+    fn crc_writer_checksums_written_data() {
+        let mut writer = CrcWriter::new(Cursor::new([0u8; 16]), Crc16Ccitt::new());
+        writer.write_all(&CHECK_DATA[..4]).unwrap();
+        writer.write_all(&CHECK_DATA[4..]).unwrap();
+        assert_eq!(writer.checksum(), 0x29b1);
+
+        let (cursor, _) = writer.into_parts();
+        assert_eq!(&cursor.into_inner()[..9], CHECK_DATA);
+    }
+
+    #[test]
+    fn crc_writer_only_checksums_accepted_data() {
+        let mut writer = CrcWriter::new(Cursor::new([0u8; 4]), Crc16Ccitt::new());
+        assert_eq!(writer.write(CHECK_DATA), Ok(4));
+        assert_eq!(writer.write(CHECK_DATA), Ok(0));
+        assert_eq!(writer.checksum(), Crc16Ccitt::calculate(&CHECK_DATA[..4]));
+    }
+
+    #[test]
+    fn crc_writer_checksums_vectored_writes() {
+        let mut writer = CrcWriter::new(Cursor::new([0u8; 6]), Crc32Ieee::new());
+        assert_eq!(writer.write_vectored(&[b"123", b"45", b"6789"]), Ok(6));
+        assert_eq!(writer.checksum(), Crc32Ieee::calculate(b"123456"));
+    }
+}
@@ -14,11 +14,10 @@
 
 use core::cmp::min;
 
-use paste::paste;
 use pw_status::{Error, Result};
 use pw_varint::{VarintDecode, VarintEncode};
 
-use super::{Read, Seek, SeekFrom, Write};
+use super::{Read, Seek, SeekFrom, TryRead, TryWrite, Write};
 
 /// Wraps an <code>[AsRef]<[u8]></code> in a container implementing
 /// [`Read`], [`Write`], and [`Seek`].
@@ -70,11 +69,16 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Cursor<T> {
     fn remaining_mut(&mut self) -> &mut [u8] {
         &mut self.inner.as_mut()[self.pos..]
     }
+
+    // Returns the `len` bytes at the current position.
+    fn writable_slice(&mut self, len: usize) -> Result<&mut [u8]> {
+        self.remaining_mut().get_mut(..len).ok_or(Error::OutOfRange)
+    }
 }
 
 // Implement `read()` as a concrete function to avoid extra monomorphization
 // overhead.
-fn read_impl(inner: &[u8], pos: &mut usize, buf: &mut [u8]) -> Result<usize> {
+pub(crate) fn read_impl(inner: &[u8], pos: &mut usize, buf: &mut [u8]) -> Result<usize> {
     let remaining = inner.len() - *pos;
     let read_len = min(remaining, buf.len());
     buf[..read_len].copy_from_slice(&inner[*pos..(*pos + read_len)]);
@@ -90,7 +94,7 @@ impl<T: AsRef<[u8]>> Read for Cursor<T> {
 
 // Implement `write()` as a concrete function to avoid extra monomorphization
 // overhead.
-fn write_impl(inner: &mut [u8], pos: &mut usize, buf: &[u8]) -> Result<usize> {
+pub(crate) fn write_impl(inner: &mut [u8], pos: &mut usize, buf: &[u8]) -> Result<usize> {
     let remaining = inner.len() - *pos;
     let write_len = min(remaining, buf.len());
     inner[*pos..(*pos + write_len)].copy_from_slice(&buf[0..write_len]);
@@ -107,6 +111,31 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Write for Cursor<T> {
         // Cursor does not provide any buffering so flush() is a noop.
         Ok(())
     }
+
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<usize> {
+        let mut total_len = 0;
+        for buf in bufs {
+            let len = write_impl(self.inner.as_mut(), &mut self.pos, buf)?;
+            total_len += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(total_len)
+    }
+}
+
+// Cursors operate on in memory buffers and never block.
+impl<T: AsRef<[u8]>> TryRead for Cursor<T> {
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        read_impl(self.inner.as_ref(), &mut self.pos, buf)
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> TryWrite for Cursor<T> {
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        write_impl(self.inner.as_mut(), &mut self.pos, buf)
+    }
 }
 
 impl<T: AsRef<[u8]>> Seek for Cursor<T> {
@@ -148,19 +177,17 @@ impl<T: AsRef<[u8]>> Seek for Cursor<T> {
     }
 }
 
+// The integer accessors are shared with `VecCursor`.  They read from
+// `remaining_slice()`, write to `writable_slice()`, and advance `pos`.
 macro_rules! cursor_read_type_impl {
     ($ty:ident, $endian:ident) => {
-        paste! {
+        ::paste::paste! {
           fn [<read_ $ty _ $endian>](&mut self) -> Result<$ty> {
             const NUM_BYTES: usize = $ty::BITS as usize / 8;
-            if NUM_BYTES > self.remaining() {
-                return Err(Error::OutOfRange);
-            }
             let sub_slice = self
-                .inner
-                .as_ref()
-                .get(self.pos..self.pos + NUM_BYTES)
-                .ok_or_else(|| Error::InvalidArgument)?;
+                .remaining_slice()
+                .get(..NUM_BYTES)
+                .ok_or(Error::OutOfRange)?;
             // Because we are code size conscious we want an infallible way to
             // turn `sub_slice` into a fixed sized array as opposed to using
             // something like `.try_into()?`.
@@ -179,7 +206,7 @@ macro_rules! cursor_read_type_impl {
 
 macro_rules! cursor_read_bits_impl {
     ($bits:literal) => {
-        paste! {
+        ::paste::paste! {
           cursor_read_type_impl!([<i $bits>], le);
           cursor_read_type_impl!([<u $bits>], le);
           cursor_read_type_impl!([<i $bits>], be);
@@ -190,20 +217,11 @@ macro_rules! cursor_read_bits_impl {
 
 macro_rules! cursor_write_type_impl {
     ($ty:ident, $endian:ident) => {
-        paste! {
+        ::paste::paste! {
           fn [<write_ $ty _ $endian>](&mut self, value: &$ty) -> Result<()> {
             const NUM_BYTES: usize = $ty::BITS as usize / 8;
-            if NUM_BYTES > self.remaining() {
-                return Err(Error::OutOfRange);
-            }
             let value_bytes = $ty::[<to_ $endian _bytes>](*value);
-            let sub_slice = self
-                .inner
-                .as_mut()
-                .get_mut(self.pos..self.pos + NUM_BYTES)
-                .ok_or_else(|| Error::InvalidArgument)?;
-
-            sub_slice.copy_from_slice(&value_bytes[..]);
+            self.writable_slice(NUM_BYTES)?.copy_from_slice(&value_bytes[..]);
 
             self.pos += NUM_BYTES;
             Ok(())
@@ -214,7 +232,7 @@ macro_rules! cursor_write_type_impl {
 
 macro_rules! cursor_write_bits_impl {
     ($bits:literal) => {
-        paste! {
+        ::paste::paste! {
           cursor_write_type_impl!([<i $bits>], le);
           cursor_write_type_impl!([<u $bits>], le);
           cursor_write_type_impl!([<i $bits>], be);
@@ -223,6 +241,11 @@ macro_rules! cursor_write_bits_impl {
     };
 }
 
+#[cfg(feature = "alloc")]
+pub(crate) use {
+    cursor_read_bits_impl, cursor_read_type_impl, cursor_write_bits_impl, cursor_write_type_impl,
+};
+
 impl<T: AsRef<[u8]>> crate::ReadInteger for Cursor<T> {
     cursor_read_bits_impl!(8);
     cursor_read_bits_impl!(16);
@@ -269,6 +292,8 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> crate::WriteVarint for Cursor<T> {
 
 #[cfg(test)]
 mod tests {
+    use paste::paste;
+
     use super::*;
     use crate::{test_utils::*, ReadInteger, ReadVarint, WriteInteger, WriteVarint};
 
@@ -321,6 +346,45 @@ mod tests {
         assert_eq!(cursor.inner, &[0, 0, 0, 0, 1, 2, 3, 4]);
     }
 
+    #[test]
+    fn cursor_write_vectored_writes_all_buffers() {
+        let mut cursor = Cursor::new([0u8; 8]);
+        assert_eq!(cursor.write_vectored(&[&[1, 2], &[], &[3, 4, 5]]), Ok(5));
+        assert_eq!(cursor.position(), 5);
+        assert_eq!(cursor.into_inner(), [1, 2, 3, 4, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn cursor_write_vectored_stops_when_full() {
+        let mut cursor = Cursor::new([0u8; 4]);
+        assert_eq!(cursor.write_vectored(&[&[1, 2], &[3, 4, 5], &[6]]), Ok(4));
+        assert_eq!(cursor.into_inner(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn cursor_try_read_of_partial_buffer_reads_correct_data() {
+        let mut cursor = Cursor {
+            inner: &[1, 2, 3, 4, 5, 6, 7, 8],
+            pos: 4,
+        };
+        let mut buf = [0u8; 8];
+        assert_eq!(cursor.try_read(&mut buf), Ok(4));
+        assert_eq!(buf, [5, 6, 7, 8, 0, 0, 0, 0]);
+        assert_eq!(cursor.try_read(&mut buf), Ok(0));
+    }
+
+    #[test]
+    fn cursor_try_write_of_partial_buffer_writes_correct_data() {
+        let mut cursor = Cursor {
+            inner: &mut [0, 0, 0, 0, 0, 0, 0, 0],
+            pos: 4,
+        };
+        let buf = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(cursor.try_write_all(&buf), Ok(4));
+        assert_eq!(cursor.inner, &[0, 0, 0, 0, 1, 2, 3, 4]);
+        assert_eq!(cursor.try_write(&buf), Ok(0));
+    }
+
     #[test]
     fn cursor_rewind_resets_position_to_zero() {
         test_rewind_resets_position_to_zero::<64, _>(Cursor::new(&[0u8; 64]));
@@ -0,0 +1,354 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use core::fmt;
+
+use pw_status::{Error, Result};
+
+use super::{BufWriter, Cursor, VecWriter, Write};
+
+/// An adapter which implements [`core::fmt::Write`] for any [`Write`].
+///
+/// This allows code using `write!()` to target `pw_stream` writers.
+/// [`core::fmt::Error`] does not carry any information so the [`Error`]
+/// returned by the inner writer is recorded and can be retrieved with
+/// [`FmtWriter::error()`].
+///
+/// # Example
+///
+/// ```
+/// use core::fmt::Write as _;
+/// use pw_stream::{FmtWriter, VecWriter};
+///
+/// let mut writer = FmtWriter::new(VecWriter::<16>::new());
+/// write!(writer, "answer={}", 42).unwrap();
+/// assert_eq!(writer.get_ref().as_slice(), b"answer=42");
+/// ```
+pub struct FmtWriter<W: Write> {
+    inner: W,
+    error: Option<Error>,
+}
+
+impl<W: Write> FmtWriter<W> {
+    /// Create a new `FmtWriter` which writes to `inner`.
+    pub const fn new(inner: W) -> Self {
+        Self { inner, error: None }
+    }
+
+    /// Returns the error returned by the inner writer during the last failed
+    /// write, if any.
+    pub fn error(&self) -> Option<Error> {
+        self.error
+    }
+
+    /// Writes formatted arguments to the inner writer, returning the inner
+    /// writer's error on failure.
+    pub fn write_args(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+        self.error = None;
+        match fmt::Write::write_fmt(self, args) {
+            Ok(()) => Ok(()),
+            // Errors may also come from a `Display` implementation.
+            Err(fmt::Error) => Err(self.error.unwrap_or(Error::Unknown)),
+        }
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consumes the `FmtWriter` and returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> fmt::Write for FmtWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+/// An adapter which implements [`Write`] for any [`core::fmt::Write`].
+///
+/// Data written must be valid UTF-8.  Multi-byte characters may be split
+/// across writes; incomplete characters are held until the rest of their
+/// bytes are written.
+///
+/// # Example
+///
+/// ```
+/// use pw_stream::{FmtSink, Write};
+///
+/// let mut sink = FmtSink::new(String::new());
+/// sink.write_all("💖".as_bytes()[..2].as_ref()).unwrap();
+/// sink.write_all("💖".as_bytes()[2..].as_ref()).unwrap();
+/// assert_eq!(sink.into_inner(), "💖");
+/// ```
+pub struct FmtSink<F: fmt::Write> {
+    inner: F,
+    // Bytes of an incomplete UTF-8 character.
+    partial: [u8; 4],
+    partial_len: usize,
+}
+
+impl<F: fmt::Write> FmtSink<F> {
+    /// Create a new `FmtSink` which writes to `inner`.
+    pub const fn new(inner: F) -> Self {
+        Self {
+            inner,
+            partial: [0; 4],
+            partial_len: 0,
+        }
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &F {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut F {
+        &mut self.inner
+    }
+
+    /// Consumes the `FmtSink` and returns the underlying writer.
+    ///
+    /// Bytes of an incomplete character are discarded.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    fn write_str(&mut self, s: &str) -> Result<()> {
+        self.inner.write_str(s).map_err(|_| Error::Unknown)
+    }
+
+    // Writes as many complete characters from `buf` as possible.  Returns
+    // the number of bytes at the end of `buf` which form an incomplete
+    // character.
+    fn write_utf8(&mut self, buf: &[u8]) -> Result<usize> {
+        match core::str::from_utf8(buf) {
+            Ok(s) => {
+                self.write_str(s)?;
+                Ok(0)
+            }
+            Err(e) => {
+                if e.error_len().is_some() {
+                    return Err(Error::InvalidArgument);
+                }
+                let valid = e.valid_up_to();
+                // Safety: `from_utf8()` verified `buf[..valid]` is UTF-8.
+                self.write_str(unsafe { core::str::from_utf8_unchecked(&buf[..valid]) })?;
+                Ok(buf.len() - valid)
+            }
+        }
+    }
+}
+
+impl<F: fmt::Write> Write for FmtSink<F> {
+    /// Writes `buf` to the inner [`core::fmt::Write`].
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `buf` is not valid UTF-8.
+    /// - [`Error::Unknown`] - The inner writer returned an error.
+    fn write(&mut self, mut buf: &[u8]) -> Result<usize> {
+        let len = buf.len();
+
+        // Complete any partial character from a previous write.
+        while self.partial_len > 0 && !buf.is_empty() {
+            self.partial[self.partial_len] = buf[0];
+            self.partial_len += 1;
+            buf = &buf[1..];
+
+            let partial = self.partial;
+            let remaining = self.write_utf8(&partial[..self.partial_len])?;
+            if remaining == 0 {
+                self.partial_len = 0;
+            } else if self.partial_len == self.partial.len() {
+                return Err(Error::InvalidArgument);
+            }
+        }
+
+        let remaining = self.write_utf8(buf)?;
+        self.partial[..remaining].copy_from_slice(&buf[buf.len() - remaining..]);
+        self.partial_len += remaining;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> fmt::Write for Cursor<T> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl<const N: usize> fmt::Write for VecWriter<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl<const N: usize, W: Write> fmt::Write for BufWriter<N, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Write as _;
+
+    use super::*;
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> Result<usize> {
+            Err(Error::Unavailable)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // A `core::fmt::Write` with a fixed capacity.
+    struct StrBuf {
+        buf: [u8; 16],
+        len: usize,
+    }
+
+    impl StrBuf {
+        fn new() -> Self {
+            Self {
+                buf: [0; 16],
+                len: 0,
+            }
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.buf[..self.len]).unwrap()
+        }
+    }
+
+    impl fmt::Write for StrBuf {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.buf
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn fmt_writer_formats_to_stream() {
+        let mut writer = FmtWriter::new(VecWriter::<16>::new());
+        let name = 'a';
+        write!(writer, "{}-{:02x}", name, 10).unwrap();
+        assert_eq!(writer.error(), None);
+        assert_eq!(writer.into_inner().as_slice(), b"a-0a");
+    }
+
+    #[test]
+    fn fmt_writer_records_stream_errors() {
+        let mut writer = FmtWriter::new(FailingWriter);
+        assert!(write!(writer, "{}", 1).is_err());
+        assert_eq!(writer.error(), Some(Error::Unavailable));
+        assert_eq!(
+            writer.write_args(format_args!("{}", 1)),
+            Err(Error::Unavailable)
+        );
+    }
+
+    #[test]
+    fn fmt_writer_write_args_reports_out_of_range() {
+        let mut writer = FmtWriter::new(VecWriter::<4>::new());
+        assert_eq!(writer.write_args(format_args!("{}", 123)), Ok(()));
+        assert_eq!(
+            writer.write_args(format_args!("{}", 45)),
+            Err(Error::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn crate_writers_implement_fmt_write() {
+        let mut cursor = Cursor::new([0u8; 8]);
+        write!(cursor, "{}", 1234).unwrap();
+        assert_eq!(cursor.position(), 4);
+
+        let mut vec_writer = VecWriter::<8>::new();
+        write!(vec_writer, "{}", 1234).unwrap();
+        assert_eq!(vec_writer.as_slice(), b"1234");
+        assert!(write!(vec_writer, "{}", 56789).is_err());
+
+        let mut buf_writer = BufWriter::<4, _>::new(VecWriter::<8>::new());
+        write!(buf_writer, "{}", 12).unwrap();
+        assert_eq!(buf_writer.buffer(), b"12");
+    }
+
+    #[test]
+    fn fmt_sink_writes_utf8() {
+        let mut sink = FmtSink::new(StrBuf::new());
+        assert_eq!(sink.write(b"abc"), Ok(3));
+        assert_eq!(sink.get_ref().as_str(), "abc");
+    }
+
+    #[test]
+    fn fmt_sink_joins_split_characters() {
+        let bytes = "a💖b".as_bytes();
+        for split in 0..bytes.len() {
+            let mut sink = FmtSink::new(StrBuf::new());
+            sink.write_all(&bytes[..split]).unwrap();
+            sink.write_all(&bytes[split..]).unwrap();
+            assert_eq!(sink.get_ref().as_str(), "a💖b");
+        }
+
+        let mut sink = FmtSink::new(StrBuf::new());
+        for byte in bytes {
+            sink.write_all(&[*byte]).unwrap();
+        }
+        assert_eq!(sink.get_ref().as_str(), "a💖b");
+    }
+
+    #[test]
+    fn fmt_sink_rejects_invalid_utf8() {
+        let mut sink = FmtSink::new(StrBuf::new());
+        assert_eq!(sink.write(&[b'a', 0xff]), Err(Error::InvalidArgument));
+
+        let mut sink = FmtSink::new(StrBuf::new());
+        assert_eq!(sink.write(&[0xf0, 0x9f]), Ok(2));
+        assert_eq!(sink.write(b"a"), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn fmt_sink_reports_inner_errors() {
+        let mut sink = FmtSink::new(StrBuf::new());
+        assert_eq!(sink.write(&[b'a'; 17]), Err(Error::Unknown));
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! HDLC framing shared by [`HdlcWriter`] and the `pw_hdlc` crate.
+
+use pw_checksum::{Checksum, Crc32Ieee};
+use pw_status::Result;
+
+use super::Write;
+
+/// The byte which delimits HDLC frames.
+pub const FLAG: u8 = 0x7e;
+
+/// The byte which precedes escaped bytes in an HDLC frame.
+pub const ESCAPE: u8 = 0x7d;
+
+/// The value escaped bytes are XORed with.
+pub const ESCAPE_XOR: u8 = 0x20;
+
+/// The control field of an unnumbered information (UI) frame.
+pub const UI_FRAME_CONTROL: u8 = 0x03;
+
+/// The address of frames containing `pw_rpc` packets, matching the C++
+/// `pw::hdlc::kDefaultRpcAddress`.
+pub const DEFAULT_RPC_ADDRESS: u64 = b'R' as u64;
+
+/// The address of frames containing plain text logs, matching the C++
+/// `pw::hdlc::kDefaultLogAddress`.
+pub const DEFAULT_LOG_ADDRESS: u64 = 1;
+
+/// The maximum size of an encoded `u64` address.
+pub const MAX_ADDRESS_SIZE: usize = pw_varint::MAX_VARINT64_SIZE_BYTES;
+
+/// Returns true if `byte` must be escaped within a frame.
+pub const fn needs_escape(byte: u8) -> bool {
+    byte == FLAG || byte == ESCAPE
+}
+
+/// Encodes `address` as a one terminated varint into `buffer` and returns the
+/// number of bytes used.
+///
+/// Each byte holds seven bits of the address, least significant first,
+/// shifted left by one.  The low bit is set only in the last byte.
+pub const fn encode_address(address: u64, buffer: &mut [u8; MAX_ADDRESS_SIZE]) -> usize {
+    let mut len = 0;
+    let mut remaining = address;
+    loop {
+        buffer[len] = ((remaining & 0x7f) as u8) << 1;
+        remaining >>= 7;
+        len += 1;
+        if remaining == 0 {
+            buffer[len - 1] |= 1;
+            return len;
+        }
+    }
+}
+
+/// A writer adapter which frames data written through it using HDLC.
+///
+/// Data is escaped as it is written so that the flag (`0x7e`) and escape
+/// (`0x7d`) bytes never appear inside a frame.  A flag byte is written to the
+/// inner writer before the first byte of each frame and after the last byte
+/// when [`HdlcWriter::finish()`] is called.  Writing after `finish()` starts a
+/// new frame.
+///
+/// Unescaped runs of data are passed directly to the inner writer so no
+/// intermediate buffer is needed.  For example, a `pw_tokenizer`
+/// `MessageWriter` can forward its writes to an `HdlcWriter` over a UART and
+/// call `finish()` when the message is finalized.
+///
+/// `HdlcWriter` only handles framing.  Protocols which require a frame check
+/// sequence can compute one by wrapping the `HdlcWriter` in a
+/// [`crate::CrcWriter`] and writing the checksum to the `HdlcWriter` before
+/// finishing the frame.
+///
+/// # Example
+///
+/// ```
+/// use pw_stream::{Cursor, HdlcWriter, Write};
+///
+/// let mut writer = HdlcWriter::new(Cursor::new([0u8; 16]));
+/// writer.write_all(&[0x01, 0x7e, 0x02]).unwrap();
+/// writer.finish().unwrap();
+///
+/// let cursor = writer.into_inner();
+/// let len = cursor.position();
+/// assert_eq!(
+///     &cursor.into_inner()[..len],
+///     &[0x7e, 0x01, 0x7d, 0x5e, 0x02, 0x7e]
+/// );
+/// ```
+pub struct HdlcWriter<W: Write> {
+    inner: W,
+    in_frame: bool,
+}
+
+impl<W: Write> HdlcWriter<W> {
+    /// Create a new `HdlcWriter` which writes frames to `inner`.
+    pub const fn new(inner: W) -> Self {
+        Self {
+            inner,
+            in_frame: false,
+        }
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    ///
+    /// Data written directly to the underlying writer is not escaped.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consumes the `HdlcWriter` and returns the underlying writer.
+    ///
+    /// An unfinished frame is left unterminated.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes `payload` as a complete HDLC unnumbered information (UI) frame
+    /// addressed to `address`, matching the C++ `pw::hdlc::WriteUIFrame()`.
+    ///
+    /// The address is encoded as a one terminated varint and the frame ends
+    /// with a CRC-32 frame check sequence.  Any frame in progress is finished
+    /// first.
+    ///
+    /// ```
+    /// use pw_stream::{Cursor, HdlcWriter};
+    ///
+    /// let mut writer = HdlcWriter::new(Cursor::new([0u8; 16]));
+    /// writer.write_ui_frame(1, b"hi").unwrap();
+    ///
+    /// let cursor = writer.into_inner();
+    /// let len = cursor.position();
+    /// assert_eq!(
+    ///     &cursor.into_inner()[..len],
+    ///     &[0x7e, 0x03, 0x03, b'h', b'i', 0xf8, 0xf6, 0xfd, 0xa8, 0x7e]
+    /// );
+    /// ```
+    pub fn write_ui_frame(&mut self, address: u64, payload: &[u8]) -> Result<()> {
+        self.write_frame(address, UI_FRAME_CONTROL, payload)
+    }
+
+    /// Writes `payload` as a complete HDLC frame addressed to `address` with
+    /// the one byte `control` field.
+    ///
+    /// The address is encoded as a one terminated varint and the frame ends
+    /// with a CRC-32 frame check sequence.  Any frame in progress is finished
+    /// first.
+    pub fn write_frame(&mut self, address: u64, control: u8, payload: &[u8]) -> Result<()> {
+        if self.in_frame {
+            self.finish()?;
+        }
+
+        let mut address_bytes = [0u8; MAX_ADDRESS_SIZE];
+        let address_len = encode_address(address, &mut address_bytes);
+        let address_bytes = &address_bytes[..address_len];
+
+        let mut fcs = Crc32Ieee::new();
+        fcs.update(address_bytes);
+        fcs.update(&[control]);
+        fcs.update(payload);
+
+        // A frame is started even if nothing is written.
+        self.start_frame()?;
+        self.write_all(address_bytes)?;
+        self.write_all(&[control])?;
+        self.write_all(payload)?;
+        self.write_all(&fcs.value().to_le_bytes())?;
+        self.finish()
+    }
+
+    /// Ends the current frame by writing the closing flag byte.
+    ///
+    /// If nothing has been written since the last frame was finished, an empty
+    /// frame is written.
+    pub fn finish(&mut self) -> Result<()> {
+        self.start_frame()?;
+        self.inner.write_all(&[FLAG])?;
+        self.in_frame = false;
+        Ok(())
+    }
+
+    fn start_frame(&mut self) -> Result<()> {
+        if !self.in_frame {
+            self.inner.write_all(&[FLAG])?;
+            self.in_frame = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HdlcWriter<W> {
+    fn write(&mut self, mut buf: &[u8]) -> Result<usize> {
+        let len = buf.len();
+        if len == 0 {
+            return Ok(0);
+        }
+        self.start_frame()?;
+
+        while !buf.is_empty() {
+            let run = buf
+                .iter()
+                .position(|&b| needs_escape(b))
+                .unwrap_or(buf.len());
+            self.inner.write_all(&buf[..run])?;
+            if let Some(&byte) = buf.get(run) {
+                self.inner.write_all(&[ESCAPE, byte ^ ESCAPE_XOR])?;
+                buf = &buf[run + 1..];
+            } else {
+                buf = &buf[run..];
+            }
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_status::Error;
+
+    use super::*;
+    use crate::{CrcWriter, VecWriter};
+
+    fn frame<const N: usize>(writes: &[&[u8]]) -> VecWriter<N> {
+        let mut writer = HdlcWriter::new(VecWriter::<N>::new());
+        for data in writes {
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.into_inner()
+    }
+
+    #[test]
+    fn data_is_delimited_by_flags() {
+        assert_eq!(frame::<8>(&[b"abc"]).as_slice(), b"\x7eabc\x7e");
+    }
+
+    #[test]
+    fn special_bytes_are_escaped() {
+        assert_eq!(
+            frame::<16>(&[&[0x7e, 0x7d, 0x00, 0x5e, 0x7e]]).as_slice(),
+            &[0x7e, 0x7d, 0x5e, 0x7d, 0x5d, 0x00, 0x5e, 0x7d, 0x5e, 0x7e]
+        );
+    }
+
+    #[test]
+    fn multiple_writes_form_one_frame() {
+        assert_eq!(
+            frame::<16>(&[b"ab", &[0x7d], b"cd"]).as_slice(),
+            b"\x7eab\x7d\x5dcd\x7e"
+        );
+    }
+
+    #[test]
+    fn finish_without_data_writes_empty_frame() {
+        assert_eq!(frame::<4>(&[]).as_slice(), b"\x7e\x7e");
+    }
+
+    #[test]
+    fn writes_after_finish_start_a_new_frame() {
+        let mut writer = HdlcWriter::new(VecWriter::<16>::new());
+        writer.write_all(b"a").unwrap();
+        writer.finish().unwrap();
+        writer.write_all(b"b").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(writer.get_ref().as_slice(), b"\x7ea\x7e\x7eb\x7e");
+    }
+
+    #[test]
+    fn errors_from_inner_writer_are_returned() {
+        let mut writer = HdlcWriter::new(VecWriter::<2>::new());
+        assert_eq!(writer.write_all(b"ab"), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn ui_frame_address_is_one_terminated_varint() {
+        let mut writer = HdlcWriter::new(VecWriter::<32>::new());
+        writer.write_ui_frame(u64::from(b'R'), &[]).unwrap();
+        writer.write_ui_frame(300, &[0x7e]).unwrap();
+
+        let mut expected = VecWriter::<32>::new();
+        let mut hdlc = HdlcWriter::new(&mut expected);
+        // 300 is 0b10_0101100, which is encoded as 0b0101100_0, 0b10_1.
+        for frame in [&[0xa5, 0x03][..], &[0x58, 0x05, 0x03, 0x7e]] {
+            hdlc.write_all(frame).unwrap();
+            hdlc.write_all(&Crc32Ieee::calculate(frame).to_le_bytes())
+                .unwrap();
+            hdlc.finish().unwrap();
+        }
+        assert_eq!(writer.get_ref().as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn frame_check_sequence_is_computed_over_unescaped_data() {
+        let data = [0x01, 0x7e, 0x02];
+        let mut writer = CrcWriter::new(HdlcWriter::new(VecWriter::<16>::new()), Crc32Ieee::new());
+        writer.write_all(&data).unwrap();
+        let fcs = writer.checksum().to_le_bytes();
+        let (mut hdlc, _) = writer.into_parts();
+        hdlc.write_all(&fcs).unwrap();
+        hdlc.finish().unwrap();
+
+        let mut expected = VecWriter::<16>::new();
+        HdlcWriter::new(&mut expected)
+            .write_all(&[0x01, 0x7e, 0x02])
+            .unwrap();
+        assert_eq!(Crc32Ieee::calculate(&data), u32::from_le_bytes(fcs));
+        assert!(hdlc.get_ref().as_slice().starts_with(expected.as_slice()));
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_status::{Error, Result};
+use pw_varint::{VarintDecode, VarintEncode, MAX_VARINT64_SIZE_BYTES};
+
+use super::{Read, Write};
+
+/// An adapter which exchanges length-prefixed records over a byte stream.
+///
+/// Each record is preceded by its length encoded as a varint.  This preserves
+/// record boundaries over stream transports such as TCP, allowing code which
+/// expects whole packets, like that written for a datagram transport, to be
+/// reused unchanged.
+///
+/// # Example
+///
+/// ```
+/// use pw_stream::{Cursor, LengthPrefixed};
+///
+/// let mut stream = LengthPrefixed::new(Cursor::new([0u8; 16]));
+/// stream.write_record(b"abc").unwrap();
+/// stream.write_record(b"de").unwrap();
+///
+/// let mut stream = LengthPrefixed::new(Cursor::new(stream.into_inner().into_inner()));
+/// let mut buf = [0u8; 8];
+/// assert_eq!(stream.read_record(&mut buf), Ok(3));
+/// assert_eq!(&buf[..3], b"abc");
+/// assert_eq!(stream.read_record(&mut buf), Ok(2));
+/// assert_eq!(&buf[..2], b"de");
+/// ```
+pub struct LengthPrefixed<S> {
+    inner: S,
+}
+
+impl<S> LengthPrefixed<S> {
+    /// Create a new `LengthPrefixed` exchanging records over `inner`.
+    pub const fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Consumes the `LengthPrefixed` and returns the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Write> LengthPrefixed<S> {
+    /// Writes `record` preceded by its length.
+    pub fn write_record(&mut self, record: &[u8]) -> Result<()> {
+        let mut prefix = [0u8; MAX_VARINT64_SIZE_BYTES];
+        let prefix_len = (record.len() as u64).varint_encode(&mut prefix)?;
+        self.inner
+            .write_all_vectored(&[&prefix[..prefix_len], record])
+    }
+}
+
+impl<S: Read> LengthPrefixed<S> {
+    /// Reads the next record into `buf`, returning the record's length.
+    ///
+    /// # Errors
+    /// - [`Error::OutOfRange`] - The stream ended before a complete record
+    ///   was read.
+    /// - [`Error::DataLoss`] - The length prefix is malformed.
+    /// - [`Error::ResourceExhausted`] - The record does not fit in `buf`.  The
+    ///   record is discarded so the next record can still be read.
+    pub fn read_record(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.read_length()?;
+        if len > buf.len() as u64 {
+            self.discard(len)?;
+            return Err(Error::ResourceExhausted);
+        }
+        let len = len as usize;
+        self.inner.read_exact(&mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn read_length(&mut self) -> Result<u64> {
+        let mut prefix = [0u8; MAX_VARINT64_SIZE_BYTES];
+        for i in 0..prefix.len() {
+            self.inner.read_exact(&mut prefix[i..i + 1])?;
+            if prefix[i] & 0x80 == 0 {
+                let (_, len) = u64::varint_decode(&prefix[..=i]).map_err(|_| Error::DataLoss)?;
+                return Ok(len);
+            }
+        }
+        Err(Error::DataLoss)
+    }
+
+    fn discard(&mut self, mut len: u64) -> Result<()> {
+        let mut scratch = [0u8; 32];
+        while len > 0 {
+            let chunk = len.min(scratch.len() as u64) as usize;
+            self.inner.read_exact(&mut scratch[..chunk])?;
+            len -= chunk as u64;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cursor, VecWriter};
+
+    #[test]
+    fn records_are_prefixed_with_varint_length() {
+        let mut stream = LengthPrefixed::new(VecWriter::<256>::new());
+        stream.write_record(b"abc").unwrap();
+        stream.write_record(&[0x55; 200]).unwrap();
+        stream.write_record(b"").unwrap();
+
+        let data = stream.into_inner();
+        assert_eq!(&data.as_slice()[..4], b"\x03abc");
+        assert_eq!(&data.as_slice()[4..6], &[0xc8, 0x01]);
+        assert_eq!(data.as_slice()[206], 0);
+        assert_eq!(data.len(), 207);
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let mut writer = LengthPrefixed::new(VecWriter::<256>::new());
+        writer.write_record(b"abc").unwrap();
+        writer.write_record(&[0x55; 200]).unwrap();
+        writer.write_record(b"").unwrap();
+
+        let mut reader = LengthPrefixed::new(Cursor::new(writer.into_inner()));
+        let mut buf = [0u8; 256];
+        assert_eq!(reader.read_record(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(reader.read_record(&mut buf), Ok(200));
+        assert_eq!(&buf[..200], &[0x55; 200]);
+        assert_eq!(reader.read_record(&mut buf), Ok(0));
+        assert_eq!(reader.read_record(&mut buf), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn oversized_records_are_skipped() {
+        let mut reader = LengthPrefixed::new(Cursor::new(b"\x05hello\x02hi"));
+        let mut buf = [0u8; 4];
+        assert_eq!(reader.read_record(&mut buf), Err(Error::ResourceExhausted));
+        assert_eq!(reader.read_record(&mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"hi");
+    }
+
+    #[test]
+    fn truncated_records_fail() {
+        let mut reader = LengthPrefixed::new(Cursor::new(b"\x05hel"));
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.read_record(&mut buf), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn malformed_lengths_fail() {
+        let mut reader = LengthPrefixed::new(Cursor::new([0xff; 12]));
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.read_record(&mut buf), Err(Error::DataLoss));
+    }
+}
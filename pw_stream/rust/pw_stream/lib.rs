@@ -21,14 +21,45 @@
 // Allows docs to reference `std`
 #![cfg_attr(feature = "no_std", no_std)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+use core::cmp::min;
+
 use pw_status::{Error, Result};
 
+mod buf_writer;
+mod crc;
 #[doc(hidden)]
 mod cursor;
+mod fmt;
+pub mod hdlc;
 mod integer;
-
+mod length_prefixed;
+mod shared;
+#[cfg(not(feature = "no_std"))]
+mod std_io;
+mod take;
+mod tee;
+#[cfg(feature = "alloc")]
+mod vec_cursor;
+mod vec_writer;
+
+pub use buf_writer::BufWriter;
+pub use crc::CrcWriter;
 pub use cursor::Cursor;
+pub use fmt::{FmtSink, FmtWriter};
+pub use hdlc::HdlcWriter;
 pub use integer::{ReadInteger, ReadVarint, WriteInteger, WriteVarint};
+pub use length_prefixed::LengthPrefixed;
+pub use shared::SharedWriter;
+#[cfg(not(feature = "no_std"))]
+pub use std_io::{IoAdapter, TcpAdapter, UdpAdapter};
+pub use take::Take;
+pub use tee::Tee;
+#[cfg(feature = "alloc")]
+pub use vec_cursor::VecCursor;
+pub use vec_writer::VecWriter;
 
 /// A trait for objects that provide streaming read capability.
 pub trait Read {
@@ -93,6 +124,188 @@ pub trait Write {
             Ok(())
         }
     }
+
+    /// Write a sequence of buffers to a stream.
+    ///
+    /// Allows writers that support scatter-gather operations to write
+    /// multiple non-contiguous buffers without first copying them into a
+    /// contiguous buffer.  Returns the total number of bytes written which
+    /// may span multiple buffers.
+    ///
+    /// The default implementation writes the first non-empty buffer using
+    /// [`Write::write()`].
+    ///
+    /// Semantics match [`std::io::Write::write_vectored()`].
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<usize> {
+        let buf = bufs.iter().find(|b| !b.is_empty()).map_or(&[][..], |b| *b);
+        self.write(buf)
+    }
+
+    /// Writes an entire sequence of buffers to a stream.
+    ///
+    /// Repeatedly calls [`Write::write_vectored()`] until all buffers have
+    /// been written.
+    ///
+    /// Semantics match [`std::io::Write::write_all_vectored()`].
+    fn write_all_vectored(&mut self, mut bufs: &[&[u8]]) -> Result<()> {
+        // Offset into the first buffer of `bufs` of data that has not yet
+        // been written.
+        let mut offset = 0;
+        loop {
+            // Skip any empty or fully written buffers.
+            while let Some((first, rest)) = bufs.split_first() {
+                if offset < first.len() {
+                    break;
+                }
+                bufs = rest;
+                offset = 0;
+            }
+            let Some(first) = bufs.first() else {
+                return Ok(());
+            };
+
+            // A partially written buffer can not be passed along with the rest
+            // of `bufs` without copying so it is written on its own.
+            let mut len = if offset == 0 {
+                self.write_vectored(bufs)?
+            } else {
+                self.write_vectored(&[&first[offset..]])?
+            };
+
+            // End of stream
+            if len == 0 {
+                return Err(Error::OutOfRange);
+            }
+
+            // Consume `len` bytes from the first buffer and any following
+            // buffers.
+            while len > 0 {
+                let first = bufs.first().ok_or(Error::Internal)?;
+                let consumed = min(len, first.len() - offset);
+                offset += consumed;
+                len -= consumed;
+                if len > 0 {
+                    bufs = &bufs[1..];
+                    offset = 0;
+                }
+            }
+        }
+    }
+}
+
+/// A trait for objects that provide non-blocking streaming read capability.
+///
+/// `TryRead` is intended for use in contexts where blocking is unacceptable
+/// such as interrupt handlers and pollers.  Implementations must return
+/// immediately instead of waiting for data to become available.
+///
+/// The status [`Error::Unavailable`] is used to signal that the operation
+/// would have blocked.  This mirrors the use of `UNAVAILABLE` by Pigweed's
+/// C++ non-blocking APIs.  See [`is_would_block()`].
+pub trait TryRead {
+    /// Read from a stream into a buffer without blocking.
+    ///
+    /// Returns the number of bytes read.  Unlike [`Read::read()`], a return
+    /// value of `Ok(0)` only signals end of stream.  If no data is currently
+    /// available, `Err(Error::Unavailable)` is returned.
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize>;
+}
+
+/// A trait for objects that provide non-blocking streaming write capability.
+///
+/// `TryWrite` is intended for use in contexts where blocking is unacceptable
+/// such as interrupt handlers and pollers.  Implementations must return
+/// immediately instead of waiting for space to become available.
+///
+/// The status [`Error::Unavailable`] is used to signal that the operation
+/// would have blocked.  This mirrors the use of `UNAVAILABLE` by Pigweed's
+/// C++ non-blocking APIs.  See [`is_would_block()`].
+pub trait TryWrite {
+    /// Write a buffer to a stream without blocking.
+    ///
+    /// Returns the number of bytes accepted by the stream which may be less
+    /// than `buf.len()`.  A return value of `Ok(0)` for a non-empty `buf`
+    /// signals that the stream can not accept more data.  If the stream
+    /// would need to block to accept any data, `Err(Error::Unavailable)` is
+    /// returned.
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Write as much of a buffer to a stream as possible without blocking.
+    ///
+    /// Returns the number of bytes accepted.  Unlike [`Write::write_all()`],
+    /// running out of space is not an error and the caller is expected to
+    /// retry with the remaining data at a later time.  Errors other than
+    /// [`Error::Unavailable`] are returned as is.
+    fn try_write_all(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            match self.try_write(&buf[written..]) {
+                Ok(0) => break,
+                Ok(len) => written += len,
+                Err(e) if is_would_block(&e) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// Returns true if `error` signals that a non-blocking operation would have
+/// blocked.
+///
+/// See [`TryRead`] and [`TryWrite`].
+pub fn is_would_block(error: &Error) -> bool {
+    *error == Error::Unavailable
+}
+
+// Forwarding implementations allow adapters such as [`Take`] to operate on a
+// borrowed stream.
+impl<T: Read + ?Sized> Read for &mut T {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        (**self).read_exact(buf)
+    }
+}
+
+impl<T: Write + ?Sized> Write for &mut T {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        (**self).write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        (**self).write_all(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<usize> {
+        (**self).write_vectored(bufs)
+    }
+
+    fn write_all_vectored(&mut self, bufs: &[&[u8]]) -> Result<()> {
+        (**self).write_all_vectored(bufs)
+    }
+}
+
+impl<T: TryRead + ?Sized> TryRead for &mut T {
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        (**self).try_read(buf)
+    }
+}
+
+impl<T: TryWrite + ?Sized> TryWrite for &mut T {
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        (**self).try_write(buf)
+    }
+
+    fn try_write_all(&mut self, buf: &[u8]) -> Result<usize> {
+        (**self).try_write_all(buf)
+    }
 }
 
 /// A description of a seek operation in a stream.
@@ -150,6 +363,12 @@ pub trait Seek {
     }
 }
 
+impl<T: Seek + ?Sized> Seek for &mut T {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        (**self).seek(pos)
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod test_utils {
     use super::{Seek, SeekFrom};
@@ -250,6 +469,45 @@ mod tests {
         }
     }
 
+    // A writer that records the buffers passed to each `write_vectored` call
+    // while accepting at most `chunk_size` bytes per call.
+    struct VectoredRecorder {
+        data: Vec<u8>,
+        chunk_size: usize,
+        calls: Vec<Vec<Vec<u8>>>,
+    }
+
+    impl VectoredRecorder {
+        fn new(chunk_size: usize) -> Self {
+            Self {
+                data: Vec::new(),
+                chunk_size,
+                calls: Vec::new(),
+            }
+        }
+    }
+
+    impl Write for VectoredRecorder {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.write_vectored(&[buf])
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<usize> {
+            self.calls.push(bufs.iter().map(|b| b.to_vec()).collect());
+            let mut remaining = self.chunk_size;
+            for buf in bufs {
+                let len = min(remaining, buf.len());
+                self.data.extend_from_slice(&buf[..len]);
+                remaining -= len;
+            }
+            Ok(self.chunk_size - remaining)
+        }
+    }
+
     struct ErrorStream {
         error: Error,
     }
@@ -270,6 +528,80 @@ mod tests {
         }
     }
 
+    // A non-blocking stream that accepts at most `chunk_size` bytes per call
+    // and reports that it would block after `capacity` bytes.
+    struct TestTryWriter {
+        data: Vec<u8>,
+        capacity: usize,
+        chunk_size: usize,
+        error: Option<Error>,
+    }
+
+    impl TestTryWriter {
+        fn new(capacity: usize, chunk_size: usize) -> Self {
+            Self {
+                data: Vec::new(),
+                capacity,
+                chunk_size,
+                error: None,
+            }
+        }
+    }
+
+    impl TryWrite for TestTryWriter {
+        fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+            if let Some(error) = self.error {
+                return Err(error);
+            }
+            let available = self.capacity - self.data.len();
+            if available == 0 {
+                return Err(Error::Unavailable);
+            }
+            let len = min(min(available, self.chunk_size), buf.len());
+            self.data.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn is_would_block_only_matches_unavailable() {
+        assert!(is_would_block(&Error::Unavailable));
+        assert!(!is_would_block(&Error::OutOfRange));
+        assert!(!is_would_block(&Error::ResourceExhausted));
+    }
+
+    #[test]
+    fn try_write_all_writes_full_buffer_on_short_writes() {
+        let mut writer = TestTryWriter::new(256, 10);
+        let write_buffer = (0x0..=0xff).collect::<Vec<u8>>();
+
+        assert_eq!(writer.try_write_all(&write_buffer), Ok(256));
+        assert_eq!(writer.data, write_buffer);
+    }
+
+    #[test]
+    fn try_write_all_returns_accepted_len_when_it_would_block() {
+        let mut writer = TestTryWriter::new(100, 10);
+        let write_buffer = (0x0..=0xff).collect::<Vec<u8>>();
+
+        assert_eq!(writer.try_write_all(&write_buffer), Ok(100));
+        assert_eq!(writer.data, write_buffer[..100]);
+
+        // A subsequent write with no space available accepts nothing.
+        assert_eq!(writer.try_write_all(&write_buffer[100..]), Ok(0));
+        assert_eq!(
+            writer.try_write(&write_buffer[100..]),
+            Err(Error::Unavailable)
+        );
+    }
+
+    #[test]
+    fn try_write_all_propagates_errors() {
+        let mut writer = TestTryWriter::new(100, 10);
+        writer.error = Some(Error::Internal);
+        assert_eq!(writer.try_write_all(&[0u8; 16]), Err(Error::Internal));
+    }
+
     #[test]
     fn default_rewind_impl_resets_position_to_zero() {
         test_rewind_resets_position_to_zero::<64, _>(TestSeeker { len: 64, pos: 0 });
@@ -349,6 +681,60 @@ mod tests {
         assert_eq!(wrapper.write_all(&write_buffer), Err(Error::OutOfRange));
     }
 
+    #[test]
+    fn default_write_vectored_writes_first_non_empty_buffer() {
+        let mut wrapper = ChunkedStreamAdapter::new(Cursor::new(vec![0u8; 8]), 10);
+        assert_eq!(wrapper.write_vectored(&[&[], &[1, 2], &[3, 4]]), Ok(2));
+        assert_eq!(wrapper.num_writes, 1);
+        assert_eq!(wrapper.write_vectored(&[]), Ok(0));
+    }
+
+    #[test]
+    fn write_all_vectored_writes_all_buffers_on_short_writes() {
+        let cursor = Cursor::new(vec![0u8; 256]);
+        // Limit writes to 10 bytes per write.
+        let mut wrapper = ChunkedStreamAdapter::new(cursor, 10);
+        let write_buffer = (0x0..=0xff).collect::<Vec<u8>>();
+
+        wrapper
+            .write_all_vectored(&[&write_buffer[..3], &[], &write_buffer[3..]])
+            .unwrap();
+
+        assert_eq!(wrapper.inner.into_inner(), write_buffer);
+        // The first 3 byte buffer is written on its own.
+        assert_eq!(wrapper.num_writes, 27);
+    }
+
+    #[test]
+    fn write_all_vectored_gathers_whole_buffers() {
+        let mut recorder = VectoredRecorder::new(4);
+        recorder
+            .write_all_vectored(&[&[1, 2], &[3, 4, 5], &[6]])
+            .unwrap();
+
+        assert_eq!(recorder.data, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(
+            recorder.calls,
+            vec![
+                // All buffers are passed together initially.
+                vec![vec![1, 2], vec![3, 4, 5], vec![6]],
+                // The partially written buffer is passed on its own.
+                vec![vec![5]],
+                // Followed by the remaining whole buffers.
+                vec![vec![6]],
+            ]
+        );
+    }
+
+    #[test]
+    fn write_all_vectored_returns_error_on_too_little_space() {
+        let mut cursor = Cursor::new(vec![0u8; 4]);
+        assert_eq!(
+            cursor.write_all_vectored(&[&[1, 2], &[3, 4, 5]]),
+            Err(Error::OutOfRange)
+        );
+    }
+
     #[test]
     fn write_all_propagates_write_errors() {
         let mut error_stream = ErrorStream {
@@ -0,0 +1,260 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use core::cell::{Cell, UnsafeCell};
+
+use critical_section::Mutex;
+use pw_status::{Error, Result};
+
+use super::Write;
+
+/// A wrapper allowing a single writer to be shared between thread and
+/// interrupt contexts.
+///
+/// `SharedWriter` is intended to be placed in a `static` so that, for example,
+/// both threads and interrupt handlers can log to the same UART without
+/// resorting to `static mut`.
+///
+/// Access to the inner writer is arbitrated by a lock flag which is only
+/// modified inside a short critical section (see the [`critical_section`]
+/// crate).  Interrupts are not masked while data is being written.
+///
+/// # Ordering and contention
+///
+/// Each call to [`SharedWriter::with()`] or [`Write::write()`] on a
+/// `&SharedWriter` has exclusive access to the inner writer for its duration,
+/// so data from different callers is never interleaved.  Data is written in
+/// the order in which callers acquire access.
+///
+/// Callers never wait for access.  If the writer is already in use, for
+/// example when an interrupt handler preempts a thread in the middle of a
+/// write, the new write is dropped, [`Error::Unavailable`] is returned, and a
+/// drop counter is incremented.  The counter can be read with
+/// [`SharedWriter::dropped()`] so that lost data can be reported.
+///
+/// Threads which must not lose data, such as those sending RPC packets, can
+/// instead wait for access with [`SharedWriter::with_blocking()`].
+///
+/// # Example
+///
+/// ```
+/// use pw_stream::{SharedWriter, VecWriter, Write};
+///
+/// static WRITER: SharedWriter<VecWriter<16>> = SharedWriter::new(VecWriter::new());
+///
+/// (&WRITER).write_all(b"hello").unwrap();
+///
+/// // Multi-part messages can be written while holding exclusive access.
+/// WRITER
+///     .with(|writer| {
+///         writer.write_all(b" ")?;
+///         writer.write_all(b"world")
+///     })
+///     .unwrap()
+///     .unwrap();
+///
+/// WRITER.with(|writer| assert_eq!(writer.as_slice(), b"hello world")).unwrap();
+/// assert_eq!(WRITER.dropped(), 0);
+/// ```
+pub struct SharedWriter<W> {
+    locked: Mutex<Cell<bool>>,
+    dropped: Mutex<Cell<u32>>,
+    inner: UnsafeCell<W>,
+}
+
+// Safety: Access to `inner` is guarded by the `locked` flag which is only
+// read and modified inside critical sections.
+unsafe impl<W: Send> Sync for SharedWriter<W> {}
+
+impl<W> SharedWriter<W> {
+    /// Create a new `SharedWriter` wrapping `inner`.
+    pub const fn new(inner: W) -> Self {
+        Self {
+            locked: Mutex::new(Cell::new(false)),
+            dropped: Mutex::new(Cell::new(0)),
+            inner: UnsafeCell::new(inner),
+        }
+    }
+
+    /// Calls `f` with exclusive access to the inner writer.
+    ///
+    /// # Errors
+    /// - [`Error::Unavailable`] - The writer is in use.  `f` is not called and
+    ///   the drop counter is incremented.
+    pub fn with<R>(&self, f: impl FnOnce(&mut W) -> R) -> Result<R> {
+        if !self.try_acquire() {
+            critical_section::with(|cs| {
+                let dropped = self.dropped.borrow(cs);
+                dropped.set(dropped.get().saturating_add(1));
+            });
+            return Err(Error::Unavailable);
+        }
+        Ok(self.with_acquired(f))
+    }
+
+    /// Calls `f` with exclusive access to the inner writer, waiting for the
+    /// current user to finish if the writer is in use.
+    ///
+    /// Waiting does not count as a drop.  The wait spins, so this must only
+    /// be called from threads which the current user can run alongside or
+    /// preempt.  Called from an interrupt handler which preempted the
+    /// current user, it never returns.
+    pub fn with_blocking<R>(&self, f: impl FnOnce(&mut W) -> R) -> R {
+        while !self.try_acquire() {
+            core::hint::spin_loop();
+        }
+        self.with_acquired(f)
+    }
+
+    // Sets the lock flag and returns true if it was clear.
+    fn try_acquire(&self) -> bool {
+        critical_section::with(|cs| !self.locked.borrow(cs).replace(true))
+    }
+
+    // Calls `f` with the inner writer and releases the lock flag set by
+    // `try_acquire()`.
+    fn with_acquired<R>(&self, f: impl FnOnce(&mut W) -> R) -> R {
+        // Release the lock even if `f` panics.
+        struct Release<'a>(&'a Mutex<Cell<bool>>);
+        impl Drop for Release<'_> {
+            fn drop(&mut self) {
+                critical_section::with(|cs| self.0.borrow(cs).set(false));
+            }
+        }
+        let _release = Release(&self.locked);
+
+        // Safety: The caller acquired the lock flag so no other reference to
+        // `inner` exists until it is released.
+        f(unsafe { &mut *self.inner.get() })
+    }
+
+    /// Returns the number of writes dropped because the writer was in use.
+    pub fn dropped(&self) -> u32 {
+        critical_section::with(|cs| self.dropped.borrow(cs).get())
+    }
+
+    /// Returns the number of writes dropped because the writer was in use
+    /// and resets the count to zero.
+    pub fn take_dropped(&self) -> u32 {
+        critical_section::with(|cs| self.dropped.borrow(cs).replace(0))
+    }
+
+    /// Returns a mutable reference to the inner writer.
+    ///
+    /// Exclusive access is guaranteed statically so no locking is required.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    /// Consumes the `SharedWriter` and returns the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner.into_inner()
+    }
+}
+
+impl<W: Write> Write for &SharedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.with(|writer| writer.write(buf))?
+    }
+
+    /// Writes all of `buf` while holding exclusive access to the inner writer
+    /// so that it is not interleaved with other writes.
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.with(|writer| writer.write_all(buf))?
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.with(|writer| writer.flush())?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VecWriter;
+
+    #[test]
+    fn writes_reach_inner_writer() {
+        let shared = SharedWriter::new(VecWriter::<8>::new());
+        assert_eq!((&shared).write(b"abc"), Ok(3));
+        (&shared).write_all(b"de").unwrap();
+        (&shared).flush().unwrap();
+        assert_eq!(shared.into_inner().as_slice(), b"abcde");
+    }
+
+    #[test]
+    fn contended_writes_are_dropped_and_counted() {
+        let shared = SharedWriter::new(VecWriter::<8>::new());
+        shared
+            .with(|writer| {
+                writer.write_all(b"ab").unwrap();
+                // Simulate an interrupt writing while the writer is in use.
+                assert_eq!((&shared).write_all(b"cd"), Err(Error::Unavailable));
+                assert_eq!(shared.with(|_| ()), Err(Error::Unavailable));
+                writer.write_all(b"ef").unwrap();
+            })
+            .unwrap();
+
+        assert_eq!(shared.dropped(), 2);
+        assert_eq!(shared.take_dropped(), 2);
+        assert_eq!(shared.dropped(), 0);
+
+        // The lock is released once `with()` returns.
+        (&shared).write_all(b"gh").unwrap();
+        assert_eq!(shared.into_inner().as_slice(), b"abefgh");
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn blocking_writes_wait_for_access() {
+        use std::sync::mpsc;
+        use std::thread;
+        use std::time::Duration;
+
+        let shared = SharedWriter::new(VecWriter::<8>::new());
+        let (locked_tx, locked_rx) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                shared.with(|writer| {
+                    locked_tx.send(()).unwrap();
+                    thread::sleep(Duration::from_millis(20));
+                    writer.write_all(b"ab").unwrap();
+                })
+            });
+            locked_rx.recv().unwrap();
+            shared.with_blocking(|writer| writer.write_all(b"cd").unwrap());
+        });
+
+        assert_eq!(shared.dropped(), 0);
+        assert_eq!(shared.into_inner().as_slice(), b"abcd");
+    }
+
+    #[test]
+    fn inner_errors_are_returned() {
+        let shared = SharedWriter::new(VecWriter::<2>::new());
+        assert_eq!((&shared).write_all(b"abc"), Err(Error::OutOfRange));
+        assert_eq!(shared.dropped(), 0);
+    }
+
+    #[cfg(not(feature = "no_std"))]
+    #[test]
+    fn shared_writer_can_be_static() {
+        static WRITER: SharedWriter<VecWriter<8>> = SharedWriter::new(VecWriter::new());
+        let writer = std::thread::spawn(|| (&WRITER).write_all(b"thread"));
+        writer.join().unwrap().unwrap();
+        WRITER
+            .with(|writer| assert_eq!(writer.as_slice(), b"thread"))
+            .unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use std::io;
+use std::net::{TcpStream, UdpSocket};
+
+use pw_status::{Error, Result};
+
+use super::{Read, Seek, SeekFrom, TryRead, TryWrite, Write};
+
+// Retries `f` while it is interrupted by a signal.
+fn retry_interrupted<T>(mut f: impl FnMut() -> io::Result<T>) -> Result<T> {
+    loop {
+        match f() {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result.map_err(Error::from),
+        }
+    }
+}
+
+/// An adapter exposing a [`std::io`] stream through the `pw_stream` traits.
+///
+/// Any type implementing [`std::io::Read`], [`std::io::Write`], or
+/// [`std::io::Seek`] can be used, including [`TcpStream`].  This allows host
+/// tools, simulators, and integration tests to reuse device side code which
+/// targets `pw_stream`.
+///
+/// [`std::io::ErrorKind::WouldBlock`] is reported as [`Error::Unavailable`]
+/// so [`TryRead`] and [`TryWrite`] can be used with streams in non-blocking
+/// mode.
+///
+/// Only available with `std`.
+///
+/// # Example
+///
+/// ```no_run
+/// use std::net::TcpStream;
+/// use pw_stream::{IoAdapter, Write};
+///
+/// let mut stream = IoAdapter::new(TcpStream::connect("localhost:33000").unwrap());
+/// stream.write_all(b"hello").unwrap();
+/// ```
+pub struct IoAdapter<T> {
+    inner: T,
+}
+
+/// A TCP connection exposed through the `pw_stream` traits.
+pub type TcpAdapter = IoAdapter<TcpStream>;
+
+impl<T> IoAdapter<T> {
+    /// Create a new `IoAdapter` wrapping `inner`.
+    pub const fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consumes the `IoAdapter` and returns the underlying stream.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: io::Read> Read for IoAdapter<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        retry_interrupted(|| self.inner.read(buf))
+    }
+}
+
+impl<T: io::Write> Write for IoAdapter<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        retry_interrupted(|| self.inner.write(buf))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        retry_interrupted(|| self.inner.flush())
+    }
+
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<usize> {
+        let slices: Vec<io::IoSlice<'_>> = bufs.iter().map(|buf| io::IoSlice::new(buf)).collect();
+        retry_interrupted(|| self.inner.write_vectored(&slices))
+    }
+}
+
+impl<T: io::Read> TryRead for IoAdapter<T> {
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read(buf)
+    }
+}
+
+impl<T: io::Write> TryWrite for IoAdapter<T> {
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.write(buf)
+    }
+}
+
+impl<T: io::Seek> Seek for IoAdapter<T> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => io::SeekFrom::Start(pos),
+            SeekFrom::End(pos) => io::SeekFrom::End(pos),
+            SeekFrom::Current(pos) => io::SeekFrom::Current(pos),
+        };
+        retry_interrupted(|| self.inner.seek(pos))
+    }
+}
+
+/// A connected UDP socket exposed through the `pw_stream` traits.
+///
+/// Each call to [`Write::write()`] sends one datagram and each call to
+/// [`Read::read()`] receives one datagram.  Datagrams larger than the read
+/// buffer are truncated, matching [`UdpSocket::recv()`].
+///
+/// Only available with `std`.
+pub struct UdpAdapter {
+    socket: UdpSocket,
+}
+
+impl UdpAdapter {
+    /// Create a new `UdpAdapter` from a socket which has been connected to
+    /// its peer with [`UdpSocket::connect()`].
+    pub const fn new(socket: UdpSocket) -> Self {
+        Self { socket }
+    }
+
+    /// Returns a reference to the underlying socket.
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Consumes the `UdpAdapter` and returns the underlying socket.
+    pub fn into_inner(self) -> UdpSocket {
+        self.socket
+    }
+}
+
+impl Read for UdpAdapter {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        retry_interrupted(|| self.socket.recv(buf))
+    }
+}
+
+impl Write for UdpAdapter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        retry_interrupted(|| self.socket.send(buf))
+    }
+
+    /// Datagrams are sent immediately so `flush()` is a noop.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl TryRead for UdpAdapter {
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read(buf)
+    }
+}
+
+impl TryWrite for UdpAdapter {
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.write(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+    use crate::LengthPrefixed;
+
+    #[test]
+    fn io_errors_map_to_status() {
+        let error = |kind| Error::from(io::Error::from(kind));
+        assert_eq!(error(io::ErrorKind::WouldBlock), Error::Unavailable);
+        assert_eq!(error(io::ErrorKind::NotFound), Error::NotFound);
+        assert_eq!(error(io::ErrorKind::TimedOut), Error::DeadlineExceeded);
+        assert_eq!(error(io::ErrorKind::BrokenPipe), Error::FailedPrecondition);
+        assert_eq!(error(io::ErrorKind::Other), Error::Unknown);
+    }
+
+    #[test]
+    fn io_adapter_reads_writes_and_seeks() {
+        let mut stream = IoAdapter::new(io::Cursor::new(vec![0u8; 4]));
+        stream.write_all(b"ab").unwrap();
+        stream.rewind().unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(stream.read(&mut buf), Ok(4));
+        assert_eq!(&buf, b"ab\0\0");
+        assert_eq!(stream.stream_len(), Ok(4));
+    }
+
+    #[test]
+    fn tcp_length_prefixed_records_round_trip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut stream = LengthPrefixed::new(TcpAdapter::new(socket));
+            let mut buf = [0u8; 16];
+            let len = stream.read_record(&mut buf).unwrap();
+            stream.write_record(&buf[..len]).unwrap();
+        });
+
+        let mut client = LengthPrefixed::new(TcpAdapter::new(TcpStream::connect(addr).unwrap()));
+        client.write_record(b"echo").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(client.read_record(&mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"echo");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn udp_datagrams_round_trip() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        a.connect(b.local_addr().unwrap()).unwrap();
+        b.connect(a.local_addr().unwrap()).unwrap();
+        let mut a = UdpAdapter::new(a);
+        let mut b = UdpAdapter::new(b);
+
+        assert_eq!(a.write(b"ping"), Ok(4));
+        let mut buf = [0u8; 8];
+        assert_eq!(b.read(&mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"ping");
+    }
+
+    #[test]
+    fn nonblocking_reads_would_block() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(socket.local_addr().unwrap()).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut socket = UdpAdapter::new(socket);
+        let mut buf = [0u8; 8];
+        assert_eq!(socket.try_read(&mut buf), Err(Error::Unavailable));
+    }
+}
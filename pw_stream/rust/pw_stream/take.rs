@@ -0,0 +1,228 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use core::cmp::min;
+
+use pw_status::{Error, Result};
+
+use super::{Read, TryRead, TryWrite, Write};
+
+/// An adapter which limits the number of bytes read from or written to an
+/// underlying stream.
+///
+/// `Take` allows a length-prefixed record to be consumed in place without
+/// first copying it into a contiguous buffer.  Once `limit` bytes have been
+/// read, further reads return `Ok(0)` as if the end of the stream had been
+/// reached.  Once `limit` bytes have been written, further writes fail with
+/// [`Error::OutOfRange`].
+///
+/// # Example
+///
+/// ```
+/// use pw_stream::{Cursor, Read, Take};
+///
+/// // A record with a one byte length prefix followed by a trailing byte.
+/// let mut cursor = Cursor::new([3u8, b'a', b'b', b'c', b'd']);
+/// let mut len = [0u8; 1];
+/// cursor.read_exact(&mut len).unwrap();
+///
+/// let mut record = Take::new(&mut cursor, len[0] as usize);
+/// let mut buf = [0u8; 8];
+/// assert_eq!(record.read(&mut buf), Ok(3));
+/// assert_eq!(&buf[..3], b"abc");
+/// assert_eq!(record.read(&mut buf), Ok(0));
+///
+/// // The underlying stream is positioned after the record.
+/// assert_eq!(cursor.read(&mut buf), Ok(1));
+/// assert_eq!(buf[0], b'd');
+/// ```
+pub struct Take<T> {
+    inner: T,
+    limit: usize,
+}
+
+impl<T> Take<T> {
+    /// Create a new `Take` allowing at most `limit` bytes to be read from or
+    /// written to `inner`.
+    pub const fn new(inner: T, limit: usize) -> Self {
+        Self { inner, limit }
+    }
+
+    /// Returns the number of bytes which may still be read or written.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Sets the number of bytes which may be read or written.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Reading from or writing to the underlying stream directly does not
+    /// affect the limit.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consumes the `Take` and returns the underlying stream.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    // Shortens `len` to the remaining limit.
+    fn limited(&self, len: usize) -> usize {
+        min(len, self.limit)
+    }
+
+    fn consume(&mut self, len: usize) -> Result<usize> {
+        // Guard against misbehaving streams claiming to have handled more
+        // bytes than they were given.
+        self.limit = self.limit.checked_sub(len).ok_or(Error::Internal)?;
+        Ok(len)
+    }
+}
+
+impl<T: Read> Read for Take<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.limited(buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = self.inner.read(&mut buf[..len])?;
+        self.consume(read)
+    }
+}
+
+impl<T: Write> Write for Take<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = self.limited(buf.len());
+        if len == 0 {
+            return Err(Error::OutOfRange);
+        }
+        let written = self.inner.write(&buf[..len])?;
+        self.consume(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: TryRead> TryRead for Take<T> {
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.limited(buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = self.inner.try_read(&mut buf[..len])?;
+        self.consume(read)
+    }
+}
+
+impl<T: TryWrite> TryWrite for Take<T> {
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        let len = self.limited(buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        let written = self.inner.try_write(&buf[..len])?;
+        self.consume(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cursor;
+
+    #[test]
+    fn read_stops_at_limit() {
+        let mut cursor = Cursor::new([1u8, 2, 3, 4, 5, 6]);
+        let mut take = Take::new(&mut cursor, 4);
+        let mut buf = [0u8; 3];
+        assert_eq!(take.read(&mut buf), Ok(3));
+        assert_eq!(buf, [1, 2, 3]);
+        assert_eq!(take.limit(), 1);
+        assert_eq!(take.read(&mut buf), Ok(1));
+        assert_eq!(buf[0], 4);
+        assert_eq!(take.read(&mut buf), Ok(0));
+        assert_eq!(cursor.position(), 4);
+    }
+
+    #[test]
+    fn read_exact_past_limit_fails() {
+        let mut take = Take::new(Cursor::new([1u8, 2, 3, 4]), 2);
+        let mut buf = [0u8; 3];
+        assert!(take.read_exact(&mut buf).is_err());
+    }
+
+    #[test]
+    fn read_stops_at_end_of_inner_stream() {
+        let mut take = Take::new(Cursor::new([1u8, 2]), 4);
+        let mut buf = [0u8; 4];
+        assert_eq!(take.read(&mut buf), Ok(2));
+        assert_eq!(take.limit(), 2);
+    }
+
+    #[test]
+    fn write_stops_at_limit() {
+        let mut cursor = Cursor::new([0u8; 8]);
+        let mut take = Take::new(&mut cursor, 5);
+        assert_eq!(take.write(&[1, 2, 3]), Ok(3));
+        assert_eq!(take.write(&[4, 5, 6]), Ok(2));
+        assert_eq!(take.write(&[7]), Err(Error::OutOfRange));
+        assert_eq!(take.write(&[]), Ok(0));
+        assert_eq!(cursor.position(), 5);
+        assert_eq!(cursor.into_inner(), [1, 2, 3, 4, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn write_all_past_limit_fails() {
+        let mut take = Take::new(Cursor::new([0u8; 8]), 2);
+        assert_eq!(take.write_all(&[1, 2, 3]), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn set_limit_resets_remaining_bytes() {
+        let mut take = Take::new(Cursor::new([1u8, 2, 3, 4]), 1);
+        let mut buf = [0u8; 4];
+        assert_eq!(take.read(&mut buf), Ok(1));
+        take.set_limit(2);
+        assert_eq!(take.read(&mut buf), Ok(2));
+        assert_eq!(&buf[..2], &[2, 3]);
+        assert_eq!(take.into_inner().position(), 3);
+    }
+
+    #[test]
+    fn try_read_and_try_write_respect_limit() {
+        let mut take = Take::new(Cursor::new([1u8, 2, 3, 4]), 3);
+        let mut buf = [0u8; 4];
+        assert_eq!(take.try_read(&mut buf), Ok(3));
+        assert_eq!(take.try_read(&mut buf), Ok(0));
+
+        let mut take = Take::new(Cursor::new([0u8; 4]), 3);
+        assert_eq!(take.try_write(&[1, 2, 3, 4]), Ok(3));
+        assert_eq!(take.try_write(&[5]), Ok(0));
+    }
+}
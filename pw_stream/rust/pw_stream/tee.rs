@@ -0,0 +1,168 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_status::Result;
+
+use super::Write;
+
+/// A writer which duplicates all data written to it to two inner writers.
+///
+/// Writes to more than two writers are supported by nesting `Tee`s.
+///
+/// # Errors
+///
+/// Each call to [`Write::write()`] writes the entire buffer to both writers
+/// using [`Write::write_all()`] and returns the buffer's full length on
+/// success.  A failure of one writer does not prevent data from being written
+/// to the other writer.  If either writer fails, the error from the first
+/// writer to fail is returned, with the first writer taking precedence.
+/// [`Write::flush()`] behaves the same way.
+///
+/// This allows, for example, logs to continue to be mirrored to a RAM crash
+/// buffer even if the UART they are also sent to stops accepting data.
+///
+/// # Example
+///
+/// ```
+/// use pw_stream::{Tee, VecWriter, Write};
+///
+/// let mut tee = Tee::new(VecWriter::<8>::new(), VecWriter::<4>::new());
+/// tee.write_all(b"abc").unwrap();
+///
+/// // The second writer fills up but the first continues to receive data.
+/// assert!(tee.write_all(b"def").is_err());
+///
+/// let (first, second) = tee.into_inner();
+/// assert_eq!(first.as_slice(), b"abcdef");
+/// assert_eq!(second.as_slice(), b"abcd");
+/// ```
+pub struct Tee<A: Write, B: Write> {
+    first: A,
+    second: B,
+}
+
+impl<A: Write, B: Write> Tee<A, B> {
+    /// Create a new `Tee` which writes to `first` and `second`.
+    pub const fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    /// Returns a reference to the first writer.
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    /// Returns a mutable reference to the first writer.
+    pub fn first_mut(&mut self) -> &mut A {
+        &mut self.first
+    }
+
+    /// Returns a reference to the second writer.
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+
+    /// Returns a mutable reference to the second writer.
+    pub fn second_mut(&mut self) -> &mut B {
+        &mut self.second
+    }
+
+    /// Consumes the `Tee` and returns both writers.
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let first = self.first.write_all(buf);
+        let second = self.second.write_all(buf);
+        first.and(second).map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let first = self.first.flush();
+        let second = self.second.flush();
+        first.and(second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_status::Error;
+
+    use super::*;
+    use crate::VecWriter;
+
+    struct FailingWriter(Error);
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> Result<usize> {
+            Err(self.0)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Err(self.0)
+        }
+    }
+
+    #[test]
+    fn data_is_written_to_both_writers() {
+        let mut tee = Tee::new(VecWriter::<8>::new(), VecWriter::<8>::new());
+        assert_eq!(tee.write(b"abc"), Ok(3));
+        assert_eq!(tee.write(b"de"), Ok(2));
+        assert_eq!(tee.flush(), Ok(()));
+        assert_eq!(tee.first().as_slice(), b"abcde");
+        assert_eq!(tee.second().as_slice(), b"abcde");
+    }
+
+    #[test]
+    fn first_writer_failure_does_not_stop_second() {
+        let mut tee = Tee::new(FailingWriter(Error::Unavailable), VecWriter::<8>::new());
+        assert_eq!(tee.write(b"abc"), Err(Error::Unavailable));
+        assert_eq!(tee.flush(), Err(Error::Unavailable));
+        assert_eq!(tee.second().as_slice(), b"abc");
+    }
+
+    #[test]
+    fn second_writer_failure_does_not_stop_first() {
+        let mut tee = Tee::new(VecWriter::<8>::new(), FailingWriter(Error::DataLoss));
+        assert_eq!(tee.write(b"abc"), Err(Error::DataLoss));
+        assert_eq!(tee.first().as_slice(), b"abc");
+    }
+
+    #[test]
+    fn first_error_takes_precedence() {
+        let mut tee = Tee::new(
+            FailingWriter(Error::Unavailable),
+            FailingWriter(Error::DataLoss),
+        );
+        assert_eq!(tee.write(b"abc"), Err(Error::Unavailable));
+        assert_eq!(tee.flush(), Err(Error::Unavailable));
+    }
+
+    #[test]
+    fn nested_tees_write_to_all_writers() {
+        let mut tee = Tee::new(
+            VecWriter::<4>::new(),
+            Tee::new(VecWriter::<4>::new(), VecWriter::<4>::new()),
+        );
+        tee.write_all(b"ab").unwrap();
+        let (a, inner) = tee.into_inner();
+        let (b, c) = inner.into_inner();
+        assert_eq!(a.as_slice(), b"ab");
+        assert_eq!(b.as_slice(), b"ab");
+        assert_eq!(c.as_slice(), b"ab");
+    }
+}
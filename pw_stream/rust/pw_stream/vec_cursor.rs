@@ -0,0 +1,351 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use pw_status::{Error, Result};
+use pw_varint::{VarintDecode, VarintEncode, MAX_VARINT64_SIZE_BYTES};
+
+use super::cursor::{
+    cursor_read_bits_impl, cursor_read_type_impl, cursor_write_bits_impl, cursor_write_type_impl,
+    read_impl, write_impl,
+};
+use super::{Read, Seek, SeekFrom, TryRead, TryWrite, Write};
+
+/// A cursor over a [`Vec<u8>`] which grows as data is written past its end.
+///
+/// Unlike a [`crate::Cursor`] over a fixed size buffer, writes to a
+/// `VecCursor` never run out of space.  This is useful for host tools which
+/// encode variable sized payloads.
+///
+/// Requires the `alloc` feature.
+///
+/// # Example
+///
+/// ```
+/// use pw_stream::{VecCursor, Write, WriteInteger};
+///
+/// let mut cursor = VecCursor::new();
+/// cursor.write_all(b"hello").unwrap();
+/// cursor.write_u16_le(&0x0201).unwrap();
+/// assert_eq!(cursor.into_inner(), b"hello\x01\x02");
+/// ```
+#[derive(Default)]
+pub struct VecCursor {
+    inner: Vec<u8>,
+    pos: usize,
+}
+
+impl VecCursor {
+    /// Create a new, empty `VecCursor`.
+    pub const fn new() -> Self {
+        Self {
+            inner: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Create a new `VecCursor` wrapping `inner` with an initial position of
+    /// 0.
+    pub fn from_vec(inner: Vec<u8>) -> Self {
+        Self { inner, pos: 0 }
+    }
+
+    /// Consumes the cursor and returns the inner [`Vec`].
+    pub fn into_inner(self) -> Vec<u8> {
+        self.inner
+    }
+
+    /// Returns the data in the cursor.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.inner
+    }
+
+    /// Returns the number of bytes between the current position and the end
+    /// of the data.
+    pub fn remaining(&self) -> usize {
+        self.len() - self.pos
+    }
+
+    /// Returns the total length of the data.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns true if the cursor contains no data.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns current IO position of the cursor.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn remaining_slice(&self) -> &[u8] {
+        &self.inner[self.pos..]
+    }
+
+    // Grows the inner `Vec` so that `len` bytes may be written at the current
+    // position.
+    fn reserve_write(&mut self, len: usize) -> Result<()> {
+        let end = self.pos.checked_add(len).ok_or(Error::OutOfRange)?;
+        if end > self.inner.len() {
+            self.inner.resize(end, 0);
+        }
+        Ok(())
+    }
+
+    // Returns the `len` bytes at the current position, growing the inner
+    // `Vec` if needed.
+    fn writable_slice(&mut self, len: usize) -> Result<&mut [u8]> {
+        self.reserve_write(len)?;
+        Ok(&mut self.inner[self.pos..self.pos + len])
+    }
+}
+
+impl From<Vec<u8>> for VecCursor {
+    fn from(inner: Vec<u8>) -> Self {
+        Self::from_vec(inner)
+    }
+}
+
+impl Read for VecCursor {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        read_impl(&self.inner, &mut self.pos, buf)
+    }
+}
+
+impl Write for VecCursor {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.reserve_write(buf.len())?;
+        write_impl(&mut self.inner, &mut self.pos, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // VecCursor does not provide any buffering so flush() is a noop.
+        Ok(())
+    }
+
+    fn write_vectored(&mut self, bufs: &[&[u8]]) -> Result<usize> {
+        let total_len = bufs.iter().map(|buf| buf.len()).sum();
+        self.reserve_write(total_len)?;
+        for buf in bufs {
+            write_impl(&mut self.inner, &mut self.pos, buf)?;
+        }
+        Ok(total_len)
+    }
+}
+
+// In memory cursors never block.
+impl TryRead for VecCursor {
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read(buf)
+    }
+}
+
+impl TryWrite for VecCursor {
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.write(buf)
+    }
+}
+
+impl Seek for VecCursor {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        // Match the semantics of `Cursor`.
+        let new_pos = match pos {
+            SeekFrom::Start(pos) => pos,
+            SeekFrom::Current(pos) => (self.pos as u64)
+                .checked_add_signed(pos)
+                .ok_or(Error::OutOfRange)?,
+            SeekFrom::End(pos) => (self.len() as u64)
+                .checked_add_signed(-pos)
+                .ok_or(Error::OutOfRange)?,
+        };
+        let new_pos: usize = new_pos.try_into().map_err(|_| Error::OutOfRange)?;
+
+        if new_pos > self.len() {
+            Err(Error::OutOfRange)
+        } else {
+            self.pos = new_pos;
+            Ok(new_pos as u64)
+        }
+    }
+
+    fn rewind(&mut self) -> Result<()> {
+        self.pos = 0;
+        Ok(())
+    }
+
+    fn stream_len(&mut self) -> Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    fn stream_position(&mut self) -> Result<u64> {
+        Ok(self.pos as u64)
+    }
+}
+
+impl fmt::Write for VecCursor {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl crate::ReadInteger for VecCursor {
+    cursor_read_bits_impl!(8);
+    cursor_read_bits_impl!(16);
+    cursor_read_bits_impl!(32);
+    cursor_read_bits_impl!(64);
+    cursor_read_bits_impl!(128);
+}
+
+impl crate::WriteInteger for VecCursor {
+    cursor_write_bits_impl!(8);
+    cursor_write_bits_impl!(16);
+    cursor_write_bits_impl!(32);
+    cursor_write_bits_impl!(64);
+    cursor_write_bits_impl!(128);
+}
+
+impl crate::ReadVarint for VecCursor {
+    fn read_varint(&mut self) -> Result<u64> {
+        let (len, value) = u64::varint_decode(self.remaining_slice())?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    fn read_signed_varint(&mut self) -> Result<i64> {
+        let (len, value) = i64::varint_decode(self.remaining_slice())?;
+        self.pos += len;
+        Ok(value)
+    }
+}
+
+impl crate::WriteVarint for VecCursor {
+    fn write_varint(&mut self, value: u64) -> Result<()> {
+        let mut encoded = [0u8; MAX_VARINT64_SIZE_BYTES];
+        let len = value.varint_encode(&mut encoded)?;
+        self.write_all(&encoded[..len])
+    }
+
+    fn write_signed_varint(&mut self, value: i64) -> Result<()> {
+        let mut encoded = [0u8; MAX_VARINT64_SIZE_BYTES];
+        let len = value.varint_encode(&mut encoded)?;
+        self.write_all(&encoded[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{test_utils::*, ReadInteger, ReadVarint, WriteInteger, WriteVarint};
+
+    #[test]
+    fn writes_grow_the_vec() {
+        let mut cursor = VecCursor::new();
+        assert!(cursor.is_empty());
+        assert_eq!(cursor.write(&[1, 2, 3]), Ok(3));
+        assert_eq!(cursor.write(&[4, 5]), Ok(2));
+        assert_eq!(cursor.position(), 5);
+        assert_eq!(cursor.as_slice(), &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn writes_overwrite_existing_data_before_growing() {
+        let mut cursor = VecCursor::from_vec(vec![1, 2, 3, 4]);
+        cursor.seek(SeekFrom::Start(2)).unwrap();
+        cursor.write_all(&[5, 6, 7]).unwrap();
+        assert_eq!(cursor.into_inner(), vec![1, 2, 5, 6, 7]);
+    }
+
+    #[test]
+    fn vectored_writes_grow_the_vec() {
+        let mut cursor = VecCursor::new();
+        assert_eq!(cursor.write_vectored(&[b"ab", b"", b"cde"]), Ok(5));
+        assert_eq!(cursor.as_slice(), b"abcde");
+    }
+
+    #[test]
+    fn reads_stop_at_end_of_data() {
+        let mut cursor = VecCursor::from(vec![1, 2, 3]);
+        let mut buf = [0u8; 4];
+        assert_eq!(cursor.read(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert_eq!(cursor.read(&mut buf), Ok(0));
+        assert_eq!(cursor.remaining(), 0);
+    }
+
+    #[test]
+    fn integers_round_trip() {
+        let mut cursor = VecCursor::new();
+        cursor.write_u8_le(&0x01).unwrap();
+        cursor.write_i16_be(&-2).unwrap();
+        cursor.write_u32_le(&0x0403_0201).unwrap();
+        cursor.write_u128_be(&u128::MAX).unwrap();
+        assert_eq!(cursor.len(), 23);
+
+        cursor.rewind().unwrap();
+        assert_eq!(cursor.read_u8_le(), Ok(0x01));
+        assert_eq!(cursor.read_i16_be(), Ok(-2));
+        assert_eq!(cursor.read_u32_le(), Ok(0x0403_0201));
+        assert_eq!(cursor.read_u128_be(), Ok(u128::MAX));
+        assert_eq!(cursor.read_u8_le(), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        let mut cursor = VecCursor::new();
+        cursor.write_varint(u64::MAX).unwrap();
+        cursor.write_signed_varint(-1).unwrap();
+        assert_eq!(cursor.len(), 11);
+
+        cursor.rewind().unwrap();
+        assert_eq!(cursor.read_varint(), Ok(u64::MAX));
+        assert_eq!(cursor.read_signed_varint(), Ok(-1));
+    }
+
+    #[test]
+    fn seek_past_end_fails() {
+        let mut cursor = VecCursor::from_vec(vec![0; 4]);
+        assert_eq!(cursor.seek(SeekFrom::Start(5)), Err(Error::OutOfRange));
+        assert_eq!(cursor.seek(SeekFrom::Start(4)), Ok(4));
+    }
+
+    #[test]
+    fn fmt_write_appends_text() {
+        use core::fmt::Write as _;
+        let mut cursor = VecCursor::new();
+        write!(cursor, "{}", 1234).unwrap();
+        assert_eq!(cursor.as_slice(), b"1234");
+    }
+
+    #[test]
+    fn rewind_resets_position_to_zero() {
+        test_rewind_resets_position_to_zero::<64, _>(VecCursor::from_vec(vec![0; 64]));
+    }
+
+    #[test]
+    fn stream_pos_reports_correct_position() {
+        test_stream_pos_reports_correct_position::<64, _>(VecCursor::from_vec(vec![0; 64]));
+    }
+
+    #[test]
+    fn stream_len_reports_correct_length() {
+        test_stream_len_reports_correct_length::<64, _>(VecCursor::from_vec(vec![0; 64]));
+    }
+}
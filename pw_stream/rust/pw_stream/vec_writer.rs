@@ -0,0 +1,199 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use core::cmp::min;
+
+use heapless::Vec;
+use pw_status::{Error, Result};
+
+use super::{TryWrite, Write};
+
+/// A fixed capacity buffer implementing [`Write`] that grows as data is
+/// written to it.
+///
+/// Unlike a [`crate::Cursor`] over a `[u8; N]`, `VecWriter` tracks the length
+/// of the data written so callers do not need to track it separately.  Writes
+/// past the capacity `N` fail with [`Error::OutOfRange`].  No allocation is
+/// performed.
+///
+/// # Example
+///
+/// ```
+/// use pw_stream::{VecWriter, Write};
+///
+/// let mut writer = VecWriter::<8>::new();
+/// writer.write_all(b"hello").unwrap();
+/// assert_eq!(writer.as_slice(), b"hello");
+///
+/// // Writing past the capacity fails.
+/// assert!(writer.write_all(b" world").is_err());
+///
+/// // Data up to the capacity is retained.
+/// let data = writer.into_inner();
+/// assert_eq!(&data[..], b"hello wo");
+/// ```
+pub struct VecWriter<const N: usize> {
+    inner: Vec<u8, N>,
+}
+
+impl<const N: usize> VecWriter<N> {
+    /// Create a new, empty `VecWriter`.
+    pub const fn new() -> Self {
+        Self { inner: Vec::new() }
+    }
+
+    /// Consumes the writer and returns the inner [`heapless::Vec`].
+    pub fn into_inner(self) -> Vec<u8, N> {
+        self.inner
+    }
+
+    /// Returns the data written so far.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.inner
+    }
+
+    /// Returns the number of bytes written.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Returns true if no data has been written.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Returns the maximum number of bytes that can be written.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the number of bytes that can be written before the writer is
+    /// full.
+    pub fn remaining(&self) -> usize {
+        N - self.inner.len()
+    }
+
+    /// Discards all data written so far.
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+}
+
+impl<const N: usize> Default for VecWriter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> From<Vec<u8, N>> for VecWriter<N> {
+    fn from(inner: Vec<u8, N>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for VecWriter<N> {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl<const N: usize> Write for VecWriter<N> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = min(self.remaining(), buf.len());
+        if len == 0 {
+            return Err(Error::OutOfRange);
+        }
+
+        // `len` is bounded by the remaining capacity so this can not fail.
+        self.inner
+            .extend_from_slice(&buf[..len])
+            .map_err(|_| Error::Internal)?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // VecWriter does not provide any buffering so flush() is a noop.
+        Ok(())
+    }
+}
+
+impl<const N: usize> TryWrite for VecWriter<N> {
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        // VecWriter never blocks.  Signal that no more space is available with
+        // `Ok(0)` instead of an error.
+        let len = min(self.remaining(), buf.len());
+        self.write(&buf[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_writer_is_empty() {
+        let writer = VecWriter::<16>::new();
+        assert!(writer.is_empty());
+        assert_eq!(writer.len(), 0);
+        assert_eq!(writer.capacity(), 16);
+        assert_eq!(writer.remaining(), 16);
+    }
+
+    #[test]
+    fn write_grows_length() {
+        let mut writer = VecWriter::<16>::new();
+        assert_eq!(writer.write(&[1, 2, 3]), Ok(3));
+        assert_eq!(writer.write(&[4, 5]), Ok(2));
+        assert_eq!(writer.len(), 5);
+        assert_eq!(writer.remaining(), 11);
+        assert_eq!(writer.as_slice(), &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn write_past_capacity_is_truncated_then_fails() {
+        let mut writer = VecWriter::<4>::new();
+        assert_eq!(writer.write(&[1, 2, 3, 4, 5, 6]), Ok(4));
+        assert_eq!(writer.write(&[7]), Err(Error::OutOfRange));
+        assert_eq!(writer.write(&[]), Ok(0));
+        assert_eq!(&writer.into_inner()[..], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn write_all_past_capacity_returns_out_of_range() {
+        let mut writer = VecWriter::<4>::new();
+        assert_eq!(writer.write_all(&[1, 2, 3, 4, 5]), Err(Error::OutOfRange));
+        assert_eq!(writer.as_slice(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn try_write_when_full_returns_zero() {
+        let mut writer = VecWriter::<4>::new();
+        assert_eq!(writer.try_write(&[1, 2, 3, 4, 5]), Ok(4));
+        assert_eq!(writer.try_write(&[5]), Ok(0));
+    }
+
+    #[test]
+    fn clear_discards_data() {
+        let mut writer = VecWriter::<4>::new();
+        writer.write_all(&[1, 2, 3, 4]).unwrap();
+        writer.clear();
+        assert!(writer.is_empty());
+        writer.write_all(&[5, 6]).unwrap();
+        assert_eq!(writer.as_slice(), &[5, 6]);
+    }
+}
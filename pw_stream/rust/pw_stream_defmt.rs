@@ -0,0 +1,189 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+#![no_std]
+#![deny(missing_docs)]
+
+//! `pw_stream_defmt` sends [`defmt`] frames over a [`pw_stream::Write`]
+//! transport.
+//!
+//! Projects migrating from `defmt` can keep their existing `defmt` logs while
+//! adopting `pw_stream` transports such as [`pw_stream::HdlcWriter`] or a
+//! UART, instead of running a second transport such as RTT alongside them.
+//!
+//! [`StreamLogger`] implements [`defmt::Logger`] and writes each encoded
+//! frame to the writer registered with [`set_writer()`].  Enabling the
+//! `global_logger` feature makes it `defmt`'s global logger:
+//!
+//! ```ignore
+//! use pw_stream_defmt::set_writer;
+//!
+//! static mut UART: MyUart = MyUart::new();
+//!
+//! // Safety: `UART` is only accessed through the logger.
+//! set_writer(unsafe { &mut *core::ptr::addr_of_mut!(UART) });
+//! defmt::info!("Booted in {} ms", 42);
+//! ```
+//!
+//! Frames are written while `defmt` holds a critical section so the writer
+//! must not block on interrupts.  Frames logged while no writer is registered,
+//! or which the writer fails to write, are dropped.
+//!
+//! To route `pw_log` messages through `defmt` instead, use the
+//! `pw_log_backend_defmt` backend.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use critical_section::{Mutex, RestoreState};
+use defmt::Encoder;
+use pw_stream::Write;
+
+/// A writer which `defmt` frames can be sent to.
+pub type Writer = &'static mut (dyn Write + Send);
+
+static WRITER: Mutex<RefCell<Option<Writer>>> = Mutex::new(RefCell::new(None));
+
+/// Sets the writer that [`StreamLogger`] sends frames to.
+///
+/// Returns the previously registered writer, if any.
+pub fn set_writer(writer: Writer) -> Option<Writer> {
+    critical_section::with(|cs| WRITER.borrow_ref_mut(cs).replace(writer))
+}
+
+/// Removes and returns the writer that [`StreamLogger`] sends frames to.
+pub fn take_writer() -> Option<Writer> {
+    critical_section::with(|cs| WRITER.borrow_ref_mut(cs).take())
+}
+
+// Writes encoded data to the registered writer, dropping it if there is no
+// writer or the write fails.
+fn write_encoded(data: &[u8]) {
+    critical_section::with(|cs| {
+        if let Some(writer) = WRITER.borrow_ref_mut(cs).as_mut() {
+            let _ = writer.write_all(data);
+        }
+    })
+}
+
+// Set while a frame is being logged.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+// State of the frame being logged.  Only accessed between `acquire()` and
+// `release()` while holding a critical section.
+static mut RESTORE_STATE: RestoreState = RestoreState::invalid();
+static mut ENCODER: Encoder = Encoder::new();
+
+/// A [`defmt::Logger`] which sends frames to the writer registered with
+/// [`set_writer()`].
+///
+/// With the `global_logger` feature enabled, this is `defmt`'s global
+/// logger.
+#[cfg_attr(feature = "global_logger", defmt::global_logger)]
+pub struct StreamLogger;
+
+// Safety: `acquire()` takes a critical section which is held until
+// `release()` and panics if called reentrantly, so the frame state is never
+// accessed concurrently.
+unsafe impl defmt::Logger for StreamLogger {
+    fn acquire() {
+        // Safety: The critical section is released in `release()`.
+        let restore = unsafe { critical_section::acquire() };
+
+        if TAKEN.load(Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly")
+        }
+        TAKEN.store(true, Ordering::Relaxed);
+
+        // Safety: The frame state is protected by the critical section.
+        unsafe {
+            RESTORE_STATE = restore;
+            (*core::ptr::addr_of_mut!(ENCODER)).start_frame(write_encoded);
+        }
+    }
+
+    unsafe fn flush() {
+        critical_section::with(|cs| {
+            if let Some(writer) = WRITER.borrow_ref_mut(cs).as_mut() {
+                let _ = writer.flush();
+            }
+        })
+    }
+
+    unsafe fn release() {
+        (*core::ptr::addr_of_mut!(ENCODER)).end_frame(write_encoded);
+        TAKEN.store(false, Ordering::Relaxed);
+        critical_section::release(RESTORE_STATE);
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        (*core::ptr::addr_of_mut!(ENCODER)).write(bytes, write_encoded);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    use defmt::Logger;
+    use pw_stream::VecWriter;
+
+    use super::*;
+
+    // Serializes tests which use the global writer.
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    fn log_frame(data: &[u8]) {
+        StreamLogger::acquire();
+        // Safety: The logger has been acquired.
+        unsafe {
+            StreamLogger::write(data);
+            StreamLogger::flush();
+            StreamLogger::release();
+        }
+    }
+
+    #[test]
+    fn frames_are_written_to_writer() {
+        let _lock = LOCK.lock().unwrap();
+        let writer: &'static mut VecWriter<64> = Box::leak(Box::new(VecWriter::new()));
+        let writer_ptr: *const VecWriter<64> = writer;
+        assert!(set_writer(writer).is_none());
+
+        log_frame(&[1, 2, 3]);
+        log_frame(&[4, 5]);
+        assert!(take_writer().is_some());
+
+        let mut expected = Vec::new();
+        let mut encoder = Encoder::new();
+        for frame in [&[1, 2, 3][..], &[4, 5]] {
+            encoder.start_frame(|data| expected.extend_from_slice(data));
+            encoder.write(frame, |data| expected.extend_from_slice(data));
+            encoder.end_frame(|data| expected.extend_from_slice(data));
+        }
+        // Safety: The writer has been removed from the logger.
+        assert_eq!(unsafe { (*writer_ptr).as_slice() }, &expected[..]);
+    }
+
+    #[test]
+    fn frames_without_writer_are_dropped() {
+        let _lock = LOCK.lock().unwrap();
+        assert!(take_writer().is_none());
+        log_frame(&[1, 2, 3]);
+        assert!(!TAKEN.load(Ordering::Relaxed));
+    }
+}
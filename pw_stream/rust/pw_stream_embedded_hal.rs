@@ -0,0 +1,466 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+#![no_std]
+#![deny(missing_docs)]
+
+//! `pw_stream_embedded_hal` exposes serial peripherals from the
+//! [`embedded-hal`](https://docs.rs/embedded-hal) ecosystem through the
+//! [`pw_stream`] traits.
+//!
+//! This allows the tokenizer, logging, and HDLC stacks to run on any chip with
+//! a HAL implementation without bespoke glue code.
+//!
+//! - [`NbSerial`] wraps a word oriented, non-blocking
+//!   [`embedded_hal_nb::serial`] peripheral.
+//! - [`IoSerial`] wraps a blocking [`embedded_io`] peripheral, the interface
+//!   used for serial ports by `embedded-hal` 1.0 based HALs.
+//!
+//! ```
+//! # use core::convert::Infallible;
+//! # struct Uart;
+//! # impl embedded_hal_nb::serial::ErrorType for Uart { type Error = Infallible; }
+//! # impl embedded_hal_nb::serial::Write for Uart {
+//! #     fn write(&mut self, _: u8) -> nb::Result<(), Infallible> { Ok(()) }
+//! #     fn flush(&mut self) -> nb::Result<(), Infallible> { Ok(()) }
+//! # }
+//! use pw_stream::{HdlcWriter, Write};
+//! use pw_stream_embedded_hal::NbSerial;
+//!
+//! let mut writer = HdlcWriter::new(NbSerial::new(Uart));
+//! writer.write_all(b"hello").unwrap();
+//! writer.finish().unwrap();
+//! ```
+
+use embedded_hal_nb::serial;
+use pw_status::{Error, Result};
+use pw_stream::{Read, TryRead, TryWrite, Write};
+
+fn status_from_serial_error(error: impl serial::Error) -> Error {
+    match error.kind() {
+        serial::ErrorKind::Overrun => Error::ResourceExhausted,
+        serial::ErrorKind::FrameFormat | serial::ErrorKind::Parity | serial::ErrorKind::Noise => {
+            Error::DataLoss
+        }
+        _ => Error::Unknown,
+    }
+}
+
+fn status_from_io_error(error: impl embedded_io::Error) -> Error {
+    use embedded_io::ErrorKind;
+    match error.kind() {
+        ErrorKind::NotFound | ErrorKind::AddrNotAvailable => Error::NotFound,
+        ErrorKind::PermissionDenied => Error::PermissionDenied,
+        ErrorKind::AlreadyExists | ErrorKind::AddrInUse => Error::AlreadyExists,
+        ErrorKind::InvalidInput => Error::InvalidArgument,
+        ErrorKind::InvalidData => Error::DataLoss,
+        ErrorKind::TimedOut => Error::DeadlineExceeded,
+        ErrorKind::Interrupted => Error::Aborted,
+        ErrorKind::Unsupported => Error::Unimplemented,
+        ErrorKind::OutOfMemory => Error::ResourceExhausted,
+        ErrorKind::WriteZero => Error::OutOfRange,
+        ErrorKind::ConnectionRefused
+        | ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::NotConnected
+        | ErrorKind::BrokenPipe => Error::FailedPrecondition,
+        _ => Error::Unknown,
+    }
+}
+
+/// A non-blocking `embedded-hal-nb` serial peripheral exposed through the
+/// `pw_stream` traits.
+///
+/// [`Read`] and [`Write`] busy wait until at least one byte can be
+/// transferred.  [`TryRead`] and [`TryWrite`] never wait and return
+/// [`Error::Unavailable`] if no bytes can be transferred.
+///
+/// As with [`std::io`], an error after some bytes were transferred is
+/// returned by the next call so the caller does not lose those bytes.
+///
+/// [`std::io`]: https://doc.rust-lang.org/std/io/
+pub struct NbSerial<S> {
+    serial: S,
+    read_error: Option<Error>,
+    write_error: Option<Error>,
+}
+
+impl<S> NbSerial<S> {
+    /// Create a new `NbSerial` wrapping `serial`.
+    pub const fn new(serial: S) -> Self {
+        Self {
+            serial,
+            read_error: None,
+            write_error: None,
+        }
+    }
+
+    /// Returns a reference to the underlying peripheral.
+    pub fn get_ref(&self) -> &S {
+        &self.serial
+    }
+
+    /// Returns a mutable reference to the underlying peripheral.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.serial
+    }
+
+    /// Consumes the `NbSerial` and returns the underlying peripheral.
+    pub fn into_inner(self) -> S {
+        self.serial
+    }
+}
+
+impl<S: serial::Read> NbSerial<S> {
+    // Reads available bytes into `buf` after the `start` bytes already read
+    // and returns the total.  An error after the first byte is saved for the
+    // next call.
+    fn read_available(&mut self, buf: &mut [u8], start: usize) -> Result<usize> {
+        for (i, byte) in buf.iter_mut().enumerate().skip(start) {
+            match self.serial.read() {
+                Ok(b) => *byte = b,
+                Err(nb::Error::WouldBlock) => return Ok(i),
+                Err(nb::Error::Other(e)) if i > 0 => {
+                    self.read_error = Some(status_from_serial_error(e));
+                    return Ok(i);
+                }
+                Err(nb::Error::Other(e)) => return Err(status_from_serial_error(e)),
+            }
+        }
+        Ok(buf.len())
+    }
+}
+
+impl<S: serial::Write> NbSerial<S> {
+    // Writes bytes from `buf` after the `start` bytes already written until
+    // the peripheral would block and returns the total.  An error after the
+    // first byte is saved for the next call.
+    fn write_available(&mut self, buf: &[u8], start: usize) -> Result<usize> {
+        for (i, byte) in buf.iter().enumerate().skip(start) {
+            match self.serial.write(*byte) {
+                Ok(()) => {}
+                Err(nb::Error::WouldBlock) => return Ok(i),
+                Err(nb::Error::Other(e)) if i > 0 => {
+                    self.write_error = Some(status_from_serial_error(e));
+                    return Ok(i);
+                }
+                Err(nb::Error::Other(e)) => return Err(status_from_serial_error(e)),
+            }
+        }
+        Ok(buf.len())
+    }
+}
+
+impl<S: serial::Read> Read for NbSerial<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if let Some(error) = self.read_error.take() {
+            return Err(error);
+        }
+        let Some(first) = buf.first_mut() else {
+            return Ok(0);
+        };
+        *first = nb::block!(self.serial.read()).map_err(status_from_serial_error)?;
+        self.read_available(buf, 1)
+    }
+}
+
+impl<S: serial::Write> Write for NbSerial<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if let Some(error) = self.write_error.take() {
+            return Err(error);
+        }
+        let Some(first) = buf.first() else {
+            return Ok(0);
+        };
+        nb::block!(self.serial.write(*first)).map_err(status_from_serial_error)?;
+        self.write_available(buf, 1)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        nb::block!(self.serial.flush()).map_err(status_from_serial_error)
+    }
+}
+
+impl<S: serial::Read> TryRead for NbSerial<S> {
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if let Some(error) = self.read_error.take() {
+            return Err(error);
+        }
+        match self.read_available(buf, 0)? {
+            0 if !buf.is_empty() => Err(Error::Unavailable),
+            len => Ok(len),
+        }
+    }
+}
+
+impl<S: serial::Write> TryWrite for NbSerial<S> {
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        if let Some(error) = self.write_error.take() {
+            return Err(error);
+        }
+        match self.write_available(buf, 0)? {
+            0 if !buf.is_empty() => Err(Error::Unavailable),
+            len => Ok(len),
+        }
+    }
+}
+
+/// A blocking `embedded-io` serial peripheral exposed through the
+/// `pw_stream` traits.
+///
+/// If the peripheral also implements [`embedded_io::ReadReady`] or
+/// [`embedded_io::WriteReady`], [`TryRead`] and [`TryWrite`] are provided and
+/// return [`Error::Unavailable`] instead of blocking.
+pub struct IoSerial<S> {
+    serial: S,
+}
+
+impl<S> IoSerial<S> {
+    /// Create a new `IoSerial` wrapping `serial`.
+    pub const fn new(serial: S) -> Self {
+        Self { serial }
+    }
+
+    /// Returns a reference to the underlying peripheral.
+    pub fn get_ref(&self) -> &S {
+        &self.serial
+    }
+
+    /// Returns a mutable reference to the underlying peripheral.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.serial
+    }
+
+    /// Consumes the `IoSerial` and returns the underlying peripheral.
+    pub fn into_inner(self) -> S {
+        self.serial
+    }
+}
+
+impl<S: embedded_io::Read> Read for IoSerial<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.serial.read(buf).map_err(status_from_io_error)
+    }
+}
+
+impl<S: embedded_io::Write> Write for IoSerial<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.serial.write(buf).map_err(status_from_io_error)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.serial.flush().map_err(status_from_io_error)
+    }
+}
+
+impl<S: embedded_io::Read + embedded_io::ReadReady> TryRead for IoSerial<S> {
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.serial.read_ready().map_err(status_from_io_error)? {
+            return Err(Error::Unavailable);
+        }
+        self.read(buf)
+    }
+}
+
+impl<S: embedded_io::Write + embedded_io::WriteReady> TryWrite for IoSerial<S> {
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.serial.write_ready().map_err(status_from_io_error)? {
+            return Err(Error::Unavailable);
+        }
+        self.write(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use heapless::{Deque, Vec};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct TestError(serial::ErrorKind);
+
+    impl serial::Error for TestError {
+        fn kind(&self) -> serial::ErrorKind {
+            self.0
+        }
+    }
+
+    // A fake UART with `rx` bytes waiting to be read and room for `tx_space`
+    // more bytes to be transmitted.  Once either runs out, transfers in that
+    // direction fail with `error` if it is set.
+    struct FakeUart {
+        rx: Deque<u8, 16>,
+        tx: Vec<u8, 16>,
+        tx_space: usize,
+        error: Option<serial::ErrorKind>,
+    }
+
+    impl FakeUart {
+        fn new(rx: &[u8], tx_space: usize) -> Self {
+            let mut uart = Self {
+                rx: Deque::new(),
+                tx: Vec::new(),
+                tx_space,
+                error: None,
+            };
+            for byte in rx {
+                uart.rx.push_back(*byte).unwrap();
+            }
+            uart
+        }
+    }
+
+    impl serial::ErrorType for FakeUart {
+        type Error = TestError;
+    }
+
+    impl serial::Read for FakeUart {
+        fn read(&mut self) -> nb::Result<u8, TestError> {
+            match (self.rx.pop_front(), self.error) {
+                (Some(byte), _) => Ok(byte),
+                (None, Some(kind)) => Err(nb::Error::Other(TestError(kind))),
+                (None, None) => Err(nb::Error::WouldBlock),
+            }
+        }
+    }
+
+    impl serial::Write for FakeUart {
+        fn write(&mut self, word: u8) -> nb::Result<(), TestError> {
+            if self.tx_space == 0 {
+                return Err(match self.error {
+                    Some(kind) => nb::Error::Other(TestError(kind)),
+                    None => nb::Error::WouldBlock,
+                });
+            }
+            self.tx_space -= 1;
+            self.tx.push(word).unwrap();
+            Ok(())
+        }
+
+        fn flush(&mut self) -> nb::Result<(), TestError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn nb_read_returns_available_bytes() {
+        let mut serial = NbSerial::new(FakeUart::new(b"abc", 0));
+        let mut buf = [0u8; 8];
+        assert_eq!(serial.read(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(serial.try_read(&mut buf), Err(Error::Unavailable));
+    }
+
+    #[test]
+    fn nb_write_stops_when_tx_is_full() {
+        let mut serial = NbSerial::new(FakeUart::new(b"", 4));
+        assert_eq!(serial.write(b"abc"), Ok(3));
+        assert_eq!(serial.try_write(b"def"), Ok(1));
+        assert_eq!(serial.try_write(b"gh"), Err(Error::Unavailable));
+        assert_eq!(serial.try_write(b""), Ok(0));
+        serial.flush().unwrap();
+        assert_eq!(&serial.into_inner().tx[..], b"abcd");
+    }
+
+    #[test]
+    fn nb_errors_map_to_status() {
+        let mut uart = FakeUart::new(b"", 0);
+        uart.error = Some(serial::ErrorKind::Parity);
+        let mut serial = NbSerial::new(uart);
+        let mut buf = [0u8; 8];
+        assert_eq!(serial.read(&mut buf), Err(Error::DataLoss));
+
+        serial.get_mut().error = Some(serial::ErrorKind::Overrun);
+        assert_eq!(serial.try_read(&mut buf), Err(Error::ResourceExhausted));
+    }
+
+    #[test]
+    fn nb_errors_after_partial_transfer_are_returned_next() {
+        let mut uart = FakeUart::new(b"ab", 2);
+        uart.error = Some(serial::ErrorKind::Noise);
+        let mut serial = NbSerial::new(uart);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(serial.read(&mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"ab");
+        assert_eq!(serial.write(b"abc"), Ok(2));
+
+        serial.get_mut().error = None;
+        assert_eq!(serial.try_read(&mut buf), Err(Error::DataLoss));
+        assert_eq!(serial.try_read(&mut buf), Err(Error::Unavailable));
+        assert_eq!(serial.try_write(b"c"), Err(Error::DataLoss));
+        assert_eq!(serial.try_write(b"c"), Err(Error::Unavailable));
+    }
+
+    // A fake `embedded-io` UART.
+    struct FakeIoUart {
+        rx: &'static [u8],
+        tx: Vec<u8, 16>,
+    }
+
+    impl embedded_io::ErrorType for FakeIoUart {
+        type Error = Infallible;
+    }
+
+    impl embedded_io::Read for FakeIoUart {
+        fn read(&mut self, buf: &mut [u8]) -> core::result::Result<usize, Infallible> {
+            let len = buf.len().min(self.rx.len());
+            buf[..len].copy_from_slice(&self.rx[..len]);
+            self.rx = &self.rx[len..];
+            Ok(len)
+        }
+    }
+
+    impl embedded_io::ReadReady for FakeIoUart {
+        fn read_ready(&mut self) -> core::result::Result<bool, Infallible> {
+            Ok(!self.rx.is_empty())
+        }
+    }
+
+    impl embedded_io::Write for FakeIoUart {
+        fn write(&mut self, buf: &[u8]) -> core::result::Result<usize, Infallible> {
+            let len = buf.len().min(self.tx.capacity() - self.tx.len());
+            self.tx.extend_from_slice(&buf[..len]).unwrap();
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> core::result::Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn io_serial_reads_and_writes() {
+        let mut serial = IoSerial::new(FakeIoUart {
+            rx: b"abc",
+            tx: Vec::new(),
+        });
+        let mut buf = [0u8; 2];
+        assert_eq!(serial.try_read(&mut buf), Ok(2));
+        assert_eq!(serial.read(&mut buf), Ok(1));
+        assert_eq!(serial.try_read(&mut buf), Err(Error::Unavailable));
+
+        serial.write_all(b"hello").unwrap();
+        serial.flush().unwrap();
+        assert_eq!(&serial.get_ref().tx[..], b"hello");
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+#![no_std]
+#![deny(missing_docs)]
+
+//! `pw_stream_rtt` sends data to a debug probe over SEGGER RTT up-channels.
+//!
+//! RTT is available as soon as the core is running and a probe is attached,
+//! making it useful for emitting tokenized logs during bring-up before any
+//! other transport works.  Channels are created with
+//! [`rtt_target::rtt_init!`] and handed to this crate.
+//!
+//! - [`RttWriter`] exposes an up-channel through [`pw_stream::Write`].
+//! - [`RttMessageWriter`] implements [`pw_tokenizer::MessageWriter`] over a
+//!   channel registered with [`set_message_channel()`], writing each
+//!   tokenized message to the channel atomically.
+//!
+//! ```no_run
+//! use pw_stream_rtt::{set_message_channel, RttMessageWriter};
+//! use pw_tokenizer::tokenize_to_writer;
+//! use rtt_target::rtt_init;
+//!
+//! let channels = rtt_init! {
+//!     up: {
+//!         0: { size: 1024, name: "Tokenized" }
+//!     }
+//! };
+//! set_message_channel(channels.up.0);
+//!
+//! tokenize_to_writer!(RttMessageWriter, "Booted in %d ms", 42).unwrap();
+//! ```
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use heapless::Vec;
+use pw_status::{Error, Result};
+use pw_stream::{TryWrite, Write};
+use pw_tokenizer::MessageWriter;
+use rtt_target::{ChannelMode, UpChannel};
+
+// Writes `buf` to `channel` returning the number of bytes made visible to the
+// probe.
+fn write_channel(channel: &mut UpChannel, buf: &[u8]) -> usize {
+    let len = channel.write(buf);
+    // In `NoBlockSkip` mode a write which does not fit is discarded entirely
+    // but `UpChannel::write()` still reports the bytes it staged.
+    if len < buf.len() && channel.mode() == ChannelMode::NoBlockSkip {
+        0
+    } else {
+        len
+    }
+}
+
+/// An RTT up-channel exposed through [`pw_stream::Write`].
+///
+/// The behavior of writes when the channel's buffer is full depends on the
+/// channel's [`ChannelMode`].  In the default `NoBlockSkip` mode
+/// writes that do not fit are dropped and `Ok(0)` is returned.
+pub struct RttWriter {
+    channel: UpChannel,
+}
+
+impl RttWriter {
+    /// Create a new `RttWriter` writing to `channel`.
+    pub const fn new(channel: UpChannel) -> Self {
+        Self { channel }
+    }
+
+    /// Returns a mutable reference to the underlying channel.
+    pub fn get_mut(&mut self) -> &mut UpChannel {
+        &mut self.channel
+    }
+
+    /// Consumes the `RttWriter` and returns the underlying channel.
+    pub fn into_inner(self) -> UpChannel {
+        self.channel
+    }
+}
+
+impl Write for RttWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(write_channel(&mut self.channel, buf))
+    }
+
+    /// Data is visible to the probe as soon as it is written so `flush()` is
+    /// a noop.
+    ///
+    /// Unlike [`UpChannel::flush()`], this does not wait for the probe to read
+    /// the data, which would never return if no probe is attached.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl TryWrite for RttWriter {
+    /// Writes to the channel without blocking.
+    ///
+    /// The channel must not be in `BlockIfFull` mode.
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        match write_channel(&mut self.channel, buf) {
+            0 if !buf.is_empty() => Err(Error::Unavailable),
+            len => Ok(len),
+        }
+    }
+}
+
+static MESSAGE_CHANNEL: Mutex<RefCell<Option<UpChannel>>> = Mutex::new(RefCell::new(None));
+
+/// Sets the channel used by [`RttMessageWriter`].
+///
+/// Returns the previously registered channel, if any.
+pub fn set_message_channel(channel: UpChannel) -> Option<UpChannel> {
+    critical_section::with(|cs| MESSAGE_CHANNEL.borrow_ref_mut(cs).replace(channel))
+}
+
+/// Removes and returns the channel used by [`RttMessageWriter`].
+pub fn take_message_channel() -> Option<UpChannel> {
+    critical_section::with(|cs| MESSAGE_CHANNEL.borrow_ref_mut(cs).take())
+}
+
+/// A [`MessageWriter`] which writes messages to the RTT channel registered
+/// with [`set_message_channel()`].
+///
+/// Messages of up to `N` bytes are buffered and written to the channel in a
+/// single operation when finalized so that messages from different contexts
+/// are never interleaved.
+pub struct RttMessageWriter<const N: usize = 64> {
+    buffer: Vec<u8, N>,
+}
+
+impl<const N: usize> MessageWriter for RttMessageWriter<N> {
+    fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.buffer
+            .extend_from_slice(data)
+            .map_err(|_| Error::OutOfRange)
+    }
+
+    fn remaining(&self) -> usize {
+        N - self.buffer.len()
+    }
+
+    /// Writes the message to the registered channel.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - No channel has been registered.
+    /// - [`Error::ResourceExhausted`] - The channel did not have room for the
+    ///   message.
+    fn finalize(self) -> Result<()> {
+        critical_section::with(|cs| {
+            let mut channel = MESSAGE_CHANNEL.borrow_ref_mut(cs);
+            let channel = channel.as_mut().ok_or(Error::FailedPrecondition)?;
+            if write_channel(channel, &self.buffer) != self.buffer.len() {
+                return Err(Error::ResourceExhausted);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rtt_target::rtt_init;
+
+    use super::*;
+
+    // `rtt_init!` may only be called once so all functionality is tested
+    // together.
+    #[test]
+    fn writes_to_rtt_channels() {
+        let channels = rtt_init! {
+            up: {
+                0: { size: 16, mode: ChannelMode::NoBlockSkip }
+                1: { size: 16, mode: ChannelMode::NoBlockSkip }
+            }
+        };
+
+        let mut writer = RttWriter::new(channels.up.0);
+        assert_eq!(writer.write(b"hello"), Ok(5));
+        // Writes that do not fit in the remaining space are skipped.
+        assert_eq!(writer.write(&[0u8; 12]), Ok(0));
+        assert_eq!(writer.try_write(&[0u8; 12]), Err(Error::Unavailable));
+        writer.get_mut().set_mode(ChannelMode::NoBlockTrim);
+        assert_eq!(writer.try_write(&[0u8; 12]), Ok(10));
+        assert!(!writer.into_inner().is_empty());
+
+        // Messages fail until a channel is registered.
+        let mut message = RttMessageWriter::<8>::new();
+        message.write(b"abc").unwrap();
+        assert_eq!(message.remaining(), 5);
+        assert_eq!(message.finalize(), Err(Error::FailedPrecondition));
+
+        assert!(set_message_channel(channels.up.1).is_none());
+        let mut message = RttMessageWriter::<8>::new();
+        assert_eq!(message.write(b"too long!"), Err(Error::OutOfRange));
+        message.write(b"abcdefgh").unwrap();
+        assert_eq!(message.finalize(), Ok(()));
+
+        let mut message = RttMessageWriter::<8>::new();
+        message.write(b"abcdefgh").unwrap();
+        assert_eq!(message.finalize(), Err(Error::ResourceExhausted));
+
+        assert!(!take_message_channel().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+#![no_std]
+#![deny(missing_docs)]
+
+//! `pw_stream_semihosting` writes to the host's console using ARM
+//! semihosting.
+//!
+//! Semihosting requires no peripherals on the device, only a debugger or an
+//! emulator such as QEMU, making it useful for emitting tokenized output
+//! before any real transport works.  Every semihosting operation halts the
+//! core while the host services it so it is much slower than a hardware
+//! transport.
+//!
+//! Semihosting support is enabled with the `cortex-m` feature:
+//!
+//! - [`SemihostingWriter`] exposes the host's stdout or stderr through
+//!   [`pw_stream::Write`].
+//! - [`SemihostingMessageWriter`] implements [`pw_tokenizer::MessageWriter`],
+//!   printing each tokenized message to the host's stdout as a line of
+//!   prefixed Base64 which Pigweed's detokenization tools recognize.
+//!
+//! ```ignore
+//! use pw_stream_semihosting::SemihostingMessageWriter;
+//! use pw_tokenizer::tokenize_to_writer;
+//!
+//! // Prints a line like `$kCnFPSo=` to the host's stdout.
+//! tokenize_to_writer!(SemihostingMessageWriter, "Booted in %d ms", 42).unwrap();
+//! ```
+//!
+//! [`write_message()`] formats messages the same way for any writer and is
+//! available without the `cortex-m` feature.
+
+#[cfg(feature = "cortex-m")]
+use core::cell::Cell;
+
+#[cfg(feature = "cortex-m")]
+use cortex_m_semihosting::hio::{self, HostStream};
+#[cfg(feature = "cortex-m")]
+use critical_section::Mutex;
+#[cfg(feature = "cortex-m")]
+use heapless::Vec;
+use pw_base64::{Base64Writer, TOKEN_PREFIX};
+#[cfg(feature = "cortex-m")]
+use pw_status::Error;
+use pw_status::Result;
+#[cfg(feature = "cortex-m")]
+use pw_stream::BufWriter;
+use pw_stream::Write;
+#[cfg(feature = "cortex-m")]
+use pw_tokenizer::MessageWriter;
+
+/// Writes `message` to `writer` as a line of Base64 prefixed with
+/// [`TOKEN_PREFIX`], then flushes `writer`.
+///
+/// # Example
+///
+/// ```
+/// use pw_stream::VecWriter;
+/// use pw_stream_semihosting::write_message;
+///
+/// let mut writer = VecWriter::<16>::new();
+/// write_message(&mut writer, &[0x31, 0x3d, 0x4b, 0xa1]).unwrap();
+/// assert_eq!(writer.as_slice(), b"$MT1LoQ==\n");
+/// ```
+pub fn write_message<W: Write>(writer: &mut W, message: &[u8]) -> Result<()> {
+    let mut encoder = Base64Writer::with_prefix(&mut *writer, TOKEN_PREFIX);
+    encoder.write_all(message)?;
+    encoder.finish()?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+/// The host's stdout or stderr exposed through [`pw_stream::Write`].
+///
+/// Each write is a separate semihosting operation.  Wrap the writer in a
+/// [`pw_stream::BufWriter`] to reduce the number of operations when writing
+/// many small pieces of data.
+#[cfg(feature = "cortex-m")]
+pub struct SemihostingWriter {
+    stream: HostStream,
+}
+
+#[cfg(feature = "cortex-m")]
+impl SemihostingWriter {
+    /// Opens the host's stdout.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The host refused to open the stream.
+    pub fn stdout() -> Result<Self> {
+        hio::hstdout()
+            .map(|stream| Self { stream })
+            .map_err(|()| Error::FailedPrecondition)
+    }
+
+    /// Opens the host's stderr.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The host refused to open the stream.
+    pub fn stderr() -> Result<Self> {
+        hio::hstderr()
+            .map(|stream| Self { stream })
+            .map_err(|()| Error::FailedPrecondition)
+    }
+}
+
+#[cfg(feature = "cortex-m")]
+impl Write for SemihostingWriter {
+    /// Writes all of `buf` to the host.
+    ///
+    /// # Errors
+    /// - [`Error::Unknown`] - The host failed to write the data.
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.stream
+            .write_all(buf)
+            .map(|()| buf.len())
+            .map_err(|()| Error::Unknown)
+    }
+
+    /// Data is passed to the host as soon as it is written so `flush()` is a
+    /// noop.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// The host's stdout, opened on first use.  Opening a stream is a semihosting
+// operation so the stream is reused for all messages.
+#[cfg(feature = "cortex-m")]
+static MESSAGE_STREAM: Mutex<Cell<Option<HostStream>>> = Mutex::new(Cell::new(None));
+
+// Number of bytes of encoded output buffered before being passed to the host.
+#[cfg(feature = "cortex-m")]
+const LINE_BUFFER_SIZE: usize = 64;
+
+/// A [`MessageWriter`] which prints messages to the host's stdout with
+/// [`write_message()`].
+///
+/// Messages of up to `N` bytes are buffered and printed in a critical section
+/// when finalized so that messages from different contexts are never
+/// interleaved.
+#[cfg(feature = "cortex-m")]
+pub struct SemihostingMessageWriter<const N: usize = 64> {
+    buffer: Vec<u8, N>,
+}
+
+#[cfg(feature = "cortex-m")]
+impl<const N: usize> MessageWriter for SemihostingMessageWriter<N> {
+    fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.buffer
+            .extend_from_slice(data)
+            .map_err(|_| Error::OutOfRange)
+    }
+
+    fn remaining(&self) -> usize {
+        N - self.buffer.len()
+    }
+
+    /// Prints the message to the host's stdout.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The host refused to open stdout.
+    /// - [`Error::Unknown`] - The host failed to write the message.
+    fn finalize(self) -> Result<()> {
+        critical_section::with(|cs| {
+            let stream = match MESSAGE_STREAM.borrow(cs).get() {
+                Some(stream) => stream,
+                None => {
+                    let stream = hio::hstdout().map_err(|()| Error::FailedPrecondition)?;
+                    MESSAGE_STREAM.borrow(cs).set(Some(stream));
+                    stream
+                }
+            };
+            let mut writer = BufWriter::<LINE_BUFFER_SIZE, _>::new(SemihostingWriter { stream });
+            write_message(&mut writer, &self.buffer)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_status::Error;
+    use pw_stream::VecWriter;
+
+    use super::*;
+
+    #[test]
+    fn write_message_writes_prefixed_line() {
+        let mut writer = VecWriter::<32>::new();
+        write_message(&mut writer, b"abc").unwrap();
+        write_message(&mut writer, b"abcd").unwrap();
+        assert_eq!(writer.as_slice(), b"$YWJj\n$YWJjZA==\n");
+    }
+
+    #[test]
+    fn write_message_with_empty_message_writes_empty_line() {
+        let mut writer = VecWriter::<4>::new();
+        write_message(&mut writer, &[]).unwrap();
+        assert_eq!(writer.as_slice(), b"\n");
+    }
+
+    #[test]
+    fn write_message_reports_writer_errors() {
+        let mut writer = VecWriter::<4>::new();
+        assert_eq!(write_message(&mut writer, b"abcd"), Err(Error::OutOfRange));
+    }
+}
@@ -0,0 +1,433 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+#![deny(missing_docs)]
+
+//! `pw_stream_serial` provides host side access to serial ports through the
+//! `pw_stream` traits.
+//!
+//! [`SerialPort`] opens a Linux tty device, such as a USB serial adapter,
+//! configures it for raw 8N1 communication using termios, and exposes it
+//! through [`pw_stream::Read`] and [`pw_stream::Write`].  This allows host
+//! tools like detokenizers and HDLC decoders to talk to devices directly.
+//!
+//! ```no_run
+//! use pw_stream::{Read, Write};
+//! use pw_stream_serial::SerialPort;
+//!
+//! let mut port = SerialPort::open("/dev/ttyACM0", 115200).unwrap();
+//! port.write_all(b"hello").unwrap();
+//!
+//! let mut buf = [0u8; 64];
+//! let len = port.read(&mut buf).unwrap();
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::Duration;
+
+use pw_status::{Error, Result};
+use pw_stream::{IoAdapter, Read, TryRead, TryWrite, Write};
+
+// The longest read timeout supported by termios, in tenths of a second.
+const MAX_READ_TIMEOUT_DECISECONDS: u128 = 255;
+
+// Supported baud rates and their termios speed constants.
+const BAUD_RATES: &[(u32, libc::speed_t)] = &[
+    (1200, libc::B1200),
+    (2400, libc::B2400),
+    (4800, libc::B4800),
+    (9600, libc::B9600),
+    (19200, libc::B19200),
+    (38400, libc::B38400),
+    (57600, libc::B57600),
+    (115200, libc::B115200),
+    (230400, libc::B230400),
+    (460800, libc::B460800),
+    (500000, libc::B500000),
+    (576000, libc::B576000),
+    (921600, libc::B921600),
+    (1000000, libc::B1000000),
+    (1500000, libc::B1500000),
+    (2000000, libc::B2000000),
+    (3000000, libc::B3000000),
+    (4000000, libc::B4000000),
+];
+
+// Converts the return value of a libc call into a `Result`.
+fn check(ret: libc::c_int) -> Result<libc::c_int> {
+    if ret < 0 {
+        Err(Error::from(io::Error::last_os_error()))
+    } else {
+        Ok(ret)
+    }
+}
+
+// Retries the libc call `f` while it is interrupted by a signal.
+fn retry_interrupted(mut f: impl FnMut() -> libc::c_int) -> Result<libc::c_int> {
+    loop {
+        let ret = f();
+        if ret >= 0 {
+            return Ok(ret);
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(Error::from(error));
+        }
+    }
+}
+
+/// A serial port exposed through the `pw_stream` traits.
+///
+/// The port is configured for raw communication with 8 data bits, no parity,
+/// one stop bit, and no flow control.  Reads block until at least one byte is
+/// available unless a timeout is set with [`SerialPort::set_read_timeout()`].
+pub struct SerialPort {
+    inner: IoAdapter<File>,
+    read_timeout: Option<Duration>,
+}
+
+impl SerialPort {
+    /// Opens the tty device at `path` and configures it to communicate at
+    /// `baud_rate`.
+    ///
+    /// # Errors
+    /// - [`Error::NotFound`] - `path` does not exist.
+    /// - [`Error::PermissionDenied`] - The device can not be opened by the
+    ///   current user.
+    /// - [`Error::FailedPrecondition`] - `path` is not a tty device.
+    /// - [`Error::InvalidArgument`] - `baud_rate` is not supported.
+    pub fn open(path: impl AsRef<Path>, baud_rate: u32) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)?;
+        // Safety: The file descriptor is valid for the lifetime of `file`.
+        if unsafe { libc::isatty(file.as_raw_fd()) } == 0 {
+            return Err(Error::FailedPrecondition);
+        }
+
+        let mut port = Self {
+            inner: IoAdapter::new(file),
+            read_timeout: None,
+        };
+        port.update_termios(|termios| {
+            // Safety: `termios` is a valid, initialized struct.
+            unsafe { libc::cfmakeraw(termios) };
+            termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+            termios.c_cflag &= !(libc::CSTOPB | libc::CRTSCTS);
+            termios.c_iflag &= !(libc::IXON | libc::IXOFF | libc::IXANY);
+            set_read_timeout(termios, None)?;
+            set_speed(termios, baud_rate)
+        })?;
+        Ok(port)
+    }
+
+    /// Returns the baud rate the port is configured for.
+    pub fn baud_rate(&self) -> Result<u32> {
+        let termios = self.termios()?;
+        // Safety: `termios` is a valid, initialized struct.
+        let speed = unsafe { libc::cfgetospeed(&termios) };
+        BAUD_RATES
+            .iter()
+            .find(|(_, s)| *s == speed)
+            .map(|(baud_rate, _)| *baud_rate)
+            .ok_or(Error::Unknown)
+    }
+
+    /// Changes the baud rate of the port.
+    ///
+    /// Data which has been written but not yet transmitted is sent before
+    /// the baud rate changes.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `baud_rate` is not supported.
+    pub fn set_baud_rate(&mut self, baud_rate: u32) -> Result<()> {
+        self.update_termios(|termios| set_speed(termios, baud_rate))
+    }
+
+    /// Returns the read timeout.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Sets the maximum time [`Read::read()`] waits for data.
+    ///
+    /// If `timeout` is `None`, reads block until data is available.  Timeouts
+    /// have a resolution of 100 milliseconds.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `timeout` is zero or longer than 25.5
+    ///   seconds.  Use [`TryRead::try_read()`] to read without waiting.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.update_termios(|termios| set_read_timeout(termios, timeout))?;
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    /// Discards any data which has been received but not read and any data
+    /// which has been written but not transmitted.
+    pub fn clear(&mut self) -> Result<()> {
+        // Safety: The file descriptor is valid for the lifetime of `self`.
+        check(unsafe { libc::tcflush(self.fd(), libc::TCIOFLUSH) })?;
+        Ok(())
+    }
+
+    /// Returns a reference to the underlying file.
+    pub fn get_ref(&self) -> &File {
+        self.inner.get_ref()
+    }
+
+    /// Consumes the `SerialPort` and returns the underlying file.
+    pub fn into_inner(self) -> File {
+        self.inner.into_inner()
+    }
+
+    fn fd(&self) -> RawFd {
+        self.inner.get_ref().as_raw_fd()
+    }
+
+    fn termios(&self) -> Result<libc::termios> {
+        // Safety: `termios` is plain data which is fully initialized by
+        // `tcgetattr()` before use.
+        let mut termios = unsafe { core::mem::zeroed() };
+        // Safety: The file descriptor is valid for the lifetime of `self`.
+        check(unsafe { libc::tcgetattr(self.fd(), &mut termios) })?;
+        Ok(termios)
+    }
+
+    fn update_termios(
+        &mut self,
+        update: impl FnOnce(&mut libc::termios) -> Result<()>,
+    ) -> Result<()> {
+        let mut termios = self.termios()?;
+        update(&mut termios)?;
+        // Safety: The file descriptor is valid for the lifetime of `self`.
+        check(unsafe { libc::tcsetattr(self.fd(), libc::TCSADRAIN, &termios) })?;
+        Ok(())
+    }
+
+    // Returns true if the port is ready for the operation in `events` without
+    // blocking.
+    fn poll(&self, events: libc::c_short) -> Result<bool> {
+        let mut pollfd = libc::pollfd {
+            fd: self.fd(),
+            events,
+            revents: 0,
+        };
+        // Safety: `pollfd` is valid for the duration of the call.
+        let ready = retry_interrupted(|| unsafe { libc::poll(&mut pollfd, 1, 0) })?;
+        Ok(ready > 0)
+    }
+}
+
+fn set_speed(termios: &mut libc::termios, baud_rate: u32) -> Result<()> {
+    let (_, speed) = BAUD_RATES
+        .iter()
+        .find(|(b, _)| *b == baud_rate)
+        .ok_or(Error::InvalidArgument)?;
+    // Safety: `termios` is a valid, initialized struct.
+    check(unsafe { libc::cfsetspeed(termios, *speed) })?;
+    Ok(())
+}
+
+fn set_read_timeout(termios: &mut libc::termios, timeout: Option<Duration>) -> Result<()> {
+    let (min, time) = match timeout {
+        // Block until at least one byte is received.
+        None => (1, 0),
+        Some(timeout) => {
+            let deciseconds = timeout.as_millis().div_ceil(100);
+            if deciseconds == 0 || deciseconds > MAX_READ_TIMEOUT_DECISECONDS {
+                return Err(Error::InvalidArgument);
+            }
+            // Return as soon as any data is received or the timeout expires.
+            (0, deciseconds as libc::cc_t)
+        }
+    };
+    termios.c_cc[libc::VMIN] = min;
+    termios.c_cc[libc::VTIME] = time;
+    Ok(())
+}
+
+impl Read for SerialPort {
+    /// Reads data received by the port.
+    ///
+    /// # Errors
+    /// - [`Error::DeadlineExceeded`] - No data was received before the read
+    ///   timeout expired.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.inner.read(buf)? {
+            0 if !buf.is_empty() && self.read_timeout.is_some() => Err(Error::DeadlineExceeded),
+            len => Ok(len),
+        }
+    }
+}
+
+impl Write for SerialPort {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.inner.write(buf)
+    }
+
+    /// Waits until all data written has been transmitted.
+    fn flush(&mut self) -> Result<()> {
+        // Safety: The file descriptor is valid for the lifetime of `self`.
+        retry_interrupted(|| unsafe { libc::tcdrain(self.fd()) })?;
+        Ok(())
+    }
+}
+
+impl TryRead for SerialPort {
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.poll(libc::POLLIN)? {
+            return Err(Error::Unavailable);
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl TryWrite for SerialPort {
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.poll(libc::POLLOUT)? {
+            return Err(Error::Unavailable);
+        }
+        self.inner.write(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+    use std::os::unix::io::FromRawFd;
+
+    use super::*;
+
+    // Opens a pseudoterminal, returning the controlling side and the path of
+    // the device side.
+    fn open_pty() -> (IoAdapter<File>, String) {
+        // Safety: The returned file descriptors are checked before use.
+        unsafe {
+            let fd = check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY)).unwrap();
+            check(libc::grantpt(fd)).unwrap();
+            check(libc::unlockpt(fd)).unwrap();
+            let mut name = [0 as libc::c_char; 64];
+            assert_eq!(libc::ptsname_r(fd, name.as_mut_ptr(), name.len()), 0);
+            let path = CStr::from_ptr(name.as_ptr()).to_str().unwrap().to_string();
+            (IoAdapter::new(File::from_raw_fd(fd)), path)
+        }
+    }
+
+    #[test]
+    fn open_configures_baud_rate() {
+        let (_pty, path) = open_pty();
+        let mut port = SerialPort::open(&path, 115200).unwrap();
+        assert_eq!(port.baud_rate(), Ok(115200));
+        port.set_baud_rate(9600).unwrap();
+        assert_eq!(port.baud_rate(), Ok(9600));
+        assert_eq!(port.set_baud_rate(1234), Err(Error::InvalidArgument));
+        assert_eq!(port.baud_rate(), Ok(9600));
+    }
+
+    #[test]
+    fn open_reports_errors() {
+        assert_eq!(
+            SerialPort::open("/nonexistent/tty", 115200).err(),
+            Some(Error::NotFound)
+        );
+        assert_eq!(
+            SerialPort::open("/dev/null", 115200).err(),
+            Some(Error::FailedPrecondition)
+        );
+        let (_pty, path) = open_pty();
+        assert_eq!(
+            SerialPort::open(&path, 1234).err(),
+            Some(Error::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn reads_and_writes_data() {
+        let (mut pty, path) = open_pty();
+        let mut port = SerialPort::open(&path, 115200).unwrap();
+
+        port.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        pty.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        pty.write_all(b"pong").unwrap();
+        port.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[test]
+    fn raw_mode_passes_bytes_unmodified() {
+        let (mut pty, path) = open_pty();
+        let mut port = SerialPort::open(&path, 115200).unwrap();
+
+        let data: Vec<u8> = (0..=255).collect();
+        pty.write_all(&data).unwrap();
+        let mut buf = [0u8; 256];
+        port.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &data[..]);
+    }
+
+    #[test]
+    fn try_read_without_data_is_unavailable() {
+        let (mut pty, path) = open_pty();
+        let mut port = SerialPort::open(&path, 115200).unwrap();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(port.try_read(&mut buf), Err(Error::Unavailable));
+        pty.write_all(b"a").unwrap();
+        let result = loop {
+            match port.try_read(&mut buf) {
+                Err(Error::Unavailable) => std::thread::sleep(Duration::from_millis(1)),
+                result => break result,
+            }
+        };
+        assert_eq!(result, Ok(1));
+        assert_eq!(port.try_write(b"b"), Ok(1));
+    }
+
+    #[test]
+    fn read_times_out() {
+        let (_pty, path) = open_pty();
+        let mut port = SerialPort::open(&path, 115200).unwrap();
+
+        assert_eq!(port.read_timeout(), None);
+        assert_eq!(
+            port.set_read_timeout(Some(Duration::ZERO)),
+            Err(Error::InvalidArgument)
+        );
+        assert_eq!(
+            port.set_read_timeout(Some(Duration::from_secs(26))),
+            Err(Error::InvalidArgument)
+        );
+        port.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert_eq!(port.read_timeout(), Some(Duration::from_millis(100)));
+
+        let mut buf = [0u8; 4];
+        assert_eq!(port.read(&mut buf), Err(Error::DeadlineExceeded));
+    }
+}
@@ -0,0 +1,358 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_sync` provides synchronization primitives which are portable across
+//! bare-metal targets, RTOSes, and hosts:
+//!
+//! - [`Mutex`] protects data shared between threads.
+//! - [`InterruptSpinLock`] protects data shared with interrupts, or between
+//!   cores.
+//! - [`ThreadNotification`] wakes a thread from another thread or an
+//!   interrupt.
+//!
+//! ```
+//! use pw_sync::{Mutex, ThreadNotification};
+//!
+//! static COUNT: Mutex<u32> = Mutex::new(0);
+//! static DONE: ThreadNotification = ThreadNotification::new();
+//!
+//! *COUNT.lock() += 1;
+//! DONE.release();
+//!
+//! DONE.acquire();
+//! assert_eq!(*COUNT.lock(), 1);
+//! ```
+//!
+//! `pw_sync` is a facade: [`Mutex`] and [`ThreadNotification`] are
+//! implemented by the backend selected with the `//pw_sync/rust:pw_sync_backend`
+//! label flag.  The bare-metal backend uses critical sections and the `std`
+//! backend uses [`std::sync`](https://doc.rust-lang.org/std/sync/).  An RTOS
+//! backend implements the traits in `pw_sync_backend_api`.
+//! [`InterruptSpinLock`] is built on the `critical-section` crate, whose
+//! implementation is provided by the target's support crate, and is locked for
+//! the duration of a closure.
+//!
+//! Timed waits, such as [`Mutex::try_lock_for()`], are measured with the
+//! `pw_chrono` system clock.
+#![no_std]
+#![deny(missing_docs)]
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use pw_chrono::{Duration, Instant};
+use pw_status::{Error, Result};
+use pw_sync_backend_api::{RawMutex, RawThreadNotification};
+
+/// A lock which blocks threads waiting for it, protecting data of type `T`.
+///
+/// Mutexes may not be used from interrupts; use an [`InterruptSpinLock`]
+/// instead.  They are not recursive.
+pub struct Mutex<T: ?Sized> {
+    raw: pw_sync_backend::RawMutex,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Creates an unlocked mutex holding `value`.
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: pw_sync_backend::RawMutex::INIT,
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes the mutex, returning its data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Locks the mutex, blocking until it is available.
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.raw.lock();
+        MutexGuard::new(self)
+    }
+
+    /// Locks the mutex if it is available.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.raw.try_lock().then(|| MutexGuard::new(self))
+    }
+
+    /// Locks the mutex, blocking for at most `timeout` until it is
+    /// available.
+    pub fn try_lock_for(&self, timeout: Duration) -> Option<MutexGuard<'_, T>> {
+        self.try_lock_until(pw_chrono::now() + timeout)
+    }
+
+    /// Locks the mutex, blocking until it is available or the system clock
+    /// reaches `deadline`.
+    pub fn try_lock_until(&self, deadline: Instant) -> Option<MutexGuard<'_, T>> {
+        self.raw
+            .try_lock_until(deadline)
+            .then(|| MutexGuard::new(self))
+    }
+
+    /// Returns the data without locking, since the mutex is borrowed
+    /// exclusively.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Access to the data of a locked [`Mutex`], which is unlocked when the
+/// guard is dropped.
+#[must_use = "the mutex is unlocked when the guard is dropped"]
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    // Mutexes must be unlocked by the thread which locked them.
+    _not_send: PhantomData<*const ()>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    fn new(mutex: &'a Mutex<T>) -> Self {
+        Self {
+            mutex,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The mutex is locked by this guard.
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The mutex is locked by this guard.
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // SAFETY: The mutex was locked by this guard on this thread.
+        unsafe { self.mutex.raw.unlock() }
+    }
+}
+
+/// A lock which may be used from interrupts, protecting data of type `T`.
+///
+/// The data is only accessed in a closure passed to
+/// [`lock()`](InterruptSpinLock::lock), which runs in a critical section.
+/// The critical section disables interrupts and excludes other cores on
+/// multi-core targets, so the closure should return quickly.  Scoping the
+/// lock to a closure guarantees that nested critical sections are released
+/// in the reverse order they were acquired, which the `critical-section`
+/// crate requires.
+///
+/// ```
+/// use pw_sync::InterruptSpinLock;
+///
+/// static RX_BYTES: InterruptSpinLock<u32> = InterruptSpinLock::new(0);
+///
+/// RX_BYTES.lock(|count| *count += 1)?;
+/// assert_eq!(RX_BYTES.lock(|count| *count), Ok(1));
+/// # Ok::<(), pw_status::Error>(())
+/// ```
+pub struct InterruptSpinLock<T: ?Sized> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for InterruptSpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for InterruptSpinLock<T> {}
+
+impl<T> InterruptSpinLock<T> {
+    /// Creates an unlocked lock holding `value`.
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes the lock, returning its data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> InterruptSpinLock<T> {
+    /// Locks the lock and calls `f` with its data, returning what `f`
+    /// returns.
+    ///
+    /// Other cores and interrupts wait for the critical section to enter, so
+    /// the lock can only already be locked if it is locked again from `f`.
+    /// Waiting for it would never finish, so an error is returned instead.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - The lock is already locked by the
+    ///   caller.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R> {
+        critical_section::with(|_| {
+            // Other cores are excluded by the critical section, so the flag
+            // only needs atomic loads and stores.
+            if self.locked.load(Ordering::Acquire) {
+                return Err(Error::FailedPrecondition);
+            }
+            self.locked.store(true, Ordering::Release);
+            // Clears the flag even if `f` panics.
+            let _unlock = Unlock(&self.locked);
+            // SAFETY: The flag was clear, so no other reference to the data
+            // exists, and it stays set until `f` returns.
+            Ok(f(unsafe { &mut *self.data.get() }))
+        })
+    }
+
+    /// Returns the data without locking, since the lock is borrowed
+    /// exclusively.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+// Clears an `InterruptSpinLock`'s flag when dropped.
+struct Unlock<'a>(&'a AtomicBool);
+
+impl Drop for Unlock<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// A notification which one thread waits for and other threads or
+/// interrupts release, like the C++ `pw::sync::ThreadNotification`.
+///
+/// Releasing the notification when it is already released has no effect, so
+/// a thread acquiring it may handle several releases at once.
+pub struct ThreadNotification {
+    raw: pw_sync_backend::RawThreadNotification,
+}
+
+impl ThreadNotification {
+    /// Creates an unreleased notification.
+    pub const fn new() -> Self {
+        Self {
+            raw: pw_sync_backend::RawThreadNotification::INIT,
+        }
+    }
+
+    /// Releases the notification, waking the waiting thread.  May be called
+    /// from an interrupt.
+    pub fn release(&self) {
+        self.raw.release()
+    }
+
+    /// Blocks until the notification is released, then consumes it.
+    pub fn acquire(&self) {
+        self.raw.acquire()
+    }
+
+    /// Consumes the notification if it is released, returning `true` if it
+    /// was.
+    pub fn try_acquire(&self) -> bool {
+        self.raw.try_acquire()
+    }
+
+    /// Blocks for at most `timeout` until the notification is released,
+    /// returning `true` if it was acquired.
+    pub fn try_acquire_for(&self, timeout: Duration) -> bool {
+        self.try_acquire_until(pw_chrono::now() + timeout)
+    }
+
+    /// Blocks until the notification is released or the system clock reaches
+    /// `deadline`, returning `true` if it was acquired.
+    pub fn try_acquire_until(&self, deadline: Instant) -> bool {
+        self.raw.try_acquire_until(deadline)
+    }
+}
+
+impl Default for ThreadNotification {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mutex_guards_data() {
+        let mutex = Mutex::new(1);
+        {
+            let mut guard = mutex.lock();
+            *guard += 1;
+            assert!(mutex.try_lock().is_none());
+        }
+        assert_eq!(*mutex.try_lock().unwrap(), 2);
+        assert_eq!(mutex.into_inner(), 2);
+    }
+
+    #[test]
+    fn interrupt_spin_lock_guards_data() {
+        let mut lock = InterruptSpinLock::new(1);
+        assert_eq!(lock.lock(|value| *value += 1), Ok(()));
+        assert_eq!(lock.lock(|value| *value), Ok(2));
+        *lock.get_mut() += 1;
+        assert_eq!(lock.into_inner(), 3);
+    }
+
+    #[test]
+    fn interrupt_spin_lock_is_not_recursive() {
+        let lock = InterruptSpinLock::new(1);
+        let nested = lock.lock(|_| lock.lock(|value| *value));
+        assert_eq!(nested, Ok(Err(Error::FailedPrecondition)));
+        // The lock is unlocked after the error.
+        assert_eq!(lock.lock(|value| *value), Ok(1));
+    }
+
+    #[test]
+    fn interrupt_spin_lock_nests_with_other_locks() {
+        let first = InterruptSpinLock::new(1);
+        let second = InterruptSpinLock::new(2);
+        let sum = first.lock(|a| second.lock(|b| *a + *b));
+        assert_eq!(sum, Ok(Ok(3)));
+    }
+
+    #[test]
+    fn notification_is_consumed_by_acquire() {
+        let notification = ThreadNotification::new();
+        assert!(!notification.try_acquire());
+        notification.release();
+        notification.release();
+        notification.acquire();
+        assert!(!notification.try_acquire());
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! The interface between the `pw_sync` facade and its backends.
+//!
+//! A backend is a crate named `pw_sync_backend` which exports a `RawMutex`
+//! and a `RawThreadNotification` implementing the traits below.  Bare-metal
+//! and `std` backends are provided.  Backends for an RTOS implement these
+//! traits with the RTOS's mutexes and semaphores or task notifications.
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_chrono::Instant;
+
+/// A lock without data, on which `pw_sync::Mutex` is built.
+///
+/// # Safety
+/// Implementations must ensure only one thread holds the lock at a time.
+pub unsafe trait RawMutex: Sync {
+    /// An unlocked mutex.
+    const INIT: Self;
+
+    /// Locks the mutex, blocking until it is available.
+    fn lock(&self);
+
+    /// Locks the mutex if it is available, returning `true` if it was
+    /// locked.
+    fn try_lock(&self) -> bool;
+
+    /// Locks the mutex, blocking until it is available or the system clock
+    /// reaches `deadline`.  Returns `true` if it was locked.
+    fn try_lock_until(&self, deadline: Instant) -> bool;
+
+    /// Unlocks the mutex.
+    ///
+    /// # Safety
+    /// The mutex must be locked by the calling thread.
+    unsafe fn unlock(&self);
+}
+
+/// A notification which one thread waits for and any thread or interrupt
+/// releases, on which `pw_sync::ThreadNotification` is built.
+///
+/// The notification is a binary semaphore: releasing it when it is already
+/// released has no effect.
+pub trait RawThreadNotification: Sync {
+    /// An unreleased notification.
+    const INIT: Self;
+
+    /// Releases the notification, waking the waiting thread.  May be called
+    /// from an interrupt.
+    fn release(&self);
+
+    /// Blocks until the notification is released, then consumes it.
+    fn acquire(&self);
+
+    /// Consumes the notification if it is released, returning `true` if it
+    /// was.
+    fn try_acquire(&self) -> bool;
+
+    /// Blocks until the notification is released or the system clock reaches
+    /// `deadline`, then consumes it if it was released.  Returns `true` if
+    /// it was.
+    fn try_acquire_until(&self, deadline: Instant) -> bool;
+}
@@ -0,0 +1,145 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_sync` backend for bare-metal targets without threads.
+//!
+//! Without other threads to release a lock, locking a locked [`RawMutex`]
+//! panics, as does the C++ `pw_sync_baremetal` backend.  A
+//! [`RawThreadNotification`] may be released by an interrupt, so acquiring
+//! it spins until it is.  State is updated in critical sections, so this
+//! backend works on targets without atomic compare and swap.
+#![no_std]
+#![deny(missing_docs)]
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use pw_chrono::Instant;
+use pw_sync_backend_api as api;
+
+// Sets `flag` to `value` if it is `current`, returning whether it was.
+fn swap_if(flag: &AtomicBool, current: bool, value: bool) -> bool {
+    critical_section::with(|_| {
+        if flag.load(Ordering::Acquire) != current {
+            return false;
+        }
+        flag.store(value, Ordering::Release);
+        true
+    })
+}
+
+/// A mutex for a single thread of execution.
+pub struct RawMutex {
+    locked: AtomicBool,
+}
+
+unsafe impl api::RawMutex for RawMutex {
+    const INIT: Self = Self {
+        locked: AtomicBool::new(false),
+    };
+
+    fn lock(&self) {
+        assert!(self.try_lock(), "Mutex is already locked");
+    }
+
+    fn try_lock(&self) -> bool {
+        swap_if(&self.locked, false, true)
+    }
+
+    fn try_lock_until(&self, _deadline: Instant) -> bool {
+        // Nothing else can unlock the mutex while waiting.
+        self.try_lock()
+    }
+
+    unsafe fn unlock(&self) {
+        assert!(
+            swap_if(&self.locked, true, false),
+            "Mutex unlocked while not locked"
+        );
+    }
+}
+
+/// A notification which is released by an interrupt.
+pub struct RawThreadNotification {
+    released: AtomicBool,
+}
+
+impl api::RawThreadNotification for RawThreadNotification {
+    const INIT: Self = Self {
+        released: AtomicBool::new(false),
+    };
+
+    fn release(&self) {
+        self.released.store(true, Ordering::Release);
+    }
+
+    fn acquire(&self) {
+        while !self.try_acquire() {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        swap_if(&self.released, true, false)
+    }
+
+    fn try_acquire_until(&self, deadline: Instant) -> bool {
+        loop {
+            if self.try_acquire() {
+                return true;
+            }
+            if deadline.has_passed() {
+                return false;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use api::{RawMutex as _, RawThreadNotification as _};
+
+    use super::*;
+
+    #[test]
+    fn mutex_locks_once() {
+        let mutex = RawMutex::INIT;
+        mutex.lock();
+        assert!(!mutex.try_lock());
+        assert!(!mutex.try_lock_until(Instant::EPOCH));
+        unsafe { mutex.unlock() };
+        assert!(mutex.try_lock());
+    }
+
+    #[test]
+    #[should_panic(expected = "Mutex is already locked")]
+    fn locking_locked_mutex_panics() {
+        let mutex = RawMutex::INIT;
+        mutex.lock();
+        mutex.lock();
+    }
+
+    #[test]
+    fn notification_is_consumed_by_acquire() {
+        let notification = RawThreadNotification::INIT;
+        assert!(!notification.try_acquire());
+        assert!(!notification.try_acquire_until(Instant::EPOCH));
+        notification.release();
+        notification.release();
+        notification.acquire();
+        assert!(!notification.try_acquire());
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_sync` backend for hosts, built on [`std::sync::Mutex`] and
+//! [`std::sync::Condvar`].
+//!
+//! Waits with a deadline are measured with the `pw_chrono` system clock,
+//! which must be set, such as to a `pw_chrono::StdClock`.
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+use pw_chrono::Instant;
+use pw_sync_backend_api as api;
+
+// A flag which threads wait to change.
+struct WaitableFlag {
+    flag: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl WaitableFlag {
+    const fn new() -> Self {
+        Self {
+            flag: Mutex::new(false),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, bool> {
+        // The flag is only set while the lock is held, so it can't be left
+        // inconsistent by a panic.
+        self.flag.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Sets the flag and wakes a waiting thread.
+    fn set(&self, value: bool) {
+        *self.lock() = value;
+        self.changed.notify_one();
+    }
+
+    // Waits for the flag to be `from`, then sets it to `to`.  Gives up once
+    // `deadline` passes, if there is one.  Returns `true` if the flag was
+    // set.
+    fn wait_and_swap(&self, from: bool, to: bool, deadline: Option<Instant>) -> bool {
+        let mut flag = self.lock();
+        while *flag != from {
+            flag = match deadline {
+                None => self.changed.wait(flag).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let remaining = deadline - pw_chrono::now();
+                    if remaining.is_zero() {
+                        return false;
+                    }
+                    self.changed
+                        .wait_timeout(flag, remaining)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        }
+        *flag = to;
+        true
+    }
+
+    fn try_swap(&self, from: bool, to: bool) -> bool {
+        let mut flag = self.lock();
+        if *flag != from {
+            return false;
+        }
+        *flag = to;
+        true
+    }
+}
+
+/// A mutex which blocks the calling thread while locked by another.
+pub struct RawMutex(WaitableFlag);
+
+unsafe impl api::RawMutex for RawMutex {
+    const INIT: Self = Self(WaitableFlag::new());
+
+    fn lock(&self) {
+        self.0.wait_and_swap(false, true, None);
+    }
+
+    fn try_lock(&self) -> bool {
+        self.0.try_swap(false, true)
+    }
+
+    fn try_lock_until(&self, deadline: Instant) -> bool {
+        self.0.wait_and_swap(false, true, Some(deadline))
+    }
+
+    unsafe fn unlock(&self) {
+        self.0.set(false);
+    }
+}
+
+/// A notification which blocks the acquiring thread until released.
+pub struct RawThreadNotification(WaitableFlag);
+
+impl api::RawThreadNotification for RawThreadNotification {
+    const INIT: Self = Self(WaitableFlag::new());
+
+    fn release(&self) {
+        self.0.set(true);
+    }
+
+    fn acquire(&self) {
+        self.0.wait_and_swap(true, false, None);
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.0.try_swap(true, false)
+    }
+
+    fn try_acquire_until(&self, deadline: Instant) -> bool {
+        self.0.wait_and_swap(true, false, Some(deadline))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::thread;
+
+    use api::{RawMutex as _, RawThreadNotification as _};
+    use pw_chrono::{Duration, StdClock};
+
+    use super::*;
+
+    static CLOCK: StdClock = StdClock;
+
+    #[test]
+    fn mutex_excludes_other_threads() {
+        static MUTEX: RawMutex = RawMutex::INIT;
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(|| {
+                    for _ in 0..100 {
+                        MUTEX.lock();
+                        // A non-atomic increment, which would lose updates
+                        // without the mutex.
+                        let count = COUNT.load(Ordering::Relaxed);
+                        thread::yield_now();
+                        COUNT.store(count + 1, Ordering::Relaxed);
+                        unsafe { MUTEX.unlock() };
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(COUNT.load(Ordering::Relaxed), 400);
+    }
+
+    #[test]
+    fn try_lock_until_times_out() {
+        pw_chrono::set_system_clock(&CLOCK);
+        let mutex = RawMutex::INIT;
+        mutex.lock();
+        assert!(!mutex.try_lock_until(pw_chrono::now() + Duration::from_millis(10)));
+        unsafe { mutex.unlock() };
+        assert!(mutex.try_lock_until(pw_chrono::now() + Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn notification_wakes_waiting_thread() {
+        static NOTIFICATION: RawThreadNotification = RawThreadNotification::INIT;
+
+        let waiter = thread::spawn(|| NOTIFICATION.acquire());
+        NOTIFICATION.release();
+        waiter.join().unwrap();
+        assert!(!NOTIFICATION.try_acquire());
+    }
+
+    #[test]
+    fn try_acquire_until_times_out() {
+        pw_chrono::set_system_clock(&CLOCK);
+        let notification = RawThreadNotification::INIT;
+        let start = pw_chrono::now();
+        assert!(!notification.try_acquire_until(start + Duration::from_millis(10)));
+        assert!(pw_chrono::now() - start >= Duration::from_millis(10));
+        notification.release();
+        assert!(notification.try_acquire_until(pw_chrono::now()));
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_sys_io` reads and writes bytes on a target's simplest I/O interface,
+//! such as a console UART, without any setup beyond the backend's.
+//!
+//! It is intended for bring-up and early boot, before full transports such as
+//! HDLC or RPC are available:
+//!
+//! ```no_run
+//! pw_sys_io::write_line("Booting")?;
+//! let command = pw_sys_io::read_byte()?;
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! [`SysIoMessageWriter`] sends tokenized messages as Base64 lines, which the
+//! detokenizer decodes from a console's output, so tokenized logging works as
+//! soon as bytes can be written.  [`SysIoReader`] and [`SysIoWriter`] adapt
+//! `pw_sys_io` to [`pw_stream`].
+//!
+//! `pw_sys_io` is a facade: the I/O is implemented by the backend selected
+//! with the `//pw_sys_io/rust:pw_sys_io_backend` label flag.  The `std`
+//! backend uses standard input and output, the UART backend uses a
+//! `pw_uart::UartNonBlocking` driver, and the RTT backend uses SEGGER RTT
+//! channels.  Other backends implement the trait in `pw_sys_io_backend_api`.
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_base64::{Base64Writer, TOKEN_PREFIX};
+use pw_status::{Error, Result};
+use pw_stream::Write;
+use pw_sys_io_backend_api::SysIo;
+use pw_tokenizer::MessageWriter;
+
+type Backend = pw_sys_io_backend::SysIo;
+
+/// Blocks until a byte is available, then reads it.
+pub fn read_byte() -> Result<u8> {
+    Backend::read_byte()
+}
+
+/// Reads a byte if one is available.
+///
+/// # Errors
+/// - [`Error::Unavailable`] - No byte is available.
+/// - [`Error::Unimplemented`] - The backend only supports blocking reads.
+pub fn try_read_byte() -> Result<u8> {
+    Backend::try_read_byte()
+}
+
+/// Writes a byte, blocking until it is queued.
+pub fn write_byte(byte: u8) -> Result<()> {
+    Backend::write_byte(byte)
+}
+
+/// Writes all of `bytes`, blocking until they are queued.
+pub fn write_all(bytes: &[u8]) -> Result<()> {
+    Backend::write_all(bytes)
+}
+
+/// Writes `line` followed by `"\r\n"`.
+pub fn write_line(line: &str) -> Result<()> {
+    write_all(line.as_bytes())?;
+    write_all(b"\r\n")
+}
+
+/// Blocks until written bytes have been sent.
+pub fn flush() -> Result<()> {
+    Backend::flush()
+}
+
+/// Exposes [`read_byte()`] through [`pw_stream::Read`].
+///
+/// Each read blocks until a byte is available, then reads the bytes which are
+/// available without blocking.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SysIoReader;
+
+impl pw_stream::Read for SysIoReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let Some((first, rest)) = buf.split_first_mut() else {
+            return Ok(0);
+        };
+        *first = read_byte()?;
+        let mut len = 1;
+        for byte in rest {
+            match try_read_byte() {
+                Ok(value) => *byte = value,
+                Err(_) => break,
+            }
+            len += 1;
+        }
+        Ok(len)
+    }
+}
+
+/// Exposes [`write_all()`] through [`pw_stream::Write`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SysIoWriter;
+
+impl Write for SysIoWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        flush()
+    }
+}
+
+/// A [`MessageWriter`] which writes each tokenized message as a line of
+/// Base64 prefixed with [`TOKEN_PREFIX`].
+///
+/// Messages of up to `N` bytes are buffered and written when finalized.
+/// Messages written from different threads may be interleaved.
+pub struct SysIoMessageWriter<const N: usize = 64> {
+    buffer: [u8; N],
+    len: usize,
+}
+
+// Writes `message` to `writer` as a prefixed Base64 line.
+fn write_base64_line(writer: impl Write, message: &[u8]) -> Result<()> {
+    let mut base64 = Base64Writer::with_prefix(writer, TOKEN_PREFIX);
+    base64.write_all(message)?;
+    base64.finish()?;
+    let mut writer = base64.into_inner();
+    writer.write_all(b"\r\n")
+}
+
+impl<const N: usize> MessageWriter for SysIoMessageWriter<N> {
+    fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            len: 0,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let dest = self
+            .buffer
+            .get_mut(self.len..self.len + data.len())
+            .ok_or(Error::OutOfRange)?;
+        dest.copy_from_slice(data);
+        self.len += data.len();
+        Ok(())
+    }
+
+    fn remaining(&self) -> usize {
+        N - self.len
+    }
+
+    fn finalize(self) -> Result<()> {
+        write_base64_line(SysIoWriter, &self.buffer[..self.len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_stream::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn message_is_written_as_base64_line() {
+        let mut cursor = Cursor::new([0u8; 16]);
+        write_base64_line(&mut cursor, &[0x31, 0x3d, 0x4b, 0xa1]).unwrap();
+        let len = cursor.position();
+        assert_eq!(&cursor.into_inner()[..len], b"$MT1LoQ==\r\n");
+    }
+
+    #[test]
+    fn message_writer_is_bounded() {
+        let mut writer = SysIoMessageWriter::<4>::new();
+        writer.write(&[1, 2, 3]).unwrap();
+        assert_eq!(writer.remaining(), 1);
+        assert_eq!(writer.write(&[4, 5]), Err(Error::OutOfRange));
+        writer.write(&[4]).unwrap();
+        assert_eq!(writer.remaining(), 0);
+    }
+}
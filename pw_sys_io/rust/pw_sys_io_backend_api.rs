@@ -0,0 +1,55 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! The interface between the `pw_sys_io` facade and its backends.
+//!
+//! A backend is a crate named `pw_sys_io_backend` which exports a `SysIo`
+//! type implementing the trait below.  Backends are provided for `std`
+//! standard input and output, `pw_uart` UARTs, and SEGGER RTT.
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_status::Result;
+
+/// Reads and writes bytes on a target's simple I/O interface, such as a
+/// console UART.
+pub trait SysIo {
+    /// Blocks until a byte is available, then reads it.
+    fn read_byte() -> Result<u8>;
+
+    /// Reads a byte if one is available.
+    ///
+    /// # Errors
+    /// - [`pw_status::Error::Unavailable`] - No byte is available.
+    /// - [`pw_status::Error::Unimplemented`] - The backend only supports
+    ///   blocking reads.
+    fn try_read_byte() -> Result<u8>;
+
+    /// Writes a byte, blocking until it is queued.
+    fn write_byte(byte: u8) -> Result<()>;
+
+    /// Writes all of `bytes`, blocking until they are queued.  Backends
+    /// override this when they can write several bytes more efficiently.
+    fn write_all(bytes: &[u8]) -> Result<()> {
+        for &byte in bytes {
+            Self::write_byte(byte)?;
+        }
+        Ok(())
+    }
+
+    /// Blocks until written bytes have been sent.
+    fn flush() -> Result<()> {
+        Ok(())
+    }
+}
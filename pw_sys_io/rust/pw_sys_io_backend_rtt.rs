@@ -0,0 +1,148 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_sys_io` backend which reads from and writes to a debug probe over
+//! SEGGER RTT.
+//!
+//! RTT is available as soon as the core is running and a probe is attached,
+//! so no peripheral needs to be set up.  Channels are created with
+//! [`rtt_target::rtt_init!`] and registered with [`set_channels()`]:
+//!
+//! ```no_run
+//! use rtt_target::{rtt_init, ChannelMode};
+//!
+//! let channels = rtt_init! {
+//!     up: {
+//!         0: { size: 1024, mode: ChannelMode::BlockIfFull, name: "Terminal" }
+//!     }
+//!     down: {
+//!         0: { size: 16, name: "Terminal" }
+//!     }
+//! };
+//! pw_sys_io_backend::set_channels(channels.up.0, Some(channels.down.0));
+//! ```
+//!
+//! Writes which do not fit in the up-channel's buffer fail with
+//! [`Error::ResourceExhausted`] unless the channel is in
+//! [`rtt_target::ChannelMode::BlockIfFull`] mode, which waits for the probe to read
+//! data.  Reads fail with [`Error::FailedPrecondition`] if no down-channel is
+//! registered.
+#![no_std]
+#![deny(missing_docs)]
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use pw_status::{Error, Result};
+use rtt_target::{DownChannel, UpChannel};
+
+struct Channels {
+    up: UpChannel,
+    down: Option<DownChannel>,
+}
+
+static CHANNELS: Mutex<RefCell<Option<Channels>>> = Mutex::new(RefCell::new(None));
+
+/// Sets the channels used by `pw_sys_io`.
+pub fn set_channels(up: UpChannel, down: Option<DownChannel>) {
+    critical_section::with(|cs| CHANNELS.borrow_ref_mut(cs).replace(Channels { up, down }));
+}
+
+/// Removes and returns the channels used by `pw_sys_io`.
+pub fn take_channels() -> Option<(UpChannel, Option<DownChannel>)> {
+    critical_section::with(|cs| {
+        let channels = CHANNELS.borrow_ref_mut(cs).take()?;
+        Some((channels.up, channels.down))
+    })
+}
+
+// Calls `f` with the registered channels in a critical section.
+fn with_channels<T>(f: impl FnOnce(&mut Channels) -> Result<T>) -> Result<T> {
+    critical_section::with(|cs| {
+        let mut channels = CHANNELS.borrow_ref_mut(cs);
+        f(channels.as_mut().ok_or(Error::FailedPrecondition)?)
+    })
+}
+
+/// The RTT implementation of [`pw_sys_io_backend_api::SysIo`].
+///
+/// Operations fail with [`Error::FailedPrecondition`] if no channels are
+/// registered.
+pub struct SysIo;
+
+impl pw_sys_io_backend_api::SysIo for SysIo {
+    fn read_byte() -> Result<u8> {
+        loop {
+            match Self::try_read_byte() {
+                Err(Error::Unavailable) => core::hint::spin_loop(),
+                result => return result,
+            }
+        }
+    }
+
+    fn try_read_byte() -> Result<u8> {
+        with_channels(|channels| {
+            let down = channels.down.as_mut().ok_or(Error::FailedPrecondition)?;
+            let mut byte = [0u8];
+            match down.read(&mut byte) {
+                0 => Err(Error::Unavailable),
+                _ => Ok(byte[0]),
+            }
+        })
+    }
+
+    fn write_byte(byte: u8) -> Result<()> {
+        Self::write_all(&[byte])
+    }
+
+    fn write_all(bytes: &[u8]) -> Result<()> {
+        with_channels(|channels| {
+            // Writes which do not fit are discarded in `NoBlockSkip` mode and
+            // truncated in `NoBlockTrim` mode.
+            if channels.up.write(bytes) < bytes.len() {
+                return Err(Error::ResourceExhausted);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_sys_io_backend_api::SysIo as _;
+    use rtt_target::{rtt_init, ChannelMode};
+
+    use super::*;
+
+    // `rtt_init!` may only be called once so all functionality is tested
+    // together.
+    #[test]
+    fn writes_to_rtt_channel() {
+        assert_eq!(SysIo::write_byte(b'a'), Err(Error::FailedPrecondition));
+
+        let channels = rtt_init! {
+            up: {
+                0: { size: 8, mode: ChannelMode::NoBlockTrim }
+            }
+        };
+        set_channels(channels.up.0, None);
+        SysIo::write_all(b"hello").unwrap();
+        assert_eq!(SysIo::write_all(b"world"), Err(Error::ResourceExhausted));
+        assert_eq!(SysIo::try_read_byte(), Err(Error::FailedPrecondition));
+
+        let (up, down) = take_channels().unwrap();
+        assert!(!up.is_empty());
+        assert!(down.is_none());
+    }
+}
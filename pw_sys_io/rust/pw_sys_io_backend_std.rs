@@ -0,0 +1,64 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_sys_io` backend for hosts, which reads from standard input and writes
+//! to standard output.
+//!
+//! Standard input can not be read without blocking, so
+//! `pw_sys_io::try_read_byte()` returns [`Error::Unimplemented`].
+//!
+//! *Note*: This module requires `std`.
+use std::io;
+
+use pw_status::{Error, Result};
+use pw_stream::{IoAdapter, Read, Write};
+
+/// The `std` implementation of [`pw_sys_io_backend_api::SysIo`].
+pub struct SysIo;
+
+impl pw_sys_io_backend_api::SysIo for SysIo {
+    fn read_byte() -> Result<u8> {
+        let mut byte = [0u8];
+        IoAdapter::new(io::stdin().lock()).read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn try_read_byte() -> Result<u8> {
+        Err(Error::Unimplemented)
+    }
+
+    fn write_byte(byte: u8) -> Result<()> {
+        Self::write_all(&[byte])
+    }
+
+    fn write_all(bytes: &[u8]) -> Result<()> {
+        let mut stdout = IoAdapter::new(io::stdout().lock());
+        stdout.write_all(bytes)?;
+        stdout.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_sys_io_backend_api::SysIo as _;
+
+    use super::*;
+
+    #[test]
+    fn writes_to_stdout() {
+        assert_eq!(SysIo::write_all(b"pw_sys_io\n"), Ok(()));
+        assert_eq!(SysIo::write_byte(b'\n'), Ok(()));
+        assert_eq!(SysIo::try_read_byte(), Err(Error::Unimplemented));
+    }
+}
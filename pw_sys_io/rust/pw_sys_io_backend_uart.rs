@@ -0,0 +1,195 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_sys_io` backend which reads from and writes to a UART.
+//!
+//! The UART is a [`UartNonBlocking`] driver registered with [`set_uart()`],
+//! which is polled until bytes are transferred.  Each poll holds a critical
+//! section, so `pw_sys_io` may be used from any context, including before a
+//! scheduler is running.
+//!
+//! ```
+//! # use pw_status::Result;
+//! # use pw_uart::{Config, UartNonBlocking};
+//! # struct Usart1;
+//! # impl UartNonBlocking for Usart1 {
+//! #     fn configure(&mut self, _config: &Config) -> Result<()> { Ok(()) }
+//! #     fn try_read(&mut self, _buf: &mut [u8]) -> Result<usize> {
+//! #         Err(pw_status::Error::Unavailable)
+//! #     }
+//! #     fn try_write(&mut self, buf: &[u8]) -> Result<usize> { Ok(buf.len()) }
+//! #     fn is_tx_idle(&self) -> bool { true }
+//! #     fn conservative_read_available(&self) -> usize { 0 }
+//! #     fn clear_pending_receive_bytes(&mut self) {}
+//! # }
+//! # fn usart1() -> &'static mut Usart1 { Box::leak(Box::new(Usart1)) }
+//! let uart = usart1();
+//! uart.configure(&Config::new(115_200))?;
+//! pw_sys_io_backend::set_uart(uart);
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use pw_status::{Error, Result};
+use pw_uart::UartNonBlocking;
+
+type SysIoUart = &'static mut (dyn UartNonBlocking + Send);
+
+static UART: Mutex<RefCell<Option<SysIoUart>>> = Mutex::new(RefCell::new(None));
+
+/// Sets the UART used by `pw_sys_io`.
+///
+/// Returns the previously registered UART, if any.
+pub fn set_uart(uart: SysIoUart) -> Option<SysIoUart> {
+    critical_section::with(|cs| UART.borrow_ref_mut(cs).replace(uart))
+}
+
+/// Removes and returns the UART used by `pw_sys_io`.
+pub fn take_uart() -> Option<SysIoUart> {
+    critical_section::with(|cs| UART.borrow_ref_mut(cs).take())
+}
+
+// Calls `f` with the registered UART in a critical section.
+fn with_uart<T>(f: impl FnOnce(&mut dyn UartNonBlocking) -> Result<T>) -> Result<T> {
+    critical_section::with(|cs| {
+        let mut uart = UART.borrow_ref_mut(cs);
+        let uart = uart.as_mut().ok_or(Error::FailedPrecondition)?;
+        f(*uart)
+    })
+}
+
+// Retries `f` while it is unavailable.
+fn poll<T>(mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    loop {
+        match f() {
+            Err(Error::Unavailable) => core::hint::spin_loop(),
+            result => return result,
+        }
+    }
+}
+
+/// The UART implementation of [`pw_sys_io_backend_api::SysIo`].
+///
+/// Operations fail with [`Error::FailedPrecondition`] if no UART is
+/// registered.
+pub struct SysIo;
+
+impl pw_sys_io_backend_api::SysIo for SysIo {
+    fn read_byte() -> Result<u8> {
+        poll(Self::try_read_byte)
+    }
+
+    fn try_read_byte() -> Result<u8> {
+        let mut byte = [0u8];
+        with_uart(|uart| uart.try_read(&mut byte))?;
+        Ok(byte[0])
+    }
+
+    fn write_byte(byte: u8) -> Result<()> {
+        Self::write_all(&[byte])
+    }
+
+    fn write_all(mut bytes: &[u8]) -> Result<()> {
+        while !bytes.is_empty() {
+            let len = poll(|| with_uart(|uart| uart.try_write(bytes)))?;
+            bytes = &bytes[len..];
+        }
+        Ok(())
+    }
+
+    fn flush() -> Result<()> {
+        while !with_uart(|uart| Ok(uart.is_tx_idle()))? {
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::boxed::Box;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use pw_sys_io_backend_api::SysIo as _;
+    use pw_uart::Config;
+
+    use super::*;
+
+    // A UART which accepts up to two bytes per write.
+    #[derive(Default)]
+    struct FakeUart {
+        rx: VecDeque<u8>,
+        tx: Vec<u8>,
+    }
+
+    impl UartNonBlocking for FakeUart {
+        fn configure(&mut self, _config: &Config) -> Result<()> {
+            Ok(())
+        }
+
+        fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            match (buf.first_mut(), self.rx.pop_front()) {
+                (Some(dest), Some(byte)) => {
+                    *dest = byte;
+                    Ok(1)
+                }
+                _ => Err(Error::Unavailable),
+            }
+        }
+
+        fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+            let len = buf.len().min(2);
+            self.tx.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn is_tx_idle(&self) -> bool {
+            true
+        }
+
+        fn conservative_read_available(&self) -> usize {
+            self.rx.len()
+        }
+
+        fn clear_pending_receive_bytes(&mut self) {
+            self.rx.clear();
+        }
+    }
+
+    // The UART is global, so all functionality is tested together.
+    #[test]
+    fn reads_and_writes_registered_uart() {
+        assert_eq!(SysIo::write_byte(b'a'), Err(Error::FailedPrecondition));
+
+        let uart = Box::leak(Box::new(FakeUart::default()));
+        uart.rx.extend(b"hi");
+        assert!(set_uart(uart).is_none());
+
+        SysIo::write_all(b"hello").unwrap();
+        SysIo::flush().unwrap();
+        assert_eq!(SysIo::read_byte(), Ok(b'h'));
+        assert_eq!(SysIo::try_read_byte(), Ok(b'i'));
+        assert_eq!(SysIo::try_read_byte(), Err(Error::Unavailable));
+
+        let uart = take_uart().unwrap();
+        assert_eq!(uart.conservative_read_available(), 0);
+        assert_eq!(SysIo::read_byte(), Err(Error::FailedPrecondition));
+    }
+}
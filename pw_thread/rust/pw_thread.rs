@@ -0,0 +1,121 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_thread` creates threads and controls the calling thread portably, so
+//! subsystems can run background work on any target with threads.
+//!
+//! A [`Thread`] runs a [`ThreadCore`], which is any `Fn()` or a static
+//! object holding the thread's state.  Threads are configured with
+//! [`Options`], which are specific to the backend, so portable code takes
+//! them from the application:
+//!
+//! ```
+//! use core::sync::atomic::{AtomicU32, Ordering};
+//!
+//! use pw_thread::{Options, Thread, ThreadCore};
+//!
+//! struct Worker {
+//!     iterations: AtomicU32,
+//! }
+//!
+//! impl ThreadCore for Worker {
+//!     fn run(&self) {
+//!         for _ in 0..3 {
+//!             self.iterations.fetch_add(1, Ordering::Relaxed);
+//!             pw_thread::yield_now();
+//!         }
+//!     }
+//! }
+//!
+//! static WORKER: Worker = Worker { iterations: AtomicU32::new(0) };
+//!
+//! fn start_worker(options: &Options) -> pw_status::Result<Thread> {
+//!     Thread::spawn(options, &WORKER)
+//! }
+//!
+//! start_worker(&Options::default())?.join()?;
+//! assert_eq!(WORKER.iterations.load(Ordering::Relaxed), 3);
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! `pw_thread` is a facade implemented by the backend selected with the
+//! `//pw_thread/rust:pw_thread_backend` label flag.  The `std` backend uses
+//! [`std::thread`](https://doc.rust-lang.org/std/thread/), and the
+//! bare-metal backend supports sleeping but not spawning threads.  An RTOS
+//! backend implements the traits in `pw_thread_backend_api`.
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_chrono::{Duration, Instant};
+use pw_status::Result;
+use pw_thread_backend_api::{RawThread, ThisThread};
+
+pub use pw_thread_backend::Options;
+pub use pw_thread_backend_api::ThreadCore;
+
+/// A handle to a running thread.
+///
+/// Dropping the handle detaches the thread, which keeps running.
+pub struct Thread {
+    raw: pw_thread_backend::RawThread,
+}
+
+impl Thread {
+    /// Starts a thread which calls `core.run()`.
+    ///
+    /// # Errors
+    /// Returns the backend's error if the thread can't be created, such as
+    /// [`pw_status::Error::Unimplemented`] on targets without threads.
+    pub fn spawn(options: &Options, core: &'static dyn ThreadCore) -> Result<Self> {
+        pw_thread_backend::RawThread::spawn(options, core).map(|raw| Self { raw })
+    }
+
+    /// Blocks until the thread ends.
+    ///
+    /// # Errors
+    /// Returns the backend's error if the thread ended abnormally, such as
+    /// by panicking.
+    pub fn join(self) -> Result<()> {
+        self.raw.join()
+    }
+}
+
+/// Blocks the calling thread for at least `duration`.
+pub fn sleep_for(duration: Duration) {
+    pw_thread_backend::ThisThread::sleep_for(duration)
+}
+
+/// Blocks the calling thread until the `pw_chrono` system clock reaches
+/// `deadline`.
+pub fn sleep_until(deadline: Instant) {
+    pw_thread_backend::ThisThread::sleep_until(deadline)
+}
+
+/// Lets other threads of the same priority run.
+pub fn yield_now() {
+    pw_thread_backend::ThisThread::yield_now()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sleep_for_blocks_for_duration() {
+        extern crate std;
+        let start = std::time::Instant::now();
+        sleep_for(Duration::from_millis(5));
+        assert!(start.elapsed() >= Duration::from_millis(5));
+    }
+}
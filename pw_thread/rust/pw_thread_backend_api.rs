@@ -0,0 +1,70 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! The interface between the `pw_thread` facade and its backends.
+//!
+//! A backend is a crate named `pw_thread_backend` which exports:
+//!
+//! - `Options`, the configuration of a new thread, which is specific to the
+//!   backend.
+//! - `RawThread`, a handle to a thread implementing [`RawThread`].
+//! - `ThisThread`, which implements [`ThisThread`] for the calling thread.
+//!
+//! Bare-metal and `std` backends are provided.  Backends for an RTOS
+//! implement these traits with the RTOS's tasks, taking the task's stack and
+//! priority in their `Options`.
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_chrono::{Duration, Instant};
+use pw_status::Result;
+
+/// The work done by a thread, like the C++ `pw::thread::ThreadCore`.
+///
+/// Implemented for functions and closures, so threads can be spawned with
+/// either a function or a static object which holds the thread's state.
+pub trait ThreadCore: Sync {
+    /// Runs the thread.  The thread ends when this returns.
+    fn run(&self);
+}
+
+impl<F: Fn() + Sync> ThreadCore for F {
+    fn run(&self) {
+        self()
+    }
+}
+
+/// A handle to a running thread.  Dropping the handle detaches the thread.
+pub trait RawThread: Sized + Send {
+    /// The configuration of a new thread.
+    type Options;
+
+    /// Starts a thread which calls `core.run()`.
+    fn spawn(options: &Self::Options, core: &'static dyn ThreadCore) -> Result<Self>;
+
+    /// Blocks until the thread ends.
+    fn join(self) -> Result<()>;
+}
+
+/// Operations on the calling thread.
+pub trait ThisThread {
+    /// Blocks the calling thread for at least `duration`.
+    fn sleep_for(duration: Duration);
+
+    /// Blocks the calling thread until the system clock reaches `deadline`.
+    fn sleep_until(deadline: Instant);
+
+    /// Lets other threads of the same priority run.
+    fn yield_now();
+}
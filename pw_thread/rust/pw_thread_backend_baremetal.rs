@@ -0,0 +1,84 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_thread` backend for bare-metal targets without threads.
+//!
+//! Spawning threads is unsupported.  Sleeping spins until the `pw_chrono`
+//! system clock reaches the deadline, and yielding does nothing.
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_chrono::{Duration, Instant};
+use pw_status::{Error, Result};
+use pw_thread_backend_api as api;
+
+/// The configuration of a new thread.  There are no options, as threads
+/// can't be spawned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options;
+
+/// A thread which can't be created.
+pub enum RawThread {}
+
+impl api::RawThread for RawThread {
+    type Options = Options;
+
+    /// # Errors
+    /// - [`Error::Unimplemented`] - Always.
+    fn spawn(_options: &Options, _core: &'static dyn api::ThreadCore) -> Result<Self> {
+        Err(Error::Unimplemented)
+    }
+
+    fn join(self) -> Result<()> {
+        match self {}
+    }
+}
+
+/// The only thread.
+pub struct ThisThread;
+
+impl api::ThisThread for ThisThread {
+    fn sleep_for(duration: Duration) {
+        Self::sleep_until(pw_chrono::now() + duration)
+    }
+
+    fn sleep_until(deadline: Instant) {
+        while !deadline.has_passed() {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn yield_now() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use api::{RawThread as _, ThisThread as _};
+
+    use super::*;
+
+    #[test]
+    fn spawn_is_unimplemented() {
+        assert!(matches!(
+            RawThread::spawn(&Options, &|| {}),
+            Err(Error::Unimplemented)
+        ));
+    }
+
+    #[test]
+    fn sleep_until_passed_deadline_returns() {
+        ThisThread::sleep_until(Instant::EPOCH);
+        ThisThread::yield_now();
+    }
+}
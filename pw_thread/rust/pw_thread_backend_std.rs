@@ -0,0 +1,119 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_thread` backend for hosts, built on [`std::thread`].
+use std::thread::{Builder, JoinHandle};
+
+use pw_chrono::{Duration, Instant};
+use pw_status::{Error, Result};
+use pw_thread_backend_api as api;
+
+/// The configuration of a new thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    /// The thread's name, or `""` for an unnamed thread.
+    pub name: &'static str,
+    /// The size of the thread's stack, or 0 for the platform's default.
+    pub stack_size_bytes: usize,
+}
+
+/// A handle to a [`std::thread`].
+pub struct RawThread(JoinHandle<()>);
+
+impl api::RawThread for RawThread {
+    type Options = Options;
+
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - The OS failed to create the thread.
+    fn spawn(options: &Options, core: &'static dyn api::ThreadCore) -> Result<Self> {
+        let mut builder = Builder::new();
+        if !options.name.is_empty() {
+            builder = builder.name(options.name.to_string());
+        }
+        if options.stack_size_bytes != 0 {
+            builder = builder.stack_size(options.stack_size_bytes);
+        }
+        builder
+            .spawn(move || core.run())
+            .map(RawThread)
+            .map_err(|_| Error::ResourceExhausted)
+    }
+
+    /// # Errors
+    /// - [`Error::Aborted`] - The thread panicked.
+    fn join(self) -> Result<()> {
+        self.0.join().map_err(|_| Error::Aborted)
+    }
+}
+
+/// The calling [`std::thread`].
+pub struct ThisThread;
+
+impl api::ThisThread for ThisThread {
+    fn sleep_for(duration: Duration) {
+        std::thread::sleep(duration)
+    }
+
+    fn sleep_until(deadline: Instant) {
+        // The system clock may not be `std`'s, so check it after each sleep.
+        while !deadline.has_passed() {
+            std::thread::sleep(deadline - pw_chrono::now());
+        }
+    }
+
+    fn yield_now() {
+        std::thread::yield_now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use api::{RawThread as _, ThisThread as _};
+    use pw_chrono::StdClock;
+
+    use super::*;
+
+    #[test]
+    fn spawned_thread_runs_core() {
+        static RAN: AtomicBool = AtomicBool::new(false);
+        fn run() {
+            assert_eq!(std::thread::current().name(), Some("worker"));
+            RAN.store(true, Ordering::Relaxed);
+        }
+
+        let options = Options {
+            name: "worker",
+            stack_size_bytes: 64 * 1024,
+        };
+        RawThread::spawn(&options, &run).unwrap().join().unwrap();
+        assert!(RAN.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn join_reports_panic() {
+        let thread = RawThread::spawn(&Options::default(), &|| panic!("Thread failed")).unwrap();
+        assert_eq!(thread.join().err(), Some(Error::Aborted));
+    }
+
+    #[test]
+    fn sleep_until_waits_for_system_clock() {
+        static CLOCK: StdClock = StdClock;
+        pw_chrono::set_system_clock(&CLOCK);
+        let deadline = pw_chrono::now() + Duration::from_millis(5);
+        ThisThread::sleep_until(deadline);
+        assert!(deadline.has_passed());
+    }
+}
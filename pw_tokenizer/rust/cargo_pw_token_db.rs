@@ -0,0 +1,734 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! `cargo pw-token-db` updates a package's token database with the tokenized
+//! strings in its binaries, as `pw_tokenizer`'s `database.py add` does.
+//!
+//! The database is configured in the package's `Cargo.toml`:
+//!
+//! ```toml
+//! [package.metadata.pw_tokenizer]
+//! # A CSV database file, or an existing directory database, relative to
+//! # Cargo.toml.
+//! database = "tokens.csv"
+//! # The binaries to read tokens from.  Defaults to all of the package's
+//! # binaries.
+//! binaries = ["firmware"]
+//! # The tokenization domain to read.  Defaults to the default domain.
+//! domain = ""
+//! ```
+//!
+//! After building with `cargo build`, run `cargo pw-token-db` with the same
+//! `--target` and profile options to add the binaries' tokens to the
+//! database.  With `--mark-removed`, strings which are no longer in the
+//! binaries are marked as removed today.  With `--check`, the database is not
+//! written, and the command fails if it is out of date.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pw_detokenizer::Database;
+use pw_elf_reader::Elf;
+
+const USAGE: &str = "\
+Usage: cargo pw-token-db [OPTIONS] [ELF]...
+
+Adds the tokens in a package's binaries to its token database, which is
+configured in Cargo.toml under [package.metadata.pw_tokenizer].  ELF files
+may be listed instead of reading the package's binaries.
+
+Options:
+  --manifest-path PATH  Path to Cargo.toml
+  -p, --package NAME    Package to update; may be repeated [default: all]
+  --target TRIPLE       Target the binaries were built for
+  --release             Read binaries built with the release profile
+  --profile NAME        Read binaries built with the NAME profile
+  --mark-removed        Mark strings which are not in the binaries as removed
+  --check               Fail if the database is out of date instead of
+                        updating it";
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    manifest_path: Option<String>,
+    packages: Vec<String>,
+    target: Option<String>,
+    profile: Option<String>,
+    mark_removed: bool,
+    check: bool,
+    elfs: Vec<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} requires a value"));
+        match arg.as_str() {
+            "--manifest-path" => parsed.manifest_path = Some(value()?),
+            "-p" | "--package" => parsed.packages.push(value()?),
+            "--target" => parsed.target = Some(value()?),
+            "--release" => parsed.profile = Some("release".to_string()),
+            "--profile" => parsed.profile = Some(value()?),
+            "--mark-removed" => parsed.mark_removed = true,
+            "--check" => parsed.check = true,
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') => return Err(format!("unknown argument: {arg}")),
+            _ => parsed.elfs.push(arg.into()),
+        }
+    }
+    Ok(parsed)
+}
+
+/// A package's `[package.metadata.pw_tokenizer]` configuration.
+#[derive(Debug, PartialEq)]
+struct Config {
+    package: String,
+    database: PathBuf,
+    binaries: Vec<String>,
+    domain: String,
+}
+
+// Returns the configuration of each package in `cargo metadata` output
+// which has a `[package.metadata.pw_tokenizer]` table.
+fn package_configs(metadata: &Json) -> Result<Vec<Config>, String> {
+    let invalid = |field: &str| format!("invalid cargo metadata: {field}");
+    let packages = metadata
+        .get("packages")
+        .and_then(Json::as_array)
+        .ok_or_else(|| invalid("packages"))?;
+
+    let mut configs = Vec::new();
+    for package in packages {
+        let name = package
+            .get("name")
+            .and_then(Json::as_str)
+            .ok_or_else(|| invalid("name"))?;
+        let Some(config) = package
+            .get("metadata")
+            .and_then(|metadata| metadata.get("pw_tokenizer"))
+        else {
+            continue;
+        };
+        let error = |message: &str| format!("{name}: package.metadata.pw_tokenizer: {message}");
+
+        let manifest_path = package
+            .get("manifest_path")
+            .and_then(Json::as_str)
+            .ok_or_else(|| invalid("manifest_path"))?;
+        let database = config
+            .get("database")
+            .and_then(Json::as_str)
+            .ok_or_else(|| error("`database` must be set to a path"))?;
+        let binaries = match config.get("binaries") {
+            Some(binaries) => binaries
+                .as_array()
+                .and_then(|binaries| {
+                    binaries
+                        .iter()
+                        .map(Json::as_str)
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| error("`binaries` must be a list of binary names"))?,
+            None => package
+                .get("targets")
+                .and_then(Json::as_array)
+                .ok_or_else(|| invalid("targets"))?
+                .iter()
+                .filter(|target| {
+                    target
+                        .get("kind")
+                        .and_then(Json::as_array)
+                        .is_some_and(|kinds| kinds.iter().any(|kind| kind.as_str() == Some("bin")))
+                })
+                .filter_map(|target| target.get("name").and_then(Json::as_str))
+                .collect(),
+        };
+        let domain = match config.get("domain") {
+            Some(domain) => domain
+                .as_str()
+                .ok_or_else(|| error("`domain` must be a string"))?,
+            None => "",
+        };
+
+        configs.push(Config {
+            package: name.to_string(),
+            database: Path::new(manifest_path)
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(database),
+            binaries: binaries.into_iter().map(str::to_string).collect(),
+            domain: domain.to_string(),
+        });
+    }
+    Ok(configs)
+}
+
+// Returns the path cargo builds `binary` to.
+fn binary_path(target_directory: &Path, args: &Args, binary: &str) -> PathBuf {
+    let mut path = target_directory.to_path_buf();
+    if let Some(target) = &args.target {
+        path.push(target);
+    }
+    // The dev profile's output directory is `debug`.
+    path.push(match args.profile.as_deref() {
+        None | Some("dev") => "debug",
+        Some(profile) => profile,
+    });
+    path.push(binary);
+    path
+}
+
+// Returns the current date in UTC as an ISO 8601 date, such as
+// `2024-03-14`.
+fn today() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    date_from_days((seconds / 86400) as i64)
+}
+
+// Converts days since the Unix epoch to a date, with Howard Hinnant's
+// `civil_from_days` algorithm.
+fn date_from_days(days: i64) -> String {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+fn read_elf_database(path: &Path, domain: &str) -> Result<Database, String> {
+    let data = std::fs::read(path).map_err(|e| {
+        format!(
+            "failed to read {}: {e}; build it with `cargo build` first",
+            path.display()
+        )
+    })?;
+    let elf = Elf::parse(&data)
+        .map_err(|e| format!("{} is not a valid ELF file: {e}", path.display()))?;
+    Database::from_elf(&elf, domain)
+        .map_err(|e| format!("failed to read tokens from {}: {e}", path.display()))
+}
+
+// Updates the database for one package.  Returns whether the database is
+// up to date.
+fn update(config: &Config, elfs: &[PathBuf], args: &Args) -> Result<bool, String> {
+    let mut tokens = Database::new();
+    for elf in elfs {
+        tokens.merge(read_elf_database(elf, &config.domain)?);
+    }
+
+    let path = &config.database;
+    let initial = if path.exists() {
+        Database::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?
+    } else {
+        Database::new()
+    };
+    let mut database = initial.clone();
+    database.merge(tokens.clone());
+    let added = database.len() - initial.len();
+    let removed = if args.mark_removed {
+        database.mark_removed(&tokens, &today())
+    } else {
+        0
+    };
+
+    if args.check {
+        if database != initial {
+            eprintln!(
+                "{}: {} is out of date; {added} entries would be added and {removed} marked removed",
+                config.package,
+                path.display()
+            );
+        }
+        return Ok(database == initial);
+    }
+    if database != initial || !path.exists() {
+        database
+            .write(path, args.mark_removed)
+            .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+    }
+    println!(
+        "{}: added {added} entries and marked {removed} removed; {} has {} entries",
+        config.package,
+        path.display(),
+        database.len()
+    );
+    Ok(true)
+}
+
+fn run(args: &Args) -> Result<bool, String> {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut command = Command::new(cargo);
+    command.args(["metadata", "--format-version", "1", "--no-deps"]);
+    if let Some(manifest_path) = &args.manifest_path {
+        command.args(["--manifest-path", manifest_path]);
+    }
+    let output = command
+        .output()
+        .map_err(|e| format!("failed to run cargo metadata: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "cargo metadata failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let metadata = Json::parse(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|e| format!("invalid cargo metadata: {e}"))?;
+    let target_directory = metadata
+        .get("target_directory")
+        .and_then(Json::as_str)
+        .ok_or("invalid cargo metadata: target_directory")?;
+
+    let mut configs = package_configs(&metadata)?;
+    if !args.packages.is_empty() {
+        for package in &args.packages {
+            if !configs.iter().any(|config| &config.package == package) {
+                return Err(format!(
+                    "package {package} has no [package.metadata.pw_tokenizer] configuration"
+                ));
+            }
+        }
+        configs.retain(|config| args.packages.contains(&config.package));
+    }
+    match configs.len() {
+        0 => return Err("no packages have a [package.metadata.pw_tokenizer] configuration".into()),
+        1 => (),
+        _ if !args.elfs.is_empty() => {
+            return Err(
+                "ELF files may only be listed when updating one package; use --package".into(),
+            )
+        }
+        _ => (),
+    }
+
+    let mut up_to_date = true;
+    for config in &configs {
+        let elfs = if args.elfs.is_empty() {
+            config
+                .binaries
+                .iter()
+                .map(|binary| binary_path(Path::new(target_directory), args, binary))
+                .collect()
+        } else {
+            args.elfs.clone()
+        };
+        up_to_date &= update(config, &elfs, args)?;
+    }
+    Ok(up_to_date)
+}
+
+fn main() -> ExitCode {
+    // Cargo passes the subcommand's name as the first argument.
+    let args = std::env::args()
+        .skip(1)
+        .skip_while(|arg| arg == "pw-token-db");
+    let args = match parse_args(args) {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("error: {message}\n");
+            }
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(message) => {
+            eprintln!("error: {message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// A JSON value, as read from `cargo metadata`.
+#[derive(Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    // Numbers are not interpreted.
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = JsonParser {
+            chars: text.chars().peekable(),
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        match parser.chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("unexpected {c:?} after value")),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.get(key),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl JsonParser<'_> {
+    fn whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected {expected:?}, found {c:?}")),
+            None => Err(format!("expected {expected:?}, found end of input")),
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json, String> {
+        for c in literal.chars() {
+            self.expect(c)?;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.chars.peek() {
+            Some('n') => self.literal("null", Json::Null),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.chars.next();
+                let mut values = Vec::new();
+                self.whitespace();
+                if self.chars.next_if_eq(&']').is_some() {
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.whitespace();
+                    if self.chars.next_if_eq(&']').is_some() {
+                        return Ok(Json::Array(values));
+                    }
+                    self.expect(',')?;
+                }
+            }
+            Some('{') => {
+                self.chars.next();
+                let mut members = BTreeMap::new();
+                self.whitespace();
+                if self.chars.next_if_eq(&'}').is_some() {
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(':')?;
+                    members.insert(key, self.value()?);
+                    self.whitespace();
+                    if self.chars.next_if_eq(&'}').is_some() {
+                        return Ok(Json::Object(members));
+                    }
+                    self.expect(',')?;
+                }
+            }
+            Some(c) if *c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+                {
+                    number.push(c);
+                }
+                Ok(Json::Number(number))
+            }
+            Some(c) => Err(format!("unexpected {c:?}")),
+            None => Err("unexpected end of input".to_string()),
+        }
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, String> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let digit = self
+                .chars
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or("invalid \\u escape")?;
+            value = value * 16 + digit;
+        }
+        Ok(value)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next().ok_or("unterminated string")? {
+                '"' => return Ok(string),
+                '\\' => string.push(match self.chars.next().ok_or("unterminated string")? {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'u' => {
+                        let mut code = self.hex_escape()?;
+                        // Characters outside the BMP are UTF-16 surrogate pairs.
+                        if (0xd800..0xdc00).contains(&code) {
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let low = self.hex_escape()?;
+                            code = 0x10000
+                                + ((code - 0xd800) << 10)
+                                + (low.wrapping_sub(0xdc00) & 0x3ff);
+                        }
+                        char::from_u32(code).ok_or("invalid \\u escape")?
+                    }
+                    c => c,
+                }),
+                c => string.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &str = r#"{
+        "packages": [
+            {
+                "name": "firmware",
+                "manifest_path": "/work/firmware/Cargo.toml",
+                "targets": [
+                    {"kind": ["lib"], "name": "firmware"},
+                    {"kind": ["bin"], "name": "app"},
+                    {"kind": ["bin"], "name": "bootloader"}
+                ],
+                "metadata": {"pw_tokenizer": {"database": "tokens.csv"}}
+            },
+            {
+                "name": "tool",
+                "manifest_path": "/work/tool/Cargo.toml",
+                "targets": [{"kind": ["bin"], "name": "tool"}],
+                "metadata": {
+                    "pw_tokenizer": {
+                        "database": "../database",
+                        "binaries": ["tool"],
+                        "domain": "tool"
+                    }
+                }
+            },
+            {
+                "name": "other",
+                "manifest_path": "/work/other/Cargo.toml",
+                "targets": [],
+                "metadata": null
+            }
+        ],
+        "target_directory": "/work/target",
+        "version": 1
+    }"#;
+
+    #[test]
+    fn parses_json() {
+        assert_eq!(
+            Json::parse(r#" {"a": [1, -2.5e3, true, false, null], "b": "x\"\\\né😀"} "#),
+            Ok(Json::Object(BTreeMap::from([
+                (
+                    "a".to_string(),
+                    Json::Array(vec![
+                        Json::Number("1".to_string()),
+                        Json::Number("-2.5e3".to_string()),
+                        Json::Bool(true),
+                        Json::Bool(false),
+                        Json::Null,
+                    ])
+                ),
+                ("b".to_string(), Json::String("x\"\\\né😀".to_string())),
+            ])))
+        );
+        assert_eq!(Json::parse("[]"), Ok(Json::Array(Vec::new())));
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert!(Json::parse("\"unterminated").is_err());
+        assert!(Json::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn reads_package_configs() {
+        let configs = package_configs(&Json::parse(METADATA).unwrap()).unwrap();
+        assert_eq!(
+            configs,
+            [
+                Config {
+                    package: "firmware".to_string(),
+                    database: "/work/firmware/tokens.csv".into(),
+                    binaries: vec!["app".to_string(), "bootloader".to_string()],
+                    domain: String::new(),
+                },
+                Config {
+                    package: "tool".to_string(),
+                    database: "/work/tool/../database".into(),
+                    binaries: vec!["tool".to_string()],
+                    domain: "tool".to_string(),
+                },
+            ]
+        );
+
+        let invalid = METADATA.replace(r#""binaries": ["tool"]"#, r#""binaries": "tool""#);
+        assert_eq!(
+            package_configs(&Json::parse(&invalid).unwrap()),
+            Err(
+                "tool: package.metadata.pw_tokenizer: `binaries` must be a list of binary names"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn parses_args() {
+        let args = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(
+            args(&[
+                "-p",
+                "firmware",
+                "--target",
+                "thumbv7m-none-eabi",
+                "--release",
+                "--check"
+            ]),
+            Ok(Args {
+                packages: vec!["firmware".to_string()],
+                target: Some("thumbv7m-none-eabi".to_string()),
+                profile: Some("release".to_string()),
+                check: true,
+                ..Args::default()
+            })
+        );
+        assert_eq!(
+            args(&["--mark-removed", "a.elf", "b.elf"]),
+            Ok(Args {
+                mark_removed: true,
+                elfs: vec!["a.elf".into(), "b.elf".into()],
+                ..Args::default()
+            })
+        );
+        assert!(args(&["--profile"]).is_err());
+        assert!(args(&["--unknown"]).is_err());
+    }
+
+    #[test]
+    fn finds_binaries() {
+        let target = Path::new("/work/target");
+        assert_eq!(
+            binary_path(target, &Args::default(), "app"),
+            Path::new("/work/target/debug/app")
+        );
+        let args = Args {
+            target: Some("thumbv7m-none-eabi".to_string()),
+            profile: Some("release".to_string()),
+            ..Args::default()
+        };
+        assert_eq!(
+            binary_path(target, &args, "app"),
+            Path::new("/work/target/thumbv7m-none-eabi/release/app")
+        );
+    }
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(date_from_days(0), "1970-01-01");
+        assert_eq!(date_from_days(11016), "2000-02-29");
+        assert_eq!(date_from_days(19796), "2024-03-14");
+    }
+
+    #[test]
+    fn updates_database() {
+        let dir = std::env::temp_dir().join(format!("cargo_pw_token_db_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let elf = dir.join("firmware.elf");
+        std::fs::write(
+            &elf,
+            include_bytes!("../py/example_binary_with_tokenized_strings.elf"),
+        )
+        .unwrap();
+        let elfs = [elf];
+        let config = Config {
+            package: "test".to_string(),
+            database: dir.join("tokens.csv"),
+            binaries: Vec::new(),
+            domain: "TEST_DOMAIN".to_string(),
+        };
+        std::fs::write(&config.database, "00000001,          ,\"Old\"\n").unwrap();
+
+        let check = Args {
+            check: true,
+            ..Args::default()
+        };
+        assert_eq!(update(&config, &elfs, &check), Ok(false));
+        assert_eq!(update(&config, &elfs, &Args::default()), Ok(true));
+        assert_eq!(update(&config, &elfs, &check), Ok(true));
+        let database = Database::read(&config.database).unwrap();
+        assert_eq!(database.len(), 6);
+        assert_eq!(database.lookup(1)[0].date_removed, None);
+
+        let mark_removed = Args {
+            mark_removed: true,
+            ..Args::default()
+        };
+        assert_eq!(update(&config, &elfs, &mark_removed), Ok(true));
+        let database = Database::read(&config.database).unwrap();
+        assert_eq!(database.lookup(1)[0].date_removed, Some(today()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
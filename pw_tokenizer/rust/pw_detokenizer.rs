@@ -0,0 +1,1193 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! `pw_detokenizer` converts tokenized messages back to strings on the host,
+//! using the token databases produced by `pw_tokenizer`'s database tools.
+//!
+//! A [`Database`] is read from a CSV database file or a directory database,
+//! and a [`Detokenizer`] looks up each message's token in it and formats the
+//! message's encoded arguments into the tokenized format string:
+//!
+//! ```
+//! use pw_detokenizer::{Database, Detokenizer};
+//!
+//! let database = Database::parse_csv("0000002a,          ,\"Battery at %d%%\"\n");
+//! let detokenizer = Detokenizer::new(database);
+//! assert_eq!(
+//!     detokenizer.detokenize(&[0x2a, 0, 0, 0, 0xa8, 0x01]).as_deref(),
+//!     Some("Battery at 84%")
+//! );
+//! ```
+//!
+//! Databases can also be created from the tokenized strings in ELF files with
+//! [`Database::from_elf()`], updated, and written back to CSV or directory
+//! databases with [`Database::write()`], as the Python `database.py` tool
+//! does.
+//!
+//! Arguments are decoded as in the Python and C++ detokenizers.  If several
+//! strings have a message's token, the string which decodes the arguments
+//! best is used.  Arguments which can not be decoded are replaced by an error
+//! such as `<[%d MISSING]>`.
+//!
+//! *Note*: This module requires `std`.
+#![deny(missing_docs)]
+
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+
+use pw_elf_reader::Elf;
+
+use pw_format::{
+    ConversionSpec, Flag, FormatFragment, FormatString, Length, MinFieldWidth, Precision, Specifier,
+};
+use pw_status::{Error, Result};
+use pw_tokenizer_core::TOKENIZER_ENTRY_MAGIC;
+
+/// The suffix of the CSV files in a directory database.
+pub const DIRECTORY_DATABASE_SUFFIX: &str = ".pw_tokenizer.csv";
+
+/// The name of the ELF sections tokenized string entries are stored in.
+/// Sections with this name followed by a `.` suffix are also read.
+pub const ENTRIES_SECTION: &str = ".pw_tokenizer.entries";
+
+// The size of an entry's header, which is its magic number, token, domain
+// size, and string size.
+const ENTRY_HEADER_SIZE: usize = 16;
+
+// The first bytes of a binary database, which is not supported.
+const BINARY_DATABASE_MAGIC: &[u8] = b"TOKENS\0\0";
+
+/// A string in a token database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// The string's token.
+    pub token: u32,
+    /// The date the string was removed from the source, as an ISO 8601
+    /// date such as `2024-03-14`, or `None` if it is still present.
+    pub date_removed: Option<String>,
+    /// The string.
+    pub string: String,
+}
+
+/// A token database, which maps tokens to the strings which have them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Database {
+    entries: BTreeMap<u32, Vec<Entry>>,
+}
+
+impl Database {
+    /// Creates an empty database.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a CSV database, which has a `token,date removed,"string"`
+    /// record per entry.
+    ///
+    /// Malformed records are skipped, as they are by the Python tools.
+    pub fn parse_csv(csv: &str) -> Self {
+        let mut database = Self::new();
+        for record in csv_records(csv) {
+            let [token, date_removed, string] = &record[..] else {
+                continue;
+            };
+            let Ok(token) = u32::from_str_radix(token.trim(), 16) else {
+                continue;
+            };
+            let date_removed = date_removed.trim();
+            database.add(Entry {
+                token,
+                date_removed: (!date_removed.is_empty()).then(|| date_removed.to_string()),
+                string: string.clone(),
+            });
+        }
+        database
+    }
+
+    /// Reads a CSV database file, or a directory database of
+    /// [`DIRECTORY_DATABASE_SUFFIX`] files.
+    ///
+    /// # Errors
+    /// - [`Error::NotFound`] - `path` does not exist.
+    /// - [`Error::Unimplemented`] - `path` is a binary database.
+    /// - [`Error::DataLoss`] - `path` is not UTF-8 text.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return Self::read_csv(path);
+        }
+        let mut database = Self::new();
+        for file in csv_files(path)? {
+            database.merge(Self::read_csv(&file)?);
+        }
+        Ok(database)
+    }
+
+    fn read_csv(path: &Path) -> Result<Self> {
+        let data = fs::read(path).map_err(|_| Error::NotFound)?;
+        if data.starts_with(BINARY_DATABASE_MAGIC) {
+            return Err(Error::Unimplemented);
+        }
+        let csv = String::from_utf8(data).map_err(|_| Error::DataLoss)?;
+        Ok(Self::parse_csv(&csv))
+    }
+
+    /// Reads the tokenized strings in `domain` from the [`ENTRIES_SECTION`]
+    /// sections of an ELF file.  The default domain is `""`.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The sections contain malformed entries.
+    pub fn from_elf(elf: &Elf, domain: &str) -> Result<Self> {
+        let mut database = Self::new();
+        let Some(data) = elf.section_contents(|name| {
+            name.strip_prefix(ENTRIES_SECTION)
+                .is_some_and(|suffix| suffix.is_empty() || suffix.starts_with('.'))
+        }) else {
+            return Ok(database);
+        };
+
+        let mut data = data.as_slice();
+        while data.len() >= ENTRY_HEADER_SIZE {
+            let field = |index: usize| {
+                u32::from_le_bytes(data[index * 4..index * 4 + 4].try_into().unwrap())
+            };
+            let (magic, token) = (field(0), field(1));
+            let domain_size = field(2) as usize;
+            let string_size = field(3) as usize;
+            if magic != TOKENIZER_ENTRY_MAGIC {
+                return Err(Error::DataLoss);
+            }
+
+            // The domain and string are null terminated.
+            let end = ENTRY_HEADER_SIZE + domain_size + string_size;
+            let entry = data.get(ENTRY_HEADER_SIZE..end).ok_or(Error::DataLoss)?;
+            let (entry_domain, string) = entry.split_at(domain_size);
+            let (Some((0, entry_domain)), Some((0, string))) =
+                (entry_domain.split_last(), string.split_last())
+            else {
+                return Err(Error::DataLoss);
+            };
+            if entry_domain == domain.as_bytes() {
+                database.add(Entry {
+                    token,
+                    date_removed: None,
+                    string: String::from_utf8_lossy(string).into_owned(),
+                });
+            }
+            data = &data[end..];
+        }
+        Ok(database)
+    }
+
+    /// Adds an entry.  If the database already has the string with the same
+    /// token, the latest of the two removal dates is kept.
+    pub fn add(&mut self, entry: Entry) {
+        let entries = self.entries.entry(entry.token).or_default();
+        match entries
+            .iter_mut()
+            .find(|existing| existing.string == entry.string)
+        {
+            Some(existing) => {
+                if newest_date(&entry.date_removed) > newest_date(&existing.date_removed) {
+                    existing.date_removed = entry.date_removed;
+                }
+            }
+            None => entries.push(entry),
+        }
+        sort_entries(entries);
+    }
+
+    /// Adds the entries of `other`.
+    pub fn merge(&mut self, other: Database) {
+        for entry in other.entries.into_values().flatten() {
+            self.add(entry);
+        }
+    }
+
+    /// Marks the entries which are not in `present` as removed on `date`,
+    /// an ISO 8601 date such as `2024-03-14`.  Entries already removed after
+    /// `date` are given the earlier date.
+    ///
+    /// Returns the number of entries marked as removed.
+    pub fn mark_removed(&mut self, present: &Database, date: &str) -> usize {
+        let mut marked = 0;
+        for entries in self.entries.values_mut() {
+            for entry in entries.iter_mut() {
+                let is_present = present
+                    .lookup(entry.token)
+                    .iter()
+                    .any(|other| other.string == entry.string);
+                let is_newer = entry
+                    .date_removed
+                    .as_ref()
+                    .is_none_or(|removed| date < removed.as_str());
+                if !is_present && is_newer {
+                    entry.date_removed = Some(date.to_string());
+                    marked += 1;
+                }
+            }
+            sort_entries(entries);
+        }
+        marked
+    }
+
+    /// Returns the entries whose token and string are not in `other`.
+    pub fn difference(&self, other: &Database) -> Database {
+        let mut difference = Self::new();
+        for entry in self.entries() {
+            if !other
+                .lookup(entry.token)
+                .iter()
+                .any(|other| other.string == entry.string)
+            {
+                difference.add(entry.clone());
+            }
+        }
+        difference
+    }
+
+    /// Returns the database in CSV format, as the Python tools write it.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        for entry in self.entries() {
+            csv.push_str(&format!(
+                "{:08x},{:10},\"{}\"\n",
+                entry.token,
+                entry.date_removed.as_deref().unwrap_or(""),
+                entry.string.replace('"', "\"\""),
+            ));
+        }
+        csv
+    }
+
+    /// Writes the database to the CSV database file or directory database at
+    /// `path`, as the Python tools update databases.
+    ///
+    /// A CSV file is overwritten.  For a directory, the entries which are not
+    /// already in the directory are written to a new CSV file, unless
+    /// `rewrite` is set, in which case the whole database is written to a new
+    /// file which replaces the existing ones.
+    ///
+    /// # Errors
+    /// - [`Error::Unavailable`] - A file could not be written or removed.
+    /// - Any error from reading the existing directory database.
+    pub fn write(&self, path: impl AsRef<Path>, rewrite: bool) -> Result<()> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return fs::write(path, self.to_csv()).map_err(|_| Error::Unavailable);
+        }
+        let existing = csv_files(path)?;
+
+        if rewrite {
+            let file = new_csv_file(path);
+            fs::write(&file, self.to_csv()).map_err(|_| Error::Unavailable)?;
+            for old_file in existing {
+                fs::remove_file(old_file).map_err(|_| Error::Unavailable)?;
+            }
+        } else {
+            let new_entries = self.difference(&Self::read(path)?);
+            if !new_entries.is_empty() {
+                fs::write(new_csv_file(path), new_entries.to_csv())
+                    .map_err(|_| Error::Unavailable)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the entries with `token`, with the most recently removed
+    /// first.
+    pub fn lookup(&self, token: u32) -> &[Entry] {
+        self.entries.get(&token).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the entries, sorted by token, removal date with the newest
+    /// first, and string.
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values().flatten()
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    /// Returns whether the database has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+// Returns the CSV files of a directory database, in sorted order.
+fn csv_files(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<_> = fs::read_dir(directory)
+        .map_err(|_| Error::NotFound)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| {
+            file.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.ends_with(DIRECTORY_DATABASE_SUFFIX))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// Returns a path for a new CSV file in a directory database, with a random
+// name as the Python tools use.
+fn new_csv_file(directory: &Path) -> PathBuf {
+    loop {
+        let random = || RandomState::new().build_hasher().finish();
+        let file = directory.join(format!(
+            "{:016x}{:016x}{DIRECTORY_DATABASE_SUFFIX}",
+            random(),
+            random()
+        ));
+        if !file.exists() {
+            return file;
+        }
+    }
+}
+
+// Sorts entries with the same token by removal date, with the newest first,
+// and then by string.
+fn sort_entries(entries: &mut [Entry]) {
+    entries.sort_by(|a, b| {
+        newest_date(&b.date_removed)
+            .cmp(&newest_date(&a.date_removed))
+            .then_with(|| a.string.cmp(&b.string))
+    });
+}
+
+// Returns a sort key for removal dates in which a string which is still
+// present is the newest.
+fn newest_date(date_removed: &Option<String>) -> (bool, &str) {
+    match date_removed {
+        Some(date) => (false, date),
+        None => (true, ""),
+    }
+}
+
+// Splits CSV text into records of fields, as RFC 4180 with either line
+// ending.  Quoted fields may contain commas, newlines, and `""` escapes.
+fn csv_records(csv: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) => in_quotes = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) if chars.peek() == Some(&'\n') => (),
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+/// Detokenizes messages with a token database.
+pub struct Detokenizer {
+    database: Database,
+}
+
+impl Detokenizer {
+    /// Creates a detokenizer which looks up tokens in `database`.
+    pub fn new(database: Database) -> Self {
+        Self { database }
+    }
+
+    /// Returns the detokenizer's database.
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+
+    /// Detokenizes a binary message, which is a little endian token followed
+    /// by the encoded arguments.
+    ///
+    /// Returns `None` if the message is too short to have a token or the
+    /// token is not in the database.
+    pub fn detokenize(&self, message: &[u8]) -> Option<String> {
+        let token = u32::from_le_bytes(message.get(..4)?.try_into().unwrap());
+        let args = &message[4..];
+        self.database
+            .lookup(token)
+            .iter()
+            .map(|entry| (format(&entry.string, args), entry))
+            .max_by_key(|(formatted, entry)| (formatted.score(), newest_date(&entry.date_removed)))
+            .map(|(formatted, _)| formatted.text)
+    }
+
+    /// Detokenizes a message encoded as text, which is `$` followed by the
+    /// Base64 encoded binary message.
+    ///
+    /// Returns `None` if the message is not valid Base64 or can not be
+    /// detokenized.
+    pub fn detokenize_base64(&self, message: &str) -> Option<String> {
+        let encoded = message.strip_prefix('$')?.trim_end().as_bytes();
+        let mut decoded = vec![0u8; pw_base64::max_decoded_size(encoded.len())];
+        let len = pw_base64::decode(encoded, &mut decoded).ok()?;
+        self.detokenize(&decoded[..len])
+    }
+}
+
+/// A format string formatted with encoded arguments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FormattedString {
+    /// The formatted text, with errors in place of arguments which could
+    /// not be decoded.
+    pub text: String,
+    /// The number of arguments decoded.
+    pub args: usize,
+    /// The number of arguments which could not be decoded.
+    pub errors: usize,
+    /// The number of encoded bytes left after the last argument.
+    pub remaining: usize,
+}
+
+impl FormattedString {
+    /// Returns whether all arguments were decoded and no data was left.
+    pub fn ok(&self) -> bool {
+        self.errors == 0 && self.remaining == 0
+    }
+
+    // Sorts formatted strings by how successfully they were decoded, as the
+    // Python and C++ detokenizers do.
+    fn score(&self) -> (bool, bool, Reverse<usize>, usize) {
+        (
+            self.ok(),
+            self.remaining == 0,
+            Reverse(self.errors),
+            self.args,
+        )
+    }
+}
+
+/// Formats the printf-style `format_string` with its encoded `args`.
+pub fn format(format_string: &str, args: &[u8]) -> FormattedString {
+    let Ok(parsed) = FormatString::parse(format_string) else {
+        return FormattedString {
+            text: format_string.to_string(),
+            args: 0,
+            errors: 1,
+            remaining: args.len(),
+        };
+    };
+    let mut formatted = FormattedString {
+        text: String::new(),
+        args: 0,
+        errors: 0,
+        remaining: 0,
+    };
+    let mut data = args;
+    for fragment in &parsed.fragments {
+        match fragment {
+            FormatFragment::Literal(literal) => formatted.text.push_str(literal),
+            FormatFragment::Percent => formatted.text.push('%'),
+            FormatFragment::Conversion(spec) => {
+                formatted.args += 1;
+                if formatted.errors > 0 {
+                    // Arguments after an error can not be located.
+                    formatted.errors += 1;
+                    formatted
+                        .text
+                        .push_str(&format!("<[{} SKIPPED]>", spec_string(spec)));
+                    continue;
+                }
+                match decode_arg(spec, &mut data) {
+                    Ok(arg) => formatted.text.push_str(&arg),
+                    Err(status) => {
+                        formatted.errors += 1;
+                        formatted
+                            .text
+                            .push_str(&format!("<[{} {status}]>", spec_string(spec)));
+                    }
+                }
+            }
+        }
+    }
+    if formatted.errors == 0 {
+        formatted.remaining = data.len();
+    }
+    formatted
+}
+
+// Reconstructs the text of a conversion specification.
+fn spec_string(spec: &ConversionSpec) -> String {
+    let mut text = String::from("%");
+    for (flag, c) in [
+        (Flag::LeftJustify, '-'),
+        (Flag::ForceSign, '+'),
+        (Flag::SpaceSign, ' '),
+        (Flag::AlternateSyntax, '#'),
+        (Flag::LeadingZeros, '0'),
+    ] {
+        if spec.flags.contains(&flag) {
+            text.push(c);
+        }
+    }
+    match spec.min_field_width {
+        MinFieldWidth::None => (),
+        MinFieldWidth::Fixed(width) => text.push_str(&width.to_string()),
+        MinFieldWidth::Variable => text.push('*'),
+    }
+    match spec.precision {
+        Precision::None => (),
+        Precision::Fixed(precision) => text.push_str(&format!(".{precision}")),
+        Precision::Variable => text.push_str(".*"),
+    }
+    text.push_str(match spec.length {
+        None => "",
+        Some(Length::Char) => "hh",
+        Some(Length::Short) => "h",
+        Some(Length::Long) => "l",
+        Some(Length::LongLong) => "ll",
+        Some(Length::LongDouble) => "L",
+        Some(Length::IntMax) => "j",
+        Some(Length::Size) => "z",
+        Some(Length::PointerDiff) => "t",
+    });
+    text.push(match spec.specifier {
+        Specifier::Decimal => 'd',
+        Specifier::Integer => 'i',
+        Specifier::Octal => 'o',
+        Specifier::Unsigned => 'u',
+        Specifier::Hex => 'x',
+        Specifier::UpperHex => 'X',
+        Specifier::Double => 'f',
+        Specifier::UpperDouble => 'F',
+        Specifier::Exponential => 'e',
+        Specifier::UpperExponential => 'E',
+        Specifier::SmallDouble => 'g',
+        Specifier::UpperSmallDouble => 'G',
+        Specifier::Char => 'c',
+        Specifier::String => 's',
+        Specifier::Pointer => 'p',
+        Specifier::Untyped => 'v',
+    });
+    text
+}
+
+// Why an argument could not be decoded, as shown in its place.
+enum ArgError {
+    Missing,
+    Error,
+}
+
+impl std::fmt::Display for ArgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            ArgError::Missing => "MISSING",
+            ArgError::Error => "ERROR",
+        })
+    }
+}
+
+fn decode_signed(data: &mut &[u8]) -> std::result::Result<i64, ArgError> {
+    if data.is_empty() {
+        return Err(ArgError::Missing);
+    }
+    let (len, value) = pw_varint::decode_i64(data).map_err(|_| ArgError::Error)?;
+    *data = &data[len..];
+    Ok(value)
+}
+
+// Decodes and formats the argument for `spec` from the front of `data`.
+fn decode_arg(spec: &ConversionSpec, data: &mut &[u8]) -> std::result::Result<String, ArgError> {
+    let mut left_justify = spec.flags.contains(&Flag::LeftJustify);
+    let width = match spec.min_field_width {
+        MinFieldWidth::None => 0,
+        MinFieldWidth::Fixed(width) => width as usize,
+        MinFieldWidth::Variable => {
+            // A negative width left justifies the argument.
+            let width = decode_signed(data)?;
+            left_justify |= width < 0;
+            width.unsigned_abs() as usize
+        }
+    };
+    let precision = match spec.precision {
+        Precision::None => None,
+        Precision::Fixed(precision) => Some(precision as usize),
+        // A negative precision is taken as if the precision were omitted.
+        Precision::Variable => usize::try_from(decode_signed(data)?).ok(),
+    };
+    let options = Options {
+        left_justify,
+        force_sign: spec.flags.contains(&Flag::ForceSign),
+        space_sign: spec.flags.contains(&Flag::SpaceSign),
+        alternate: spec.flags.contains(&Flag::AlternateSyntax),
+        leading_zeros: spec.flags.contains(&Flag::LeadingZeros),
+        width,
+        precision,
+    };
+
+    let unsigned = |value: i64| match spec.length {
+        Some(Length::LongLong | Length::IntMax) => value as u64,
+        _ => value as u32 as u64,
+    };
+    Ok(match spec.specifier {
+        Specifier::Decimal | Specifier::Integer => {
+            let value = decode_signed(data)?;
+            options.integer(value < 0, &value.unsigned_abs().to_string(), "")
+        }
+        Specifier::Unsigned => {
+            options.integer(false, &unsigned(decode_signed(data)?).to_string(), "")
+        }
+        Specifier::Hex | Specifier::UpperHex => {
+            let value = unsigned(decode_signed(data)?);
+            let upper = spec.specifier == Specifier::UpperHex;
+            let digits = if upper {
+                format!("{value:X}")
+            } else {
+                format!("{value:x}")
+            };
+            let prefix = match (options.alternate && value != 0, upper) {
+                (false, _) => "",
+                (true, false) => "0x",
+                (true, true) => "0X",
+            };
+            options.integer(false, &digits, prefix)
+        }
+        Specifier::Octal => {
+            let value = unsigned(decode_signed(data)?);
+            let digits = format!("{value:o}");
+            let prefix = if options.alternate && value != 0 {
+                "0"
+            } else {
+                ""
+            };
+            options.integer(false, &digits, prefix)
+        }
+        Specifier::Pointer => {
+            let value = unsigned(decode_signed(data)?);
+            options.pad(format!("0x{value:08X}"), false)
+        }
+        Specifier::Char => {
+            let value = decode_signed(data)?;
+            let c = u32::try_from(value)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or(ArgError::Error)?;
+            options.pad(c.to_string(), false)
+        }
+        Specifier::String => {
+            let (&size_and_status, rest) = data.split_first().ok_or(ArgError::Missing)?;
+            let truncated = size_and_status & 0x80 != 0;
+            let size = usize::from(size_and_status & 0x7f);
+            let bytes = rest.get(..size).ok_or(ArgError::Error)?;
+            *data = &rest[size..];
+            let mut string = String::from_utf8_lossy(bytes).into_owned();
+            if let Some(precision) = precision {
+                string = string.chars().take(precision).collect();
+            }
+            if truncated {
+                string.push_str("[...]");
+            }
+            options.pad(string, false)
+        }
+        Specifier::Double
+        | Specifier::UpperDouble
+        | Specifier::Exponential
+        | Specifier::UpperExponential
+        | Specifier::SmallDouble
+        | Specifier::UpperSmallDouble => {
+            let bytes = data.get(..4).ok_or(ArgError::Missing)?;
+            let value = f32::from_le_bytes(bytes.try_into().unwrap());
+            *data = &data[4..];
+            options.float(&spec.specifier, value.into())
+        }
+        // Untyped arguments are converted to typed arguments when they are
+        // tokenized, so they can not be decoded.
+        Specifier::Untyped => return Err(ArgError::Error),
+    })
+}
+
+// The formatting options of a conversion specification.
+struct Options {
+    left_justify: bool,
+    force_sign: bool,
+    space_sign: bool,
+    alternate: bool,
+    leading_zeros: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Options {
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.force_sign {
+            "+"
+        } else if self.space_sign {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    // Pads `text` to the field width.  Numbers are padded with zeros after
+    // their sign and prefix, which are `prefix_len` bytes, if requested.
+    fn pad_number(&self, text: String, prefix_len: usize, zeros: bool) -> String {
+        let len = text.chars().count();
+        if len >= self.width {
+            return text;
+        }
+        let padding = self.width - len;
+        if self.left_justify {
+            text + &" ".repeat(padding)
+        } else if zeros && self.leading_zeros {
+            format!(
+                "{}{}{}",
+                &text[..prefix_len],
+                "0".repeat(padding),
+                &text[prefix_len..]
+            )
+        } else {
+            " ".repeat(padding) + &text
+        }
+    }
+
+    fn pad(&self, text: String, zeros: bool) -> String {
+        self.pad_number(text, 0, zeros)
+    }
+
+    fn integer(&self, negative: bool, digits: &str, prefix: &str) -> String {
+        let digits = match self.precision {
+            // Zero with a precision of zero has no digits.
+            Some(0) if digits == "0" => "",
+            Some(precision) if digits.len() < precision => {
+                return self.pad(
+                    format!(
+                        "{}{prefix}{}{digits}",
+                        self.sign(negative),
+                        "0".repeat(precision - digits.len())
+                    ),
+                    false,
+                );
+            }
+            _ => digits,
+        };
+        let prefix = format!("{}{prefix}", self.sign(negative));
+        let prefix_len = prefix.len();
+        // Zero padding is ignored if there is a precision.
+        self.pad_number(prefix + digits, prefix_len, self.precision.is_none())
+    }
+
+    fn float(&self, specifier: &Specifier, value: f64) -> String {
+        let upper = matches!(
+            specifier,
+            Specifier::UpperDouble | Specifier::UpperExponential | Specifier::UpperSmallDouble
+        );
+        let sign = self.sign(value.is_sign_negative() && !value.is_nan());
+        if !value.is_finite() {
+            let text = if value.is_nan() { "nan" } else { "inf" };
+            let text = if upper {
+                text.to_uppercase()
+            } else {
+                text.to_string()
+            };
+            // Infinity and NaN are not padded with zeros.
+            return self.pad(format!("{sign}{text}"), false);
+        }
+        let value = value.abs();
+        let precision = self.precision.unwrap_or(6);
+        let text = match specifier {
+            Specifier::Double | Specifier::UpperDouble => {
+                let text = format!("{value:.precision$}");
+                if self.alternate && precision == 0 {
+                    text + "."
+                } else {
+                    text
+                }
+            }
+            Specifier::Exponential | Specifier::UpperExponential => {
+                exponential(value, precision, self.alternate)
+            }
+            _ => {
+                let precision = precision.max(1);
+                let exponent = exponent(value, precision - 1);
+                let text = if exponent < -4 || exponent >= precision as i32 {
+                    exponential(value, precision - 1, self.alternate)
+                } else {
+                    let decimals = (precision as i32 - 1 - exponent) as usize;
+                    format!("{value:.decimals$}")
+                };
+                if self.alternate {
+                    text
+                } else {
+                    strip_trailing_zeros(&text)
+                }
+            }
+        };
+        let text = if upper { text.to_uppercase() } else { text };
+        self.pad_number(format!("{sign}{text}"), sign.len(), true)
+    }
+}
+
+// Returns the decimal exponent of `value` when it is rounded to `precision`
+// digits after the decimal point in exponential notation.
+fn exponent(value: f64, precision: usize) -> i32 {
+    let text = format!("{value:.precision$e}");
+    text[text.find('e').unwrap() + 1..].parse().unwrap()
+}
+
+// Formats `value` as C does for `%e`, with a signed exponent of at least two
+// digits.
+fn exponential(value: f64, precision: usize, alternate: bool) -> String {
+    let text = format!("{value:.precision$e}");
+    let (mantissa, exponent) = text.split_at(text.find('e').unwrap());
+    let exponent: i32 = exponent[1..].parse().unwrap();
+    let point = if alternate && precision == 0 { "." } else { "" };
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{mantissa}{point}e{sign}{:02}", exponent.unsigned_abs())
+}
+
+// Removes trailing zeros after the decimal point, and the decimal point if
+// no digits follow it, as `%g` does.
+fn strip_trailing_zeros(text: &str) -> String {
+    let (number, exponent) = text.split_at(text.find('e').unwrap_or(text.len()));
+    let number = if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    };
+    format!("{number}{exponent}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(token: u32, args: &[u8]) -> Vec<u8> {
+        let mut message = token.to_le_bytes().to_vec();
+        message.extend_from_slice(args);
+        message
+    }
+
+    #[test]
+    fn parses_csv_database() {
+        let database = Database::parse_csv(
+            "00000001,          ,\"Hello\"\n\
+             00000001,2024-01-02,\"Goodbye, \"\"world\"\"\"\r\n\
+             0000000a,          ,\"Two\nlines\"\n\
+             not a token,          ,\"Ignored\"\n\
+             00000002,\"Too few fields\"\n",
+        );
+        assert_eq!(database.len(), 3);
+        assert_eq!(
+            database.lookup(1),
+            &[
+                Entry {
+                    token: 1,
+                    date_removed: None,
+                    string: "Hello".to_string(),
+                },
+                Entry {
+                    token: 1,
+                    date_removed: Some("2024-01-02".to_string()),
+                    string: "Goodbye, \"world\"".to_string(),
+                },
+            ]
+        );
+        assert_eq!(database.lookup(10)[0].string, "Two\nlines");
+        assert!(database.lookup(2).is_empty());
+    }
+
+    #[test]
+    fn adding_entry_keeps_latest_removal_date() {
+        let mut database = Database::parse_csv("00000001,2024-01-02,\"Hello\"\n");
+        database.add(Entry {
+            token: 1,
+            date_removed: Some("2023-01-01".to_string()),
+            string: "Hello".to_string(),
+        });
+        assert_eq!(
+            database.lookup(1)[0].date_removed.as_deref(),
+            Some("2024-01-02")
+        );
+        database.add(Entry {
+            token: 1,
+            date_removed: None,
+            string: "Hello".to_string(),
+        });
+        assert_eq!(database.lookup(1)[0].date_removed, None);
+        assert_eq!(database.len(), 1);
+    }
+
+    #[test]
+    fn reads_directory_database() {
+        let dir = std::env::temp_dir().join(format!("pw_detokenizer_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("a.pw_tokenizer.csv"),
+            "00000001,          ,\"One\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("b.pw_tokenizer.csv"),
+            "00000002,          ,\"Two\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("c.csv"), "00000003,          ,\"Other\"\n").unwrap();
+
+        let database = Database::read(&dir).unwrap();
+        assert_eq!(database.len(), 2);
+        assert_eq!(
+            Database::read(dir.join("c.csv")).unwrap().lookup(3)[0].string,
+            "Other"
+        );
+        assert_eq!(
+            Database::read(dir.join("missing.csv")),
+            Err(Error::NotFound)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_elf_database() {
+        let data = include_bytes!("../py/example_binary_with_tokenized_strings.elf");
+        let elf = Elf::parse(data).unwrap();
+
+        let database = Database::from_elf(&elf, "").unwrap();
+        assert_eq!(database.len(), 22);
+        assert_eq!(database.lookup(0x881436a0)[0].string, "The answer is: %s");
+        assert_eq!(database.lookup(0x2e668cd6)[0].string, "Jello, world!");
+
+        let database = Database::from_elf(&elf, "TEST_DOMAIN").unwrap();
+        assert_eq!(database.len(), 5);
+        assert_eq!(database.lookup(0x59b2701c)[0].string, "The answer was: %s");
+        assert!(Database::from_elf(&elf, "OTHER").unwrap().is_empty());
+    }
+
+    #[test]
+    fn writes_csv_database() {
+        let csv = "00000001,          ,\"Present\"\n\
+                   00000001,2024-01-02,\"Removed \"\"later\"\"\"\n\
+                   00000001,2023-05-06,\"A removed\"\n\
+                   00000001,2023-05-06,\"Removed\"\n\
+                   0000abcd,          ,\"Other\"\n";
+        // Entries are sorted regardless of the input order.
+        let lines: Vec<&str> = csv.lines().rev().collect();
+        let database = Database::parse_csv(&(lines.join("\n") + "\n"));
+        assert_eq!(database.to_csv(), csv);
+        assert_eq!(Database::parse_csv(csv), database);
+    }
+
+    #[test]
+    fn marks_entries_removed() {
+        let mut database = Database::parse_csv(
+            "00000001,          ,\"Present\"\n\
+             00000002,          ,\"Missing\"\n\
+             00000003,2025-01-01,\"Removed later\"\n\
+             00000004,2023-01-01,\"Removed earlier\"\n",
+        );
+        let present = Database::parse_csv("00000001,          ,\"Present\"\n");
+        assert_eq!(database.mark_removed(&present, "2024-03-14"), 2);
+        let dates: Vec<_> = database
+            .entries()
+            .map(|entry| entry.date_removed.as_deref())
+            .collect();
+        assert_eq!(
+            dates,
+            [
+                None,
+                Some("2024-03-14"),
+                Some("2024-03-14"),
+                Some("2023-01-01")
+            ]
+        );
+        assert_eq!(database.difference(&present).len(), 3);
+    }
+
+    #[test]
+    fn writes_directory_database() {
+        let dir = std::env::temp_dir().join(format!("pw_detokenizer_write_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("a.pw_tokenizer.csv"),
+            "00000001,          ,\"One\"\n",
+        )
+        .unwrap();
+
+        let mut database = Database::read(&dir).unwrap();
+        database.add(Entry {
+            token: 2,
+            date_removed: None,
+            string: "Two".to_string(),
+        });
+        // Only the new entry is written to a new file.
+        database.write(&dir, false).unwrap();
+        let files = csv_files(&dir).unwrap();
+        assert_eq!(files.len(), 2);
+        let new_file = files
+            .iter()
+            .find(|file| !file.ends_with("a.pw_tokenizer.csv"))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(new_file).unwrap(),
+            "00000002,          ,\"Two\"\n"
+        );
+        assert_eq!(Database::read(&dir).unwrap(), database);
+
+        // Nothing is written if there are no new entries.
+        database.write(&dir, false).unwrap();
+        assert_eq!(csv_files(&dir).unwrap().len(), 2);
+
+        // Rewriting replaces the files with one.
+        database.mark_removed(&Database::new(), "2024-03-14");
+        database.write(&dir, true).unwrap();
+        let files = csv_files(&dir).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(fs::read_to_string(&files[0]).unwrap(), database.to_csv());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn formats_integers() {
+        // Arguments are ZigZag encoded varints.
+        assert_eq!(format("%d %i", &[0x03, 0x04]).text, "-2 2");
+        assert_eq!(format("%u", &[0x01]).text, "4294967295");
+        assert_eq!(format("%llu", &[0x01]).text, "18446744073709551615");
+        assert_eq!(
+            format("%x %#X %o", &[0xfe, 0x01, 0x54, 0x10]).text,
+            "7f 0X2A 10"
+        );
+        assert_eq!(
+            format("[%5d|%-5d|%05d]", &[0x54, 0x54, 0x53]).text,
+            "[   42|42   |-0042]"
+        );
+        assert_eq!(format("%+.3d % d", &[0x0e, 0x0e]).text, "+007  7");
+        assert_eq!(format("%.0d", &[0x00]).text, "");
+        assert_eq!(format("%*d", &[0x08, 0x0e]).text, "   7");
+        assert_eq!(format("%p", &[0x80, 0x80, 0x04]).text, "0x00008000");
+    }
+
+    #[test]
+    fn formats_strings_and_chars() {
+        assert_eq!(format("%s!", b"\x05hello").text, "hello!");
+        assert_eq!(format("%.2s", b"\x05hello").text, "he");
+        assert_eq!(format("%6s|%-6s|", b"\x02hi\x02hi").text, "    hi|hi    |");
+        assert_eq!(format("%s", b"\x83abc").text, "abc[...]");
+        assert_eq!(format("%c%c", &[0x82, 0x01, 0xd2, 0x01]).text, "Ai");
+    }
+
+    #[test]
+    fn formats_floats() {
+        let float = |value: f32| value.to_le_bytes();
+        assert_eq!(format("%f", &float(1.5)).text, "1.500000");
+        assert_eq!(
+            format(
+                "%.2f|%8.3f|%-8.1f|",
+                &[float(-2.25), float(3.0), float(0.5)].concat()
+            )
+            .text,
+            "-2.25|   3.000|0.5     |"
+        );
+        assert_eq!(format("%e", &float(1234.5)).text, "1.234500e+03");
+        assert_eq!(format("%.1E", &float(0.00025)).text, "2.5E-04");
+        assert_eq!(
+            format(
+                "%g %g %g",
+                &[float(100000.0), float(1e6), float(0.0001)].concat()
+            )
+            .text,
+            "100000 1e+06 0.0001"
+        );
+        assert_eq!(
+            format("%g %G", &[float(0.5), float(1e-5)].concat()).text,
+            "0.5 1E-05"
+        );
+        assert_eq!(format("%05.1f", &float(-1.0)).text, "-01.0");
+        assert_eq!(
+            format("%f %F", &[float(f32::INFINITY), float(f32::NAN)].concat()).text,
+            "inf NAN"
+        );
+    }
+
+    #[test]
+    fn reports_argument_errors() {
+        let formatted = format("%d and %s", &[]);
+        assert_eq!(formatted.text, "<[%d MISSING]> and <[%s SKIPPED]>");
+        assert_eq!(formatted.errors, 2);
+        assert!(!formatted.ok());
+
+        let formatted = format("%d", &[0x02, 0x02]);
+        assert_eq!(formatted.text, "1");
+        assert_eq!(formatted.remaining, 1);
+        assert!(!formatted.ok());
+
+        assert_eq!(format("%f", &[0, 0]).text, "<[%f MISSING]>");
+        assert_eq!(format("%s", b"\x05hi").text, "<[%s ERROR]>");
+    }
+
+    #[test]
+    fn detokenizes_messages() {
+        let detokenizer = Detokenizer::new(Database::parse_csv(
+            "0000002a,          ,\"Temperature %d C\"\n\
+             0000002b,          ,\"No arguments\"\n",
+        ));
+        assert_eq!(
+            detokenizer.detokenize(&message(0x2a, &[0x2a])).as_deref(),
+            Some("Temperature 21 C")
+        );
+        assert_eq!(
+            detokenizer.detokenize(&message(0x2b, &[])).as_deref(),
+            Some("No arguments")
+        );
+        assert_eq!(detokenizer.detokenize(&message(0x2c, &[])), None);
+        assert_eq!(detokenizer.detokenize(&[0x2a, 0]), None);
+        // The Base64 encoding of 2a00000002.
+        assert_eq!(
+            detokenizer.detokenize_base64("$KgAAAAI=").as_deref(),
+            Some("Temperature 1 C")
+        );
+        assert_eq!(detokenizer.detokenize_base64("KgAAAAI="), None);
+    }
+
+    #[test]
+    fn resolves_collisions_by_decoding_success() {
+        let detokenizer = Detokenizer::new(Database::parse_csv(
+            "00000001,2020-01-01,\"Old %d\"\n\
+             00000001,          ,\"Current %s\"\n\
+             00000001,2023-01-01,\"Newer %d\"\n",
+        ));
+        // Only the string argument decodes completely.
+        assert_eq!(
+            detokenizer.detokenize(&message(1, b"\x02hi")).as_deref(),
+            Some("Current hi")
+        );
+        // Both integer strings decode, so the most recently removed is used.
+        assert_eq!(
+            detokenizer.detokenize(&message(1, &[0x04])).as_deref(),
+            Some("Newer 2")
+        );
+    }
+}
@@ -0,0 +1,457 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! `pw_elf_reader` reads the sections and symbols of ELF files, such as the
+//! `.pw_tokenizer.entries` sections tokenized strings are stored in.
+//!
+//! It supports 32 and 64 bit ELF files of either byte order, matching the
+//! Python `pw_tokenizer.elf_reader` module.
+//!
+//! ```no_run
+//! use pw_elf_reader::Elf;
+//!
+//! let data = std::fs::read("firmware.elf").unwrap();
+//! let elf = Elf::parse(&data)?;
+//! for section in elf.sections() {
+//!     println!("{} 0x{:08x} {}", section.name, section.address, section.size);
+//! }
+//! let entries = elf.section_contents(|name| name.starts_with(".pw_tokenizer.entries"));
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! *Note*: This module requires `std`.
+#![deny(missing_docs)]
+
+use pw_status::{Error, Result};
+
+/// The first bytes of every ELF file.
+pub const MAGIC: &[u8] = b"\x7fELF";
+
+/// The section type of symbol tables.
+pub const SHT_SYMTAB: u32 = 2;
+
+/// The section type of sections which occupy no space in the file, such as
+/// `.bss`.
+pub const SHT_NOBITS: u32 = 8;
+
+/// The section flag of sections which occupy memory when the program runs.
+pub const SHF_ALLOC: u64 = 0x2;
+
+/// The section flag of sections which are writable when the program runs.
+pub const SHF_WRITE: u64 = 0x1;
+
+/// The section flag of sections which contain executable instructions.
+pub const SHF_EXECINSTR: u64 = 0x4;
+
+/// The symbol type of data objects, such as variables.
+pub const STT_OBJECT: u8 = 1;
+
+/// The symbol type of functions.
+pub const STT_FUNC: u8 = 2;
+
+/// The symbol type of symbols which name a section.
+pub const STT_SECTION: u8 = 3;
+
+/// The symbol type of symbols which name a source file.
+pub const STT_FILE: u8 = 4;
+
+/// Returns whether `data` starts with the ELF magic number.
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+// Reads integers of the ELF file's byte order.
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&self, offset: u64) -> Result<[u8; N]> {
+        let offset = usize::try_from(offset).map_err(|_| Error::DataLoss)?;
+        self.data
+            .get(offset..)
+            .and_then(|data| data.get(..N))
+            .map(|bytes| bytes.try_into().unwrap())
+            .ok_or(Error::DataLoss)
+    }
+
+    fn u16(&self, offset: u64) -> Result<u16> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: u64) -> Result<u32> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, offset: u64) -> Result<u64> {
+        let bytes = self.bytes(offset)?;
+        Ok(if self.little_endian {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+
+    // Reads an address or offset, which is 32 or 64 bits.
+    fn word(&self, offset: u64, is_64bit: bool) -> Result<u64> {
+        if is_64bit {
+            self.u64(offset)
+        } else {
+            self.u32(offset).map(u64::from)
+        }
+    }
+
+    fn slice(&self, offset: u64, size: u64) -> Result<&'a [u8]> {
+        let start = usize::try_from(offset).map_err(|_| Error::DataLoss)?;
+        let size = usize::try_from(size).map_err(|_| Error::DataLoss)?;
+        self.data
+            .get(start..)
+            .and_then(|data| data.get(..size))
+            .ok_or(Error::DataLoss)
+    }
+
+    // Reads the null terminated string at `offset`.
+    fn str(&self, offset: u64) -> Result<&'a str> {
+        let start = usize::try_from(offset).map_err(|_| Error::DataLoss)?;
+        let data = self.data.get(start..).ok_or(Error::DataLoss)?;
+        let len = data.iter().position(|&b| b == 0).ok_or(Error::DataLoss)?;
+        core::str::from_utf8(&data[..len]).map_err(|_| Error::DataLoss)
+    }
+}
+
+/// A section of an ELF file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Section<'a> {
+    /// The section's name, such as `.text`.
+    pub name: &'a str,
+    /// The section's type (`sh_type`).
+    pub kind: u32,
+    /// The section's flags (`sh_flags`), such as [`SHF_ALLOC`].
+    pub flags: u64,
+    /// The section's address in memory.
+    pub address: u64,
+    /// The section's size in bytes, which for [`SHT_NOBITS`] sections is
+    /// the size it occupies in memory.
+    pub size: u64,
+    /// The index of an associated section (`sh_link`), such as a symbol
+    /// table's string table.
+    pub link: u32,
+    /// The section's contents, which are empty for [`SHT_NOBITS`] sections.
+    pub data: &'a [u8],
+}
+
+impl Section<'_> {
+    /// Returns whether the section occupies memory when the program runs.
+    pub fn is_allocated(&self) -> bool {
+        self.flags & SHF_ALLOC != 0
+    }
+}
+
+/// A symbol from an ELF file's symbol table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    /// The symbol's name, which may be mangled.
+    pub name: &'a str,
+    /// The symbol's address.
+    pub address: u64,
+    /// The size of the object or function, or 0 if it is unknown.
+    pub size: u64,
+    /// The symbol's type, such as [`STT_FUNC`].
+    pub kind: u8,
+    /// The symbol's binding, such as 1 for global symbols.
+    pub binding: u8,
+    /// The index of the section the symbol is defined in.
+    pub section_index: u16,
+}
+
+/// An ELF file's sections.
+pub struct Elf<'a> {
+    is_64bit: bool,
+    little_endian: bool,
+    sections: Vec<Section<'a>>,
+}
+
+impl<'a> Elf<'a> {
+    /// Parses the section headers of the ELF file in `data`.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - `data` is not an ELF file.
+    /// - [`Error::DataLoss`] - The ELF file is truncated or malformed.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        if !is_elf(data) || data.len() < 6 {
+            return Err(Error::InvalidArgument);
+        }
+        let is_64bit = match data[4] {
+            1 => false,
+            2 => true,
+            _ => return Err(Error::DataLoss),
+        };
+        let little_endian = match data[5] {
+            1 => true,
+            2 => false,
+            _ => return Err(Error::DataLoss),
+        };
+        let reader = Reader {
+            data,
+            little_endian,
+        };
+
+        let (section_table, header_size, count, names_index) = if is_64bit {
+            (
+                reader.u64(0x28)?,
+                reader.u16(0x3a)?,
+                reader.u16(0x3c)?,
+                reader.u16(0x3e)?,
+            )
+        } else {
+            (
+                u64::from(reader.u32(0x20)?),
+                reader.u16(0x2e)?,
+                reader.u16(0x30)?,
+                reader.u16(0x32)?,
+            )
+        };
+
+        // Reads the header fields of section `index`, without its name.
+        let header = |index: u16| -> Result<(u32, u32, u64, u64, u64, u64, u32)> {
+            let offset = section_table + u64::from(index) * u64::from(header_size);
+            let name = reader.u32(offset)?;
+            let kind = reader.u32(offset + 4)?;
+            let flags = reader.word(offset + 8, is_64bit)?;
+            let (address, file_offset, size, link) = if is_64bit {
+                (
+                    reader.u64(offset + 16)?,
+                    reader.u64(offset + 24)?,
+                    reader.u64(offset + 32)?,
+                    reader.u32(offset + 40)?,
+                )
+            } else {
+                (
+                    reader.word(offset + 12, false)?,
+                    reader.word(offset + 16, false)?,
+                    reader.word(offset + 20, false)?,
+                    reader.u32(offset + 24)?,
+                )
+            };
+            Ok((name, kind, flags, address, file_offset, size, link))
+        };
+
+        let names = if count == 0 {
+            0
+        } else {
+            header(names_index)?.4
+        };
+        let sections = (0..count)
+            .map(|index| {
+                let (name, kind, flags, address, file_offset, size, link) = header(index)?;
+                Ok(Section {
+                    name: reader.str(names + u64::from(name))?,
+                    kind,
+                    flags,
+                    address,
+                    size,
+                    link,
+                    data: if kind == SHT_NOBITS {
+                        &[]
+                    } else {
+                        reader.slice(file_offset, size)?
+                    },
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            is_64bit,
+            little_endian,
+            sections,
+        })
+    }
+
+    /// Returns whether the ELF file is 64 bit.
+    pub fn is_64bit(&self) -> bool {
+        self.is_64bit
+    }
+
+    /// Returns whether the ELF file is little endian.
+    pub fn is_little_endian(&self) -> bool {
+        self.little_endian
+    }
+
+    /// Returns the ELF file's sections, in the order of the section header
+    /// table.
+    pub fn sections(&self) -> &[Section<'a>] {
+        &self.sections
+    }
+
+    /// Returns the first section named `name`.
+    pub fn section(&self, name: &str) -> Option<&Section<'a>> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    /// Returns the concatenated contents of the sections whose names match,
+    /// or `None` if no sections match.
+    pub fn section_contents(&self, mut matches: impl FnMut(&str) -> bool) -> Option<Vec<u8>> {
+        let mut contents: Option<Vec<u8>> = None;
+        for section in self.sections.iter().filter(|section| matches(section.name)) {
+            contents
+                .get_or_insert_with(Vec::new)
+                .extend_from_slice(section.data);
+        }
+        contents
+    }
+
+    /// Returns the symbols in the ELF file's symbol table (`.symtab`), in
+    /// table order. Returns no symbols if the file has been stripped.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The symbol table is truncated or malformed.
+    pub fn symbols(&self) -> Result<Vec<Symbol<'a>>> {
+        let Some(table) = self
+            .sections
+            .iter()
+            .find(|section| section.kind == SHT_SYMTAB)
+        else {
+            return Ok(Vec::new());
+        };
+        let names = usize::try_from(table.link)
+            .ok()
+            .and_then(|index| self.sections.get(index))
+            .ok_or(Error::DataLoss)?;
+        let names = Reader {
+            data: names.data,
+            little_endian: self.little_endian,
+        };
+        let reader = Reader {
+            data: table.data,
+            little_endian: self.little_endian,
+        };
+
+        let entry_size = if self.is_64bit { 24 } else { 16 };
+        (0..table.data.len() as u64 / entry_size)
+            .map(|index| {
+                let offset = index * entry_size;
+                let name = reader.u32(offset)?;
+                let (address, size, info, section_index) = if self.is_64bit {
+                    (
+                        reader.u64(offset + 8)?,
+                        reader.u64(offset + 16)?,
+                        reader.bytes::<1>(offset + 4)?[0],
+                        reader.u16(offset + 6)?,
+                    )
+                } else {
+                    (
+                        reader.word(offset + 4, false)?,
+                        reader.word(offset + 8, false)?,
+                        reader.bytes::<1>(offset + 12)?[0],
+                        reader.u16(offset + 14)?,
+                    )
+                };
+                Ok(Symbol {
+                    name: names.str(u64::from(name))?,
+                    address,
+                    size,
+                    kind: info & 0xf,
+                    binding: info >> 4,
+                    section_index,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_ELF: &[u8] = include_bytes!("../py/elf_reader_test_binary.elf");
+
+    #[test]
+    fn reads_sections() {
+        let elf = Elf::parse(TEST_ELF).unwrap();
+        assert!(elf.is_64bit());
+        assert!(elf.is_little_endian());
+        assert_eq!(elf.sections().len(), 33);
+        assert_eq!(elf.sections()[0].name, "");
+        assert_eq!(elf.sections()[32].name, ".shstrtab");
+
+        let text = elf.section(".text").unwrap();
+        assert_eq!((text.address, text.size), (0x560, 0x151));
+        assert_eq!(text.flags, SHF_ALLOC | SHF_EXECINSTR);
+        assert!(text.is_allocated());
+        assert!(!elf.section(".comment").unwrap().is_allocated());
+
+        let bss = elf.section(".bss").unwrap();
+        assert_eq!((bss.kind, bss.size, bss.data), (SHT_NOBITS, 1, &[][..]));
+        assert!(elf.section(".missing").is_none());
+    }
+
+    #[test]
+    fn reads_section_contents() {
+        let elf = Elf::parse(TEST_ELF).unwrap();
+        assert_eq!(
+            elf.section(".test_section_1").unwrap().data,
+            b"You cannot pass\0"
+        );
+        assert_eq!(
+            elf.section_contents(|name| name.starts_with(".test_section_")),
+            Some([&b"You cannot pass\0"[..], &0xfeedbeef_u32.to_le_bytes()].concat())
+        );
+        assert_eq!(elf.section_contents(|name| name == ".missing"), None);
+    }
+
+    #[test]
+    fn reads_symbols() {
+        let elf = Elf::parse(TEST_ELF).unwrap();
+        let symbols = elf.symbols().unwrap();
+        assert_eq!(symbols[0].name, "");
+
+        let message = symbols.iter().find(|s| s.name == "message").unwrap();
+        assert_eq!((message.address, message.size), (0x6d0, 16));
+        assert_eq!((message.kind, message.binding), (STT_OBJECT, 1));
+        let section = &elf.sections()[usize::from(message.section_index)];
+        assert_eq!(section.name, ".test_section_1");
+
+        let start = symbols.iter().find(|s| s.name == "_start").unwrap();
+        assert_eq!(
+            (start.address, start.size, start.kind),
+            (0x560, 43, STT_FUNC)
+        );
+        assert_eq!(
+            elf.sections()[usize::from(start.section_index)].name,
+            ".text"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(is_elf(TEST_ELF));
+        assert!(!is_elf(b"not an ELF"));
+        assert_eq!(
+            Elf::parse(b"not an ELF").err(),
+            Some(Error::InvalidArgument)
+        );
+        assert_eq!(Elf::parse(&TEST_ELF[..0x100]).err(), Some(Error::DataLoss));
+    }
+}
@@ -20,12 +20,19 @@ use pw_varint::VarintEncode;
 
 use crate::MessageWriter;
 
-// The `Argument` enum is used to marshal arguments to pass to the tokenization
-// engine.
+/// An argument of a tokenized message before it is encoded.
+///
+/// Returned by [`crate::tokenize_to_args!`] for code which encodes messages
+/// after they are logged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Argument<'a> {
+    /// A string, encoded as a length byte followed by the string's bytes.
     String(&'a str),
+    /// A 32 bit integer, encoded as a ZigZag varint.
     Varint(i32),
+    /// A 64 bit integer, encoded as a ZigZag varint.
     Varint64(i64),
+    /// A character, encoded as a single byte.
     Char(u8),
 }
 
@@ -48,6 +48,8 @@ use pw_status::Result;
 #[doc(hidden)]
 pub mod internal;
 
+pub use internal::Argument;
+
 #[doc(hidden)]
 // Creating a __private namespace allows us a way to get to the modules
 // we need from macros by doing:
@@ -63,7 +65,9 @@ pub mod __private {
     pub use pw_status::Result;
     pub use pw_stream::{Cursor, Seek, WriteInteger, WriteVarint};
     pub use pw_tokenizer_core::hash_string;
-    pub use pw_tokenizer_macro::{_token, _tokenize_to_buffer, _tokenize_to_writer};
+    pub use pw_tokenizer_macro::{
+        _masked_token, _token, _tokenize_to_args, _tokenize_to_buffer, _tokenize_to_writer,
+    };
 }
 
 /// Return the [`u32`] token for the specified string and add it to the token
@@ -91,8 +95,9 @@ pub mod __private {
 /// assert_eq!(token, 3537412730);
 /// ```
 ///
-/// Currently there is no support for encoding tokens to specific domains
-/// or with "fixed lengths" per [`pw_tokenizer_core::hash_bytes_fixed`].
+/// Currently there is no support for encoding tokens with "fixed lengths" per
+/// [`pw_tokenizer_core::hash_bytes_fixed`].  Reduced width tokens and tokens
+/// in specific domains are supported with [`masked_token!`].
 #[macro_export]
 macro_rules! token {
     ($string:literal) => {{
@@ -101,6 +106,52 @@ macro_rules! token {
     }};
 }
 
+/// Return the token for the specified string masked with `mask` and add the
+/// masked token to the token database.
+///
+/// Masking reduces the width of tokens for links where even 4 byte tokens are
+/// too expensive.  `mask` is a `u32` constant expression, typically
+/// [`TOKEN_MASK_16_BIT`](pw_tokenizer_core::TOKEN_MASK_16_BIT) or
+/// [`TOKEN_MASK_8_BIT`](pw_tokenizer_core::TOKEN_MASK_8_BIT).  Since the
+/// masked token is what is added to the database, strings are detokenized
+/// from the masked token alone.
+///
+/// Narrower tokens collide more often.  Use
+/// [`pw_tokenizer_core::find_collision()`] to check a set of strings for
+/// collisions.
+///
+/// # Example
+/// ```
+/// use pw_tokenizer::{masked_token, token};
+/// use pw_tokenizer_core::TOKEN_MASK_16_BIT;
+///
+/// let token = masked_token!(TOKEN_MASK_16_BIT, "hello, \"world\"");
+/// assert_eq!(token, token!("hello, \"world\"") & 0xffff);
+/// let token = token as u16;
+/// # let _ = token;
+/// ```
+///
+/// The token may be added to a specific domain of the token database, like
+/// `PW_TOKENIZE_STRING_MASK` in C++, by passing the domain first:
+///
+/// ```
+/// use pw_tokenizer::masked_token;
+///
+/// let token = masked_token!("metrics", 0x7fff_ffff, "rx_bytes");
+/// # let _ = token;
+/// ```
+#[macro_export]
+macro_rules! masked_token {
+    ($domain:literal, $mask:expr, $string:literal) => {{
+        use $crate::__private as __pw_tokenizer_crate;
+        $crate::__private::_masked_token!($domain, $mask, $string)
+    }};
+    ($mask:expr, $string:literal) => {{
+        use $crate::__private as __pw_tokenizer_crate;
+        $crate::__private::_masked_token!($mask, $string)
+    }};
+}
+
 /// Tokenize a format string and arguments to an [`AsMut<u8>`] buffer and add
 /// the format string's token to the token database.
 ///
@@ -229,6 +280,34 @@ macro_rules! tokenize_to_writer {
     }};
 }
 
+/// Tokenize a format string and add its token to the token database without
+/// encoding the arguments.
+///
+/// Returns the format string's token and an array of [`Argument`]s, one for
+/// each conversion.  This allows code such as deferred logging to capture a
+/// message cheaply and encode it later.  Encoding the returned token and
+/// arguments produces the same message as [`tokenize_to_buffer!`].
+///
+/// See [`token`] for an explanation on how strings are tokenized and entries
+/// are added to the token database.
+///
+/// # Example
+///
+/// ```
+/// use pw_tokenizer::{tokenize_to_args, Argument};
+///
+/// let (token, args) = tokenize_to_args!("%s is %d", "answer", 42);
+/// assert_eq!(token, pw_tokenizer::token!("%s is %d"));
+/// assert_eq!(args, [Argument::String("answer"), Argument::Varint(42)]);
+/// ```
+#[macro_export]
+macro_rules! tokenize_to_args {
+    ($($format_string:literal)PW_FMT_CONCAT+ $(, $args:expr)* $(,)?) => {{
+      use $crate::__private as __pw_tokenizer_crate;
+      __pw_tokenizer_crate::_tokenize_to_args!($($format_string)PW_FMT_CONCAT+, $($args),*)
+    }};
+}
+
 /// A trait used by [`tokenize_to_writer!`] to output tokenized messages.
 ///
 /// For more details on how this type is used, see the [`tokenize_to_writer!`]
@@ -332,6 +411,28 @@ mod tests {
         }};
     }
 
+    #[test]
+    fn masked_token_masks_hash() {
+        assert_eq!(masked_token!(0xffff, "Hello Pigweed"), 0x92e0);
+        assert_eq!(
+            masked_token!(pw_tokenizer_core::TOKEN_MASK_8_BIT, "Hello Pigweed"),
+            0xe0
+        );
+        assert_eq!(
+            masked_token!(u32::MAX, "Hello Pigweed"),
+            token!("Hello Pigweed")
+        );
+    }
+
+    #[test]
+    fn masked_token_in_domain_masks_hash() {
+        assert_eq!(masked_token!("metrics", 0xffff, "Hello Pigweed"), 0x92e0);
+        assert_eq!(
+            masked_token!("metrics", u32::MAX, "Hello Pigweed"),
+            token!("Hello Pigweed")
+        );
+    }
+
     #[test]
     fn bare_string_encodes_correctly() {
         tokenize_test!(
@@ -412,6 +513,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_untyped_format() {
+        let answer: i8 = 1;
+        // %v is converted to the format specifier of the argument's type.
+        tokenize_test!(
+            &[0x52, 0x1c, 0xb0, 0x4c, 0x2], // expected buffer
+            64,                             // buffer size
+            "The answer is %v!",
+            answer as i32
+        );
+    }
+
+    #[test]
+    fn test_key_value_fields() {
+        let sensor_id: u8 = 3;
+        // Tokenized as "Reading■sensor_id♦%d".
+        tokenize_test!(
+            &[0x06, 0xec, 0xb0, 0xbd, 0x6], // expected buffer
+            64,                             // buffer size
+            "Reading",
+            sensor_id = sensor_id as i32
+        );
+    }
+
     #[test]
     fn test_string_format() {
         tokenize_test!(
@@ -455,4 +580,22 @@ mod tests {
         .unwrap();
         assert_eq!(&buffer[..len], &[0x2e, 0x52, 0xac, 0xe4, 0x50]);
     }
+
+    #[test]
+    fn tokenize_to_args_matches_tokenize_to_buffer() {
+        let (token, args) = tokenize_to_args!("Hello: %cigweed %d", "P".as_bytes()[0], -1);
+        assert_eq!(args, [Argument::Char(b'P'), Argument::Varint(-1)]);
+
+        let mut expected = [0u8; 64];
+        let expected_len =
+            tokenize_to_buffer!(&mut expected, "Hello: %cigweed %d", "P".as_bytes()[0], -1)
+                .unwrap();
+        let mut buffer = [0u8; 64];
+        let len = internal::tokenize_to_buffer(&mut buffer, token, &args).unwrap();
+        assert_eq!(&buffer[..len], &expected[..expected_len]);
+
+        let (token, args) = tokenize_to_args!("Hello" PW_FMT_CONCAT " Pigweed");
+        assert_eq!(token.to_le_bytes(), [0xe0, 0x92, 0xe0, 0xa]);
+        assert!(args.is_empty());
+    }
 }
@@ -26,10 +26,30 @@ pub const HASH_CONSTANT: u32 = 65599u32;
 ///
 /// The hash state is encapsulated in the `Hasher` to allow the hashing of
 /// multi-part strings where the concatenated value can not be know at macro
-/// expansion time.  All functions are `const`.
+/// expansion time.  All functions except [`Hasher::update()`] are `const`.
+///
+/// When the total length of the data is not known up front, such as when
+/// hashing data as it arrives in chunks, create the `Hasher` with
+/// [`Hasher::streaming()`].  The length is then counted as data is processed
+/// and added to the hash by [`Hasher::finish()`]:
+///
+/// ```
+/// use pw_tokenizer_core::{hash_string, Hasher};
+///
+/// let mut hasher = Hasher::default();
+/// for segment in ["sensors", "/", "imu", "/", "accel"] {
+///     hasher.update(segment.as_bytes());
+/// }
+/// assert_eq!(hasher.finish(), hash_string("sensors/imu/accel"));
+/// ```
+#[derive(Clone, Copy)]
 pub struct Hasher {
     coefficient: u32,
     hash: u32,
+    // The seed length, or `None` if the length is counted as data is
+    // processed.
+    data_len: Option<usize>,
+    bytes_processed: usize,
     bytes_hashed: usize,
     hash_len: usize,
 }
@@ -45,17 +65,32 @@ impl Hasher {
         {
             Self {
                 coefficient: HASH_CONSTANT,
-                hash: data_len as u32,
+                hash: 0,
+                data_len: Some(data_len),
+                bytes_processed: 0,
                 bytes_hashed: 0,
                 hash_len,
             }
         }
     }
 
+    /// Create a new `Hasher` for data whose total length is not yet known.
+    ///
+    /// `hash_len` is the number of bytes of data to be used in calculating the
+    /// hash.  The hash is seeded with the number of bytes processed when the
+    /// hash is returned.
+    pub const fn streaming(hash_len: usize) -> Self {
+        Self {
+            data_len: None,
+            ..Self::new(0, hash_len)
+        }
+    }
+
     /// Processes `bytes` and updates hash state.
     ///
     /// Consumes `self` and returns a [`Hasher`] with the updated state.
     pub const fn process_bytes(mut self, bytes: &[u8]) -> Self {
+        self.bytes_processed = self.bytes_processed.wrapping_add(bytes.len());
         let bytes_left = self.hash_len - self.bytes_hashed;
 
         let bytes = if bytes.len() > bytes_left {
@@ -80,11 +115,97 @@ impl Hasher {
         self
     }
 
+    /// Processes `bytes` and updates hash state in place.
+    pub fn update(&mut self, bytes: &[u8]) {
+        *self = self.process_bytes(bytes);
+    }
+
+    /// Return the hash of the data processed so far.
+    pub const fn finish(&self) -> u32 {
+        // The length seed is added to the hash rather than used as its initial
+        // value, so it may be added once all data is processed.
+        let data_len = match self.data_len {
+            Some(data_len) => data_len,
+            None => self.bytes_processed,
+        };
+        self.hash.wrapping_add(data_len as u32)
+    }
+
     /// Consume `self` and return the hash.
     pub const fn hash(self) -> u32 {
-        self.hash
+        self.finish()
+    }
+}
+
+impl Default for Hasher {
+    /// Returns a [`Hasher::streaming()`] `Hasher` which hashes all data.
+    fn default() -> Self {
+        Self::streaming(usize::MAX)
+    }
+}
+
+/// A [`core::hash::Hasher`] which calculates the token hash.
+///
+/// Along with [`BuildTokenHasher`], this allows maps keyed by strings, such as
+/// `no_std` maps from `heapless`, to use the same stable hash as tokens.
+/// [`finish()`](core::hash::Hasher::finish) returns the token hash of all
+/// bytes written, zero extended to a `u64`.
+///
+/// Note that the [`Hash`] implementation of `str` writes a `0xff` byte after
+/// the string, so the hash of a `&str` key differs from its token.  Wrap keys
+/// in [`TokenKey`] to hash them to their token instead.
+#[derive(Clone, Copy, Default)]
+pub struct TokenHasher(Hasher);
+
+impl core::hash::Hasher for TokenHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0.finish().into()
     }
 }
+
+/// A [`core::hash::BuildHasher`] which creates [`TokenHasher`]s.
+///
+/// ```
+/// use std::collections::HashMap;
+/// use std::hash::BuildHasher;
+///
+/// use pw_tokenizer_core::{hash_string, BuildTokenHasher, TokenKey};
+///
+/// let mut counts = HashMap::with_hasher(BuildTokenHasher);
+/// *counts.entry(TokenKey("rx")).or_insert(0) += 1;
+/// assert_eq!(counts[&TokenKey("rx")], 1);
+///
+/// assert_eq!(BuildTokenHasher.hash_one(TokenKey("rx")), hash_string("rx").into());
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BuildTokenHasher;
+
+impl core::hash::BuildHasher for BuildTokenHasher {
+    type Hasher = TokenHasher;
+
+    fn build_hasher(&self) -> TokenHasher {
+        TokenHasher::default()
+    }
+}
+
+/// A string which [`Hash`]es to its token.
+///
+/// Unlike a bare `&str`, a `TokenKey` hashed with a [`TokenHasher`] produces
+/// the same value as [`hash_string()`], so keys stay comparable with tokens
+/// received over the wire.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct TokenKey<'a>(pub &'a str);
+
+impl core::hash::Hash for TokenKey<'_> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        state.write(self.0.as_bytes());
+    }
+}
+
 /// Calculate the hash for a sequence of bytes.
 ///
 /// ```
@@ -121,11 +242,103 @@ pub const fn hash_string(s: &str) -> u32 {
     hash_bytes(s.as_bytes())
 }
 
+/// Calculate the hash for a string, examining at most `N` bytes.
+///
+/// Strings tokenized by C code are hashed with a fixed length, which defaults
+/// to 128 bytes, so `N` must match to produce the same tokens.  As a `const`
+/// function, this may be used in `const` and `static` initializers.
+///
+/// ```
+/// use pw_tokenizer_core::{hash_bytes_fixed, hash_string_fixed};
+///
+/// const TOKEN: u32 = hash_string_fixed::<4>("Pigweed");
+/// assert_eq!(TOKEN, hash_bytes_fixed(b"Pigweed", 4));
+/// ```
+pub const fn hash_string_fixed<const N: usize>(s: &str) -> u32 {
+    hash_bytes_fixed(s.as_bytes(), N)
+}
+
+/// Calculate the hashes of `strings`, examining at most `N` bytes of each.
+///
+/// This builds lookup tables keyed by fixed length tokens at compile time:
+///
+/// ```
+/// use pw_tokenizer_core::{hash_string_fixed, hash_strings_fixed};
+///
+/// const COMMANDS: [&str; 3] = ["start", "stop", "reset"];
+/// const TOKENS: [u32; 3] = hash_strings_fixed::<128, 3>(COMMANDS);
+///
+/// let index = TOKENS.iter().position(|t| *t == hash_string_fixed::<128>("stop"));
+/// assert_eq!(index, Some(1));
+/// ```
+pub const fn hash_strings_fixed<const N: usize, const M: usize>(strings: [&str; M]) -> [u32; M] {
+    let mut hashes = [0u32; M];
+    // For loops are not allowed in const functions.
+    let mut i = 0;
+    while i < M {
+        hashes[i] = hash_string_fixed::<N>(strings[i]);
+        i += 1;
+    }
+    hashes
+}
+
 pub const TOKENIZER_ENTRY_MAGIC: u32 = 0xBAA98DEE;
 
+/// Mask for 16 bit tokens.
+pub const TOKEN_MASK_16_BIT: u32 = 0x0000_ffff;
+
+/// Mask for 8 bit tokens.
+pub const TOKEN_MASK_8_BIT: u32 = 0x0000_00ff;
+
+/// Calculate the token for a string masked with `mask`.
+///
+/// ```
+/// use pw_tokenizer_core::{hash_string, masked_hash_string, TOKEN_MASK_16_BIT};
+///
+/// let token = masked_hash_string("I 💖 Pigweed", TOKEN_MASK_16_BIT);
+/// assert_eq!(token, 0xd1b3);
+/// ```
+pub const fn masked_hash_string(s: &str, mask: u32) -> u32 {
+    hash_string(s) & mask
+}
+
+/// Returns the first pair of `strings` whose tokens are equal when masked with
+/// `mask`.
+///
+/// Duplicate strings are not collisions since they share a database entry.
+/// This compares every pair of strings, so it is intended for build time and
+/// test checks rather than on device use.
+///
+/// ```
+/// use pw_tokenizer_core::{find_collision, TOKEN_MASK_16_BIT};
+///
+/// assert_eq!(find_collision(&["Start", "Stop", "Reset"], TOKEN_MASK_16_BIT), None);
+/// ```
+pub fn find_collision<'a>(strings: &[&'a str], mask: u32) -> Option<(&'a str, &'a str)> {
+    strings.iter().enumerate().find_map(|(i, a)| {
+        let token = masked_hash_string(a, mask);
+        strings[i + 1..]
+            .iter()
+            .find(|b| *b != a && masked_hash_string(b, mask) == token)
+            .map(|b| (*a, *b))
+    })
+}
+
+/// Returns the expected number of colliding pairs among `count` distinct
+/// strings whose tokens are masked with `mask`.
+///
+/// For example, 100 strings with 16 bit tokens are expected to have 0.076
+/// colliding pairs, while 100 strings with 8 bit tokens are expected to have
+/// 19.3.
+pub fn expected_collisions(count: usize, mask: u32) -> f64 {
+    let pairs = count as f64 * count.saturating_sub(1) as f64 / 2.0;
+    let tokens = (1u64 << mask.count_ones()) as f64;
+    pairs / tokens
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{hash_bytes_fixed, Hasher};
+    use super::*;
 
     struct TestCase {
         string: &'static [u8],
@@ -165,4 +378,77 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn streaming_hasher_generates_correct_hash() {
+        for test in test_cases() {
+            let mut hasher = Hasher::streaming(test.hash_length);
+            for chunk in test.string.chunks(3) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finish(), test.hash, "string: {:x?}", test.string);
+        }
+    }
+
+    #[test]
+    fn masked_tokens_collide() {
+        assert_eq!(
+            masked_hash_string("ab", TOKEN_MASK_8_BIT),
+            hash_string("ab") & 0xff
+        );
+        let strings: Vec<String> = (0..64).map(|i| format!("message {i}")).collect();
+        let strings: Vec<&str> = strings.iter().map(String::as_str).collect();
+        assert_eq!(find_collision(&strings, u32::MAX), None);
+        let (a, b) = find_collision(&strings, TOKEN_MASK_8_BIT).unwrap();
+        assert_ne!(a, b);
+        assert_eq!(
+            masked_hash_string(a, TOKEN_MASK_8_BIT),
+            masked_hash_string(b, TOKEN_MASK_8_BIT)
+        );
+        assert_eq!(find_collision(&["same", "same"], TOKEN_MASK_8_BIT), None);
+    }
+
+    #[test]
+    fn expected_collisions_uses_mask_width() {
+        assert_eq!(expected_collisions(0, TOKEN_MASK_8_BIT), 0.0);
+        assert_eq!(expected_collisions(2, TOKEN_MASK_8_BIT), 1.0 / 256.0);
+        assert!((expected_collisions(100, TOKEN_MASK_16_BIT) - 0.0755).abs() < 0.001);
+        assert!((expected_collisions(100, TOKEN_MASK_8_BIT) - 19.34).abs() < 0.01);
+    }
+
+    #[test]
+    fn token_hasher_matches_token() {
+        use core::hash::{BuildHasher, Hash, Hasher as _};
+
+        for test in test_cases()
+            .iter()
+            .filter(|test| test.hash_length >= test.string.len())
+        {
+            let mut hasher = BuildTokenHasher.build_hasher();
+            // Write the string in pieces, as a `Hash` implementation might.
+            for chunk in test.string.chunks(5) {
+                hasher.write(chunk);
+            }
+            assert_eq!(hasher.finish(), u64::from(test.hash));
+        }
+
+        // `str` appends a terminator when hashed, while `TokenKey` does not.
+        assert_ne!(
+            BuildTokenHasher.hash_one("key"),
+            u64::from(hash_string("key"))
+        );
+        let mut hasher = TokenHasher::default();
+        TokenKey("key").hash(&mut hasher);
+        assert_eq!(hasher.finish(), u64::from(hash_string("key")));
+    }
+
+    #[test]
+    fn const_generic_fixed_hashes_match() {
+        const HASHES: [u32; 2] = hash_strings_fixed::<4, 2>(["abcdef", "abcdxyz"]);
+        assert_eq!(HASHES[0], hash_bytes_fixed(b"abcdef", 4));
+        assert_eq!(HASHES[1], hash_string_fixed::<4>("abcdxyz"));
+        // Only the length distinguishes strings which share an `N` byte prefix.
+        assert_ne!(HASHES[0], HASHES[1]);
+        assert_eq!(hash_string_fixed::<4>("abcd"), hash_string("abcd"));
+    }
 }
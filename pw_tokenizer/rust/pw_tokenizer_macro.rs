@@ -37,6 +37,17 @@ type TokenStream2 = proc_macro2::TokenStream;
 // with the specified `domain`.  A detailed description of what's happening is
 // found in the docs for [`pw_tokenizer::token`] macro.
 fn token_backend(domain: &str, fragments: &[TokenStream2]) -> TokenStream2 {
+    masked_token_backend(domain, &quote!(u32::MAX), fragments)
+}
+
+// Same as `token_backend` but the token is the hash masked with `mask`, which
+// is a `u32` constant expression.  The masked token is what is added to the
+// token database.
+fn masked_token_backend(
+    domain: &str,
+    mask: &TokenStream2,
+    fragments: &[TokenStream2],
+) -> TokenStream2 {
     let ident = format_ident!("_PW_TOKENIZER_STRING_ENTRY_RUST");
 
     // pw_tokenizer is intended for use with ELF files only. Mach-O files (macOS
@@ -60,7 +71,7 @@ fn token_backend(domain: &str, fragments: &[TokenStream2]) -> TokenStream2 {
             const STRING_BYTES: &[u8] = STRING.as_bytes();
             const STRING_LEN: usize = STRING_BYTES.len();
 
-            const HASH: u32 = __pw_tokenizer_crate::hash_string(STRING);
+            const HASH: u32 = __pw_tokenizer_crate::hash_string(STRING) & (#mask);
 
             #[repr(C, packed(1))]
             struct TokenEntry {
@@ -99,6 +110,52 @@ pub fn _token(tokens: TokenStream) -> TokenStream {
     token_backend("", &[input.into_token_stream()]).into()
 }
 
+// Args to masked token that are parsed according to the pattern:
+//   ($domain:literal, $mask:expr, $string:literal)
+// where the domain is optional.
+struct MaskedTokenArgs {
+    domain: Option<LitStr>,
+    mask: Expr,
+    string: LitStr,
+}
+
+impl Parse for MaskedTokenArgs {
+    fn parse(input: ParseStream) -> syn::parse::Result<Self> {
+        // A mask is never a string literal, so a leading one is the domain.
+        let domain = if input.peek(LitStr) {
+            let domain: LitStr = input.parse()?;
+            input.parse::<Token![,]>()?;
+            Some(domain)
+        } else {
+            None
+        };
+        let mask: Expr = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let string: LitStr = input.parse()?;
+        Ok(MaskedTokenArgs {
+            domain,
+            mask,
+            string,
+        })
+    }
+}
+
+// Documented in `pw_tokenizer::masked_token`.
+#[proc_macro]
+pub fn _masked_token(tokens: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(tokens as MaskedTokenArgs);
+    let domain = input
+        .domain
+        .map(|domain| domain.value())
+        .unwrap_or_default();
+    masked_token_backend(
+        &domain,
+        &input.mask.into_token_stream(),
+        &[input.string.into_token_stream()],
+    )
+    .into()
+}
+
 // Args to tokenize to buffer that are parsed according to the pattern:
 //   ($buffer:expr, $format_string:literal, $($args:expr),*)
 #[derive(Debug)]
@@ -205,7 +262,7 @@ impl<'a> PrintfFormatMacroGenerator for TokenizeToBufferGenerator<'a> {
 
     fn untyped_conversion(&mut self, expression: Arg) -> Result<()> {
         self.encoding_fragments.push(quote! {
-          __pw_tokenizer_crate::internal::Encoder::encode(#expression, &mut cursor)?;
+          Argument::from(#expression)
         });
         Ok(())
     }
@@ -229,6 +286,100 @@ pub fn _tokenize_to_buffer(tokens: TokenStream) -> TokenStream {
     }
 }
 
+// A PrintfFormatMacroGenerator that provides the code generation backend for
+// the `tokenize_to_args!` macro.
+struct TokenizeToArgsGenerator<'a> {
+    domain: &'a str,
+    encoding_fragments: Vec<TokenStream2>,
+}
+
+impl<'a> TokenizeToArgsGenerator<'a> {
+    fn new(domain: &'a str) -> Self {
+        Self {
+            domain,
+            encoding_fragments: Vec::new(),
+        }
+    }
+}
+
+impl<'a> PrintfFormatMacroGenerator for TokenizeToArgsGenerator<'a> {
+    fn finalize(
+        self,
+        format_string_fragments: &[PrintfFormatStringFragment],
+    ) -> Result<TokenStream2> {
+        // Locally scoped aliases so we can refer to them in `quote!()`
+        let encoding_fragments = self.encoding_fragments;
+        let arg_count = encoding_fragments.len();
+
+        let format_string_pieces: Vec<_> = format_string_fragments
+            .iter()
+            .map(|fragment| fragment.as_token_stream("__pw_tokenizer_crate"))
+            .collect::<Result<Vec<_>>>()?;
+
+        // `token_backend` returns a `TokenStream2` which both inserts the
+        // string into the token database and returns the hash value.
+        let token = token_backend(self.domain, &format_string_pieces);
+
+        Ok(quote! {
+          {
+            use __pw_tokenizer_crate::internal::Argument;
+            let args: [Argument<'_>; #arg_count] = [#(#encoding_fragments),*];
+            (#token, args)
+          }
+        })
+    }
+
+    fn string_fragment(&mut self, _string: &str) -> Result<()> {
+        // String fragments are encoded directly into the format string.
+        Ok(())
+    }
+
+    fn integer_conversion(&mut self, ty: Ident, expression: Arg) -> Result<Option<String>> {
+        self.encoding_fragments.push(quote! {
+          Argument::Varint(#ty::from(#expression) as i32)
+        });
+
+        Ok(None)
+    }
+
+    fn string_conversion(&mut self, expression: Arg) -> Result<Option<String>> {
+        self.encoding_fragments.push(quote! {
+          Argument::String(#expression)
+        });
+        Ok(None)
+    }
+
+    fn char_conversion(&mut self, expression: Arg) -> Result<Option<String>> {
+        self.encoding_fragments.push(quote! {
+          Argument::Char(u8::from(#expression))
+        });
+        Ok(None)
+    }
+
+    fn untyped_conversion(&mut self, expression: Arg) -> Result<()> {
+        self.encoding_fragments.push(quote! {
+          Argument::from(#expression)
+        });
+        Ok(())
+    }
+}
+
+// Generates code which tokenizes a format string and returns its token along
+// with its unencoded arguments.  See [`pw_tokenizer::tokenize_to_args`] for
+// details on behavior.
+#[proc_macro]
+pub fn _tokenize_to_args(tokens: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(tokens as FormatAndArgs);
+
+    // Hard codes domain to "".
+    let generator = TokenizeToArgsGenerator::new("");
+
+    match generate_printf(generator, input) {
+        Ok(token_stream) => token_stream.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
 // Args to tokenize to buffer that are parsed according to the pattern:
 //   ($ty:ty, $format_string:literal, $($args:expr),*)
 #[derive(Debug)]
@@ -0,0 +1,790 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! Tokenized tracing, compatible with the C++ `pw_trace_tokenized` module and
+//! its host tooling.
+//!
+//! Trace events are recorded with the `trace_*!` macros, which tokenize the
+//! event's type, group, and label into the `"trace"` domain of the token
+//! database as `PW_TRACE_REF()` does in C++.  Each event is stored in a ring
+//! buffer as the token, the time since the previous event as a varint, the
+//! event's trace ID as a varint for async events, and any data:
+//!
+//! ```
+//! use pw_trace_tokenized::{trace_end, trace_instant, trace_scope, trace_start};
+//!
+//! pw_trace_tokenized::set_enabled(true);
+//!
+//! trace_instant!("Boot");
+//!
+//! // A duration event in the "Sensor" group.
+//! trace_start!("Read", "Sensor");
+//! trace_end!("Read", "Sensor");
+//!
+//! // Async events are matched by trace ID.
+//! let request_id = 7;
+//! trace_start!("Request", "Network", request_id);
+//! trace_end!("Request", "Network", request_id);
+//!
+//! fn process() {
+//!     // A duration event which ends when the scope ends.
+//!     let _scope = trace_scope!("Process");
+//! }
+//! # process();
+//! ```
+//!
+//! Events are only recorded while tracing is enabled with [`set_enabled()`],
+//! and are timestamped with the low 32 bits of the ticks of the system clock
+//! set by [`pw_chrono::set_system_clock()`].  The
+//! buffer is read with [`pop_event()`], or as a whole with
+//! [`read_raw_buffer()`], which produces the size prefixed format read by
+//! `pw_trace_tokenized.trace_tokenized`.  [`push_events_to()`] moves the
+//! events into a [`MultiSink`], so they can share its drains with logs.
+#![no_std]
+#![deny(missing_docs)]
+
+use core::cell::{Cell, RefCell};
+
+use critical_section::Mutex;
+use pw_multisink::MultiSink;
+use pw_ring_buffer::{PrefixedEntryRingBuffer, ReaderId};
+use pw_status::{Error, Result};
+use pw_varint::{VarintEncode, MAX_VARINT32_SIZE_BYTES};
+
+/// Size of the buffer trace events are recorded in.
+pub const TRACE_BUFFER_SIZE_BYTES: usize = 256;
+
+/// Largest data which may be recorded with an event.  Events with larger data
+/// are dropped.
+pub const MAX_DATA_SIZE_BYTES: usize = 32;
+
+// Token, time delta, and trace ID.
+const MAX_HEADER_SIZE_BYTES: usize = 4 + 2 * MAX_VARINT32_SIZE_BYTES;
+
+/// The type of a trace event, as a `pw_trace_EventType`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventType {
+    /// An instant event.
+    Instant = 1,
+    /// An instant event in a group.
+    InstantGroup = 2,
+    /// The start of an async span.
+    AsyncStart = 3,
+    /// A step of an async span.
+    AsyncStep = 4,
+    /// The end of an async span.
+    AsyncEnd = 5,
+    /// The start of a duration.
+    DurationStart = 6,
+    /// The end of a duration.
+    DurationEnd = 7,
+    /// The start of a duration in a group.
+    DurationGroupStart = 8,
+    /// The end of a duration in a group.
+    DurationGroupEnd = 9,
+}
+
+impl EventType {
+    /// Returns `true` if events of this type are recorded with a trace ID.
+    pub const fn has_trace_id(self) -> bool {
+        matches!(
+            self,
+            EventType::AsyncStart | EventType::AsyncStep | EventType::AsyncEnd
+        )
+    }
+}
+
+struct Tracer {
+    buffer: PrefixedEntryRingBuffer<[u8; TRACE_BUFFER_SIZE_BYTES]>,
+    reader: Option<ReaderId>,
+    last_time: u32,
+}
+
+impl Tracer {
+    fn reader(&mut self) -> Result<ReaderId> {
+        if let Some(reader) = self.reader {
+            return Ok(reader);
+        }
+        let reader = self.buffer.attach_reader()?;
+        self.reader = Some(reader);
+        Ok(reader)
+    }
+}
+
+static ENABLED: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+static TRACER: Mutex<RefCell<Tracer>> = Mutex::new(RefCell::new(Tracer {
+    buffer: PrefixedEntryRingBuffer::new([0; TRACE_BUFFER_SIZE_BYTES], false),
+    reader: None,
+    last_time: 0,
+}));
+
+/// Enables or disables recording trace events.  Tracing is disabled
+/// initially.
+pub fn set_enabled(enabled: bool) {
+    critical_section::with(|cs| ENABLED.borrow(cs).set(enabled))
+}
+
+/// Returns `true` if trace events are being recorded.
+pub fn is_enabled() -> bool {
+    critical_section::with(|cs| ENABLED.borrow(cs).get())
+}
+
+/// Records a trace event with the given token if tracing is enabled.
+///
+/// Use the `trace_*!` macros rather than calling this directly, so the
+/// token's string is added to the token database.  `trace_id` is ignored for
+/// event types without one.  The oldest events are dropped if the buffer is
+/// full, and events whose data is larger than [`MAX_DATA_SIZE_BYTES`] are
+/// dropped.
+pub fn trace_event(token: u32, event_type: EventType, trace_id: u32, data: &[u8]) {
+    if !is_enabled() || data.len() > MAX_DATA_SIZE_BYTES {
+        return;
+    }
+    let mut event = [0u8; MAX_HEADER_SIZE_BYTES + MAX_DATA_SIZE_BYTES];
+    critical_section::with(|cs| {
+        let mut tracer = TRACER.borrow_ref_mut(cs);
+        // The system clock's ticks per second must be passed to the host
+        // tooling.  The time wraps.
+        let time = pw_chrono::system_clock().map_or(0, |clock| clock.now_ticks() as u32);
+        // As in C++, the first event has no delta.
+        let delta = match tracer.last_time {
+            0 => 0,
+            last_time => time.wrapping_sub(last_time),
+        };
+        tracer.last_time = time;
+
+        event[..4].copy_from_slice(&token.to_le_bytes());
+        let mut len = 4;
+        // The header always fits.
+        len += delta.varint_encode(&mut event[len..]).unwrap_or(0);
+        if event_type.has_trace_id() {
+            len += trace_id.varint_encode(&mut event[len..]).unwrap_or(0);
+        }
+        event[len..len + data.len()].copy_from_slice(data);
+        len += data.len();
+
+        if tracer.reader().is_ok() {
+            // Events only fail to fit if the buffer is smaller than an event.
+            let _ = tracer.buffer.push_back(&event[..len], 0);
+        }
+    })
+}
+
+/// Copies the oldest recorded event to `buffer` and removes it from the trace
+/// buffer, returning its length.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - No events are recorded.
+/// - [`Error::ResourceExhausted`] - `buffer` is too small for the event,
+///   which is not removed.
+pub fn pop_event(buffer: &mut [u8]) -> Result<usize> {
+    critical_section::with(|cs| {
+        let mut tracer = TRACER.borrow_ref_mut(cs);
+        let reader = tracer.reader()?;
+        let len = tracer.buffer.peek_front(reader, buffer)?;
+        tracer.buffer.pop_front(reader)?;
+        Ok(len)
+    })
+}
+
+/// Copies all recorded events, each prefixed by its size, to `buffer`
+/// without removing them, returning the length copied.
+///
+/// This is the format `pw_trace_tokenized.trace_tokenized` reads from a file.
+///
+/// # Errors
+/// - [`Error::ResourceExhausted`] - `buffer` is too small.
+pub fn read_raw_buffer(buffer: &mut [u8]) -> Result<usize> {
+    critical_section::with(|cs| {
+        let mut tracer = TRACER.borrow_ref_mut(cs);
+        tracer.reader()?;
+        let mut len = 0;
+        for entry in tracer.buffer.iter() {
+            let data = entry?.data;
+            let remaining = buffer.get_mut(len..).ok_or(Error::ResourceExhausted)?;
+            let prefix_len = (data.len() as u32)
+                .varint_encode(remaining)
+                .map_err(|_| Error::ResourceExhausted)?;
+            len += prefix_len;
+            buffer
+                .get_mut(len..len + data.len())
+                .ok_or(Error::ResourceExhausted)?
+                .copy_from_slice(data);
+            len += data.len();
+        }
+        Ok(len)
+    })
+}
+
+/// Moves the recorded events, oldest first, into `sink` as entries without
+/// metadata, returning the number of events moved.
+///
+/// Log entries and trace events can then be read through the same drains,
+/// as with the C++ `pw_multisink`.  Events which do not fit in the sink are
+/// counted as its ingress drops.
+pub fn push_events_to<const N: usize>(sink: &MultiSink<N>) -> usize {
+    let mut event = [0u8; MAX_HEADER_SIZE_BYTES + MAX_DATA_SIZE_BYTES];
+    let mut count = 0;
+    while let Ok(len) = pop_event(&mut event) {
+        if sink.push_entry(&event[..len]).is_ok() {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Removes all recorded events.
+pub fn clear_buffer() {
+    critical_section::with(|cs| {
+        let mut tracer = TRACER.borrow_ref_mut(cs);
+        tracer.buffer.clear();
+        tracer.last_time = 0;
+    })
+}
+
+/// Ends a duration or async span started by [`trace_scope!`] when dropped.
+#[must_use = "the traced scope ends when the guard is dropped"]
+pub struct TraceScope {
+    end_token: u32,
+    end_type: EventType,
+    trace_id: u32,
+}
+
+impl TraceScope {
+    #[doc(hidden)]
+    pub fn new(end_token: u32, end_type: EventType, trace_id: u32) -> Self {
+        Self {
+            end_token,
+            end_type,
+            trace_id,
+        }
+    }
+}
+
+impl Drop for TraceScope {
+    fn drop(&mut self) {
+        trace_event(self.end_token, self.end_type, self.trace_id, &[]);
+    }
+}
+
+// Re-export dependences of the trace macros to be accessed via
+// `$crate::__private`.
+#[doc(hidden)]
+pub mod __private {
+    pub use pw_tokenizer::masked_token;
+    pub use pw_trace_tokenized_macro::_trace_token;
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! _trace {
+    ($event_type:ident, $type_name:literal, $trace_id:expr, $data:expr,
+     $group:literal, $label:literal $(, $data_type:literal)?) => {{
+        use $crate::__private as __pw_trace_crate;
+        const TOKEN: u32 =
+            __pw_trace_crate::_trace_token!($type_name, $group, $label $(, $data_type)?);
+        $crate::trace_event(TOKEN, $crate::EventType::$event_type, $trace_id, $data);
+    }};
+}
+
+/// Records an instant event.
+///
+/// Events with a group are recorded as `PW_TRACE_TYPE_INSTANT_GROUP`, and
+/// events with a group and trace ID as an async step
+/// (`PW_TRACE_TYPE_ASYNC_INSTANT`).
+///
+/// ```
+/// use pw_trace_tokenized::trace_instant;
+///
+/// trace_instant!("Button pressed");
+/// trace_instant!("Packet received", "Radio");
+/// trace_instant!("Retry", "Network", 7);
+/// ```
+#[macro_export]
+macro_rules! trace_instant {
+    ($label:literal $(,)?) => {
+        $crate::_trace!(Instant, "PW_TRACE_EVENT_TYPE_INSTANT", 0, &[], "", $label)
+    };
+    ($label:literal, $group:literal $(,)?) => {
+        $crate::_trace!(
+            InstantGroup,
+            "PW_TRACE_EVENT_TYPE_INSTANT_GROUP",
+            0,
+            &[],
+            $group,
+            $label
+        )
+    };
+    ($label:literal, $group:literal, $trace_id:expr $(,)?) => {
+        $crate::_trace!(
+            AsyncStep,
+            "PW_TRACE_EVENT_TYPE_ASYNC_STEP",
+            $trace_id,
+            &[],
+            $group,
+            $label
+        )
+    };
+}
+
+/// Records the start of a duration, or of an async span if a trace ID is
+/// given.  The span is ended by [`trace_end!`] with the same arguments.
+#[macro_export]
+macro_rules! trace_start {
+    ($label:literal $(,)?) => {
+        $crate::_trace!(
+            DurationStart,
+            "PW_TRACE_EVENT_TYPE_DURATION_START",
+            0,
+            &[],
+            "",
+            $label
+        )
+    };
+    ($label:literal, $group:literal $(,)?) => {
+        $crate::_trace!(
+            DurationGroupStart,
+            "PW_TRACE_EVENT_TYPE_DURATION_GROUP_START",
+            0,
+            &[],
+            $group,
+            $label
+        )
+    };
+    ($label:literal, $group:literal, $trace_id:expr $(,)?) => {
+        $crate::_trace!(
+            AsyncStart,
+            "PW_TRACE_EVENT_TYPE_ASYNC_START",
+            $trace_id,
+            &[],
+            $group,
+            $label
+        )
+    };
+}
+
+/// Records the end of a duration or async span started by [`trace_start!`].
+#[macro_export]
+macro_rules! trace_end {
+    ($label:literal $(,)?) => {
+        $crate::_trace!(
+            DurationEnd,
+            "PW_TRACE_EVENT_TYPE_DURATION_END",
+            0,
+            &[],
+            "",
+            $label
+        )
+    };
+    ($label:literal, $group:literal $(,)?) => {
+        $crate::_trace!(
+            DurationGroupEnd,
+            "PW_TRACE_EVENT_TYPE_DURATION_GROUP_END",
+            0,
+            &[],
+            $group,
+            $label
+        )
+    };
+    ($label:literal, $group:literal, $trace_id:expr $(,)?) => {
+        $crate::_trace!(
+            AsyncEnd,
+            "PW_TRACE_EVENT_TYPE_ASYNC_END",
+            $trace_id,
+            &[],
+            $group,
+            $label
+        )
+    };
+}
+
+/// Records the start of a duration or async span, returning a [`TraceScope`]
+/// which records its end when dropped.
+///
+/// Takes the same arguments as [`trace_start!`].
+#[macro_export]
+macro_rules! trace_scope {
+    ($label:literal $(,)?) => {{
+        $crate::trace_start!($label);
+        const END_TOKEN: u32 = {
+            use $crate::__private as __pw_trace_crate;
+            __pw_trace_crate::_trace_token!("PW_TRACE_EVENT_TYPE_DURATION_END", "", $label)
+        };
+        $crate::TraceScope::new(END_TOKEN, $crate::EventType::DurationEnd, 0)
+    }};
+    ($label:literal, $group:literal $(,)?) => {{
+        $crate::trace_start!($label, $group);
+        const END_TOKEN: u32 = {
+            use $crate::__private as __pw_trace_crate;
+            __pw_trace_crate::_trace_token!(
+                "PW_TRACE_EVENT_TYPE_DURATION_GROUP_END",
+                $group,
+                $label
+            )
+        };
+        $crate::TraceScope::new(END_TOKEN, $crate::EventType::DurationGroupEnd, 0)
+    }};
+    ($label:literal, $group:literal, $trace_id:expr $(,)?) => {{
+        let trace_id: u32 = $trace_id;
+        $crate::trace_start!($label, $group, trace_id);
+        const END_TOKEN: u32 = {
+            use $crate::__private as __pw_trace_crate;
+            __pw_trace_crate::_trace_token!("PW_TRACE_EVENT_TYPE_ASYNC_END", $group, $label)
+        };
+        $crate::TraceScope::new(END_TOKEN, $crate::EventType::AsyncEnd, trace_id)
+    }};
+}
+
+/// Records an instant event with data, as [`trace_instant!`].
+///
+/// The data is a byte slice described by the `data_type` string, which the
+/// host tooling uses to decode it, as in `PW_TRACE_INSTANT_DATA()`.
+///
+/// ```
+/// use pw_trace_tokenized::trace_instant_data;
+///
+/// let temperature: u16 = 215;
+/// trace_instant_data!("Temperature", "Sensor", "@pw_py_struct_fmt:H", &temperature.to_le_bytes());
+/// ```
+#[macro_export]
+macro_rules! trace_instant_data {
+    ($label:literal, $data_type:literal, $data:expr $(,)?) => {
+        $crate::_trace!(
+            Instant,
+            "PW_TRACE_EVENT_TYPE_INSTANT",
+            0,
+            $data,
+            "",
+            $label,
+            $data_type
+        )
+    };
+    ($label:literal, $group:literal, $data_type:literal, $data:expr $(,)?) => {
+        $crate::_trace!(
+            InstantGroup,
+            "PW_TRACE_EVENT_TYPE_INSTANT_GROUP",
+            0,
+            $data,
+            $group,
+            $label,
+            $data_type
+        )
+    };
+    ($label:literal, $group:literal, $trace_id:expr, $data_type:literal, $data:expr $(,)?) => {
+        $crate::_trace!(
+            AsyncStep,
+            "PW_TRACE_EVENT_TYPE_ASYNC_STEP",
+            $trace_id,
+            $data,
+            $group,
+            $label,
+            $data_type
+        )
+    };
+}
+
+/// Records the start of a duration or async span with data, as
+/// [`trace_start!`] and [`trace_instant_data!`].
+#[macro_export]
+macro_rules! trace_start_data {
+    ($label:literal, $data_type:literal, $data:expr $(,)?) => {
+        $crate::_trace!(
+            DurationStart,
+            "PW_TRACE_EVENT_TYPE_DURATION_START",
+            0,
+            $data,
+            "",
+            $label,
+            $data_type
+        )
+    };
+    ($label:literal, $group:literal, $data_type:literal, $data:expr $(,)?) => {
+        $crate::_trace!(
+            DurationGroupStart,
+            "PW_TRACE_EVENT_TYPE_DURATION_GROUP_START",
+            0,
+            $data,
+            $group,
+            $label,
+            $data_type
+        )
+    };
+    ($label:literal, $group:literal, $trace_id:expr, $data_type:literal, $data:expr $(,)?) => {
+        $crate::_trace!(
+            AsyncStart,
+            "PW_TRACE_EVENT_TYPE_ASYNC_START",
+            $trace_id,
+            $data,
+            $group,
+            $label,
+            $data_type
+        )
+    };
+}
+
+/// Records the end of a duration or async span with data, as [`trace_end!`]
+/// and [`trace_instant_data!`].
+#[macro_export]
+macro_rules! trace_end_data {
+    ($label:literal, $data_type:literal, $data:expr $(,)?) => {
+        $crate::_trace!(
+            DurationEnd,
+            "PW_TRACE_EVENT_TYPE_DURATION_END",
+            0,
+            $data,
+            "",
+            $label,
+            $data_type
+        )
+    };
+    ($label:literal, $group:literal, $data_type:literal, $data:expr $(,)?) => {
+        $crate::_trace!(
+            DurationGroupEnd,
+            "PW_TRACE_EVENT_TYPE_DURATION_GROUP_END",
+            0,
+            $data,
+            $group,
+            $label,
+            $data_type
+        )
+    };
+    ($label:literal, $group:literal, $trace_id:expr, $data_type:literal, $data:expr $(,)?) => {
+        $crate::_trace!(
+            AsyncEnd,
+            "PW_TRACE_EVENT_TYPE_ASYNC_END",
+            $trace_id,
+            $data,
+            $group,
+            $label,
+            $data_type
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::sync::Mutex as StdMutex;
+    use std::vec::Vec;
+
+    use pw_tokenizer::masked_token;
+
+    use super::*;
+
+    // Trace state is global, so tests which record events run one at a time.
+    static LOCK: StdMutex<()> = StdMutex::new(());
+
+    struct TestClock;
+
+    static TIME: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+    impl pw_chrono::SystemClock for TestClock {
+        fn ticks_per_second(&self) -> u64 {
+            1_000_000
+        }
+
+        fn now_ticks(&self) -> u64 {
+            critical_section::with(|cs| TIME.borrow(cs).get().into())
+        }
+    }
+
+    static CLOCK_INSTANCE: TestClock = TestClock;
+
+    fn set_time(time: u32) {
+        critical_section::with(|cs| TIME.borrow(cs).set(time))
+    }
+
+    fn start() -> std::sync::MutexGuard<'static, ()> {
+        let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        pw_chrono::set_system_clock(&CLOCK_INSTANCE);
+        set_time(0);
+        clear_buffer();
+        set_enabled(true);
+        guard
+    }
+
+    fn events() -> Vec<Vec<u8>> {
+        let mut events = Vec::new();
+        let mut buffer = [0u8; 64];
+        while let Ok(len) = pop_event(&mut buffer) {
+            events.push(buffer[..len].to_vec());
+        }
+        events
+    }
+
+    fn event(token: u32, rest: &[u8]) -> Vec<u8> {
+        let mut event = token.to_le_bytes().to_vec();
+        event.extend_from_slice(rest);
+        event
+    }
+
+    #[test]
+    fn events_are_tokenized_like_cpp() {
+        let _guard = start();
+        set_time(100);
+        trace_instant!("Boot");
+        set_time(105);
+        trace_start!("Read", "Sensor");
+        set_time(300);
+        trace_end!("Request", "Network", 7);
+
+        assert_eq!(
+            events(),
+            [
+                event(
+                    masked_token!("trace", u32::MAX, "PW_TRACE_EVENT_TYPE_INSTANT|0|||Boot"),
+                    &[0]
+                ),
+                event(
+                    masked_token!(
+                        "trace",
+                        u32::MAX,
+                        "PW_TRACE_EVENT_TYPE_DURATION_GROUP_START|0||Sensor|Read"
+                    ),
+                    &[5]
+                ),
+                event(
+                    masked_token!(
+                        "trace",
+                        u32::MAX,
+                        "PW_TRACE_EVENT_TYPE_ASYNC_END|0||Network|Request"
+                    ),
+                    &[0xc3, 0x01, 7]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn data_follows_header() {
+        let _guard = start();
+        trace_instant_data!(
+            "Temperature",
+            "Sensor",
+            "@pw_py_struct_fmt:H",
+            &[0xd7, 0x00]
+        );
+        assert_eq!(
+            events(),
+            [event(
+                masked_token!(
+                    "trace",
+                    u32::MAX,
+                    "PW_TRACE_EVENT_TYPE_INSTANT_GROUP|0||Sensor|Temperature|@pw_py_struct_fmt:H"
+                ),
+                &[0, 0xd7, 0x00]
+            )]
+        );
+    }
+
+    #[test]
+    fn disabled_tracing_records_nothing() {
+        let _guard = start();
+        set_enabled(false);
+        trace_instant!("Boot");
+        assert!(events().is_empty());
+    }
+
+    #[test]
+    fn oversized_data_is_dropped() {
+        let _guard = start();
+        trace_instant_data!("Blob", "@pw_arg_bytes", &[0; MAX_DATA_SIZE_BYTES + 1]);
+        assert!(events().is_empty());
+    }
+
+    #[test]
+    fn scope_ends_span_when_dropped() {
+        let _guard = start();
+        set_time(5);
+        {
+            let _scope = trace_scope!("Work", "Group", 3);
+            set_time(15);
+        }
+        let events = events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[1],
+            event(
+                masked_token!(
+                    "trace",
+                    u32::MAX,
+                    "PW_TRACE_EVENT_TYPE_ASYNC_END|0||Group|Work"
+                ),
+                &[10, 3]
+            )
+        );
+    }
+
+    #[test]
+    fn raw_buffer_is_size_prefixed() {
+        let _guard = start();
+        trace_instant!("A");
+        trace_instant!("B");
+        let mut buffer = [0u8; 32];
+        let len = read_raw_buffer(&mut buffer).unwrap();
+
+        let mut expected = Vec::new();
+        for label in [
+            masked_token!("trace", u32::MAX, "PW_TRACE_EVENT_TYPE_INSTANT|0|||A"),
+            masked_token!("trace", u32::MAX, "PW_TRACE_EVENT_TYPE_INSTANT|0|||B"),
+        ] {
+            expected.push(5);
+            expected.extend_from_slice(&event(label, &[0]));
+        }
+        assert_eq!(&buffer[..len], expected);
+        assert_eq!(
+            read_raw_buffer(&mut [0u8; 4]),
+            Err(Error::ResourceExhausted)
+        );
+        // Reading the raw buffer doesn't remove events.
+        assert_eq!(events().len(), 2);
+    }
+
+    #[test]
+    fn oldest_events_are_dropped_when_full() {
+        let _guard = start();
+        for _ in 0..TRACE_BUFFER_SIZE_BYTES {
+            trace_instant!("Tick");
+        }
+        let count = events().len();
+        assert_eq!(count, TRACE_BUFFER_SIZE_BYTES / 6);
+    }
+
+    #[test]
+    fn events_share_multisink_with_logs() {
+        let _guard = start();
+        let sink = MultiSink::<32>::new();
+        let mut drain = sink.attach_drain();
+        sink.push_entry_with_metadata(0x1234, b"log").unwrap();
+        trace_instant!("A");
+        trace_instant_data!("B", "@pw_arg_bytes", &[0; MAX_DATA_SIZE_BYTES]);
+        assert_eq!(push_events_to(&sink), 1);
+        assert!(events().is_empty());
+
+        let mut buffer = [0u8; 64];
+        let (entry, drops) = drain.pop_entry(&mut buffer);
+        assert_eq!(entry.unwrap().entry(), b"log");
+        // The second event is larger than the sink.
+        assert_eq!(drops.ingress, 1);
+        let (entry, _) = drain.pop_entry(&mut buffer);
+        let label = masked_token!("trace", u32::MAX, "PW_TRACE_EVENT_TYPE_INSTANT|0|||A");
+        assert_eq!(entry.unwrap().entry(), event(label, &[0]));
+        assert_eq!(
+            drain.pop_entry(&mut buffer).0.err(),
+            Some(Error::OutOfRange)
+        );
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+// This proc macro crate is a private API for the `pw_trace_tokenized` crate.
+#![doc(hidden)]
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    LitStr, Token,
+};
+
+// Args to `_trace_token!` that are parsed according to the pattern:
+//   ($event_type:literal, $group:literal, $label:literal, $data_type:literal?)
+struct TraceTokenArgs {
+    fields: Punctuated<LitStr, Token![,]>,
+}
+
+impl Parse for TraceTokenArgs {
+    fn parse(input: ParseStream) -> syn::parse::Result<Self> {
+        let fields = Punctuated::parse_terminated(input)?;
+        if !(3..=4).contains(&fields.len()) {
+            return Err(input.error("expected an event type, group, label, and optional data type"));
+        }
+        Ok(TraceTokenArgs { fields })
+    }
+}
+
+// Returns the token of a trace event in the `"trace"` domain.  The tokenized
+// string is `event_type|flags|module|group|label`, followed by `|data_type`
+// for events with data, as in `PW_TRACE_REF()` and `PW_TRACE_REF_DATA()`.
+// Flags and the module are always `0` and empty.
+#[proc_macro]
+pub fn _trace_token(tokens: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(tokens as TraceTokenArgs);
+    for field in input.fields.iter().skip(1) {
+        if field.value().contains('|') {
+            return syn::Error::new(
+                field.span(),
+                "trace labels, groups, and data types may not contain `|`",
+            )
+            .to_compile_error()
+            .into();
+        }
+    }
+    let mut fields = input.fields.iter().map(LitStr::value);
+    let event_type = fields.next().unwrap_or_default();
+    let rest: Vec<String> = fields.collect();
+    let string = format!("{event_type}|0||{}", rest.join("|"));
+    quote! {
+        __pw_trace_crate::masked_token!("trace", u32::MAX, #string)
+    }
+    .into()
+}
@@ -0,0 +1,442 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_protobuf::{Decoder, MemoryEncoder};
+use pw_status::{from_c_status, to_c_status, Error, Result};
+
+// Field numbers of the `pw.transfer.Chunk` message.
+const TRANSFER_ID_FIELD: u32 = 1;
+const PENDING_BYTES_FIELD: u32 = 2;
+const MAX_CHUNK_SIZE_BYTES_FIELD: u32 = 3;
+const MIN_DELAY_MICROSECONDS_FIELD: u32 = 4;
+const OFFSET_FIELD: u32 = 5;
+const DATA_FIELD: u32 = 6;
+const REMAINING_BYTES_FIELD: u32 = 7;
+const STATUS_FIELD: u32 = 8;
+const WINDOW_END_OFFSET_FIELD: u32 = 9;
+const TYPE_FIELD: u32 = 10;
+const RESOURCE_ID_FIELD: u32 = 11;
+const SESSION_ID_FIELD: u32 = 12;
+const PROTOCOL_VERSION_FIELD: u32 = 13;
+const DESIRED_SESSION_ID_FIELD: u32 = 14;
+const INITIAL_OFFSET_FIELD: u32 = 15;
+
+/// The largest size of an encoded chunk's fields other than its data.
+pub const MAX_CHUNK_OVERHEAD: usize = 6 // transfer_id
+    + 6 // pending_bytes
+    + 6 // max_chunk_size_bytes
+    + 6 // min_delay_microseconds
+    + 11 // offset
+    + 6 // data key and length
+    + 11 // remaining_bytes
+    + 3 // status
+    + 6 // window_end_offset
+    + 2 // type
+    + 6 // resource_id
+    + 6 // session_id
+    + 2 // protocol_version
+    + 6 // desired_session_id
+    + 11; // initial_offset
+
+/// A version of the transfer protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum ProtocolVersion {
+    /// The original protocol, in which transfers are identified by their
+    /// resource ID and have no opening or closing handshake.
+    Legacy = 1,
+    /// The protocol with session IDs and opening and closing handshakes.
+    Version2 = 2,
+}
+
+impl ProtocolVersion {
+    /// The newest protocol version.
+    pub const LATEST: Self = Self::Version2;
+
+    const fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            1 => Some(Self::Legacy),
+            2 => Some(Self::Version2),
+            _ => None,
+        }
+    }
+}
+
+/// The type of a chunk, from the `pw.transfer.Chunk.Type` enum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ChunkType {
+    /// A chunk of the resource's data.
+    Data = 0,
+    /// The first chunk of a transfer.
+    Start = 1,
+    /// The receiver's transfer parameters, requesting that data is sent
+    /// from the chunk's offset.
+    ParametersRetransmit = 2,
+    /// The receiver's transfer parameters, extending the window without
+    /// interrupting the data being sent.
+    ParametersContinue = 3,
+    /// The transfer has finished with the chunk's status.
+    Completion = 4,
+    /// Acknowledges a [`ChunkType::Completion`].
+    CompletionAck = 5,
+    /// Acknowledges a [`ChunkType::Start`], assigning the session.
+    StartAck = 6,
+    /// Confirms a [`ChunkType::StartAck`], completing the opening handshake.
+    StartAckConfirmation = 7,
+}
+
+impl ChunkType {
+    const fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Data),
+            1 => Some(Self::Start),
+            2 => Some(Self::ParametersRetransmit),
+            3 => Some(Self::ParametersContinue),
+            4 => Some(Self::Completion),
+            5 => Some(Self::CompletionAck),
+            6 => Some(Self::StartAck),
+            7 => Some(Self::StartAckConfirmation),
+            _ => None,
+        }
+    }
+
+    const fn is_initial_handshake(self) -> bool {
+        matches!(
+            self,
+            ChunkType::Start | ChunkType::StartAck | ChunkType::StartAckConfirmation
+        )
+    }
+}
+
+/// A chunk exchanged in a transfer, as the `pw.transfer.Chunk` message.
+///
+/// Chunks are encoded in the format of their protocol version, so the
+/// fields of legacy chunks are translated as the C++ and Python
+/// implementations do.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    /// The protocol version the chunk is encoded with.
+    pub protocol_version: ProtocolVersion,
+    /// The type of the chunk.
+    pub chunk_type: ChunkType,
+    /// The ID of the transfer session, which is the resource ID in the
+    /// legacy protocol.
+    pub session_id: u32,
+    /// In a version 2 [`ChunkType::Start`] chunk, the session ID the client
+    /// requests.
+    pub desired_session_id: Option<u32>,
+    /// In a [`ChunkType::Start`] chunk, the resource to transfer.
+    pub resource_id: Option<u32>,
+    /// The offset of the chunk's data, or of the data requested.
+    pub offset: u64,
+    /// In a parameters chunk, the end of the window of data the receiver can
+    /// accept.
+    pub window_end_offset: u32,
+    /// The chunk's data.
+    pub data: &'a [u8],
+    /// The number of bytes after this chunk's data, which is 0 in the last
+    /// data chunk.
+    pub remaining_bytes: Option<u64>,
+    /// The largest amount of data the receiver accepts in a chunk.
+    pub max_chunk_size_bytes: Option<u32>,
+    /// The time the sender should wait between data chunks.
+    pub min_delay_microseconds: Option<u32>,
+    /// In a [`ChunkType::Completion`] chunk, the transfer's final status.
+    pub status: Option<Result<()>>,
+    /// The offset the transfer starts at.
+    pub initial_offset: u64,
+}
+
+// `Error` only implements `Debug` with the `std` feature, so the status is
+// formatted as its code.
+impl core::fmt::Debug for Chunk<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Chunk")
+            .field("protocol_version", &self.protocol_version)
+            .field("chunk_type", &self.chunk_type)
+            .field("session_id", &self.session_id)
+            .field("desired_session_id", &self.desired_session_id)
+            .field("resource_id", &self.resource_id)
+            .field("offset", &self.offset)
+            .field("window_end_offset", &self.window_end_offset)
+            .field("data", &self.data)
+            .field("remaining_bytes", &self.remaining_bytes)
+            .field("max_chunk_size_bytes", &self.max_chunk_size_bytes)
+            .field("min_delay_microseconds", &self.min_delay_microseconds)
+            .field("status", &self.status.as_ref().map(to_c_status))
+            .field("initial_offset", &self.initial_offset)
+            .finish()
+    }
+}
+
+impl<'a> Chunk<'a> {
+    /// Creates a chunk with no data or optional fields.
+    pub const fn new(
+        protocol_version: ProtocolVersion,
+        chunk_type: ChunkType,
+        session_id: u32,
+    ) -> Self {
+        Self {
+            protocol_version,
+            chunk_type,
+            session_id,
+            desired_session_id: None,
+            resource_id: None,
+            offset: 0,
+            window_end_offset: 0,
+            data: &[],
+            remaining_bytes: None,
+            max_chunk_size_bytes: None,
+            min_delay_microseconds: None,
+            status: None,
+            initial_offset: 0,
+        }
+    }
+
+    /// Returns true if the chunk asks the sender to send data from its
+    /// offset, rather than continue from where it is.
+    pub const fn requests_transmission_from_offset(&self) -> bool {
+        matches!(
+            self.chunk_type,
+            ChunkType::ParametersRetransmit | ChunkType::Start | ChunkType::StartAckConfirmation
+        )
+    }
+
+    /// Decodes a chunk.  Missing fields take their default values.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - `data` is not a valid chunk.
+    pub fn decode(data: &'a [u8]) -> Result<Self> {
+        let mut chunk = Self::new(ProtocolVersion::Legacy, ChunkType::Data, 0);
+        let mut chunk_type = None;
+        let mut transfer_id = 0;
+        let mut session_id = None;
+        let mut protocol_version = None;
+        let mut pending_bytes = None;
+        for field in Decoder::new(data) {
+            let field = field?;
+            match field.number {
+                TRANSFER_ID_FIELD => transfer_id = field.value.as_u32()?,
+                PENDING_BYTES_FIELD => pending_bytes = Some(field.value.as_u32()?),
+                MAX_CHUNK_SIZE_BYTES_FIELD => {
+                    chunk.max_chunk_size_bytes = Some(field.value.as_u32()?)
+                }
+                MIN_DELAY_MICROSECONDS_FIELD => {
+                    chunk.min_delay_microseconds = Some(field.value.as_u32()?)
+                }
+                OFFSET_FIELD => chunk.offset = field.value.as_u64()?,
+                DATA_FIELD => chunk.data = field.value.as_bytes()?,
+                REMAINING_BYTES_FIELD => chunk.remaining_bytes = Some(field.value.as_u64()?),
+                STATUS_FIELD => {
+                    let code = field.value.as_u32()?;
+                    chunk.status =
+                        Some(from_c_status(code.try_into().map_err(|_| Error::DataLoss)?))
+                }
+                WINDOW_END_OFFSET_FIELD => chunk.window_end_offset = field.value.as_u32()?,
+                TYPE_FIELD => {
+                    chunk_type =
+                        Some(ChunkType::from_u32(field.value.as_u32()?).ok_or(Error::DataLoss)?)
+                }
+                RESOURCE_ID_FIELD => chunk.resource_id = Some(field.value.as_u32()?),
+                SESSION_ID_FIELD => session_id = Some(field.value.as_u32()?),
+                PROTOCOL_VERSION_FIELD => {
+                    protocol_version = Some(
+                        ProtocolVersion::from_u32(field.value.as_u32()?).ok_or(Error::DataLoss)?,
+                    )
+                }
+                DESIRED_SESSION_ID_FIELD => chunk.desired_session_id = Some(field.value.as_u32()?),
+                INITIAL_OFFSET_FIELD => chunk.initial_offset = field.value.as_u64()?,
+                // Unknown fields are ignored for forward compatibility.
+                _ => {}
+            }
+        }
+
+        // Legacy chunks may not have a type, which is inferred from their
+        // contents.
+        chunk.chunk_type = chunk_type.unwrap_or(
+            if chunk.offset == 0 && chunk.data.is_empty() && chunk.status.is_none() {
+                ChunkType::Start
+            } else if !chunk.data.is_empty() {
+                ChunkType::Data
+            } else {
+                ChunkType::ParametersRetransmit
+            },
+        );
+        let is_version2 = session_id.is_some() || chunk.desired_session_id.is_some();
+        chunk.protocol_version = protocol_version.unwrap_or(if is_version2 {
+            ProtocolVersion::Version2
+        } else {
+            ProtocolVersion::Legacy
+        });
+        // Version 2 start chunks have no session ID until the server assigns
+        // one.
+        chunk.session_id = match session_id {
+            Some(session_id) => session_id,
+            None if is_version2 => 0,
+            None => transfer_id,
+        };
+        if let Some(pending_bytes) = pending_bytes {
+            chunk.window_end_offset =
+                u32::try_from(chunk.offset + u64::from(pending_bytes)).unwrap_or(u32::MAX);
+        }
+        Ok(chunk)
+    }
+
+    /// Encodes the chunk into `buffer` and returns the encoded size.
+    ///
+    /// # Errors
+    /// - [`Error::ResourceExhausted`] - The chunk does not fit in `buffer`.
+    pub fn encode(&self, buffer: &mut [u8]) -> Result<usize> {
+        let mut encoder = MemoryEncoder::new(buffer);
+        let version2 = self.protocol_version == ProtocolVersion::Version2;
+        // Legacy fields are also sent in version 2 start chunks, so servers
+        // which only support the legacy protocol can respond to them.
+        if !version2 || self.chunk_type == ChunkType::Start {
+            encoder.write_uint32(
+                TRANSFER_ID_FIELD,
+                self.resource_id.unwrap_or(self.session_id),
+            )?;
+            if u64::from(self.window_end_offset) > self.offset {
+                let pending_bytes = u64::from(self.window_end_offset) - self.offset;
+                encoder.write_uint32(PENDING_BYTES_FIELD, pending_bytes as u32)?;
+            }
+        }
+        if version2 {
+            if self.session_id != 0 {
+                encoder.write_uint32(SESSION_ID_FIELD, self.session_id)?;
+            }
+            if let Some(desired_session_id) = self.desired_session_id {
+                encoder.write_uint32(DESIRED_SESSION_ID_FIELD, desired_session_id)?;
+            }
+            if self.chunk_type.is_initial_handshake() {
+                encoder.write_uint32(PROTOCOL_VERSION_FIELD, self.protocol_version as u32)?;
+            }
+        }
+        if let Some(resource_id) = self.resource_id {
+            encoder.write_uint32(RESOURCE_ID_FIELD, resource_id)?;
+        }
+        encoder.write_uint32(TYPE_FIELD, self.chunk_type as u32)?;
+        if self.offset != 0 {
+            encoder.write_uint64(OFFSET_FIELD, self.offset)?;
+        }
+        if self.window_end_offset != 0 {
+            encoder.write_uint32(WINDOW_END_OFFSET_FIELD, self.window_end_offset)?;
+        }
+        if let Some(max_chunk_size_bytes) = self.max_chunk_size_bytes {
+            encoder.write_uint32(MAX_CHUNK_SIZE_BYTES_FIELD, max_chunk_size_bytes)?;
+        }
+        if let Some(min_delay_microseconds) = self.min_delay_microseconds {
+            encoder.write_uint32(MIN_DELAY_MICROSECONDS_FIELD, min_delay_microseconds)?;
+        }
+        if let Some(remaining_bytes) = self.remaining_bytes {
+            encoder.write_uint64(REMAINING_BYTES_FIELD, remaining_bytes)?;
+        }
+        if let Some(status) = self.status {
+            encoder.write_uint32(STATUS_FIELD, to_c_status(&status) as u32)?;
+        }
+        if self.initial_offset != 0 {
+            encoder.write_uint64(INITIAL_OFFSET_FIELD, self.initial_offset)?;
+        }
+        if !self.data.is_empty() {
+            encoder.write_bytes(DATA_FIELD, self.data)?;
+        }
+        Ok(encoder.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn version2_chunk_round_trips() {
+        let mut chunk = Chunk::new(ProtocolVersion::Version2, ChunkType::Data, 7);
+        chunk.offset = 1 << 33;
+        chunk.data = b"hello";
+        chunk.remaining_bytes = Some(0);
+        chunk.window_end_offset = 4096;
+        chunk.max_chunk_size_bytes = Some(512);
+        chunk.min_delay_microseconds = Some(10);
+        chunk.initial_offset = 3;
+
+        let mut buffer = [0u8; 64];
+        let len = chunk.encode(&mut buffer).unwrap();
+        assert_eq!(Chunk::decode(&buffer[..len]), Ok(chunk));
+
+        let mut completion = Chunk::new(ProtocolVersion::Version2, ChunkType::Completion, 7);
+        completion.status = Some(Err(Error::DataLoss));
+        let len = completion.encode(&mut buffer).unwrap();
+        assert_eq!(Chunk::decode(&buffer[..len]), Ok(completion));
+    }
+
+    #[test]
+    fn start_chunk_has_legacy_fields() {
+        let mut start = Chunk::new(ProtocolVersion::Version2, ChunkType::Start, 0);
+        start.desired_session_id = Some(1);
+        start.resource_id = Some(3);
+        start.window_end_offset = 1024;
+
+        let mut buffer = [0u8; 64];
+        let len = start.encode(&mut buffer).unwrap();
+        let mut decoder = Decoder::new(&buffer[..len]);
+        let transfer_id = decoder.next().unwrap().unwrap();
+        assert_eq!(transfer_id.number, TRANSFER_ID_FIELD);
+        assert_eq!(transfer_id.value.as_u32(), Ok(3));
+        let pending_bytes = decoder.next().unwrap().unwrap();
+        assert_eq!(pending_bytes.number, PENDING_BYTES_FIELD);
+        assert_eq!(pending_bytes.value.as_u32(), Ok(1024));
+        assert_eq!(Chunk::decode(&buffer[..len]), Ok(start));
+    }
+
+    #[test]
+    fn legacy_chunk_fields_are_translated() {
+        // A legacy parameters chunk with `transfer_id` 3, `offset` 16 and
+        // `pending_bytes` 32 and no type.
+        let data = [0x08, 0x03, 0x10, 0x20, 0x28, 0x10];
+        let chunk = Chunk::decode(&data).unwrap();
+        assert_eq!(chunk.protocol_version, ProtocolVersion::Legacy);
+        assert_eq!(chunk.chunk_type, ChunkType::ParametersRetransmit);
+        assert_eq!(chunk.session_id, 3);
+        assert_eq!(chunk.window_end_offset, 48);
+
+        // A legacy data chunk.
+        let chunk = Chunk::decode(&[0x08, 0x03, 0x32, 0x01, 0xaa]).unwrap();
+        assert_eq!(chunk.chunk_type, ChunkType::Data);
+        assert_eq!(chunk.data, &[0xaa]);
+
+        let mut buffer = [0u8; 16];
+        let mut legacy = Chunk::new(ProtocolVersion::Legacy, ChunkType::Completion, 3);
+        legacy.status = Some(Ok(()));
+        let len = legacy.encode(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], &[0x08, 0x03, 0x50, 0x04, 0x40, 0x00]);
+        assert_eq!(Chunk::decode(&buffer[..len]), Ok(legacy));
+    }
+
+    #[test]
+    fn invalid_chunks_are_data_loss() {
+        assert_eq!(Chunk::decode(&[0x50, 0x08]), Err(Error::DataLoss));
+        assert_eq!(Chunk::decode(&[0x68, 0x03]), Err(Error::DataLoss));
+        assert_eq!(Chunk::decode(&[0x32, 0x05]), Err(Error::DataLoss));
+    }
+
+    #[test]
+    fn debug_formats_status_code() {
+        let mut chunk = Chunk::new(ProtocolVersion::Version2, ChunkType::Completion, 7);
+        chunk.status = Some(Err(Error::NotFound));
+        assert!(std::format!("{chunk:?}").contains("status: Some(5)"));
+    }
+}
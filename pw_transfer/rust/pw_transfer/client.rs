@@ -0,0 +1,984 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_rpc::{Call, Client};
+use pw_status::{Error, Result};
+use pw_stream::Write;
+
+use crate::chunk::{Chunk, ChunkType, ProtocolVersion};
+
+/// Sends the chunks of a transfer to the server.
+///
+/// Implemented for closures which take a chunk.
+pub trait ChunkOutput {
+    /// Sends a chunk.
+    fn send_chunk(&mut self, chunk: &Chunk) -> Result<()>;
+}
+
+impl<F: FnMut(&Chunk) -> Result<()>> ChunkOutput for F {
+    fn send_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        self(chunk)
+    }
+}
+
+/// A [`ChunkOutput`] which sends chunks as client stream messages of a
+/// `Read` or `Write` call to the transfer service.
+pub struct RpcChunkOutput<'c, 'a, 'b, const MAX_CALLS: usize> {
+    client: &'c mut Client<'a, MAX_CALLS>,
+    call: Call,
+    buffer: &'b mut [u8],
+}
+
+impl<'c, 'a, 'b, const MAX_CALLS: usize> RpcChunkOutput<'c, 'a, 'b, MAX_CALLS> {
+    /// Creates an output which sends chunks on `call` through `client`.
+    ///
+    /// Chunks are encoded in `buffer`, which should have room for the
+    /// largest data chunk plus [`crate::MAX_CHUNK_OVERHEAD`].
+    pub fn new(client: &'c mut Client<'a, MAX_CALLS>, call: Call, buffer: &'b mut [u8]) -> Self {
+        Self {
+            client,
+            call,
+            buffer,
+        }
+    }
+}
+
+impl<const MAX_CALLS: usize> ChunkOutput for RpcChunkOutput<'_, '_, '_, MAX_CALLS> {
+    fn send_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        let len = chunk.encode(self.buffer)?;
+        self.client.write(&self.call, &self.buffer[..len])
+    }
+}
+
+/// Configuration of a client transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransferConfig {
+    /// The protocol version to start the transfer with.  Transfers fall back
+    /// to the legacy protocol if the server does not support it.
+    pub protocol_version: ProtocolVersion,
+    /// The number of consecutive timeouts after which the transfer fails
+    /// with [`Error::DeadlineExceeded`].
+    pub max_retries: u32,
+    /// The total number of timeouts after which the transfer fails with
+    /// [`Error::DeadlineExceeded`].
+    pub max_lifetime_retries: u32,
+    /// The size of the window of data requested in read transfers.
+    pub max_bytes_to_receive: u32,
+    /// The largest amount of data sent or requested in a chunk.
+    pub max_chunk_size_bytes: u32,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            protocol_version: ProtocolVersion::LATEST,
+            max_retries: 3,
+            max_lifetime_retries: 1500,
+            max_bytes_to_receive: 8192,
+            max_chunk_size_bytes: 1024,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    // The start chunk was sent and the server has not responded.
+    Initiating,
+    // Waiting for the server to send data or transfer parameters.
+    Waiting,
+    // A read transfer received data at the wrong offset and asked the server
+    // to retransmit it.
+    Recovery,
+    // The final status was sent and the server has not acknowledged it.
+    Terminating,
+    Complete,
+}
+
+// The state shared by read and write transfers.
+struct Session {
+    config: TransferConfig,
+    protocol_version: ProtocolVersion,
+    session_id: u32,
+    resource_id: u32,
+    state: State,
+    status: Option<Result<()>>,
+    offset: u64,
+    retries: u32,
+    lifetime_retries: u32,
+}
+
+impl Session {
+    fn new(session_id: u32, resource_id: u32, config: TransferConfig) -> Self {
+        let legacy = config.protocol_version == ProtocolVersion::Legacy;
+        Self {
+            config,
+            protocol_version: config.protocol_version,
+            session_id: if legacy { resource_id } else { session_id },
+            resource_id,
+            state: State::Initiating,
+            status: None,
+            offset: 0,
+            retries: 0,
+            lifetime_retries: 0,
+        }
+    }
+
+    fn is_version2(&self) -> bool {
+        self.protocol_version == ProtocolVersion::Version2
+    }
+
+    fn chunk(&self, chunk_type: ChunkType) -> Chunk<'static> {
+        Chunk::new(self.protocol_version, chunk_type, self.session_id)
+    }
+
+    fn start_chunk(&self) -> Chunk<'static> {
+        if self.is_version2() {
+            let mut chunk = Chunk::new(self.protocol_version, ChunkType::Start, 0);
+            chunk.desired_session_id = Some(self.session_id);
+            chunk.resource_id = Some(self.resource_id);
+            chunk
+        } else {
+            self.chunk(ChunkType::Start)
+        }
+    }
+
+    fn completion_chunk(&self) -> Chunk<'static> {
+        let mut chunk = self.chunk(ChunkType::Completion);
+        chunk.status = self.status;
+        chunk
+    }
+
+    // Returns true if `chunk` belongs to this transfer.
+    fn is_for_session(&self, chunk: &Chunk) -> bool {
+        chunk.session_id == self.session_id
+            || (self.state == State::Initiating
+                && chunk.protocol_version == ProtocolVersion::Legacy
+                && chunk.session_id == self.resource_id)
+    }
+
+    // Processes the server's response to the start chunk.  Returns true if
+    // the server acknowledged a version 2 transfer, or falls back to the
+    // legacy protocol and returns false.
+    fn handle_initial_response(&mut self, chunk: &Chunk) -> bool {
+        self.state = State::Waiting;
+        if self.is_version2() && chunk.chunk_type == ChunkType::StartAck {
+            self.protocol_version = self.protocol_version.min(chunk.protocol_version);
+            true
+        } else {
+            self.protocol_version = ProtocolVersion::Legacy;
+            self.session_id = self.resource_id;
+            false
+        }
+    }
+
+    // Ends the transfer with `status`, which is sent to the server.
+    fn finish(&mut self, status: Result<()>, output: &mut impl ChunkOutput) -> Result<()> {
+        self.status = Some(status);
+        self.state = if self.is_version2() {
+            State::Terminating
+        } else {
+            State::Complete
+        };
+        output.send_chunk(&self.completion_chunk())
+    }
+
+    // Ends the transfer with the status the server sent.
+    fn handle_server_status(
+        &mut self,
+        status: Result<()>,
+        output: &mut impl ChunkOutput,
+    ) -> Result<()> {
+        self.status = Some(status);
+        self.state = State::Complete;
+        if self.is_version2() {
+            output.send_chunk(&self.chunk(ChunkType::CompletionAck))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Counts a timeout.  Returns false if the transfer has run out of
+    // retries, which ends it.
+    fn retry(&mut self) -> bool {
+        self.retries += 1;
+        self.lifetime_retries += 1;
+        if self.retries <= self.config.max_retries
+            && self.lifetime_retries <= self.config.max_lifetime_retries
+        {
+            return true;
+        }
+        // A transfer which was already finished keeps its status when the
+        // server does not acknowledge it.
+        if self.state != State::Terminating {
+            self.status = Some(Err(Error::DeadlineExceeded));
+        }
+        self.state = State::Complete;
+        false
+    }
+}
+
+// The last chunk a write transfer sent, which is resent on timeouts.
+#[derive(Clone, Copy)]
+enum LastChunk {
+    Start,
+    StartAckConfirmation,
+    Data { offset: u64, end: u64 },
+}
+
+/// A transfer which sends data to a resource on the server, such as a
+/// firmware image.
+///
+/// The transfer is driven by the caller: [`WriteTransfer::start()`] sends the
+/// first chunk, each chunk the server sends is passed to
+/// [`WriteTransfer::handle_chunk()`], and [`WriteTransfer::handle_timeout()`]
+/// is called when no chunk arrives in time.  The transfer sends the data
+/// requested in each window of transfer parameters at once and is done when
+/// [`WriteTransfer::status()`] returns its result.
+///
+/// ```
+/// use pw_transfer::{Chunk, ChunkType, ProtocolVersion, TransferConfig, WriteTransfer};
+///
+/// let mut sent = Vec::new();
+/// let mut output = |chunk: &Chunk| {
+///     sent.push((chunk.chunk_type, chunk.data.len()));
+///     Ok(())
+/// };
+///
+/// let mut transfer = WriteTransfer::new(1, 7, b"firmware", TransferConfig::default());
+/// transfer.start(&mut output).unwrap();
+///
+/// // The server accepts the transfer, then asks for up to 1 KiB of data.
+/// let start_ack = Chunk::new(ProtocolVersion::Version2, ChunkType::StartAck, 1);
+/// transfer.handle_chunk(&start_ack, &mut output).unwrap();
+/// let mut parameters =
+///     Chunk::new(ProtocolVersion::Version2, ChunkType::ParametersRetransmit, 1);
+/// parameters.window_end_offset = 1024;
+/// transfer.handle_chunk(&parameters, &mut output).unwrap();
+///
+/// let mut completion = Chunk::new(ProtocolVersion::Version2, ChunkType::Completion, 1);
+/// completion.status = Some(Ok(()));
+/// transfer.handle_chunk(&completion, &mut output).unwrap();
+/// assert_eq!(transfer.status(), Some(Ok(())));
+/// assert_eq!(
+///     sent,
+///     [
+///         (ChunkType::Start, 0),
+///         (ChunkType::StartAckConfirmation, 0),
+///         (ChunkType::Data, 8),
+///         (ChunkType::CompletionAck, 0),
+///     ]
+/// );
+/// ```
+pub struct WriteTransfer<'d> {
+    session: Session,
+    data: &'d [u8],
+    window_end_offset: u64,
+    max_chunk_size: u32,
+    last_chunk: LastChunk,
+}
+
+impl<'d> WriteTransfer<'d> {
+    /// Creates a transfer of `data` to resource `resource_id`.
+    ///
+    /// `session_id` must be unique among the client's active transfers.  It
+    /// is replaced by `resource_id` if the transfer uses the legacy protocol.
+    pub fn new(session_id: u32, resource_id: u32, data: &'d [u8], config: TransferConfig) -> Self {
+        Self {
+            session: Session::new(session_id, resource_id, config),
+            data,
+            window_end_offset: 0,
+            max_chunk_size: config.max_chunk_size_bytes,
+            last_chunk: LastChunk::Start,
+        }
+    }
+
+    /// Sends the chunk which starts the transfer.
+    pub fn start(&mut self, output: &mut impl ChunkOutput) -> Result<()> {
+        self.last_chunk = LastChunk::Start;
+        output.send_chunk(&self.session.start_chunk())
+    }
+
+    /// Processes a chunk the server sent and sends any chunks in response.
+    ///
+    /// Chunks for other transfers are ignored.
+    ///
+    /// # Errors
+    /// Any error sending a chunk is returned.  The chunk is resent by
+    /// [`WriteTransfer::handle_timeout()`].
+    pub fn handle_chunk(&mut self, chunk: &Chunk, output: &mut impl ChunkOutput) -> Result<()> {
+        if self.session.state == State::Complete || !self.session.is_for_session(chunk) {
+            return Ok(());
+        }
+        self.session.retries = 0;
+        if self.session.state == State::Initiating && self.session.handle_initial_response(chunk) {
+            self.last_chunk = LastChunk::StartAckConfirmation;
+            return output.send_chunk(&self.session.chunk(ChunkType::StartAckConfirmation));
+        }
+        if chunk.chunk_type == ChunkType::StartAck {
+            // The server did not receive the confirmation.
+            return output.send_chunk(&self.session.chunk(ChunkType::StartAckConfirmation));
+        }
+        if self.session.state == State::Terminating {
+            if chunk.chunk_type == ChunkType::CompletionAck {
+                self.session.state = State::Complete;
+            }
+            return Ok(());
+        }
+        if let Some(status) = chunk.status {
+            return self.session.handle_server_status(status, output);
+        }
+        if !matches!(
+            chunk.chunk_type,
+            ChunkType::ParametersRetransmit
+                | ChunkType::ParametersContinue
+                | ChunkType::Start
+                | ChunkType::StartAckConfirmation
+        ) {
+            return Ok(());
+        }
+
+        let len = self.data.len() as u64;
+        if chunk.offset > len {
+            return self.session.finish(Err(Error::OutOfRange), output);
+        }
+        if chunk.requests_transmission_from_offset() {
+            self.session.offset = chunk.offset;
+        } else if u64::from(chunk.window_end_offset) <= self.session.offset {
+            // A stale window which the data already sent has passed.
+            return Ok(());
+        }
+        self.window_end_offset = u64::from(chunk.window_end_offset).min(len);
+        if let Some(max_chunk_size) = chunk.max_chunk_size_bytes {
+            self.max_chunk_size = max_chunk_size.min(self.session.config.max_chunk_size_bytes);
+        }
+        self.transmit_window(output)
+    }
+
+    /// Resends the last chunk after the server has not responded in time.
+    ///
+    /// The transfer fails with [`Error::DeadlineExceeded`] after
+    /// [`TransferConfig::max_retries`] consecutive timeouts.
+    ///
+    /// # Errors
+    /// Any error sending the chunk is returned.
+    pub fn handle_timeout(&mut self, output: &mut impl ChunkOutput) -> Result<()> {
+        if self.session.state == State::Complete || !self.session.retry() {
+            return Ok(());
+        }
+        match (self.session.state, self.last_chunk) {
+            (State::Terminating, _) => output.send_chunk(&self.session.completion_chunk()),
+            (_, LastChunk::Start) => output.send_chunk(&self.session.start_chunk()),
+            (_, LastChunk::StartAckConfirmation) => {
+                output.send_chunk(&self.session.chunk(ChunkType::StartAckConfirmation))
+            }
+            (_, LastChunk::Data { offset, end }) => {
+                output.send_chunk(&self.data_chunk(offset, end))
+            }
+        }
+    }
+
+    /// Cancels the transfer and tells the server.
+    pub fn cancel(&mut self, output: &mut impl ChunkOutput) -> Result<()> {
+        if self.session.state == State::Complete {
+            return Ok(());
+        }
+        self.session.finish(Err(Error::Cancelled), output)
+    }
+
+    /// Returns the result of the transfer, or `None` if it is not done.
+    pub fn status(&self) -> Option<Result<()>> {
+        match self.session.state {
+            State::Complete => self.session.status,
+            _ => None,
+        }
+    }
+
+    /// Returns the session ID identifying the transfer's chunks.
+    pub fn session_id(&self) -> u32 {
+        self.session.session_id
+    }
+
+    /// Returns the offset of the next data to send.
+    pub fn offset(&self) -> u64 {
+        self.session.offset
+    }
+
+    fn data_chunk(&self, offset: u64, end: u64) -> Chunk<'d> {
+        let mut chunk = self.session.chunk(ChunkType::Data);
+        chunk.offset = offset;
+        chunk.data = &self.data[offset as usize..end as usize];
+        if end == self.data.len() as u64 {
+            chunk.remaining_bytes = Some(0);
+        }
+        chunk
+    }
+
+    // Sends the data from the offset to the end of the window.  If all data
+    // was sent, the empty final chunk is resent.
+    fn transmit_window(&mut self, output: &mut impl ChunkOutput) -> Result<()> {
+        let len = self.data.len() as u64;
+        if self.session.offset >= self.window_end_offset && self.session.offset < len {
+            return Ok(());
+        }
+        let max_chunk_size = u64::from(self.max_chunk_size.max(1));
+        loop {
+            let offset = self.session.offset;
+            let end = (offset + max_chunk_size).min(self.window_end_offset);
+            self.last_chunk = LastChunk::Data { offset, end };
+            output.send_chunk(&self.data_chunk(offset, end))?;
+            self.session.offset = end;
+            if end >= self.window_end_offset {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// A transfer which receives data from a resource on the server, such as a
+/// crash log, and writes it to a sink.
+///
+/// The transfer is driven by the caller like a [`WriteTransfer`].  It
+/// requests data in windows of [`TransferConfig::max_bytes_to_receive`]
+/// bytes and is done when [`ReadTransfer::status()`] returns its result.
+///
+/// ```
+/// use pw_stream::Cursor;
+/// use pw_transfer::{Chunk, ChunkType, ProtocolVersion, ReadTransfer, TransferConfig};
+///
+/// let mut output = |_: &Chunk| Ok(());
+/// let mut transfer =
+///     ReadTransfer::new(1, 7, Cursor::new([0u8; 16]), TransferConfig::default());
+/// transfer.start(&mut output).unwrap();
+///
+/// let start_ack = Chunk::new(ProtocolVersion::Version2, ChunkType::StartAck, 1);
+/// transfer.handle_chunk(&start_ack, &mut output).unwrap();
+/// let mut data = Chunk::new(ProtocolVersion::Version2, ChunkType::Data, 1);
+/// data.data = b"crash log";
+/// data.remaining_bytes = Some(0);
+/// transfer.handle_chunk(&data, &mut output).unwrap();
+///
+/// // The server acknowledges the transfer's final status.
+/// let ack = Chunk::new(ProtocolVersion::Version2, ChunkType::CompletionAck, 1);
+/// transfer.handle_chunk(&ack, &mut output).unwrap();
+/// assert_eq!(transfer.status(), Some(Ok(())));
+/// let sink = transfer.into_sink();
+/// assert_eq!(&sink.into_inner()[..9], b"crash log");
+/// ```
+pub struct ReadTransfer<W: Write> {
+    session: Session,
+    sink: W,
+    window_end_offset: u64,
+}
+
+impl<W: Write> ReadTransfer<W> {
+    /// Creates a transfer from resource `resource_id` which writes the data
+    /// received to `sink`.
+    ///
+    /// `session_id` must be unique among the client's active transfers.  It
+    /// is replaced by `resource_id` if the transfer uses the legacy protocol.
+    pub fn new(session_id: u32, resource_id: u32, sink: W, config: TransferConfig) -> Self {
+        Self {
+            session: Session::new(session_id, resource_id, config),
+            sink,
+            window_end_offset: 0,
+        }
+    }
+
+    /// Sends the chunk which starts the transfer, with the transfer
+    /// parameters for servers which only support the legacy protocol.
+    pub fn start(&mut self, output: &mut impl ChunkOutput) -> Result<()> {
+        self.window_end_offset = self.next_window_end();
+        output.send_chunk(&self.with_parameters(self.session.start_chunk()))
+    }
+
+    /// Processes a chunk the server sent and sends any chunks in response.
+    ///
+    /// Chunks for other transfers are ignored.  If writing to the sink
+    /// fails, the transfer ends with the error.
+    ///
+    /// # Errors
+    /// Any error sending a chunk is returned.  Lost transfer parameters are
+    /// resent by [`ReadTransfer::handle_timeout()`].
+    pub fn handle_chunk(&mut self, chunk: &Chunk, output: &mut impl ChunkOutput) -> Result<()> {
+        if self.session.state == State::Complete || !self.session.is_for_session(chunk) {
+            return Ok(());
+        }
+        self.session.retries = 0;
+        if self.session.state == State::Initiating && self.session.handle_initial_response(chunk) {
+            let confirmation = self.session.chunk(ChunkType::StartAckConfirmation);
+            return output.send_chunk(&self.with_parameters(confirmation));
+        }
+        if chunk.chunk_type == ChunkType::StartAck {
+            // The server did not receive the confirmation.
+            let confirmation = self.session.chunk(ChunkType::StartAckConfirmation);
+            return output.send_chunk(&self.with_parameters(confirmation));
+        }
+        if self.session.state == State::Terminating {
+            if chunk.chunk_type == ChunkType::CompletionAck {
+                self.session.state = State::Complete;
+            }
+            return Ok(());
+        }
+        if let Some(status) = chunk.status {
+            return self.session.handle_server_status(status, output);
+        }
+        if chunk.chunk_type != ChunkType::Data && self.session.is_version2() {
+            return Ok(());
+        }
+
+        if chunk.offset != self.session.offset {
+            if self.session.state == State::Recovery {
+                // Data sent before the retransmit request is dropped.
+                return Ok(());
+            }
+            self.session.state = State::Recovery;
+            return self.send_parameters(ChunkType::ParametersRetransmit, output);
+        }
+        self.session.state = State::Waiting;
+        if let Err(error) = self.sink.write_all(chunk.data) {
+            return self.session.finish(Err(error), output);
+        }
+        self.session.offset += chunk.data.len() as u64;
+
+        if chunk.remaining_bytes == Some(0) {
+            return self.session.finish(Ok(()), output);
+        }
+        let remaining_window = self.window_end_offset.saturating_sub(self.session.offset);
+        if remaining_window == 0 {
+            self.send_parameters(ChunkType::ParametersRetransmit, output)
+        } else if remaining_window <= u64::from(self.session.config.max_bytes_to_receive / 2) {
+            self.send_parameters(ChunkType::ParametersContinue, output)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Resends the last chunk or asks the server to resend data after it
+    /// has not responded in time.
+    ///
+    /// The transfer fails with [`Error::DeadlineExceeded`] after
+    /// [`TransferConfig::max_retries`] consecutive timeouts.
+    ///
+    /// # Errors
+    /// Any error sending the chunk is returned.
+    pub fn handle_timeout(&mut self, output: &mut impl ChunkOutput) -> Result<()> {
+        if self.session.state == State::Complete || !self.session.retry() {
+            return Ok(());
+        }
+        match self.session.state {
+            State::Initiating => {
+                output.send_chunk(&self.with_parameters(self.session.start_chunk()))
+            }
+            State::Terminating => output.send_chunk(&self.session.completion_chunk()),
+            _ => self.send_parameters(ChunkType::ParametersRetransmit, output),
+        }
+    }
+
+    /// Cancels the transfer and tells the server.
+    pub fn cancel(&mut self, output: &mut impl ChunkOutput) -> Result<()> {
+        if self.session.state == State::Complete {
+            return Ok(());
+        }
+        self.session.finish(Err(Error::Cancelled), output)
+    }
+
+    /// Returns the result of the transfer, or `None` if it is not done.
+    pub fn status(&self) -> Option<Result<()>> {
+        match self.session.state {
+            State::Complete => self.session.status,
+            _ => None,
+        }
+    }
+
+    /// Returns the session ID identifying the transfer's chunks.
+    pub fn session_id(&self) -> u32 {
+        self.session.session_id
+    }
+
+    /// Returns the number of bytes received.
+    pub fn offset(&self) -> u64 {
+        self.session.offset
+    }
+
+    /// Returns the sink the data is written to.
+    pub fn sink(&self) -> &W {
+        &self.sink
+    }
+
+    /// Consumes the transfer and returns the sink.
+    pub fn into_sink(self) -> W {
+        self.sink
+    }
+
+    fn next_window_end(&self) -> u64 {
+        (self.session.offset + u64::from(self.session.config.max_bytes_to_receive))
+            .min(u64::from(u32::MAX))
+    }
+
+    fn with_parameters<'c>(&self, mut chunk: Chunk<'c>) -> Chunk<'c> {
+        chunk.offset = self.session.offset;
+        chunk.window_end_offset = self.window_end_offset as u32;
+        chunk.max_chunk_size_bytes = Some(self.session.config.max_chunk_size_bytes);
+        chunk
+    }
+
+    fn send_parameters(
+        &mut self,
+        chunk_type: ChunkType,
+        output: &mut impl ChunkOutput,
+    ) -> Result<()> {
+        self.window_end_offset = self.next_window_end();
+        output.send_chunk(&self.with_parameters(self.session.chunk(chunk_type)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use pw_stream::Cursor;
+
+    use super::*;
+
+    const V2: ProtocolVersion = ProtocolVersion::Version2;
+
+    // Records the encoded chunks a transfer sends.
+    #[derive(Default)]
+    struct Output {
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl ChunkOutput for Output {
+        fn send_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+            let mut buffer = [0u8; 128];
+            let len = chunk.encode(&mut buffer)?;
+            self.sent.push(buffer[..len].to_vec());
+            Ok(())
+        }
+    }
+
+    impl Output {
+        fn take(&mut self) -> Vec<Vec<u8>> {
+            core::mem::take(&mut self.sent)
+        }
+    }
+
+    fn decode(sent: &[Vec<u8>]) -> Vec<Chunk<'_>> {
+        sent.iter()
+            .map(|chunk| Chunk::decode(chunk).unwrap())
+            .collect()
+    }
+
+    fn parameters(chunk_type: ChunkType, offset: u64, window_end_offset: u32) -> Chunk<'static> {
+        let mut chunk = Chunk::new(V2, chunk_type, 1);
+        chunk.offset = offset;
+        chunk.window_end_offset = window_end_offset;
+        chunk.max_chunk_size_bytes = Some(8);
+        chunk
+    }
+
+    fn data(offset: u64, data: &[u8]) -> Chunk<'_> {
+        let mut chunk = Chunk::new(V2, ChunkType::Data, 1);
+        chunk.offset = offset;
+        chunk.data = data;
+        chunk
+    }
+
+    fn completion(status: Result<()>) -> Chunk<'static> {
+        let mut chunk = Chunk::new(V2, ChunkType::Completion, 1);
+        chunk.status = Some(status);
+        chunk
+    }
+
+    const DATA: &[u8; 20] = b"0123456789abcdefghij";
+
+    // Starts a write transfer of `DATA` and completes the opening handshake.
+    fn start_write(output: &mut Output) -> WriteTransfer<'static> {
+        let mut transfer = WriteTransfer::new(1, 7, DATA, TransferConfig::default());
+        transfer.start(output).unwrap();
+        let start_ack = Chunk::new(V2, ChunkType::StartAck, 1);
+        transfer.handle_chunk(&start_ack, output).unwrap();
+
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].chunk_type, ChunkType::Start);
+        assert_eq!(chunks[0].desired_session_id, Some(1));
+        assert_eq!(chunks[0].resource_id, Some(7));
+        assert_eq!(chunks[1].chunk_type, ChunkType::StartAckConfirmation);
+        assert_eq!(chunks[1].session_id, 1);
+        transfer
+    }
+
+    #[test]
+    fn write_transfer_sends_each_window() {
+        let mut output = Output::default();
+        let mut transfer = start_write(&mut output);
+
+        let window = parameters(ChunkType::ParametersRetransmit, 0, 16);
+        transfer.handle_chunk(&window, &mut output).unwrap();
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!((chunks[0].offset, chunks[0].data), (0, &DATA[..8]));
+        assert_eq!((chunks[1].offset, chunks[1].data), (8, &DATA[8..16]));
+        assert_eq!(chunks[1].remaining_bytes, None);
+        assert_eq!(transfer.offset(), 16);
+
+        let window = parameters(ChunkType::ParametersContinue, 16, 32);
+        transfer.handle_chunk(&window, &mut output).unwrap();
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!((chunks[0].offset, chunks[0].data), (16, &DATA[16..]));
+        assert_eq!(chunks[0].remaining_bytes, Some(0));
+
+        assert_eq!(transfer.status(), None);
+        transfer
+            .handle_chunk(&completion(Ok(())), &mut output)
+            .unwrap();
+        assert_eq!(transfer.status(), Some(Ok(())));
+        let sent = output.take();
+        assert_eq!(decode(&sent)[0].chunk_type, ChunkType::CompletionAck);
+    }
+
+    #[test]
+    fn write_transfer_retransmits_from_requested_offset() {
+        let mut output = Output::default();
+        let mut transfer = start_write(&mut output);
+        let window = parameters(ChunkType::ParametersRetransmit, 0, 16);
+        transfer.handle_chunk(&window, &mut output).unwrap();
+        output.take();
+
+        // The chunk at offset 8 was lost.
+        let window = parameters(ChunkType::ParametersRetransmit, 8, 24);
+        transfer.handle_chunk(&window, &mut output).unwrap();
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!((chunks[0].offset, chunks[0].data), (8, &DATA[8..16]));
+        assert_eq!((chunks[1].offset, chunks[1].data), (16, &DATA[16..]));
+
+        // Stale windows are ignored.
+        let window = parameters(ChunkType::ParametersContinue, 8, 16);
+        transfer.handle_chunk(&window, &mut output).unwrap();
+        assert!(output.take().is_empty());
+    }
+
+    #[test]
+    fn write_transfer_rejects_offset_past_end() {
+        let mut output = Output::default();
+        let mut transfer = start_write(&mut output);
+        let window = parameters(ChunkType::ParametersRetransmit, 21, 32);
+        transfer.handle_chunk(&window, &mut output).unwrap();
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks[0].chunk_type, ChunkType::Completion);
+        assert_eq!(chunks[0].status, Some(Err(Error::OutOfRange)));
+
+        // The transfer is done once the server acknowledges the status.
+        assert_eq!(transfer.status(), None);
+        let ack = Chunk::new(V2, ChunkType::CompletionAck, 1);
+        transfer.handle_chunk(&ack, &mut output).unwrap();
+        assert_eq!(transfer.status(), Some(Err(Error::OutOfRange)));
+    }
+
+    #[test]
+    fn write_transfer_falls_back_to_legacy_protocol() {
+        let mut output = Output::default();
+        let mut transfer = WriteTransfer::new(1, 7, DATA, TransferConfig::default());
+        transfer.start(&mut output).unwrap();
+        output.take();
+
+        // A legacy server responds with transfer parameters for the resource.
+        let mut window = Chunk::new(ProtocolVersion::Legacy, ChunkType::ParametersRetransmit, 7);
+        window.window_end_offset = 64;
+        transfer.handle_chunk(&window, &mut output).unwrap();
+        assert_eq!(transfer.session_id(), 7);
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].protocol_version, ProtocolVersion::Legacy);
+        assert_eq!(chunks[0].session_id, 7);
+        assert_eq!(chunks[0].data, DATA);
+
+        // Legacy transfers end without a closing handshake.
+        let mut status = Chunk::new(ProtocolVersion::Legacy, ChunkType::Completion, 7);
+        status.status = Some(Ok(()));
+        transfer.handle_chunk(&status, &mut output).unwrap();
+        assert_eq!(transfer.status(), Some(Ok(())));
+        assert!(output.take().is_empty());
+    }
+
+    #[test]
+    fn transfer_fails_after_max_retries() {
+        let mut output = Output::default();
+        let mut transfer = WriteTransfer::new(1, 7, DATA, TransferConfig::default());
+        transfer.start(&mut output).unwrap();
+        for _ in 0..3 {
+            transfer.handle_timeout(&mut output).unwrap();
+            assert_eq!(transfer.status(), None);
+        }
+        transfer.handle_timeout(&mut output).unwrap();
+        assert_eq!(transfer.status(), Some(Err(Error::DeadlineExceeded)));
+
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.chunk_type == ChunkType::Start));
+    }
+
+    #[test]
+    fn chunks_for_other_sessions_are_ignored() {
+        let mut output = Output::default();
+        let mut transfer = start_write(&mut output);
+        let mut window = parameters(ChunkType::ParametersRetransmit, 0, 16);
+        window.session_id = 2;
+        transfer.handle_chunk(&window, &mut output).unwrap();
+        assert!(output.take().is_empty());
+    }
+
+    // Starts a read transfer with an 16 byte window and completes the
+    // opening handshake.
+    fn start_read(output: &mut Output, sink_size: usize) -> ReadTransfer<Cursor<Vec<u8>>> {
+        let config = TransferConfig {
+            max_bytes_to_receive: 16,
+            max_chunk_size_bytes: 8,
+            ..TransferConfig::default()
+        };
+        let sink = Cursor::new(std::vec![0u8; sink_size]);
+        let mut transfer = ReadTransfer::new(1, 7, sink, config);
+        transfer.start(output).unwrap();
+        let start_ack = Chunk::new(V2, ChunkType::StartAck, 1);
+        transfer.handle_chunk(&start_ack, output).unwrap();
+
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].chunk_type, ChunkType::Start);
+        assert_eq!(chunks[0].window_end_offset, 16);
+        assert_eq!(chunks[1].chunk_type, ChunkType::StartAckConfirmation);
+        assert_eq!(chunks[1].window_end_offset, 16);
+        assert_eq!(chunks[1].max_chunk_size_bytes, Some(8));
+        transfer
+    }
+
+    #[test]
+    fn read_transfer_extends_window() {
+        let mut output = Output::default();
+        let mut transfer = start_read(&mut output, 32);
+
+        transfer
+            .handle_chunk(&data(0, &DATA[..8]), &mut output)
+            .unwrap();
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].chunk_type, ChunkType::ParametersContinue);
+        assert_eq!((chunks[0].offset, chunks[0].window_end_offset), (8, 24));
+
+        transfer
+            .handle_chunk(&data(8, &DATA[8..16]), &mut output)
+            .unwrap();
+        let mut last = data(16, &DATA[16..]);
+        last.remaining_bytes = Some(0);
+        transfer.handle_chunk(&last, &mut output).unwrap();
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.last().unwrap().chunk_type, ChunkType::Completion);
+        assert_eq!(chunks.last().unwrap().status, Some(Ok(())));
+
+        let ack = Chunk::new(V2, ChunkType::CompletionAck, 1);
+        transfer.handle_chunk(&ack, &mut output).unwrap();
+        assert_eq!(transfer.status(), Some(Ok(())));
+        assert_eq!(transfer.offset(), 20);
+        assert_eq!(&transfer.into_sink().into_inner()[..20], DATA);
+    }
+
+    #[test]
+    fn read_transfer_recovers_lost_data() {
+        let mut output = Output::default();
+        let mut transfer = start_read(&mut output, 32);
+
+        transfer
+            .handle_chunk(&data(8, &DATA[8..16]), &mut output)
+            .unwrap();
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].chunk_type, ChunkType::ParametersRetransmit);
+        assert_eq!(chunks[0].offset, 0);
+
+        // Chunks sent before the server received the request are dropped.
+        transfer
+            .handle_chunk(&data(16, &DATA[16..]), &mut output)
+            .unwrap();
+        assert!(output.take().is_empty());
+
+        transfer
+            .handle_chunk(&data(0, &DATA[..8]), &mut output)
+            .unwrap();
+        assert_eq!(transfer.offset(), 8);
+    }
+
+    #[test]
+    fn read_transfer_timeout_requests_retransmission() {
+        let mut output = Output::default();
+        let mut transfer = start_read(&mut output, 32);
+        transfer
+            .handle_chunk(&data(0, &DATA[..4]), &mut output)
+            .unwrap();
+        transfer.handle_timeout(&mut output).unwrap();
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].chunk_type, ChunkType::ParametersRetransmit);
+        assert_eq!((chunks[0].offset, chunks[0].window_end_offset), (4, 20));
+    }
+
+    #[test]
+    fn read_transfer_ends_when_sink_is_full() {
+        let mut output = Output::default();
+        let mut transfer = start_read(&mut output, 4);
+        transfer
+            .handle_chunk(&data(0, &DATA[..8]), &mut output)
+            .unwrap();
+        let sent = output.take();
+        let chunks = decode(&sent);
+        assert_eq!(chunks[0].chunk_type, ChunkType::Completion);
+        assert_eq!(chunks[0].status, Some(Err(Error::OutOfRange)));
+
+        // The status is kept if the server never acknowledges it.
+        for _ in 0..4 {
+            transfer.handle_timeout(&mut output).unwrap();
+        }
+        assert_eq!(transfer.status(), Some(Err(Error::OutOfRange)));
+    }
+
+    #[test]
+    fn server_status_ends_read_transfer() {
+        let mut output = Output::default();
+        let mut transfer = start_read(&mut output, 4);
+        transfer
+            .handle_chunk(&completion(Err(Error::NotFound)), &mut output)
+            .unwrap();
+        assert_eq!(transfer.status(), Some(Err(Error::NotFound)));
+        let sent = output.take();
+        assert_eq!(decode(&sent)[0].chunk_type, ChunkType::CompletionAck);
+    }
+}
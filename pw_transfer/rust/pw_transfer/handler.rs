@@ -0,0 +1,292 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_status::{Error, Result};
+use pw_stream::{Read, Seek, SeekFrom, Write};
+
+/// A resource which clients can read or write through a
+/// [`crate::TransferService`], matching the C++ `pw::transfer::Handler`.
+///
+/// A handler is used by at most one transfer at a time.  Each transfer
+/// starts with [`Handler::prepare_read()`] or [`Handler::prepare_write()`]
+/// and ends with the matching finalize call, which receives the transfer's
+/// status.  Handlers only support the directions they implement the
+/// prepare calls of.
+pub trait Handler {
+    /// Returns the ID clients transfer the resource with.
+    fn resource_id(&self) -> u32;
+
+    /// Prepares the resource to be read from its start.
+    ///
+    /// # Errors
+    /// Any error rejects the transfer with the error.  By default, reads are
+    /// rejected with [`Error::PermissionDenied`].
+    fn prepare_read(&mut self) -> Result<()> {
+        Err(Error::PermissionDenied)
+    }
+
+    /// Reads the resource's data at `offset` into `buffer` and returns the
+    /// number of bytes read, which is less than `buffer.len()` only at the
+    /// end of the resource.
+    ///
+    /// Offsets may go backwards when a client asks for lost data to be
+    /// resent.
+    ///
+    /// # Errors
+    /// Any error ends the transfer with the error.
+    fn read(&mut self, _offset: u64, _buffer: &mut [u8]) -> Result<usize> {
+        Err(Error::PermissionDenied)
+    }
+
+    /// Called when a read transfer ends with `status`.
+    fn finalize_read(&mut self, _status: Result<()>) {}
+
+    /// Prepares the resource to be written from its start.
+    ///
+    /// # Errors
+    /// Any error rejects the transfer with the error.  By default, writes
+    /// are rejected with [`Error::PermissionDenied`].
+    fn prepare_write(&mut self) -> Result<()> {
+        Err(Error::PermissionDenied)
+    }
+
+    /// Writes `data` to the resource at `offset`.
+    ///
+    /// Offsets may go backwards when the client resends data which was
+    /// lost.
+    ///
+    /// # Errors
+    /// Any error ends the transfer with the error.
+    fn write(&mut self, _offset: u64, _data: &[u8]) -> Result<()> {
+        Err(Error::PermissionDenied)
+    }
+
+    /// Called when a write transfer ends with `status`.  Returns the final
+    /// status of the transfer, which lets the handler reject the data, for
+    /// example if an image fails verification.
+    fn finalize_write(&mut self, status: Result<()>) -> Result<()> {
+        status
+    }
+}
+
+// Reads from `offset` until `buffer` is full or the stream ends.
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut len = 0;
+    while len < buffer.len() {
+        match reader.read(&mut buffer[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+    Ok(len)
+}
+
+fn write_at<W: Write + Seek>(writer: &mut W, offset: u64, data: &[u8]) -> Result<()> {
+    writer.seek(SeekFrom::Start(offset))?;
+    writer.write_all(data)
+}
+
+/// A [`Handler`] for a resource which clients can only read, such as a log
+/// or crash dump, backed by a `pw_stream` reader.
+pub struct ReadOnlyHandler<R: Read + Seek> {
+    resource_id: u32,
+    reader: R,
+}
+
+impl<R: Read + Seek> ReadOnlyHandler<R> {
+    /// Creates a handler for resource `resource_id` which reads `reader`.
+    pub const fn new(resource_id: u32, reader: R) -> Self {
+        Self {
+            resource_id,
+            reader,
+        }
+    }
+
+    /// Returns a reference to the reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Consumes the handler and returns the reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read + Seek> Handler for ReadOnlyHandler<R> {
+    fn resource_id(&self) -> u32 {
+        self.resource_id
+    }
+
+    fn prepare_read(&mut self) -> Result<()> {
+        self.reader.rewind()
+    }
+
+    fn read(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        read_at(&mut self.reader, offset, buffer)
+    }
+}
+
+/// A [`Handler`] for a resource which clients can only write, such as an
+/// update payload, backed by a `pw_stream` writer.
+pub struct WriteOnlyHandler<W: Write + Seek> {
+    resource_id: u32,
+    writer: W,
+}
+
+impl<W: Write + Seek> WriteOnlyHandler<W> {
+    /// Creates a handler for resource `resource_id` which writes to
+    /// `writer`.
+    pub const fn new(resource_id: u32, writer: W) -> Self {
+        Self {
+            resource_id,
+            writer,
+        }
+    }
+
+    /// Returns a reference to the writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Consumes the handler and returns the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Seek> Handler for WriteOnlyHandler<W> {
+    fn resource_id(&self) -> u32 {
+        self.resource_id
+    }
+
+    fn prepare_write(&mut self) -> Result<()> {
+        self.writer.rewind()
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        write_at(&mut self.writer, offset, data)
+    }
+
+    fn finalize_write(&mut self, status: Result<()>) -> Result<()> {
+        status?;
+        self.writer.flush()
+    }
+}
+
+/// A [`Handler`] for a resource which clients can read and write, backed by
+/// a `pw_stream` stream.
+pub struct ReadWriteHandler<S: Read + Write + Seek> {
+    resource_id: u32,
+    stream: S,
+}
+
+impl<S: Read + Write + Seek> ReadWriteHandler<S> {
+    /// Creates a handler for resource `resource_id` which reads and writes
+    /// `stream`.
+    pub const fn new(resource_id: u32, stream: S) -> Self {
+        Self {
+            resource_id,
+            stream,
+        }
+    }
+
+    /// Returns a reference to the stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Consumes the handler and returns the stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read + Write + Seek> Handler for ReadWriteHandler<S> {
+    fn resource_id(&self) -> u32 {
+        self.resource_id
+    }
+
+    fn prepare_read(&mut self) -> Result<()> {
+        self.stream.rewind()
+    }
+
+    fn read(&mut self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        read_at(&mut self.stream, offset, buffer)
+    }
+
+    fn prepare_write(&mut self) -> Result<()> {
+        self.stream.rewind()
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        write_at(&mut self.stream, offset, data)
+    }
+
+    fn finalize_write(&mut self, status: Result<()>) -> Result<()> {
+        status?;
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_stream::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn read_only_handler_reads_at_offsets() {
+        let mut handler = ReadOnlyHandler::new(3, Cursor::new(*b"0123456789"));
+        assert_eq!(handler.resource_id(), 3);
+        assert_eq!(handler.prepare_read(), Ok(()));
+        assert_eq!(handler.prepare_write(), Err(Error::PermissionDenied));
+
+        let mut buffer = [0u8; 4];
+        assert_eq!(handler.read(4, &mut buffer), Ok(4));
+        assert_eq!(&buffer, b"4567");
+        assert_eq!(handler.read(0, &mut buffer), Ok(4));
+        assert_eq!(&buffer, b"0123");
+        assert_eq!(handler.read(8, &mut buffer), Ok(2));
+        assert_eq!(&buffer[..2], b"89");
+    }
+
+    #[test]
+    fn write_only_handler_writes_at_offsets() {
+        let mut handler = WriteOnlyHandler::new(3, Cursor::new([0u8; 8]));
+        assert_eq!(handler.prepare_read(), Err(Error::PermissionDenied));
+        assert_eq!(handler.prepare_write(), Ok(()));
+        assert_eq!(handler.write(4, b"4567"), Ok(()));
+        assert_eq!(handler.write(0, b"0123"), Ok(()));
+        assert_eq!(handler.write(6, b"678"), Err(Error::OutOfRange));
+        assert_eq!(handler.finalize_write(Ok(())), Ok(()));
+        assert_eq!(&handler.into_inner().into_inner(), b"01234567");
+    }
+
+    #[test]
+    fn read_write_handler_supports_both_directions() {
+        let mut handler = ReadWriteHandler::new(3, Cursor::new([0u8; 4]));
+        assert_eq!(handler.prepare_write(), Ok(()));
+        assert_eq!(handler.write(0, b"abcd"), Ok(()));
+        assert_eq!(
+            handler.finalize_write(Err(Error::Aborted)),
+            Err(Error::Aborted)
+        );
+        assert_eq!(handler.prepare_read(), Ok(()));
+        let mut buffer = [0u8; 8];
+        assert_eq!(handler.read(1, &mut buffer), Ok(3));
+        assert_eq!(&buffer[..3], b"bcd");
+    }
+}
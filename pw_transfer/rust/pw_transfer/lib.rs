@@ -0,0 +1,94 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_transfer` implements Pigweed's transfer protocol, compatible with the
+//! C++ and Python transfer clients and services, for reliably moving large
+//! resources such as firmware images and crash logs over RPC.
+//!
+//! Data is sent in [`Chunk`]s over the `Read` and `Write` bidirectional
+//! streaming methods of the `pw.transfer.Transfer` service.  The receiver
+//! grants the sender windows of data with transfer parameters and asks for
+//! data to be retransmitted when chunks are lost.  Transfers use version 2
+//! of the protocol, with session IDs and opening and closing handshakes, and
+//! fall back to the legacy protocol for servers which do not support it.
+//!
+//! Devices serve resources with a [`TransferService`], which passes the
+//! data of each transfer to the resource's [`Handler`].  Handlers for
+//! resources backed by `pw_stream` streams, such as an update partition or
+//! a crash log, are provided.
+//!
+//! On the client side, [`WriteTransfer`] sends data to a resource on the server and
+//! [`ReadTransfer`] receives data from one.  Transfers do no I/O themselves:
+//! the caller opens the RPC call, passes each chunk the server sends to the
+//! transfer, and tells it when the server has not responded in time so it
+//! can retry.  Chunks are sent to a [`ChunkOutput`], such as an
+//! [`RpcChunkOutput`] which writes them to the call:
+//!
+//! ```
+//! use pw_rpc::{Channel, Client, ClientEvent};
+//! use pw_transfer::{
+//!     Chunk, RpcChunkOutput, TransferConfig, WriteTransfer, SERVICE_ID, WRITE_METHOD_ID,
+//! };
+//!
+//! # fn main() -> pw_status::Result<()> {
+//! # let mut channel_output = |_: &[u8]| Ok(());
+//! let mut channels = [Channel::new(1, &mut channel_output)];
+//! let mut packet_buffer = [0u8; 1100];
+//! let mut client = Client::<4>::new(&mut channels, &mut packet_buffer);
+//! let call = client.invoke(1, SERVICE_ID, WRITE_METHOD_ID, &[])?;
+//!
+//! let image = [0u8; 4096];
+//! let mut chunk_buffer = [0u8; 1100];
+//! let mut transfer = WriteTransfer::new(1, 3, &image, TransferConfig::default());
+//! transfer.start(&mut RpcChunkOutput::new(&mut client, call, &mut chunk_buffer))?;
+//!
+//! # let received: [&[u8]; 0] = [];
+//! // For each packet received from the device:
+//! for packet in received {
+//!     if let Some(ClientEvent::Stream { call: c, payload }) = client.process_packet(packet)? {
+//!         if c == call {
+//!             let mut output = RpcChunkOutput::new(&mut client, call, &mut chunk_buffer);
+//!             transfer.handle_chunk(&Chunk::decode(payload)?, &mut output)?;
+//!         }
+//!     }
+//!     if transfer.status().is_some() {
+//!         break;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+mod chunk;
+mod client;
+mod handler;
+mod service;
+
+pub use chunk::{Chunk, ChunkType, ProtocolVersion, MAX_CHUNK_OVERHEAD};
+pub use client::{ChunkOutput, ReadTransfer, RpcChunkOutput, TransferConfig, WriteTransfer};
+pub use handler::{Handler, ReadOnlyHandler, ReadWriteHandler, WriteOnlyHandler};
+pub use service::TransferService;
+
+/// The ID of the `pw.transfer.Transfer` service.
+pub const SERVICE_ID: u32 = pw_rpc::id("pw.transfer.Transfer");
+
+/// The ID of the transfer service's `Read` method, which streams chunks of
+/// resources read by the client.
+pub const READ_METHOD_ID: u32 = pw_rpc::id("Read");
+
+/// The ID of the transfer service's `Write` method, which streams chunks of
+/// resources written by the client.
+pub const WRITE_METHOD_ID: u32 = pw_rpc::id("Write");
@@ -0,0 +1,901 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+use pw_rpc::{Call, MethodType, Responder, Service};
+use pw_status::{Error, Result};
+
+use crate::chunk::{Chunk, ChunkType, ProtocolVersion, MAX_CHUNK_OVERHEAD};
+use crate::client::ChunkOutput;
+use crate::handler::Handler;
+use crate::{READ_METHOD_ID, SERVICE_ID, WRITE_METHOD_ID};
+
+// Sends chunks as server stream messages of a call.
+struct ResponderOutput<'o, 'b, 'r, 'a> {
+    responder: &'o mut Responder<'r, 'a>,
+    buffer: &'b mut [u8],
+}
+
+impl ChunkOutput for ResponderOutput<'_, '_, '_, '_> {
+    fn send_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        let len = chunk.encode(self.buffer)?;
+        self.responder.write(&self.buffer[..len])
+    }
+}
+
+// Which way a transfer's data flows, named from the client's side like the
+// service's methods.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    // The start was acknowledged and the client has not confirmed it.
+    Initiating,
+    Active,
+    // A write transfer received data at the wrong offset and asked the
+    // client to retransmit it.
+    Recovery,
+    // The final status was sent and the client has not acknowledged it.
+    Terminating,
+    // A legacy transfer sent its final status.
+    Completed,
+}
+
+// The limits of the chunks a service sends and receives.
+#[derive(Clone, Copy)]
+struct Limits {
+    max_chunk_size_bytes: u32,
+    max_window_size_bytes: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Transfer {
+    call: Call,
+    direction: Direction,
+    handler: usize,
+    protocol_version: ProtocolVersion,
+    session_id: u32,
+    state: State,
+    status: Result<()>,
+    offset: u64,
+    window_end_offset: u64,
+    // The offset of the last unexpected chunk of a transfer in recovery.
+    recovery_offset: u64,
+    max_chunk_size: u32,
+}
+
+impl Transfer {
+    fn chunk(&self, chunk_type: ChunkType) -> Chunk<'static> {
+        Chunk::new(self.protocol_version, chunk_type, self.session_id)
+    }
+
+    fn completion_chunk(&self) -> Chunk<'static> {
+        let mut chunk = self.chunk(ChunkType::Completion);
+        chunk.status = Some(self.status);
+        chunk
+    }
+
+    // Processes a chunk from the client.  Returns true when the transfer is
+    // done.
+    fn handle_chunk(
+        &mut self,
+        chunk: &Chunk,
+        handler: &mut dyn Handler,
+        limits: Limits,
+        buffer: &mut [u8],
+        output: &mut impl ChunkOutput,
+    ) -> bool {
+        // Errors sending chunks are not handled: the client retries when
+        // chunks are lost.
+        if self.state == State::Terminating {
+            if chunk.chunk_type == ChunkType::CompletionAck {
+                return true;
+            }
+            let _ = output.send_chunk(&self.completion_chunk());
+            return false;
+        }
+        if self.state == State::Completed {
+            if chunk.status.is_none() {
+                let _ = output.send_chunk(&self.completion_chunk());
+            }
+            return false;
+        }
+        if let Some(status) = chunk.status {
+            // The client ended the transfer.
+            self.finalize(handler, status);
+            if self.protocol_version == ProtocolVersion::Version2 {
+                let _ = output.send_chunk(&self.chunk(ChunkType::CompletionAck));
+            }
+            return true;
+        }
+        if self.state == State::Initiating {
+            // A read client which did not receive a response to its
+            // confirmation retries with the same transfer parameters.
+            let confirmed = chunk.chunk_type == ChunkType::StartAckConfirmation
+                || (self.direction == Direction::Read
+                    && chunk.chunk_type == ChunkType::ParametersRetransmit);
+            if !confirmed {
+                return false;
+            }
+            self.state = State::Active;
+        }
+        match self.direction {
+            Direction::Read => self.handle_parameters(chunk, handler, limits, buffer, output),
+            Direction::Write => self.handle_data(chunk, handler, limits, output),
+        }
+    }
+
+    // Sends the data a read transfer's client asked for.
+    fn handle_parameters(
+        &mut self,
+        chunk: &Chunk,
+        handler: &mut dyn Handler,
+        limits: Limits,
+        buffer: &mut [u8],
+        output: &mut impl ChunkOutput,
+    ) -> bool {
+        if !matches!(
+            chunk.chunk_type,
+            ChunkType::ParametersRetransmit
+                | ChunkType::ParametersContinue
+                | ChunkType::Start
+                | ChunkType::StartAckConfirmation
+        ) {
+            return false;
+        }
+        if chunk.requests_transmission_from_offset() {
+            self.offset = chunk.offset;
+        }
+        self.window_end_offset = u64::from(chunk.window_end_offset);
+        if let Some(max_chunk_size) = chunk.max_chunk_size_bytes {
+            self.max_chunk_size = max_chunk_size.min(limits.max_chunk_size_bytes);
+        }
+
+        let max_chunk_size = u64::from(self.max_chunk_size.max(1));
+        while self.offset < self.window_end_offset {
+            let len = max_chunk_size.min(self.window_end_offset - self.offset) as usize;
+            let data = &mut buffer[..len];
+            let read = match handler.read(self.offset, data) {
+                Ok(read) => read.min(len),
+                Err(error) => return self.finish(handler, Err(error), output),
+            };
+            let mut data_chunk = self.chunk(ChunkType::Data);
+            data_chunk.offset = self.offset;
+            data_chunk.data = &data[..read];
+            if read < len {
+                data_chunk.remaining_bytes = Some(0);
+            }
+            let _ = output.send_chunk(&data_chunk);
+            self.offset += read as u64;
+            if read < len {
+                break;
+            }
+        }
+        false
+    }
+
+    // Writes the data of a write transfer and asks the client for more.
+    fn handle_data(
+        &mut self,
+        chunk: &Chunk,
+        handler: &mut dyn Handler,
+        limits: Limits,
+        output: &mut impl ChunkOutput,
+    ) -> bool {
+        if chunk.chunk_type == ChunkType::StartAckConfirmation {
+            self.send_parameters(ChunkType::ParametersRetransmit, limits, output);
+            return false;
+        }
+        if chunk.chunk_type != ChunkType::Data && self.protocol_version == ProtocolVersion::Version2
+        {
+            return false;
+        }
+        if chunk.offset != self.offset {
+            // Once in recovery, the chunks the client sent before it received
+            // the request have increasing offsets and are dropped.  A chunk
+            // which does not advance is a retry by the client, which has
+            // not received the request.
+            if self.state != State::Recovery || chunk.offset <= self.recovery_offset {
+                self.state = State::Recovery;
+                self.send_parameters(ChunkType::ParametersRetransmit, limits, output);
+            }
+            self.recovery_offset = chunk.offset;
+            return false;
+        }
+        self.state = State::Active;
+        if let Err(error) = handler.write(self.offset, chunk.data) {
+            return self.finish(handler, Err(error), output);
+        }
+        self.offset += chunk.data.len() as u64;
+
+        if chunk.remaining_bytes == Some(0) {
+            return self.finish(handler, Ok(()), output);
+        }
+        let remaining_window = self.window_end_offset.saturating_sub(self.offset);
+        if remaining_window == 0 {
+            self.send_parameters(ChunkType::ParametersRetransmit, limits, output);
+        } else if remaining_window <= u64::from(limits.max_window_size_bytes / 2) {
+            self.send_parameters(ChunkType::ParametersContinue, limits, output);
+        }
+        false
+    }
+
+    fn send_parameters(
+        &mut self,
+        chunk_type: ChunkType,
+        limits: Limits,
+        output: &mut impl ChunkOutput,
+    ) {
+        self.window_end_offset =
+            (self.offset + u64::from(limits.max_window_size_bytes)).min(u64::from(u32::MAX));
+        let mut chunk = self.chunk(chunk_type);
+        chunk.offset = self.offset;
+        chunk.window_end_offset = self.window_end_offset as u32;
+        chunk.max_chunk_size_bytes = Some(limits.max_chunk_size_bytes);
+        let _ = output.send_chunk(&chunk);
+    }
+
+    fn finalize(&mut self, handler: &mut dyn Handler, status: Result<()>) {
+        self.status = match self.direction {
+            Direction::Read => {
+                handler.finalize_read(status);
+                status
+            }
+            Direction::Write => handler.finalize_write(status),
+        };
+    }
+
+    // Ends the transfer with `status` and sends the final status to the
+    // client.
+    fn finish(
+        &mut self,
+        handler: &mut dyn Handler,
+        status: Result<()>,
+        output: &mut impl ChunkOutput,
+    ) -> bool {
+        self.finalize(handler, status);
+        let _ = output.send_chunk(&self.completion_chunk());
+        self.state = if self.protocol_version == ProtocolVersion::Version2 {
+            State::Terminating
+        } else {
+            State::Completed
+        };
+        false
+    }
+
+    // Returns false once the handler is finalized.  Finished transfers are
+    // kept to answer the client until their slot is needed, since the
+    // service has no timeouts to discard them.
+    fn is_active(&self) -> bool {
+        !matches!(self.state, State::Terminating | State::Completed)
+    }
+}
+
+/// The standard `pw.transfer.Transfer` service, which lets clients read and
+/// write the resources of a set of [`Handler`]s.
+///
+/// The service is compatible with the C++, Python, and Rust transfer
+/// clients, using version 2 of the protocol or the legacy protocol as the
+/// client requests.  Up to `MAX_TRANSFERS` transfers may be active at once,
+/// each on a different handler.  The service does not track time: lost
+/// chunks are recovered when the client times out and retries.
+///
+/// The `Read` and `Write` calls are deferred while clients transfer data,
+/// so the server needs a free deferred call for each.
+///
+/// ```
+/// use pw_rpc::{Channel, Server, Service};
+/// use pw_stream::Cursor;
+/// use pw_transfer::{Handler, ReadOnlyHandler, TransferService, WriteOnlyHandler};
+///
+/// let mut crash_log = ReadOnlyHandler::new(1, Cursor::new([0u8; 256]));
+/// let mut update = WriteOnlyHandler::new(2, Cursor::new([0u8; 1024]));
+/// let mut handlers: [&mut dyn Handler; 2] = [&mut crash_log, &mut update];
+/// let mut chunk_buffer = [0u8; 64];
+/// let mut encoding_buffer = [0u8; 128];
+/// let mut transfer = TransferService::<2>::new(
+///     &mut handlers,
+///     &mut chunk_buffer,
+///     &mut encoding_buffer,
+///     256,
+/// );
+///
+/// # let mut output = |_: &[u8]| Ok(());
+/// let mut channels = [Channel::new(1, &mut output)];
+/// let mut services: [&mut dyn Service; 1] = [&mut transfer];
+/// let mut buffer = [0u8; 192];
+/// let mut server = Server::<2>::new(&mut channels, &mut services, &mut buffer);
+/// ```
+pub struct TransferService<'h, const MAX_TRANSFERS: usize> {
+    handlers: &'h mut [&'h mut dyn Handler],
+    chunk_buffer: &'h mut [u8],
+    encoding_buffer: &'h mut [u8],
+    limits: Limits,
+    transfers: [Option<Transfer>; MAX_TRANSFERS],
+}
+
+impl<'h, const MAX_TRANSFERS: usize> TransferService<'h, MAX_TRANSFERS> {
+    /// Creates a service for the resources of `handlers`.
+    ///
+    /// The data of read transfers is staged in `chunk_buffer`, whose size is
+    /// the largest chunk the service sends or asks clients for.  Chunks are
+    /// encoded in `encoding_buffer`, which should be [`MAX_CHUNK_OVERHEAD`]
+    /// bytes larger.  Clients writing resources are asked for windows of
+    /// `max_window_size_bytes` bytes.
+    pub fn new(
+        handlers: &'h mut [&'h mut dyn Handler],
+        chunk_buffer: &'h mut [u8],
+        encoding_buffer: &'h mut [u8],
+        max_window_size_bytes: u32,
+    ) -> Self {
+        let max_chunk_size = chunk_buffer
+            .len()
+            .min(encoding_buffer.len().saturating_sub(MAX_CHUNK_OVERHEAD));
+        Self {
+            handlers,
+            chunk_buffer,
+            encoding_buffer,
+            limits: Limits {
+                max_chunk_size_bytes: u32::try_from(max_chunk_size).unwrap_or(u32::MAX),
+                max_window_size_bytes,
+            },
+            transfers: [None; MAX_TRANSFERS],
+        }
+    }
+
+    /// Returns the number of active transfers.
+    pub fn active_transfers(&self) -> usize {
+        self.transfers
+            .iter()
+            .flatten()
+            .filter(|transfer| transfer.is_active())
+            .count()
+    }
+
+    fn handle_chunk(
+        &mut self,
+        call: Call,
+        direction: Direction,
+        chunk: &Chunk,
+        output: &mut impl ChunkOutput,
+    ) {
+        if chunk.chunk_type == ChunkType::Start {
+            self.start(call, direction, chunk, output);
+            return;
+        }
+        let Some(slot) = self.find(direction, chunk.session_id) else {
+            // Tell the client the transfer is unknown, unless the client is
+            // ending it.
+            if chunk.status.is_none() && chunk.chunk_type != ChunkType::CompletionAck {
+                let mut status = Chunk::new(
+                    chunk.protocol_version,
+                    ChunkType::Completion,
+                    chunk.session_id,
+                );
+                status.status = Some(Err(Error::FailedPrecondition));
+                let _ = output.send_chunk(&status);
+            }
+            return;
+        };
+        let Some(transfer) = self.transfers[slot].as_mut() else {
+            return;
+        };
+        let done = transfer.handle_chunk(
+            chunk,
+            &mut *self.handlers[transfer.handler],
+            self.limits,
+            self.chunk_buffer,
+            output,
+        );
+        if done {
+            self.transfers[slot] = None;
+        }
+    }
+
+    fn start(
+        &mut self,
+        call: Call,
+        direction: Direction,
+        chunk: &Chunk,
+        output: &mut impl ChunkOutput,
+    ) {
+        let (protocol_version, session_id) = match chunk.desired_session_id {
+            Some(desired_session_id) if chunk.protocol_version >= ProtocolVersion::Version2 => {
+                (ProtocolVersion::Version2, desired_session_id)
+            }
+            _ => (ProtocolVersion::Legacy, chunk.session_id),
+        };
+        let resource_id = chunk.resource_id.unwrap_or(chunk.session_id);
+
+        if let Some(slot) = self.find(direction, session_id) {
+            if let Some(transfer) = self.transfers[slot].as_mut() {
+                if protocol_version == ProtocolVersion::Version2 && transfer.is_active() {
+                    // The client did not receive the acknowledgement.
+                    if transfer.state == State::Initiating {
+                        let _ = output.send_chunk(&start_ack(transfer, resource_id));
+                    }
+                    return;
+                }
+                // A legacy client restarts the transfer, and finished
+                // transfers are replaced.
+                if transfer.is_active() {
+                    transfer.finalize(&mut *self.handlers[transfer.handler], Err(Error::Aborted));
+                }
+            }
+            self.transfers[slot] = None;
+        }
+
+        let mut transfer = Transfer {
+            call,
+            direction,
+            handler: 0,
+            protocol_version,
+            session_id,
+            state: State::Active,
+            status: Ok(()),
+            offset: 0,
+            window_end_offset: 0,
+            recovery_offset: 0,
+            max_chunk_size: self.limits.max_chunk_size_bytes,
+        };
+        let slot = match self.prepare(&mut transfer, resource_id) {
+            Ok(slot) => slot,
+            Err(error) => {
+                transfer.status = Err(error);
+                let _ = output.send_chunk(&transfer.completion_chunk());
+                return;
+            }
+        };
+
+        if protocol_version == ProtocolVersion::Version2 {
+            transfer.state = State::Initiating;
+            let _ = output.send_chunk(&start_ack(&transfer, resource_id));
+        } else if direction == Direction::Write {
+            transfer.send_parameters(ChunkType::ParametersRetransmit, self.limits, output);
+        } else if transfer.handle_chunk(
+            chunk,
+            &mut *self.handlers[transfer.handler],
+            self.limits,
+            self.chunk_buffer,
+            output,
+        ) {
+            // Legacy read starts carry the client's transfer parameters.
+            return;
+        }
+        self.transfers[slot] = Some(transfer);
+    }
+
+    // Finds a free slot and the handler for a new transfer and prepares the
+    // handler.
+    fn prepare(&mut self, transfer: &mut Transfer, resource_id: u32) -> Result<usize> {
+        let handler = self
+            .handlers
+            .iter()
+            .position(|handler| handler.resource_id() == resource_id)
+            .ok_or(Error::NotFound)?;
+        if self
+            .transfers
+            .iter()
+            .flatten()
+            .any(|active| active.is_active() && active.handler == handler)
+        {
+            return Err(Error::Unavailable);
+        }
+        // Finished transfers are replaced once the free slots are used.
+        let slot = self
+            .transfers
+            .iter()
+            .position(Option::is_none)
+            .or_else(|| {
+                self.transfers
+                    .iter()
+                    .position(|slot| slot.is_some_and(|transfer| !transfer.is_active()))
+            })
+            .ok_or(Error::ResourceExhausted)?;
+        match transfer.direction {
+            Direction::Read => self.handlers[handler].prepare_read()?,
+            Direction::Write => self.handlers[handler].prepare_write()?,
+        }
+        transfer.handler = handler;
+        Ok(slot)
+    }
+
+    fn find(&self, direction: Direction, session_id: u32) -> Option<usize> {
+        self.transfers.iter().position(|transfer| {
+            transfer.as_ref().is_some_and(|transfer| {
+                transfer.direction == direction && transfer.session_id == session_id
+            })
+        })
+    }
+
+    // Ends the transfers on `call` without telling the client.
+    fn abort(&mut self, call: &Call) {
+        for slot in &mut self.transfers {
+            if let Some(transfer) = slot.as_mut().filter(|transfer| transfer.call == *call) {
+                if transfer.is_active() {
+                    transfer.finalize(&mut *self.handlers[transfer.handler], Err(Error::Aborted));
+                }
+                *slot = None;
+            }
+        }
+    }
+}
+
+fn start_ack(transfer: &Transfer, resource_id: u32) -> Chunk<'static> {
+    let mut chunk = transfer.chunk(ChunkType::StartAck);
+    chunk.resource_id = Some(resource_id);
+    chunk
+}
+
+impl<const MAX_TRANSFERS: usize> Service for TransferService<'_, MAX_TRANSFERS> {
+    fn id(&self) -> u32 {
+        SERVICE_ID
+    }
+
+    fn method_type(&self, method_id: u32) -> Option<MethodType> {
+        matches!(method_id, READ_METHOD_ID | WRITE_METHOD_ID)
+            .then_some(MethodType::BidirectionalStreaming)
+    }
+
+    fn invoke(&mut self, _request: &[u8], responder: &mut Responder<'_, '_>) {
+        // A repeated request replaces the call and ends its transfers.
+        self.abort(&responder.call());
+        if let Err(error) = responder.defer() {
+            let _ = responder.finish(&[], Err(error));
+        }
+    }
+
+    fn client_stream(&mut self, request: &[u8], responder: &mut Responder<'_, '_>) {
+        // Chunks which can not be decoded are dropped, as if they were lost.
+        let Ok(chunk) = Chunk::decode(request) else {
+            return;
+        };
+        let call = responder.call();
+        let direction = if call.method_id() == READ_METHOD_ID {
+            Direction::Read
+        } else {
+            Direction::Write
+        };
+        // The encoding buffer is moved out while the transfers use the
+        // rest of the service.
+        let buffer = core::mem::take(&mut self.encoding_buffer);
+        let mut output = ResponderOutput { responder, buffer };
+        self.handle_chunk(call, direction, &chunk, &mut output);
+        self.encoding_buffer = output.buffer;
+    }
+
+    fn client_stream_end(&mut self, responder: &mut Responder<'_, '_>) {
+        self.abort(&responder.call());
+        let _ = responder.finish(&[], Ok(()));
+    }
+
+    fn cancel(&mut self, call: &Call) {
+        self.abort(call);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::RefCell;
+    use std::vec::Vec;
+
+    use pw_rpc::{Channel, Packet, PacketType, Server};
+    use pw_stream::{Cursor, Write};
+
+    use super::*;
+    use crate::{ReadOnlyHandler, ReadTransfer, TransferConfig, WriteOnlyHandler, WriteTransfer};
+
+    // The client side of a read or write transfer.
+    trait ClientTransfer {
+        fn start(&mut self, output: &mut impl ChunkOutput) -> Result<()>;
+        fn handle_chunk(&mut self, chunk: &Chunk, output: &mut impl ChunkOutput) -> Result<()>;
+        fn handle_timeout(&mut self, output: &mut impl ChunkOutput) -> Result<()>;
+        fn status(&self) -> Option<Result<()>>;
+    }
+
+    macro_rules! impl_client_transfer {
+        ($($generics:tt)*) => {
+            impl $($generics)* {
+                fn start(&mut self, output: &mut impl ChunkOutput) -> Result<()> {
+                    Self::start(self, output)
+                }
+                fn handle_chunk(
+                    &mut self,
+                    chunk: &Chunk,
+                    output: &mut impl ChunkOutput,
+                ) -> Result<()> {
+                    Self::handle_chunk(self, chunk, output)
+                }
+                fn handle_timeout(&mut self, output: &mut impl ChunkOutput) -> Result<()> {
+                    Self::handle_timeout(self, output)
+                }
+                fn status(&self) -> Option<Result<()>> {
+                    Self::status(self)
+                }
+            }
+        };
+    }
+
+    impl_client_transfer!(ClientTransfer for WriteTransfer<'_>);
+    impl_client_transfer!(<W: Write> ClientTransfer for ReadTransfer<W>);
+
+    // Records the encoded chunks a client sends.
+    #[derive(Default)]
+    struct Chunks(Vec<Vec<u8>>);
+
+    impl ChunkOutput for Chunks {
+        fn send_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+            let mut buffer = [0u8; 128];
+            let len = chunk.encode(&mut buffer)?;
+            self.0.push(buffer[..len].to_vec());
+            Ok(())
+        }
+    }
+
+    // Runs `transfer` against a transfer service for `handler` until it is
+    // done.  `lose` is called with the index of each chunk sent in either
+    // direction and drops it if it returns true.  Returns the number of
+    // active transfers the service has afterwards.
+    fn run(
+        method_id: u32,
+        handler: &mut dyn Handler,
+        transfer: &mut impl ClientTransfer,
+        mut lose: impl FnMut(usize) -> bool,
+    ) -> usize {
+        let responses = RefCell::new(Vec::new());
+        let mut output = |packet: &[u8]| -> Result<()> {
+            responses.borrow_mut().push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut handlers: [&mut dyn Handler; 1] = [handler];
+        let mut chunk_buffer = [0u8; 16];
+        let mut encoding_buffer = [0u8; 16 + MAX_CHUNK_OVERHEAD];
+        let mut service =
+            TransferService::<1>::new(&mut handlers, &mut chunk_buffer, &mut encoding_buffer, 40);
+        let mut services: [&mut dyn Service; 1] = [&mut service];
+        let mut buffer = [0u8; 256];
+        let mut server = Server::<1>::new(&mut channels, &mut services, &mut buffer);
+
+        let mut packet = [0u8; 256];
+        let mut send = |server: &mut Server<1>, packet_type, payload: &[u8]| {
+            let len = Packet::new(packet_type, 1, SERVICE_ID, method_id, 1)
+                .with_payload(payload)
+                .encode(&mut packet)
+                .unwrap();
+            server.process_packet(&packet[..len]).unwrap();
+        };
+        send(&mut server, PacketType::Request, &[]);
+
+        let mut to_server = Chunks::default();
+        let mut sent = 0;
+        transfer.start(&mut to_server).unwrap();
+        for _ in 0..1000 {
+            let requests = core::mem::take(&mut to_server.0);
+            let packets = core::mem::take(&mut *responses.borrow_mut());
+            for request in &requests {
+                sent += 1;
+                if !lose(sent) {
+                    send(&mut server, PacketType::ClientStream, request);
+                }
+            }
+            for response in &packets {
+                let packet = Packet::decode(response).unwrap();
+                assert_eq!(packet.packet_type, PacketType::ServerStream);
+                sent += 1;
+                if !lose(sent) {
+                    let chunk = Chunk::decode(packet.payload).unwrap();
+                    transfer.handle_chunk(&chunk, &mut to_server).unwrap();
+                }
+            }
+            if to_server.0.is_empty() && responses.borrow().is_empty() {
+                if transfer.status().is_some() {
+                    return service.active_transfers();
+                }
+                transfer.handle_timeout(&mut to_server).unwrap();
+            }
+        }
+        panic!("transfer did not finish");
+    }
+
+    const DATA: &[u8; 100] = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do \
+                               eiusmod tempor incididunt ut labore.";
+
+    fn test_config(protocol_version: ProtocolVersion) -> TransferConfig {
+        TransferConfig {
+            protocol_version,
+            max_bytes_to_receive: 40,
+            max_chunk_size_bytes: 32,
+            // Lossy tests may time out several times in a row.
+            max_retries: 10,
+            ..TransferConfig::default()
+        }
+    }
+
+    fn write(protocol_version: ProtocolVersion, lose: impl FnMut(usize) -> bool) {
+        let mut handler = WriteOnlyHandler::new(3, Cursor::new([0u8; 100]));
+        let mut transfer = WriteTransfer::new(1, 3, DATA, test_config(protocol_version));
+        assert_eq!(run(WRITE_METHOD_ID, &mut handler, &mut transfer, lose), 0);
+        assert_eq!(transfer.status(), Some(Ok(())));
+        assert_eq!(&handler.into_inner().into_inner(), DATA);
+    }
+
+    fn read(protocol_version: ProtocolVersion, lose: impl FnMut(usize) -> bool) {
+        let mut handler = ReadOnlyHandler::new(3, Cursor::new(*DATA));
+        let sink = Cursor::new([0u8; 128]);
+        let mut transfer = ReadTransfer::new(1, 3, sink, test_config(protocol_version));
+        let active_transfers = run(READ_METHOD_ID, &mut handler, &mut transfer, lose);
+        // Legacy clients do not wait for the service to receive their final
+        // status, so it may be lost.
+        if protocol_version == ProtocolVersion::Version2 {
+            assert_eq!(active_transfers, 0);
+        }
+        assert_eq!(transfer.status(), Some(Ok(())));
+        assert_eq!(transfer.offset(), 100);
+        assert_eq!(&transfer.into_sink().into_inner()[..100], DATA);
+    }
+
+    #[test]
+    fn client_writes_resource() {
+        write(ProtocolVersion::Version2, |_| false);
+    }
+
+    #[test]
+    fn client_reads_resource() {
+        read(ProtocolVersion::Version2, |_| false);
+    }
+
+    #[test]
+    fn legacy_clients_read_and_write() {
+        write(ProtocolVersion::Legacy, |_| false);
+        read(ProtocolVersion::Legacy, |_| false);
+    }
+
+    // Loses about a quarter of the chunks sent, in a pattern set by `seed`.
+    fn lossy(seed: u32) -> impl FnMut(usize) -> bool {
+        let mut state = seed;
+        move |_| {
+            // xorshift32
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state & 3 == 0
+        }
+    }
+
+    #[test]
+    fn lost_chunks_are_retransmitted() {
+        for seed in 1..=20 {
+            write(ProtocolVersion::Version2, lossy(seed));
+            read(ProtocolVersion::Version2, lossy(seed));
+            write(ProtocolVersion::Legacy, lossy(seed));
+            read(ProtocolVersion::Legacy, lossy(seed));
+        }
+    }
+
+    #[test]
+    fn transfers_are_rejected_by_handler() {
+        let mut handler = ReadOnlyHandler::new(3, Cursor::new(*DATA));
+        let config = TransferConfig::default();
+        let mut transfer = WriteTransfer::new(1, 3, DATA, config);
+        assert_eq!(
+            run(WRITE_METHOD_ID, &mut handler, &mut transfer, |_| false),
+            0
+        );
+        assert_eq!(transfer.status(), Some(Err(Error::PermissionDenied)));
+
+        let mut transfer = WriteTransfer::new(1, 4, DATA, config);
+        assert_eq!(
+            run(WRITE_METHOD_ID, &mut handler, &mut transfer, |_| false),
+            0
+        );
+        assert_eq!(transfer.status(), Some(Err(Error::NotFound)));
+    }
+
+    // Rejects written data when the transfer finishes, like a handler which
+    // verifies an update.
+    struct RejectingHandler;
+
+    impl Handler for RejectingHandler {
+        fn resource_id(&self) -> u32 {
+            3
+        }
+
+        fn prepare_write(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn write(&mut self, _offset: u64, _data: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn finalize_write(&mut self, _status: Result<()>) -> Result<()> {
+            Err(Error::DataLoss)
+        }
+    }
+
+    #[test]
+    fn handler_sets_final_write_status() {
+        let mut transfer = WriteTransfer::new(1, 3, DATA, test_config(ProtocolVersion::Version2));
+        assert_eq!(
+            run(
+                WRITE_METHOD_ID,
+                &mut RejectingHandler,
+                &mut transfer,
+                |_| false
+            ),
+            0
+        );
+        assert_eq!(transfer.status(), Some(Err(Error::DataLoss)));
+    }
+
+    #[test]
+    fn unknown_sessions_are_rejected() {
+        let mut handler = ReadOnlyHandler::new(3, Cursor::new(*DATA));
+        let mut transfer =
+            ReadTransfer::new(1, 3, Cursor::new([0u8; 128]), TransferConfig::default());
+        // Losing the start chunk and every retry fails the transfer.
+        assert_eq!(
+            run(READ_METHOD_ID, &mut handler, &mut transfer, |_| true),
+            0
+        );
+        assert_eq!(transfer.status(), Some(Err(Error::DeadlineExceeded)));
+
+        let mut responses = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            responses.push(packet.to_vec());
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut handlers: [&mut dyn Handler; 1] = [&mut handler];
+        let mut chunk_buffer = [0u8; 16];
+        let mut encoding_buffer = [0u8; 64];
+        let mut service =
+            TransferService::<1>::new(&mut handlers, &mut chunk_buffer, &mut encoding_buffer, 40);
+        let mut services: [&mut dyn Service; 1] = [&mut service];
+        let mut buffer = [0u8; 128];
+        let mut server = Server::<1>::new(&mut channels, &mut services, &mut buffer);
+
+        let mut chunk = [0u8; 64];
+        let len = Chunk::new(
+            ProtocolVersion::Version2,
+            ChunkType::ParametersRetransmit,
+            9,
+        )
+        .encode(&mut chunk)
+        .unwrap();
+        let mut packet = [0u8; 128];
+        for (packet_type, payload) in [
+            (PacketType::Request, &[][..]),
+            (PacketType::ClientStream, &chunk[..len]),
+        ] {
+            let len = Packet::new(packet_type, 1, SERVICE_ID, READ_METHOD_ID, 1)
+                .with_payload(payload)
+                .encode(&mut packet)
+                .unwrap();
+            server.process_packet(&packet[..len]).unwrap();
+        }
+
+        assert_eq!(responses.len(), 1);
+        let packet = Packet::decode(&responses[0]).unwrap();
+        let chunk = Chunk::decode(packet.payload).unwrap();
+        assert_eq!(chunk.session_id, 9);
+        assert_eq!(chunk.status, Some(Err(Error::FailedPrecondition)));
+    }
+}
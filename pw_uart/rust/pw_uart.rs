@@ -0,0 +1,517 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_uart` defines the interface between UART drivers and the code which
+//! uses them, so that transports such as HDLC, log drains, and shells bind to
+//! hardware the same way across vendors.
+//!
+//! Drivers implement one of two traits:
+//! - [`Uart`] is a blocking interface, with deadlines on reads and writes,
+//!   for drivers which can block the calling thread until data arrives or
+//!   their transmit buffer drains.
+//! - [`UartNonBlocking`] only reads data which has already arrived and
+//!   queues data which fits in the transmit buffer.  It is simpler to
+//!   implement on bare-metal targets, and [`PollingUart`] provides [`Uart`]
+//!   on top of it by polling.
+//!
+//! All errors are reported as [`pw_status::Error`]s.  A [`UartStream`]
+//! adapts a [`Uart`] to [`pw_stream::Read`] and [`pw_stream::Write`]:
+//!
+//! ```
+//! use pw_stream::Write;
+//! use pw_uart::{Config, PollingUart, Uart, UartStream};
+//! # use pw_status::Result;
+//! # struct Usart1;
+//! # impl pw_uart::UartNonBlocking for Usart1 {
+//! #     fn configure(&mut self, _config: &Config) -> Result<()> { Ok(()) }
+//! #     fn try_read(&mut self, _buf: &mut [u8]) -> Result<usize> {
+//! #         Err(pw_status::Error::Unavailable)
+//! #     }
+//! #     fn try_write(&mut self, buf: &[u8]) -> Result<usize> { Ok(buf.len()) }
+//! #     fn is_tx_idle(&self) -> bool { true }
+//! #     fn conservative_read_available(&self) -> usize { 0 }
+//! #     fn clear_pending_receive_bytes(&mut self) {}
+//! # }
+//!
+//! let mut uart = PollingUart::new(Usart1);
+//! uart.configure(&Config::new(115_200))?;
+//!
+//! let mut stream = UartStream::new(uart);
+//! stream.write_all(b"Hello over UART\r\n")?;
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_chrono::{Duration, Instant};
+use pw_status::{Error, Result};
+
+/// Parity bit setting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Parity {
+    /// No parity bit.
+    #[default]
+    None,
+    /// Even parity.
+    Even,
+    /// Odd parity.
+    Odd,
+}
+
+/// Number of stop bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StopBits {
+    /// One stop bit.
+    #[default]
+    One,
+    /// Two stop bits.
+    Two,
+}
+
+/// Settings of a UART.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Baud rate in bits per second.
+    pub baud_rate: u32,
+    /// Number of data bits in each character, usually 8.
+    pub data_bits: u8,
+    /// Parity bit setting.
+    pub parity: Parity,
+    /// Number of stop bits.
+    pub stop_bits: StopBits,
+    /// Whether RTS/CTS hardware flow control is used.
+    pub flow_control: bool,
+}
+
+impl Config {
+    /// Creates a configuration with `baud_rate` and 8 data bits, no parity,
+    /// one stop bit, and no flow control.
+    pub const fn new(baud_rate: u32) -> Self {
+        Self {
+            baud_rate,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: false,
+        }
+    }
+}
+
+/// A UART driver which blocks until data is read or written.
+///
+/// Reads and writes with a deadline fail with [`Error::DeadlineExceeded`] if
+/// no data is transferred before the [`pw_chrono`] system clock reaches the
+/// deadline.
+pub trait Uart {
+    /// Applies `config` to the UART.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - The UART does not support `config`.
+    fn configure(&mut self, config: &Config) -> Result<()>;
+
+    /// Blocks until at least one byte is received, then reads as many
+    /// received bytes as fit in `buf`, returning how many were read.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Reads like [`Uart::read()`], blocking for at most until `deadline`.
+    fn try_read_until(&mut self, buf: &mut [u8], deadline: Instant) -> Result<usize>;
+
+    /// Blocks until at least one byte of `buf` is queued for transmission,
+    /// returning how many were queued.
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Writes like [`Uart::write()`], blocking for at most until `deadline`.
+    fn try_write_until(&mut self, buf: &[u8], deadline: Instant) -> Result<usize>;
+
+    /// Blocks until all queued data has been transmitted.
+    fn flush_output(&mut self) -> Result<()>;
+
+    /// Returns a number of bytes which can be read without blocking.  There
+    /// may be more.
+    fn conservative_read_available(&self) -> usize;
+
+    /// Discards any received data which has not been read.
+    fn clear_pending_receive_bytes(&mut self);
+
+    /// Reads like [`Uart::read()`], blocking for at most `timeout`.
+    fn try_read_for(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.try_read_until(buf, pw_chrono::now() + timeout)
+    }
+
+    /// Writes like [`Uart::write()`], blocking for at most `timeout`.
+    fn try_write_for(&mut self, buf: &[u8], timeout: Duration) -> Result<usize> {
+        self.try_write_until(buf, pw_chrono::now() + timeout)
+    }
+
+    /// Blocks until `buf` is filled.
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let len = self.read(buf)?;
+            buf = &mut buf[len..];
+        }
+        Ok(())
+    }
+
+    /// Blocks until all of `buf` is queued for transmission.
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let len = self.write(buf)?;
+            buf = &buf[len..];
+        }
+        Ok(())
+    }
+}
+
+/// A UART driver which never blocks.
+///
+/// Reads and writes transfer as much data as possible immediately, failing
+/// with [`Error::Unavailable`] if no data can be transferred.
+pub trait UartNonBlocking {
+    /// Applies `config` to the UART.
+    ///
+    /// # Errors
+    /// - [`Error::InvalidArgument`] - The UART does not support `config`.
+    fn configure(&mut self, config: &Config) -> Result<()>;
+
+    /// Reads as many received bytes as fit in `buf`, returning how many were
+    /// read.
+    ///
+    /// # Errors
+    /// - [`Error::Unavailable`] - No data has been received.
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Queues as much of `buf` as fits in the transmit buffer, returning how
+    /// many bytes were queued.
+    ///
+    /// # Errors
+    /// - [`Error::Unavailable`] - The transmit buffer is full.
+    fn try_write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Returns whether all queued data has been transmitted.
+    fn is_tx_idle(&self) -> bool;
+
+    /// Returns a number of bytes which can be read without blocking.  There
+    /// may be more.
+    fn conservative_read_available(&self) -> usize;
+
+    /// Discards any received data which has not been read.
+    fn clear_pending_receive_bytes(&mut self);
+}
+
+/// A [`Uart`] which busy waits on a [`UartNonBlocking`] driver.
+pub struct PollingUart<U: UartNonBlocking> {
+    uart: U,
+}
+
+impl<U: UartNonBlocking> PollingUart<U> {
+    /// Creates a blocking UART from a non-blocking driver.
+    pub const fn new(uart: U) -> Self {
+        Self { uart }
+    }
+
+    /// Returns a reference to the non-blocking driver.
+    pub fn get_ref(&self) -> &U {
+        &self.uart
+    }
+
+    /// Returns a mutable reference to the non-blocking driver.
+    pub fn get_mut(&mut self) -> &mut U {
+        &mut self.uart
+    }
+
+    /// Consumes the `PollingUart` and returns the non-blocking driver.
+    pub fn into_inner(self) -> U {
+        self.uart
+    }
+
+    // Retries `f` while it is unavailable, until `deadline` passes.
+    fn poll(deadline: Option<Instant>, mut f: impl FnMut() -> Result<usize>) -> Result<usize> {
+        loop {
+            match f() {
+                Err(Error::Unavailable) => {
+                    if matches!(deadline, Some(deadline) if deadline.has_passed()) {
+                        return Err(Error::DeadlineExceeded);
+                    }
+                    core::hint::spin_loop();
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<U: UartNonBlocking> Uart for PollingUart<U> {
+    fn configure(&mut self, config: &Config) -> Result<()> {
+        self.uart.configure(config)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        Self::poll(None, || self.uart.try_read(buf))
+    }
+
+    fn try_read_until(&mut self, buf: &mut [u8], deadline: Instant) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        Self::poll(Some(deadline), || self.uart.try_read(buf))
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        Self::poll(None, || self.uart.try_write(buf))
+    }
+
+    fn try_write_until(&mut self, buf: &[u8], deadline: Instant) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        Self::poll(Some(deadline), || self.uart.try_write(buf))
+    }
+
+    fn flush_output(&mut self) -> Result<()> {
+        while !self.uart.is_tx_idle() {
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    fn conservative_read_available(&self) -> usize {
+        self.uart.conservative_read_available()
+    }
+
+    fn clear_pending_receive_bytes(&mut self) {
+        self.uart.clear_pending_receive_bytes()
+    }
+}
+
+/// Adapts a [`Uart`] to [`pw_stream::Read`] and [`pw_stream::Write`].
+///
+/// Reads block until at least one byte is received, and flushing waits for
+/// all written data to be transmitted.
+pub struct UartStream<U: Uart> {
+    uart: U,
+}
+
+impl<U: Uart> UartStream<U> {
+    /// Creates a stream which reads from and writes to `uart`.
+    pub const fn new(uart: U) -> Self {
+        Self { uart }
+    }
+
+    /// Returns a reference to the UART.
+    pub fn get_ref(&self) -> &U {
+        &self.uart
+    }
+
+    /// Returns a mutable reference to the UART.
+    pub fn get_mut(&mut self) -> &mut U {
+        &mut self.uart
+    }
+
+    /// Consumes the `UartStream` and returns the UART.
+    pub fn into_inner(self) -> U {
+        self.uart
+    }
+}
+
+impl<U: Uart> pw_stream::Read for UartStream<U> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.uart.read(buf)
+    }
+}
+
+impl<U: Uart> pw_stream::Write for UartStream<U> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.uart.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.uart.flush_output()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::vec::Vec;
+
+    use pw_chrono::SystemClock;
+    use pw_stream::{Read, Write};
+
+    use super::*;
+
+    // A clock which advances by a millisecond each time it is read.
+    struct TestClock(AtomicU64);
+
+    impl SystemClock for TestClock {
+        fn ticks_per_second(&self) -> u64 {
+            1000
+        }
+
+        fn now_ticks(&self) -> u64 {
+            self.0.fetch_add(1, Ordering::Relaxed)
+        }
+    }
+
+    static CLOCK: TestClock = TestClock(AtomicU64::new(0));
+
+    // A UART whose transmit buffer holds `tx_capacity` bytes, which are
+    // transmitted when `transmit()` is called.
+    #[derive(Default)]
+    struct FakeUart {
+        config: Option<Config>,
+        rx: VecDeque<u8>,
+        tx: Vec<u8>,
+        tx_capacity: usize,
+        transmitted: Vec<u8>,
+    }
+
+    impl FakeUart {
+        fn new(tx_capacity: usize) -> Self {
+            Self {
+                tx_capacity,
+                ..Default::default()
+            }
+        }
+
+        fn transmit(&mut self) {
+            self.transmitted.append(&mut self.tx);
+        }
+    }
+
+    impl UartNonBlocking for FakeUart {
+        fn configure(&mut self, config: &Config) -> Result<()> {
+            if config.data_bits != 8 {
+                return Err(Error::InvalidArgument);
+            }
+            self.config = Some(*config);
+            Ok(())
+        }
+
+        fn try_read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            if self.rx.is_empty() {
+                return Err(Error::Unavailable);
+            }
+            let len = buf.len().min(self.rx.len());
+            for byte in &mut buf[..len] {
+                *byte = self.rx.pop_front().unwrap();
+            }
+            Ok(len)
+        }
+
+        fn try_write(&mut self, buf: &[u8]) -> Result<usize> {
+            let len = buf.len().min(self.tx_capacity - self.tx.len());
+            if len == 0 {
+                return Err(Error::Unavailable);
+            }
+            self.tx.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn is_tx_idle(&self) -> bool {
+            self.tx.is_empty()
+        }
+
+        fn conservative_read_available(&self) -> usize {
+            self.rx.len()
+        }
+
+        fn clear_pending_receive_bytes(&mut self) {
+            self.rx.clear();
+        }
+    }
+
+    #[test]
+    fn configure_is_passed_to_driver() {
+        let mut uart = PollingUart::new(FakeUart::new(8));
+        let config = Config {
+            parity: Parity::Even,
+            ..Config::new(9600)
+        };
+        uart.configure(&config).unwrap();
+        assert_eq!(uart.get_mut().config, Some(config));
+        assert_eq!(
+            uart.configure(&Config {
+                data_bits: 7,
+                ..config
+            }),
+            Err(Error::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn read_returns_received_bytes() {
+        let mut uart = PollingUart::new(FakeUart::new(8));
+        uart.get_mut().rx.extend(b"abc");
+        assert_eq!(uart.conservative_read_available(), 3);
+
+        let mut buffer = [0u8; 2];
+        assert_eq!(uart.read(&mut buffer), Ok(2));
+        assert_eq!(&buffer, b"ab");
+        assert_eq!(uart.read(&mut buffer), Ok(1));
+
+        uart.get_mut().rx.extend(b"xyz");
+        uart.clear_pending_receive_bytes();
+        assert_eq!(uart.conservative_read_available(), 0);
+    }
+
+    #[test]
+    fn read_exact_fills_buffer() {
+        let mut uart = PollingUart::new(FakeUart::new(8));
+        uart.get_mut().rx.extend(b"12345");
+        let mut buffer = [0u8; 5];
+        uart.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"12345");
+    }
+
+    #[test]
+    fn reads_and_writes_time_out() {
+        pw_chrono::set_system_clock(&CLOCK);
+        let mut uart = PollingUart::new(FakeUart::new(2));
+        let mut buffer = [0u8; 4];
+        assert_eq!(
+            uart.try_read_for(&mut buffer, Duration::from_millis(10)),
+            Err(Error::DeadlineExceeded)
+        );
+
+        assert_eq!(uart.try_write_for(b"abc", Duration::from_millis(10)), Ok(2));
+        assert_eq!(
+            uart.try_write_for(b"c", Duration::from_millis(10)),
+            Err(Error::DeadlineExceeded)
+        );
+        uart.get_mut().transmit();
+        assert_eq!(uart.try_write_for(b"c", Duration::from_millis(10)), Ok(1));
+    }
+
+    #[test]
+    fn stream_writes_through_uart() {
+        let mut stream = UartStream::new(PollingUart::new(FakeUart::new(64)));
+        stream.write_all(b"Hello").unwrap();
+        stream.get_mut().get_mut().transmit();
+        stream.flush().unwrap();
+        assert_eq!(stream.get_mut().get_mut().transmitted, b"Hello");
+
+        stream.get_mut().get_mut().rx.extend(b"Hi");
+        let mut buffer = [0u8; 8];
+        assert_eq!(stream.read(&mut buffer), Ok(2));
+        assert_eq!(&buffer[..2], b"Hi");
+    }
+}
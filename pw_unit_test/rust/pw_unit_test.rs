@@ -0,0 +1,675 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_unit_test` runs unit tests on a device and reports their results with
+//! the `pw.unit_test.UnitTest` RPC service, so Rust firmware tests are run
+//! and reported by the same host tooling as C++ tests.
+//!
+//! Tests are functions marked with [`#[pw_test]`](pw_test), which take a
+//! [`Test`] for checking expectations.  Each becomes a [`TestCase`] static,
+//! which is registered by listing it in the slice of tests given to
+//! [`run_tests()`] or [`UnitTestService`]:
+//!
+//! ```
+//! use pw_unit_test::{expect, expect_eq, pw_test, require, Test, TestCase};
+//!
+//! #[pw_test]
+//! fn adds_numbers(t: &mut Test) {
+//!     expect_eq!(t, 2 + 2, 4);
+//! }
+//!
+//! #[pw_test]
+//! fn parses_numbers(t: &mut Test) {
+//!     let value = "42".parse::<u32>();
+//!     // Ends the test if the expectation fails.
+//!     require!(t, value.is_ok());
+//!     expect!(t, value.unwrap() > 10);
+//! }
+//!
+//! static TESTS: &[&TestCase] = &[&adds_numbers, &parses_numbers];
+//! # let summary = pw_unit_test::run_tests(TESTS, |_| true, &mut ());
+//! # assert_eq!(summary.passed, 2);
+//! ```
+//!
+//! Add a [`UnitTestService`] with the tests to the device's RPC server.  When
+//! the host calls `UnitTest.Run`, the tests run in the RPC handler and each
+//! event is streamed to the host as a `pw.unit_test.Event`.
+//!
+//! Expectations which fail mark the test as failed.  The `expect*!` macros
+//! let the test continue, like the C++ `EXPECT_*` macros, while the
+//! `require*!` macros return from the test, like `ASSERT_*`.
+#![no_std]
+#![deny(missing_docs)]
+
+// Allows `#[pw_test]` to refer to this crate as `::pw_unit_test` in its tests.
+extern crate self as pw_unit_test;
+
+use core::fmt::{self, Write};
+
+use pw_protobuf::{Decoder, MemoryEncoder, Value};
+use pw_rpc::{id, MethodType, Responder, Service};
+use pw_status::Result;
+
+/// Marks a function as a unit test, replacing it with a [`TestCase`] static
+/// of the same name.
+///
+/// The function takes a `&mut Test`.  Its test suite is the name of the
+/// module it is defined in.
+pub use pw_unit_test_macro::pw_test;
+
+/// A registered unit test.
+pub struct TestCase {
+    module_path: &'static str,
+    name: &'static str,
+    file: &'static str,
+    run: fn(&mut Test),
+}
+
+impl TestCase {
+    /// Creates a test case.  Use [`#[pw_test]`](pw_test) instead.
+    pub const fn new(
+        module_path: &'static str,
+        name: &'static str,
+        file: &'static str,
+        run: fn(&mut Test),
+    ) -> Self {
+        Self {
+            module_path,
+            name,
+            file,
+            run,
+        }
+    }
+
+    /// Returns the name of the test suite, which is the last component of
+    /// the path of the module defining the test.
+    pub fn suite_name(&self) -> &'static str {
+        self.module_path
+            .rsplit("::")
+            .next()
+            .unwrap_or(self.module_path)
+    }
+
+    /// Returns the name of the test.
+    pub fn test_name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the path of the file defining the test.
+    pub fn file_name(&self) -> &'static str {
+        self.file
+    }
+}
+
+/// The result of a test case, as a `pw.unit_test.TestCaseResult`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TestResult {
+    /// Every expectation succeeded.
+    Success = 0,
+    /// An expectation failed.
+    Failure = 1,
+    /// The test skipped itself.
+    Skipped = 2,
+}
+
+/// The counts of test results in a run, as a `pw.unit_test.TestRunEnd`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    /// The number of tests which succeeded.
+    pub passed: u32,
+    /// The number of tests which failed.
+    pub failed: u32,
+    /// The number of tests which were skipped.
+    pub skipped: u32,
+}
+
+/// A checked expectation.
+pub struct Expectation<'a> {
+    /// The source code of the expectation.
+    pub expression: &'static str,
+    /// The expectation with its arguments evaluated.
+    pub evaluated: fmt::Arguments<'a>,
+    /// The line of the expectation.
+    pub line: u32,
+    /// Whether the expectation succeeded.
+    pub success: bool,
+}
+
+/// Receives the events of a test run.
+///
+/// Every method does nothing by default.
+pub trait EventHandler {
+    /// Called before the first test runs.
+    fn run_start(&mut self) {}
+
+    /// Called after the last test runs.
+    fn run_end(&mut self, _summary: &RunSummary) {}
+
+    /// Called before `test` runs.
+    fn test_case_start(&mut self, _test: &TestCase) {}
+
+    /// Called after `test` runs.
+    fn test_case_end(&mut self, _test: &TestCase, _result: TestResult) {}
+
+    /// Called when the running test checks an expectation.
+    fn expectation(&mut self, _expectation: &Expectation<'_>) {}
+}
+
+/// Ignores all events.
+impl EventHandler for () {}
+
+/// The state of a running test, through which it checks expectations.
+pub struct Test<'a> {
+    handler: &'a mut dyn EventHandler,
+    result: TestResult,
+}
+
+impl Test<'_> {
+    /// Records an expectation, failing the test if it didn't succeed.
+    /// Returns `success`.  Use the `expect*!` and `require*!` macros
+    /// instead.
+    pub fn check(
+        &mut self,
+        success: bool,
+        expression: &'static str,
+        evaluated: fmt::Arguments<'_>,
+        line: u32,
+    ) -> bool {
+        if !success {
+            self.result = TestResult::Failure;
+        }
+        self.handler.expectation(&Expectation {
+            expression,
+            evaluated,
+            line,
+            success,
+        });
+        success
+    }
+
+    /// Marks the test as skipped, unless it already failed.  The test should
+    /// return after skipping.
+    pub fn skip(&mut self) {
+        if self.result == TestResult::Success {
+            self.result = TestResult::Skipped;
+        }
+    }
+
+    /// Returns `true` if an expectation failed.
+    pub fn failed(&self) -> bool {
+        self.result == TestResult::Failure
+    }
+}
+
+/// Runs each test for which `filter` returns `true`, in order, sending their
+/// events to `handler`.
+pub fn run_tests(
+    tests: &[&TestCase],
+    mut filter: impl FnMut(&TestCase) -> bool,
+    handler: &mut dyn EventHandler,
+) -> RunSummary {
+    let mut summary = RunSummary::default();
+    handler.run_start();
+    for test in tests.iter().filter(|test| filter(test)) {
+        handler.test_case_start(test);
+        let mut state = Test {
+            handler: &mut *handler,
+            result: TestResult::Success,
+        };
+        (test.run)(&mut state);
+        let result = state.result;
+        match result {
+            TestResult::Success => summary.passed += 1,
+            TestResult::Failure => summary.failed += 1,
+            TestResult::Skipped => summary.skipped += 1,
+        }
+        handler.test_case_end(test, result);
+    }
+    handler.run_end(&summary);
+    summary
+}
+
+/// Checks that a condition is true, letting the test continue if it isn't.
+/// Returns the condition.
+#[macro_export]
+macro_rules! expect {
+    ($test:expr, $condition:expr $(,)?) => {
+        $test.check(
+            $condition,
+            stringify!($condition),
+            format_args!("{}", stringify!($condition)),
+            line!(),
+        )
+    };
+}
+
+/// Checks that two values are equal, letting the test continue if they
+/// aren't.  Returns whether they are.
+#[macro_export]
+macro_rules! expect_eq {
+    ($test:expr, $left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => $test.check(
+                *left == *right,
+                concat!(stringify!($left), " == ", stringify!($right)),
+                format_args!("{:?} == {:?}", left, right),
+                line!(),
+            ),
+        }
+    };
+}
+
+/// Checks that two values are not equal, letting the test continue if they
+/// are.  Returns whether they aren't.
+#[macro_export]
+macro_rules! expect_ne {
+    ($test:expr, $left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => $test.check(
+                *left != *right,
+                concat!(stringify!($left), " != ", stringify!($right)),
+                format_args!("{:?} != {:?}", left, right),
+                line!(),
+            ),
+        }
+    };
+}
+
+/// Checks that a condition is true, returning from the test if it isn't.
+#[macro_export]
+macro_rules! require {
+    ($test:expr, $condition:expr $(,)?) => {
+        if !$crate::expect!($test, $condition) {
+            return;
+        }
+    };
+}
+
+/// Checks that two values are equal, returning from the test if they
+/// aren't.
+#[macro_export]
+macro_rules! require_eq {
+    ($test:expr, $left:expr, $right:expr $(,)?) => {
+        if !$crate::expect_eq!($test, $left, $right) {
+            return;
+        }
+    };
+}
+
+/// Checks that two values are not equal, returning from the test if they
+/// are.
+#[macro_export]
+macro_rules! require_ne {
+    ($test:expr, $left:expr, $right:expr $(,)?) => {
+        if !$crate::expect_ne!($test, $left, $right) {
+            return;
+        }
+    };
+}
+
+/// Size of the buffer each `pw.unit_test.Event` is encoded in.  Evaluated
+/// expressions which don't fit are truncated.
+pub const EVENT_BUFFER_SIZE_BYTES: usize = 256;
+
+// Writes formatted text to a buffer, discarding what doesn't fit.
+struct TruncatingWriter<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for TruncatingWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let available = self.buffer.len() - self.len;
+        let mut len = s.len().min(available);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+// Streams events as `pw.unit_test.Event` responses.
+struct RpcEventHandler<'r, 'a, 'b> {
+    responder: &'r mut Responder<'a, 'b>,
+    report_passed_expectations: bool,
+}
+
+impl RpcEventHandler<'_, '_, '_> {
+    // Events which can't be encoded or sent are dropped, since the test run
+    // continues regardless.
+    fn send(&mut self, encode: impl FnOnce(&mut MemoryEncoder) -> Result<()>) {
+        let mut buffer = [0u8; EVENT_BUFFER_SIZE_BYTES];
+        let mut encoder = MemoryEncoder::new(&mut buffer);
+        if encode(&mut encoder).is_ok() {
+            let _ = self.responder.write(encoder.as_slice());
+        }
+    }
+}
+
+impl EventHandler for RpcEventHandler<'_, '_, '_> {
+    fn run_start(&mut self) {
+        self.send(|event| event.write_nested(1, |_| Ok(())));
+    }
+
+    fn run_end(&mut self, summary: &RunSummary) {
+        self.send(|event| {
+            event.write_nested(2, |end| {
+                end.write_uint32(1, summary.passed)?;
+                end.write_uint32(2, summary.failed)?;
+                end.write_uint32(3, summary.skipped)?;
+                end.write_uint32(4, 0)
+            })
+        });
+    }
+
+    fn test_case_start(&mut self, test: &TestCase) {
+        self.send(|event| {
+            event.write_nested(3, |descriptor| {
+                descriptor.write_string(1, test.suite_name())?;
+                descriptor.write_string(2, test.test_name())?;
+                descriptor.write_string(3, test.file_name())
+            })
+        });
+    }
+
+    fn test_case_end(&mut self, _test: &TestCase, result: TestResult) {
+        self.send(|event| event.write_uint32(4, result as u32));
+    }
+
+    fn expectation(&mut self, expectation: &Expectation<'_>) {
+        if expectation.success && !self.report_passed_expectations {
+            return;
+        }
+        let mut evaluated = [0u8; EVENT_BUFFER_SIZE_BYTES / 2];
+        let mut writer = TruncatingWriter {
+            buffer: &mut evaluated,
+            len: 0,
+        };
+        let _ = writer.write_fmt(expectation.evaluated);
+        let len = writer.len;
+        self.send(|event| {
+            event.write_nested(6, |nested| {
+                nested.write_string(1, expectation.expression)?;
+                nested.write_bytes(2, &evaluated[..len])?;
+                nested.write_uint32(3, expectation.line)?;
+                nested.write_bool(4, expectation.success)
+            })
+        });
+    }
+}
+
+/// The `pw.unit_test.UnitTest` service, which runs tests when the host calls
+/// `Run` and streams their events as `pw.unit_test.Event` responses.
+///
+/// Tests are filtered by the request's `test_suite` names, if any.  They run
+/// in the RPC handler, so the server processes no other packets until they
+/// finish.
+pub struct UnitTestService<'t> {
+    tests: &'t [&'t TestCase],
+}
+
+impl<'t> UnitTestService<'t> {
+    /// The ID of `pw.unit_test.UnitTest`.
+    pub const SERVICE_ID: u32 = id("pw.unit_test.UnitTest");
+    /// The ID of the `Run` method.
+    pub const RUN: u32 = id("Run");
+
+    /// Creates a service which runs `tests`.
+    pub const fn new(tests: &'t [&'t TestCase]) -> Self {
+        Self { tests }
+    }
+}
+
+// Returns the `test_suite` names in a `pw.unit_test.TestRunRequest`.
+fn requested_suites(request: &[u8]) -> impl Iterator<Item = &[u8]> {
+    Decoder::new(request).filter_map(|field| match field {
+        Ok(field) if field.number == 2 => match field.value {
+            Value::Delimited(suite) => Some(suite),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+impl Service for UnitTestService<'_> {
+    fn id(&self) -> u32 {
+        Self::SERVICE_ID
+    }
+
+    fn method_type(&self, method_id: u32) -> Option<MethodType> {
+        (method_id == Self::RUN).then_some(MethodType::ServerStreaming)
+    }
+
+    fn invoke(&mut self, request: &[u8], responder: &mut Responder<'_, '_>) {
+        let report_passed_expectations = Decoder::new(request).any(|field| {
+            matches!(field, Ok(field) if field.number == 1 && field.value == Value::Varint(1))
+        });
+        let filter_suites = requested_suites(request).next().is_some();
+        let mut handler = RpcEventHandler {
+            responder,
+            report_passed_expectations,
+        };
+        run_tests(
+            self.tests,
+            |test| {
+                !filter_suites
+                    || requested_suites(request).any(|suite| suite == test.suite_name().as_bytes())
+            },
+            &mut handler,
+        );
+        let _ = responder.finish(&[], Ok(()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::string::String;
+    use std::vec::Vec;
+
+    use pw_rpc::{Channel, Packet, PacketType, Server};
+
+    use super::*;
+
+    #[pw_test]
+    fn passes(t: &mut Test) {
+        expect_eq!(t, 1 + 1, 2);
+        expect_ne!(t, 1, 2);
+    }
+
+    #[pw_test]
+    fn fails(t: &mut Test) {
+        let value = 3;
+        require_eq!(t, value, 4);
+        // Not reached.
+        expect!(t, false);
+    }
+
+    #[pw_test]
+    fn skips(t: &mut Test) {
+        t.skip();
+    }
+
+    static TESTS: &[&TestCase] = &[&passes, &fails, &skips];
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl EventHandler for Recorder {
+        fn test_case_start(&mut self, test: &TestCase) {
+            self.events.push(std::format!(
+                "start {}.{}",
+                test.suite_name(),
+                test.test_name()
+            ));
+        }
+
+        fn test_case_end(&mut self, _test: &TestCase, result: TestResult) {
+            self.events.push(std::format!("end {result:?}"));
+        }
+
+        fn expectation(&mut self, expectation: &Expectation<'_>) {
+            self.events.push(std::format!(
+                "{} {} ({})",
+                if expectation.success { "pass" } else { "fail" },
+                expectation.expression,
+                expectation.evaluated
+            ));
+        }
+    }
+
+    #[test]
+    fn runner_reports_expectations_and_results() {
+        let mut recorder = Recorder::default();
+        let summary = run_tests(TESTS, |_| true, &mut recorder);
+        assert_eq!(
+            summary,
+            RunSummary {
+                passed: 1,
+                failed: 1,
+                skipped: 1
+            }
+        );
+        assert_eq!(
+            recorder.events,
+            [
+                "start tests.passes",
+                "pass 1 + 1 == 2 (2 == 2)",
+                "pass 1 != 2 (1 != 2)",
+                "end Success",
+                "start tests.fails",
+                "fail value == 4 (3 == 4)",
+                "end Failure",
+                "start tests.skips",
+                "end Skipped",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_case_records_location() {
+        assert_eq!(passes.suite_name(), "tests");
+        assert_eq!(passes.test_name(), "passes");
+        assert!(passes.file_name().ends_with("pw_unit_test.rs"));
+    }
+
+    #[test]
+    fn truncating_writer_keeps_whole_characters() {
+        let mut buffer = [0u8; 4];
+        let mut writer = TruncatingWriter {
+            buffer: &mut buffer,
+            len: 0,
+        };
+        write!(writer, "ab♦").unwrap();
+        let len = writer.len;
+        assert_eq!(&buffer[..len], b"ab");
+    }
+
+    // Calls `Run` with `request` and returns the streamed event payloads.
+    fn run_over_rpc(request: &[u8]) -> Vec<Vec<u8>> {
+        let mut sent = Vec::new();
+        let mut output = |packet: &[u8]| -> Result<()> {
+            let packet = Packet::decode(packet)?;
+            if packet.packet_type == PacketType::ServerStream {
+                sent.push(packet.payload.to_vec());
+            }
+            Ok(())
+        };
+        let mut channels = [Channel::new(1, &mut output)];
+        let mut service = UnitTestService::new(TESTS);
+        let mut services: [&mut dyn Service; 1] = [&mut service];
+        let mut buffer = [0u8; 512];
+        let mut server = Server::<0>::new(&mut channels, &mut services, &mut buffer);
+
+        let mut data = [0u8; 64];
+        let len = Packet::new(
+            PacketType::Request,
+            1,
+            UnitTestService::SERVICE_ID,
+            UnitTestService::RUN,
+            1,
+        )
+        .with_payload(request)
+        .encode(&mut data)
+        .unwrap();
+        server.process_packet(&data[..len]).unwrap();
+        sent
+    }
+
+    // Returns the field number and value of an encoded `pw.unit_test.Event`.
+    fn decode_event(event: &[u8]) -> (u32, Value<'_>) {
+        let mut decoder = Decoder::new(event);
+        let field = decoder.next().unwrap().unwrap();
+        assert_eq!(decoder.next(), None);
+        (field.number, field.value)
+    }
+
+    #[test]
+    fn service_streams_events() {
+        let events = run_over_rpc(&[]);
+        let events: Vec<_> = events.iter().map(|event| decode_event(event)).collect();
+        let types: Vec<u32> = events.iter().map(|(number, _)| *number).collect();
+        assert_eq!(types, [1, 3, 4, 3, 6, 4, 3, 4, 2]);
+
+        let Value::Delimited(descriptor) = events[1].1 else {
+            panic!("TestCaseDescriptor is not a message");
+        };
+        let fields: Vec<_> = Decoder::new(descriptor).map(Result::unwrap).collect();
+        assert_eq!(fields[0].value, Value::Delimited(b"tests"));
+        assert_eq!(fields[1].value, Value::Delimited(b"passes"));
+        assert_eq!(fields[2].value, Value::Delimited(file!().as_bytes()));
+
+        // Only the failed expectation is reported.
+        let Value::Delimited(expectation) = events[4].1 else {
+            panic!("TestCaseExpectation is not a message");
+        };
+        let fields: Vec<_> = Decoder::new(expectation).map(Result::unwrap).collect();
+        assert_eq!(fields[0].value, Value::Delimited(b"value == 4"));
+        assert_eq!(fields[1].value, Value::Delimited(b"3 == 4"));
+        assert_eq!(fields[3].value, Value::Varint(0));
+
+        assert_eq!(events[2].1, Value::Varint(0));
+        assert_eq!(events[5].1, Value::Varint(1));
+        assert_eq!(events[7].1, Value::Varint(2));
+        assert_eq!(
+            events[8].1,
+            Value::Delimited(b"\x08\x01\x10\x01\x18\x01\x20\x00")
+        );
+    }
+
+    #[test]
+    fn service_filters_suites_and_reports_passed_expectations() {
+        // `report_passed_expectations: true, test_suite: "other"`
+        assert_eq!(
+            run_over_rpc(b"\x08\x01\x12\x05other"),
+            [
+                b"\x0a\x00".to_vec(),
+                b"\x12\x08\x08\x00\x10\x00\x18\x00\x20\x00".to_vec()
+            ]
+        );
+        // `report_passed_expectations: true, test_suite: "tests"`
+        let events = run_over_rpc(b"\x08\x01\x12\x05tests");
+        let expectations = events
+            .iter()
+            .filter(|event| decode_event(event).0 == 6)
+            .count();
+        assert_eq!(expectations, 3);
+    }
+}
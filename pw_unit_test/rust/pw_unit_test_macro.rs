@@ -0,0 +1,63 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+// This proc macro crate is a private API for the `pw_unit_test` crate.
+#![doc(hidden)]
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, ItemFn};
+
+// Replaces a test function with a `pw_unit_test::TestCase` static of the same
+// name which runs it.
+#[proc_macro_attribute]
+pub fn pw_test(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(
+            proc_macro2::TokenStream::from(attr)
+                .into_iter()
+                .next()
+                .map_or_else(proc_macro2::Span::call_site, |token| token.span()),
+            "#[pw_test] takes no arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let mut test_fn = parse_macro_input!(item as ItemFn);
+    if test_fn.sig.inputs.len() != 1 {
+        return syn::Error::new_spanned(
+            &test_fn.sig,
+            "test functions take one argument, `&mut pw_unit_test::Test`",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let ident = test_fn.sig.ident.clone();
+    let vis = std::mem::replace(&mut test_fn.vis, syn::Visibility::Inherited);
+    let (docs, attrs): (Vec<_>, Vec<_>) = test_fn
+        .attrs
+        .drain(..)
+        .partition(|attr| attr.path().is_ident("doc"));
+    test_fn.attrs = attrs;
+
+    quote! {
+        #(#docs)*
+        #[allow(non_upper_case_globals)]
+        #vis static #ident: ::pw_unit_test::TestCase = {
+            #test_fn
+            ::pw_unit_test::TestCase::new(module_path!(), stringify!(#ident), file!(), #ident)
+        };
+    }
+    .into()
+}
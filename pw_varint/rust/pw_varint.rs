@@ -24,7 +24,15 @@
 //! [Protocol Buffers](https://developers.google.com/protocol-buffers/docs/encoding#varints).
 //!
 //! Encoding and decoding is provided through the [VarintEncode] and
-//! [VarintDecode] traits.
+//! [VarintDecode] traits as well as the [`encode_u64()`], [`decode_u64()`],
+//! [`encode_i64()`], and [`decode_i64()`] functions.  [`encoded_size()`] and
+//! [`zig_zag_encoded_size()`] return the size of a value's encoding, and
+//! [`MAX_VARINT32_SIZE_BYTES`] and [`MAX_VARINT64_SIZE_BYTES`] size buffers
+//! which hold any encoded value.  Varints which arrive in pieces are decoded
+//! with [`decode_u64_partial()`] or a [`Decoder`].
+//!
+//! 128 bit values are encoded with [`encode_u128()`] and [`encode_i128()`],
+//! which use up to [`MAX_VARINT128_SIZE_BYTES`].
 //!
 //! # Example
 //!
@@ -37,17 +45,33 @@
 //!
 //! let (decoded_len, val) = i64::varint_decode(&buffer ).unwrap();
 //! ```
+//!
+//! ```
+//! let mut buffer = [0u8; pw_varint::MAX_VARINT64_SIZE_BYTES];
+//!
+//! let len = pw_varint::encode_u64(&mut buffer, 300).unwrap();
+//! assert_eq!(&buffer[..len], &[0xac, 0x02]);
+//! assert_eq!(pw_varint::encoded_size(300), len);
+//! assert_eq!(pw_varint::decode_u64(&buffer), Ok((len, 300)));
+//! ```
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
-use core::num::Wrapping;
-
 use pw_status::{Error, Result};
 
+/// The maximum number of bytes used to encode a 32 bit value.
+pub const MAX_VARINT32_SIZE_BYTES: usize = 5;
+
+/// The maximum number of bytes used to encode a 64 bit value.
+pub const MAX_VARINT64_SIZE_BYTES: usize = 10;
+
+/// The maximum number of bytes used to encode a 128 bit value.
+pub const MAX_VARINT128_SIZE_BYTES: usize = 19;
+
 /// A trait for objects than can be decoded from a varint.
 ///
-/// `pw_varint` provides implementations for [i16], [u16], [i32], [u32],
-/// [i64], and [u64].
+/// `pw_varint` provides implementations for [i8], [u8], [i16], [u16], [i32],
+/// [u32], [i64], [u64], [i128], and [u128].
 pub trait VarintDecode: Sized {
     /// Decode a type from a varint encoded series of bytes.
     ///
@@ -57,8 +81,8 @@ pub trait VarintDecode: Sized {
 
 /// A trait for objects than can be encoded into a varint.
 ///
-/// `pw_varint` provides implementations for [i16], [u16], [i32], [u32],
-/// [i64], and [u64].
+/// `pw_varint` provides implementations for [i8], [u8], [i16], [u16], [i32],
+/// [u32], [i64], [u64], [i128], and [u128].
 pub trait VarintEncode: Sized {
     /// Encode a type into a varint encoded series of bytes.
     ///
@@ -110,9 +134,40 @@ signed_varint_impl!(i16);
 signed_varint_impl!(i32);
 signed_varint_impl!(i64);
 
-fn decode_u64(data: &[u8]) -> Result<(usize, u64)> {
+impl VarintDecode for u128 {
+    fn varint_decode(data: &[u8]) -> Result<(usize, Self)> {
+        decode_u128(data)
+    }
+}
+
+impl VarintEncode for u128 {
+    fn varint_encode(self, data: &mut [u8]) -> Result<usize> {
+        encode_u128(data, self)
+    }
+}
+
+impl VarintDecode for i128 {
+    fn varint_decode(data: &[u8]) -> Result<(usize, Self)> {
+        decode_i128(data)
+    }
+}
+
+impl VarintEncode for i128 {
+    fn varint_encode(self, data: &mut [u8]) -> Result<usize> {
+        encode_i128(data, self)
+    }
+}
+
+/// Decodes a varint from the start of `data`.
+///
+/// Returns the number of bytes decoded and the value.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `data` ends before the varint does, or the
+///   varint is longer than [`MAX_VARINT64_SIZE_BYTES`].
+pub fn decode_u64(data: &[u8]) -> Result<(usize, u64)> {
     let mut value: u64 = 0;
-    for (i, d) in data.iter().enumerate() {
+    for (i, d) in data.iter().take(MAX_VARINT64_SIZE_BYTES).enumerate() {
         value |= (*d as u64 & 0x7f) << (i * 7);
 
         if (*d & 0x80) == 0 {
@@ -122,7 +177,13 @@ fn decode_u64(data: &[u8]) -> Result<(usize, u64)> {
     Err(Error::OutOfRange)
 }
 
-fn encode_u64(data: &mut [u8], value: u64) -> Result<usize> {
+/// Encodes `value` as a varint at the start of `data`.
+///
+/// Returns the number of bytes encoded.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `data` is too small to hold the encoded value.
+pub fn encode_u64(data: &mut [u8], value: u64) -> Result<usize> {
     let mut value = value;
     for (i, d) in data.iter_mut().enumerate() {
         let mut byte: u8 = (value & 0x7f) as u8;
@@ -138,23 +199,269 @@ fn encode_u64(data: &mut [u8], value: u64) -> Result<usize> {
     Err(Error::OutOfRange)
 }
 
-// ZigZag encodes a signed integer. This maps small negative numbers to small,
-// unsigned positive numbers, which improves their density for LEB128 encoding.
-//
-// ZigZag encoding works by moving the sign bit from the most-significant bit to
-// the least-significant bit. For the signed k-bit integer n, the formula is
-//
-//   (n << 1) ^ (n >> (k - 1))
-//
-// See the following for a description of ZigZag encoding:
-//   https://developers.google.com/protocol-buffers/docs/encoding#types
-fn zig_zag_encode(value: i64) -> u64 {
+/// Decodes a zig-zag encoded varint from the start of `data`.
+///
+/// Returns the number of bytes decoded and the value.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `data` ends before the varint does, or the
+///   varint is longer than [`MAX_VARINT64_SIZE_BYTES`].
+pub fn decode_i64(data: &[u8]) -> Result<(usize, i64)> {
+    let (len, value) = decode_u64(data)?;
+    Ok((len, zig_zag_decode(value)))
+}
+
+/// Zig-zag encodes `value` as a varint at the start of `data`.
+///
+/// Returns the number of bytes encoded.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `data` is too small to hold the encoded value.
+pub fn encode_i64(data: &mut [u8], value: i64) -> Result<usize> {
+    encode_u64(data, zig_zag_encode(value))
+}
+
+/// Decodes a 128 bit varint from the start of `data`.
+///
+/// Returns the number of bytes decoded and the value.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `data` ends before the varint does, or the
+///   varint is longer than [`MAX_VARINT128_SIZE_BYTES`].
+pub fn decode_u128(data: &[u8]) -> Result<(usize, u128)> {
+    let mut value: u128 = 0;
+    for (i, d) in data.iter().take(MAX_VARINT128_SIZE_BYTES).enumerate() {
+        value |= (*d as u128 & 0x7f) << (i * 7);
+
+        if (*d & 0x80) == 0 {
+            return Ok((i + 1, value));
+        }
+    }
+    Err(Error::OutOfRange)
+}
+
+/// Encodes a 128 bit `value` as a varint at the start of `data`.
+///
+/// Returns the number of bytes encoded.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `data` is too small to hold the encoded value.
+pub fn encode_u128(data: &mut [u8], value: u128) -> Result<usize> {
+    let mut value = value;
+    for (i, d) in data.iter_mut().enumerate() {
+        let mut byte: u8 = (value & 0x7f) as u8;
+        value >>= 7;
+        if value > 0 {
+            byte |= 0x80;
+        }
+        *d = byte;
+        if value == 0 {
+            return Ok(i + 1);
+        }
+    }
+    Err(Error::OutOfRange)
+}
+
+/// Decodes a zig-zag encoded 128 bit varint from the start of `data`.
+///
+/// Returns the number of bytes decoded and the value.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `data` ends before the varint does, or the
+///   varint is longer than [`MAX_VARINT128_SIZE_BYTES`].
+pub fn decode_i128(data: &[u8]) -> Result<(usize, i128)> {
+    let (len, value) = decode_u128(data)?;
+    Ok((len, zig_zag_decode_i128(value)))
+}
+
+/// Zig-zag encodes a 128 bit `value` as a varint at the start of `data`.
+///
+/// Returns the number of bytes encoded.
+///
+/// # Errors
+/// - [`Error::OutOfRange`] - `data` is too small to hold the encoded value.
+pub fn encode_i128(data: &mut [u8], value: i128) -> Result<usize> {
+    encode_u128(data, zig_zag_encode_i128(value))
+}
+
+/// The result of decoding a varint from data which may not hold all of it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Partial<T> {
+    /// The varint ended after `len` bytes and decoded to `value`.
+    Complete {
+        /// The number of bytes decoded.
+        len: usize,
+        /// The decoded value.
+        value: T,
+    },
+    /// The data ended before the varint did.  At least `needed` more bytes
+    /// are required to decode it.
+    Incomplete {
+        /// The minimum number of additional bytes required.
+        needed: usize,
+    },
+}
+
+/// Incrementally decodes a varint from data which arrives in pieces, such as
+/// from a streaming transport, without buffering the whole varint.
+///
+/// # Example
+///
+/// ```
+/// use pw_varint::{Decoder, Partial};
+///
+/// let mut decoder = Decoder::new();
+/// assert_eq!(decoder.decode(&[0xac]), Ok(Partial::Incomplete { needed: 1 }));
+/// assert_eq!(
+///     decoder.decode(&[0x02, 0x05]),
+///     Ok(Partial::Complete { len: 1, value: 300 })
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Decoder {
+    value: u64,
+    len: usize,
+}
+
+impl Decoder {
+    /// Creates a decoder which has not decoded any bytes.
+    pub const fn new() -> Self {
+        Self { value: 0, len: 0 }
+    }
+
+    /// Decodes bytes from the start of `data`, continuing the varint decoded
+    /// by previous calls.
+    ///
+    /// Returns [`Partial::Complete`] with the number of bytes of `data` which
+    /// were used once the varint ends, after which the decoder is reset to
+    /// decode another varint.  Otherwise all of `data` was used and
+    /// [`Partial::Incomplete`] is returned.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The varint is longer than
+    ///   [`MAX_VARINT64_SIZE_BYTES`].  The decoder is reset.
+    pub fn decode(&mut self, data: &[u8]) -> Result<Partial<u64>> {
+        for (i, d) in data.iter().enumerate() {
+            if self.len == MAX_VARINT64_SIZE_BYTES {
+                self.reset();
+                return Err(Error::DataLoss);
+            }
+            self.value |= (*d as u64 & 0x7f) << (self.len * 7);
+            self.len += 1;
+
+            if (*d & 0x80) == 0 {
+                let value = self.value;
+                self.reset();
+                return Ok(Partial::Complete { len: i + 1, value });
+            }
+        }
+        Ok(Partial::Incomplete { needed: 1 })
+    }
+
+    /// Returns the number of bytes of the current varint decoded so far.
+    pub const fn bytes_decoded(&self) -> usize {
+        self.len
+    }
+
+    /// Discards any partially decoded varint.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Decodes a varint from the start of `data`, which may end before the
+/// varint does.
+///
+/// Unlike [`decode_u64()`], data which ends early is not an error, so the
+/// caller can wait for [`Partial::Incomplete::needed`] more bytes and retry.
+///
+/// # Errors
+/// - [`Error::DataLoss`] - The varint is longer than
+///   [`MAX_VARINT64_SIZE_BYTES`].
+pub fn decode_u64_partial(data: &[u8]) -> Result<Partial<u64>> {
+    Decoder::new().decode(data)
+}
+
+/// Decodes a zig-zag encoded varint from the start of `data`, which may end
+/// before the varint does.
+///
+/// See [`decode_u64_partial()`].
+///
+/// # Errors
+/// - [`Error::DataLoss`] - The varint is longer than
+///   [`MAX_VARINT64_SIZE_BYTES`].
+pub fn decode_i64_partial(data: &[u8]) -> Result<Partial<i64>> {
+    Ok(match decode_u64_partial(data)? {
+        Partial::Complete { len, value } => Partial::Complete {
+            len,
+            value: zig_zag_decode(value),
+        },
+        Partial::Incomplete { needed } => Partial::Incomplete { needed },
+    })
+}
+
+/// Returns the number of bytes used to encode `value` as a varint.
+pub const fn encoded_size(value: u64) -> usize {
+    let bits = (u64::BITS - value.leading_zeros()) as usize;
+    if bits == 0 {
+        1
+    } else {
+        bits.div_ceil(7)
+    }
+}
+
+/// Returns the number of bytes used to zig-zag encode `value` as a varint.
+pub const fn zig_zag_encoded_size(value: i64) -> usize {
+    encoded_size(zig_zag_encode(value))
+}
+
+/// Returns the number of bytes used to encode a 128 bit `value` as a varint.
+pub const fn encoded_size_u128(value: u128) -> usize {
+    let bits = (u128::BITS - value.leading_zeros()) as usize;
+    if bits == 0 {
+        1
+    } else {
+        bits.div_ceil(7)
+    }
+}
+
+/// Returns the number of bytes used to zig-zag encode a 128 bit `value` as a
+/// varint.
+pub const fn zig_zag_encoded_size_i128(value: i128) -> usize {
+    encoded_size_u128(zig_zag_encode_i128(value))
+}
+
+/// Zig-zag encodes a signed integer.
+///
+/// This maps small negative numbers to small, unsigned positive numbers,
+/// which improves their density for LEB128 encoding.
+///
+/// ZigZag encoding works by moving the sign bit from the most-significant bit
+/// to the least-significant bit. For the signed k-bit integer n, the formula
+/// is
+///
+/// ```text
+/// (n << 1) ^ (n >> (k - 1))
+/// ```
+///
+/// See the [Protocol Buffers encoding documentation](https://developers.google.com/protocol-buffers/docs/encoding#types).
+pub const fn zig_zag_encode(value: i64) -> u64 {
     ((value as u64) << 1) ^ ((value >> (i64::BITS - 1)) as u64)
 }
 
-fn zig_zag_decode(value: u64) -> i64 {
-    let value = Wrapping(value);
-    ((value >> 1) ^ (!(value & Wrapping(1)) + Wrapping(1))).0 as i64
+/// Decodes a zig-zag encoded integer.
+pub const fn zig_zag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Zig-zag encodes a 128 bit signed integer.  See [`zig_zag_encode()`].
+pub const fn zig_zag_encode_i128(value: i128) -> u128 {
+    ((value as u128) << 1) ^ ((value >> (i128::BITS - 1)) as u128)
+}
+
+/// Decodes a zig-zag encoded 128 bit integer.
+pub const fn zig_zag_decode_i128(value: u128) -> i128 {
+    ((value >> 1) as i128) ^ -((value & 1) as i128)
 }
 
 #[cfg(test)]
@@ -216,6 +523,40 @@ mod test {
         ]
     }
 
+    fn success_cases_u64<T>() -> Vec<(Vec<u8>, T)>
+    where
+        T: From<u64>,
+    {
+        vec![
+            // From varint_test.cc EncodeSizeUnsigned64_MultiByte.
+            (
+                vec![0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+                (u64::MAX - 1).into(),
+            ),
+            (
+                vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+                u64::MAX.into(),
+            ),
+        ]
+    }
+
+    fn success_cases_i64<T>() -> Vec<(Vec<u8>, T)>
+    where
+        T: From<i64>,
+    {
+        vec![
+            // From varint_test.cc EncodeSizeSigned64_MultiByte.
+            (
+                vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+                i64::MIN.into(),
+            ),
+            (
+                vec![0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+                i64::MAX.into(),
+            ),
+        ]
+    }
+
     fn success_cases_i32<T>() -> Vec<(Vec<u8>, T)>
     where
         T: From<i32>,
@@ -274,6 +615,7 @@ mod test {
         for case in success_cases_u8::<u64>()
             .into_iter()
             .chain(success_cases_u32::<u64>())
+            .chain(success_cases_u64::<u64>())
         {
             assert_eq!(u64::varint_decode(&case.0), Ok((case.0.len(), case.1)));
         }
@@ -286,6 +628,7 @@ mod test {
         for case in success_cases_i8::<i64>()
             .into_iter()
             .chain(success_cases_i32::<i64>())
+            .chain(success_cases_i64::<i64>())
         {
             assert_eq!(i64::varint_decode(&case.0), Ok((case.0.len(), case.1)));
         }
@@ -344,6 +687,7 @@ mod test {
         for case in success_cases_u8::<u64>()
             .into_iter()
             .chain(success_cases_u32::<u64>())
+            .chain(success_cases_u64::<u64>())
         {
             let mut buffer = [0u8; 64];
             let len = case.1.varint_encode(&mut buffer).unwrap();
@@ -357,6 +701,7 @@ mod test {
         for case in success_cases_i8::<i64>()
             .into_iter()
             .chain(success_cases_i32::<i64>())
+            .chain(success_cases_i64::<i64>())
         {
             let mut buffer = [0u8; 64];
             let len = case.1.varint_encode(&mut buffer).unwrap();
@@ -364,4 +709,133 @@ mod test {
             assert_eq!(&buffer[0..len], case.0);
         }
     }
+
+    #[test]
+    fn zig_zag_round_trips() {
+        for value in [0, 1, -1, 2, -2, i32::MIN.into(), i64::MAX, i64::MIN] {
+            assert_eq!(zig_zag_decode(zig_zag_encode(value)), value);
+        }
+        assert_eq!(zig_zag_encode(-1), 1);
+        assert_eq!(zig_zag_encode(i64::MIN), u64::MAX);
+    }
+
+    #[test]
+    fn encoded_size_matches_encoding() {
+        for value in [0, 127, 128, u32::MAX.into(), u64::MAX] {
+            let mut buffer = [0u8; MAX_VARINT64_SIZE_BYTES];
+            assert_eq!(encoded_size(value), encode_u64(&mut buffer, value).unwrap());
+        }
+        assert_eq!(encoded_size(u32::MAX.into()), MAX_VARINT32_SIZE_BYTES);
+        assert_eq!(encoded_size(u64::MAX), MAX_VARINT64_SIZE_BYTES);
+        assert_eq!(zig_zag_encoded_size(-64), 1);
+        assert_eq!(zig_zag_encoded_size(64), 2);
+    }
+
+    #[test]
+    fn signed_functions_zig_zag_encode() {
+        let mut buffer = [0u8; MAX_VARINT64_SIZE_BYTES];
+        assert_eq!(encode_i64(&mut buffer, -65), Ok(2));
+        assert_eq!(&buffer[..2], &[0x81, 0x01]);
+        assert_eq!(decode_i64(&buffer), Ok((2, -65)));
+    }
+
+    #[test]
+    fn decode_rejects_overlong_varint() {
+        assert_eq!(decode_u64(&[0xff; 11]), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn partial_decode_reports_incomplete_data() {
+        assert_eq!(
+            decode_u64_partial(&[]),
+            Ok(Partial::Incomplete { needed: 1 })
+        );
+        assert_eq!(
+            decode_u64_partial(&[0x96]),
+            Ok(Partial::Incomplete { needed: 1 })
+        );
+        assert_eq!(
+            decode_u64_partial(&[0x96, 0x01, 0xff]),
+            Ok(Partial::Complete { len: 2, value: 150 })
+        );
+        assert_eq!(
+            decode_i64_partial(&[0x81, 0x01]),
+            Ok(Partial::Complete { len: 2, value: -65 })
+        );
+        assert_eq!(decode_u64_partial(&[0xff; 11]), Err(Error::DataLoss));
+    }
+
+    #[test]
+    fn decoder_resumes_across_pieces() {
+        let mut decoder = Decoder::new();
+        for piece in [[0xff, 0xff], [0xff, 0xff]] {
+            assert_eq!(
+                decoder.decode(&piece),
+                Ok(Partial::Incomplete { needed: 1 })
+            );
+        }
+        assert_eq!(decoder.bytes_decoded(), 4);
+        assert_eq!(
+            decoder.decode(&[0x0f, 0x01]),
+            Ok(Partial::Complete {
+                len: 1,
+                value: u32::MAX.into()
+            })
+        );
+        // The decoder is reset after each varint.
+        assert_eq!(
+            decoder.decode(&[0x01]),
+            Ok(Partial::Complete { len: 1, value: 1 })
+        );
+
+        for _ in 0..MAX_VARINT64_SIZE_BYTES {
+            assert_eq!(
+                decoder.decode(&[0x80]),
+                Ok(Partial::Incomplete { needed: 1 })
+            );
+        }
+        assert_eq!(decoder.decode(&[0x00]), Err(Error::DataLoss));
+        assert_eq!(decoder.bytes_decoded(), 0);
+    }
+
+    #[test]
+    fn round_trip_test_u128() {
+        let mut u128_max = vec![0xff; MAX_VARINT128_SIZE_BYTES - 1];
+        u128_max.push(0x03);
+        for case in success_cases_u8::<u128>()
+            .into_iter()
+            .chain(success_cases_u32::<u128>())
+            .chain(success_cases_u64::<u128>())
+            .chain([(u128_max, u128::MAX)])
+        {
+            let mut buffer = [0u8; MAX_VARINT128_SIZE_BYTES];
+            let len = case.1.varint_encode(&mut buffer).unwrap();
+            assert_eq!(&buffer[..len], case.0);
+            assert_eq!(encoded_size_u128(case.1), len);
+            assert_eq!(u128::varint_decode(&buffer), Ok((len, case.1)));
+        }
+        assert_eq!(decode_u128(&[0xff; 20]), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn round_trip_test_i128() {
+        let mut i128_min = vec![0xff; MAX_VARINT128_SIZE_BYTES - 1];
+        i128_min.push(0x03);
+        for case in success_cases_i8::<i128>()
+            .into_iter()
+            .chain(success_cases_i32::<i128>())
+            .chain(success_cases_i64::<i128>())
+            .chain([(i128_min, i128::MIN)])
+        {
+            let mut buffer = [0u8; MAX_VARINT128_SIZE_BYTES];
+            let len = case.1.varint_encode(&mut buffer).unwrap();
+            assert_eq!(&buffer[..len], case.0);
+            assert_eq!(zig_zag_encoded_size_i128(case.1), len);
+            assert_eq!(i128::varint_decode(&buffer), Ok((len, case.1)));
+        }
+        assert_eq!(
+            zig_zag_decode_i128(zig_zag_encode_i128(i128::MAX)),
+            i128::MAX
+        );
+    }
 }
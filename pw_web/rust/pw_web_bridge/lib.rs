@@ -0,0 +1,493 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! `pw_web_bridge` connects a device's HDLC stream to browser tooling, such
+//! as the web console and log viewer, over WebSockets.
+//!
+//! A [`Bridge`] reads HDLC frames from the device:
+//!
+//! - Frames sent to the log address ([`pw_hdlc::DEFAULT_LOG_ADDRESS`] by
+//!   default) are detokenized and sent to every client as a JSON text message
+//!   in the format `pw_console` logs JSON in (see [`LogRecord::to_json()`]).
+//! - Frames sent to the RPC address ([`pw_hdlc::DEFAULT_RPC_ADDRESS`] by
+//!   default) are sent to every client as binary messages with the RPC
+//!   packet.
+//!
+//! Binary messages from clients are RPC packets, which are sent to the device
+//! in HDLC frames to the RPC address.  This lets a browser make RPCs through
+//! the bridge.
+//!
+//! ```no_run
+//! use std::net::{TcpListener, TcpStream};
+//!
+//! use pw_detokenizer::{Database, Detokenizer};
+//! use pw_stream::TcpAdapter;
+//! use pw_web_bridge::{Bridge, DEFAULT_PORT};
+//!
+//! let device = TcpStream::connect("localhost:33000").unwrap();
+//! let listener = TcpListener::bind(("localhost", DEFAULT_PORT)).unwrap();
+//! let detokenizer = Detokenizer::new(Database::read("tokens.csv")?);
+//!
+//! Bridge::new(detokenizer).run(
+//!     TcpAdapter::new(device.try_clone().unwrap()),
+//!     TcpAdapter::new(device),
+//!     listener,
+//! )?;
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! The `pw_web_bridge` binary runs a bridge for a serial port or socket.
+//!
+//! *Note*: This module requires `std`.
+#![deny(missing_docs)]
+
+use std::collections::BTreeMap;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pw_detokenizer::Detokenizer;
+use pw_hdlc::{Decoder, DEFAULT_LOG_ADDRESS, DEFAULT_RPC_ADDRESS};
+use pw_status::{Error, Result};
+use pw_stream::{Read, TcpAdapter, Write};
+
+pub mod websocket;
+
+use websocket::{Message, MessageReader};
+
+/// The port the bridge serves WebSockets on by default.
+pub const DEFAULT_PORT: u16 = 8765;
+
+/// The size of the largest HDLC frame the bridge decodes.
+pub const MAX_FRAME_SIZE: usize = 4096;
+
+/// A log message decoded from the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogRecord {
+    /// The message text.
+    pub message: String,
+    /// The message's metadata fields, such as `module` and `file`.
+    pub fields: BTreeMap<String, String>,
+    /// When the message was received.
+    pub time: SystemTime,
+}
+
+impl LogRecord {
+    /// Creates a record from a log message, which may have metadata fields
+    /// encoded as `■key♦value` as `pw_log_tokenized` does.  The `msg` field
+    /// is the message text.
+    pub fn parse(text: &str, time: SystemTime) -> Self {
+        let mut record = Self {
+            message: text.to_string(),
+            fields: BTreeMap::new(),
+            time,
+        };
+        // Only look for fields if the message starts with one.
+        let Some(mut rest) = text.strip_prefix('■') else {
+            return record;
+        };
+        let mut fields = BTreeMap::new();
+        loop {
+            let Some((key, value)) = rest.split_once('♦') else {
+                return record;
+            };
+            let is_key = key.starts_with(|c: char| c.is_ascii_alphabetic())
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !is_key {
+                return record;
+            }
+            let (value, next) = match value.split_once('■') {
+                Some((value, next)) => (value, Some(next)),
+                None => (value, None),
+            };
+            fields.insert(key.to_string(), value.to_string());
+            match next {
+                Some(next) => rest = next,
+                None => break,
+            }
+        }
+        if let Some(message) = fields.remove("msg") {
+            record.message = message;
+        }
+        record.fields = fields;
+        record
+    }
+
+    /// Returns the record as JSON in the format of `pw_console`'s JSON logs,
+    /// which the web log viewer reads.
+    pub fn to_json(&self) -> String {
+        let since_epoch = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut json = format!(
+            "{{\"message\": {}, \"levelno\": 20, \"levelname\": \"INFO\", \
+             \"time\": \"{}.{:06}\", \"time_string\": \"{}\"",
+            json_string(&self.message),
+            since_epoch.as_secs(),
+            since_epoch.subsec_micros(),
+            utc_time_string(since_epoch.as_secs()),
+        );
+        if !self.fields.is_empty() {
+            let fields: Vec<String> = self
+                .fields
+                .iter()
+                .map(|(key, value)| format!("{}: {}", json_string(key), json_string(value)))
+                .collect();
+            json.push_str(&format!(", \"fields\": {{{}}}", fields.join(", ")));
+        }
+        json.push('}');
+        json
+    }
+}
+
+// Quotes and escapes `text` as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut json = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if u32::from(c) < 0x20 => json.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// Formats seconds since the Unix epoch as an ISO 8601 UTC time, such as
+// `2024-03-14T15:09:26`.
+fn utc_time_string(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let seconds = seconds % 86400;
+    // Converts days to a civil date, from Howard Hinnant's date algorithms.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+type Client = Arc<Mutex<TcpAdapter>>;
+
+/// Bridges a device's HDLC stream to WebSocket clients.
+pub struct Bridge {
+    detokenizer: Detokenizer,
+    log_address: u64,
+    rpc_address: u64,
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+impl Bridge {
+    /// Creates a bridge which detokenizes logs with `detokenizer`.
+    pub fn new(detokenizer: Detokenizer) -> Self {
+        Self {
+            detokenizer,
+            log_address: DEFAULT_LOG_ADDRESS,
+            rpc_address: DEFAULT_RPC_ADDRESS,
+            clients: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Sets the HDLC address of log frames.
+    #[must_use]
+    pub fn with_log_address(mut self, address: u64) -> Self {
+        self.log_address = address;
+        self
+    }
+
+    /// Sets the HDLC address of RPC frames.
+    #[must_use]
+    pub fn with_rpc_address(mut self, address: u64) -> Self {
+        self.rpc_address = address;
+        self
+    }
+
+    /// Decodes a log frame's payload to text.
+    ///
+    /// Payloads are detokenized if they are Base64 encoded (starting with
+    /// `$`) or binary tokenized messages in the bridge's database, and are
+    /// otherwise taken as plain text.
+    pub fn decode_log(&self, payload: &[u8]) -> String {
+        let text = String::from_utf8_lossy(payload);
+        let text = text.trim_end_matches(['\r', '\n']);
+        if text.starts_with('$') {
+            if let Some(message) = self.detokenizer.detokenize_base64(text) {
+                return message;
+            }
+        } else if let Some(message) = self.detokenizer.detokenize(payload) {
+            return message;
+        }
+        text.to_string()
+    }
+
+    /// Serves WebSocket clients on `listener` and bridges them to the
+    /// device, which is read from `device_reader` and written to with
+    /// `device_writer`.
+    ///
+    /// Returns when the device's stream ends.  Clients are served on
+    /// background threads, which continue until the process exits.
+    ///
+    /// # Errors
+    /// Returns any error reading from the device.
+    pub fn run(
+        self,
+        mut device_reader: impl Read,
+        device_writer: impl Write + Send + 'static,
+        listener: TcpListener,
+    ) -> Result<()> {
+        let device_writer = Arc::new(Mutex::new(device_writer));
+        let clients = self.clients.clone();
+        let rpc_address = self.rpc_address;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let clients = clients.clone();
+                let device_writer = device_writer.clone();
+                thread::spawn(move || serve_client(stream, &clients, &device_writer, rpc_address));
+            }
+        });
+
+        let mut decoder = Decoder::new(vec![0; MAX_FRAME_SIZE]);
+        let mut data = [0u8; 1024];
+        loop {
+            let len = match device_reader.read(&mut data) {
+                Ok(0) | Err(Error::OutOfRange) => return Ok(()),
+                Ok(len) => len,
+                Err(e) => return Err(e),
+            };
+            let mut messages = Vec::new();
+            decoder.process(&data[..len], |frame| {
+                // Frames which fail to decode are counted by the decoder.
+                let Ok(frame) = frame else {
+                    return;
+                };
+                if frame.address() == self.log_address {
+                    let text = self.decode_log(frame.payload());
+                    let record = LogRecord::parse(&text, SystemTime::now());
+                    messages.push(Message::Text(record.to_json()));
+                } else if frame.address() == self.rpc_address {
+                    messages.push(Message::Binary(frame.payload().to_vec()));
+                }
+            });
+            for message in &messages {
+                self.broadcast(message);
+            }
+        }
+    }
+
+    // Sends `message` to every client, dropping clients which can not be
+    // written to.
+    fn broadcast(&self, message: &Message) {
+        self.clients.lock().unwrap().retain(|client| {
+            websocket::write_message(&mut *client.lock().unwrap(), message).is_ok()
+        });
+    }
+}
+
+// Performs the opening handshake with a client and relays the RPC packets it
+// sends to the device until it disconnects.
+fn serve_client(
+    stream: TcpStream,
+    clients: &Mutex<Vec<Client>>,
+    device_writer: &Mutex<impl Write>,
+    rpc_address: u64,
+) {
+    let Ok(writer) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = TcpAdapter::new(stream);
+    let client = Arc::new(Mutex::new(TcpAdapter::new(writer)));
+    if websocket::accept(&mut reader).is_err() {
+        return;
+    }
+    clients.lock().unwrap().push(client.clone());
+
+    let mut messages = MessageReader::new();
+    loop {
+        let result = match messages.read(&mut reader) {
+            Ok(Message::Binary(packet)) => {
+                pw_hdlc::write_ui_frame(&mut *device_writer.lock().unwrap(), rpc_address, &packet)
+            }
+            Ok(Message::Ping(data)) => {
+                websocket::write_message(&mut *client.lock().unwrap(), &Message::Pong(data))
+            }
+            Ok(Message::Text(_) | Message::Pong(_)) => Ok(()),
+            Ok(Message::Close) => {
+                let _ = websocket::write_message(&mut *client.lock().unwrap(), &Message::Close);
+                Err(Error::Cancelled)
+            }
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            break;
+        }
+    }
+    clients
+        .lock()
+        .unwrap()
+        .retain(|other| !Arc::ptr_eq(other, &client));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use pw_detokenizer::Database;
+    use pw_stream::IoAdapter;
+
+    use super::*;
+
+    // Connects to a bridge as a browser would, returning the connection.
+    fn connect_client(port: u16) -> TcpAdapter {
+        let mut client = TcpAdapter::new(TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap());
+        client
+            .write_all(
+                b"GET / HTTP/1.1\r\n\
+                  Upgrade: websocket\r\n\
+                  Connection: Upgrade\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            )
+            .unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            client.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        assert!(response.starts_with(b"HTTP/1.1 101 "));
+        client
+    }
+
+    // Writes a masked frame, as clients send them.
+    fn send_client_message(client: &mut TcpAdapter, opcode: u8, payload: &[u8]) {
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        client.write_all(&frame).unwrap();
+    }
+
+    #[test]
+    fn parses_log_fields() {
+        let time = UNIX_EPOCH + Duration::from_micros(1_710_428_966_500_000);
+        let record = LogRecord::parse("■msg♦Battery \"low\"■module♦power■file♦main.cc", time);
+        assert_eq!(record.message, "Battery \"low\"");
+        assert_eq!(record.fields.len(), 2);
+        assert_eq!(
+            record.to_json(),
+            "{\"message\": \"Battery \\\"low\\\"\", \"levelno\": 20, \"levelname\": \"INFO\", \
+             \"time\": \"1710428966.500000\", \"time_string\": \"2024-03-14T15:09:26\", \
+             \"fields\": {\"file\": \"main.cc\", \"module\": \"power\"}}"
+        );
+
+        let record = LogRecord::parse("Plain ■text♦", time);
+        assert_eq!(record.message, "Plain ■text♦");
+        assert!(record.fields.is_empty());
+    }
+
+    #[test]
+    fn formats_utc_times() {
+        assert_eq!(utc_time_string(0), "1970-01-01T00:00:00");
+        assert_eq!(utc_time_string(951_825_600), "2000-02-29T12:00:00");
+        assert_eq!(utc_time_string(1_735_689_599), "2024-12-31T23:59:59");
+    }
+
+    #[test]
+    fn decodes_logs() {
+        let bridge = Bridge::new(Detokenizer::new(Database::parse_csv(
+            "0000002a,          ,\"Count: %d\"\n",
+        )));
+        assert_eq!(bridge.decode_log(&[0x2a, 0, 0, 0, 0x06]), "Count: 3");
+        assert_eq!(bridge.decode_log(b"$KgAAAAY=\n"), "Count: 3");
+        assert_eq!(bridge.decode_log(b"Plain text\r\n"), "Plain text");
+        assert_eq!(bridge.decode_log(b"$invalid"), "$invalid");
+    }
+
+    #[test]
+    fn bridges_logs_and_rpcs() {
+        let device_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let bridge_device = TcpStream::connect(device_listener.local_addr().unwrap()).unwrap();
+        let mut device = TcpAdapter::new(device_listener.accept().unwrap().0);
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let bridge = Bridge::new(Detokenizer::new(Database::parse_csv(
+            "0000002a,          ,\"■msg♦Count: %d■module♦test\"\n",
+        )));
+        let bridge_thread = thread::spawn(move || {
+            bridge.run(
+                TcpAdapter::new(bridge_device.try_clone().unwrap()),
+                TcpAdapter::new(bridge_device),
+                listener,
+            )
+        });
+
+        let mut client = connect_client(port);
+        let mut messages = MessageReader::new();
+        // The bridge answers pings once the client is connected.
+        send_client_message(&mut client, 0x9, b"ready");
+        assert_eq!(
+            messages.read(&mut client),
+            Ok(Message::Pong(b"ready".to_vec()))
+        );
+
+        pw_hdlc::write_ui_frame(&mut device, DEFAULT_LOG_ADDRESS, &[0x2a, 0, 0, 0, 0x06]).unwrap();
+        pw_hdlc::write_ui_frame(&mut device, DEFAULT_RPC_ADDRESS, b"response").unwrap();
+        let Ok(Message::Text(json)) = messages.read(&mut client) else {
+            panic!("expected a log message");
+        };
+        assert!(json.starts_with("{\"message\": \"Count: 3\", \"levelno\": 20"));
+        assert!(json.ends_with("\"fields\": {\"module\": \"test\"}}"));
+        assert_eq!(
+            messages.read(&mut client),
+            Ok(Message::Binary(b"response".to_vec()))
+        );
+
+        send_client_message(&mut client, 0x2, b"request");
+        let mut frame = IoAdapter::new(Vec::new());
+        pw_hdlc::write_ui_frame(&mut frame, DEFAULT_RPC_ADDRESS, b"request").unwrap();
+        let frame = frame.into_inner();
+        let mut received = vec![0u8; frame.len()];
+        device.read_exact(&mut received).unwrap();
+        assert_eq!(received, frame);
+
+        send_client_message(&mut client, 0x8, &[]);
+        assert_eq!(messages.read(&mut client), Ok(Message::Close));
+
+        drop(device);
+        assert_eq!(bridge_thread.join().unwrap(), Ok(()));
+    }
+}
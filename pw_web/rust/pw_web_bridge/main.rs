@@ -0,0 +1,151 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! Bridges a device on a serial port or socket to browser tooling over
+//! WebSockets.
+//!
+//! ```text
+//! pw_web_bridge (--device PATH [--baud RATE] | --socket HOST:PORT)
+//!               [--database PATH]... [--port PORT]
+//!               [--log-address ADDRESS] [--rpc-address ADDRESS]
+//! ```
+
+use std::fs::File;
+use std::net::{TcpListener, TcpStream};
+use std::process::ExitCode;
+use std::str::FromStr;
+
+use pw_detokenizer::{Database, Detokenizer};
+use pw_hdlc::{DEFAULT_LOG_ADDRESS, DEFAULT_RPC_ADDRESS};
+use pw_status::{Error, Result};
+use pw_stream::{IoAdapter, TcpAdapter};
+use pw_stream_serial::SerialPort;
+use pw_web_bridge::{Bridge, DEFAULT_PORT};
+
+const USAGE: &str = "\
+Usage: pw_web_bridge (--device PATH [--baud RATE] | --socket HOST:PORT)
+                     [--database PATH]... [--port PORT]
+                     [--log-address ADDRESS] [--rpc-address ADDRESS]
+
+Options:
+  --device PATH          Serial port the device is connected to
+  --baud RATE            Serial port baud rate [default: 115200]
+  --socket HOST:PORT     Socket the device or simulator is listening on
+  --database PATH        Token database file or directory; may be repeated
+  --port PORT            Port to serve WebSockets on [default: 8765]
+  --log-address ADDRESS  HDLC address of log frames [default: 1]
+  --rpc-address ADDRESS  HDLC address of RPC frames [default: 82]";
+
+enum Connection {
+    Serial { path: String, baud_rate: u32 },
+    Socket(String),
+}
+
+struct Args {
+    connection: Connection,
+    databases: Vec<String>,
+    port: u16,
+    log_address: u64,
+    rpc_address: u64,
+}
+
+fn parse_number<T: FromStr>(arg: &str, value: String) -> std::result::Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {arg}: {value}"))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> std::result::Result<Args, String> {
+    let mut device = None;
+    let mut baud_rate = 115200;
+    let mut socket = None;
+    let mut databases = Vec::new();
+    let mut port = DEFAULT_PORT;
+    let mut log_address = DEFAULT_LOG_ADDRESS;
+    let mut rpc_address = DEFAULT_RPC_ADDRESS;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} requires a value"));
+        match arg.as_str() {
+            "--device" => device = Some(value()?),
+            "--baud" => baud_rate = parse_number(&arg, value()?)?,
+            "--socket" => socket = Some(value()?),
+            "--database" => databases.push(value()?),
+            "--port" => port = parse_number(&arg, value()?)?,
+            "--log-address" => log_address = parse_number(&arg, value()?)?,
+            "--rpc-address" => rpc_address = parse_number(&arg, value()?)?,
+            "-h" | "--help" => return Err(String::new()),
+            _ => return Err(format!("unknown argument: {arg}")),
+        }
+    }
+
+    let connection = match (device, socket) {
+        (Some(path), None) => Connection::Serial { path, baud_rate },
+        (None, Some(address)) => Connection::Socket(address),
+        _ => return Err("exactly one of --device or --socket is required".to_string()),
+    };
+    Ok(Args {
+        connection,
+        databases,
+        port,
+        log_address,
+        rpc_address,
+    })
+}
+
+fn run(args: Args) -> Result<()> {
+    let mut database = Database::new();
+    for path in &args.databases {
+        database.merge(Database::read(path)?);
+    }
+    let bridge = Bridge::new(Detokenizer::new(database))
+        .with_log_address(args.log_address)
+        .with_rpc_address(args.rpc_address);
+    let listener = TcpListener::bind(("localhost", args.port)).map_err(|_| Error::Unavailable)?;
+    if let Ok(address) = listener.local_addr() {
+        println!("Serving WebSockets on ws://{address}");
+    }
+
+    match args.connection {
+        Connection::Serial { path, baud_rate } => {
+            let port: File = SerialPort::open(path, baud_rate)?.into_inner();
+            let writer = port.try_clone().map_err(|_| Error::Unavailable)?;
+            bridge.run(IoAdapter::new(port), IoAdapter::new(writer), listener)
+        }
+        Connection::Socket(address) => {
+            let socket = TcpStream::connect(address).map_err(|_| Error::Unavailable)?;
+            let writer = socket.try_clone().map_err(|_| Error::Unavailable)?;
+            bridge.run(TcpAdapter::new(socket), TcpAdapter::new(writer), listener)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("error: {message}\n");
+            }
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,530 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+//! A minimal [RFC 6455](https://www.rfc-editor.org/rfc/rfc6455) WebSocket
+//! server, sufficient for serving browser tooling.
+//!
+//! [`accept()`] performs the opening handshake on a connection, after which
+//! a [`MessageReader`] and [`write_message()`] exchange messages on it.
+//! Extensions and subprotocols are not supported.
+
+use pw_status::{Error, Result};
+use pw_stream::{Read, Write};
+
+/// The GUID appended to a client's key to compute the handshake's accept
+/// key.
+pub const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The size of the largest message a [`MessageReader`] accepts.
+pub const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+// The size of the largest handshake request accepted.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+mod opcode {
+    pub const CONTINUATION: u8 = 0x0;
+    pub const TEXT: u8 = 0x1;
+    pub const BINARY: u8 = 0x2;
+    pub const CLOSE: u8 = 0x8;
+    pub const PING: u8 = 0x9;
+    pub const PONG: u8 = 0xa;
+}
+
+/// A WebSocket message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// A UTF-8 text message.
+    Text(String),
+    /// A binary message.
+    Binary(Vec<u8>),
+    /// A ping, which should be answered with a [`Message::Pong`] with the
+    /// same payload.
+    Ping(Vec<u8>),
+    /// A pong, sent in reply to a [`Message::Ping`].
+    Pong(Vec<u8>),
+    /// A request to close the connection.
+    Close,
+}
+
+/// Returns the `Sec-WebSocket-Accept` value for a client's
+/// `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    let digest = sha1.finalize();
+    let mut encoded = [0u8; pw_base64::encoded_size(20)];
+    pw_base64::encode_str(&digest, &mut encoded)
+        .expect("buffer fits the digest")
+        .to_string()
+}
+
+/// Reads a WebSocket opening handshake request from `stream` and accepts it.
+///
+/// Returns the request's path.
+///
+/// # Errors
+/// - [`Error::InvalidArgument`] - The request is not a WebSocket upgrade
+///   request.  A `400 Bad Request` response is sent.
+/// - [`Error::ResourceExhausted`] - The request is too large.
+/// - Any error from `stream`.
+pub fn accept(stream: &mut (impl Read + Write)) -> Result<String> {
+    let request = read_request(stream)?;
+    match parse_request(&request) {
+        Some((path, key)) => {
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            );
+            stream.write_all(response.as_bytes())?;
+            stream.flush()?;
+            Ok(path.to_string())
+        }
+        None => {
+            stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+            stream.flush()?;
+            Err(Error::InvalidArgument)
+        }
+    }
+}
+
+// Reads the request line and headers, one byte at a time so that no data
+// after them is consumed.
+fn read_request(stream: &mut impl Read) -> Result<String> {
+    let mut request = Vec::new();
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() == MAX_REQUEST_SIZE {
+            return Err(Error::ResourceExhausted);
+        }
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        request.push(byte[0]);
+    }
+    String::from_utf8(request).map_err(|_| Error::InvalidArgument)
+}
+
+// Returns the path and `Sec-WebSocket-Key` of a WebSocket upgrade request.
+fn parse_request(request: &str) -> Option<(&str, &str)> {
+    let mut lines = request.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let (Some("GET"), Some(path)) = (request_line.next(), request_line.next()) else {
+        return None;
+    };
+
+    let mut upgrade = false;
+    let mut key = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("upgrade") {
+            upgrade = value.eq_ignore_ascii_case("websocket");
+        } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+            key = Some(value);
+        }
+    }
+    upgrade.then_some((path, key?))
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+fn read_frame(stream: &mut impl Read) -> Result<Frame> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header)?;
+    let fin = header[0] & 0x80 != 0;
+    // Reserved bits are only used by extensions.
+    if header[0] & 0x70 != 0 {
+        return Err(Error::DataLoss);
+    }
+    let opcode = header[0] & 0x0f;
+    let masked = header[1] & 0x80 != 0;
+    let len = match header[1] & 0x7f {
+        126 => {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+    if len > MAX_MESSAGE_SIZE as u64 {
+        return Err(Error::ResourceExhausted);
+    }
+    let mut mask = [0u8; 4];
+    if masked {
+        stream.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+/// Reads messages from a connection, reassembling fragmented messages.
+///
+/// Control messages, such as pings, are returned as they are received, even
+/// if they arrive between the fragments of another message.
+#[derive(Default)]
+pub struct MessageReader {
+    // The opcode and payload of a fragmented message being received.
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl MessageReader {
+    /// Creates a reader for a new connection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the next message from `stream`.
+    ///
+    /// # Errors
+    /// - [`Error::DataLoss`] - The stream does not contain valid WebSocket
+    ///   frames, or a text message is not UTF-8.
+    /// - [`Error::ResourceExhausted`] - The message is larger than
+    ///   [`MAX_MESSAGE_SIZE`].
+    /// - Any error from `stream`.
+    pub fn read(&mut self, stream: &mut impl Read) -> Result<Message> {
+        loop {
+            let frame = read_frame(stream)?;
+            match frame.opcode {
+                opcode::CLOSE => return Ok(Message::Close),
+                opcode::PING => return Ok(Message::Ping(frame.payload)),
+                opcode::PONG => return Ok(Message::Pong(frame.payload)),
+                _ => (),
+            }
+            let (opcode, payload) = match (frame.opcode, &mut self.partial) {
+                (opcode::TEXT | opcode::BINARY, None) => {
+                    self.partial.insert((frame.opcode, frame.payload))
+                }
+                (opcode::CONTINUATION, Some(partial)) => {
+                    if partial.1.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+                        self.partial = None;
+                        return Err(Error::ResourceExhausted);
+                    }
+                    partial.1.extend_from_slice(&frame.payload);
+                    partial
+                }
+                _ => return Err(Error::DataLoss),
+            };
+            if !frame.fin {
+                continue;
+            }
+            let message = if *opcode == opcode::TEXT {
+                String::from_utf8(std::mem::take(payload))
+                    .map(Message::Text)
+                    .map_err(|_| Error::DataLoss)
+            } else {
+                Ok(Message::Binary(std::mem::take(payload)))
+            };
+            self.partial = None;
+            return message;
+        }
+    }
+}
+
+/// Writes `message` to `stream` as a single unmasked frame, as servers send
+/// them.
+///
+/// # Errors
+/// Returns any error from `stream`.
+pub fn write_message(stream: &mut impl Write, message: &Message) -> Result<()> {
+    let (opcode, payload) = match message {
+        Message::Text(text) => (opcode::TEXT, text.as_bytes()),
+        Message::Binary(data) => (opcode::BINARY, data.as_slice()),
+        Message::Ping(data) => (opcode::PING, data.as_slice()),
+        Message::Pong(data) => (opcode::PONG, data.as_slice()),
+        Message::Close => (opcode::CLOSE, &[][..]),
+    };
+    let mut header = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => header.push(len as u8),
+        len @ 126..=0xffff => {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    stream.write_all_vectored(&[&header, payload])?;
+    stream.flush()
+}
+
+// SHA-1, which the opening handshake requires.  It is not used for security.
+struct Sha1 {
+    state: [u32; 5],
+    block: [u8; 64],
+    block_len: usize,
+    len: u64,
+}
+
+impl Sha1 {
+    const fn new() -> Self {
+        Self {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0],
+            block: [0; 64],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.block[self.block_len] = byte;
+            self.block_len += 1;
+            if self.block_len == self.block.len() {
+                self.process_block();
+                self.block_len = 0;
+            }
+        }
+        self.len += data.len() as u64;
+    }
+
+    fn finalize(mut self) -> [u8; 20] {
+        let bit_len = self.len * 8;
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bit_len.to_be_bytes());
+
+        let mut digest = [0u8; 20];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn process_block(&mut self) {
+        let mut w = [0u32; 80];
+        for (i, word) in self.block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pw_stream::Cursor;
+
+    use super::*;
+
+    // A connection which reads `input` and records what is written.
+    struct TestStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl TestStream {
+        fn new(input: &[u8]) -> Self {
+            Self {
+                input: Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for TestStream {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for TestStream {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // Encodes a masked frame, as clients send them.
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![u8::from(fin) << 7 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    #[test]
+    fn sha1_matches_test_vectors() {
+        let hash = |data: &[u8]| {
+            let mut sha1 = Sha1::new();
+            sha1.update(data);
+            sha1.finalize()
+        };
+        assert_eq!(
+            hash(b""),
+            *b"\xda\x39\xa3\xee\x5e\x6b\x4b\x0d\x32\x55\xbf\xef\x95\x60\x18\x90\xaf\xd8\x07\x09"
+        );
+        assert_eq!(
+            hash(b"abc"),
+            *b"\xa9\x99\x3e\x36\x47\x06\x81\x6a\xba\x3e\x25\x71\x78\x50\xc2\x6c\x9c\xd0\xd8\x9d"
+        );
+        assert_eq!(
+            hash(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            *b"\x84\x98\x3e\x44\x1c\x3b\xd2\x6e\xba\xae\x4a\xa1\xf9\x51\x29\xe5\xe5\x46\x70\xf1"
+        );
+    }
+
+    #[test]
+    fn computes_accept_key() {
+        // The example from RFC 6455 section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn accepts_upgrade_request() {
+        let mut stream = TestStream::new(
+            b"GET /logs HTTP/1.1\r\n\
+                        Host: localhost:8765\r\n\
+                        Upgrade: websocket\r\n\
+                        Connection: Upgrade\r\n\
+                        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                        Sec-WebSocket-Version: 13\r\n\r\n",
+        );
+        assert_eq!(accept(&mut stream).unwrap(), "/logs");
+        assert_eq!(
+            stream.output,
+            b"HTTP/1.1 101 Switching Protocols\r\n\
+              Upgrade: websocket\r\n\
+              Connection: Upgrade\r\n\
+              Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn rejects_other_requests() {
+        let mut stream = TestStream::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(accept(&mut stream), Err(Error::InvalidArgument));
+        assert!(stream.output.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn reads_messages() {
+        let mut data = client_frame(true, opcode::TEXT, b"hello");
+        data.extend(client_frame(false, opcode::BINARY, &[1; 200]));
+        data.extend(client_frame(true, opcode::PING, b"ping"));
+        data.extend(client_frame(true, opcode::CONTINUATION, &[2; 3]));
+        data.extend(client_frame(true, opcode::CLOSE, &[]));
+        let mut stream = Cursor::new(data);
+        let mut reader = MessageReader::new();
+
+        assert_eq!(reader.read(&mut stream), Ok(Message::Text("hello".into())));
+        assert_eq!(
+            reader.read(&mut stream),
+            Ok(Message::Ping(b"ping".to_vec()))
+        );
+        assert_eq!(
+            reader.read(&mut stream),
+            Ok(Message::Binary([[1; 200].as_slice(), &[2; 3]].concat()))
+        );
+        assert_eq!(reader.read(&mut stream), Ok(Message::Close));
+        assert_eq!(reader.read(&mut stream), Err(Error::OutOfRange));
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        let mut stream = Cursor::new(client_frame(true, opcode::CONTINUATION, b"x"));
+        assert_eq!(MessageReader::new().read(&mut stream), Err(Error::DataLoss));
+
+        let mut stream = Cursor::new(client_frame(true, opcode::TEXT, b"\xff"));
+        assert_eq!(MessageReader::new().read(&mut stream), Err(Error::DataLoss));
+
+        let mut stream = Cursor::new(vec![0x82, 127, 0, 0, 0, 0, 0, 0x20, 0, 0]);
+        assert_eq!(
+            MessageReader::new().read(&mut stream),
+            Err(Error::ResourceExhausted)
+        );
+    }
+
+    #[test]
+    fn writes_messages() {
+        let mut stream = TestStream::new(&[]);
+        write_message(&mut stream, &Message::Text("hi".into())).unwrap();
+        write_message(&mut stream, &Message::Binary(vec![7; 300])).unwrap();
+        write_message(&mut stream, &Message::Close).unwrap();
+        let data = &stream.output;
+        assert_eq!(data.len(), 310);
+        assert_eq!(&data[..4], b"\x81\x02hi");
+        assert_eq!(&data[4..8], &[0x82, 126, 0x01, 0x2c]);
+        assert_eq!(&data[8..308], &[7; 300]);
+        assert_eq!(&data[308..310], &[0x88, 0x00]);
+    }
+}
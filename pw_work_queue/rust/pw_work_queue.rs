@@ -0,0 +1,309 @@
+// Copyright 2024 The Pigweed Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not
+// use this file except in compliance with the License. You may obtain a copy of
+// the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS, WITHOUT
+// WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied. See the
+// License for the specific language governing permissions and limitations under
+// the License.
+
+//! `pw_work_queue` runs work items on a dedicated thread, so that threads and
+//! interrupts can move slow work off their time-critical paths.
+//!
+//! This is the Rust counterpart of the C++ `pw_work_queue` module.  A
+//! [`WorkQueue`] is a [`pw_thread::ThreadCore`] holding a bounded queue of
+//! work items.  Work is pushed from any thread or interrupt with
+//! [`WorkQueue::push_work()`], and the thread running the queue executes the
+//! items in the order they were pushed.
+//!
+//! Work items are any [`Work`], which includes closures.  Since a queue holds
+//! one type of item, a queue shared by several subsystems usually holds an
+//! `enum`, such as one which encodes deferred log messages, collects garbage
+//! in a key-value store, and expires stale RPC calls:
+//!
+//! ```
+//! use pw_thread::{Options, Thread};
+//! use pw_work_queue::{Work, WorkQueue};
+//!
+//! enum Housekeeping {
+//!     EncodeLogs,
+//!     CollectKvsGarbage,
+//!     ExpireRpcCalls,
+//! }
+//!
+//! impl Work for Housekeeping {
+//!     fn run(self) {
+//!         match self {
+//!             // Call `pw_log_backend::process_deferred_logs()`, etc.
+//!             Housekeeping::EncodeLogs => {}
+//!             Housekeeping::CollectKvsGarbage => {}
+//!             Housekeeping::ExpireRpcCalls => {}
+//!         }
+//!     }
+//! }
+//!
+//! static WORK_QUEUE: WorkQueue<Housekeeping, 8> = WorkQueue::new();
+//!
+//! let thread = Thread::spawn(&Options::default(), &WORK_QUEUE)?;
+//! WORK_QUEUE.push_work(Housekeeping::EncodeLogs)?;
+//! WORK_QUEUE.push_work(Housekeeping::CollectKvsGarbage)?;
+//!
+//! WORK_QUEUE.request_stop();
+//! thread.join()?;
+//! # Ok::<(), pw_status::Error>(())
+//! ```
+//!
+//! The queue is built on `pw_thread` and `pw_sync`, so it runs on whichever of
+//! their backends the target uses: threads on hosts with the `std` backends,
+//! or tasks on an RTOS.
+#![no_std]
+#![deny(missing_docs)]
+
+use pw_status::{Error, Result};
+use pw_sync::{InterruptSpinLock, ThreadNotification};
+use pw_thread::ThreadCore;
+
+/// An item of work executed by a [`WorkQueue`].
+pub trait Work: Send {
+    /// Does the work.
+    fn run(self);
+}
+
+impl<F: FnOnce() + Send> Work for F {
+    fn run(self) {
+        self()
+    }
+}
+
+struct State<W, const N: usize> {
+    items: [Option<W>; N],
+    // Index of the oldest item.
+    head: usize,
+    len: usize,
+    max_len: usize,
+    stop_requested: bool,
+}
+
+impl<W, const N: usize> State<W, N> {
+    fn pop(&mut self) -> Option<W> {
+        if self.len == 0 {
+            return None;
+        }
+        let work = self.items[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        work
+    }
+}
+
+/// A queue of up to `N` work items of type `W`, which are executed by the
+/// thread running the queue.
+///
+/// Work may be pushed from threads and interrupts.  Once a stop is requested
+/// with [`WorkQueue::request_stop()`], no more work is accepted and the
+/// thread returns after executing the work already queued.  A queue can not
+/// be restarted.
+pub struct WorkQueue<W: Work, const N: usize> {
+    state: InterruptSpinLock<State<W, N>>,
+    work_notification: ThreadNotification,
+}
+
+impl<W: Work, const N: usize> WorkQueue<W, N> {
+    /// Creates an empty work queue.
+    pub const fn new() -> Self {
+        Self {
+            state: InterruptSpinLock::new(State {
+                items: [const { None }; N],
+                head: 0,
+                len: 0,
+                max_len: 0,
+                stop_requested: false,
+            }),
+            work_notification: ThreadNotification::new(),
+        }
+    }
+
+    /// Queues `work` for execution by the work queue thread.
+    ///
+    /// # Errors
+    /// - [`Error::FailedPrecondition`] - A stop was requested, so work is no
+    ///   longer accepted.
+    /// - [`Error::ResourceExhausted`] - The queue is full.
+    pub fn push_work(&self, work: W) -> Result<()> {
+        self.with_state(|state| {
+            if state.stop_requested {
+                return Err(Error::FailedPrecondition);
+            }
+            if state.len == N {
+                return Err(Error::ResourceExhausted);
+            }
+            let tail = (state.head + state.len) % N;
+            state.items[tail] = Some(work);
+            state.len += 1;
+            state.max_len = state.max_len.max(state.len);
+            Ok(())
+        })?;
+        self.work_notification.release();
+        Ok(())
+    }
+
+    /// Queues `work` for execution, panicking if it can not be queued.
+    ///
+    /// Use this where a full or stopped queue is a bug, to save error
+    /// handling at the call site.
+    ///
+    /// # Panics
+    /// Panics if the queue is full or a stop was requested.
+    pub fn check_push_work(&self, work: W) {
+        if let Err(e) = self.push_work(work) {
+            // `Error` only implements `Debug` with the `std` feature.
+            panic!("Failed to push work: status {}", e as u32);
+        }
+    }
+
+    /// Stops accepting work and lets the thread running the queue return once
+    /// the work already queued has been executed.
+    pub fn request_stop(&self) {
+        self.with_state(|state| state.stop_requested = true);
+        self.work_notification.release();
+    }
+
+    /// Returns the number of work items waiting to be executed.
+    pub fn len(&self) -> usize {
+        self.with_state(|state| state.len)
+    }
+
+    /// Returns `true` if no work is waiting to be executed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the most work items which have been waiting at once, for sizing
+    /// the queue.
+    pub fn max_queue_used(&self) -> usize {
+        self.with_state(|state| state.max_len)
+    }
+
+    // Calls `f` with the locked state.  The queue's methods never lock the
+    // state while it is locked, so locking can not fail.
+    fn with_state<R>(&self, f: impl FnOnce(&mut State<W, N>) -> R) -> R {
+        match self.state.lock(f) {
+            Ok(result) => result,
+            Err(_) => unreachable!("work queue state locked recursively"),
+        }
+    }
+}
+
+impl<W: Work, const N: usize> Default for WorkQueue<W, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Work, const N: usize> ThreadCore for WorkQueue<W, N> {
+    fn run(&self) {
+        loop {
+            // The lock is released before the work is executed, so the work
+            // may push more work.
+            let (work, stop_requested) =
+                self.with_state(|state| (state.pop(), state.stop_requested));
+            match work {
+                Some(work) => work.run(),
+                None if stop_requested => return,
+                None => self.work_notification.acquire(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use std::boxed::Box;
+    use std::sync::Arc;
+    use std::vec::Vec;
+
+    use pw_sync::Mutex;
+    use pw_thread::{Options, Thread};
+
+    use super::*;
+
+    fn spawn<W: Work + 'static, const N: usize>(queue: &'static WorkQueue<W, N>) -> Thread {
+        Thread::spawn(&Options::default(), queue).unwrap()
+    }
+
+    #[test]
+    fn work_runs_in_order() {
+        type Item = Box<dyn FnOnce() + Send>;
+        let queue: &'static WorkQueue<Item, 4> = Box::leak(Box::new(WorkQueue::new()));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..4 {
+            let order = order.clone();
+            queue
+                .push_work(Box::new(move || order.lock().push(i)))
+                .unwrap();
+        }
+        let thread = spawn(queue);
+        queue.request_stop();
+        thread.join().unwrap();
+        assert_eq!(*order.lock(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn full_queue_rejects_work() {
+        let queue = WorkQueue::<fn(), 2>::new();
+        queue.push_work(|| {}).unwrap();
+        queue.push_work(|| {}).unwrap();
+        assert_eq!(queue.push_work(|| {}), Err(Error::ResourceExhausted));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.max_queue_used(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Failed to push work: status 8")]
+    fn check_push_work_panics_with_status_code() {
+        let queue = WorkQueue::<fn(), 1>::new();
+        queue.check_push_work(|| {});
+        queue.check_push_work(|| {});
+    }
+
+    #[test]
+    fn stopped_queue_rejects_work_and_finishes_queued_work() {
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+        static QUEUE: WorkQueue<fn(), 4> = WorkQueue::new();
+        let increment: fn() = || {
+            COUNT.fetch_add(1, Ordering::Relaxed);
+        };
+        QUEUE.push_work(increment).unwrap();
+        QUEUE.push_work(increment).unwrap();
+        QUEUE.request_stop();
+        assert_eq!(QUEUE.push_work(increment), Err(Error::FailedPrecondition));
+
+        spawn(&QUEUE).join().unwrap();
+        assert_eq!(COUNT.load(Ordering::Relaxed), 2);
+        assert!(QUEUE.is_empty());
+    }
+
+    #[test]
+    fn work_can_push_more_work() {
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+        static QUEUE: WorkQueue<fn(), 2> = WorkQueue::new();
+        fn count_down() {
+            if COUNT.fetch_add(1, Ordering::Relaxed) < 5 {
+                QUEUE.push_work(count_down).unwrap();
+            } else {
+                QUEUE.request_stop();
+            }
+        }
+        QUEUE.push_work(count_down).unwrap();
+        spawn(&QUEUE).join().unwrap();
+        assert_eq!(COUNT.load(Ordering::Relaxed), 6);
+    }
+}